@@ -44,7 +44,8 @@ class LogParser:
                 results = p.map(self._parse_primaries, primaries)
         except (ValueError, IndexError, AttributeError) as e:
             raise ParseError(f'Failed to parse nodes\' logs: {e}')
-        proposals, commits, self.configs, primary_ips = zip(*results)
+        proposals, commits, self.configs, primary_ips, waves = zip(*results)
+        self.waves = [x for y in waves for x in y]
         self.proposals = self._merge_results([x.items() for x in proposals])
         self.commits = self._merge_results([x.items() for x in commits])
 
@@ -136,7 +137,13 @@ class LogParser:
             raise ParseError('Failed to find IP address in primary log')
         ip = ip_match.group(1)
         
-        return proposals, commits, configs, ip
+        tmp = findall(
+            r'Wave \d+ committed \d+ certificates with latency '
+            r'p50 (\d+) ms, p90 (\d+) ms, p99 (\d+) ms', log
+        )
+        waves = [tuple(int(x) for x in w) for w in tmp]
+
+        return proposals, commits, configs, ip, waves
 
     def _parse_workers(self, log):
         if search(r'(?:panic|Error)', log) is not None:
@@ -173,6 +180,12 @@ class LogParser:
         latency = [c - self.proposals[d] for d, c in self.commits.items()]
         return mean(latency) if latency else 0
 
+    def _commit_latency_percentiles(self):
+        # Average the per-wave percentiles over all waves and primaries.
+        if not self.waves:
+            return 0, 0, 0
+        return tuple(mean(x) for x in zip(*self.waves))
+
     def _end_to_end_throughput(self):
         if not self.commits:
             return 0, 0, 0
@@ -204,6 +217,7 @@ class LogParser:
         max_batch_delay = self.configs[0]['max_batch_delay']
 
         consensus_latency = self._consensus_latency() * 1_000
+        p50, p90, p99 = self._commit_latency_percentiles()
         consensus_tps, consensus_bps, _ = self._consensus_throughput()
         end_to_end_tps, end_to_end_bps, duration = self._end_to_end_throughput()
         end_to_end_latency = self._end_to_end_latency() * 1_000
@@ -234,6 +248,8 @@ class LogParser:
             f' Consensus TPS: {round(consensus_tps):,} tx/s\n'
             f' Consensus BPS: {round(consensus_bps):,} B/s\n'
             f' Consensus latency: {round(consensus_latency):,} ms\n'
+            f' Commit latency per wave (p50/p90/p99): '
+            f'{round(p50):,}/{round(p90):,}/{round(p99):,} ms\n'
             '\n'
             f' End-to-end TPS: {round(end_to_end_tps):,} tx/s\n'
             f' End-to-end BPS: {round(end_to_end_bps):,} B/s\n'
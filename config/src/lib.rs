@@ -1,15 +1,27 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crypto::{generate_production_keypair, PublicKey, SecretKey};
+use crypto::{
+    generate_production_bls_keypair, generate_production_keypair, BlsProofOfPossession,
+    BlsPublicKey, BlsSecretKey, PublicKey, SecretKey, ThresholdKeyShare, ThresholdPublicKey,
+};
 use log::info;
-use serde::de::DeserializeOwned;
+use network::{Address, EgressConfig, RateLimit, SocketConfig};
+use serde::de::{self, DeserializeOwned};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs::{self, OpenOptions};
 use std::io::BufWriter;
 use std::io::Write as _;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
 use thiserror::Error;
 
+#[cfg(test)]
+#[path = "tests/config_tests.rs"]
+pub mod config_tests;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Node {0} is not in the committee")]
@@ -23,13 +35,29 @@ pub enum ConfigError {
 
     #[error("Failed to write config file '{file}': {message}")]
     ExportError { file: String, message: String },
+
+    #[error("Invalid parameter '{key}': {message}")]
+    InvalidParameter { key: String, message: String },
+
+    #[error("Invalid committee of epoch {epoch}: {message}")]
+    InvalidCommittee { epoch: Epoch, message: String },
 }
 
+/// Config files are parsed as TOML or YAML when their extension says so, and as JSON otherwise.
 pub trait Import: DeserializeOwned {
     fn import(path: &str) -> Result<Self, ConfigError> {
-        let reader = || -> Result<Self, std::io::Error> {
+        let reader = || -> Result<Self, Box<dyn std::error::Error>> {
             let data = fs::read(path)?;
-            Ok(serde_json::from_slice(data.as_slice())?)
+            let config = match Path::new(path).extension().and_then(|x| x.to_str()) {
+                // TOML keys are always strings: go through JSON so that maps keyed by integers (eg. the
+                // workers of an authority) are read the same way as from a JSON file.
+                Some("toml") => {
+                    serde_json::from_value(toml::from_slice::<serde_json::Value>(&data)?)?
+                }
+                Some("yaml") | Some("yml") => serde_yaml::from_slice(&data)?,
+                _ => serde_json::from_slice(&data)?,
+            };
+            Ok(config)
         };
         reader().map_err(|e| ConfigError::ImportError {
             file: path.to_string(),
@@ -57,6 +85,7 @@ pub trait Export: Serialize {
 
 pub type Stake = u32;
 pub type WorkerId = u32;
+pub type Epoch = u64;
 
 #[derive(Deserialize, Clone)]
 pub struct Parameters {
@@ -66,19 +95,290 @@ pub struct Parameters {
     /// The maximum delay that the primary waits between generating two headers, even if the header
     /// did not reach `max_header_size`. Denominated in ms.
     pub max_header_delay: u64,
+    /// The delay that the primary waits before generating the next header when the previous one was full
+    /// (ie. it reached `max_header_size` or `max_header_num_of_batches` and digests are still pending).
+    /// Capped by `max_header_delay`. Denominated in ms.
+    #[serde(default)]
+    pub min_header_delay: u64,
+    /// Whether the primary adapts the delay between two headers to the time it takes to gather a quorum of
+    /// votes for its headers (smoothed over the recent rounds), within `min_header_delay` and
+    /// `max_header_delay`. Cuts latency on fast networks without flooding slow ones with headers.
+    #[serde(default)]
+    pub adaptive_header_delay: bool,
+    /// The maximum size of the payload of a header. The digests that do not fit are included in the
+    /// next header. Denominated in bytes; 0 means no limit.
+    #[serde(default)]
+    pub max_header_size: usize,
+    /// The maximum number of batches' digests included in a header. The digests that do not fit are
+    /// included in the next header. 0 means no limit.
+    #[serde(default)]
+    pub max_header_num_of_batches: usize,
+    /// How long the primary waits, once it holds a quorum of parent certificates, for the certificates of
+    /// the remaining authorities before advancing to the next round. Waiting stops early once all of them
+    /// are in. Trades commit latency for DAG connectivity. Denominated in ms; 0 advances on the quorum.
+    #[serde(default)]
+    pub max_parent_delay: u64,
+    /// Whether to include in the next header the parent certificates received after the quorum (until the
+    /// header is created). Otherwise headers only reference the first quorum of parents.
+    #[serde(default)]
+    pub include_late_parents: bool,
+    /// The weight of each of our workers when the primary cannot fit all their pending batches' digests in
+    /// a header: each worker gets a share of the header proportional to its weight, so that a busy worker
+    /// does not starve the others. Workers that are not listed (or with weight 0) weigh 1.
+    #[serde(default)]
+    pub worker_weights: HashMap<WorkerId, u64>,
     /// The depth of the garbage collection (Denominated in number of rounds).
     pub gc_depth: u64,
+    /// The number of rounds below its last committed round whose certificates consensus keeps in memory (they
+    /// can still be committed by the next leaders). Older certificates are pruned once a leader is committed,
+    /// and never committed. All authorities of the committee must use the same setting. Denominated in number
+    /// of rounds; 0 (or anything above `gc_depth`) is `gc_depth`.
+    #[serde(default)]
+    pub consensus_gc_depth: u64,
+    /// The number of committed leaders over which the reputation of the authorities is measured. At the end
+    /// of each window, the authorities with the fewest committed certificates are elected leaders less often.
+    /// All authorities of the committee must use the same setting. 0 disables reputation (round-robin).
+    #[serde(default)]
+    pub reputation_window: u64,
+    /// The rule used by consensus to commit the leaders of the DAG. All authorities of the committee must use
+    /// the same rule.
+    #[serde(default)]
+    pub commit_rule: CommitRule,
+    /// The stake of the certificates of the next round that must reference a leader to commit it (see
+    /// `LeaderSupport`). All authorities of the committee must use the same setting.
+    #[serde(default)]
+    pub leader_support: LeaderSupport,
+    /// Whether consensus elects a leader in every round (rather than every other round), so that the
+    /// certificates are committed with lower latency. It is a shorthand for `leaders_per_wave` equal to
+    /// `wave_length`. All authorities of the committee must use the same setting.
+    #[serde(default)]
+    pub pipelined_leaders: bool,
+    /// The number of rounds of a consensus wave. With the Tusk rule, the leaders of a wave are committed once
+    /// the first round of the next wave reveals the coin electing them. All authorities of the committee must
+    /// use the same setting.
+    #[serde(default = "defaults::wave_length")]
+    pub wave_length: u64,
+    /// The number of leaders elected in each wave (at evenly spaced rounds, it should divide `wave_length`):
+    /// more leaders lower the commit latency, at the cost of chain quality. All authorities of the committee
+    /// must use the same setting.
+    #[serde(default = "defaults::leaders_per_wave")]
+    pub leaders_per_wave: u64,
+    /// The order in which consensus outputs the certificates committed by a leader (its causal history, see
+    /// `SubDagOrder`). All authorities of the committee must use the same setting.
+    #[serde(default)]
+    pub sub_dag_order: SubDagOrder,
+    /// How consensus orders the certificates of the same round committed by a leader, when outputting them by
+    /// round (see `TieBreak`). All authorities of the committee must use the same setting to output the same
+    /// sequence.
+    #[serde(default)]
+    pub tie_break: TieBreak,
+    /// Whether consensus elects the leaders with a shared random coin rather than round-robin, so that the
+    /// adversary cannot predict (and corrupt) them. The coin of each round is revealed by the shares of the
+    /// threshold key of the committee included in the headers (see `Committee::threshold_key`), whose
+    /// threshold must be f+1. All authorities of the committee must use the same setting.
+    #[serde(default)]
+    pub random_leaders: bool,
+    /// How long consensus waits for the certificate of a leader once it could commit it, before skipping it
+    /// (the skip is recorded in the metrics but does not change the commit sequence, a later leader may still
+    /// commit it). Denominated in ms; 0 disables the timeout.
+    #[serde(default = "defaults::leader_timeout")]
+    pub leader_timeout: u64,
+    /// The number of consecutive leader slots without a committed leader after which consensus falls back to
+    /// the asynchronous Tusk rule, with the leaders elected by the random coin (if the committee has a threshold
+    /// key), so that an adversary delaying the predictable leaders cannot prevent commits. The switch is derived
+    /// from the commit sequence (like the reputation of the leaders), so that all honest authorities switch at
+    /// the same point. 0 disables the fallback. All authorities of the committee must use the same setting.
+    #[serde(default)]
+    pub fallback_after: u64,
+    /// The number of consecutive leaders committed in the fallback after which consensus returns to
+    /// `commit_rule`. All authorities of the committee must use the same setting.
+    #[serde(default = "defaults::fallback_recovery")]
+    pub fallback_recovery: u64,
+    /// The number of waves that must commit on top of a committed sub-dag before consensus declares it final
+    /// (for applications wanting more settlement assurance than a single commit). Denominated in number of
+    /// waves; 0 finalizes the sub-dags as soon as they are committed.
+    #[serde(default)]
+    pub finality_depth: u64,
+    /// The number of rounds committed by consensus between two checkpoints of its state. After a restart,
+    /// consensus resumes from its last checkpoint (replaying the certificates stored since). 0 disables the
+    /// checkpoints.
+    #[serde(default = "defaults::consensus_checkpoint_interval")]
+    pub consensus_checkpoint_interval: u64,
+    /// The number of rounds below the consensus round whose certificates (along with their headers and
+    /// payload markers) the primary keeps in storage, eg. to serve late sync requests. Older rounds are
+    /// pruned. Never below `gc_depth`. Denominated in number of rounds; 0 keeps everything.
+    #[serde(default)]
+    pub retention_depth: u64,
+    /// The number of rounds below the cleanup round of their primary whose batches the workers keep in
+    /// storage, so that they can still serve the late sync requests of slow workers. Older batches are
+    /// pruned. Never below `gc_depth`. Denominated in number of rounds; 0 keeps everything.
+    #[serde(default)]
+    pub batch_retention_depth: u64,
+    /// The workers keep their most recent batches up to this size even beyond `batch_retention_depth`.
+    /// Denominated in bytes.
+    #[serde(default)]
+    pub batch_retention_bytes: usize,
+    /// The primary notifies its workers of the progress of consensus (so that they clean up their state)
+    /// every this many committed rounds. Denominated in number of rounds; 0 behaves as 1 (every round).
+    #[serde(default = "defaults::cleanup_rounds")]
+    pub cleanup_rounds: u64,
+    /// The primary notifies its workers of the progress of consensus at least this often (if any round
+    /// was committed since the last notification), regardless of `cleanup_rounds`. Denominated in ms; 0
+    /// disables the timer.
+    #[serde(default)]
+    pub cleanup_interval: u64,
+    /// The maximum number of certificates from other primaries the core processes as a batch: their parents
+    /// are looked up concurrently, and they are then stored and delivered in order. 1 processes the
+    /// certificates one by one.
+    #[serde(default = "defaults::certificate_concurrency")]
+    pub certificate_concurrency: usize,
+    /// The primary raises an alert (a warning with a diagnostic of the missing parents and votes) when no
+    /// certificate was formed nor received for this long. Denominated in ms; 0 disables the watchdog.
+    #[serde(default = "defaults::stall_timeout")]
+    pub stall_timeout: u64,
+    /// The number of recently verified headers, votes, and certificates the primary remembers, so that their
+    /// duplicates (eg. replays or re-broadcasts) are dropped before signature verification. 0 disables the
+    /// cache.
+    #[serde(default = "defaults::duplicate_cache_size")]
+    pub duplicate_cache_size: usize,
+    /// Whether the primary excludes the authorities it caught equivocating: it stops voting for their
+    /// headers and serves their sync requests last (their certificates are still accepted).
+    #[serde(default)]
+    pub exclude_misbehaving: bool,
     /// The delay after which the synchronizer retries to send sync requests. Denominated in ms.
     pub sync_retry_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request. These nodes
     /// are picked at random from the committee.
     pub sync_retry_nodes: usize,
+    /// The rate at which the primary and worker helpers serve the sync requests of each authority
+    /// (denominated in bytes). Requests exceeding the limit are dropped, so that a lagging or malicious
+    /// authority cannot monopolize our storage and uplink. No limit when unset.
+    #[serde(default)]
+    pub sync_rate_limit: Option<RateLimit>,
+    /// A primary observing certificates this many rounds ahead of its own DAG is lagging behind: it
+    /// requests the missing rounds in bulk from other primaries. Denominated in number of rounds; 0
+    /// disables catching up.
+    #[serde(default = "defaults::catch_up_threshold")]
+    pub catch_up_threshold: u64,
     /// The preferred batch size. The workers seal a batch of transactions when it reaches this size.
     /// Denominated in bytes.
     pub batch_size: usize,
     /// The delay after which the workers seal a batch of transactions, even if `max_batch_size`
     /// is not reached. Denominated in ms.
     pub max_batch_delay: u64,
+    /// Whether the workers adapt the size and delay of their batches to the rate of incoming transactions:
+    /// small batches sealed after `min_batch_delay` at low load (for latency), up to `batch_size` and
+    /// `max_batch_delay` at high load (for throughput).
+    #[serde(default)]
+    pub adaptive_batching: bool,
+    /// The smallest size of the batches (if `adaptive_batching` is set). Denominated in bytes.
+    #[serde(default = "defaults::min_batch_size")]
+    pub min_batch_size: usize,
+    /// The shortest delay after which the workers seal a batch (if `adaptive_batching` is set).
+    /// Denominated in ms.
+    #[serde(default = "defaults::min_batch_delay")]
+    pub min_batch_delay: u64,
+    /// The number of recently received transactions (hashes) each worker remembers, so that the duplicates
+    /// submitted by retrying clients are not batched again. 0 disables deduplication.
+    #[serde(default)]
+    pub transaction_dedup_size: usize,
+    /// The maximum number of transactions waiting to be batched when the worker orders them by priority:
+    /// beyond it, the lowest-priority transactions are evicted. 0 for no limit.
+    #[serde(default = "defaults::max_pending_transactions")]
+    pub max_pending_transactions: usize,
+    /// The number of transactions (hashes) each worker remembers as covered by a committed batch: clients
+    /// submitting the same transaction to several authorities then do not get it disseminated again. 0
+    /// disables cross-worker deduplication.
+    #[serde(default)]
+    pub covered_transactions_size: usize,
+    /// The maximum number of transactions each worker holds before sealing them into batches. Beyond it, the
+    /// worker stops reading the clients' sockets (and the gRPC and HTTP gateways reject transactions) until
+    /// batches are sealed. 0 for no limit.
+    #[serde(default)]
+    pub max_admitted_transactions: usize,
+    /// The maximum size of a client transaction. Larger (and empty) transactions are rejected at ingress.
+    /// Denominated in bytes; 0 for no limit.
+    #[serde(default)]
+    pub max_transaction_size: usize,
+    /// The maximum size of the transactions each worker holds before sealing them into batches (see
+    /// `max_admitted_transactions`). Denominated in bytes; 0 for no limit.
+    #[serde(default)]
+    pub max_admitted_bytes: usize,
+    /// The rate at which each worker accepts transactions from each client address (denominated in bytes).
+    /// Transactions exceeding the quota of their client are dropped. No limit when unset.
+    #[serde(default)]
+    pub client_rate_limit: Option<RateLimit>,
+    /// The stake of the authorities whose workers must acknowledge a batch before the worker hands it to
+    /// its primary (including its own).
+    #[serde(default)]
+    pub delivery_threshold: DeliveryThreshold,
+    /// The zstd level at which the workers compress the batches they broadcast to the other workers (batches
+    /// dominate the network usage). 0 disables compression.
+    #[serde(default)]
+    pub batch_compression_level: i32,
+    /// The workers disseminate their batches of at least this size as erasure-coded shards (one per authority,
+    /// echoed by each worker to the others) rather than sending them whole to every worker, cutting their
+    /// outbound bandwidth. Requires at least 4 authorities. Denominated in bytes; 0 disables erasure coding.
+    #[serde(default)]
+    pub batch_erasure_coding_threshold: usize,
+    /// The sizes of the buckets to which the workers pad the batches they broadcast (after compression), so
+    /// that the network does not reveal the size of the batches: each batch fills the smallest bucket it fits
+    /// in (or a multiple of the largest one). Erasure-coded batches are not padded. Denominated in bytes; no
+    /// padding when empty.
+    #[serde(default)]
+    pub batch_padding_buckets: Vec<usize>,
+    /// The number of threads of each worker serializing and hashing the batches, off the event loops of the
+    /// tasks ingesting transactions. 0 behaves as 1.
+    #[serde(default = "defaults::hashing_workers")]
+    pub hashing_workers: usize,
+    /// Small latency-critical messages (votes and cleanup notifications) up to this size are sent as
+    /// unreliable UDP datagrams rather than over TCP. Denominated in bytes; 0 disables datagrams.
+    #[serde(default)]
+    pub max_datagram_size: usize,
+    /// The primary disseminates its headers of at least this size as erasure-coded shards (one per authority,
+    /// echoed by each authority to the others) rather than sending them whole to every authority, cutting its
+    /// outbound bandwidth. Requires at least 4 authorities. Denominated in bytes; 0 disables erasure coding.
+    #[serde(default)]
+    pub erasure_coding_threshold: usize,
+    /// Whether to only accept connections from hosts present in the committee (except on the port receiving
+    /// client transactions).
+    #[serde(default)]
+    pub committee_allowlist: bool,
+    /// Workers split the messages they send to each other into chunks of this size, so that large batches
+    /// do not block the connection. All workers of the committee must use the same setting. Denominated in
+    /// bytes; 0 disables chunking.
+    #[serde(default)]
+    pub chunk_size: usize,
+    /// The capacity of the channels between the tasks of the primary, of the workers and of consensus. A full
+    /// channel blocks the task feeding it, so this bounds the messages queued (and the memory used) between two
+    /// tasks before backpressure reaches the network. Denominated in number of messages; 0 behaves as 1.
+    #[serde(default = "defaults::channel_capacity")]
+    pub channel_capacity: usize,
+    /// The options of the TCP sockets opened by the node.
+    #[serde(default)]
+    pub socket: SocketConfig,
+    /// The egress rate limits of the node (eg. to emulate heterogeneous uplinks in local benchmarks).
+    #[serde(default)]
+    pub egress: EgressConfig,
+    /// The address of the read-only HTTP API exposing the state of the primary (if any).
+    #[serde(default)]
+    pub introspection_address: Option<SocketAddr>,
+    /// The address on which the primary streams the certificates committed by consensus (in commit order) to
+    /// external subscribers, eg. an execution engine (if any).
+    #[serde(default)]
+    pub commit_stream_address: Option<SocketAddr>,
+    /// The commit streams of other primaries (see `commit_stream_address`) whose commit sequence is compared
+    /// with ours to detect safety violations of consensus (forks).
+    #[serde(default)]
+    pub fork_check_peers: Vec<SocketAddr>,
+    /// The number of threads of the primary producing its signatures (headers and votes), off the event
+    /// loops of the tasks requesting them. 0 behaves as 1.
+    #[serde(default = "defaults::signature_workers")]
+    pub signature_workers: usize,
+    /// Whether to sign our votes with BLS too, so that certificates carry a single aggregate signature
+    /// rather than one signature per voter. Requires BLS keys for the whole committee.
+    #[serde(default)]
+    pub aggregate_signatures: bool,
 }
 
 impl Default for Parameters {
@@ -86,45 +386,545 @@ impl Default for Parameters {
         Self {
             header_size: 1_000,
             max_header_delay: 100,
+            min_header_delay: 0,
+            adaptive_header_delay: false,
+            max_header_size: 0,
+            max_header_num_of_batches: 0,
+            max_parent_delay: 0,
+            include_late_parents: false,
+            worker_weights: HashMap::new(),
             gc_depth: 50,
+            consensus_gc_depth: 0,
+            reputation_window: 0,
+            commit_rule: CommitRule::default(),
+            leader_support: LeaderSupport::default(),
+            pipelined_leaders: false,
+            wave_length: 2,
+            leaders_per_wave: 1,
+            sub_dag_order: SubDagOrder::default(),
+            tie_break: TieBreak::default(),
+            random_leaders: false,
+            leader_timeout: 5_000,
+            fallback_after: 0,
+            fallback_recovery: 10,
+            finality_depth: 0,
+            consensus_checkpoint_interval: 10,
+            retention_depth: 0,
+            batch_retention_depth: 0,
+            batch_retention_bytes: 0,
+            cleanup_rounds: 1,
+            cleanup_interval: 0,
+            certificate_concurrency: 16,
+            stall_timeout: 10_000,
+            duplicate_cache_size: 10_000,
+            exclude_misbehaving: false,
             sync_retry_delay: 5_000,
             sync_retry_nodes: 3,
+            sync_rate_limit: None,
+            catch_up_threshold: 10,
             batch_size: 500_000,
             max_batch_delay: 100,
+            adaptive_batching: false,
+            min_batch_size: 10_000,
+            min_batch_delay: 10,
+            transaction_dedup_size: 0,
+            max_pending_transactions: 100_000,
+            covered_transactions_size: 0,
+            max_admitted_transactions: 0,
+            max_transaction_size: 0,
+            max_admitted_bytes: 0,
+            client_rate_limit: None,
+            delivery_threshold: DeliveryThreshold::default(),
+            batch_compression_level: 0,
+            batch_erasure_coding_threshold: 0,
+            batch_padding_buckets: Vec::new(),
+            hashing_workers: 1,
+            max_datagram_size: 0,
+            erasure_coding_threshold: 0,
+            committee_allowlist: false,
+            chunk_size: 0,
+            channel_capacity: 1_000,
+            socket: SocketConfig::default(),
+            egress: EgressConfig::default(),
+            signature_workers: 1,
+            aggregate_signatures: false,
+            introspection_address: None,
+            commit_stream_address: None,
+            fork_check_peers: Vec::new(),
         }
     }
 }
 
 impl Import for Parameters {}
 
+/// The default values of the parameters missing from the parameters file (which must hold at least the parameters
+/// of the original protocol), when they differ from the default value of their type.
+mod defaults {
+    use super::Parameters;
+
+    macro_rules! defaults {
+        ($($field:ident: $type:ty),*,) => {
+            $(
+                pub fn $field() -> $type {
+                    Parameters::default().$field
+                }
+            )*
+        };
+    }
+
+    defaults!(
+        wave_length: u64,
+        leaders_per_wave: u64,
+        leader_timeout: u64,
+        fallback_recovery: u64,
+        consensus_checkpoint_interval: u64,
+        cleanup_rounds: u64,
+        certificate_concurrency: usize,
+        stall_timeout: u64,
+        duplicate_cache_size: usize,
+        catch_up_threshold: u64,
+        min_batch_size: usize,
+        min_batch_delay: u64,
+        max_pending_transactions: usize,
+        hashing_workers: usize,
+        channel_capacity: usize,
+        signature_workers: usize,
+    );
+}
+
 impl Parameters {
+    /// Returns the number of leaders elected by consensus in each wave (taking into account pipelining).
+    pub fn wave_leaders(&self) -> u64 {
+        match self.pipelined_leaders {
+            true => self.wave_length,
+            false => self.leaders_per_wave,
+        }
+    }
+
+    /// Returns these parameters updated with the ones of `update` that are safe to change while the node runs:
+    /// the delays between headers, the size and delay of the batches, the sync retry delay, the cleanup
+    /// interval and the stall timeout (which cannot enable nor disable the watchdog). The other parameters only
+    /// change when the node restarts (or moves to the next epoch).
+    pub fn reload(&self, update: &Parameters) -> Self {
+        let mut parameters = self.clone();
+        macro_rules! reload {
+            ($($field:ident),*) => {
+                $(
+                    if parameters.$field != update.$field {
+                        info!(
+                            "Reloaded {} ({} -> {})",
+                            stringify!($field),
+                            parameters.$field,
+                            update.$field
+                        );
+                        parameters.$field = update.$field;
+                    }
+                )*
+            };
+        }
+        reload!(
+            max_header_delay,
+            min_header_delay,
+            batch_size,
+            max_batch_delay,
+            min_batch_size,
+            min_batch_delay,
+            sync_retry_delay,
+            cleanup_interval
+        );
+        if update.stall_timeout > 0 && parameters.stall_timeout > 0 {
+            reload!(stall_timeout);
+        }
+        parameters
+    }
+
+    /// Override a parameter from its textual value (eg. given on the command line). Only the parameters varied
+    /// by the benchmark sweeps can be set this way.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = |message: String| ConfigError::InvalidParameter {
+            key: key.to_string(),
+            message,
+        };
+        macro_rules! set {
+            ($($field:ident),*) => {
+                match key {
+                    $(
+                        stringify!($field) => {
+                            self.$field = value.parse().map_err(|e| invalid(format!("{}", e)))?
+                        }
+                    )*
+                    _ => return Err(invalid("not a parameter that can be overridden".to_string())),
+                }
+            };
+        }
+        set!(batch_size, max_header_delay, gc_depth, sync_retry_delay);
+        Ok(())
+    }
+
     pub fn log(&self) {
         info!("Header size set to {} B", self.header_size);
         info!("Max header delay set to {} ms", self.max_header_delay);
+        info!("Min header delay set to {} ms", self.min_header_delay);
+        info!(
+            "Adaptive header delay set to {}",
+            self.adaptive_header_delay
+        );
+        info!("Max header size set to {} B", self.max_header_size);
+        info!(
+            "Max header number of batches set to {}",
+            self.max_header_num_of_batches
+        );
+        info!("Max parent delay set to {} ms", self.max_parent_delay);
+        info!("Include late parents set to {}", self.include_late_parents);
+        info!("Worker weights set to {:?}", self.worker_weights);
         info!("Garbage collection depth set to {} rounds", self.gc_depth);
+        info!(
+            "Consensus garbage collection depth set to {} rounds",
+            self.consensus_gc_depth
+        );
+        info!(
+            "Reputation window set to {} leaders",
+            self.reputation_window
+        );
+        info!("Commit rule set to {:?}", self.commit_rule);
+        info!("Leader support set to {:?}", self.leader_support);
+        info!("Pipelined leaders set to {}", self.pipelined_leaders);
+        info!("Wave length set to {} rounds", self.wave_length);
+        info!("Leaders per wave set to {}", self.leaders_per_wave);
+        info!("Sub-dag order set to {:?}", self.sub_dag_order);
+        info!("Tie break set to {:?}", self.tie_break);
+        info!("Random leaders set to {}", self.random_leaders);
+        info!("Leader timeout set to {} ms", self.leader_timeout);
+        info!("Fallback after set to {} leader slots", self.fallback_after);
+        info!(
+            "Fallback recovery set to {} leaders",
+            self.fallback_recovery
+        );
+        info!("Finality depth set to {} waves", self.finality_depth);
+        info!(
+            "Consensus checkpoint interval set to {} rounds",
+            self.consensus_checkpoint_interval
+        );
+        info!("Retention depth set to {} rounds", self.retention_depth);
+        info!(
+            "Batch retention depth set to {} rounds",
+            self.batch_retention_depth
+        );
+        info!(
+            "Batch retention bytes set to {} B",
+            self.batch_retention_bytes
+        );
+        info!("Cleanup rounds set to {} rounds", self.cleanup_rounds);
+        info!("Cleanup interval set to {} ms", self.cleanup_interval);
+        info!(
+            "Certificate concurrency set to {}",
+            self.certificate_concurrency
+        );
+        info!("Stall timeout set to {} ms", self.stall_timeout);
+        info!("Duplicate cache size set to {}", self.duplicate_cache_size);
+        info!(
+            "Exclude misbehaving authorities set to {}",
+            self.exclude_misbehaving
+        );
         info!("Sync retry delay set to {} ms", self.sync_retry_delay);
         info!("Sync retry nodes set to {} nodes", self.sync_retry_nodes);
+        info!("Sync rate limit set to {:?}", self.sync_rate_limit);
+        info!(
+            "Catch up threshold set to {} rounds",
+            self.catch_up_threshold
+        );
         info!("Batch size set to {} B", self.batch_size);
         info!("Max batch delay set to {} ms", self.max_batch_delay);
+        info!("Adaptive batching set to {}", self.adaptive_batching);
+        info!("Min batch size set to {} B", self.min_batch_size);
+        info!("Min batch delay set to {} ms", self.min_batch_delay);
+        info!(
+            "Transaction dedup size set to {}",
+            self.transaction_dedup_size
+        );
+        info!(
+            "Max pending transactions set to {}",
+            self.max_pending_transactions
+        );
+        info!(
+            "Covered transactions size set to {}",
+            self.covered_transactions_size
+        );
+        info!(
+            "Max admitted transactions set to {}",
+            self.max_admitted_transactions
+        );
+        info!("Max admitted bytes set to {} B", self.max_admitted_bytes);
+        info!(
+            "Max transaction size set to {} B",
+            self.max_transaction_size
+        );
+        info!("Client rate limit set to {:?}", self.client_rate_limit);
+        info!("Delivery threshold set to {:?}", self.delivery_threshold);
+        info!(
+            "Batch compression level set to {}",
+            self.batch_compression_level
+        );
+        info!(
+            "Batch erasure coding threshold set to {} B",
+            self.batch_erasure_coding_threshold
+        );
+        info!(
+            "Batch padding buckets set to {:?} B",
+            self.batch_padding_buckets
+        );
+        info!("Hashing workers set to {}", self.hashing_workers);
+        info!("Max datagram size set to {} B", self.max_datagram_size);
+        info!(
+            "Erasure coding threshold set to {} B",
+            self.erasure_coding_threshold
+        );
+        info!("Committee allowlist set to {}", self.committee_allowlist);
+        info!("Chunk size set to {} B", self.chunk_size);
+        info!("Channel capacity set to {}", self.channel_capacity);
+        info!("Socket options set to {:?}", self.socket);
+        info!("Egress rate limits set to {:?}", self.egress);
+        info!("Signature workers set to {}", self.signature_workers);
+        info!("Aggregate signatures set to {}", self.aggregate_signatures);
+        info!(
+            "Introspection address set to {:?}",
+            self.introspection_address
+        );
+        info!(
+            "Commit stream address set to {:?}",
+            self.commit_stream_address
+        );
+        info!("Fork check peers set to {:?}", self.fork_check_peers);
+    }
+}
+
+/// The stake of the acknowledgements a worker waits for before considering a batch delivered.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryThreshold {
+    /// A quorum of the stake (2f+1).
+    #[default]
+    Quorum,
+    /// The whole stake (3f+1): a single unresponsive authority stalls the delivery of our batches.
+    All,
+    /// A fixed stake, capped at the total stake.
+    Stake(Stake),
+    /// A quorum of the stake for the batches smaller than this size (in bytes), and the whole stake for the
+    /// larger ones (that are the costliest to synchronize).
+    Size(usize),
+}
+
+impl DeliveryThreshold {
+    /// Returns the stake of the acknowledgements to wait for before delivering a batch of `size` bytes.
+    pub fn stake(&self, committee: &Committee, size: usize) -> Stake {
+        match *self {
+            Self::Quorum => committee.quorum_threshold(),
+            Self::All => committee.total_stake(),
+            Self::Stake(stake) => stake.min(committee.total_stake()),
+            Self::Size(threshold) if size >= threshold => committee.total_stake(),
+            Self::Size(_) => committee.quorum_threshold(),
+        }
+    }
+}
+
+/// The rule used by consensus to commit a leader of the DAG (leaders are elected every other round by default,
+/// see `wave_length` and `leaders_per_wave`).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitRule {
+    /// The leader of round r is committed once f+1 certificates of round r+1 reference it, as observed by
+    /// a certificate of round r+w+1 (asynchronous, the round r+w reveals the coin electing the leader, with
+    /// w the length of a wave).
+    #[default]
+    Tusk,
+    /// The leader of round r is committed as soon as f+1 certificates of round r+1 reference it (partially
+    /// synchronous): it saves a round of latency over Tusk.
+    Bullshark,
+}
+
+/// The order in which the certificates committed by a leader (the leader and the part of its causal history not
+/// committed yet) are output. Both orders only depend on the committed certificates (and on the `TieBreak`), so
+/// that all authorities output the same sequence.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubDagOrder {
+    /// By round, then by the `TieBreak` among the certificates of the same round: each certificate is output
+    /// after its parents, and the leader is output last.
+    #[default]
+    #[serde(alias = "round_author")]
+    Round,
+    /// By digest: a pseudo-random order of the certificates, regardless of their round and author (the parents
+    /// of a certificate may be output after it).
+    Digest,
+}
+
+/// How the certificates of the same round are ordered when outputting a sub-dag by round. Both orders only
+/// depend on the certificates, so that all authorities output byte-identical sequences.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    /// By author (the order of their public keys).
+    #[default]
+    Author,
+    /// By digest: a pseudo-random order of the authors, changing every round.
+    Digest,
+}
+
+/// The stake of the certificates of the next round that must reference a leader to commit it. The commit rule is
+/// only safe with at least f+1 of the stake: any quorum of the following round then links to the leader (through
+/// one of them), so every later committed leader commits it as well. More support makes the commit sequence
+/// sturdier against slow links, at the cost of latency (and of liveness above 2f+1, should authorities crash).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderSupport {
+    /// The validity threshold (f+1).
+    #[default]
+    Validity,
+    /// The quorum threshold (2f+1).
+    Quorum,
+    /// A fixed stake, at least the validity threshold and at most the total stake.
+    Stake(Stake),
+}
+
+impl LeaderSupport {
+    /// Returns the stake of the support needed to commit a leader (within the safe bounds).
+    pub fn stake(&self, committee: &Committee) -> Stake {
+        match *self {
+            Self::Validity => committee.validity_threshold(),
+            Self::Quorum => committee.quorum_threshold(),
+            Self::Stake(stake) => stake
+                .max(committee.validity_threshold())
+                .min(committee.total_stake()),
+        }
+    }
+
+    /// Whether the setting is within the safe bounds for the specified committee.
+    pub fn is_safe(&self, committee: &Committee) -> bool {
+        match *self {
+            Self::Stake(stake) => {
+                (committee.validity_threshold()..=committee.total_stake()).contains(&stake)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// A network endpoint of an authority: the address the other nodes dial (`advertise`, eg. the public IP of a NATed
+/// host) and the address the node listens on (`bind`). The committee file holds either a single address, which is
+/// advertised and listened on with the unspecified IP of its family, or a map with both addresses.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(from = "EndpointFile")]
+pub struct Endpoint {
+    /// The address the other nodes dial to reach the endpoint.
+    pub advertise: Address,
+    /// The address the node listens on.
+    pub bind: SocketAddr,
+}
+
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "an address or a map with the 'advertise' (and optionally 'bind') address"
+)]
+enum EndpointFile {
+    Single(Address),
+    Split {
+        advertise: Address,
+        #[serde(default)]
+        bind: Option<SocketAddr>,
+    },
+}
+
+impl From<EndpointFile> for Endpoint {
+    fn from(file: EndpointFile) -> Self {
+        match file {
+            EndpointFile::Single(address) => Self::from(address),
+            EndpointFile::Split { advertise, bind } => Self {
+                bind: bind.unwrap_or_else(|| advertise.listen_address()),
+                advertise,
+            },
+        }
+    }
+}
+
+impl From<Address> for Endpoint {
+    fn from(advertise: Address) -> Self {
+        Self {
+            bind: advertise.listen_address(),
+            advertise,
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Address>().map(Self::from)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if self.bind == self.advertise.listen_address() {
+            write!(f, "{}", self.advertise)
+        } else {
+            write!(f, "{} (bound to {})", self.advertise, self.bind)
+        }
+    }
+}
+
+impl Endpoint {
+    /// Returns the advertised port.
+    pub fn port(&self) -> u16 {
+        self.advertise.port()
+    }
+
+    /// Changes the port of both the advertised and the bind addresses.
+    pub fn set_port(&mut self, port: u16) {
+        self.advertise.set_port(port);
+        self.bind.set_port(port);
     }
 }
 
 #[derive(Clone, Deserialize)]
 pub struct PrimaryAddresses {
     /// Address to receive messages from other primaries (WAN).
-    pub primary_to_primary: SocketAddr,
+    pub primary_to_primary: Endpoint,
     /// Address to receive messages from our workers (LAN).
-    pub worker_to_primary: SocketAddr,
+    pub worker_to_primary: Endpoint,
 }
 
 #[derive(Clone, Deserialize, Eq, Hash, PartialEq)]
 pub struct WorkerAddresses {
     /// Address to receive client transactions (WAN).
-    pub transactions: SocketAddr,
+    pub transactions: Endpoint,
+    /// Additional addresses to receive client transactions (WAN), eg. one per co-located client process.
+    #[serde(default)]
+    pub additional_transactions: Vec<Endpoint>,
     /// Address to receive messages from other workers (WAN).
-    pub worker_to_worker: SocketAddr,
+    pub worker_to_worker: Endpoint,
     /// Address to receive messages from our primary (LAN).
-    pub primary_to_worker: SocketAddr,
+    pub primary_to_worker: Endpoint,
+    /// Address to receive client transactions over gRPC (WAN), if any.
+    #[serde(default)]
+    pub grpc: Option<Endpoint>,
+    /// Address of the HTTP/JSON gateway to submit transactions (for debugging and low-rate clients), if any.
+    #[serde(default)]
+    pub http: Option<Endpoint>,
+    /// Address of the gRPC service serving the stored batches to the execution layer (LAN), if any.
+    #[serde(default)]
+    pub executor: Option<Endpoint>,
+    /// Address of the HTTP endpoint exposing the metrics of the worker (LAN), if any.
+    #[serde(default)]
+    pub metrics: Option<Endpoint>,
+}
+
+impl WorkerAddresses {
+    /// Returns all the addresses to receive client transactions (starting with the main one).
+    pub fn all_transactions(&self) -> impl Iterator<Item = &Endpoint> {
+        std::iter::once(&self.transactions).chain(self.additional_transactions.iter())
+    }
 }
 
 #[derive(Clone, Deserialize)]
@@ -134,12 +934,72 @@ pub struct Authority {
     /// The network addresses of the primary.
     pub primary: PrimaryAddresses,
     /// Map of workers' id and their network addresses.
+    #[serde(deserialize_with = "deserialize_workers")]
     pub workers: HashMap<WorkerId, WorkerAddresses>,
+    /// The BLS public key of the authority (to verify the aggregate signatures of certificates).
+    #[serde(default)]
+    pub bls_public_key: Option<BlsPublicKey>,
+    /// The proof of possession of the secret key of `bls_public_key`, required along with it: otherwise an
+    /// authority could pick a (rogue) key cancelling the keys of the others in an aggregate signature.
+    #[serde(default)]
+    pub bls_proof_of_possession: Option<BlsProofOfPossession>,
+}
+
+/// JSON (and TOML) files hold the worker ids as strings, which only the JSON deserializer itself parses as
+/// integers: once buffered (eg. by the untagged `CommitteesFile`), they must be parsed explicitly.
+fn deserialize_workers<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<WorkerId, WorkerAddresses>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    #[derive(PartialEq, Eq, Hash)]
+    struct Id(WorkerId);
+
+    impl<'de> Deserialize<'de> for Id {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: de::Deserializer<'de>,
+        {
+            struct IdVisitor;
+
+            impl<'de> de::Visitor<'de> for IdVisitor {
+                type Value = Id;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("a worker id")
+                }
+
+                fn visit_u64<E: de::Error>(self, id: u64) -> Result<Id, E> {
+                    WorkerId::try_from(id).map(Id).map_err(E::custom)
+                }
+
+                fn visit_str<E: de::Error>(self, id: &str) -> Result<Id, E> {
+                    id.parse().map(Id).map_err(E::custom)
+                }
+            }
+
+            deserializer.deserialize_any(IdVisitor)
+        }
+    }
+
+    let workers = HashMap::<Id, WorkerAddresses>::deserialize(deserializer)?;
+    Ok(workers.into_iter().map(|(id, x)| (id.0, x)).collect())
 }
 
 #[derive(Clone, Deserialize)]
 pub struct Committee {
     pub authorities: BTreeMap<PublicKey, Authority>,
+    /// The epoch of the committee. The committee of the next epoch takes over once a quorum of
+    /// authorities signals (through committed headers) that it is ready to switch.
+    #[serde(default)]
+    pub epoch: Epoch,
+    /// The threshold key of the committee: the clients encrypt their transactions to it (if the committee
+    /// decrypts them after commit), and its shares reveal the coin electing the leaders (see `random_leaders`).
+    /// Its threshold is f+1 (see `validate`): the faulty authorities cannot decrypt the transactions before
+    /// they are committed, and the honest authorities always can once they are.
+    #[serde(default)]
+    pub threshold_key: Option<ThresholdPublicKey>,
 }
 
 impl Import for Committee {}
@@ -155,6 +1015,68 @@ impl Committee {
         self.authorities.get(&name).map_or_else(|| 0, |x| x.stake)
     }
 
+    /// Returns the index of the share of the threshold key held by an authority: its position in the committee
+    /// (ordered by public key), starting at 1.
+    pub fn share_index(&self, name: &PublicKey) -> Option<u64> {
+        self.authorities
+            .keys()
+            .position(|x| x == name)
+            .map(|i| i as u64 + 1)
+    }
+
+    /// Check that the BLS public keys come with a valid proof of possession, that the threshold key (if any) has
+    /// one share per authority, and that its threshold is f+1 (see `validity_size`): the f faulty authorities
+    /// cannot use it on their own, while the honest authorities always hold enough shares to use it.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| ConfigError::InvalidCommittee {
+            epoch: self.epoch,
+            message,
+        };
+        for (name, authority) in &self.authorities {
+            if let Some(bls_public_key) = &authority.bls_public_key {
+                let valid = authority
+                    .bls_proof_of_possession
+                    .is_some_and(|proof| proof.verify(bls_public_key).is_ok());
+                if !valid {
+                    return Err(invalid(format!(
+                        "the BLS public key of {} has no valid proof of possession",
+                        name
+                    )));
+                }
+            }
+        }
+
+        let key = match &self.threshold_key {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        if key.verification_keys.len() != self.size() {
+            return Err(invalid(format!(
+                "the threshold key has {} shares for {} authorities",
+                key.verification_keys.len(),
+                self.size()
+            )));
+        }
+        if key.threshold != self.validity_size() {
+            return Err(invalid(format!(
+                "the threshold of the threshold key ({}) must be f+1 ({})",
+                key.threshold,
+                self.validity_size()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Return the BLS public key of a specific authority (if any).
+    pub fn bls_public_key(&self, name: &PublicKey) -> Option<BlsPublicKey> {
+        self.authorities.get(name).and_then(|x| x.bls_public_key)
+    }
+
+    /// Returns the total stake of the committee.
+    pub fn total_stake(&self) -> Stake {
+        self.authorities.values().map(|x| x.stake).sum()
+    }
+
     /// Returns the stake of all authorities except `myself`.
     pub fn others_stake(&self, myself: &PublicKey) -> Vec<(PublicKey, Stake)> {
         self.authorities
@@ -164,6 +1086,31 @@ impl Committee {
             .collect()
     }
 
+    /// Returns the smallest number of authorities holding a quorum of the stake (2f+1 with equal stakes): any
+    /// quorum (eg. the honest authorities) counts at least as many authorities.
+    pub fn quorum_size(&self) -> usize {
+        let mut stakes: Vec<_> = self.authorities.values().map(|x| x.stake).collect();
+        stakes.sort_unstable_by(|a, b| b.cmp(a));
+        let threshold = self.quorum_threshold();
+        let (mut stake, mut size) = (0, 0);
+        for x in stakes {
+            if stake >= threshold {
+                break;
+            }
+            stake += x;
+            size += 1;
+        }
+        size
+    }
+
+    /// Returns the number of authorities f+1 for the f faulty authorities the committee tolerates by count: any
+    /// f+1 authorities include an honest one, and the honest authorities always hold f+1 shares.
+    pub fn validity_size(&self) -> usize {
+        // If N = 3f + 1 + k (0 <= k < 3)
+        // then (N + 2) / 3 = f + 1 + k/3 = f + 1
+        (self.size() + 2) / 3
+    }
+
     /// Returns the stake required to reach a quorum (2f+1).
     pub fn quorum_threshold(&self) -> Stake {
         // If N = 3f + 1 + k (0 <= k < 3)
@@ -197,6 +1144,29 @@ impl Committee {
             .collect()
     }
 
+    /// Returns the advertised addresses of all primaries and workers of the committee.
+    pub fn addresses(&self) -> Vec<Address> {
+        self.authorities
+            .values()
+            .flat_map(|authority| {
+                let primary = &authority.primary;
+                let workers = authority.workers.values().flat_map(|worker| {
+                    vec![
+                        worker.transactions.advertise.clone(),
+                        worker.worker_to_worker.advertise.clone(),
+                        worker.primary_to_worker.advertise.clone(),
+                    ]
+                });
+                vec![
+                    primary.primary_to_primary.advertise.clone(),
+                    primary.worker_to_primary.advertise.clone(),
+                ]
+                .into_iter()
+                .chain(workers)
+            })
+            .collect()
+    }
+
     /// Returns the addresses of a specific worker (`id`) of a specific authority (`to`).
     pub fn worker(&self, to: &PublicKey, id: &WorkerId) -> Result<WorkerAddresses, ConfigError> {
         self.authorities
@@ -246,12 +1216,98 @@ impl Committee {
     }
 }
 
+/// The committees of successive epochs. Their file holds either a single committee or a list of committees
+/// (one per epoch), so that the committees of the next epochs can be provided ahead of time.
+#[derive(Clone, Default, Deserialize)]
+#[serde(try_from = "CommitteesFile")]
+pub struct EpochCommittees {
+    committees: BTreeMap<Epoch, Committee>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a committee or a list of committees")]
+enum CommitteesFile {
+    Single(Committee),
+    List(Vec<Committee>),
+}
+
+impl TryFrom<CommitteesFile> for EpochCommittees {
+    type Error = ConfigError;
+
+    fn try_from(file: CommitteesFile) -> Result<Self, Self::Error> {
+        let committees = match file {
+            CommitteesFile::Single(committee) => vec![committee],
+            CommitteesFile::List(committees) => committees,
+        };
+        for committee in &committees {
+            committee.validate()?;
+        }
+        Ok(Self::new(committees))
+    }
+}
+
+impl From<Committee> for EpochCommittees {
+    fn from(committee: Committee) -> Self {
+        Self::new(std::iter::once(committee))
+    }
+}
+
+impl Import for EpochCommittees {}
+
+impl EpochCommittees {
+    /// Index the committees by epoch (the last committee of an epoch wins).
+    pub fn new(committees: impl IntoIterator<Item = Committee>) -> Self {
+        let mut epoch_committees = Self::default();
+        for committee in committees {
+            epoch_committees.insert(committee);
+        }
+        epoch_committees
+    }
+
+    /// Add the committee of an epoch, replacing any previous committee of that epoch.
+    pub fn insert(&mut self, committee: Committee) {
+        self.committees.insert(committee.epoch, committee);
+    }
+
+    /// Returns the committee of the specified epoch (if known).
+    pub fn committee_for_epoch(&self, epoch: Epoch) -> Option<&Committee> {
+        self.committees.get(&epoch)
+    }
+
+    /// Returns the committee of the lowest known epoch.
+    pub fn first(&self) -> Option<&Committee> {
+        self.committees.values().next()
+    }
+
+    /// Returns the committee of the highest known epoch.
+    pub fn latest(&self) -> Option<&Committee> {
+        self.committees.values().next_back()
+    }
+
+    /// Returns the known epochs (in increasing order).
+    pub fn epochs(&self) -> impl Iterator<Item = Epoch> + '_ {
+        self.committees.keys().copied()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct KeyPair {
     /// The node's public key (and identifier).
     pub name: PublicKey,
     /// The node's secret key.
     pub secret: SecretKey,
+    /// The node's BLS public key.
+    #[serde(default)]
+    pub bls_name: Option<BlsPublicKey>,
+    /// The node's BLS secret key (to sign votes that can be aggregated into certificates).
+    #[serde(default)]
+    pub bls_secret: Option<BlsSecretKey>,
+    /// The proof of possession of the node's BLS secret key (to publish in the committee along with `bls_name`).
+    #[serde(default)]
+    pub bls_proof_of_possession: Option<BlsProofOfPossession>,
+    /// The node's share of the threshold key of the committee (if any).
+    #[serde(default)]
+    pub threshold_share: Option<ThresholdKeyShare>,
 }
 
 impl Import for KeyPair {}
@@ -260,7 +1316,16 @@ impl Export for KeyPair {}
 impl KeyPair {
     pub fn new() -> Self {
         let (name, secret) = generate_production_keypair();
-        Self { name, secret }
+        let (bls_name, bls_secret) = generate_production_bls_keypair();
+        let bls_proof_of_possession = BlsProofOfPossession::new(&bls_name, &bls_secret);
+        Self {
+            name,
+            secret,
+            bls_name: Some(bls_name),
+            bls_secret: Some(bls_secret),
+            bls_proof_of_possession: Some(bls_proof_of_possession),
+            threshold_share: None,
+        }
     }
 }
 
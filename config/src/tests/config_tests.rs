@@ -0,0 +1,291 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+// Write the specified content to a config file and import it.
+fn import<T: Import>(file: &str, content: &str) -> Result<T, ConfigError> {
+    fs::write(file, content).unwrap();
+    let result = T::import(file);
+    let _ = fs::remove_file(file);
+    result
+}
+
+fn committee_toml(name: &PublicKey) -> String {
+    format!(
+        r#"
+        epoch = 1
+
+        [authorities."{name}"]
+        stake = 2
+
+        [authorities."{name}".primary]
+        primary_to_primary = "127.0.0.1:3000"
+        worker_to_primary = "127.0.0.1:3001"
+
+        [authorities."{name}".workers.0]
+        primary_to_worker = "127.0.0.1:3002"
+        transactions = "127.0.0.1:3003"
+        worker_to_worker = "127.0.0.1:3004"
+        "#,
+        name = name.encode_base64()
+    )
+}
+
+fn committee_yaml(name: &PublicKey) -> String {
+    format!(
+        r#"
+epoch: 1
+authorities:
+  "{name}":
+    stake: 2
+    primary:
+      primary_to_primary: "127.0.0.1:3000"
+      worker_to_primary: "127.0.0.1:3001"
+    workers:
+      0:
+        primary_to_worker: "127.0.0.1:3002"
+        transactions: "127.0.0.1:3003"
+        worker_to_worker: "127.0.0.1:3004"
+"#,
+        name = name.encode_base64()
+    )
+}
+
+fn committee_json(name: &PublicKey) -> String {
+    format!(
+        r#"{{
+            "epoch": 1,
+            "authorities": {{
+                "{name}": {{
+                    "stake": 2,
+                    "primary": {{
+                        "primary_to_primary": "127.0.0.1:3000",
+                        "worker_to_primary": "127.0.0.1:3001"
+                    }},
+                    "workers": {{
+                        "0": {{
+                            "primary_to_worker": "127.0.0.1:3002",
+                            "transactions": "127.0.0.1:3003",
+                            "worker_to_worker": "127.0.0.1:3004"
+                        }}
+                    }}
+                }}
+            }}
+        }}"#,
+        name = name.encode_base64()
+    )
+}
+
+#[test]
+fn import_committee() {
+    let name = KeyPair::new().name;
+    let files = vec![
+        (".test_import_committee.toml", committee_toml(&name)),
+        (".test_import_committee.yaml", committee_yaml(&name)),
+        (".test_import_committee.json", committee_json(&name)),
+    ];
+    for (file, content) in files {
+        let committee: Committee = import(file, &content).unwrap();
+        assert_eq!(committee.epoch, 1);
+        assert_eq!(committee.stake(&name), 2);
+        let primary = committee.primary(&name).unwrap();
+        assert_eq!(primary.primary_to_primary.to_string(), "127.0.0.1:3000");
+        let worker = committee.worker(&name, &0).unwrap();
+        assert_eq!(worker.transactions.to_string(), "127.0.0.1:3003");
+    }
+}
+
+#[test]
+fn import_parameters() {
+    // The files hold the parameters of the original protocol (which are required) and the egress rate limit.
+    let files = vec![
+        (
+            ".test_import_parameters.toml",
+            "header_size = 1000\nmax_header_delay = 100\ngc_depth = 10\nsync_retry_delay = 5000\n\
+             sync_retry_nodes = 3\nbatch_size = 1000\nmax_batch_delay = 100\n\
+             [egress.rate_limit]\nrate = 100\nburst = 10\n",
+        ),
+        (
+            ".test_import_parameters.yaml",
+            "header_size: 1000\nmax_header_delay: 100\ngc_depth: 10\nsync_retry_delay: 5000\n\
+             sync_retry_nodes: 3\nbatch_size: 1000\nmax_batch_delay: 100\n\
+             egress:\n  rate_limit:\n    rate: 100\n    burst: 10\n",
+        ),
+        (
+            ".test_import_parameters.json",
+            r#"{ "header_size": 1000, "max_header_delay": 100, "gc_depth": 10, "sync_retry_delay": 5000,
+                "sync_retry_nodes": 3, "batch_size": 1000, "max_batch_delay": 100,
+                "egress": { "rate_limit": { "rate": 100, "burst": 10 } } }"#,
+        ),
+    ];
+    for (file, content) in files {
+        let parameters: Parameters = import(file, content).unwrap();
+        assert_eq!(parameters.batch_size, 1_000);
+        assert_eq!(parameters.gc_depth, 10);
+        assert_eq!(
+            parameters.egress.rate_limit,
+            Some(RateLimit {
+                rate: 100,
+                burst: 10
+            })
+        );
+
+        // The newer parameters missing from the file keep their default value.
+        assert_eq!(
+            parameters.channel_capacity,
+            Parameters::default().channel_capacity
+        );
+        assert_eq!(parameters.max_datagram_size, 0);
+    }
+}
+
+#[test]
+fn reject_malformed_files() {
+    let files = vec![
+        (".test_malformed.toml", "batch_size = "),
+        (".test_malformed.yaml", "batch_size: [1000"),
+        (".test_malformed.json", r#"{ "batch_size": 1000 "#),
+        // Well-formed files holding values of the wrong type.
+        (".test_mistyped.toml", "batch_size = \"large\"\n"),
+        (".test_mistyped.yaml", "batch_size: large\n"),
+        (".test_mistyped.json", r#"{ "batch_size": "large" }"#),
+        // Well-formed files missing parameters of the original protocol.
+        (".test_incomplete.toml", "batch_size = 1000\n"),
+        (".test_incomplete.json", r#"{ "batch_size": 1000 }"#),
+    ];
+    for (file, content) in files {
+        match import::<Parameters>(file, content) {
+            Err(ConfigError::ImportError { file: x, .. }) => assert_eq!(x, file),
+            _ => panic!("Imported malformed file {}", file),
+        }
+    }
+
+    // A committee whose authority misses its primary addresses.
+    let name = KeyPair::new().name;
+    let content = format!("[authorities.\"{}\"]\nstake = 1\n", name.encode_base64());
+    assert!(import::<Committee>(".test_malformed_committee.toml", &content).is_err());
+}
+
+#[test]
+fn parse_endpoints() {
+    // A single address is advertised, and listened on with the unspecified IP.
+    let endpoint: Endpoint = serde_json::from_str(r#""1.2.3.4:80""#).unwrap();
+    assert_eq!(endpoint.advertise, "1.2.3.4:80".parse().unwrap());
+    assert_eq!(endpoint.bind, "0.0.0.0:80".parse().unwrap());
+    assert_eq!(endpoint, "1.2.3.4:80".parse().unwrap());
+
+    // Both addresses.
+    let json = r#"{ "advertise": "example.com:80", "bind": "10.0.0.1:8080" }"#;
+    let endpoint: Endpoint = serde_json::from_str(json).unwrap();
+    assert_eq!(endpoint.advertise, "example.com:80".parse().unwrap());
+    assert_eq!(endpoint.bind, "10.0.0.1:8080".parse().unwrap());
+    assert_eq!(
+        endpoint.to_string(),
+        "example.com:80 (bound to 10.0.0.1:8080)"
+    );
+
+    // The bind address defaults to the unspecified IP with the advertised port.
+    let json = r#"{ "advertise": "[::1]:80" }"#;
+    let endpoint: Endpoint = serde_json::from_str(json).unwrap();
+    assert_eq!(endpoint.bind, "[::]:80".parse().unwrap());
+
+    // The advertised address is mandatory.
+    let json = r#"{ "bind": "10.0.0.1:8080" }"#;
+    assert!(serde_json::from_str::<Endpoint>(json).is_err());
+    assert!(serde_json::from_str::<Endpoint>(r#""1.2.3.4""#).is_err());
+}
+
+#[test]
+fn set_endpoint_port() {
+    let json = r#"{ "advertise": "1.2.3.4:80", "bind": "10.0.0.1:8080" }"#;
+    let mut endpoint: Endpoint = serde_json::from_str(json).unwrap();
+    endpoint.set_port(90);
+    assert_eq!(endpoint.port(), 90);
+    assert_eq!(endpoint.bind, "10.0.0.1:90".parse().unwrap());
+}
+
+#[test]
+fn parse_epoch_committees() {
+    let name = KeyPair::new().name;
+    let committee = |epoch| {
+        let epoch = format!("\"epoch\": {}", epoch);
+        committee_json(&name).replacen("\"epoch\": 1", &epoch, 1)
+    };
+
+    // A single committee.
+    let committees: EpochCommittees = serde_json::from_str(&committee(3)).unwrap();
+    assert_eq!(committees.epochs().collect::<Vec<_>>(), vec![3]);
+    assert_eq!(committees.first().unwrap().epoch, 3);
+
+    // The node reads its committees from a file (of any format).
+    let file = ".test_parse_epoch_committees.toml";
+    let committees: EpochCommittees = import(file, &committee_toml(&name)).unwrap();
+    assert_eq!(committees.latest().unwrap().stake(&name), 2);
+
+    // A list of committees (in any order, the last committee of an epoch wins).
+    let json = format!("[{}, {}, {}]", committee(2), committee(0), committee(2));
+    let committees: EpochCommittees = serde_json::from_str(&json).unwrap();
+    assert_eq!(committees.epochs().collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(committees.first().unwrap().epoch, 0);
+    assert_eq!(committees.latest().unwrap().epoch, 2);
+    assert!(committees.committee_for_epoch(1).is_none());
+
+    // Neither a committee nor a list of committees.
+    assert!(serde_json::from_str::<EpochCommittees>(r#"{ "epoch": 1 }"#).is_err());
+    assert!(serde_json::from_str::<EpochCommittees>(r#"[{ "epoch": 1 }]"#).is_err());
+}
+
+#[test]
+fn validate_threshold_key() {
+    let names: Vec<_> = (0..4).map(|_| KeyPair::new().name).collect();
+    let authorities = names.iter().map(|name| {
+        let committee: Committee = serde_json::from_str(&committee_json(name)).unwrap();
+        (*name, committee.authorities[name].clone())
+    });
+    let committee = |shares, threshold| Committee {
+        authorities: authorities.clone().collect(),
+        epoch: 1,
+        threshold_key: Some(ThresholdPublicKey {
+            key: crypto::G1Point([0; 48]),
+            verification_keys: vec![crypto::G1Point([0; 48]); shares],
+            threshold,
+        }),
+    };
+
+    // The threshold must be f+1 (2 out of 4 authorities): not f, nor 2f+1.
+    assert!(committee(4, 2).validate().is_ok());
+    assert!(committee(4, 1).validate().is_err());
+    assert!(committee(4, 3).validate().is_err());
+    assert!(committee(4, 0).validate().is_err());
+
+    // The key must have one share per authority.
+    assert!(committee(3, 2).validate().is_err());
+
+    // The shares are assigned by the order of the public keys.
+    let mut sorted = names.clone();
+    sorted.sort();
+    let committee = committee(4, 2);
+    assert_eq!(committee.validity_size(), 2);
+    for (i, name) in sorted.iter().enumerate() {
+        assert_eq!(committee.share_index(name), Some(i as u64 + 1));
+    }
+    assert_eq!(committee.share_index(&KeyPair::new().name), None);
+}
+
+#[test]
+fn validate_bls_keys() {
+    let keypair = KeyPair::new();
+    let other = KeyPair::new();
+    let name = keypair.name;
+    let mut committee: Committee = serde_json::from_str(&committee_json(&name)).unwrap();
+    let authority = committee.authorities.get_mut(&name).unwrap();
+
+    // A BLS public key requires a valid proof of possession of its secret key.
+    authority.bls_public_key = keypair.bls_name;
+    assert!(committee.validate().is_err());
+    let authority = committee.authorities.get_mut(&name).unwrap();
+    authority.bls_proof_of_possession = other.bls_proof_of_possession;
+    assert!(committee.validate().is_err());
+    let authority = committee.authorities.get_mut(&name).unwrap();
+    authority.bls_proof_of_possession = keypair.bls_proof_of_possession;
+    assert!(committee.validate().is_ok());
+}
@@ -0,0 +1,116 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::leader_schedule::LeaderSchedule;
+use crate::State;
+use config::Epoch;
+use crypto::Digest;
+use crypto::Hash as _;
+use log::{debug, error};
+use primary::{Certificate, CommittedSubDag, DagSnapshot, Round};
+use std::collections::VecDeque;
+use store::{Store, StoreError};
+
+#[cfg(test)]
+#[path = "tests/checkpoint_tests.rs"]
+pub mod checkpoint_tests;
+
+/// The prefix of the key holding the last checkpoint of the consensus state (one key per epoch).
+const CHECKPOINT_KEY_PREFIX: &[u8] = b"consensus_checkpoint:";
+
+/// The prefix of the key holding the last leader whose sub-dag was output, along with the sub-dags output but
+/// not yet final (one key per epoch).
+const LAST_OUTPUT_KEY_PREFIX: &[u8] = b"consensus_last_output:";
+
+/// Periodically persists the consensus state (the last committed rounds, the uncommitted frontier of the dag,
+/// and the leader schedule which depends on the commit history), so that a restarted node resumes committing
+/// from its last checkpoint rather than from genesis (it would otherwise miss the history needed to commit the
+/// next leaders). The last leader whose sub-dag was output is also persisted (after outputting it), so that
+/// the commits made after the last checkpoint are not output again after a restart. A crash between the output
+/// and its record thus yields a duplicate (the application dedupes by leader round) rather than a gap.
+pub struct Checkpoints {
+    /// The persistent storage.
+    store: Store,
+    /// The current epoch.
+    epoch: Epoch,
+    /// The number of committed rounds between two checkpoints.
+    interval: Round,
+    /// The last committed round of the last checkpoint.
+    last: Round,
+}
+
+impl Checkpoints {
+    pub fn new(store: Store, epoch: Epoch, interval: Round) -> Self {
+        Self {
+            store,
+            epoch,
+            interval,
+            last: 0,
+        }
+    }
+
+    fn key(&self) -> Vec<u8> {
+        [CHECKPOINT_KEY_PREFIX, &self.epoch.to_be_bytes()].concat()
+    }
+
+    fn last_output_key(&self) -> Vec<u8> {
+        [LAST_OUTPUT_KEY_PREFIX, &self.epoch.to_be_bytes()].concat()
+    }
+
+    /// Returns the last checkpoint of the epoch (if any).
+    pub(crate) async fn read(&mut self) -> Result<Option<(State, LeaderSchedule)>, StoreError> {
+        let checkpoint: Option<(State, LeaderSchedule)> =
+            self.store.read(self.key()).await?.map(|x| {
+                bincode::deserialize(&x).expect("Failed to deserialize consensus checkpoint")
+            });
+        if let Some((state, _)) = &checkpoint {
+            self.last = state.last_committed_round;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Persist the state if it committed enough rounds since the last checkpoint.
+    pub(crate) async fn committed(&mut self, state: &State, schedule: &LeaderSchedule) {
+        if state.last_committed_round < self.last + self.interval {
+            return;
+        }
+        self.last = state.last_committed_round;
+        let bytes = bincode::serialize(&(state, schedule))
+            .expect("Failed to serialize consensus checkpoint");
+        self.store.write(self.key(), bytes).await;
+        debug!(
+            "Checkpointed consensus state at round {}",
+            state.last_committed_round
+        );
+    }
+
+    /// Returns the round and digest of the last leader whose sub-dag was output in the epoch (if any), along
+    /// with the sub-dags output but not yet final at the time.
+    pub(crate) async fn last_output(
+        &mut self,
+    ) -> Result<Option<(Round, Digest, VecDeque<CommittedSubDag>)>, StoreError> {
+        Ok(self.store.read(self.last_output_key()).await?.map(|x| {
+            bincode::deserialize(&x).expect("Failed to deserialize the last output leader")
+        }))
+    }
+
+    /// Record that the sub-dag of the specified leader is output, along with the sub-dags not yet final.
+    pub(crate) async fn output(
+        &mut self,
+        leader: &Certificate,
+        unfinalized: &VecDeque<CommittedSubDag>,
+    ) {
+        let bytes = bincode::serialize(&(leader.round(), leader.digest(), unfinalized))
+            .expect("Failed to serialize the last output leader");
+        self.store.write(self.last_output_key(), bytes).await;
+    }
+
+    /// Returns the certificates stored from the specified round (to catch up after a restart).
+    pub(crate) async fn stored_from(&self, round: Round) -> Vec<Certificate> {
+        match DagSnapshot::load(self.store.clone(), self.epoch, round, None).await {
+            Ok(snapshot) => snapshot.certificates(),
+            Err(e) => {
+                error!("Failed to read the stored certificates: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
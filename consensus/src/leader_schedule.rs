@@ -0,0 +1,129 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use config::{Committee, Stake};
+use crypto::PublicKey;
+use log::debug;
+use primary::{Certificate, Round};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[cfg(test)]
+#[path = "tests/leader_schedule_tests.rs"]
+pub mod leader_schedule_tests;
+
+/// Elects the leader of each round, taking into account the stake and the reputation of the authorities. Each
+/// authority is elected in proportion to its stake: the coin picks a unit of the total stake, and the authority
+/// holding it leads (with equal stakes, the authorities lead round-robin). The reputation of an authority is the
+/// number of its certificates committed over the last window of committed leaders: authorities that miss
+/// rounds, or whose certificates are too late to be referenced by the next round, have fewer certificates
+/// committed. At the end of each window, the worst authorities (holding at most f of the stake) are replaced by
+/// the best ones in the schedule.
+///
+/// The reputation is only derived from the committed sequence, so that all honest authorities update their
+/// schedule at the same point of the sequence (and thus agree on the leaders).
+#[derive(Serialize, Deserialize)]
+pub struct LeaderSchedule {
+    /// The authorities, sorted by public key (the round-robin order).
+    keys: Vec<PublicKey>,
+    /// The stake of each authority (in the order of `keys`).
+    stakes: Vec<Stake>,
+    /// The largest stake of the authorities replaced as leaders (f).
+    max_swapped_stake: Stake,
+    /// The number of committed leaders after which the schedule is updated (0 disables reputation).
+    window: u64,
+    /// The score of each authority over the current window.
+    scores: HashMap<PublicKey, u64>,
+    /// The number of leaders committed in the current window.
+    committed_leaders: u64,
+    /// The authorities replacing the worst ones as leaders.
+    swaps: HashMap<PublicKey, PublicKey>,
+}
+
+impl LeaderSchedule {
+    pub fn new(committee: &Committee, window: u64) -> Self {
+        let mut keys: Vec<_> = committee.authorities.keys().cloned().collect();
+        keys.sort();
+        let stakes = keys.iter().map(|x| committee.stake(x)).collect();
+        Self {
+            keys,
+            stakes,
+            max_swapped_stake: committee.validity_threshold().saturating_sub(1),
+            window,
+            scores: HashMap::new(),
+            committed_leaders: 0,
+            swaps: HashMap::new(),
+        }
+    }
+
+    /// Returns the leader elected by the specified coin.
+    pub fn leader(&self, coin: Round) -> PublicKey {
+        let total: Stake = self.stakes.iter().sum();
+        let mut position = coin % Round::from(total.max(1));
+        let mut leader = self.keys[coin as usize % self.keys.len()];
+        for (name, stake) in self.keys.iter().zip(&self.stakes) {
+            if position < Round::from(*stake) {
+                leader = *name;
+                break;
+            }
+            position -= Round::from(*stake);
+        }
+        self.swaps.get(&leader).copied().unwrap_or(leader)
+    }
+
+    /// Returns the stake of an authority.
+    fn stake(&self, name: &PublicKey) -> Stake {
+        self.keys
+            .iter()
+            .position(|x| x == name)
+            .map_or(0, |i| self.stakes[i])
+    }
+
+    /// Record a committed certificate.
+    pub fn record(&mut self, certificate: &Certificate) {
+        if self.window > 0 {
+            *self.scores.entry(certificate.origin()).or_insert(0) += 1;
+        }
+    }
+
+    /// Record a committed leader (after all the certificates of its sub-dag). Returns whether the schedule
+    /// changed: the leaders of the next rounds must then be re-evaluated.
+    pub fn commit_leader(&mut self) -> bool {
+        if self.window == 0 {
+            return false;
+        }
+        self.committed_leaders += 1;
+        if self.committed_leaders < self.window {
+            return false;
+        }
+
+        // Rank the authorities by score (ties are broken by public key).
+        let mut ranking = self.keys.clone();
+        ranking.sort_by_key(|name| self.scores.get(name).copied().unwrap_or(0));
+        let best_score = ranking
+            .last()
+            .and_then(|name| self.scores.get(name).copied())
+            .unwrap_or(0);
+
+        // Replace the worst authorities (holding at most f of the stake) with the best ones (only if they did
+        // worse).
+        let mut swapped_stake = 0;
+        let swaps: HashMap<_, _> = ranking
+            .iter()
+            .take_while(|name| {
+                swapped_stake += self.stake(name);
+                swapped_stake <= self.max_swapped_stake
+            })
+            .filter(|name| self.scores.get(name).copied().unwrap_or(0) < best_score)
+            .cloned()
+            .zip(ranking.iter().rev().cloned())
+            .collect();
+        for (bad, good) in &swaps {
+            debug!("Replacing leader {} with {}", bad, good);
+        }
+
+        self.scores.clear();
+        self.committed_leaders = 0;
+        let changed = swaps != self.swaps;
+        self.swaps = swaps;
+        changed
+    }
+}
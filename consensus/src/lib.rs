@@ -1,21 +1,48 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use config::{Committee, Stake};
+use crate::leader_schedule::LeaderSchedule;
+use config::{CommitRule, Committee, Parameters, Stake, SubDagOrder, TieBreak};
 use crypto::Hash as _;
-use crypto::{Digest, PublicKey};
-use log::{debug, info, log_enabled, warn};
-use primary::{Certificate, Round};
+use crypto::{Digest, PublicKey, ThresholdPublicKey};
+use log::{debug, error, info, log_enabled, warn};
+use primary::{
+    Certificate, CommitEvent, CommitEventBus, CommitProof, CommittedSubDag, CommittedWave,
+    ConsensusMemory, Header, LeaderSkip, LeaderSlot, OrphanedCertificate, Round, SubDagStructure,
+};
+use serde::{Deserialize, Serialize};
 use std::cmp::max;
-use std::collections::{HashMap, HashSet};
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto as _;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep, Duration, Instant};
+
+mod checkpoint;
+mod leader_schedule;
+
+pub use crate::checkpoint::Checkpoints;
 
 #[cfg(test)]
 #[path = "tests/consensus_tests.rs"]
 pub mod consensus_tests;
 
+#[cfg(test)]
+#[path = "tests/simulation_tests.rs"]
+pub mod simulation_tests;
+
 /// The representation of the DAG in memory.
 type Dag = HashMap<Round, HashMap<PublicKey, (Digest, Certificate)>>;
 
+/// The commit mode of consensus (see `fallback_after`), derived from the commit sequence.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Mode {
+    /// Whether consensus runs the asynchronous fallback (rather than its fast path).
+    fallback: bool,
+    /// The number of consecutive leader slots without a committed leader (on the fast path), or of consecutive
+    /// committed leaders (in the fallback).
+    streak: u64,
+}
+
 /// The state that needs to be persisted for crash-recovery.
+#[derive(Serialize, Deserialize)]
 struct State {
     /// The last committed round.
     last_committed_round: Round,
@@ -25,6 +52,10 @@ struct State {
     /// Keeps the latest committed certificate (and its parents) for every authority. Anything older
     /// must be regularly cleaned up through the function `update`.
     dag: Dag,
+    /// The commit mode (as of the last committed leader).
+    mode: Mode,
+    /// The authorities that committed a header signaling they are ready to switch epoch.
+    ready: HashSet<PublicKey>,
 }
 
 impl State {
@@ -38,196 +69,815 @@ impl State {
             last_committed_round: 0,
             last_committed: genesis.iter().map(|(x, (_, y))| (*x, y.round())).collect(),
             dag: [(0, genesis)].iter().cloned().collect(),
+            mode: Mode::default(),
+            ready: HashSet::new(),
         }
     }
 
-    /// Update and clean up internal state base on committed certificates.
-    fn update(&mut self, certificate: &Certificate, gc_depth: Round) {
+    /// Update the last committed rounds with a committed certificate.
+    fn update(&mut self, certificate: &Certificate) {
         self.last_committed
             .entry(certificate.origin())
             .and_modify(|r| *r = max(*r, certificate.round()))
             .or_insert_with(|| certificate.round());
 
-        let last_committed_round = *self.last_committed.values().max().unwrap();
-        self.last_committed_round = last_committed_round;
+        self.last_committed_round = *self.last_committed.values().max().unwrap();
 
-        for (name, round) in &self.last_committed {
-            self.dag.retain(|r, authorities| {
-                authorities.retain(|n, _| n != name || r >= round);
-                !authorities.is_empty() && r + gc_depth >= last_committed_round
+        if certificate.header.reconfigure {
+            self.ready.insert(certificate.origin());
+        }
+    }
+
+    /// Whether the epoch ended: a quorum of authorities committed a header signaling they are ready to switch
+    /// epoch. Nothing is committed after the sub-dag completing the quorum, so that all honest nodes switch epoch
+    /// at the same point of the sequence.
+    fn ended(&self, committee: &Committee) -> bool {
+        let stake: Stake = self.ready.iter().map(|x| committee.stake(x)).sum();
+        stake >= committee.quorum_threshold()
+    }
+
+    /// Clean up the dag: drop the certificates below the last committed round of their author, as well as all
+    /// the rounds below the gc round (they can no longer be committed). Returns the dropped certificates.
+    fn prune(&mut self, gc_depth: Round) -> Vec<OrphanedCertificate> {
+        let last_committed_round = self.last_committed_round;
+        let last_committed = &self.last_committed;
+        let mut dropped = Vec::new();
+        self.dag.retain(|r, authorities| {
+            let gc = r + gc_depth < last_committed_round;
+            authorities.retain(|name, (digest, _)| {
+                let keep = !gc && last_committed.get(name).is_none_or(|round| r >= round);
+                if !keep {
+                    dropped.push(OrphanedCertificate {
+                        round: *r,
+                        author: *name,
+                        certificate: digest.clone(),
+                    });
+                }
+                keep
             });
+            !authorities.is_empty()
+        });
+        dropped
+    }
+
+    /// Returns the memory held by the dag.
+    fn memory(&self) -> ConsensusMemory {
+        let certificates = self.dag.values().flat_map(|x| x.values());
+        ConsensusMemory {
+            rounds: self.dag.len(),
+            certificates: certificates.clone().count(),
+            bytes: certificates.map(|(_, x)| footprint(x)).sum(),
+            lowest_round: self.dag.keys().min().copied().unwrap_or_default(),
         }
     }
 }
 
+/// Returns the (approximate) number of bytes a certificate holds in the dag.
+fn footprint(certificate: &Certificate) -> usize {
+    let size = bincode::serialized_size(certificate).unwrap_or_default() as usize;
+    // The certificate is indexed by the key of its author, and stored along with its digest.
+    size + 2 * 32
+}
+
 pub struct Consensus {
     /// The committee information.
     committee: Committee,
-    /// The depth of the garbage collector.
+    /// The depth of the garbage collector of the dag (see `consensus_gc_depth`).
     gc_depth: Round,
+    /// Elects the leaders (taking into account the reputation of the authorities).
+    schedule: LeaderSchedule,
+    /// The rule deciding when a leader is committed.
+    commit_rule: CommitRule,
+    /// The stake of the certificates of the next round that must reference a leader to commit it.
+    leader_support: Stake,
+    /// The number of rounds of a wave.
+    wave_length: Round,
+    /// The number of leaders elected in each wave (at evenly spaced rounds).
+    leaders_per_wave: Round,
+    /// The order in which the certificates committed by a leader are output.
+    sub_dag_order: SubDagOrder,
+    /// The order of the certificates of the same round committed by a leader.
+    tie_break: TieBreak,
+    /// The threshold key of the committee, whose shares reveal the random coin electing the leaders (if
+    /// enabled; the leaders are otherwise elected round-robin).
+    beacon: Option<ThresholdPublicKey>,
+    /// How long to wait for the certificate of a leader before skipping it (0 to wait forever).
+    leader_timeout: u64,
+    /// The number of consecutive leader slots without a committed leader after which we fall back to the
+    /// asynchronous rule (0 to never fall back).
+    fallback_after: u64,
+    /// The number of consecutive leaders committed in the fallback after which we return to the fast path.
+    fallback_recovery: u64,
+    /// The threshold key of the committee, whose shares reveal the random coin electing the leaders in the
+    /// fallback (if any).
+    fallback_beacon: Option<ThresholdPublicKey>,
+    /// The number of waves committed on top of a sub-dag before it is final.
+    finality_depth: Round,
 
     /// Receives new certificates from the primary. The primary should send us new certificates only
     /// if it already sent us its whole history.
     rx_primary: Receiver<Certificate>,
-    /// Outputs the sequence of ordered certificates to the primary (for cleanup and feedback).
-    tx_primary: Sender<Certificate>,
-    /// Outputs the sequence of ordered certificates to the application layer.
-    tx_output: Sender<Certificate>,
+    /// Outputs the sub-dags committed by each leader to the primary (for cleanup and feedback).
+    tx_primary: Sender<CommittedSubDag>,
+    /// Outputs the sub-dags committed by each leader to the application layer.
+    tx_output: Sender<CommittedSubDag>,
+    /// Publishes the commits (along with their leader) to any interested task.
+    commit_events: CommitEventBus,
+    /// Persists the consensus state (if enabled).
+    checkpoints: Option<Checkpoints>,
+    /// The round of the last leader whose sub-dag was output (the sub-dags up to it, committed again after a
+    /// restart, are not output again).
+    last_output: Round,
+    /// The sub-dags output but not yet final (in commit order, persisted along with the last output leader).
+    unfinalized: VecDeque<CommittedSubDag>,
 
     /// The genesis certificates.
     genesis: Vec<Certificate>,
+    /// The time each certificate was delivered to us (until it is committed or garbage collected), along with
+    /// its round.
+    delivered: HashMap<Digest, (Round, Instant)>,
+    /// The round of the leader whose certificate we are waiting for (and the leader), along with the time we
+    /// started waiting.
+    waiting: Option<(Round, PublicKey, Instant)>,
+    /// The highest round whose leader we skipped or committed.
+    skipped: Round,
+    /// The memory held by the dag (published after each certificate).
+    memory: ConsensusMemory,
 }
 
 impl Consensus {
     pub fn spawn(
         committee: Committee,
-        gc_depth: Round,
+        parameters: Parameters,
+        checkpoints: Option<Checkpoints>,
         rx_primary: Receiver<Certificate>,
-        tx_primary: Sender<Certificate>,
-        tx_output: Sender<Certificate>,
+        tx_primary: Sender<CommittedSubDag>,
+        tx_output: Sender<CommittedSubDag>,
+        commit_events: CommitEventBus,
     ) {
         tokio::spawn(async move {
-            Self {
-                committee: committee.clone(),
-                gc_depth,
+            Self::new(
+                committee,
+                &parameters,
+                checkpoints,
                 rx_primary,
                 tx_primary,
                 tx_output,
-                genesis: Certificate::genesis(&committee),
-            }
+                commit_events,
+            )
             .run()
             .await;
         });
     }
 
+    fn new(
+        committee: Committee,
+        parameters: &Parameters,
+        checkpoints: Option<Checkpoints>,
+        rx_primary: Receiver<Certificate>,
+        tx_primary: Sender<CommittedSubDag>,
+        tx_output: Sender<CommittedSubDag>,
+        commit_events: CommitEventBus,
+    ) -> Self {
+        let wave_length = parameters.wave_length.max(1);
+        let beacon = match parameters.random_leaders {
+            true => committee.threshold_key.clone(),
+            false => None,
+        };
+        if parameters.random_leaders && beacon.is_none() {
+            warn!("The committee has no threshold key: electing the leaders round-robin");
+        }
+        if parameters.fallback_after > 0 && committee.threshold_key.is_none() {
+            warn!("The committee has no threshold key: electing the leaders of the fallback round-robin");
+        }
+        let leader_support = parameters.leader_support.stake(&committee);
+        if !parameters.leader_support.is_safe(&committee) {
+            warn!(
+                "Leader support {:?} is out of the safe bounds: using a stake of {}",
+                parameters.leader_support, leader_support
+            );
+        }
+        Self {
+            schedule: LeaderSchedule::new(&committee, parameters.reputation_window),
+            fallback_beacon: committee.threshold_key.clone(),
+            genesis: Certificate::genesis(&committee),
+            committee,
+            gc_depth: match parameters.consensus_gc_depth {
+                0 => parameters.gc_depth,
+                depth => depth.min(parameters.gc_depth),
+            },
+            commit_rule: parameters.commit_rule,
+            leader_support,
+            wave_length,
+            leaders_per_wave: parameters.wave_leaders().clamp(1, wave_length),
+            sub_dag_order: parameters.sub_dag_order,
+            tie_break: parameters.tie_break,
+            beacon,
+            leader_timeout: parameters.leader_timeout,
+            fallback_after: parameters.fallback_after,
+            fallback_recovery: parameters.fallback_recovery.max(1),
+            finality_depth: parameters.finality_depth,
+            rx_primary,
+            tx_primary,
+            tx_output,
+            commit_events,
+            checkpoints,
+            last_output: 0,
+            unfinalized: VecDeque::new(),
+            delivered: HashMap::new(),
+            waiting: None,
+            skipped: 0,
+            memory: ConsensusMemory::default(),
+        }
+    }
+
+    /// Run the commit rule offline over the specified certificates (in the order given, eg. by round) and
+    /// return the resulting commit sequence. Since all honest authorities commit the same sequence whatever
+    /// the order in which they receive the certificates, replaying the certificates persisted by a primary
+    /// must reproduce the commits it made (it is a debugging and regression tool for the commit rules).
+    pub fn replay(
+        committee: Committee,
+        parameters: &Parameters,
+        certificates: Vec<Certificate>,
+    ) -> Vec<(Certificate, CommitEvent)> {
+        // The replay does not use the channels, nor waits for missing leaders.
+        let (_, rx_primary) = channel(1);
+        let (tx_primary, _) = channel(1);
+        let (tx_output, _) = channel(1);
+        let parameters = Parameters {
+            leader_timeout: 0,
+            ..parameters.clone()
+        };
+        let mut consensus = Self::new(
+            committee,
+            &parameters,
+            None,
+            rx_primary,
+            tx_primary,
+            tx_output,
+            CommitEventBus::new(),
+        );
+
+        let mut state = State::new(consensus.genesis.clone());
+        consensus.memory = state.memory();
+        let mut sequence = Vec::new();
+        for certificate in certificates {
+            // The genesis is already part of the dag.
+            if certificate.round() == 0 {
+                continue;
+            }
+            sequence.extend(consensus.process(&mut state, certificate));
+        }
+        sequence
+    }
+
+    /// Returns the state of the last checkpoint (if any), caught up with the certificates stored since (which the
+    /// primary does not send us again after a restart).
+    async fn restore(&mut self) -> State {
+        let checkpoints = match self.checkpoints.as_mut() {
+            Some(checkpoints) => checkpoints,
+            None => return State::new(self.genesis.clone()),
+        };
+        let mut state = match checkpoints.read().await {
+            Ok(Some((state, schedule))) => {
+                info!(
+                    "Resuming consensus from round {}",
+                    state.last_committed_round
+                );
+                self.schedule = schedule;
+                state
+            }
+            Ok(None) => State::new(self.genesis.clone()),
+            Err(e) => {
+                error!("Failed to read the consensus checkpoint: {}", e);
+                State::new(self.genesis.clone())
+            }
+        };
+
+        self.memory = state.memory();
+
+        // Do not output again the commits made before the restart.
+        match checkpoints.last_output().await {
+            Ok(Some((round, digest, unfinalized))) => {
+                info!(
+                    "Already output the commits up to leader {} of round {}",
+                    digest, round
+                );
+                self.last_output = round;
+                self.unfinalized = unfinalized;
+            }
+            Ok(None) => (),
+            Err(e) => error!("Failed to read the last output leader: {}", e),
+        }
+
+        // Skip the certificates we already have, and those that can no longer be committed.
+        let from = state
+            .last_committed_round
+            .saturating_sub(self.gc_depth)
+            .max(1);
+        let certificates = checkpoints.stored_from(from).await;
+        for certificate in certificates {
+            let known = state
+                .dag
+                .get(&certificate.round())
+                .is_some_and(|x| x.contains_key(&certificate.origin()));
+            let committed = state
+                .last_committed
+                .get(&certificate.origin())
+                .is_some_and(|r| *r >= certificate.round());
+            if !known && !committed {
+                let sequence = self.process(&mut state, certificate);
+                self.output(&state, sequence).await;
+            }
+        }
+        state
+    }
+
+    /// Output the sequence of committed certificates (in order), and checkpoint the resulting state.
+    async fn output(&mut self, state: &State, sequence: Vec<(Certificate, CommitEvent)>) {
+        if sequence.is_empty() {
+            return;
+        }
+        for (sub_dag, events) in Self::sub_dags(sequence) {
+            // Do not output again the sub-dags output before a restart. A sub-dag is recorded only once handed
+            // over, so a crash in between outputs it again after the restart (rather than never).
+            if sub_dag.round() <= self.last_output {
+                debug!("Already output the sub-dag of {:?}", sub_dag.leader);
+                continue;
+            }
+            self.last_output = sub_dag.round();
+
+            for certificate in &sub_dag.certificates {
+                #[cfg(not(feature = "benchmark"))]
+                info!("Committed {}", certificate.header);
+
+                #[cfg(feature = "benchmark")]
+                for digest in certificate.header.payload.keys() {
+                    // NOTE: This log entry is used to compute performance.
+                    info!("Committed {} -> {:?}", certificate.header, digest);
+                }
+            }
+
+            let structure = SubDagStructure::new(&sub_dag);
+            let wave = CommittedWave {
+                wave: sub_dag.round() / self.wave_length,
+                leader: sub_dag.leader.origin(),
+                round: sub_dag.round(),
+                certificates: sub_dag.len(),
+            };
+            let leader = sub_dag.leader.clone();
+            let round = sub_dag.round();
+            self.unfinalized.push_back(sub_dag.clone());
+            self.tx_primary
+                .send(sub_dag.clone())
+                .await
+                .expect("Failed to send committed sub-dag to primary");
+
+            if let Err(e) = self.tx_output.send(sub_dag).await {
+                warn!("Failed to output committed sub-dag: {}", e);
+            }
+            for event in events {
+                self.commit_events.publish(event);
+            }
+            self.commit_events.publish_wave(wave);
+            self.commit_events.publish_sub_dag(structure);
+            self.finalize(round);
+            if let Some(checkpoints) = self.checkpoints.as_mut() {
+                checkpoints.output(&leader, &self.unfinalized).await;
+            }
+        }
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            checkpoints.committed(state, &self.schedule).await;
+        }
+    }
+
+    /// Publish as final the sub-dags with `finality_depth` waves committed on top of them, now that the leader
+    /// of the specified round is committed. Like the output, a crash may publish a final sub-dag again but never
+    /// skips one (the sub-dags pending finality are restored along with the last output leader).
+    fn finalize(&mut self, round: Round) {
+        let wave = round / self.wave_length;
+        while let Some(sub_dag) = self.unfinalized.front() {
+            if sub_dag.round() / self.wave_length + self.finality_depth > wave {
+                break;
+            }
+            let sub_dag = self.unfinalized.pop_front().unwrap();
+            debug!("Finalized the sub-dag of {:?}", sub_dag.leader);
+            self.commit_events.publish_finalized(sub_dag);
+        }
+    }
+
+    /// Split the commit sequence into the sub-dags committed by each leader (along with their commit events).
+    fn sub_dags(
+        sequence: Vec<(Certificate, CommitEvent)>,
+    ) -> Vec<(CommittedSubDag, Vec<CommitEvent>)> {
+        let mut sub_dags = Vec::new();
+        let mut certificates = Vec::new();
+        let mut events = Vec::new();
+        let mut sequence = sequence.into_iter().peekable();
+        while let Some((certificate, event)) = sequence.next() {
+            let (round, name) = (event.leader_round, event.leader);
+            certificates.push(certificate);
+            events.push(event);
+
+            // The certificates of a sub-dag are contiguous in the sequence, and include their leader.
+            if sequence.peek().is_none_or(|(_, x)| x.leader_round != round) {
+                let leader = certificates
+                    .iter()
+                    .find(|x| x.round() == round && x.origin() == name)
+                    .cloned()
+                    .expect("Committed sub-dag without its leader");
+                let sub_dag = CommittedSubDag {
+                    leader,
+                    certificates: std::mem::take(&mut certificates),
+                };
+                sub_dags.push((sub_dag, std::mem::take(&mut events)));
+            }
+        }
+        sub_dags
+    }
+
     async fn run(&mut self) {
         // The consensus state (everything else is immutable).
-        let mut state = State::new(self.genesis.clone());
+        let mut state = self.restore().await;
+        self.memory = state.memory();
+
+        let timer = sleep(Duration::from_millis(self.leader_timeout));
+        tokio::pin!(timer);
 
         // Listen to incoming certificates.
-        while let Some(certificate) = self.rx_primary.recv().await {
-            debug!("Processing {:?}", certificate);
-            let round = certificate.round();
+        loop {
+            tokio::select! {
+                Some(certificate) = self.rx_primary.recv() => {
+                    let sequence = self.process(&mut state, certificate);
+                    self.commit_events.publish_memory(self.memory.clone());
+                    self.output(&state, sequence).await;
+                },
+
+                // Stop waiting for the certificate of the leader.
+                () = &mut timer, if self.waiting.is_some() => {
+                    if let Some((round, leader, since)) = self.waiting.take() {
+                        self.skip(round, leader, since);
+                    }
+                },
+
+                else => break,
+            }
 
-            // Add the new certificate to the local storage.
-            state
-                .dag
-                .entry(round)
-                .or_insert_with(HashMap::new)
-                .insert(certificate.origin(), (certificate.digest(), certificate));
+            // Reschedule the timer for the leader we are waiting for (if any).
+            if let Some((_, _, since)) = self.waiting {
+                let deadline = since + Duration::from_millis(self.leader_timeout);
+                timer.as_mut().reset(deadline);
+            }
+        }
+    }
+
+    /// Start waiting for the certificate of the leader of the specified round (if we do not already), skipping
+    /// the leader we were waiting for until now: the dag moved on without it.
+    fn wait_for(&mut self, round: Round, leader: PublicKey) {
+        if self.leader_timeout == 0 || round <= self.skipped {
+            return;
+        }
+        match self.waiting {
+            Some((r, _, _)) if r >= round => return,
+            Some((r, l, since)) => self.skip(r, l, since),
+            None => (),
+        }
+        self.waiting = Some((round, leader, Instant::now()));
+    }
 
-            // Try to order the dag to commit. Start from the highest round for which we have at least
-            // 2f+1 certificates. This is because we need them to reveal the common coin.
-            let r = round - 1;
+    /// Record that we stopped waiting for the certificate of the leader of the specified round. This does not
+    /// change the commit sequence (a later leader may still commit it, if linked to it).
+    fn skip(&mut self, round: Round, leader: PublicKey, since: Instant) {
+        self.skipped = max(self.skipped, round);
+        let skip = LeaderSkip {
+            round,
+            leader,
+            waited: since.elapsed().as_secs_f64() * 1_000.0,
+        };
+        warn!(
+            "Skipped leader {} of round {} after {:.0} ms",
+            skip.leader, skip.round, skip.waited
+        );
+        self.commit_events.publish_skip(skip);
+    }
 
-            // We only elect leaders for even round numbers.
-            if r % 2 != 0 || r < 4 {
-                continue;
+    /// Add a certificate to the dag and return the sequence of certificates it allows to commit (along with
+    /// their commit event), in commit order.
+    fn process(
+        &mut self,
+        state: &mut State,
+        certificate: Certificate,
+    ) -> Vec<(Certificate, CommitEvent)> {
+        debug!("Processing {:?}", certificate);
+        let round = certificate.round();
+
+        // Add the new certificate to the local storage.
+        self.delivered
+            .insert(certificate.digest(), (round, Instant::now()));
+        self.memory.certificates += 1;
+        self.memory.bytes += footprint(&certificate);
+        let replaced = state
+            .dag
+            .entry(round)
+            .or_insert_with(HashMap::new)
+            .insert(certificate.origin(), (certificate.digest(), certificate));
+        if let Some((_, x)) = replaced {
+            self.memory.certificates -= 1;
+            self.memory.bytes -= footprint(&x);
+        }
+        self.memory.rounds = state.dag.len();
+
+        // Try to order the dag to commit (unless the epoch ended).
+        let mut sequence = Vec::new();
+        while let Some((leader_round, support_round)) = self.commit_round(round, state) {
+            if state.ended(&self.committee) {
+                break;
             }
 
-            // Get the certificate's digest of the leader of round r-2. If we already ordered this leader,
-            // there is nothing to do.
-            let leader_round = r - 2;
+            // Get the certificate's digest of the leader. If we already ordered this leader, there is nothing
+            // to do.
             if leader_round <= state.last_committed_round {
-                continue;
+                break;
             }
-            let (leader_digest, leader) = match self.leader(leader_round, &state.dag) {
+            let (leader_digest, leader) = match self.leader(leader_round, state) {
                 Some(x) => x,
-                None => continue,
+                None => {
+                    if let Some(leader) = self.elect(leader_round, state) {
+                        self.wait_for(leader_round, leader);
+                    }
+                    break;
+                }
             };
 
-            // Check if the leader has f+1 support from its children (ie. round r-1).
-            let stake: Stake = state
+            // Check if the leader has enough support from its children (f+1 by default).
+            let support: Vec<_> = state
                 .dag
-                .get(&(r - 1))
+                .get(&support_round)
                 .expect("We should have the whole history by now")
                 .values()
-                .filter(|(_, x)| x.header.parents.contains(&leader_digest))
-                .map(|(_, x)| self.committee.stake(&x.origin()))
+                .filter(|(_, x)| x.header.parents.contains(leader_digest))
+                .map(|(_, x)| x)
+                .collect();
+            let stake: Stake = support
+                .iter()
+                .map(|x| self.committee.stake(&x.origin()))
                 .sum();
 
             // If it is the case, we can commit the leader. But first, we need to recursively go back to
             // the last committed leader, and commit all preceding leaders in the right order. Committing
             // a leader block means committing all its dependencies.
-            if stake < self.committee.validity_threshold() {
+            if stake < self.leader_support {
                 debug!("Leader {:?} does not have enough support", leader);
-                continue;
+                break;
             }
 
             // Get an ordered list of past leaders that are linked to the current leader.
             debug!("Leader {:?} has enough support", leader);
-            let mut sequence = Vec::new();
-            for leader in self.order_leaders(leader, &state).iter().rev() {
+            self.commit_events.publish_proof(CommitProof {
+                leader: leader.clone(),
+                support: support.into_iter().cloned().collect(),
+            });
+            let mut changed = false;
+            for leader in self.order_leaders(leader, state).iter().rev() {
+                // Resolve the leader slots since the previous committed leader: only this one is honored.
+                let skipped = self.resolve_slots(leader, state);
+
                 // Starting from the oldest leader, flatten the sub-dag referenced by the leader.
-                for x in self.order_dag(leader, &state) {
-                    // Update and clean up internal state.
-                    state.update(&x, self.gc_depth);
+                for x in self.order_dag(leader, state) {
+                    // Update internal state.
+                    state.update(&x);
+                    self.schedule.record(&x);
+
+                    // Add the certificate to the sequence (along with the wave of its leader).
+                    let digest = x.digest();
+                    let latency = self
+                        .delivered
+                        .remove(&digest)
+                        .map_or(0.0, |(_, t)| t.elapsed().as_secs_f64() * 1_000.0);
+                    let event = CommitEvent {
+                        round: x.round(),
+                        wave: leader.round() / self.wave_length,
+                        leader: leader.origin(),
+                        leader_round: leader.round(),
+                        author: x.origin(),
+                        certificate: digest,
+                        latency,
+                    };
+                    sequence.push((x, event));
+                }
 
-                    // Add the certificate to the sequence.
-                    sequence.push(x);
+                // Clean up the dag below the new gc round. The dropped certificates that were not committed are
+                // orphans: they can no longer be committed.
+                for dropped in state.prune(self.gc_depth) {
+                    if self.delivered.remove(&dropped.certificate).is_some() {
+                        self.commit_events.publish_orphan(dropped);
+                    }
+                }
+
+                // The epoch ends with this sub-dag.
+                if state.ended(&self.committee) {
+                    info!(
+                        "Epoch {} ended with the sub-dag of {:?}",
+                        self.committee.epoch, leader
+                    );
+                    changed = false;
+                    break;
                 }
-            }
 
-            // Log the latest committed round of every authority (for debug).
-            if log_enabled!(log::Level::Debug) {
-                for (name, round) in &state.last_committed {
-                    debug!("Latest commit of {}: Round {}", name, round);
+                // If the schedule or the commit mode changed, the next leaders must be elected anew.
+                let mode_changed = self.update_mode(state, skipped);
+                changed = self.schedule.commit_leader() || mode_changed;
+                if changed {
+                    break;
                 }
             }
+            if !changed {
+                break;
+            }
+        }
 
-            // Output the sequence in the right order.
-            for certificate in sequence {
-                #[cfg(not(feature = "benchmark"))]
-                info!("Committed {}", certificate.header);
+        // Stop waiting for the leaders we committed.
+        self.skipped = max(self.skipped, state.last_committed_round);
+        if self
+            .waiting
+            .is_some_and(|(r, _, _)| r <= state.last_committed_round)
+        {
+            self.waiting = None;
+        }
 
-                #[cfg(feature = "benchmark")]
-                for digest in certificate.header.payload.keys() {
-                    // NOTE: This log entry is used to compute performance.
-                    info!("Committed {} -> {:?}", certificate.header, digest);
-                }
+        // Forget the delivery time of the garbage collected certificates, and account for the memory freed.
+        if !sequence.is_empty() {
+            let gc_round = state.last_committed_round.saturating_sub(self.gc_depth);
+            self.delivered.retain(|_, (r, _)| *r >= gc_round);
+            self.memory = state.memory();
+        }
+
+        // Log the latest committed round of every authority (for debug).
+        if log_enabled!(log::Level::Debug) {
+            for (name, round) in &state.last_committed {
+                debug!("Latest commit of {}: Round {}", name, round);
+            }
+        }
+
+        sequence
+    }
+
+    /// Returns the number of rounds between two leaders.
+    fn leader_period(&self) -> Round {
+        self.wave_length / self.leaders_per_wave
+    }
 
-                self.tx_primary
-                    .send(certificate.clone())
-                    .await
-                    .expect("Failed to send certificate to primary");
+    /// Returns the rule committing the leaders in the current mode: the asynchronous Tusk rule in the fallback.
+    fn commit_rule(&self, state: &State) -> CommitRule {
+        match state.mode.fallback {
+            true => CommitRule::Tusk,
+            false => self.commit_rule,
+        }
+    }
+
+    /// Returns the threshold key whose shares reveal the coin electing the leaders in the current mode (if any).
+    fn beacon(&self, state: &State) -> Option<&ThresholdPublicKey> {
+        match state.mode.fallback {
+            true => self.fallback_beacon.as_ref(),
+            false => self.beacon.as_ref(),
+        }
+    }
 
-                if let Err(e) = self.tx_output.send(certificate).await {
-                    warn!("Failed to output certificate: {}", e);
+    /// Update the commit mode with a committed leader, preceded by the specified number of leader slots without
+    /// a committed leader. Returns whether the mode changed.
+    fn update_mode(&self, state: &mut State, skipped: u64) -> bool {
+        if self.fallback_after == 0 {
+            return false;
+        }
+        let mode = &mut state.mode;
+        match mode.fallback {
+            false => {
+                mode.streak = skipped;
+                if mode.streak < self.fallback_after {
+                    return false;
+                }
+                warn!(
+                    "Falling back to the asynchronous commit rule after {} leader slots without commit",
+                    mode.streak
+                );
+            }
+            true => {
+                mode.streak = match skipped {
+                    0 => mode.streak + 1,
+                    _ => 1,
+                };
+                if mode.streak < self.fallback_recovery {
+                    return false;
                 }
+                info!(
+                    "Returning to the fast path after {} committed leaders",
+                    mode.streak
+                );
             }
         }
+        mode.fallback = !mode.fallback;
+        mode.streak = 0;
+        true
+    }
+
+    /// Returns the round of the leader to try to commit upon receiving a certificate of the specified round,
+    /// along with the round of the certificates supporting it (if any).
+    fn commit_round(&self, round: Round, state: &State) -> Option<(Round, Round)> {
+        let (leader_round, support_round) = match self.commit_rule(state) {
+            // Start from the highest round for which we have at least 2f+1 certificates (r). This is because
+            // we need them to reveal the common coin: the leader of the previous wave (round r-w, with w the
+            // length of a wave) is supported by the certificates of the next round.
+            CommitRule::Tusk => {
+                let leader_round = round.checked_sub(self.wave_length + 1)?;
+                (leader_round, leader_round + 1)
+            }
+            // The leader of the previous round is supported by the certificates of this round: there is no
+            // coin to reveal (the leaders are known in advance).
+            CommitRule::Bullshark => (round.checked_sub(1)?, round),
+        };
+
+        // We only elect leaders every `leader_period` rounds.
+        let period = self.leader_period();
+        (leader_round % period == 0 && leader_round >= period)
+            .then_some((leader_round, support_round))
     }
 
     /// Returns the certificate (and the certificate's digest) originated by the leader of the
     /// specified round (if any).
-    fn leader<'a>(&self, round: Round, dag: &'a Dag) -> Option<&'a (Digest, Certificate)> {
-        // TODO: We should elect the leader of round r-2 using the common coin revealed at round r.
-        // At this stage, we are guaranteed to have 2f+1 certificates from round r (which is enough to
-        // compute the coin). We currently just use round-robin.
-        #[cfg(test)]
-        let coin = 0;
-        #[cfg(not(test))]
-        let coin = round;
-
+    fn leader<'a>(&self, round: Round, state: &'a State) -> Option<&'a (Digest, Certificate)> {
         // Elect the leader.
-        let mut keys: Vec<_> = self.committee.authorities.keys().cloned().collect();
-        keys.sort();
-        let leader = keys[coin as usize % self.committee.size()];
+        let leader = self.elect(round, state)?;
 
         // Return its certificate and the certificate's digest.
-        dag.get(&round).map(|x| x.get(&leader)).flatten()
+        state.dag.get(&round).map(|x| x.get(&leader)).flatten()
+    }
+
+    /// Returns the leader of the specified round (if we know the coin electing it).
+    fn elect(&self, round: Round, state: &State) -> Option<PublicKey> {
+        let coin = match self.beacon(state) {
+            Some(beacon) => self.coin(beacon, round, state)?,
+            // Round-robin (in proportion to the stake) without a random coin.
+            None => round,
+        };
+        Some(self.schedule.leader(coin))
+    }
+
+    /// Returns the random coin electing the leader of the specified round. With the Tusk rule, the coin is
+    /// revealed by the certificates of the first round of the next wave (so that the leader is only known once
+    /// the round is over); the Bullshark rule does not hide the leaders for a wave, the coin is revealed by the
+    /// certificates of the round itself. Any `threshold` shares yield the same coin, and the authorities try to
+    /// commit a leader only once they hold 2f+1 certificates of that round.
+    fn coin(&self, beacon: &ThresholdPublicKey, round: Round, state: &State) -> Option<Round> {
+        let reveal = match self.commit_rule(state) {
+            CommitRule::Tusk => round + self.wave_length,
+            CommitRule::Bullshark => round,
+        };
+        let shares: Vec<_> = state
+            .dag
+            .get(&reveal)?
+            .values()
+            .filter_map(|(_, x)| x.header.coin.clone())
+            .collect();
+        let seed = Header::coin_seed(self.committee.epoch, reveal);
+        let coin = beacon.coin(&seed, &shares).ok()?;
+        Some(Round::from_le_bytes(coin[..8].try_into().unwrap()))
+    }
+
+    /// Publish the leader slots from the last committed leader (exclusive) to the specified leader (about to be
+    /// committed). The leaders of the slots in between are not committed. Returns the number of these slots.
+    fn resolve_slots(&self, leader: &Certificate, state: &State) -> u64 {
+        let period = self.leader_period();
+        let mut skipped = 0;
+        for round in (state.last_committed_round + period..leader.round()).step_by(period as usize)
+        {
+            skipped += 1;
+            if let Some(elected) = self.elect(round, state) {
+                self.commit_events.publish_slot(LeaderSlot {
+                    round,
+                    leader: elected,
+                    committed: false,
+                });
+            }
+        }
+        self.commit_events.publish_slot(LeaderSlot {
+            round: leader.round(),
+            leader: leader.origin(),
+            committed: true,
+        });
+        skipped
     }
 
     /// Order the past leaders that we didn't already commit.
     fn order_leaders(&self, leader: &Certificate, state: &State) -> Vec<Certificate> {
         let mut to_commit = vec![leader.clone()];
         let mut leader = leader;
-        for r in (state.last_committed_round + 2..leader.round())
-            .rev()
-            .step_by(2)
-        {
+        let period = self.leader_period();
+        let slots: Vec<_> = (state.last_committed_round + period..leader.round())
+            .step_by(period as usize)
+            .collect();
+        for r in slots.into_iter().rev() {
             // Get the certificate proposed by the previous leader.
-            let (_, prev_leader) = match self.leader(r, &state.dag) {
+            let (_, prev_leader) = match self.leader(r, state) {
                 Some(x) => x,
                 None => continue,
             };
@@ -295,8 +945,17 @@ impl Consensus {
         // Ensure we do not commit garbage collected certificates.
         ordered.retain(|x| x.round() + self.gc_depth >= state.last_committed_round);
 
-        // Ordering the output by round is not really necessary but it makes the commit sequence prettier.
-        ordered.sort_by_key(|x| x.round());
+        // The traversal above depends on the parents we still hold: sort the certificates to output them in an
+        // order that only depends on the committed certificates themselves.
+        match (self.sub_dag_order, self.tie_break) {
+            (SubDagOrder::Round, TieBreak::Author) => {
+                ordered.sort_by_key(|x| (x.round(), x.origin()))
+            }
+            (SubDagOrder::Round, TieBreak::Digest) => {
+                ordered.sort_by_key(|x| (x.round(), x.digest()))
+            }
+            (SubDagOrder::Digest, _) => ordered.sort_by_key(|x| x.digest()),
+        }
         ordered
     }
 }
@@ -0,0 +1,55 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::consensus_tests::mock_committee;
+use std::fs;
+
+#[tokio::test]
+async fn write_read_checkpoint() {
+    let path = ".db_test_write_read_checkpoint";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let committee = mock_committee();
+    let mut state = State::new(Certificate::genesis(&committee));
+    let schedule = LeaderSchedule::new(&committee, /* window */ 0);
+
+    // Nothing is persisted until enough rounds are committed.
+    let mut checkpoints =
+        Checkpoints::new(store.clone(), /* epoch */ 0, /* interval */ 2);
+    state.last_committed_round = 1;
+    checkpoints.committed(&state, &schedule).await;
+    assert!(checkpoints.read().await.unwrap().is_none());
+
+    state.last_committed_round = 2;
+    checkpoints.committed(&state, &schedule).await;
+    let (restored, _) = checkpoints.read().await.unwrap().unwrap();
+    assert_eq!(restored.last_committed_round, 2);
+    assert_eq!(restored.last_committed, state.last_committed);
+
+    // Each epoch has its own checkpoint.
+    let mut checkpoints = Checkpoints::new(store, /* epoch */ 1, /* interval */ 2);
+    assert!(checkpoints.read().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn write_read_last_output() {
+    let path = ".db_test_write_read_last_output";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let leader = Certificate::genesis(&mock_committee()).pop().unwrap();
+    let sub_dag = CommittedSubDag {
+        leader: leader.clone(),
+        certificates: vec![leader.clone()],
+    };
+
+    // The sub-dags pending finality are restored along with the last output leader.
+    let mut checkpoints = Checkpoints::new(store, /* epoch */ 0, /* interval */ 2);
+    assert!(checkpoints.last_output().await.unwrap().is_none());
+    checkpoints
+        .output(&leader, &VecDeque::from(vec![sub_dag]))
+        .await;
+    let (round, digest, unfinalized) = checkpoints.last_output().await.unwrap().unwrap();
+    assert_eq!(round, leader.round());
+    assert_eq!(digest, leader.digest());
+    assert_eq!(unfinalized.len(), 1);
+    assert_eq!(unfinalized[0].leader.digest(), leader.digest());
+}
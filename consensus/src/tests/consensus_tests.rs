@@ -1,12 +1,15 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use config::{Authority, PrimaryAddresses};
-use crypto::{generate_keypair, SecretKey};
-use primary::Header;
+use config::{Authority, LeaderSupport, Parameters, PrimaryAddresses, TieBreak};
+use crypto::{generate_keypair, generate_threshold_keys, SecretKey};
+use primary::{ForkDetector, Header};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use store::Store;
 use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
 
 // Fixture
 fn keys() -> Vec<(PublicKey, SecretKey)> {
@@ -14,6 +17,15 @@ fn keys() -> Vec<(PublicKey, SecretKey)> {
     (0..4).map(|_| generate_keypair(&mut rng)).collect()
 }
 
+// Fixture
+pub fn mock_parameters() -> Parameters {
+    Parameters {
+        gc_depth: 50,
+        leader_timeout: 0,
+        ..Parameters::default()
+    }
+}
+
 // Fixture
 pub fn mock_committee() -> Committee {
     Committee {
@@ -29,13 +41,30 @@ pub fn mock_committee() -> Committee {
                             worker_to_primary: "0.0.0.0:0".parse().unwrap(),
                         },
                         workers: HashMap::default(),
+                        bls_public_key: None,
+                        bls_proof_of_possession: None,
                     },
                 )
             })
             .collect(),
+        epoch: 0,
+        threshold_key: None,
     }
 }
 
+// Fixture
+// Returns the leader of the specified round without a random coin (round-robin, in proportion to the stake).
+pub fn mock_leader(committee: &Committee, round: Round) -> PublicKey {
+    LeaderSchedule::new(committee, /* window */ 0).leader(round)
+}
+
+// Fixture
+// Returns the public keys of the authorities in the order they lead the rounds, starting from round 2: the
+// i-th authority leads the rounds 2 + i, 6 + i, etc.
+fn leader_keys() -> Vec<PublicKey> {
+    (2..6).map(|x| mock_leader(&mock_committee(), x)).collect()
+}
+
 // Fixture
 fn mock_certificate(
     origin: PublicKey,
@@ -54,6 +83,11 @@ fn mock_certificate(
     (certificate.digest(), certificate)
 }
 
+// Returns the rounds of the certificates committed by a leader (in commit order).
+fn rounds(sub_dag: &CommittedSubDag) -> Vec<Round> {
+    sub_dag.certificates.iter().map(|x| x.round()).collect()
+}
+
 // Creates one certificate per authority starting and finishing at the specified rounds (inclusive).
 // Outputs a VecDeque of certificates (the certificate with higher round is on the front) and a set
 // of digests to be used as parents for the certificates of the next round.
@@ -99,12 +133,19 @@ async fn commit_one() {
     let (tx_waiter, rx_waiter) = channel(1);
     let (tx_primary, mut rx_primary) = channel(1);
     let (tx_output, mut rx_output) = channel(1);
+    let commit_events = CommitEventBus::new();
+    let mut rx_events = commit_events.subscribe();
+    let mut rx_proofs = commit_events.subscribe_proofs();
+    let mut rx_sub_dags = commit_events.subscribe_sub_dags();
+    let mut rx_wave = commit_events.watch_wave();
     Consensus::spawn(
         mock_committee(),
-        /* gc_depth */ 50,
+        mock_parameters(),
+        /* checkpoints */ None,
         rx_waiter,
         tx_primary,
         tx_output,
+        commit_events,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -116,12 +157,388 @@ async fn commit_one() {
 
     // Ensure the first 4 ordered certificates are from round 1 (they are the parents of the committed
     // leader); then the leader's certificate should be committed.
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![1, 1, 1, 1, 2]);
+    let certificate = sub_dag.leader;
+    assert_eq!(sub_dag.certificates.last(), Some(&certificate));
+
+    // Ensure the commits are published along with their leader (of wave 1).
     for _ in 1..=4 {
-        let certificate = rx_output.recv().await.unwrap();
-        assert_eq!(certificate.round(), 1);
+        let event = rx_events.recv().await.unwrap();
+        assert_eq!(event.round, 1);
+        assert_eq!(event.wave, 1);
+    }
+    let event = rx_events.recv().await.unwrap();
+    assert_eq!(event.certificate, certificate.digest());
+    assert_eq!(event.leader, certificate.origin());
+
+    // Ensure the proof of the commit holds the certificates of round 3 supporting the leader.
+    let proof = rx_proofs.recv().await.unwrap();
+    assert_eq!(proof.leader.digest(), certificate.digest());
+    assert_eq!(proof.support.len(), 4);
+    assert!(proof.support.iter().all(|x| x.round() == 3));
+
+    // Ensure the structure of the sub-dag links the leader to the certificates of round 1.
+    let structure = rx_sub_dags.recv().await.unwrap();
+    assert_eq!(structure.leader_round, 2);
+    assert_eq!(structure.depth(), 2);
+    let leader = structure.vertices.last().unwrap();
+    assert_eq!(leader.certificate, certificate.digest());
+    assert!(structure.vertices[..4]
+        .iter()
+        .all(|x| leader.parents.contains(&x.certificate)));
+
+    // Ensure the wave is published along with the leader that drove it.
+    let expected = CommittedWave {
+        wave: 1,
+        leader: certificate.origin(),
+        round: 2,
+        certificates: 5,
+    };
+    assert_eq!(*rx_wave.borrow_and_update(), Some(expected));
+}
+
+// Commit the leader of round 2 (as in `commit_one`), then restart consensus from its checkpoint: the next
+// leader (of round 4) should be committed along with its uncommitted history.
+#[tokio::test]
+async fn resume_from_checkpoint() {
+    let path = ".db_test_resume_from_checkpoint";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, parents.clone());
+    certificates.push_back(certificate);
+
+    // Run consensus until it commits the leader of round 2 (and checkpoints its state).
+    let (tx_waiter, rx_waiter) = channel(10);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        mock_parameters(),
+        Some(Checkpoints::new(
+            store.clone(),
+            /* epoch */ 0,
+            /* interval */ 1,
+        )),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        CommitEventBus::new(),
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    while let Some(certificate) = certificates.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+    assert_eq!(rx_output.recv().await.unwrap().len(), 5);
+    drop(tx_waiter);
+    sleep(Duration::from_millis(50)).await;
+
+    // Restart consensus and feed it the next rounds.
+    let (tx_waiter, rx_waiter) = channel(10);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        mock_parameters(),
+        Some(Checkpoints::new(
+            store, /* epoch */ 0, /* interval */ 1,
+        )),
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        CommitEventBus::new(),
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    let (mut certificates, _) = make_certificates(5, 7, &parents, &keys);
+    while let Some(certificate) = certificates.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+
+    // The leader of round 2 is not committed again; the remaining certificates of round 2 are committed
+    // along with those of round 3 and the leader of round 4.
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![2, 2, 2, 3, 3, 3, 3, 4]);
+}
+
+// Commit the leader of round 2 (as in `commit_one`) without checkpointing the state, then restart consensus
+// and feed it the whole dag again (along with the next rounds). The leader of round 2 is committed again, but
+// its sub-dag should not be output twice.
+#[tokio::test]
+async fn no_output_after_restart() {
+    let path = ".db_test_no_output_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, parents) = make_certificates(1, 4, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 5, parents.clone());
+
+    for run in 0..2 {
+        let (tx_waiter, rx_waiter) = channel(10);
+        let (tx_primary, mut rx_primary) = channel(1);
+        let (tx_output, mut rx_output) = channel(1);
+        Consensus::spawn(
+            mock_committee(),
+            mock_parameters(),
+            Some(Checkpoints::new(
+                store.clone(),
+                /* epoch */ 0,
+                /* interval */ 100,
+            )),
+            rx_waiter,
+            tx_primary,
+            tx_output,
+            CommitEventBus::new(),
+        );
+        tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+        for certificate in certificates.iter().cloned() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+        tx_waiter.send(certificate.clone()).await.unwrap();
+
+        match run {
+            0 => assert_eq!(
+                rounds(&rx_output.recv().await.unwrap()),
+                vec![1, 1, 1, 1, 2]
+            ),
+            _ => {
+                let (mut next, _) = make_certificates(5, 7, &parents, &keys);
+                while let Some(certificate) = next.pop_front() {
+                    tx_waiter.send(certificate).await.unwrap();
+                }
+                let sub_dag = rx_output.recv().await.unwrap();
+                assert_eq!(rounds(&sub_dag), vec![2, 2, 2, 3, 3, 3, 3, 4]);
+            }
+        }
+        drop(tx_waiter);
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+// Replay offline the certificates of `commit_one` (along with the genesis, as read from storage). We should
+// get the same commit sequence.
+#[test]
+fn replay_commits() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let mut certificates = Certificate::genesis(&mock_committee());
+    let genesis = certificates
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (rounds, next_parents) = make_certificates(1, 4, &genesis, &keys);
+    certificates.extend(rounds);
+    let (_, certificate) = mock_certificate(keys[0], 5, next_parents);
+    certificates.push(certificate);
+
+    let sequence = Consensus::replay(mock_committee(), &mock_parameters(), certificates);
+    let rounds: Vec<_> = sequence.iter().map(|(x, _)| x.round()).collect();
+    assert_eq!(rounds, vec![1, 1, 1, 1, 2]);
+    let (leader, event) = sequence.last().unwrap();
+    assert_eq!(event.certificate, leader.digest());
+}
+
+// Replay a dag committing the leaders of rounds 2 and 4, where a quorum of authorities signals in round 1 that
+// it is ready to switch epoch. The epoch ends with the sub-dag of the leader of round 2: nothing is committed
+// after it.
+#[test]
+fn end_of_epoch() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let mut certificates = Certificate::genesis(&mock_committee());
+    let genesis = certificates
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let mut parents = BTreeSet::new();
+    for (i, name) in keys.iter().enumerate() {
+        let certificate = Certificate {
+            header: Header {
+                author: *name,
+                round: 1,
+                parents: genesis.clone(),
+                reconfigure: i < 3,
+                ..Header::default()
+            },
+            ..Certificate::default()
+        };
+        parents.insert(certificate.digest());
+        certificates.push(certificate);
     }
-    let certificate = rx_output.recv().await.unwrap();
+    let (rounds, next_parents) = make_certificates(2, 6, &parents, &keys);
+    certificates.extend(rounds);
+    let (_, certificate) = mock_certificate(keys[0], 7, next_parents);
+    certificates.push(certificate);
+
+    let sequence = Consensus::replay(mock_committee(), &mock_parameters(), certificates);
+    assert!(!sequence.is_empty());
+    assert!(sequence.iter().all(|(_, event)| event.leader_round == 2));
+}
+
+// Replay a dag committing the leaders of rounds 2 and 4 with both sub-dag orders. The certificates committed
+// by each leader should be output in the configured order, and both orders should commit the same certificates.
+#[test]
+fn sub_dag_order() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let mut certificates = Certificate::genesis(&mock_committee());
+    let genesis = certificates
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (rounds, next_parents) = make_certificates(1, 6, &genesis, &keys);
+    certificates.extend(rounds);
+    let (_, certificate) = mock_certificate(keys[0], 7, next_parents);
+    certificates.push(certificate);
+
+    let mut committed = Vec::new();
+    for sub_dag_order in [SubDagOrder::Round, SubDagOrder::Digest] {
+        let parameters = Parameters {
+            sub_dag_order,
+            ..mock_parameters()
+        };
+        let sequence = Consensus::replay(mock_committee(), &parameters, certificates.clone());
+        let leaders: BTreeSet<_> = sequence.iter().map(|(_, x)| x.leader_round).collect();
+        assert_eq!(leaders, [2, 4].iter().cloned().collect());
+
+        for leader_round in leaders {
+            let sub_dag: Vec<_> = sequence
+                .iter()
+                .filter(|(_, x)| x.leader_round == leader_round)
+                .map(|(x, _)| x)
+                .collect();
+            let mut sorted = sub_dag.clone();
+            match sub_dag_order {
+                SubDagOrder::Round => {
+                    sorted.sort_by_key(|x| (x.round(), x.origin()));
+                    assert_eq!(sub_dag.last().unwrap().round(), leader_round);
+                }
+                SubDagOrder::Digest => sorted.sort_by_key(|x| x.digest()),
+            }
+            assert_eq!(sub_dag, sorted);
+        }
+
+        let mut digests: Vec<_> = sequence.iter().map(|(x, _)| x.digest()).collect();
+        digests.sort();
+        committed.push(digests);
+    }
+    assert_eq!(committed[0], committed[1]);
+}
+
+// Replay the same dag on two nodes receiving the certificates in different orders (the second one lagging
+// behind). Their commit sequences should not fork, unlike the one of a faulty node reordering its commits.
+#[test]
+fn no_fork_across_nodes() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, _) = make_certificates(1, 9, &genesis, &keys);
+    let certificates: Vec<_> = certificates.into_iter().collect();
+    let reordered: Vec<_> = certificates
+        .chunks(keys.len())
+        .take(7)
+        .flat_map(|x| x.iter().rev().cloned())
+        .collect();
+
+    let first = Consensus::replay(mock_committee(), &mock_parameters(), certificates);
+    let second = Consensus::replay(mock_committee(), &mock_parameters(), reordered);
+    assert!(second.len() < first.len());
+
+    let mut detector = ForkDetector::default();
+    for (_, event) in &first {
+        assert!(detector.local(event.certificate.clone()).is_ok());
+    }
+    for (_, event) in &second {
+        assert!(detector.remote(event.certificate.clone()).is_ok());
+    }
+
+    let mut faulty: Vec<_> = first.iter().map(|(_, x)| x.certificate.clone()).collect();
+    faulty.swap(5, 6);
+    let mut detector = ForkDetector::default();
+    for (_, event) in &first {
+        assert!(detector.local(event.certificate.clone()).is_ok());
+    }
+    let fork = faulty
+        .into_iter()
+        .find_map(|x| detector.remote(x).err())
+        .unwrap();
+    assert_eq!(fork.position, 5);
+}
+
+// Run for 4 dag rounds in ideal conditions with leaders elected by the random coin (revealed by the coin
+// shares of the headers of round 4). We should commit the leader of round 2 elected by the coin.
+#[test]
+fn random_leaders() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (threshold_key, shares) = generate_threshold_keys(4, 2, &mut rng);
+    let committee = Committee {
+        threshold_key: Some(threshold_key.clone()),
+        ..mock_committee()
+    };
+
+    // Make certificates for rounds 1 to 4 carrying coin shares, and one with round 5 to trigger the commits.
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort();
+    let mut certificates = Vec::new();
+    let mut parents = Certificate::genesis(&committee)
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    for round in 1..=5 {
+        let seed = Header::coin_seed(committee.epoch, round);
+        let mut next_parents = BTreeSet::new();
+        for (name, share) in keys
+            .iter()
+            .zip(&shares)
+            .take(if round == 5 { 1 } else { 4 })
+        {
+            let certificate = Certificate {
+                header: Header {
+                    author: *name,
+                    round,
+                    parents: parents.clone(),
+                    coin: Some(share.coin_share(&seed, &mut rng).unwrap()),
+                    ..Header::default()
+                },
+                ..Certificate::default()
+            };
+            next_parents.insert(certificate.digest());
+            certificates.push(certificate);
+        }
+        parents = next_parents;
+    }
+
+    // The coin of round 4 elects the leader of round 2.
+    let reveal: Vec<_> = certificates
+        .iter()
+        .filter(|x| x.round() == 4)
+        .map(|x| x.header.coin.clone().unwrap())
+        .collect();
+    let coin = threshold_key
+        .coin(&Header::coin_seed(committee.epoch, 4), &reveal)
+        .unwrap();
+    let coin = u64::from_le_bytes(coin[..8].try_into().unwrap());
+    let leader = mock_leader(&committee, coin);
+
+    let parameters = Parameters {
+        random_leaders: true,
+        ..mock_parameters()
+    };
+    let sequence = Consensus::replay(committee, &parameters, certificates);
+    let (certificate, event) = sequence.last().unwrap();
     assert_eq!(certificate.round(), 2);
+    assert_eq!(certificate.origin(), leader);
+    assert_eq!(event.certificate, certificate.digest());
 }
 
 // Run for 8 dag rounds with one dead node node (that is not a leader). We should commit the leaders of
@@ -129,8 +546,7 @@ async fn commit_one() {
 #[tokio::test]
 async fn dead_node() {
     // Make the certificates.
-    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
-    keys.sort(); // Ensure we don't remove one of the leaders.
+    let mut keys = leader_keys(); // Ensure we don't remove one of the leaders.
     let _ = keys.pop().unwrap();
 
     let genesis = Certificate::genesis(&mock_committee())
@@ -146,10 +562,12 @@ async fn dead_node() {
     let (tx_output, mut rx_output) = channel(1);
     Consensus::spawn(
         mock_committee(),
-        /* gc_depth */ 50,
+        mock_parameters(),
+        /* checkpoints */ None,
         rx_waiter,
         tx_primary,
         tx_output,
+        CommitEventBus::new(),
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -161,21 +579,225 @@ async fn dead_node() {
     });
 
     // We should commit 3 leaders (rounds 2, 4, and 6).
-    for i in 1..=15 {
-        let certificate = rx_output.recv().await.unwrap();
-        let expected = ((i - 1) / keys.len() as u64) + 1;
-        assert_eq!(certificate.round(), expected);
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![1, 1, 1, 2]);
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![2, 2, 3, 3, 3, 4]);
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![4, 4, 5, 5, 5, 6]);
+}
+
+// Run for 20 dag rounds with one slow node whose only certificate (of round 1) is never referenced. Consensus
+// should account for the memory held by its dag, and prune (and report) the orphan once it falls below its gc
+// depth.
+#[tokio::test]
+async fn prune_dag() {
+    let mut keys = leader_keys(); // Ensure we don't remove one of the leaders.
+    let slow = keys.pop().unwrap();
+
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (_, orphan) = mock_certificate(slow, 1, genesis.clone());
+    let (certificates, _) = make_certificates(1, 20, &genesis, &keys);
+
+    for (consensus_gc_depth, lowest_round) in [(2, 15), (0, 0)] {
+        let (tx_waiter, rx_waiter) = channel(1);
+        let (tx_primary, mut rx_primary) = channel(1);
+        let (tx_output, mut rx_output) = channel(1);
+        let commit_events = CommitEventBus::new();
+        let memory = commit_events.watch_memory();
+        let mut rx_orphans = commit_events.subscribe_orphans();
+        let parameters = Parameters {
+            consensus_gc_depth,
+            ..mock_parameters()
+        };
+        Consensus::spawn(
+            mock_committee(),
+            parameters,
+            /* checkpoints */ None,
+            rx_waiter,
+            tx_primary,
+            tx_output,
+            commit_events,
+        );
+        tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+        tokio::spawn(async move { while rx_output.recv().await.is_some() {} });
+
+        tx_waiter.send(orphan.clone()).await.unwrap();
+        for certificate in certificates.iter().cloned() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        // The leader of round 16 is committed, along with the certificates of round 15 (but its own). Without
+        // a dedicated gc depth, the genesis and the orphan of the slow node are also kept.
+        let memory = memory.borrow().clone();
+        assert_eq!(memory.lowest_round, lowest_round);
+        let certificates = 2 + 3 * 5 + 2 * (lowest_round == 0) as usize;
+        assert_eq!(memory.certificates, certificates);
+        assert!(memory.bytes > 0);
+
+        // Only the orphan of the slow node is dropped without being committed (its genesis was never
+        // delivered).
+        if lowest_round > 1 {
+            let dropped = rx_orphans.try_recv().unwrap();
+            assert_eq!((dropped.round, dropped.author), (1, slow));
+            assert_eq!(dropped.certificate, orphan.digest());
+        }
+        assert!(rx_orphans.try_recv().is_err());
+    }
+}
+
+// Replay a dag whose leader of round 2 is referenced by only 2 certificates of round 3 (f+1). It should be
+// committed by the certificate of round 5 with the default support, but not when requiring a quorum.
+#[test]
+fn leader_support() {
+    let keys = leader_keys();
+
+    let mut certificates: Vec<_> = Certificate::genesis(&mock_committee());
+    let genesis = certificates
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (out, parents) = make_certificates(1, 2, &genesis, &keys);
+    let leader = out
+        .iter()
+        .find(|x| x.round() == 2 && x.origin() == keys[0])
+        .unwrap()
+        .clone();
+    certificates.extend(out);
+
+    // Round 3: Only nodes 0 and 1 link to the leader of round 2.
+    let mut next_parents = BTreeSet::new();
+    for (i, name) in keys.iter().enumerate() {
+        let mut parents = parents.clone();
+        if i >= 2 {
+            parents.remove(&leader.digest());
+        }
+        let (digest, certificate) = mock_certificate(*name, 3, parents);
+        certificates.push(certificate);
+        next_parents.insert(digest);
+    }
+
+    // Round 4: Fully connected graph. Round 5: a single certificate to trigger the commit.
+    let (out, parents) = make_certificates(4, 4, &next_parents, &keys);
+    certificates.extend(out);
+    let (_, certificate) = mock_certificate(keys[0], 5, parents);
+    certificates.push(certificate);
+
+    for (leader_support, committed) in [
+        (LeaderSupport::Validity, true),
+        (LeaderSupport::Quorum, false),
+        (LeaderSupport::Stake(1), true), // Below the safe bound: f+1.
+        (LeaderSupport::Stake(3), false),
+    ] {
+        let parameters = Parameters {
+            leader_support,
+            ..mock_parameters()
+        };
+        let sequence = Consensus::replay(mock_committee(), &parameters, certificates.clone());
+        let leaders: Vec<_> = sequence.iter().map(|(_, x)| x.leader).collect();
+        assert_eq!(leaders.contains(&leader.origin()), committed);
+        assert_eq!(sequence.is_empty(), !committed);
+    }
+}
+
+// Replay with the Bullshark rule a dag whose leader of round 4 is missing. Once the leader of round 6 is committed,
+// consensus falls back to the Tusk rule (if enabled): the leader of round 8 is then only committed by the
+// certificates of round 11. It returns to the fast path once enough leaders are committed in the fallback.
+#[test]
+fn asynchronous_fallback() {
+    let keys = leader_keys();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, parents) = make_certificates(1, 3, &genesis, &keys);
+    // Round 4: without its leader (the third authority of the schedule).
+    let others: Vec<_> = keys.iter().cloned().filter(|x| *x != keys[2]).collect();
+    let (out, parents) = make_certificates(4, 4, &parents, &others);
+    certificates.extend(out);
+    let (out, _) = make_certificates(5, 11, &parents, &keys);
+    certificates.extend(out);
+
+    // Returns the rounds of the leaders committed by the certificates up to the specified round.
+    let leaders = |fallback_after, fallback_recovery, round| {
+        let parameters = Parameters {
+            commit_rule: CommitRule::Bullshark,
+            fallback_after,
+            fallback_recovery,
+            ..mock_parameters()
+        };
+        let certificates = certificates
+            .iter()
+            .filter(|x| x.round() <= round)
+            .cloned()
+            .collect();
+        let mut rounds: Vec<_> = Consensus::replay(mock_committee(), &parameters, certificates)
+            .into_iter()
+            .map(|(_, event)| event.leader_round)
+            .collect();
+        rounds.dedup();
+        rounds
+    };
+    assert_eq!(leaders(0, 10, 10), vec![2, 6, 8]);
+    assert_eq!(leaders(1, 10, 10), vec![2, 6]);
+    assert_eq!(leaders(1, 10, 11), vec![2, 6, 8]);
+    assert_eq!(leaders(1, 1, 11), vec![2, 6, 8, 10]);
+}
+
+// Replay a dag whose leader of round 2 is only referenced by some of the certificates of round 3, with a
+// committee where the last authority holds 4 of the 7 units of stake. The support is weighted by stake: the
+// leader is committed when supported by the last authority alone (f+1 is 3), but not by two others.
+#[test]
+fn stake_weighted_support() {
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort();
+    let mut committee = mock_committee();
+    committee.authorities.get_mut(&keys[3]).unwrap().stake = 4;
+
+    for (supporters, committed) in [(vec![keys[3]], true), (vec![keys[1], keys[2]], false)] {
+        let mut certificates: Vec<_> = Certificate::genesis(&committee);
+        let genesis = certificates
+            .iter()
+            .map(|x| x.digest())
+            .collect::<BTreeSet<_>>();
+        let (out, parents) = make_certificates(1, 2, &genesis, &keys);
+        let leader = out
+            .iter()
+            .find(|x| x.round() == 2 && x.origin() == mock_leader(&committee, 2))
+            .unwrap()
+            .clone();
+        certificates.extend(out);
+
+        // Round 3: only the supporters link to the leader of round 2.
+        let mut next_parents = BTreeSet::new();
+        for name in &keys {
+            let mut parents = parents.clone();
+            if !supporters.contains(name) {
+                parents.remove(&leader.digest());
+            }
+            let (digest, certificate) = mock_certificate(*name, 3, parents);
+            certificates.push(certificate);
+            next_parents.insert(digest);
+        }
+        let (out, parents) = make_certificates(4, 4, &next_parents, &keys);
+        certificates.extend(out);
+        let (_, certificate) = mock_certificate(keys[0], 5, parents);
+        certificates.push(certificate);
+
+        let sequence = Consensus::replay(committee.clone(), &mock_parameters(), certificates);
+        assert_eq!(!sequence.is_empty(), committed);
     }
-    let certificate = rx_output.recv().await.unwrap();
-    assert_eq!(certificate.round(), 6);
 }
 
 // Run for 6 dag rounds. The leaders of round 2 does not have enough support, but the leader of
 // round 4 does. The leader of rounds 2 and 4 should thus be committed upon entering round 6.
 #[tokio::test]
 async fn not_enough_support() {
-    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
-    keys.sort();
+    let keys = leader_keys();
 
     let genesis = Certificate::genesis(&mock_committee())
         .iter()
@@ -212,7 +834,7 @@ async fn not_enough_support() {
     next_parents.insert(digest);
 
     let name = &keys[0];
-    parents.insert(leader_2_digest);
+    parents.insert(leader_2_digest.clone());
     let (digest, certificate) = mock_certificate(*name, 3, parents.clone());
     certificates.push_back(certificate);
     next_parents.insert(digest);
@@ -234,10 +856,12 @@ async fn not_enough_support() {
     let (tx_output, mut rx_output) = channel(1);
     Consensus::spawn(
         mock_committee(),
-        /* gc_depth */ 50,
+        mock_parameters(),
+        /* checkpoints */ None,
         rx_waiter,
         tx_primary,
         tx_output,
+        CommitEventBus::new(),
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -248,28 +872,18 @@ async fn not_enough_support() {
     }
 
     // We should commit 2 leaders (rounds 2 and 4).
-    for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
-        assert_eq!(certificate.round(), 1);
-    }
-    for _ in 1..=4 {
-        let certificate = rx_output.recv().await.unwrap();
-        assert_eq!(certificate.round(), 2);
-    }
-    for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
-        assert_eq!(certificate.round(), 3);
-    }
-    let certificate = rx_output.recv().await.unwrap();
-    assert_eq!(certificate.round(), 4);
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![1, 1, 1, 2]);
+    assert_eq!(sub_dag.leader.digest(), leader_2_digest);
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![2, 2, 2, 3, 3, 3, 4]);
 }
 
 // Run for 6 dag rounds. Node 0 (the leader of round 2) is missing for rounds 1 and 2,
 // and reapers from round 3.
 #[tokio::test]
 async fn missing_leader() {
-    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
-    keys.sort();
+    let keys = leader_keys();
 
     let genesis = Certificate::genesis(&mock_committee())
         .iter()
@@ -295,12 +909,16 @@ async fn missing_leader() {
     let (tx_waiter, rx_waiter) = channel(1);
     let (tx_primary, mut rx_primary) = channel(1);
     let (tx_output, mut rx_output) = channel(1);
+    let commit_events = CommitEventBus::new();
+    let mut rx_slots = commit_events.subscribe_slots();
     Consensus::spawn(
         mock_committee(),
-        /* gc_depth */ 50,
+        mock_parameters(),
+        /* checkpoints */ None,
         rx_waiter,
         tx_primary,
         tx_output,
+        commit_events,
     );
     tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
 
@@ -310,19 +928,327 @@ async fn missing_leader() {
         tx_waiter.send(certificate).await.unwrap();
     }
 
-    // Ensure the commit sequence is as expected.
-    for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
-        assert_eq!(certificate.round(), 1);
+    // Ensure the commit sequence is as expected (the leader of round 4 commits the whole dag).
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![1, 1, 1, 2, 2, 2, 3, 3, 3, 3, 4]);
+
+    // The slot of the missing leader was not honored.
+    let slot = rx_slots.recv().await.unwrap();
+    assert_eq!(
+        (slot.round, slot.leader, slot.committed),
+        (2, keys[0], false)
+    );
+    let slot = rx_slots.recv().await.unwrap();
+    assert_eq!(
+        (slot.round, slot.leader, slot.committed),
+        (4, keys[2], true)
+    );
+}
+
+// Node 0 (the leader of round 2) is missing for rounds 1 and 2. Consensus should stop waiting for it
+// after the leader timeout, and record the skip.
+#[tokio::test]
+async fn skip_missing_leader() {
+    let keys = leader_keys();
+
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+
+    // Remove the leader for rounds 1 and 2, and add it back for rounds 3 and 4.
+    let nodes: Vec<_> = keys.iter().cloned().skip(1).collect();
+    let (mut certificates, parents) = make_certificates(1, 2, &genesis, &nodes);
+    let (out, parents) = make_certificates(3, 4, &parents, &keys);
+    certificates.extend(out);
+
+    // Add a certificate of round 5 to try to commit the leader of round 2.
+    let (_, certificate) = mock_certificate(keys[0], 5, parents);
+    certificates.push_back(certificate);
+
+    // Spawn the consensus engine and sink the primary channel.
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, _rx_output) = channel(1);
+    let commit_events = CommitEventBus::new();
+    let mut rx_skips = commit_events.subscribe_skips();
+    Consensus::spawn(
+        mock_committee(),
+        Parameters {
+            leader_timeout: 100,
+            ..mock_parameters()
+        },
+        /* checkpoints */ None,
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        commit_events,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+    // Feed all certificates to the consensus.
+    while let Some(certificate) = certificates.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+
+    // Ensure the leader of round 2 is skipped after the timeout.
+    let skip = rx_skips.recv().await.unwrap();
+    assert_eq!(skip.round, 2);
+    assert_eq!(skip.leader, keys[0]);
+    assert!(skip.waited >= 100.0);
+}
+
+// Run for 3 dag rounds in ideal conditions with the Bullshark rule. We should commit the leader of round 2 as
+// soon as f+1 certificates of round 3 reference it.
+#[tokio::test]
+async fn bullshark_commit_one() {
+    // Make certificates for rounds 1 to 3.
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 3, &genesis, &keys);
+
+    // Spawn the consensus engine and sink the primary channel.
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    Consensus::spawn(
+        mock_committee(),
+        Parameters {
+            commit_rule: CommitRule::Bullshark,
+            ..mock_parameters()
+        },
+        /* checkpoints */ None,
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        CommitEventBus::new(),
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+    // Feed all certificates to the consensus.
+    tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_front() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+
+    // Ensure the first 4 ordered certificates are from round 1; then the leader of round 2 is committed.
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![1, 1, 1, 1, 2]);
+}
+
+// Run for 4 dag rounds in ideal conditions with pipelined leaders (and the Bullshark rule). We should commit
+// the leaders of rounds 1, 2, and 3 one after the other.
+#[tokio::test]
+async fn pipelined_leaders() {
+    // Make certificates for rounds 1 to 4.
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, _) = make_certificates(1, 4, &genesis, &keys);
+
+    // Spawn the consensus engine and sink the primary channel.
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    let commit_events = CommitEventBus::new();
+    let mut rx_events = commit_events.subscribe();
+    Consensus::spawn(
+        mock_committee(),
+        Parameters {
+            commit_rule: CommitRule::Bullshark,
+            leaders_per_wave: 2,
+            ..mock_parameters()
+        },
+        /* checkpoints */ None,
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        commit_events,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+    tokio::spawn(async move { while rx_output.recv().await.is_some() {} });
+
+    // Feed all certificates to the consensus.
+    tokio::spawn(async move {
+        while let Some(certificate) = certificates.pop_front() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+    });
+
+    // The first leader only commits its own certificate (its parents are genesis); each next leader commits
+    // the rest of the previous round along with its own certificate. The rounds are mapped to waves of two
+    // rounds.
+    let mut commits = Vec::new();
+    for _ in 0..9 {
+        let event = rx_events.recv().await.unwrap();
+        commits.push((event.round, event.wave));
+    }
+    let expected = vec![
+        (1, 0),
+        (1, 1),
+        (1, 1),
+        (1, 1),
+        (2, 1),
+        (2, 1),
+        (2, 1),
+        (2, 1),
+        (3, 1),
+    ];
+    assert_eq!(commits, expected);
+}
+
+// Run for 6 dag rounds in ideal conditions with waves of 4 rounds (and 2 leaders per wave). The certificates
+// of round 6 reveal the coin of the first wave: we should commit the leader of round 2 only upon receiving a
+// certificate of round 7.
+#[tokio::test]
+async fn longer_waves() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, next_parents) = make_certificates(1, 6, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 7, next_parents);
+    certificates.push_back(certificate);
+
+    // Spawn the consensus engine and sink the primary channel.
+    let (tx_waiter, rx_waiter) = channel(1);
+    let (tx_primary, mut rx_primary) = channel(1);
+    let (tx_output, mut rx_output) = channel(1);
+    let commit_events = CommitEventBus::new();
+    let mut rx_events = commit_events.subscribe();
+    Consensus::spawn(
+        mock_committee(),
+        Parameters {
+            wave_length: 4,
+            leaders_per_wave: 2,
+            ..mock_parameters()
+        },
+        /* checkpoints */ None,
+        rx_waiter,
+        tx_primary,
+        tx_output,
+        commit_events,
+    );
+    tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+    // Feed all certificates to the consensus. Only the last certificate should trigger commits.
+    while let Some(certificate) = certificates.pop_front() {
+        tx_waiter.send(certificate).await.unwrap();
+    }
+
+    // Ensure the leader of round 2 is committed (along with its parents) in the first wave.
+    let sub_dag = rx_output.recv().await.unwrap();
+    assert_eq!(rounds(&sub_dag), vec![1, 1, 1, 1, 2]);
+    for _ in 1..=5 {
+        let event = rx_events.recv().await.unwrap();
+        assert_eq!(event.wave, 0);
     }
-    for _ in 1..=3 {
-        let certificate = rx_output.recv().await.unwrap();
-        assert_eq!(certificate.round(), 2);
+}
+
+#[tokio::test]
+async fn finality_depth() {
+    // Make certificates for rounds 1 to 6, and one certificate with round 7 to commit the leaders of rounds
+    // 2 and 4 (of waves 1 and 2).
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (mut certificates, next_parents) = make_certificates(1, 6, &genesis, &keys);
+    let (_, certificate) = mock_certificate(keys[0], 7, next_parents);
+    certificates.push_back(certificate);
+
+    for (depth, expected) in [(0, vec![2, 4]), (1, vec![2])] {
+        // Spawn the consensus engine and sink the primary channel.
+        let (tx_waiter, rx_waiter) = channel(1_000);
+        let (tx_primary, mut rx_primary) = channel(1);
+        let (tx_output, mut rx_output) = channel(1);
+        let commit_events = CommitEventBus::new();
+        let mut rx_finalized = commit_events.subscribe_finalized();
+        Consensus::spawn(
+            mock_committee(),
+            Parameters {
+                finality_depth: depth,
+                ..mock_parameters()
+            },
+            /* checkpoints */ None,
+            rx_waiter,
+            tx_primary,
+            tx_output,
+            commit_events,
+        );
+        tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+        for certificate in certificates.iter().cloned() {
+            tx_waiter.send(certificate).await.unwrap();
+        }
+
+        // Both leaders are committed, but only the sub-dags with enough waves on top of them are final.
+        assert_eq!(rx_output.recv().await.unwrap().round(), 2);
+        assert_eq!(rx_output.recv().await.unwrap().round(), 4);
+        sleep(Duration::from_millis(50)).await;
+        let mut finalized = Vec::new();
+        while let Ok(sub_dag) = rx_finalized.try_recv() {
+            finalized.push(sub_dag.round());
+        }
+        assert_eq!(finalized, expected);
     }
-    for _ in 1..=4 {
-        let certificate = rx_output.recv().await.unwrap();
-        assert_eq!(certificate.round(), 3);
+}
+
+// Replay the same dag on two nodes receiving the certificates of each round in different orders: whatever the
+// tie break, both output byte-identical sequences.
+#[test]
+fn tie_break_across_nodes() {
+    let keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    let genesis = Certificate::genesis(&mock_committee())
+        .iter()
+        .map(|x| x.digest())
+        .collect::<BTreeSet<_>>();
+    let (certificates, next_parents) = make_certificates(1, 6, &genesis, &keys);
+    let mut certificates: Vec<_> = certificates.into_iter().collect();
+    let (_, certificate) = mock_certificate(keys[0], 7, next_parents);
+    certificates.push(certificate);
+    let reordered: Vec<_> = certificates
+        .chunks(keys.len())
+        .flat_map(|x| x.iter().rev().cloned())
+        .collect();
+
+    for tie_break in [TieBreak::Author, TieBreak::Digest] {
+        let parameters = Parameters {
+            tie_break,
+            ..mock_parameters()
+        };
+        let first = Consensus::replay(mock_committee(), &parameters, certificates.clone());
+        let second = Consensus::replay(mock_committee(), &parameters, reordered.clone());
+        assert_eq!(first.len(), second.len());
+
+        // Check the order of each sub-dag.
+        for sequence in [&first, &second] {
+            for leader_round in [2, 4] {
+                let sub_dag: Vec<_> = sequence
+                    .iter()
+                    .filter(|(_, x)| x.leader_round == leader_round)
+                    .map(|(x, _)| x.clone())
+                    .collect();
+                let mut sorted = sub_dag.clone();
+                match tie_break {
+                    TieBreak::Author => sorted.sort_by_key(|x| (x.round(), x.origin())),
+                    TieBreak::Digest => sorted.sort_by_key(|x| (x.round(), x.digest())),
+                }
+                assert_eq!(sub_dag, sorted);
+            }
+        }
+
+        let first: Vec<_> = first.into_iter().map(|(x, _)| x).collect();
+        let second: Vec<_> = second.into_iter().map(|(x, _)| x).collect();
+        let first = bincode::serialize(&first).unwrap();
+        let second = bincode::serialize(&second).unwrap();
+        assert_eq!(first, second);
     }
-    let certificate = rx_output.recv().await.unwrap();
-    assert_eq!(certificate.round(), 4);
 }
@@ -0,0 +1,96 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::consensus_tests::mock_committee;
+use primary::Header;
+
+// Fixture
+fn mock_certificate(origin: PublicKey) -> Certificate {
+    Certificate {
+        header: Header {
+            author: origin,
+            ..Header::default()
+        },
+        ..Certificate::default()
+    }
+}
+
+#[test]
+fn demote_worst_leader() {
+    let committee = mock_committee();
+    let mut schedule = LeaderSchedule::new(&committee, /* window */ 2);
+    let mut keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    keys.sort();
+
+    // Initially, leaders are elected round-robin.
+    for (i, name) in keys.iter().enumerate() {
+        assert_eq!(schedule.leader(i as Round), *name);
+    }
+
+    // The first authority misses all rounds, and the last one is the best.
+    for _ in 0..2 {
+        for name in &keys[1..] {
+            schedule.record(&mock_certificate(*name));
+        }
+        schedule.record(&mock_certificate(keys[3]));
+        let changed = schedule.commit_leader();
+        assert_eq!(changed, schedule.committed_leaders == 0);
+    }
+
+    // The worst authority is replaced by the best one.
+    assert_eq!(schedule.leader(0), keys[3]);
+    for (i, name) in keys.iter().enumerate().skip(1) {
+        assert_eq!(schedule.leader(i as Round), *name);
+    }
+
+    // Once all authorities perform equally, the schedule goes back to round-robin.
+    for _ in 0..2 {
+        for name in &keys {
+            schedule.record(&mock_certificate(*name));
+        }
+        schedule.commit_leader();
+    }
+    assert_eq!(schedule.leader(0), keys[0]);
+}
+
+#[test]
+fn reputation_disabled() {
+    let committee = mock_committee();
+    let mut schedule = LeaderSchedule::new(&committee, /* window */ 0);
+    let (name, _) = committee.authorities.iter().next().unwrap();
+    schedule.record(&mock_certificate(*name));
+    assert!(!schedule.commit_leader());
+}
+
+#[test]
+fn stake_weighted_leaders() {
+    let mut committee = mock_committee();
+    let mut keys: Vec<_> = committee.authorities.keys().cloned().collect();
+    keys.sort();
+
+    // The last authority holds 4 of the 7 units of stake (f is then 2).
+    committee.authorities.get_mut(&keys[3]).unwrap().stake = 4;
+    let mut schedule = LeaderSchedule::new(&committee, /* window */ 1);
+
+    // Each authority leads in proportion to its stake.
+    let leaders: Vec<_> = (0..8).map(|coin| schedule.leader(coin)).collect();
+    let expected = vec![
+        keys[0], keys[1], keys[2], keys[3], keys[3], keys[3], keys[3], keys[0],
+    ];
+    assert_eq!(leaders, expected);
+
+    // The first two authorities miss all rounds: both are replaced (they hold f of the stake).
+    schedule.record(&mock_certificate(keys[2]));
+    schedule.record(&mock_certificate(keys[3]));
+    schedule.record(&mock_certificate(keys[3]));
+    assert!(schedule.commit_leader());
+    assert_eq!(schedule.leader(0), keys[3]);
+    assert_eq!(schedule.leader(1), keys[2]);
+    assert_eq!(schedule.leader(2), keys[2]);
+
+    // The authority holding most of the stake is never replaced (it holds more than f).
+    for name in &keys[..3] {
+        schedule.record(&mock_certificate(*name));
+    }
+    schedule.commit_leader();
+    assert_eq!(schedule.leader(3), keys[3]);
+}
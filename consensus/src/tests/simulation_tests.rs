@@ -0,0 +1,350 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::consensus_tests::{mock_committee, mock_leader, mock_parameters};
+use std::collections::{BTreeSet, HashSet};
+use tokio::time::sleep_until;
+
+// The delay between two rounds of the simulated dag. Denominated in ms.
+const ROUND_DELAY: u64 = 100;
+
+// A fault of an authority (identified by its index in the sorted committee), injected into the simulated dag.
+#[derive(Clone, Copy)]
+enum Fault {
+    // The authority crashes at the specified round: it no longer creates certificates.
+    Crash {
+        authority: usize,
+        from: Round,
+    },
+    // The certificates of the authority reach us late, eg. over a slow link. Denominated in ms.
+    Delay {
+        authority: usize,
+        delay: u64,
+    },
+    // The other authorities do not reference the certificates of the authority of the specified rounds
+    // (inclusive), eg. when it is partitioned from them or too slow to be included in their headers.
+    Isolate {
+        authority: usize,
+        from: Round,
+        to: Round,
+    },
+}
+
+// What consensus output over a simulation.
+struct Outcome {
+    sub_dags: Vec<CommittedSubDag>,
+    skips: Vec<LeaderSkip>,
+}
+
+impl Outcome {
+    // Returns the rounds of the committed leaders.
+    fn leader_rounds(&self) -> Vec<Round> {
+        self.sub_dags.iter().map(|x| x.round()).collect()
+    }
+
+    // Returns the digests of the committed certificates, in commit order.
+    fn sequence(&self) -> Vec<Digest> {
+        self.sub_dags
+            .iter()
+            .flat_map(|x| x.certificates.iter().map(|x| x.digest()))
+            .collect()
+    }
+}
+
+// Drives a consensus instance with a synthetic dag (one certificate per authority and round, referencing all
+// the certificates of the previous round), under tokio's paused time: the certificates are delivered at their
+// (virtual) time, in causal order. Faults change the shape of the dag and the delivery times.
+struct Simulation {
+    committee: Committee,
+    parameters: Parameters,
+    rounds: Round,
+    faults: Vec<Fault>,
+}
+
+impl Simulation {
+    fn new(rounds: Round) -> Self {
+        Self {
+            committee: mock_committee(),
+            parameters: mock_parameters(),
+            rounds,
+            faults: Vec::new(),
+        }
+    }
+
+    fn with_fault(mut self, fault: Fault) -> Self {
+        self.faults.push(fault);
+        self
+    }
+
+    fn with_parameters(mut self, parameters: Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    // Returns the authorities, sorted by public key.
+    fn keys(&self) -> Vec<PublicKey> {
+        let mut keys: Vec<_> = self.committee.authorities.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    // Returns the index of the leader of the specified round (in the sorted committee).
+    fn leader(&self, round: Round) -> usize {
+        let leader = mock_leader(&self.committee, round);
+        self.keys().iter().position(|x| *x == leader).unwrap()
+    }
+
+    fn crashed(&self, authority: usize, round: Round) -> bool {
+        self.faults.iter().any(|x| match *x {
+            Fault::Crash { authority: a, from } => a == authority && round >= from,
+            _ => false,
+        })
+    }
+
+    fn isolated(&self, authority: usize, round: Round) -> bool {
+        self.faults.iter().any(|x| match *x {
+            Fault::Isolate {
+                authority: a,
+                from,
+                to,
+            } => a == authority && (from..=to).contains(&round),
+            _ => false,
+        })
+    }
+
+    fn delay(&self, authority: usize) -> u64 {
+        self.faults
+            .iter()
+            .map(|x| match *x {
+                Fault::Delay {
+                    authority: a,
+                    delay,
+                } if a == authority => delay,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    // Returns the certificates of the dag along with their delivery time (in ms), in delivery order. An
+    // authority only creates a certificate if it can reference a quorum of the previous round.
+    fn dag(&self) -> Vec<(u64, Certificate)> {
+        let keys = self.keys();
+        let genesis: Vec<_> = Certificate::genesis(&self.committee)
+            .iter()
+            .map(|x| x.digest())
+            .collect();
+        let mut previous: Vec<Option<(Digest, u64)>> =
+            genesis.into_iter().map(|x| Some((x, 0))).collect();
+        let mut dag = Vec::new();
+        for round in 1..=self.rounds {
+            let mut current = Vec::new();
+            for (i, name) in keys.iter().enumerate() {
+                let parents: Vec<_> = previous
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j == i || round == 1 || !self.isolated(*j, round - 1))
+                    .filter_map(|(j, x)| x.clone().map(|x| (keys[j], x)))
+                    .collect();
+                let stake: Stake = parents.iter().map(|(x, _)| self.committee.stake(x)).sum();
+                if self.crashed(i, round) || stake < self.committee.quorum_threshold() {
+                    current.push(None);
+                    continue;
+                }
+
+                // The certificate is delivered after its parents.
+                let time = parents
+                    .iter()
+                    .map(|(_, (_, time))| *time)
+                    .fold(round * ROUND_DELAY + self.delay(i), max);
+                let certificate = Certificate {
+                    header: Header {
+                        author: *name,
+                        round,
+                        parents: parents.into_iter().map(|(_, (x, _))| x).collect(),
+                        ..Header::default()
+                    },
+                    ..Certificate::default()
+                };
+                current.push(Some((certificate.digest(), time)));
+                dag.push((time, certificate));
+            }
+            previous = current;
+        }
+        dag.sort_by_key(|(time, x)| (*time, x.round()));
+        dag
+    }
+
+    // Run consensus over the dag (under paused time) and return its output.
+    async fn run(&self) -> Outcome {
+        let (tx_waiter, rx_waiter) = channel(1_000);
+        let (tx_primary, mut rx_primary) = channel(1_000);
+        let (tx_output, mut rx_output) = channel(1_000);
+        let commit_events = CommitEventBus::new();
+        let mut rx_skips = commit_events.subscribe_skips();
+        Consensus::spawn(
+            self.committee.clone(),
+            self.parameters.clone(),
+            /* checkpoints */ None,
+            rx_waiter,
+            tx_primary,
+            tx_output,
+            commit_events,
+        );
+        tokio::spawn(async move { while rx_primary.recv().await.is_some() {} });
+
+        let start = Instant::now();
+        for (time, certificate) in self.dag() {
+            sleep_until(start + Duration::from_millis(time)).await;
+            tx_waiter.send(certificate).await.unwrap();
+        }
+
+        // Give consensus one more round to time out on the last leaders, then stop it.
+        sleep(Duration::from_millis(
+            self.parameters.leader_timeout + ROUND_DELAY,
+        ))
+        .await;
+        drop(tx_waiter);
+        let mut sub_dags = Vec::new();
+        while let Some(sub_dag) = rx_output.recv().await {
+            sub_dags.push(sub_dag);
+        }
+        let mut skips = Vec::new();
+        while let Ok(skip) = rx_skips.try_recv() {
+            skips.push(skip);
+        }
+        Outcome { sub_dags, skips }
+    }
+}
+
+// Ensure every certificate is committed at most once, and after its parents committed in the same sub-dag.
+fn assert_safe(outcome: &Outcome) {
+    let mut committed = HashSet::new();
+    for sub_dag in &outcome.sub_dags {
+        for certificate in &sub_dag.certificates {
+            assert!(committed.insert(certificate.digest()));
+        }
+    }
+    let mut seen = HashSet::new();
+    for sub_dag in &outcome.sub_dags {
+        let digests: BTreeSet<_> = sub_dag.certificates.iter().map(|x| x.digest()).collect();
+        for certificate in &sub_dag.certificates {
+            assert!(certificate
+                .header
+                .parents
+                .iter()
+                .all(|x| !digests.contains(x) || seen.contains(x)));
+            seen.insert(certificate.digest());
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn no_faults() {
+    let outcome = Simulation::new(12).run().await;
+    assert_safe(&outcome);
+    assert_eq!(outcome.leader_rounds(), vec![2, 4, 6, 8]);
+    assert!(outcome.skips.is_empty());
+
+    // All the certificates below the last leader are committed.
+    assert_eq!(outcome.sequence().len(), 4 * 7 + 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn deterministic() {
+    let simulation = Simulation::new(12)
+        .with_fault(Fault::Delay {
+            authority: 2,
+            delay: 250,
+        })
+        .with_fault(Fault::Isolate {
+            authority: 0,
+            from: 4,
+            to: 4,
+        });
+    let first = simulation.run().await;
+    let second = simulation.run().await;
+    assert_eq!(first.sequence(), second.sequence());
+}
+
+// A slow link delays the commits, but does not change the commit sequence.
+#[tokio::test(start_paused = true)]
+async fn delayed_authority() {
+    let expected = Simulation::new(12).run().await;
+    for authority in 0..4 {
+        let outcome = Simulation::new(12)
+            .with_fault(Fault::Delay {
+                authority,
+                delay: 3 * ROUND_DELAY,
+            })
+            .run()
+            .await;
+        assert_safe(&outcome);
+        assert_eq!(outcome.sequence(), expected.sequence());
+    }
+}
+
+// The dag keeps growing (and committing) without a crashed authority.
+#[tokio::test(start_paused = true)]
+async fn crashed_authority() {
+    let keys = Simulation::new(0).keys();
+    let outcome = Simulation::new(12)
+        .with_fault(Fault::Crash {
+            authority: 3,
+            from: 3,
+        })
+        .run()
+        .await;
+    assert_safe(&outcome);
+    assert_eq!(outcome.leader_rounds(), vec![2, 4, 6, 8]);
+    assert!(outcome
+        .sub_dags
+        .iter()
+        .flat_map(|x| &x.certificates)
+        .all(|x| x.origin() != keys[3] || x.round() < 3));
+}
+
+// Without the certificates of a crashed leader, consensus skips its rounds after waiting for it (in virtual
+// time), and commits the rest of the dag with the next leaders.
+#[tokio::test(start_paused = true)]
+async fn crashed_leader() {
+    let parameters = Parameters {
+        leader_timeout: 500,
+        ..mock_parameters()
+    };
+    let simulation = Simulation::new(14).with_parameters(parameters);
+    let authority = simulation.leader(6);
+    assert_eq!(simulation.leader(10), authority);
+    let outcome = simulation
+        .with_fault(Fault::Crash { authority, from: 6 })
+        .run()
+        .await;
+    assert_safe(&outcome);
+    assert_eq!(outcome.leader_rounds(), vec![2, 4, 8]);
+
+    // The leader of round 8 commits the dag before consensus times out on the one of round 6 (whose slot is
+    // not honored), but nothing commits the dag past the leader of round 10: consensus times out on it.
+    let skips: Vec<_> = outcome.skips.iter().map(|x| (x.round, x.waited)).collect();
+    assert_eq!(skips, vec![(10, 500.0)]);
+}
+
+// A leader without enough support is not committed directly, but by the next leader linked to it.
+#[tokio::test(start_paused = true)]
+async fn isolated_leader() {
+    let expected = Simulation::new(12).run().await;
+    let simulation = Simulation::new(12);
+    let authority = simulation.leader(4);
+    let outcome = simulation
+        .with_fault(Fault::Isolate {
+            authority,
+            from: 4,
+            to: 4,
+        })
+        .run()
+        .await;
+    assert_safe(&outcome);
+    assert_eq!(outcome.leader_rounds(), expected.leader_rounds());
+
+    // The other authorities do not reference the leader of round 4: it is committed last by the next leader.
+    let sub_dag = &outcome.sub_dags[1];
+    assert_eq!(sub_dag.round(), 4);
+    assert_eq!(sub_dag.certificates.last(), Some(&sub_dag.leader));
+}
@@ -0,0 +1,270 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::{CryptoError, Digest};
+use blst::min_pk as blst_core;
+use blst::BLST_ERROR;
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use serde::{de, ser, Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+
+/// The domain separation tag of the signatures (proof-of-possession ciphersuite). The BLS keys of the
+/// committee come with a proof of possession of their secret key (checked when the committee is loaded), which
+/// rules out rogue-key attacks on the aggregate signatures.
+const DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// The domain separation tag of the proofs of possession (distinct from that of the signatures, so that no
+/// signature doubles as a proof).
+const DST_POP: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// Represents a BLS12-381 public key (compressed, in bytes).
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct BlsPublicKey(pub [u8; 48]);
+
+impl BlsPublicKey {
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0[..])
+    }
+
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes[..]
+            .try_into()
+            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
+
+    fn load(&self) -> Result<blst_core::PublicKey, CryptoError> {
+        blst_core::PublicKey::key_validate(&self.0).map_err(|_| CryptoError::new())
+    }
+}
+
+impl fmt::Debug for BlsPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode_base64())
+    }
+}
+
+impl Serialize for BlsPublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.encode_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsPublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = Self::decode_base64(&s).map_err(|e| de::Error::custom(e.to_string()))?;
+        Ok(value)
+    }
+}
+
+/// Represents a BLS12-381 secret key (in bytes).
+pub struct BlsSecretKey([u8; 32]);
+
+impl BlsSecretKey {
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0[..])
+    }
+
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes[..]
+            .try_into()
+            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
+
+    fn load(&self) -> blst_core::SecretKey {
+        blst_core::SecretKey::from_bytes(&self.0).expect("Unable to load BLS secret key")
+    }
+}
+
+impl Serialize for BlsSecretKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.encode_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsSecretKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = Self::decode_base64(&s).map_err(|e| de::Error::custom(e.to_string()))?;
+        Ok(value)
+    }
+}
+
+impl Drop for BlsSecretKey {
+    fn drop(&mut self) {
+        self.0.iter_mut().for_each(|x| *x = 0);
+    }
+}
+
+pub fn generate_production_bls_keypair() -> (BlsPublicKey, BlsSecretKey) {
+    generate_bls_keypair(&mut OsRng)
+}
+
+pub fn generate_bls_keypair<R>(csprng: &mut R) -> (BlsPublicKey, BlsSecretKey)
+where
+    R: CryptoRng + RngCore,
+{
+    let mut ikm = [0u8; 32];
+    csprng.fill_bytes(&mut ikm);
+    let secret = blst_core::SecretKey::key_gen(&ikm, &[]).expect("Failed to generate BLS key");
+    let public = BlsPublicKey(secret.sk_to_pk().compress());
+    (public, BlsSecretKey(secret.to_bytes()))
+}
+
+/// Represents a BLS12-381 signature (compressed, in bytes). Signatures over the same digest can be
+/// aggregated into a single signature of the same size.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BlsSignature(pub [u8; 96]);
+
+impl BlsSignature {
+    pub fn new(digest: &Digest, secret: &BlsSecretKey) -> Self {
+        Self(secret.load().sign(&digest.0, DST, &[]).compress())
+    }
+
+    fn load(&self) -> Result<blst_core::Signature, CryptoError> {
+        blst_core::Signature::from_bytes(&self.0).map_err(|_| CryptoError::new())
+    }
+
+    pub fn verify(&self, digest: &Digest, public_key: &BlsPublicKey) -> Result<(), CryptoError> {
+        let signature = self.load()?;
+        let key = public_key.load()?;
+        match signature.verify(true, &digest.0, DST, &[], &key, false) {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(CryptoError::new()),
+        }
+    }
+
+    /// Aggregate signatures (over the same digest) into a single signature.
+    pub fn aggregate<'a, I>(signatures: I) -> Result<Self, CryptoError>
+    where
+        I: IntoIterator<Item = &'a BlsSignature>,
+    {
+        let signatures = signatures
+            .into_iter()
+            .map(|x| x.load())
+            .collect::<Result<Vec<_>, _>>()?;
+        let signatures: Vec<_> = signatures.iter().collect();
+        let aggregate = blst_core::AggregateSignature::aggregate(&signatures, true)
+            .map_err(|_| CryptoError::new())?;
+        Ok(Self(aggregate.to_signature().compress()))
+    }
+
+    /// Verify an aggregate signature over a digest signed by all the specified keys.
+    pub fn verify_aggregate<'a, I>(&self, digest: &Digest, keys: I) -> Result<(), CryptoError>
+    where
+        I: IntoIterator<Item = &'a BlsPublicKey>,
+    {
+        let signature = self.load()?;
+        let keys = keys
+            .into_iter()
+            .map(|x| x.load())
+            .collect::<Result<Vec<_>, _>>()?;
+        let keys: Vec<_> = keys.iter().collect();
+        match signature.fast_aggregate_verify(true, &digest.0, DST, &keys) {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(CryptoError::new()),
+        }
+    }
+}
+
+/// Represents the proof of possession of the secret key of a BLS public key: the signature of the public key
+/// itself (compressed, in bytes).
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct BlsProofOfPossession(pub [u8; 96]);
+
+impl BlsProofOfPossession {
+    pub fn new(public_key: &BlsPublicKey, secret: &BlsSecretKey) -> Self {
+        Self(secret.load().sign(&public_key.0, DST_POP, &[]).compress())
+    }
+
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0[..])
+    }
+
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes[..]
+            .try_into()
+            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
+
+    pub fn verify(&self, public_key: &BlsPublicKey) -> Result<(), CryptoError> {
+        let proof = blst_core::Signature::from_bytes(&self.0).map_err(|_| CryptoError::new())?;
+        let key = public_key.load()?;
+        match proof.verify(true, &public_key.0, DST_POP, &[], &key, false) {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(CryptoError::new()),
+        }
+    }
+}
+
+impl fmt::Debug for BlsProofOfPossession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode_base64())
+    }
+}
+
+impl Serialize for BlsProofOfPossession {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.encode_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsProofOfPossession {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = Self::decode_base64(&s).map_err(|e| de::Error::custom(e.to_string()))?;
+        Ok(value)
+    }
+}
+
+impl fmt::Debug for BlsSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", base64::encode(&self.0[..]))
+    }
+}
+
+impl Serialize for BlsSignature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlsSignature {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let array = bytes[..]
+            .try_into()
+            .map_err(|_| de::Error::custom("Invalid BLS signature length"))?;
+        Ok(Self(array))
+    }
+}
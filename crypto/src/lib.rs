@@ -8,6 +8,10 @@ use serde::{de, ser, Deserialize, Serialize};
 use std::array::TryFromSliceError;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 use tokio::sync::mpsc::{channel, Sender};
 use tokio::sync::oneshot;
 
@@ -15,10 +19,22 @@ use tokio::sync::oneshot;
 #[path = "tests/crypto_tests.rs"]
 pub mod crypto_tests;
 
+mod bls;
+mod threshold;
+
+pub use crate::bls::{
+    generate_bls_keypair, generate_production_bls_keypair, BlsProofOfPossession, BlsPublicKey,
+    BlsSecretKey, BlsSignature,
+};
+pub use crate::threshold::{
+    generate_threshold_keys, Ciphertext, CoinShare, DecryptionShare, G1Point, ThresholdKeyShare,
+    ThresholdPublicKey,
+};
+
 pub type CryptoError = ed25519::Error;
 
 /// Represents a hash digest (32 bytes).
-#[derive(Hash, PartialEq, Default, Eq, Clone, Deserialize, Serialize, Ord, PartialOrd)]
+#[derive(Hash, PartialEq, Default, Eq, Clone, Ord, PartialOrd)]
 pub struct Digest(pub [u8; 32]);
 
 impl Digest {
@@ -43,6 +59,38 @@ impl fmt::Display for Digest {
     }
 }
 
+/// Digests are serialized as raw bytes, except in human-readable formats (eg. JSON) where they are
+/// encoded in base64.
+impl Serialize for Digest {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        match serializer.is_human_readable() {
+            true => serializer.serialize_str(&base64::encode(&self.0)),
+            false => self.0.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            let bytes = base64::decode(&s).map_err(|e| de::Error::custom(e.to_string()))?;
+            let array = bytes[..]
+                .try_into()
+                .map_err(|_| de::Error::custom("Invalid digest length"))?;
+            Ok(Digest(array))
+        } else {
+            Ok(Digest(<[u8; 32]>::deserialize(deserializer)?))
+        }
+    }
+}
+
 impl AsRef<[u8]> for Digest {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -217,34 +265,172 @@ impl Signature {
         }
         dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
     }
+
+    /// Verify in a single batch signatures over different digests.
+    pub fn verify_many<'a, I>(items: I) -> Result<(), CryptoError>
+    where
+        I: IntoIterator<Item = (&'a Digest, &'a PublicKey, &'a Signature)>,
+    {
+        let mut messages: Vec<&[u8]> = Vec::new();
+        let mut signatures: Vec<dalek::Signature> = Vec::new();
+        let mut keys: Vec<dalek::PublicKey> = Vec::new();
+        for (digest, key, sig) in items.into_iter() {
+            messages.push(&digest.0[..]);
+            signatures.push(ed25519::signature::Signature::from_bytes(&sig.flatten())?);
+            keys.push(dalek::PublicKey::from_bytes(&key.0)?);
+        }
+        if messages.is_empty() {
+            return Ok(());
+        }
+        dalek::verify_batch(&messages[..], &signatures[..], &keys[..])
+    }
+}
+
+/// The number of signature requests that can be queued before the requesters are slowed down.
+const QUEUE_CAPACITY: usize = 1_000;
+
+/// A signature request, along with the time it was made.
+type Request<S> = (Digest, oneshot::Sender<S>, Instant);
+
+/// The metrics of the `SignatureService` (shared by all its clones).
+#[derive(Debug, Default)]
+pub struct SignatureMetrics {
+    /// The number of signatures produced.
+    pub signatures: AtomicU64,
+    /// The number of requests waiting for a signer.
+    pub pending: AtomicU64,
+    /// The time the requests waited for a signer (summed over all requests), in us.
+    pub queue_time: AtomicU64,
+    /// The time spent signing (summed over all requests), in us.
+    pub sign_time: AtomicU64,
+}
+
+impl SignatureMetrics {
+    fn record(&self, requested: Instant, started: Instant) {
+        let now = Instant::now();
+        self.signatures.fetch_add(1, Ordering::Relaxed);
+        self.queue_time.fetch_add(
+            started.duration_since(requested).as_micros() as u64,
+            Ordering::Relaxed,
+        );
+        self.sign_time.fetch_add(
+            now.duration_since(started).as_micros() as u64,
+            Ordering::Relaxed,
+        );
+    }
 }
 
 /// This service holds the node's private key. It takes digests as input and returns a signature
-/// over the digest (through a oneshot channel).
+/// over the digest (through a oneshot channel). The signatures are produced by a pool of dedicated
+/// threads, so that bursts of signatures (eg. our votes in large committees) never block the async
+/// tasks requesting them.
 #[derive(Clone)]
 pub struct SignatureService {
-    channel: Sender<(Digest, oneshot::Sender<Signature>)>,
+    channel: Sender<Request<Signature>>,
+    bls_channel: Option<Sender<Request<BlsSignature>>>,
+    /// The number of signing threads.
+    signers: usize,
+    metrics: Arc<SignatureMetrics>,
 }
 
 impl SignatureService {
     pub fn new(secret: SecretKey) -> Self {
-        let (tx, mut rx): (Sender<(_, oneshot::Sender<_>)>, _) = channel(100);
-        tokio::spawn(async move {
-            while let Some((digest, sender)) = rx.recv().await {
-                let signature = Signature::new(&digest, &secret);
-                let _ = sender.send(signature);
-            }
-        });
-        Self { channel: tx }
+        Self::with_signers(secret, 1)
+    }
+
+    /// Sign with the specified number of threads (at least one).
+    pub fn with_signers(secret: SecretKey, signers: usize) -> Self {
+        let signers = signers.max(1);
+        let metrics = Arc::new(SignatureMetrics::default());
+        let channel = Self::spawn_signers(secret, signers, metrics.clone(), Signature::new);
+        Self {
+            channel,
+            bls_channel: None,
+            signers,
+            metrics,
+        }
+    }
+
+    /// Also hold the node's BLS secret key, to produce signatures that can be aggregated.
+    pub fn with_bls(mut self, secret: BlsSecretKey) -> Self {
+        let channel = Self::spawn_signers(
+            secret,
+            self.signers,
+            self.metrics.clone(),
+            BlsSignature::new,
+        );
+        self.bls_channel = Some(channel);
+        self
+    }
+
+    /// Returns the metrics of the service.
+    pub fn metrics(&self) -> Arc<SignatureMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Spawn the threads signing the requests of the returned channel. They exit once all the clones of
+    /// the service are dropped.
+    fn spawn_signers<K, S, F>(
+        secret: K,
+        signers: usize,
+        metrics: Arc<SignatureMetrics>,
+        sign: F,
+    ) -> Sender<Request<S>>
+    where
+        K: Send + Sync + 'static,
+        S: Send + 'static,
+        F: Fn(&Digest, &K) -> S + Send + Sync + 'static,
+    {
+        let (tx, rx): (Sender<Request<S>>, _) = channel(QUEUE_CAPACITY);
+        let rx = Arc::new(Mutex::new(rx));
+        let secret = Arc::new(secret);
+        let sign = Arc::new(sign);
+        for i in 0..signers {
+            let rx = rx.clone();
+            let secret = secret.clone();
+            let sign = sign.clone();
+            let metrics = metrics.clone();
+            thread::Builder::new()
+                .name(format!("signer-{}", i))
+                .spawn(move || loop {
+                    let request = rx.lock().unwrap().blocking_recv();
+                    let (digest, sender, requested) = match request {
+                        Some(request) => request,
+                        None => return,
+                    };
+                    metrics.pending.fetch_sub(1, Ordering::Relaxed);
+                    let started = Instant::now();
+                    let signature = sign(&digest, &secret);
+                    metrics.record(requested, started);
+                    let _ = sender.send(signature);
+                })
+                .expect("Failed to spawn signer thread");
+        }
+        tx
     }
 
     pub async fn request_signature(&mut self, digest: Digest) -> Signature {
         let (sender, receiver): (oneshot::Sender<_>, oneshot::Receiver<_>) = oneshot::channel();
-        if let Err(e) = self.channel.send((digest, sender)).await {
+        self.metrics.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = self.channel.send((digest, sender, Instant::now())).await {
             panic!("Failed to send message Signature Service: {}", e);
         }
         receiver
             .await
             .expect("Failed to receive signature from Signature Service")
     }
+
+    /// Returns a BLS signature over the digest (if the service holds a BLS secret key).
+    pub async fn request_bls_signature(&mut self, digest: Digest) -> Option<BlsSignature> {
+        let channel = self.bls_channel.as_ref()?;
+        let (sender, receiver): (oneshot::Sender<_>, oneshot::Receiver<_>) = oneshot::channel();
+        self.metrics.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = channel.send((digest, sender, Instant::now())).await {
+            panic!("Failed to send message Signature Service: {}", e);
+        }
+        let signature = receiver
+            .await
+            .expect("Failed to receive signature from Signature Service");
+        Some(signature)
+    }
 }
@@ -28,6 +28,11 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
     (0..4).map(|_| generate_keypair(&mut rng)).collect()
 }
 
+pub fn bls_keys() -> Vec<(BlsPublicKey, BlsSecretKey)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..4).map(|_| generate_bls_keypair(&mut rng)).collect()
+}
+
 #[test]
 fn import_export_public_key() {
     let (public_key, _) = keys().pop().unwrap();
@@ -46,6 +51,21 @@ fn import_export_secret_key() {
     assert_eq!(import.unwrap(), secret_key);
 }
 
+#[test]
+fn serialize_digest() {
+    let digest = b"Hello, world!".as_ref().digest();
+
+    // Digests are raw bytes in binary formats.
+    let bytes = bincode::serialize(&digest).unwrap();
+    assert_eq!(bytes, digest.to_vec());
+    assert_eq!(bincode::deserialize::<Digest>(&bytes).unwrap(), digest);
+
+    // Digests are base64 strings in human-readable formats.
+    let json = serde_json::to_string(&digest).unwrap();
+    assert_eq!(json, format!("\"{}\"", base64::encode(digest.0)));
+    assert_eq!(serde_json::from_str::<Digest>(&json).unwrap(), digest);
+}
+
 #[test]
 fn verify_valid_signature() {
     // Get a keypair.
@@ -114,6 +134,31 @@ fn verify_invalid_batch() {
     assert!(Signature::verify_batch(&digest, &signatures).is_err());
 }
 
+#[test]
+fn verify_many_digests() {
+    // Make signatures over different messages.
+    let messages: Vec<&[u8]> = vec![b"Hello", b"world", b"!"];
+    let digests: Vec<_> = messages.iter().map(|x| x.digest()).collect();
+    let signed: Vec<_> = keys()
+        .into_iter()
+        .zip(digests.iter())
+        .map(|((public_key, secret_key), digest)| {
+            (digest, public_key, Signature::new(digest, &secret_key))
+        })
+        .collect();
+
+    // Verify the batch.
+    let items = signed.iter().map(|(d, k, s)| (*d, k, s));
+    assert!(Signature::verify_many(items).is_ok());
+
+    // Verify the batch with a signature over the wrong digest.
+    let items = signed
+        .iter()
+        .zip(digests.iter().rev())
+        .map(|((_, k, s), d)| (d, k, s));
+    assert!(Signature::verify_many(items).is_err());
+}
+
 #[tokio::test]
 async fn signature_service() {
     // Get a keypair.
@@ -130,3 +175,199 @@ async fn signature_service() {
     // Verify the signature we received.
     assert!(signature.verify(&digest, &public_key).is_ok());
 }
+
+#[tokio::test]
+async fn signature_service_pool() {
+    // Get a keypair.
+    let (public_key, secret_key) = keys().pop().unwrap();
+
+    // Spawn the signature service with several signing threads.
+    let service = SignatureService::with_signers(secret_key, 4);
+
+    // Request many signatures concurrently.
+    let handles: Vec<_> = (0..20u8)
+        .map(|i| {
+            let mut service = service.clone();
+            tokio::spawn(async move {
+                let digest = Digest([i; 32]);
+                let signature = service.request_signature(digest.clone()).await;
+                (digest, signature)
+            })
+        })
+        .collect();
+
+    // Verify the signatures we received.
+    for handle in handles {
+        let (digest, signature) = handle.await.unwrap();
+        assert!(signature.verify(&digest, &public_key).is_ok());
+    }
+
+    // Ensure the metrics account for all requests.
+    let metrics = service.metrics();
+    assert_eq!(metrics.signatures.load(Ordering::Relaxed), 20);
+    assert_eq!(metrics.pending.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn verify_valid_bls_signature() {
+    // Get a keypair.
+    let (public_key, secret_key) = bls_keys().pop().unwrap();
+
+    // Make signature.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let signature = BlsSignature::new(&digest, &secret_key);
+
+    // Verify the signature.
+    assert!(signature.verify(&digest, &public_key).is_ok());
+
+    let bad_message: &[u8] = b"Bad message!";
+    assert!(signature
+        .verify(&bad_message.digest(), &public_key)
+        .is_err());
+}
+
+#[test]
+fn verify_valid_aggregate() {
+    // Make signatures.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let (public_keys, signatures): (Vec<_>, Vec<_>) = bls_keys()
+        .into_iter()
+        .take(3)
+        .map(|(public_key, secret_key)| (public_key, BlsSignature::new(&digest, &secret_key)))
+        .unzip();
+
+    // Aggregate and verify the signatures.
+    let aggregate = BlsSignature::aggregate(&signatures).unwrap();
+    assert!(aggregate.verify_aggregate(&digest, &public_keys).is_ok());
+}
+
+#[test]
+fn verify_invalid_aggregate() {
+    // Make signatures.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let mut keys = bls_keys();
+    let (public_keys, signatures): (Vec<_>, Vec<_>) = keys
+        .drain(..3)
+        .map(|(public_key, secret_key)| (public_key, BlsSignature::new(&digest, &secret_key)))
+        .unzip();
+    let aggregate = BlsSignature::aggregate(&signatures).unwrap();
+
+    // Verify the aggregate against the wrong set of keys.
+    let (other, _) = keys.pop().unwrap();
+    let wrong_keys = vec![public_keys[0], public_keys[1], other];
+    assert!(aggregate.verify_aggregate(&digest, &wrong_keys).is_err());
+}
+
+#[test]
+fn bls_proof_of_possession() {
+    let mut keys = bls_keys();
+    let (public_key, secret_key) = keys.pop().unwrap();
+    let (other, _) = keys.pop().unwrap();
+
+    // The proof only verifies against the key it was made for.
+    let proof = BlsProofOfPossession::new(&public_key, &secret_key);
+    assert!(proof.verify(&public_key).is_ok());
+    assert!(proof.verify(&other).is_err());
+
+    // A proof made with another secret key is rejected.
+    let (_, other_secret) = keys.pop().unwrap();
+    let proof = BlsProofOfPossession::new(&public_key, &other_secret);
+    assert!(proof.verify(&public_key).is_err());
+}
+
+#[tokio::test]
+async fn bls_signature_service() {
+    // Get the keypairs.
+    let (_, secret_key) = keys().pop().unwrap();
+    let (bls_public_key, bls_secret_key) = bls_keys().pop().unwrap();
+
+    // Spawn the signature service.
+    let mut service = SignatureService::new(secret_key).with_bls(bls_secret_key);
+
+    // Request signature from the service.
+    let message: &[u8] = b"Hello, world!";
+    let digest = message.digest();
+    let signature = service.request_bls_signature(digest.clone()).await.unwrap();
+
+    // Verify the signature we received.
+    assert!(signature.verify(&digest, &bls_public_key).is_ok());
+}
+
+#[test]
+fn threshold_decrypt() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (public_key, shares) = generate_threshold_keys(4, 2, &mut rng);
+    let ciphertext = public_key.encrypt(b"transaction", &mut rng).unwrap();
+
+    // Any two (valid) decryption shares decrypt the ciphertext.
+    let decryption_shares: Vec<_> = shares
+        .iter()
+        .map(|x| x.decryption_share(&ciphertext, &mut rng).unwrap())
+        .collect();
+    for share in &decryption_shares {
+        assert!(public_key.verify_share(&ciphertext, share).is_ok());
+    }
+    let plaintext = public_key
+        .decrypt(&ciphertext, &decryption_shares[2..])
+        .unwrap();
+    assert_eq!(plaintext, b"transaction");
+
+    // A single share does not suffice.
+    assert!(public_key
+        .decrypt(&ciphertext, &decryption_shares[..1])
+        .is_err());
+}
+
+#[test]
+fn threshold_invalid_share() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (public_key, shares) = generate_threshold_keys(4, 2, &mut rng);
+    let ciphertext = public_key.encrypt(b"transaction", &mut rng).unwrap();
+    let other = public_key.encrypt(b"other", &mut rng).unwrap();
+
+    // A share computed for another ciphertext (or claiming another index) is rejected.
+    let share = shares[0].decryption_share(&other, &mut rng).unwrap();
+    assert!(public_key.verify_share(&ciphertext, &share).is_err());
+    let mut share = shares[0].decryption_share(&ciphertext, &mut rng).unwrap();
+    share.index = 2;
+    assert!(public_key.verify_share(&ciphertext, &share).is_err());
+}
+
+#[test]
+fn threshold_coin() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (public_key, shares) = generate_threshold_keys(4, 2, &mut rng);
+    let coin_shares: Vec<_> = shares
+        .iter()
+        .map(|x| x.coin_share(b"seed", &mut rng).unwrap())
+        .collect();
+    for share in &coin_shares {
+        assert!(public_key
+            .verify_coin_share(b"seed", share.index, share)
+            .is_ok());
+        assert!(public_key
+            .verify_coin_share(b"other", share.index, share)
+            .is_err());
+    }
+
+    // A valid share revealed under another index is rejected.
+    let share = &coin_shares[0];
+    assert!(public_key
+        .verify_coin_share(b"seed", share.index + 1, share)
+        .is_err());
+
+    // Any two shares yield the same coin, which depends on the seed.
+    let coin = public_key.coin(b"seed", &coin_shares[..2]).unwrap();
+    assert_eq!(public_key.coin(b"seed", &coin_shares[2..]).unwrap(), coin);
+    let other: Vec<_> = shares
+        .iter()
+        .map(|x| x.coin_share(b"other", &mut rng).unwrap())
+        .collect();
+    assert_ne!(public_key.coin(b"other", &other[..2]).unwrap(), coin);
+
+    // A single share does not suffice.
+    assert!(public_key.coin(b"seed", &coin_shares[..1]).is_err());
+}
@@ -0,0 +1,498 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::CryptoError;
+use blst::{
+    blst_fr, blst_fr_add, blst_fr_from_scalar, blst_fr_from_uint64, blst_fr_inverse, blst_fr_mul,
+    blst_fr_sub, blst_hash_to_g1, blst_lendian_from_scalar, blst_p1, blst_p1_add_or_double,
+    blst_p1_affine, blst_p1_affine_in_g1, blst_p1_cneg, blst_p1_compress, blst_p1_from_affine,
+    blst_p1_generator, blst_p1_mult, blst_p1_uncompress, blst_scalar, blst_scalar_from_fr,
+    blst_scalar_from_le_bytes, BLST_ERROR,
+};
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use rand::{CryptoRng, RngCore};
+use serde::{de, ser, Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fmt;
+
+/// The domain separation tags of the hashes of the scheme.
+const DST_KEY: &[u8] = b"NARWHAL_THRESHOLD_KEY_";
+const DST_TAG: &[u8] = b"NARWHAL_THRESHOLD_TAG_";
+const DST_PROOF: &[u8] = b"NARWHAL_THRESHOLD_PROOF_";
+const DST_COIN: &[u8] = b"NARWHAL_THRESHOLD_COIN_BLS12381G1_XMD:SHA-256_SSWU_RO_";
+const DST_RANDOMNESS: &[u8] = b"NARWHAL_THRESHOLD_RANDOMNESS_";
+
+/// Represents a point of the G1 group of BLS12-381 (compressed, in bytes).
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct G1Point(pub [u8; 48]);
+
+impl G1Point {
+    pub fn encode_base64(&self) -> String {
+        base64::encode(&self.0[..])
+    }
+
+    pub fn decode_base64(s: &str) -> Result<Self, base64::DecodeError> {
+        let bytes = base64::decode(s)?;
+        let array = bytes[..]
+            .try_into()
+            .map_err(|_| base64::DecodeError::InvalidLength)?;
+        Ok(Self(array))
+    }
+
+    fn new(point: &blst_p1) -> Self {
+        let mut bytes = [0u8; 48];
+        unsafe { blst_p1_compress(bytes.as_mut_ptr(), point) };
+        Self(bytes)
+    }
+
+    fn load(&self) -> Result<blst_p1, CryptoError> {
+        let mut affine = blst_p1_affine::default();
+        let mut point = blst_p1::default();
+        unsafe {
+            if blst_p1_uncompress(&mut affine, self.0.as_ptr()) != BLST_ERROR::BLST_SUCCESS
+                || !blst_p1_affine_in_g1(&affine)
+            {
+                return Err(CryptoError::new());
+            }
+            blst_p1_from_affine(&mut point, &affine);
+        }
+        Ok(point)
+    }
+}
+
+impl fmt::Debug for G1Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.encode_base64())
+    }
+}
+
+impl Serialize for G1Point {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.encode_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for G1Point {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let value = Self::decode_base64(&s).map_err(|e| de::Error::custom(e.to_string()))?;
+        Ok(value)
+    }
+}
+
+/// Reduce bytes (little-endian) modulo the order of the group.
+fn scalar(bytes: &[u8]) -> blst_fr {
+    let mut scalar = blst_scalar::default();
+    let mut output = blst_fr::default();
+    unsafe {
+        blst_scalar_from_le_bytes(&mut scalar, bytes.as_ptr(), bytes.len());
+        blst_fr_from_scalar(&mut output, &scalar);
+    }
+    output
+}
+
+fn scalar_to_bytes(x: &blst_fr) -> [u8; 32] {
+    let mut scalar = blst_scalar::default();
+    let mut bytes = [0u8; 32];
+    unsafe {
+        blst_scalar_from_fr(&mut scalar, x);
+        blst_lendian_from_scalar(bytes.as_mut_ptr(), &scalar);
+    }
+    bytes
+}
+
+fn scalar_from_u64(x: u64) -> blst_fr {
+    let mut output = blst_fr::default();
+    unsafe { blst_fr_from_uint64(&mut output, [x, 0, 0, 0].as_ptr()) };
+    output
+}
+
+fn random_scalar<R>(csprng: &mut R) -> blst_fr
+where
+    R: CryptoRng + RngCore,
+{
+    let mut bytes = [0u8; 64];
+    csprng.fill_bytes(&mut bytes);
+    scalar(&bytes)
+}
+
+fn hash_to_scalar(parts: &[&[u8]]) -> blst_fr {
+    let mut hasher = Sha512::new();
+    hasher.update(DST_PROOF);
+    for part in parts {
+        hasher.update(part);
+    }
+    scalar(&hasher.finalize())
+}
+
+fn add_scalars(a: &blst_fr, b: &blst_fr) -> blst_fr {
+    let mut output = blst_fr::default();
+    unsafe { blst_fr_add(&mut output, a, b) };
+    output
+}
+
+fn mul_scalars(a: &blst_fr, b: &blst_fr) -> blst_fr {
+    let mut output = blst_fr::default();
+    unsafe { blst_fr_mul(&mut output, a, b) };
+    output
+}
+
+fn generator() -> blst_p1 {
+    unsafe { *blst_p1_generator() }
+}
+
+fn mul_point(point: &blst_p1, x: &blst_fr) -> blst_p1 {
+    let bytes = scalar_to_bytes(x);
+    let mut output = blst_p1::default();
+    unsafe { blst_p1_mult(&mut output, point, bytes.as_ptr(), 255) };
+    output
+}
+
+fn add_points(a: &blst_p1, b: &blst_p1) -> blst_p1 {
+    let mut output = blst_p1::default();
+    unsafe { blst_p1_add_or_double(&mut output, a, b) };
+    output
+}
+
+fn sub_points(a: &blst_p1, b: &blst_p1) -> blst_p1 {
+    let mut negated = *b;
+    unsafe { blst_p1_cneg(&mut negated, true) };
+    add_points(a, &negated)
+}
+
+/// Hash a seed to a point of G1 (whose discrete logarithm is unknown).
+fn hash_to_point(seed: &[u8]) -> blst_p1 {
+    let mut output = blst_p1::default();
+    unsafe {
+        blst_hash_to_g1(
+            &mut output,
+            seed.as_ptr(),
+            seed.len(),
+            DST_COIN.as_ptr(),
+            DST_COIN.len(),
+            std::ptr::null(),
+            0,
+        )
+    };
+    output
+}
+
+/// Returns the Lagrange coefficient (at 0) of the share `index` among the shares `indices`.
+fn lagrange_coefficient(index: u64, indices: &[u64]) -> blst_fr {
+    let x = scalar_from_u64(index);
+    let mut output = scalar_from_u64(1);
+    for j in indices.iter().filter(|j| **j != index) {
+        let xj = scalar_from_u64(*j);
+        let mut difference = blst_fr::default();
+        let mut inverse = blst_fr::default();
+        unsafe {
+            blst_fr_sub(&mut difference, &xj, &x);
+            blst_fr_inverse(&mut inverse, &difference);
+        }
+        output = mul_scalars(&output, &mul_scalars(&xj, &inverse));
+    }
+    output
+}
+
+/// Expand the shared key into a keystream of the specified length.
+fn keystream(key: &G1Point, length: usize) -> Vec<u8> {
+    let mut stream = Vec::with_capacity(length + 64);
+    let mut counter = 0u64;
+    while stream.len() < length {
+        let mut hasher = Sha512::new();
+        hasher.update(DST_KEY);
+        hasher.update(key.0);
+        hasher.update(counter.to_le_bytes());
+        stream.extend_from_slice(&hasher.finalize());
+        counter += 1;
+    }
+    stream.truncate(length);
+    stream
+}
+
+/// Authenticates the ciphertext under the shared key.
+fn tag(key: &G1Point, u: &G1Point, data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha512::new();
+    hasher.update(DST_TAG);
+    hasher.update(key.0);
+    hasher.update(u.0);
+    hasher.update(data);
+    hasher.finalize()[..32].try_into().unwrap()
+}
+
+/// The public key of the committee for threshold encryption (hashed ElGamal over G1). Any `threshold`
+/// decryption shares decrypt a ciphertext, and fewer reveal nothing about it. The ciphertexts are
+/// authenticated, but the scheme is not CCA-secure against the holders of the shares: it protects the
+/// transactions until the committee releases its decryption shares (ie. until they are committed).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ThresholdPublicKey {
+    /// The public key to encrypt to.
+    pub key: G1Point,
+    /// The key verifying the decryption shares of each share index (starting at 1).
+    pub verification_keys: Vec<G1Point>,
+    /// The number of decryption shares needed to decrypt.
+    pub threshold: usize,
+}
+
+impl ThresholdPublicKey {
+    /// Encrypt a plaintext to the committee.
+    pub fn encrypt<R>(&self, plaintext: &[u8], csprng: &mut R) -> Result<Ciphertext, CryptoError>
+    where
+        R: CryptoRng + RngCore,
+    {
+        let key = self.key.load()?;
+        let r = random_scalar(csprng);
+        let u = G1Point::new(&mul_point(&generator(), &r));
+        let shared = G1Point::new(&mul_point(&key, &r));
+        let data: Vec<_> = plaintext
+            .iter()
+            .zip(keystream(&shared, plaintext.len()))
+            .map(|(x, y)| x ^ y)
+            .collect();
+        let tag = tag(&shared, &u, &data);
+        Ok(Ciphertext { u, data, tag })
+    }
+
+    /// Check that a decryption share is the share of its index for the ciphertext.
+    pub fn verify_share(
+        &self,
+        ciphertext: &Ciphertext,
+        share: &DecryptionShare,
+    ) -> Result<(), CryptoError> {
+        self.verify_share_of(&ciphertext.u, share)
+    }
+
+    /// Check that a coin share is the share of the specified index (that of its author) for the seed. Each
+    /// authority must only reveal its own share, otherwise a single one could reveal the coin.
+    pub fn verify_coin_share(
+        &self,
+        seed: &[u8],
+        index: u64,
+        share: &CoinShare,
+    ) -> Result<(), CryptoError> {
+        if share.index != index {
+            return Err(CryptoError::new());
+        }
+        self.verify_share_of(&G1Point::new(&hash_to_point(seed)), share)
+    }
+
+    /// Check that a share is u^x_i, with x_i the secret of the share index.
+    fn verify_share_of(&self, base: &G1Point, share: &DecryptionShare) -> Result<(), CryptoError> {
+        let index: usize = share.index.try_into().map_err(|_| CryptoError::new())?;
+        let verification_key = self
+            .verification_keys
+            .get(index.wrapping_sub(1))
+            .ok_or_else(CryptoError::new)?;
+        let vk = verification_key.load()?;
+        let u = base.load()?;
+        let d = share.share.load()?;
+        let c = scalar(&share.challenge);
+        let z = scalar(&share.response);
+
+        // Recompute the commitments of the proof: g^z / vk^c and u^z / d^c.
+        let a = sub_points(&mul_point(&generator(), &z), &mul_point(&vk, &c));
+        let b = sub_points(&mul_point(&u, &z), &mul_point(&d, &c));
+        let expected = hash_to_scalar(&[
+            &verification_key.0,
+            &base.0,
+            &share.share.0,
+            &G1Point::new(&a).0,
+            &G1Point::new(&b).0,
+        ]);
+        match scalar_to_bytes(&expected) == share.challenge {
+            true => Ok(()),
+            false => Err(CryptoError::new()),
+        }
+    }
+
+    /// Decrypt a ciphertext from (verified) decryption shares of at least `threshold` distinct indices.
+    pub fn decrypt(
+        &self,
+        ciphertext: &Ciphertext,
+        shares: &[DecryptionShare],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let shared = self.interpolate(shares)?;
+        if tag(&shared, &ciphertext.u, &ciphertext.data) != ciphertext.tag {
+            return Err(CryptoError::new());
+        }
+        Ok(ciphertext
+            .data
+            .iter()
+            .zip(keystream(&shared, ciphertext.data.len()))
+            .map(|(x, y)| x ^ y)
+            .collect())
+    }
+
+    /// Returns the random coin of a seed from (verified) coin shares of at least `threshold` distinct
+    /// indices. The coin is the hash of the unique threshold signature of the seed: any such shares yield the
+    /// same coin, and fewer shares reveal nothing about it.
+    pub fn coin(&self, seed: &[u8], shares: &[CoinShare]) -> Result<[u8; 32], CryptoError> {
+        let signature = self.interpolate(shares)?;
+        let mut hasher = Sha512::new();
+        hasher.update(DST_RANDOMNESS);
+        hasher.update(seed);
+        hasher.update(signature.0);
+        Ok(hasher.finalize()[..32].try_into().unwrap())
+    }
+
+    /// Interpolate u^x (in the exponent) from shares u^x_i of at least `threshold` distinct indices.
+    fn interpolate(&self, shares: &[DecryptionShare]) -> Result<G1Point, CryptoError> {
+        let mut seen = HashSet::new();
+        let shares: Vec<_> = shares
+            .iter()
+            .filter(|x| seen.insert(x.index))
+            .take(self.threshold)
+            .collect();
+        if shares.len() < self.threshold {
+            return Err(CryptoError::new());
+        }
+
+        let indices: Vec<_> = shares.iter().map(|x| x.index).collect();
+        let mut shared: Option<blst_p1> = None;
+        for share in &shares {
+            let term = mul_point(
+                &share.share.load()?,
+                &lagrange_coefficient(share.index, &indices),
+            );
+            shared = Some(match shared {
+                Some(sum) => add_points(&sum, &term),
+                None => term,
+            });
+        }
+        Ok(G1Point::new(&shared.ok_or_else(CryptoError::new)?))
+    }
+}
+
+/// The share of an authority of the secret key of the committee.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ThresholdKeyShare {
+    /// The index of the share (starting at 1).
+    pub index: u64,
+    /// The share of the secret key (little-endian), encoded in base64.
+    secret: String,
+}
+
+impl ThresholdKeyShare {
+    fn load(&self) -> Result<blst_fr, CryptoError> {
+        let bytes = base64::decode(&self.secret).map_err(|_| CryptoError::new())?;
+        match bytes.len() {
+            32 => Ok(scalar(&bytes)),
+            _ => Err(CryptoError::new()),
+        }
+    }
+
+    /// Compute our decryption share of a ciphertext, along with a proof of its correctness.
+    pub fn decryption_share<R>(
+        &self,
+        ciphertext: &Ciphertext,
+        csprng: &mut R,
+    ) -> Result<DecryptionShare, CryptoError>
+    where
+        R: CryptoRng + RngCore,
+    {
+        self.share_of(&ciphertext.u, csprng)
+    }
+
+    /// Compute our share of the random coin of a seed, along with a proof of its correctness.
+    pub fn coin_share<R>(&self, seed: &[u8], csprng: &mut R) -> Result<CoinShare, CryptoError>
+    where
+        R: CryptoRng + RngCore,
+    {
+        self.share_of(&G1Point::new(&hash_to_point(seed)), csprng)
+    }
+
+    /// Compute u^x_i (with x_i our secret), along with a proof of its correctness.
+    fn share_of<R>(&self, base: &G1Point, csprng: &mut R) -> Result<DecryptionShare, CryptoError>
+    where
+        R: CryptoRng + RngCore,
+    {
+        let x = self.load()?;
+        let u = base.load()?;
+        let vk = G1Point::new(&mul_point(&generator(), &x));
+        let share = G1Point::new(&mul_point(&u, &x));
+
+        // Prove that the share and the verification key have the same discrete logarithm (Chaum-Pedersen).
+        let w = random_scalar(csprng);
+        let a = G1Point::new(&mul_point(&generator(), &w));
+        let b = G1Point::new(&mul_point(&u, &w));
+        let c = hash_to_scalar(&[&vk.0, &base.0, &share.0, &a.0, &b.0]);
+        let z = add_scalars(&w, &mul_scalars(&c, &x));
+        Ok(DecryptionShare {
+            index: self.index,
+            share,
+            challenge: scalar_to_bytes(&c),
+            response: scalar_to_bytes(&z),
+        })
+    }
+}
+
+/// Generate the threshold keys of a committee of `nodes` authorities (trusted dealer): any `threshold` of
+/// the returned shares decrypt the ciphertexts.
+pub fn generate_threshold_keys<R>(
+    nodes: usize,
+    threshold: usize,
+    csprng: &mut R,
+) -> (ThresholdPublicKey, Vec<ThresholdKeyShare>)
+where
+    R: CryptoRng + RngCore,
+{
+    assert!(threshold > 0 && threshold <= nodes, "Invalid threshold");
+    let coefficients: Vec<_> = (0..threshold).map(|_| random_scalar(csprng)).collect();
+    let mut shares = Vec::with_capacity(nodes);
+    let mut verification_keys = Vec::with_capacity(nodes);
+    for index in 1..=nodes as u64 {
+        // Evaluate the polynomial at the index of the share.
+        let x = scalar_from_u64(index);
+        let secret = coefficients
+            .iter()
+            .rev()
+            .fold(blst_fr::default(), |acc, coefficient| {
+                add_scalars(&mul_scalars(&acc, &x), coefficient)
+            });
+        verification_keys.push(G1Point::new(&mul_point(&generator(), &secret)));
+        shares.push(ThresholdKeyShare {
+            index,
+            secret: base64::encode(scalar_to_bytes(&secret)),
+        });
+    }
+    let key = G1Point::new(&mul_point(&generator(), &coefficients[0]));
+    let public = ThresholdPublicKey {
+        key,
+        verification_keys,
+        threshold,
+    };
+    (public, shares)
+}
+
+/// A transaction encrypted to the committee.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub struct Ciphertext {
+    /// The ephemeral key of the encryption.
+    pub u: G1Point,
+    /// The encrypted transaction.
+    pub data: Vec<u8>,
+    /// The authentication tag of the ciphertext.
+    pub tag: [u8; 32],
+}
+
+/// The share of an authority of the random coin of a seed (see `ThresholdPublicKey::coin`).
+pub type CoinShare = DecryptionShare;
+
+/// The decryption share of an authority for a ciphertext.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DecryptionShare {
+    /// The index of the key share.
+    pub index: u64,
+    /// The decryption share.
+    pub share: G1Point,
+    /// The challenge of the proof of correctness.
+    challenge: [u8; 32],
+    /// The response of the proof of correctness.
+    response: [u8; 32],
+}
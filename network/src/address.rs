@@ -0,0 +1,125 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::socket_config::SocketConfig;
+use serde::{de, ser, Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use tokio::net::TcpStream;
+
+#[cfg(test)]
+#[path = "tests/address_tests.rs"]
+pub mod address_tests;
+
+/// The network address of a peer. It is either a socket address (IPv4 or IPv6) or a DNS name and a port.
+/// DNS names are resolved every time we (re-)connect to the peer, so peers whose IP changes over time
+/// remain reachable without updating the committee file.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// An IPv4 or IPv6 socket address, eg. `127.0.0.1:80` or `[::1]:80`.
+    Socket(SocketAddr),
+    /// A DNS name and a port, eg. `localhost:80`.
+    Dns(String, u16),
+}
+
+impl Address {
+    /// Returns the port of the address.
+    pub fn port(&self) -> u16 {
+        match self {
+            Self::Socket(address) => address.port(),
+            Self::Dns(_, port) => *port,
+        }
+    }
+
+    /// Changes the port of the address.
+    pub fn set_port(&mut self, new_port: u16) {
+        match self {
+            Self::Socket(address) => address.set_port(new_port),
+            Self::Dns(_, port) => *port = new_port,
+        }
+    }
+
+    /// Returns the host part of the address (either an IP or a DNS name).
+    pub fn host(&self) -> String {
+        match self {
+            Self::Socket(address) => address.ip().to_string(),
+            Self::Dns(host, _) => host.clone(),
+        }
+    }
+
+    /// Returns the address to bind to in order to receive messages sent to this address, that is the
+    /// unspecified address of the same family (`0.0.0.0` or `[::]`) with the same port.
+    pub fn listen_address(&self) -> SocketAddr {
+        let ip = match self {
+            Self::Socket(SocketAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            _ => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        SocketAddr::new(ip, self.port())
+    }
+
+    /// Open a TCP connection to the address with the default socket options. DNS names are resolved anew
+    /// on every call.
+    pub async fn connect(&self) -> io::Result<TcpStream> {
+        SocketConfig::default().connect(self).await
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(address: SocketAddr) -> Self {
+        Self::Socket(address)
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = s.parse::<SocketAddr>() {
+            return Ok(Self::Socket(address));
+        }
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("Missing port in address '{}'", s))?;
+        let port = port
+            .parse()
+            .map_err(|e| format!("Invalid port in address '{}': {}", s, e))?;
+        if host.is_empty() || host.contains(':') {
+            return Err(format!("Invalid host in address '{}'", s));
+        }
+        Ok(Self::Dns(host.to_string(), port))
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Self::Socket(address) => write!(f, "{}", address),
+            Self::Dns(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
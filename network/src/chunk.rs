@@ -0,0 +1,211 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::NetworkError;
+use crate::frame_writer::MAX_FRAME_LENGTH;
+use bytes::{BufMut as _, Bytes, BytesMut};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::convert::TryInto as _;
+use std::net::SocketAddr;
+
+#[cfg(test)]
+#[path = "tests/chunk_tests.rs"]
+pub mod chunk_tests;
+
+/// The size of the header of each chunk: the id of the message (u64), the sequence number of the chunk
+/// within the message (u32), and a flag (u8): 0 if more chunks follow, 1 for the last chunk of the message,
+/// and 2 to abort the message. All big-endian.
+pub const CHUNK_HEADER_SIZE: usize = 13;
+
+/// The flag of the last chunk of a message.
+const LAST: u8 = 1;
+
+/// The flag of the (empty) chunk aborting a message that was partially sent.
+const ABORT: u8 = 2;
+
+/// The maximum size of a message reassembled from chunks (and the maximum amount of memory held by the
+/// partial messages of a single connection).
+pub const MAX_CHUNKED_MESSAGE_LENGTH: usize = 256 * 1024 * 1024;
+
+/// A single frame of an outgoing message.
+pub struct Chunk {
+    /// The header of the chunk (none if chunking is disabled).
+    header: Option<[u8; CHUNK_HEADER_SIZE]>,
+    /// The part of the message carried by the chunk.
+    pub payload: Bytes,
+}
+
+impl Chunk {
+    /// Returns the header of the chunk (empty if chunking is disabled).
+    pub fn header(&self) -> &[u8] {
+        match &self.header {
+            Some(header) => header,
+            None => &[],
+        }
+    }
+}
+
+/// Splits the outgoing messages of a connection into chunks, so that large messages can be sent one chunk
+/// at a time and interleaved with other messages. A chunk size of 0 disables chunking: each message is sent
+/// as a single frame without header (the receiver must then not expect chunks).
+pub struct Chunker {
+    /// The maximum size of the payload of each chunk (0 if chunking is disabled).
+    chunk_size: usize,
+    /// The id of the next message.
+    next_id: u64,
+}
+
+impl Chunker {
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk_size: min(chunk_size, MAX_FRAME_LENGTH - CHUNK_HEADER_SIZE),
+            next_id: 0,
+        }
+    }
+
+    /// Prepare a message to be sent chunk by chunk.
+    pub fn split(&mut self, data: Bytes) -> Chunks {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        Chunks {
+            id,
+            data,
+            chunk_size: self.chunk_size,
+            sequence: 0,
+            offset: 0,
+        }
+    }
+}
+
+/// The chunks of a single outgoing message.
+pub struct Chunks {
+    /// The id of the message (unique within the connection).
+    id: u64,
+    /// The whole message.
+    data: Bytes,
+    /// The maximum size of the payload of each chunk (0 if chunking is disabled).
+    chunk_size: usize,
+    /// The sequence number of the next chunk.
+    sequence: u32,
+    /// The offset of the payload of the next chunk.
+    offset: usize,
+}
+
+impl Chunks {
+    /// Returns whether all chunks of the message have been produced.
+    pub fn is_done(&self) -> bool {
+        self.sequence > 0 && self.offset >= self.data.len()
+    }
+
+    /// Returns the next chunk of the message (if any).
+    pub fn next_chunk(&mut self) -> Option<Chunk> {
+        if self.is_done() {
+            return None;
+        }
+        if self.chunk_size == 0 {
+            self.sequence = 1;
+            self.offset = self.data.len();
+            return Some(Chunk {
+                header: None,
+                payload: self.data.clone(),
+            });
+        }
+
+        let end = min(self.offset + self.chunk_size, self.data.len());
+        let mut header = [0u8; CHUNK_HEADER_SIZE];
+        header[..8].copy_from_slice(&self.id.to_be_bytes());
+        header[8..12].copy_from_slice(&self.sequence.to_be_bytes());
+        header[12] = if end == self.data.len() { LAST } else { 0 };
+        let payload = self.data.slice(self.offset..end);
+        self.sequence += 1;
+        self.offset = end;
+        Some(Chunk {
+            header: Some(header),
+            payload,
+        })
+    }
+
+    /// Returns the chunk telling the receiver to drop the part of the message it already received, if the
+    /// message is abandoned mid-stream (eg. because it was cancelled).
+    pub fn abort(&self) -> Option<Chunk> {
+        if self.chunk_size == 0 || self.sequence == 0 || self.is_done() {
+            return None;
+        }
+        let mut header = [0u8; CHUNK_HEADER_SIZE];
+        header[..8].copy_from_slice(&self.id.to_be_bytes());
+        header[8..12].copy_from_slice(&self.sequence.to_be_bytes());
+        header[12] = ABORT;
+        Some(Chunk {
+            header: Some(header),
+            payload: Bytes::new(),
+        })
+    }
+
+    /// Returns the whole message (eg. to re-transmit it from the start).
+    pub fn into_inner(self) -> Bytes {
+        self.data
+    }
+}
+
+/// Reassembles the messages received in chunks over a single connection.
+pub struct Reassembler {
+    /// The peer at the other end of the connection.
+    peer: SocketAddr,
+    /// The partial messages, with the sequence number of their next chunk.
+    partial: HashMap<u64, (u32, BytesMut)>,
+    /// The total size of the partial messages.
+    size: usize,
+}
+
+impl Reassembler {
+    pub fn new(peer: SocketAddr) -> Self {
+        Self {
+            peer,
+            partial: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Process a chunk. It returns the message once its last chunk is received.
+    pub fn push(&mut self, frame: Bytes) -> Result<Option<Bytes>, NetworkError> {
+        if frame.len() < CHUNK_HEADER_SIZE || frame[12] > ABORT {
+            return Err(NetworkError::MalformedChunk(self.peer));
+        }
+        let id = u64::from_be_bytes(frame[..8].try_into().unwrap());
+        let sequence = u32::from_be_bytes(frame[8..12].try_into().unwrap());
+        let last = frame[12] == LAST;
+        let payload = frame.slice(CHUNK_HEADER_SIZE..);
+
+        // Drop the chunks received so far if the sender abandoned the message.
+        if frame[12] == ABORT {
+            if let Some((_, message)) = self.partial.remove(&id) {
+                self.size -= message.len();
+            }
+            return Ok(None);
+        }
+
+        // Messages fitting in a single chunk are delivered without copy.
+        if sequence == 0 && last && !self.partial.contains_key(&id) {
+            return Ok(Some(payload));
+        }
+
+        let expected = self.partial.get(&id).map_or(0, |(next, _)| *next);
+        if sequence != expected {
+            return Err(NetworkError::MalformedChunk(self.peer));
+        }
+        if self.size + payload.len() > MAX_CHUNKED_MESSAGE_LENGTH {
+            return Err(NetworkError::MessageTooLarge(self.peer));
+        }
+        self.size += payload.len();
+
+        let (next, message) = self.partial.entry(id).or_default();
+        *next += 1;
+        message.put(payload);
+        if !last {
+            return Ok(None);
+        }
+
+        let (_, message) = self.partial.remove(&id).unwrap();
+        self.size -= message.len();
+        Ok(Some(message.freeze()))
+    }
+}
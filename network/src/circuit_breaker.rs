@@ -0,0 +1,89 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/circuit_breaker_tests.rs"]
+pub mod circuit_breaker_tests;
+
+/// The state of the circuit to a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The peer is reachable (or we did not fail often enough to think otherwise).
+    Closed,
+    /// The peer failed too many times in a row: messages to it are dropped rather than buffered.
+    Open,
+    /// The circuit has been open long enough: the next connection attempt probes the peer.
+    HalfOpen,
+}
+
+/// The failure record of a single peer.
+#[derive(Default)]
+struct Circuit {
+    /// The number of consecutive failures to reach the peer.
+    failures: u32,
+    /// The last time the circuit opened (if it is not closed).
+    opened_at: Option<Instant>,
+}
+
+/// Keeps track of the peers that persistently fail, so that we stop wasting resources on them. The circuit
+/// of a peer opens after `failure_threshold` consecutive failures, and half-opens `reset_timeout` later to
+/// let a single probe through. It closes again as soon as we manage to reach the peer. The breaker is cheap
+/// to clone and all clones share the same state.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    /// The number of consecutive failures after which the circuit opens.
+    failure_threshold: u32,
+    /// How long the circuit stays open before probing the peer again.
+    reset_timeout: Duration,
+    /// The failure record of each peer.
+    circuits: Arc<Mutex<HashMap<Address, Circuit>>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        Self {
+            failure_threshold,
+            reset_timeout,
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns how long the circuit stays open before probing the peer again.
+    pub fn reset_timeout(&self) -> Duration {
+        self.reset_timeout
+    }
+
+    /// Returns the state of the circuit to the specified peer.
+    pub fn state(&self, address: &Address) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap();
+        match circuits.get(address).and_then(|x| x.opened_at) {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.reset_timeout => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Returns whether it is worth sending messages to the specified peer.
+    pub fn is_available(&self, address: &Address) -> bool {
+        self.state(address) != CircuitState::Open
+    }
+
+    /// Record that we successfully reached the specified peer. This closes its circuit.
+    pub fn record_success(&self, address: &Address) {
+        self.circuits.lock().unwrap().remove(address);
+    }
+
+    /// Record that we failed to reach the specified peer. This (re-)opens its circuit if the peer failed
+    /// too many times in a row.
+    pub fn record_failure(&self, address: &Address) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(address.clone()).or_default();
+        circuit.failures = circuit.failures.saturating_add(1);
+        if circuit.failures >= self.failure_threshold {
+            circuit.opened_at = Some(Instant::now());
+        }
+    }
+}
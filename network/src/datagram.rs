@@ -0,0 +1,170 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::error::NetworkError;
+use crate::metrics::NetworkMetrics;
+use crate::simple_sender::SimpleSender;
+use crate::transport::{DatagramSocket, SharedTransport, TcpTransport};
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::{debug, warn};
+use std::error::Error;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::lookup_host;
+
+#[cfg(test)]
+#[path = "tests/datagram_tests.rs"]
+pub mod datagram_tests;
+
+/// The largest payload that fits in a single UDP datagram.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+#[async_trait]
+pub trait DatagramHandler: Clone + Send + Sync + 'static {
+    /// Defines how to handle an incoming datagram. Datagrams are unreliable: they may be lost, duplicated,
+    /// or re-ordered, and there is no way to reply to their sender.
+    async fn dispatch(&self, message: Bytes) -> Result<(), Box<dyn Error>>;
+}
+
+/// Sends small messages as UDP datagrams and falls back to (best-effort) TCP for the larger ones. This saves
+/// the latency of establishing and ACK-ing TCP connections for tiny, latency-critical messages, at the cost
+/// of reliability. The receiving end should run both a `DatagramReceiver` and a TCP `Receiver` on the same port.
+pub struct DatagramSender {
+    /// Messages up to this size (in bytes) are sent as datagrams.
+    max_datagram_size: usize,
+    /// The socket used to send datagrams to IPv4 peers (bound on first use).
+    socket_v4: Option<Box<dyn DatagramSocket>>,
+    /// The socket used to send datagrams to IPv6 peers (bound on first use).
+    socket_v6: Option<Box<dyn DatagramSocket>>,
+    /// The transport binding the datagram sockets.
+    transport: SharedTransport,
+    /// The sender used for messages that are too large to fit in a datagram.
+    fallback: SimpleSender,
+}
+
+impl DatagramSender {
+    pub fn new(max_datagram_size: usize) -> Self {
+        Self {
+            max_datagram_size: max_datagram_size.min(MAX_DATAGRAM_SIZE),
+            socket_v4: None,
+            socket_v6: None,
+            transport: Arc::new(TcpTransport::default()),
+            fallback: SimpleSender::new(),
+        }
+    }
+
+    /// Returns a socket able to reach the specified peer, binding it if needed.
+    async fn socket(&mut self, peer: &SocketAddr) -> io::Result<&dyn DatagramSocket> {
+        let (socket, unspecified) = match peer {
+            SocketAddr::V4(_) => (
+                &mut self.socket_v4,
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            ),
+            SocketAddr::V6(_) => (
+                &mut self.socket_v6,
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            ),
+        };
+        if socket.is_none() {
+            *socket = Some(self.transport.bind_datagram(&unspecified).await?);
+        }
+        Ok(socket.as_deref().unwrap())
+    }
+
+    /// Send a datagram to the specified address. DNS names are resolved anew on every call.
+    async fn send_datagram(&mut self, address: &Address, data: &Bytes) -> io::Result<()> {
+        let peer = match address {
+            Address::Socket(peer) => *peer,
+            Address::Dns(host, port) => lookup_host((host.as_str(), *port))
+                .await?
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Failed to resolve host"))?,
+        };
+        self.socket(&peer).await?.send_to(data, &peer).await
+    }
+
+    /// Set the transport used to send datagrams and large messages (UDP and TCP by default).
+    pub fn with_transport(mut self, transport: SharedTransport) -> Self {
+        self.fallback = self.fallback.with_transport(transport.clone());
+        self.transport = transport;
+        self
+    }
+
+    /// Record the connection counters of the large messages' peers into the specified metrics (datagrams
+    /// are connectionless and are not counted).
+    pub fn with_metrics(mut self, metrics: NetworkMetrics) -> Self {
+        self.fallback = self.fallback.with_metrics(metrics);
+        self
+    }
+
+    /// Try (best-effort) to send a message to a specific address.
+    pub async fn send(&mut self, address: Address, data: Bytes) {
+        if data.len() > self.max_datagram_size {
+            return self.fallback.send(address, data).await;
+        }
+        if let Err(e) = self.send_datagram(&address, &data).await {
+            warn!("{}", NetworkError::FailedToSendMessage(address, e));
+        }
+    }
+
+    /// Try (best-effort) to broadcast the message to all specified addresses.
+    pub async fn broadcast(&mut self, addresses: Vec<Address>, data: Bytes) {
+        for address in addresses {
+            self.send(address, data.clone()).await;
+        }
+    }
+}
+
+/// Receives datagrams and processes them with the provided handler.
+pub struct DatagramReceiver<Handler: DatagramHandler> {
+    /// Address to listen to.
+    address: SocketAddr,
+    /// Struct responsible to define how to handle received datagrams.
+    handler: Handler,
+    /// The transport binding the datagram socket.
+    transport: SharedTransport,
+}
+
+impl<Handler: DatagramHandler> DatagramReceiver<Handler> {
+    /// Spawn a new datagram receiver handling datagrams from any peer.
+    pub fn spawn(address: SocketAddr, handler: Handler) {
+        Self::spawn_with_transport(address, handler, Arc::new(TcpTransport::default()));
+    }
+
+    /// Spawn a new datagram receiver over a custom transport.
+    pub fn spawn_with_transport(address: SocketAddr, handler: Handler, transport: SharedTransport) {
+        tokio::spawn(async move {
+            Self {
+                address,
+                handler,
+                transport,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Main loop receiving datagrams and dispatching them to the handler.
+    async fn run(&self) {
+        let mut socket = self
+            .transport
+            .bind_datagram(&self.address)
+            .await
+            .expect("Failed to bind UDP port");
+
+        debug!("Listening to datagrams on {}", self.address);
+        loop {
+            let (message, peer) = match socket.recv_from().await {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!("{}", NetworkError::FailedToReceiveDatagram(e));
+                    continue;
+                }
+            };
+            if let Err(e) = self.handler.dispatch(message).await {
+                warn!("Failed to handle datagram from {}: {}", peer, e);
+            }
+        }
+    }
+}
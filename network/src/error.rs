@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use thiserror::Error;
@@ -6,20 +7,38 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum NetworkError {
     #[error("Failed to connect to {0} (retry {1}): {2}")]
-    FailedToConnect(SocketAddr, u16, std::io::Error),
+    FailedToConnect(Address, u16, std::io::Error),
 
     #[error("Failed to accept connection: {0}")]
     FailedToListen(std::io::Error),
 
     #[error("Failed to send message to {0}: {1}")]
-    FailedToSendMessage(SocketAddr, std::io::Error),
+    FailedToSendMessage(Address, std::io::Error),
+
+    #[error("Rejected connection from {0}: not in the allowlist")]
+    UnauthorizedPeer(SocketAddr),
 
     #[error("Failed to receive message from {0}: {1}")]
     FailedToReceiveMessage(SocketAddr, std::io::Error),
 
     #[error("Failed to receive ACK from {0}")]
-    FailedToReceiveAck(SocketAddr),
+    FailedToReceiveAck(Address),
 
     #[error("Receive unexpected ACK from {0}")]
-    UnexpectedAck(SocketAddr),
+    UnexpectedAck(Address),
+
+    #[error("Circuit to {0} is open: dropping messages until the next probe")]
+    CircuitOpen(Address),
+
+    #[error("Failed to receive datagram: {0}")]
+    FailedToReceiveDatagram(std::io::Error),
+
+    #[error("Received a malformed chunk from {0}")]
+    MalformedChunk(SocketAddr),
+
+    #[error("Received a message too large from {0}")]
+    MessageTooLarge(SocketAddr),
+
+    #[error("Failed to deserialize message: {0}")]
+    InvalidMessage(Box<bincode::ErrorKind>),
 }
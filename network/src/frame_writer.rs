@@ -0,0 +1,52 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use bytes::{Buf as _, Bytes};
+use std::io::{Error, ErrorKind, IoSlice};
+use tokio::io::{AsyncWrite, AsyncWriteExt as _};
+
+#[cfg(test)]
+#[path = "tests/frame_writer_tests.rs"]
+pub mod frame_writer_tests;
+
+/// The maximum size of a frame (this is the default limit of `LengthDelimitedCodec`).
+pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Writes length-delimited frames to a stream without copying the payload. The 4-bytes big-endian
+/// length prefix and the payload are handed to the kernel together through a vectored write, so the
+/// same `Bytes` buffer can be shared by all the connections of a broadcast. The wire format is the
+/// one of `LengthDelimitedCodec`, so the receiving end can keep using a regular `Framed` reader.
+pub struct FrameWriter<W> {
+    /// The write half of the stream.
+    stream: W,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    pub fn new(stream: W) -> Self {
+        Self { stream }
+    }
+
+    /// Write a single frame to the stream.
+    pub async fn send(&mut self, data: &Bytes) -> Result<(), Error> {
+        self.send_with_header(&[], data).await
+    }
+
+    /// Write a single frame made of the specified header followed by the data.
+    pub async fn send_with_header(&mut self, header: &[u8], data: &Bytes) -> Result<(), Error> {
+        let length = header.len() + data.len();
+        if length > MAX_FRAME_LENGTH {
+            return Err(Error::new(ErrorKind::InvalidInput, "Frame too big"));
+        }
+
+        let prefix = (length as u32).to_be_bytes();
+        let mut frame = (&prefix[..]).chain(header).chain(&data[..]);
+        while frame.has_remaining() {
+            let mut slices = [IoSlice::new(&[]); 3];
+            let n = frame.chunks_vectored(&mut slices);
+            let written = self.stream.write_vectored(&slices[..n]).await?;
+            if written == 0 {
+                return Err(ErrorKind::WriteZero.into());
+            }
+            frame.advance(written);
+        }
+        Ok(())
+    }
+}
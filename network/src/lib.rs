@@ -1,13 +1,40 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod address;
+mod chunk;
+mod circuit_breaker;
+mod datagram;
 mod error;
+mod frame_writer;
+mod metrics;
+mod proxy;
 mod receiver;
+mod reliable_broadcast;
 mod reliable_sender;
 mod simple_sender;
+mod socket_config;
+mod traffic_shaping;
+mod transport;
+mod typed_receiver;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 pub mod common;
 
+pub use crate::address::Address;
+pub use crate::chunk::{CHUNK_HEADER_SIZE, MAX_CHUNKED_MESSAGE_LENGTH};
+pub use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+pub use crate::datagram::{DatagramHandler, DatagramReceiver, DatagramSender, MAX_DATAGRAM_SIZE};
+pub use crate::frame_writer::{FrameWriter, MAX_FRAME_LENGTH};
+pub use crate::metrics::{NetworkMetrics, PeerMetrics};
+pub use crate::proxy::Proxy;
 pub use crate::receiver::{MessageHandler, Receiver, Writer};
+pub use crate::reliable_broadcast::{BroadcastStatus, ReliableBroadcast};
 pub use crate::reliable_sender::{CancelHandler, ReliableSender};
 pub use crate::simple_sender::SimpleSender;
+pub use crate::socket_config::SocketConfig;
+pub use crate::traffic_shaping::{EgressConfig, RateLimit, ShapedTransport, TokenBucket};
+pub use crate::transport::{
+    AsyncStream, BoxedStream, DatagramSocket, Listener, MemoryTransport, SharedTransport,
+    TcpTransport, Transport,
+};
+pub use crate::typed_receiver::{TypedMessageHandler, TypedReceiver};
@@ -0,0 +1,167 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/metrics_tests.rs"]
+pub mod metrics_tests;
+
+/// The connection counters of a single peer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerMetrics {
+    /// The number of attempts to connect to the peer.
+    pub connection_attempts: u64,
+    /// The number of failed connection attempts and of broken connections.
+    pub connection_failures: u64,
+    /// The number of connections established after the first one.
+    pub reconnects: u64,
+    /// The number of bytes sent but not acknowledged before the connection broke, and that are
+    /// thus re-transmitted. Denominated in bytes.
+    pub retransmitted_bytes: u64,
+    /// The total time spent without connection to the peer (since the first failure).
+    pub disconnected_time: Duration,
+}
+
+/// The record of a single peer.
+#[derive(Default)]
+struct Peer {
+    metrics: PeerMetrics,
+    /// Whether we ever connected to the peer.
+    connected: bool,
+    /// When we lost the connection to the peer (if we are currently disconnected).
+    disconnected_since: Option<Instant>,
+}
+
+/// Keeps the connection counters of each peer, so that flaky links are visible. The metrics are cheap
+/// to clone and all clones share the same counters: handing the same metrics to several senders adds
+/// up their counters.
+#[derive(Clone, Default)]
+pub struct NetworkMetrics {
+    peers: Arc<Mutex<HashMap<Address, Peer>>>,
+}
+
+impl NetworkMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the counters of the specified peer.
+    pub fn peer(&self, address: &Address) -> PeerMetrics {
+        let peers = self.peers.lock().unwrap();
+        peers.get(address).map(Self::snapshot).unwrap_or_default()
+    }
+
+    /// Returns the counters of all peers, sorted by address.
+    pub fn peers(&self) -> Vec<(Address, PeerMetrics)> {
+        let peers = self.peers.lock().unwrap();
+        let mut metrics: Vec<_> = peers
+            .iter()
+            .map(|(address, peer)| (address.clone(), Self::snapshot(peer)))
+            .collect();
+        metrics.sort_by_key(|(address, _)| address.to_string());
+        metrics
+    }
+
+    /// Returns the counters of the peer, including the ongoing disconnection (if any).
+    fn snapshot(peer: &Peer) -> PeerMetrics {
+        let mut metrics = peer.metrics.clone();
+        if let Some(since) = peer.disconnected_since {
+            metrics.disconnected_time += since.elapsed();
+        }
+        metrics
+    }
+
+    /// Record an attempt to connect to the specified peer.
+    pub fn record_attempt(&self, address: &Address) {
+        let mut peers = self.peers.lock().unwrap();
+        peers
+            .entry(address.clone())
+            .or_default()
+            .metrics
+            .connection_attempts += 1;
+    }
+
+    /// Record that we failed to connect to the specified peer, or that the connection broke.
+    pub fn record_failure(&self, address: &Address) {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = peers.entry(address.clone()).or_default();
+        peer.metrics.connection_failures += 1;
+        peer.disconnected_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Record that we established a connection with the specified peer.
+    pub fn record_connected(&self, address: &Address) {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = peers.entry(address.clone()).or_default();
+        if peer.connected {
+            peer.metrics.reconnects += 1;
+        }
+        peer.connected = true;
+        if let Some(since) = peer.disconnected_since.take() {
+            peer.metrics.disconnected_time += since.elapsed();
+        }
+    }
+
+    /// Record that the specified number of bytes will be re-transmitted to the specified peer.
+    pub fn record_retransmission(&self, address: &Address, bytes: usize) {
+        let mut peers = self.peers.lock().unwrap();
+        peers
+            .entry(address.clone())
+            .or_default()
+            .metrics
+            .retransmitted_bytes += bytes as u64;
+    }
+
+    /// Encode the counters of all peers in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let peers = self.peers();
+        // The name, description, and value of each counter.
+        type Counter = (&'static str, &'static str, fn(&PeerMetrics) -> String);
+        let counters: [Counter; 5] = [
+            (
+                "network_connection_attempts_total",
+                "Attempts to connect to the peer.",
+                |x| x.connection_attempts.to_string(),
+            ),
+            (
+                "network_connection_failures_total",
+                "Failed connection attempts and broken connections.",
+                |x| x.connection_failures.to_string(),
+            ),
+            (
+                "network_reconnects_total",
+                "Connections established after the first one.",
+                |x| x.reconnects.to_string(),
+            ),
+            (
+                "network_retransmitted_bytes_total",
+                "Bytes re-transmitted after a connection broke.",
+                |x| x.retransmitted_bytes.to_string(),
+            ),
+            (
+                "network_disconnected_seconds_total",
+                "Time spent without connection to the peer.",
+                |x| x.disconnected_time.as_secs_f64().to_string(),
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, help, value) in counters.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            for (address, metrics) in &peers {
+                let _ = writeln!(
+                    output,
+                    "{}{{peer=\"{}\"}} {}",
+                    name,
+                    address,
+                    value(metrics)
+                );
+            }
+        }
+        output
+    }
+}
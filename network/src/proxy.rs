@@ -0,0 +1,82 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use serde::Deserialize;
+use std::io::{self, Error, ErrorKind};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+
+#[cfg(test)]
+#[path = "tests/proxy_tests.rs"]
+pub mod proxy_tests;
+
+/// The maximum size of the headers of the reply of an HTTP proxy.
+const MAX_HTTP_HEADERS_SIZE: usize = 8 * 1024;
+
+/// A proxy through which to dial peers. DNS names of the peers are resolved by the proxy.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Proxy {
+    /// A SOCKS5 proxy (without authentication), eg. `{ "socks5": "127.0.0.1:9050" }`.
+    Socks5(Address),
+    /// An HTTP proxy supporting the `CONNECT` method, eg. `{ "http": "proxy.example.com:3128" }`.
+    Http(Address),
+}
+
+impl Proxy {
+    /// Returns the address of the proxy.
+    pub fn address(&self) -> &Address {
+        match self {
+            Self::Socks5(address) | Self::Http(address) => address,
+        }
+    }
+
+    /// Ask the proxy (through a connection already open with it) to open a tunnel to the target. The
+    /// returned stream then carries the traffic of the target.
+    pub async fn handshake(&self, stream: TcpStream, target: &Address) -> io::Result<TcpStream> {
+        match self {
+            Self::Socks5(_) => Self::socks5_handshake(stream, target).await,
+            Self::Http(_) => Self::http_handshake(stream, target).await,
+        }
+    }
+
+    async fn socks5_handshake(stream: TcpStream, target: &Address) -> io::Result<TcpStream> {
+        let stream = match target {
+            Address::Socket(address) => Socks5Stream::connect_with_socket(stream, *address).await,
+            Address::Dns(host, port) => {
+                Socks5Stream::connect_with_socket(stream, (host.as_str(), *port)).await
+            }
+        };
+        stream
+            .map(Socks5Stream::into_inner)
+            .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))
+    }
+
+    async fn http_handshake(mut stream: TcpStream, target: &Address) -> io::Result<TcpStream> {
+        let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target);
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read the reply one byte at a time to not consume any byte of the tunnel.
+        let mut reply = Vec::new();
+        while !reply.ends_with(b"\r\n\r\n") {
+            if reply.len() >= MAX_HTTP_HEADERS_SIZE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "HTTP proxy reply too big",
+                ));
+            }
+            reply.push(stream.read_u8().await?);
+        }
+
+        // Only a 2xx status means the tunnel is open.
+        let reply = String::from_utf8_lossy(&reply);
+        let status = reply.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(stream),
+            _ => Err(Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("HTTP proxy refused to connect to {}: {}", target, status),
+            )),
+        }
+    }
+}
@@ -1,21 +1,26 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::chunk::Reassembler;
 use crate::error::NetworkError;
+use crate::transport::{BoxedStream, SharedTransport, TcpTransport};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::stream::SplitSink;
 use futures::stream::StreamExt as _;
 use log::{debug, info, warn};
+use std::collections::HashSet;
 use std::error::Error;
-use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::lookup_host;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[cfg(test)]
 #[path = "tests/receiver_tests.rs"]
 pub mod receiver_tests;
 
-/// Convenient alias for the writer end of the TCP channel.
-pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
+/// Convenient alias for the writer end of the channel.
+pub type Writer = SplitSink<Framed<BoxedStream, LengthDelimitedCodec>, Bytes>;
 
 #[async_trait]
 pub trait MessageHandler: Clone + Send + Sync + 'static {
@@ -24,6 +29,17 @@ pub trait MessageHandler: Clone + Send + Sync + 'static {
     /// forward them through the appropriate delivery channel. Then `writer` can be used to send back
     /// responses or acknowledgements to the sender machine (see unit tests for examples).
     async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>>;
+
+    /// Same as `dispatch`, for handlers that need to know which peer sent the message (eg. to enforce
+    /// per-peer quotas). By default, the peer is ignored.
+    async fn dispatch_from(
+        &self,
+        _peer: SocketAddr,
+        writer: &mut Writer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        self.dispatch(writer, message).await
+    }
 }
 
 /// For each incoming request, we spawn a new runner responsible to receive messages and forward them
@@ -33,19 +49,85 @@ pub struct Receiver<Handler: MessageHandler> {
     address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// The hosts allowed to connect (if restricted).
+    allowlist: Option<Arc<Vec<Address>>>,
+    /// The transport accepting incoming connections.
+    transport: SharedTransport,
+    /// Whether the peers send their messages in chunks.
+    chunked: bool,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
     /// Spawn a new network receiver handling connections from any incoming peer.
     pub fn spawn(address: SocketAddr, handler: Handler) {
+        Self::spawn_with_options(
+            address,
+            handler,
+            /* allowlist */ None,
+            Arc::new(TcpTransport::default()),
+            /* chunked */ false,
+        );
+    }
+
+    /// Spawn a new network receiver over a custom transport, only accepting connections from the hosts
+    /// of the specified addresses (typically the addresses of the committee). Connections from any other host
+    /// are closed before any message reaches the handler. Only the IP of the peer is checked (not its port),
+    /// and DNS names are resolved anew for each incoming connection (by its runner, so that slow lookups do not
+    /// hold back the other connections). An allowlist set to `None` accepts any peer.
+    /// If `chunked` is set, the peers must send their messages in chunks (see `ReliableSender::with_chunk_size`):
+    /// the handler only sees whole messages.
+    pub fn spawn_with_options(
+        address: SocketAddr,
+        handler: Handler,
+        allowlist: Option<Vec<Address>>,
+        transport: SharedTransport,
+        chunked: bool,
+    ) {
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
+            Self {
+                address,
+                handler,
+                allowlist: allowlist.map(Arc::new),
+                transport,
+                chunked,
+            }
+            .run()
+            .await;
         });
     }
 
+    /// Check whether the specified peer is allowed to connect.
+    async fn is_allowed(allowlist: &[Address], peer: &SocketAddr) -> bool {
+        // Peers connecting over IPv4 to an IPv6 socket show up as IPv4-mapped addresses.
+        let ip = match peer.ip() {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        };
+
+        let mut hosts = HashSet::new();
+        for address in allowlist {
+            match address {
+                Address::Socket(address) if address.ip() == ip => return true,
+                Address::Socket(_) => (),
+                Address::Dns(host, port) => {
+                    if hosts.insert(host) {
+                        if let Ok(mut resolved) = lookup_host((host.as_str(), *port)).await {
+                            if resolved.any(|x| x.ip() == ip) {
+                                return true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
     /// Main loop responsible to accept incoming connections and spawn a new runner to handle it.
     async fn run(&self) {
-        let listener = TcpListener::bind(&self.address)
+        let mut listener = self
+            .transport
+            .listen(&self.address)
             .await
             .expect("Failed to bind TCP port");
 
@@ -58,21 +140,49 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                     continue;
                 }
             };
-            info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            Self::spawn_runner(
+                socket,
+                peer,
+                self.allowlist.clone(),
+                self.handler.clone(),
+                self.chunked,
+            )
+            .await;
         }
     }
 
-    /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
-    /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    /// Spawn a new runner to handle a specific connection. It checks that the peer is allowed to connect, then
+    /// receives messages and process them using the provided handler.
+    async fn spawn_runner(
+        socket: BoxedStream,
+        peer: SocketAddr,
+        allowlist: Option<Arc<Vec<Address>>>,
+        handler: Handler,
+        chunked: bool,
+    ) {
         tokio::spawn(async move {
+            if let Some(allowlist) = allowlist {
+                if !Self::is_allowed(&allowlist, &peer).await {
+                    warn!("{}", NetworkError::UnauthorizedPeer(peer));
+                    return;
+                }
+            }
+            info!("Incoming connection established with {}", peer);
+
             let transport = Framed::new(socket, LengthDelimitedCodec::new());
             let (mut writer, mut reader) = transport.split();
+            let mut reassembler = chunked.then(|| Reassembler::new(peer));
             while let Some(frame) = reader.next().await {
-                match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
-                    Ok(message) => {
-                        if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
+                let message = frame
+                    .map_err(|e| NetworkError::FailedToReceiveMessage(peer, e))
+                    .and_then(|frame| match &mut reassembler {
+                        Some(reassembler) => reassembler.push(frame.freeze()),
+                        None => Ok(Some(frame.freeze())),
+                    });
+                match message {
+                    Ok(None) => (),
+                    Ok(Some(message)) => {
+                        if let Err(e) = handler.dispatch_from(peer, &mut writer, message).await {
                             warn!("{}", e);
                             return;
                         }
@@ -0,0 +1,120 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::reliable_sender::{CancelHandler, ReliableSender};
+use bytes::Bytes;
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::stream::StreamExt as _;
+use std::collections::HashMap;
+
+#[cfg(test)]
+#[path = "tests/reliable_broadcast_tests.rs"]
+pub mod reliable_broadcast_tests;
+
+/// The delivery status of a broadcast.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BroadcastStatus {
+    /// The destinations that acknowledged the message.
+    pub acked: Vec<Address>,
+    /// The destinations that did not acknowledge the message yet.
+    pub stragglers: Vec<Address>,
+}
+
+impl BroadcastStatus {
+    /// Returns whether all destinations acknowledged the message.
+    pub fn is_complete(&self) -> bool {
+        self.stragglers.is_empty()
+    }
+}
+
+/// A message reliably sent to a set of destinations, keeping track of which of them acknowledged it.
+/// The message is re-transmitted to the stragglers as long as the broadcast is alive: dropping it cancels
+/// all pending transmissions.
+pub struct ReliableBroadcast {
+    /// The message.
+    data: Bytes,
+    /// The destinations that acknowledged the message.
+    acked: Vec<Address>,
+    /// The cancel handlers of the destinations that did not acknowledge the message yet.
+    pending: HashMap<Address, CancelHandler>,
+}
+
+impl ReliableBroadcast {
+    /// Start sending the message to all specified destinations.
+    pub async fn send(network: &mut ReliableSender, addresses: Vec<Address>, data: Bytes) -> Self {
+        let handlers = network.broadcast(addresses.clone(), data.clone()).await;
+        Self {
+            data,
+            acked: Vec::new(),
+            pending: addresses.into_iter().zip(handlers).collect(),
+        }
+    }
+
+    /// Record the acknowledgements received so far.
+    fn collect_acks(&mut self) {
+        let acked: Vec<_> = self
+            .pending
+            .iter_mut()
+            .filter_map(|(address, handler)| handler.try_recv().ok().map(|_| address.clone()))
+            .collect();
+        for address in acked {
+            self.pending.remove(&address);
+            self.acked.push(address);
+        }
+    }
+
+    /// Returns which destinations acknowledged the message so far (sorted by address).
+    pub fn status(&mut self) -> BroadcastStatus {
+        self.collect_acks();
+        let mut acked = self.acked.clone();
+        acked.sort_by_key(|address| address.to_string());
+        let mut stragglers: Vec<_> = self.pending.keys().cloned().collect();
+        stragglers.sort_by_key(|address| address.to_string());
+        BroadcastStatus { acked, stragglers }
+    }
+
+    /// Wait until at least `threshold` destinations acknowledged the message. It returns early if fewer
+    /// than `threshold` acknowledgements can be received (eg. because messages were dropped by the circuit
+    /// breaker of the sender). Returns the number of destinations that acknowledged the message.
+    pub async fn wait_for(&mut self, threshold: usize) -> usize {
+        self.collect_acks();
+        let mut acked = Vec::new();
+        {
+            let mut waiting: FuturesUnordered<_> = self
+                .pending
+                .iter_mut()
+                .map(|(address, handler)| async move { (address, handler.await.is_ok()) })
+                .collect();
+            while self.acked.len() + acked.len() < threshold {
+                match waiting.next().await {
+                    Some((address, true)) => acked.push(address.clone()),
+                    Some((_, false)) => (),
+                    None => break,
+                }
+            }
+        }
+        for address in acked {
+            self.pending.remove(&address);
+            self.acked.push(address);
+        }
+        self.acked.len()
+    }
+
+    /// Wait until all destinations acknowledged the message (or their messages were dropped).
+    pub async fn wait(&mut self) -> BroadcastStatus {
+        let total = self.acked.len() + self.pending.len();
+        self.wait_for(total).await;
+        self.status()
+    }
+
+    /// Send the message anew to the destinations that did not acknowledge it yet (eg. after their messages
+    /// were dropped by the circuit breaker of the sender). Returns the number of stragglers.
+    pub async fn retrigger(&mut self, network: &mut ReliableSender) -> usize {
+        self.collect_acks();
+        let stragglers: Vec<_> = self.pending.keys().cloned().collect();
+        let handlers = network
+            .broadcast(stragglers.clone(), self.data.clone())
+            .await;
+        self.pending = stragglers.into_iter().zip(handlers).collect();
+        self.pending.len()
+    }
+}
@@ -1,7 +1,13 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::chunk::{Chunker, Chunks};
+use crate::circuit_breaker::CircuitBreaker;
 use crate::error::NetworkError;
+use crate::frame_writer::FrameWriter;
+use crate::metrics::NetworkMetrics;
+use crate::transport::{BoxedStream, SharedTransport, TcpTransport};
 use bytes::Bytes;
-use futures::sink::SinkExt as _;
+use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::{info, warn};
 use rand::prelude::SliceRandom as _;
@@ -10,12 +16,17 @@ use rand::SeedableRng as _;
 use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::future::Future;
+use std::ops::AddAssign;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::split;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
+use tokio::sync::oneshot::error::RecvError;
 use tokio::time::{sleep, Duration};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
 
 #[cfg(test)]
 #[path = "tests/reliable_sender_tests.rs"]
@@ -30,9 +41,17 @@ pub type CancelHandler = oneshot::Receiver<Bytes>;
 /// receive an ACK back (until they succeed or are canceled).
 pub struct ReliableSender {
     /// A map holding the channels to our connections.
-    connections: HashMap<SocketAddr, Sender<InnerMessage>>,
+    connections: HashMap<Address, Sender<InnerMessage>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// Tracks the peers that persistently fail (if enabled).
+    circuit_breaker: Option<CircuitBreaker>,
+    /// The transport used to open our connections.
+    transport: SharedTransport,
+    /// The connection counters of each peer.
+    metrics: NetworkMetrics,
+    /// The size of the chunks in which to split messages (0 if chunking is disabled).
+    chunk_size: usize,
 }
 
 impl std::default::Default for ReliableSender {
@@ -46,22 +65,88 @@ impl ReliableSender {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            circuit_breaker: None,
+            transport: Arc::new(TcpTransport::default()),
+            metrics: NetworkMetrics::new(),
+            chunk_size: 0,
+        }
+    }
+
+    /// Stop re-transmitting messages to peers whose circuit is open. Messages to such peers are dropped
+    /// (their cancel handlers resolve with an error) until the circuit closes again.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Set the transport used to open the connections (TCP by default).
+    pub fn with_transport(mut self, transport: SharedTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Record the connection counters of each peer into the specified metrics (eg. to share them with other
+    /// senders). By default, each sender keeps its own metrics.
+    pub fn with_metrics(mut self, metrics: NetworkMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Send messages in chunks of the specified size (0 disables chunking, the default). Chunks of different
+    /// messages are interleaved, so that small messages are not stuck behind large ones. The receivers must
+    /// expect chunks.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Returns the connection counters of each peer.
+    pub fn metrics(&self) -> &NetworkMetrics {
+        &self.metrics
+    }
+
+    /// Returns whether it is worth sending messages to the specified peer, that is if its circuit is not
+    /// open. This is always true if the sender has no circuit breaker.
+    pub fn is_available(&self, address: &Address) -> bool {
+        match &self.circuit_breaker {
+            Some(breaker) => breaker.is_available(address),
+            None => true,
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
+    fn spawn_connection(
+        address: Address,
+        circuit_breaker: Option<CircuitBreaker>,
+        transport: SharedTransport,
+        metrics: NetworkMetrics,
+        chunk_size: usize,
+    ) -> Sender<InnerMessage> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, circuit_breaker, transport, metrics, chunk_size, rx);
         tx
     }
 
     /// Reliably send a message to a specific address.
-    pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
+    pub async fn send(&mut self, address: Address, data: Bytes) -> CancelHandler {
         let (sender, receiver) = oneshot::channel();
+        let (circuit_breaker, transport, metrics, chunk_size) = (
+            &self.circuit_breaker,
+            &self.transport,
+            &self.metrics,
+            self.chunk_size,
+        );
         self.connections
-            .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
+            .entry(address.clone())
+            .or_insert_with(|| {
+                Self::spawn_connection(
+                    address,
+                    circuit_breaker.clone(),
+                    transport.clone(),
+                    metrics.clone(),
+                    chunk_size,
+                )
+            })
             .send(InnerMessage {
                 data,
                 cancel_handler: sender,
@@ -72,12 +157,9 @@ impl ReliableSender {
     }
 
     /// Broadcast the message to all specified addresses in a reliable manner. It returns a vector of
-    /// cancel handlers ordered as the input `addresses` vector.
-    pub async fn broadcast(
-        &mut self,
-        addresses: Vec<SocketAddr>,
-        data: Bytes,
-    ) -> Vec<CancelHandler> {
+    /// cancel handlers ordered as the input `addresses` vector. All connections share the same
+    /// underlying buffer: the payload is never copied.
+    pub async fn broadcast(&mut self, addresses: Vec<Address>, data: Bytes) -> Vec<CancelHandler> {
         let mut handlers = Vec::new();
         for address in addresses {
             let handler = self.send(address, data.clone()).await;
@@ -86,11 +168,56 @@ impl ReliableSender {
         handlers
     }
 
+    /// Broadcast the message to all specified addresses (each with the stake of its authority) in a reliable
+    /// manner and wait until the destinations acknowledging it hold at least `threshold` of the stake (see
+    /// `wait_for_quorum`).
+    pub async fn broadcast_and_wait_quorum<S>(
+        &mut self,
+        destinations: Vec<(Address, S)>,
+        data: Bytes,
+        threshold: S,
+    ) -> Option<Vec<CancelHandler>>
+    where
+        S: Copy + Default + Ord + AddAssign + Unpin,
+    {
+        let (addresses, stakes): (Vec<_>, Vec<_>) = destinations.into_iter().unzip();
+        let handlers = self.broadcast(addresses, data).await;
+        let handlers = stakes.into_iter().zip(handlers).collect();
+        Self::wait_for_quorum(handlers, S::default(), threshold).await
+    }
+
+    /// Wait until the destinations acknowledging their message hold at least `threshold` of the stake, starting
+    /// from the specified stake (eg. our own). It returns the cancel handlers of the messages that are not yet
+    /// acknowledged (with no specific order): the caller should keep them as long as it wants these messages
+    /// to be re-transmitted. It returns `None` if the acknowledgements cannot reach the threshold.
+    pub async fn wait_for_quorum<S>(
+        handlers: Vec<(S, CancelHandler)>,
+        mut stake: S,
+        threshold: S,
+    ) -> Option<Vec<CancelHandler>>
+    where
+        S: Copy + Ord + AddAssign + Unpin,
+    {
+        let mut wait_for_quorum: FuturesUnordered<_> = handlers
+            .into_iter()
+            .map(|(stake, handler)| WeightedHandler { stake, handler })
+            .collect();
+
+        while stake < threshold {
+            match wait_for_quorum.next().await {
+                Some((acked, Ok(_))) => stake += acked,
+                Some((_, Err(_))) => (),
+                None => return None,
+            }
+        }
+        Some(wait_for_quorum.into_iter().map(|x| x.handler).collect())
+    }
+
     /// Pick a few addresses at random (specified by `nodes`) and send the message only to them.
     /// It returns a vector of cancel handlers with no specific order.
     pub async fn lucky_broadcast(
         &mut self,
-        mut addresses: Vec<SocketAddr>,
+        mut addresses: Vec<Address>,
         data: Bytes,
         nodes: usize,
     ) -> Vec<CancelHandler> {
@@ -100,6 +227,21 @@ impl ReliableSender {
     }
 }
 
+/// A cancel handler along with the stake of the destination of its message.
+struct WeightedHandler<S> {
+    stake: S,
+    handler: CancelHandler,
+}
+
+impl<S: Copy + Unpin> Future for WeightedHandler<S> {
+    type Output = (S, Result<Bytes, RecvError>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let stake = self.stake;
+        Pin::new(&mut self.handler).poll(cx).map(|x| (stake, x))
+    }
+}
+
 /// Simple message used by `ReliableSender` to communicate with its connections.
 #[derive(Debug)]
 struct InnerMessage {
@@ -113,7 +255,15 @@ struct InnerMessage {
 /// A connection is responsible to reliably establish (and keep alive) a connection with a single peer.
 struct Connection {
     /// The destination address.
-    address: SocketAddr,
+    address: Address,
+    /// Tracks whether the peer persistently fails (if enabled).
+    circuit_breaker: Option<CircuitBreaker>,
+    /// The transport used to open the connection.
+    transport: SharedTransport,
+    /// The connection counters of the peer.
+    metrics: NetworkMetrics,
+    /// The size of the chunks in which to split messages (0 if chunking is disabled).
+    chunk_size: usize,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<InnerMessage>,
     /// The initial delay to wait before re-attempting a connection (in ms).
@@ -123,10 +273,21 @@ struct Connection {
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(
+        address: Address,
+        circuit_breaker: Option<CircuitBreaker>,
+        transport: SharedTransport,
+        metrics: NetworkMetrics,
+        chunk_size: usize,
+        receiver: Receiver<InnerMessage>,
+    ) {
         tokio::spawn(async move {
             Self {
                 address,
+                circuit_breaker,
+                transport,
+                metrics,
+                chunk_size,
                 receiver,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
@@ -141,9 +302,14 @@ impl Connection {
         let mut delay = self.retry_delay;
         let mut retry = 0;
         loop {
-            match TcpStream::connect(self.address).await {
+            self.metrics.record_attempt(&self.address);
+            match self.transport.connect(&self.address).await {
                 Ok(stream) => {
                     info!("Outgoing connection established with {}", self.address);
+                    self.metrics.record_connected(&self.address);
+                    if let Some(breaker) = &self.circuit_breaker {
+                        breaker.record_success(&self.address);
+                    }
 
                     // Reset the delay.
                     delay = self.retry_delay;
@@ -153,10 +319,25 @@ impl Connection {
                     // The following function only returns if there is an error.
                     let error = self.keep_alive(stream).await;
                     warn!("{}", error);
+                    self.record_failure();
                 }
                 Err(e) => {
-                    warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
-                    let timer = sleep(Duration::from_millis(delay));
+                    warn!(
+                        "{}",
+                        NetworkError::FailedToConnect(self.address.clone(), retry, e)
+                    );
+
+                    // If the peer failed too many times in a row, drop all messages (notifying the caller through
+                    // the cancel handlers) and only probe it again once the circuit half-opens.
+                    let open = self.record_failure();
+                    let timer = match &self.circuit_breaker {
+                        Some(breaker) if open => {
+                            warn!("{}", NetworkError::CircuitOpen(self.address.clone()));
+                            self.buffer.clear();
+                            sleep(breaker.reset_timeout())
+                        }
+                        _ => sleep(Duration::from_millis(delay)),
+                    };
                     tokio::pin!(timer);
 
                     'waiter: loop {
@@ -169,10 +350,13 @@ impl Connection {
                             },
 
                             // Drain the channel into the buffer to not saturate the channel and block the caller task.
-                            // The caller is responsible to cleanup the buffer through the cancel handlers.
+                            // The caller is responsible to cleanup the buffer through the cancel handlers. Messages
+                            // are dropped while the circuit is open.
                             Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
-                                self.buffer.retain(|(_, handler)| !handler.is_closed());
+                                if !open {
+                                    self.buffer.push_back((data, cancel_handler));
+                                    self.buffer.retain(|(_, handler)| !handler.is_closed());
+                                }
                             }
                         }
                     }
@@ -181,32 +365,84 @@ impl Connection {
         }
     }
 
+    /// Record a failure to reach the peer. Returns whether the circuit of the peer is now open.
+    fn record_failure(&self) -> bool {
+        self.metrics.record_failure(&self.address);
+        match &self.circuit_breaker {
+            Some(breaker) => {
+                breaker.record_failure(&self.address);
+                !breaker.is_available(&self.address)
+            }
+            None => false,
+        }
+    }
+
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    async fn keep_alive(&mut self, stream: BoxedStream) -> NetworkError {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
 
-        let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+        // This buffer keeps the messages that we started to send (chunk by chunk) but did not finish yet. Message ids
+        // (and thus chunks) are specific to the connection.
+        let mut streaming: VecDeque<(Chunks, oneshot::Sender<Bytes>)> = VecDeque::new();
+        let mut chunker = Chunker::new(self.chunk_size);
+
+        let (reader, writer) = split(stream);
+        let mut writer = FrameWriter::new(writer);
+        let mut reader = FramedRead::new(reader, LengthDelimitedCodec::new());
         let error = 'connection: loop {
-            // Try to send all messages of the buffer.
+            // Start sending all messages of the buffer.
             while let Some((data, handler)) = self.buffer.pop_front() {
-                // Skip messages that have been cancelled.
+                streaming.push_back((chunker.split(data), handler));
+            }
+
+            // Send one chunk of each message in turn, so that small messages are not stuck behind large ones.
+            while let Some((mut chunks, handler)) = streaming.pop_front() {
+                // Skip messages that have been cancelled (and tell the receiver to drop their first chunks).
                 if handler.is_closed() {
+                    if let Some(chunk) = chunks.abort() {
+                        if let Err(e) = writer
+                            .send_with_header(chunk.header(), &chunk.payload)
+                            .await
+                        {
+                            break 'connection NetworkError::FailedToSendMessage(
+                                self.address.clone(),
+                                e,
+                            );
+                        }
+                    }
                     continue;
                 }
 
-                // Try to send the message.
-                match writer.send(data.clone()).await {
-                    Ok(()) => {
-                        // The message has been sent, we remove it from the buffer and add it to
-                        // `pending_replies` while we wait for an ACK.
-                        pending_replies.push_back((data, handler));
-                    }
-                    Err(e) => {
+                // Try to send the next chunk of the message.
+                if let Some(chunk) = chunks.next_chunk() {
+                    if let Err(e) = writer
+                        .send_with_header(chunk.header(), &chunk.payload)
+                        .await
+                    {
                         // We failed to send the message, we put it back into the buffer.
-                        self.buffer.push_front((data, handler));
-                        break 'connection NetworkError::FailedToSendMessage(self.address, e);
+                        streaming.push_front((chunks, handler));
+                        break 'connection NetworkError::FailedToSendMessage(
+                            self.address.clone(),
+                            e,
+                        );
+                    }
+                }
+
+                if chunks.is_done() {
+                    // The message has been sent, we add it to `pending_replies` while we wait for an ACK.
+                    pending_replies.push_back((chunks.into_inner(), handler));
+                } else {
+                    streaming.push_back((chunks, handler));
+
+                    // Interleave the messages that arrived in the meantime.
+                    while let Ok(InnerMessage {
+                        data,
+                        cancel_handler,
+                    }) = self.receiver.try_recv()
+                    {
+                        streaming.push_back((chunker.split(data), cancel_handler));
                     }
                 }
             }
@@ -220,7 +456,7 @@ impl Connection {
                 response = reader.next() => {
                     let (data, handler) = match pending_replies.pop_front() {
                         Some(message) => message,
-                        None => break 'connection NetworkError::UnexpectedAck(self.address)
+                        None => break 'connection NetworkError::UnexpectedAck(self.address.clone())
                     };
                     match response {
                         Some(Ok(bytes)) => {
@@ -231,7 +467,7 @@ impl Connection {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
                             // Put the message back in the buffer, we will try to send it again.
                             pending_replies.push_front((data, handler));
-                            break 'connection NetworkError::FailedToReceiveAck(self.address);
+                            break 'connection NetworkError::FailedToReceiveAck(self.address.clone());
                         }
                     }
                 },
@@ -240,7 +476,13 @@ impl Connection {
 
         // If we reach this code, it means something went wrong. Put the messages for which we didn't receive an ACK
         // back into the sending buffer, we will try to send them again once we manage to establish a new connection.
+        // Messages partially sent are re-transmitted from the start.
+        while let Some((chunks, handler)) = streaming.pop_back() {
+            self.buffer.push_front((chunks.into_inner(), handler));
+        }
         while let Some(message) = pending_replies.pop_back() {
+            self.metrics
+                .record_retransmission(&self.address, message.0.len());
             self.buffer.push_front(message);
         }
         error
@@ -1,17 +1,21 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::chunk::Chunker;
 use crate::error::NetworkError;
+use crate::frame_writer::FrameWriter;
+use crate::metrics::NetworkMetrics;
+use crate::transport::{SharedTransport, TcpTransport};
 use bytes::Bytes;
-use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use log::{info, warn};
 use rand::prelude::SliceRandom as _;
 use rand::rngs::SmallRng;
 use rand::SeedableRng as _;
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::io::split;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
 
 #[cfg(test)]
 #[path = "tests/simple_sender_tests.rs"]
@@ -21,9 +25,15 @@ pub mod simple_sender_tests;
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 pub struct SimpleSender {
     /// A map holding the channels to our connections.
-    connections: HashMap<SocketAddr, Sender<Bytes>>,
+    connections: HashMap<Address, Sender<Bytes>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// The transport used to open our connections.
+    transport: SharedTransport,
+    /// The connection counters of each peer.
+    metrics: NetworkMetrics,
+    /// The size of the chunks in which to split messages (0 if chunking is disabled).
+    chunk_size: usize,
 }
 
 impl std::default::Default for SimpleSender {
@@ -37,19 +47,53 @@ impl SimpleSender {
         Self {
             connections: HashMap::new(),
             rng: SmallRng::from_entropy(),
+            transport: Arc::new(TcpTransport::default()),
+            metrics: NetworkMetrics::new(),
+            chunk_size: 0,
         }
     }
 
+    /// Set the transport used to open the connections (TCP by default).
+    pub fn with_transport(mut self, transport: SharedTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Record the connection counters of each peer into the specified metrics (eg. to share them with other
+    /// senders). By default, each sender keeps its own metrics.
+    pub fn with_metrics(mut self, metrics: NetworkMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Send messages in chunks of the specified size (0 disables chunking, the default). Chunks of different
+    /// messages are interleaved, so that small messages are not stuck behind large ones. The receivers must
+    /// expect chunks.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Returns the connection counters of each peer.
+    pub fn metrics(&self) -> &NetworkMetrics {
+        &self.metrics
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
+    fn spawn_connection(
+        address: Address,
+        transport: SharedTransport,
+        metrics: NetworkMetrics,
+        chunk_size: usize,
+    ) -> Sender<Bytes> {
         let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+        Connection::spawn(address, transport, metrics, chunk_size, rx);
         tx
     }
 
     /// Try (best-effort) to send a message to a specific address.
     /// This is useful to answer sync requests.
-    pub async fn send(&mut self, address: SocketAddr, data: Bytes) {
+    pub async fn send(&mut self, address: Address, data: Bytes) {
         // Try to re-use an existing connection if possible.
         if let Some(tx) = self.connections.get(&address) {
             if tx.send(data.clone()).await.is_ok() {
@@ -58,14 +102,20 @@ impl SimpleSender {
         }
 
         // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
+        let tx = Self::spawn_connection(
+            address.clone(),
+            self.transport.clone(),
+            self.metrics.clone(),
+            self.chunk_size,
+        );
         if tx.send(data).await.is_ok() {
             self.connections.insert(address, tx);
         }
     }
 
-    /// Try (best-effort) to broadcast the message to all specified addresses.
-    pub async fn broadcast(&mut self, addresses: Vec<SocketAddr>, data: Bytes) {
+    /// Try (best-effort) to broadcast the message to all specified addresses. All connections share
+    /// the same underlying buffer: the payload is never copied.
+    pub async fn broadcast(&mut self, addresses: Vec<Address>, data: Bytes) {
         for address in addresses {
             self.send(address, data.clone()).await;
         }
@@ -75,7 +125,7 @@ impl SimpleSender {
     /// message only to them. This is useful to pick nodes with whom to sync.
     pub async fn lucky_broadcast(
         &mut self,
-        mut addresses: Vec<SocketAddr>,
+        mut addresses: Vec<Address>,
         data: Bytes,
         nodes: usize,
     ) {
@@ -88,41 +138,86 @@ impl SimpleSender {
 /// A connection is responsible to establish and keep alive (if possible) a connection with a single peer.
 struct Connection {
     /// The destination address.
-    address: SocketAddr,
+    address: Address,
+    /// The transport used to open the connection.
+    transport: SharedTransport,
+    /// The connection counters of the peer.
+    metrics: NetworkMetrics,
+    /// The size of the chunks in which to split messages (0 if chunking is disabled).
+    chunk_size: usize,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<Bytes>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Bytes>) {
+    fn spawn(
+        address: Address,
+        transport: SharedTransport,
+        metrics: NetworkMetrics,
+        chunk_size: usize,
+        receiver: Receiver<Bytes>,
+    ) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self {
+                address,
+                transport,
+                metrics,
+                chunk_size,
+                receiver,
+            }
+            .run()
+            .await;
         });
     }
 
     /// Main loop trying to connect to the peer and transmit messages.
     async fn run(&mut self) {
         // Try to connect to the peer.
-        let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
-            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
+        self.metrics.record_attempt(&self.address);
+        let (mut writer, mut reader) = match self.transport.connect(&self.address).await {
+            Ok(stream) => {
+                let (reader, writer) = split(stream);
+                (
+                    FrameWriter::new(writer),
+                    FramedRead::new(reader, LengthDelimitedCodec::new()),
+                )
+            }
             Err(e) => {
                 warn!(
                     "{}",
-                    NetworkError::FailedToConnect(self.address, /* retry */ 0, e)
+                    NetworkError::FailedToConnect(self.address.clone(), /* retry */ 0, e)
                 );
+                self.metrics.record_failure(&self.address);
                 return;
             }
         };
         info!("Outgoing connection established with {}", self.address);
+        self.metrics.record_connected(&self.address);
 
         // Transmit messages once we have established a connection.
+        let mut chunker = Chunker::new(self.chunk_size);
         loop {
             // Check if there are any new messages to send or if we get an ACK for messages we already sent.
             tokio::select! {
                 Some(data) = self.receiver.recv() => {
-                    if let Err(e) = writer.send(data).await {
-                        warn!("{}", NetworkError::FailedToSendMessage(self.address, e));
-                        return;
+                    // Send one chunk of each message in turn, so that small messages are not stuck behind large ones.
+                    let mut streaming = VecDeque::from(vec![chunker.split(data)]);
+                    while let Some(mut chunks) = streaming.pop_front() {
+                        if let Some(chunk) = chunks.next_chunk() {
+                            if let Err(e) = writer.send_with_header(chunk.header(), &chunk.payload).await {
+                                warn!("{}", NetworkError::FailedToSendMessage(self.address.clone(), e));
+                                self.metrics.record_failure(&self.address);
+                                return;
+                            }
+                        }
+                        if !chunks.is_done() {
+                            streaming.push_back(chunks);
+
+                            // Interleave the messages that arrived in the meantime.
+                            while let Ok(data) = self.receiver.try_recv() {
+                                streaming.push_back(chunker.split(data));
+                            }
+                        }
                     }
                 },
                 response = reader.next() => {
@@ -132,7 +227,8 @@ impl Connection {
                         },
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
-                            warn!("{}", NetworkError::FailedToReceiveAck(self.address));
+                            warn!("{}", NetworkError::FailedToReceiveAck(self.address.clone()));
+                            self.metrics.record_failure(&self.address);
                             return;
                         }
                     }
@@ -0,0 +1,106 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::proxy::Proxy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream};
+
+#[cfg(test)]
+#[path = "tests/socket_config_tests.rs"]
+pub mod socket_config_tests;
+
+/// The options of the TCP sockets opened by the senders and the receivers. Options left unset keep the
+/// kernel defaults, which may be too small for high-bandwidth links. Outgoing connections may also be
+/// dialed through a proxy.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SocketConfig {
+    /// Whether to disable Nagle's algorithm (TCP_NODELAY).
+    pub nodelay: bool,
+    /// The size of the kernel send buffer (SO_SNDBUF). Denominated in bytes.
+    pub send_buffer_size: Option<u32>,
+    /// The size of the kernel receive buffer (SO_RCVBUF). Denominated in bytes.
+    pub recv_buffer_size: Option<u32>,
+    /// How long closing a socket waits for unsent data to be flushed (SO_LINGER). Note that a non-zero
+    /// linger blocks the thread dropping the socket, while a zero linger resets the connection on close
+    /// (skipping TIME_WAIT). Denominated in s.
+    pub linger: Option<u64>,
+    /// The proxy through which to dial all peers (if any).
+    pub proxy: Option<Proxy>,
+    /// The proxies through which to dial specific peers, overriding `proxy`.
+    pub peer_proxies: HashMap<Address, Proxy>,
+}
+
+impl SocketConfig {
+    /// Make a new socket of the family of the specified address, with the buffer sizes set. The buffer sizes
+    /// need to be set before connecting (or listening) to be taken into account by the TCP window scaling.
+    fn socket(&self, address: &SocketAddr) -> io::Result<TcpSocket> {
+        let socket = match address {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(socket)
+    }
+
+    /// Set the options that apply to established connections.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        if let Some(linger) = self.linger {
+            #[allow(deprecated)]
+            stream.set_linger(Some(Duration::from_secs(linger)))?;
+        }
+        Ok(())
+    }
+
+    /// Open a TCP connection to the specified address, through its proxy if it has one.
+    pub async fn connect(&self, address: &Address) -> io::Result<TcpStream> {
+        match self.peer_proxies.get(address).or(self.proxy.as_ref()) {
+            Some(proxy) => {
+                let stream = self.connect_direct(proxy.address()).await?;
+                proxy.handshake(stream, address).await
+            }
+            None => self.connect_direct(address).await,
+        }
+    }
+
+    /// Open a TCP connection to the specified address without proxy. DNS names are resolved anew on every
+    /// call, and all the resolved IPs are tried in turn.
+    async fn connect_direct(&self, address: &Address) -> io::Result<TcpStream> {
+        let resolved: Vec<_> = match address {
+            Address::Socket(address) => vec![*address],
+            Address::Dns(host, port) => lookup_host((host.as_str(), *port)).await?.collect(),
+        };
+
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "Failed to resolve host");
+        for address in resolved {
+            match self.socket(&address)?.connect(address).await {
+                Ok(stream) => {
+                    self.apply(&stream)?;
+                    return Ok(stream);
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Bind a TCP listener to the specified address. Accepted connections inherit the buffer sizes of the
+    /// listener, but the other options need to be applied to them (see `apply`).
+    pub fn listen(&self, address: &SocketAddr) -> io::Result<TcpListener> {
+        let socket = self.socket(address)?;
+        socket.set_reuseaddr(true)?;
+        socket.bind(*address)?;
+        socket.listen(1024)
+    }
+}
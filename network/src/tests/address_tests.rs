@@ -0,0 +1,41 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::listener;
+use crate::ReliableSender;
+use bytes::Bytes;
+
+#[test]
+fn parse_addresses() {
+    let address = "127.0.0.1:80".parse::<Address>().unwrap();
+    assert_eq!(address, Address::Socket("127.0.0.1:80".parse().unwrap()));
+    assert_eq!(address.listen_address(), "0.0.0.0:80".parse().unwrap());
+
+    let address = "[::1]:80".parse::<Address>().unwrap();
+    assert_eq!(address, Address::Socket("[::1]:80".parse().unwrap()));
+    assert_eq!(address.listen_address(), "[::]:80".parse().unwrap());
+    assert_eq!(address.to_string(), "[::1]:80");
+
+    let address = "localhost:80".parse::<Address>().unwrap();
+    assert_eq!(address, Address::Dns("localhost".to_string(), 80));
+    assert_eq!(address.to_string(), "localhost:80");
+
+    assert!("localhost".parse::<Address>().is_err());
+    assert!("localhost:port".parse::<Address>().is_err());
+    assert!("::1:80".parse::<Address>().is_err());
+}
+
+#[tokio::test]
+async fn send_to_hostname() {
+    // Run a TCP server.
+    let message = "Hello, world!";
+    let handle = listener("127.0.0.1:6600".parse().unwrap(), message.to_string());
+
+    // Send a message to the server using its DNS name.
+    let mut sender = ReliableSender::new();
+    let address = "localhost:6600".parse::<Address>().unwrap();
+    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+
+    // Ensure we get back an acknowledgement.
+    assert!(cancel_handler.await.is_ok());
+    assert!(handle.await.is_ok());
+}
@@ -0,0 +1,156 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::address::Address;
+use crate::receiver::{MessageHandler, Receiver, Writer};
+use crate::reliable_sender::ReliableSender;
+use crate::transport::MemoryTransport;
+use async_trait::async_trait;
+use futures::sink::SinkExt as _;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        // Reply with an ACK.
+        let _ = writer.send(Bytes::from("Ack")).await;
+
+        // Deliver the message to the application.
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+/// Turn a chunk into the frame seen by the receiver.
+fn frame(chunk: Chunk) -> Bytes {
+    let mut frame = BytesMut::from(chunk.header());
+    frame.put(chunk.payload);
+    frame.freeze()
+}
+
+#[test]
+fn split_and_reassemble() {
+    let peer = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let large = Bytes::from((0..100u8).collect::<Vec<_>>());
+    let small = Bytes::from("Hello");
+    let mut chunker = Chunker::new(/* chunk_size */ 30);
+    let mut large_chunks = chunker.split(large.clone());
+    let mut small_chunks = chunker.split(small.clone());
+    let mut reassembler = Reassembler::new(peer);
+
+    // Interleave the small message between the chunks of the large one.
+    let chunk = large_chunks.next_chunk().unwrap();
+    assert_eq!(reassembler.push(frame(chunk)).unwrap(), None);
+    let chunk = small_chunks.next_chunk().unwrap();
+    assert_eq!(reassembler.push(frame(chunk)).unwrap(), Some(small));
+    assert!(small_chunks.is_done());
+
+    // Receive the rest of the large message.
+    let mut received = None;
+    while let Some(chunk) = large_chunks.next_chunk() {
+        assert!(received.is_none());
+        received = reassembler.push(frame(chunk)).unwrap();
+    }
+    assert_eq!(received, Some(large));
+}
+
+#[test]
+fn empty_message() {
+    let peer = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let mut chunks = Chunker::new(/* chunk_size */ 30).split(Bytes::new());
+    let chunk = chunks.next_chunk().unwrap();
+    assert!(chunks.is_done());
+    let mut reassembler = Reassembler::new(peer);
+    assert_eq!(reassembler.push(frame(chunk)).unwrap(), Some(Bytes::new()));
+}
+
+#[test]
+fn chunking_disabled() {
+    let data = Bytes::from("Hello, world!");
+    let mut chunks = Chunker::new(/* chunk_size */ 0).split(data.clone());
+    let chunk = chunks.next_chunk().unwrap();
+    assert!(chunk.header().is_empty());
+    assert_eq!(chunk.payload, data);
+    assert!(chunks.next_chunk().is_none());
+}
+
+#[test]
+fn reject_out_of_order_chunk() {
+    let peer = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let mut chunks = Chunker::new(/* chunk_size */ 2).split(Bytes::from("Hello"));
+    let _first = chunks.next_chunk().unwrap();
+    let second = chunks.next_chunk().unwrap();
+    let mut reassembler = Reassembler::new(peer);
+    assert!(matches!(
+        reassembler.push(frame(second)),
+        Err(NetworkError::MalformedChunk(_))
+    ));
+    assert!(matches!(
+        reassembler.push(Bytes::from("Hi")),
+        Err(NetworkError::MalformedChunk(_))
+    ));
+}
+
+#[test]
+fn abort_partial_message() {
+    let peer = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let mut chunks = Chunker::new(/* chunk_size */ 2).split(Bytes::from("Hello"));
+    let mut reassembler = Reassembler::new(peer);
+
+    // Nothing to abort before the first chunk is sent.
+    assert!(chunks.abort().is_none());
+    let chunk = chunks.next_chunk().unwrap();
+    assert_eq!(reassembler.push(frame(chunk)).unwrap(), None);
+    assert_eq!(reassembler.size, 2);
+
+    // Abandon the message: the receiver drops its first chunk.
+    let abort = chunks.abort().unwrap();
+    assert_eq!(reassembler.push(frame(abort)).unwrap(), None);
+    assert!(reassembler.partial.is_empty());
+    assert_eq!(reassembler.size, 0);
+
+    // The rest of the message is rejected.
+    let chunk = chunks.next_chunk().unwrap();
+    assert!(matches!(
+        reassembler.push(frame(chunk)),
+        Err(NetworkError::MalformedChunk(_))
+    ));
+}
+
+#[tokio::test]
+async fn interleave_messages() {
+    // Run a receiver expecting chunks on an in-memory transport.
+    let transport = Arc::new(MemoryTransport::new());
+    let (tx, mut rx) = channel(2);
+    Receiver::spawn_with_options(
+        "127.0.0.1:1".parse::<SocketAddr>().unwrap(),
+        TestHandler { deliver: tx },
+        /* allowlist */ None,
+        transport.clone(),
+        /* chunked */ true,
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message larger than the maximum frame size followed by a small one.
+    let address = "127.0.0.1:1".parse::<Address>().unwrap();
+    let large = Bytes::from(vec![7u8; MAX_FRAME_LENGTH + 1]);
+    let small = Bytes::from("Hello, world!");
+    let mut sender = ReliableSender::new()
+        .with_transport(transport)
+        .with_chunk_size(64 * 1024);
+    let large_handler = sender.send(address.clone(), large.clone()).await;
+    let small_handler = sender.send(address, small.clone()).await;
+
+    // Ensure the small message is not stuck behind the large one, and that both get through.
+    assert_eq!(rx.recv().await.unwrap(), small);
+    assert_eq!(rx.recv().await.unwrap(), large);
+    assert!(small_handler.await.is_ok());
+    assert!(large_handler.await.is_ok());
+}
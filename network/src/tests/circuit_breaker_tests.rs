@@ -0,0 +1,33 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::thread::sleep;
+
+#[test]
+fn open_and_close() {
+    let address = "127.0.0.1:5500".parse::<Address>().unwrap();
+    let breaker = CircuitBreaker::new(
+        /* failure_threshold */ 2,
+        /* reset_timeout */ Duration::from_millis(50),
+    );
+    assert_eq!(breaker.state(&address), CircuitState::Closed);
+
+    // The circuit opens after 2 consecutive failures.
+    breaker.record_failure(&address);
+    assert_eq!(breaker.state(&address), CircuitState::Closed);
+    breaker.record_failure(&address);
+    assert_eq!(breaker.state(&address), CircuitState::Open);
+    assert!(!breaker.is_available(&address));
+
+    // It half-opens after the reset timeout.
+    sleep(Duration::from_millis(60));
+    assert_eq!(breaker.state(&address), CircuitState::HalfOpen);
+    assert!(breaker.is_available(&address));
+
+    // A failed probe re-opens it.
+    breaker.record_failure(&address);
+    assert_eq!(breaker.state(&address), CircuitState::Open);
+
+    // A successful probe closes it.
+    breaker.record_success(&address);
+    assert_eq!(breaker.state(&address), CircuitState::Closed);
+}
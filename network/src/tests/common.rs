@@ -1,15 +1,15 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::Address;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
-use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
-pub fn listener(address: SocketAddr, expected: String) -> JoinHandle<()> {
+pub fn listener(address: Address, expected: String) -> JoinHandle<()> {
     tokio::spawn(async move {
-        let listener = TcpListener::bind(&address).await.unwrap();
+        let listener = TcpListener::bind(address.to_string()).await.unwrap();
         let (socket, _) = listener.accept().await.unwrap();
         let transport = Framed::new(socket, LengthDelimitedCodec::new());
         let (mut writer, mut reader) = transport.split();
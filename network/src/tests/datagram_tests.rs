@@ -0,0 +1,69 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::listener;
+use crate::transport::MemoryTransport;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl DatagramHandler for TestHandler {
+    async fn dispatch(&self, message: Bytes) -> Result<(), Box<dyn Error>> {
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn send_datagram() {
+    // Make the datagram receiver.
+    let address = "127.0.0.1:6800".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    DatagramReceiver::spawn(address, TestHandler { deliver: tx });
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a small message.
+    let message = Bytes::from("Hello, world!");
+    let mut sender = DatagramSender::new(/* max_datagram_size */ 100);
+    sender.send(address.into(), message.clone()).await;
+
+    // Ensure the message was received as a datagram.
+    assert_eq!(rx.recv().await.unwrap(), message);
+}
+
+#[tokio::test]
+async fn fallback_to_tcp() {
+    // Run a TCP server.
+    let address = "127.0.0.1:6801".parse::<Address>().unwrap();
+    let message = "Hello, world!";
+    let handle = listener(address.clone(), message.to_string());
+
+    // Send a message that is too large to be sent as a datagram.
+    let mut sender = DatagramSender::new(/* max_datagram_size */ 5);
+    sender.send(address, Bytes::from(message)).await;
+
+    // Ensure the server received the message over TCP.
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn send_datagram_in_memory() {
+    // Make the datagram receiver over an in-memory network.
+    let transport: SharedTransport = Arc::new(MemoryTransport::new());
+    let address = "127.0.0.1:6802".parse::<SocketAddr>().unwrap();
+    let (tx, mut rx) = channel(1);
+    DatagramReceiver::spawn_with_transport(address, TestHandler { deliver: tx }, transport.clone());
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a small message.
+    let message = Bytes::from("Hello, world!");
+    let mut sender = DatagramSender::new(/* max_datagram_size */ 100).with_transport(transport);
+    sender.send(address.into(), message.clone()).await;
+
+    // Ensure the message was received as a datagram.
+    assert_eq!(rx.recv().await.unwrap(), message);
+}
@@ -0,0 +1,43 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use futures::stream::StreamExt as _;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[tokio::test]
+async fn send_frames() {
+    // Run a TCP server expecting two length-delimited frames.
+    let address = "127.0.0.1:6500".parse::<SocketAddr>().unwrap();
+    let small = Bytes::from("Hello, world!");
+    let large = Bytes::from(vec![7u8; 1_000_000]);
+    let (expected_small, expected_large) = (small.clone(), large.clone());
+    let listener = TcpListener::bind(&address).await.unwrap();
+    let handle = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        assert_eq!(transport.next().await.unwrap().unwrap(), expected_small);
+        assert_eq!(transport.next().await.unwrap().unwrap(), expected_large);
+    });
+
+    // Write the frames without going through the codec.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let (_reader, writer) = stream.into_split();
+    let mut writer = FrameWriter::new(writer);
+    writer.send(&small).await.unwrap();
+    writer.send(&large).await.unwrap();
+
+    // Ensure the server received both frames intact (ie. it did not panic).
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn reject_oversized_frame() {
+    let address = "127.0.0.1:6501".parse::<SocketAddr>().unwrap();
+    let _listener = TcpListener::bind(&address).await.unwrap();
+    let stream = TcpStream::connect(address).await.unwrap();
+    let (_reader, writer) = stream.into_split();
+    let mut writer = FrameWriter::new(writer);
+    let data = Bytes::from(vec![0u8; MAX_FRAME_LENGTH + 1]);
+    assert!(writer.send(&data).await.is_err());
+}
@@ -0,0 +1,42 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::thread::sleep;
+
+#[test]
+fn counters() {
+    let address = "127.0.0.1:5600".parse::<Address>().unwrap();
+    let metrics = NetworkMetrics::new();
+    assert_eq!(metrics.peer(&address), PeerMetrics::default());
+
+    // Fail to connect once, then connect.
+    metrics.record_attempt(&address);
+    metrics.record_failure(&address);
+    sleep(Duration::from_millis(20));
+    metrics.record_attempt(&address);
+    metrics.record_connected(&address);
+
+    // Lose the connection with a message in flight, then reconnect.
+    metrics.record_failure(&address);
+    metrics.record_retransmission(&address, 10);
+    metrics.record_attempt(&address);
+    metrics.record_connected(&address);
+
+    let peer = metrics.peer(&address);
+    assert_eq!(peer.connection_attempts, 3);
+    assert_eq!(peer.connection_failures, 2);
+    assert_eq!(peer.reconnects, 1);
+    assert_eq!(peer.retransmitted_bytes, 10);
+    assert!(peer.disconnected_time >= Duration::from_millis(20));
+}
+
+#[test]
+fn encode() {
+    let address = "127.0.0.1:5600".parse::<Address>().unwrap();
+    let metrics = NetworkMetrics::new();
+    metrics.clone().record_attempt(&address);
+
+    let encoded = metrics.encode();
+    assert!(encoded.contains("# TYPE network_connection_attempts_total counter\n"));
+    assert!(encoded.contains("network_connection_attempts_total{peer=\"127.0.0.1:5600\"} 1\n"));
+    assert!(encoded.contains("network_reconnects_total{peer=\"127.0.0.1:5600\"} 0\n"));
+}
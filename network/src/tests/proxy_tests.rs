@@ -0,0 +1,135 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::SocketConfig;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+/// Run a fake HTTP proxy expecting a single `CONNECT` request to `target` and replying with `status`. Once the
+/// tunnel is open, it returns the first bytes received through it.
+fn http_proxy(
+    address: SocketAddr,
+    target: &'static str,
+    status: &'static str,
+) -> JoinHandle<Vec<u8>> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(socket.read_u8().await.unwrap());
+        }
+        let expected = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target);
+        assert_eq!(request, expected.as_bytes());
+
+        let reply = format!("HTTP/1.1 {}\r\n\r\n", status);
+        socket.write_all(reply.as_bytes()).await.unwrap();
+        let mut tunneled = vec![0u8; 5];
+        let _ = socket.read_exact(&mut tunneled).await;
+        tunneled
+    })
+}
+
+/// Run a fake SOCKS5 proxy (without authentication) expecting a single request to connect to `target`. Once the
+/// tunnel is open, it returns the first bytes received through it.
+fn socks5_proxy(address: SocketAddr, target: (&'static str, u16)) -> JoinHandle<Vec<u8>> {
+    tokio::spawn(async move {
+        let listener = TcpListener::bind(&address).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        // Method selection: accept 'no authentication'.
+        let mut header = [0u8; 2];
+        socket.read_exact(&mut header).await.unwrap();
+        let mut methods = vec![0u8; header[1] as usize];
+        socket.read_exact(&mut methods).await.unwrap();
+        assert!(methods.contains(&0));
+        socket.write_all(&[5, 0]).await.unwrap();
+
+        // Connect request to a domain name.
+        let mut request = [0u8; 5];
+        socket.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request[..4], &[5, 1, 0, 3]);
+        let mut host = vec![0u8; request[4] as usize];
+        socket.read_exact(&mut host).await.unwrap();
+        let port = socket.read_u16().await.unwrap();
+        assert_eq!((host.as_slice(), port), (target.0.as_bytes(), target.1));
+        socket
+            .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+            .await
+            .unwrap();
+
+        let mut tunneled = vec![0u8; 5];
+        socket.read_exact(&mut tunneled).await.unwrap();
+        tunneled
+    })
+}
+
+#[tokio::test]
+async fn connect_through_http_proxy() {
+    let proxy = "127.0.0.1:6950".parse::<SocketAddr>().unwrap();
+    let handle = http_proxy(proxy, "example.com:80", "200 Connection established");
+    let config = SocketConfig {
+        proxy: Some(Proxy::Http(proxy.into())),
+        ..SocketConfig::default()
+    };
+    sleep(Duration::from_millis(50)).await;
+
+    // Dial the target through the proxy and send a few bytes through the tunnel.
+    let target = "example.com:80".parse::<Address>().unwrap();
+    let mut stream = config.connect(&target).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    assert_eq!(handle.await.unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn http_proxy_refusal() {
+    let proxy = "127.0.0.1:6951".parse::<SocketAddr>().unwrap();
+    let _handle = http_proxy(proxy, "127.0.0.1:80", "403 Forbidden");
+    let config = SocketConfig {
+        proxy: Some(Proxy::Http(proxy.into())),
+        ..SocketConfig::default()
+    };
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure we fail to dial the target.
+    let target = "127.0.0.1:80".parse::<Address>().unwrap();
+    assert!(config.connect(&target).await.is_err());
+}
+
+#[tokio::test]
+async fn connect_through_socks5_proxy() {
+    let proxy = "127.0.0.1:6952".parse::<SocketAddr>().unwrap();
+    let handle = socks5_proxy(proxy, ("example.com", 80));
+
+    // Only use the proxy for this specific peer.
+    let target = "example.com:80".parse::<Address>().unwrap();
+    let config = SocketConfig {
+        peer_proxies: vec![(target.clone(), Proxy::Socks5(proxy.into()))]
+            .into_iter()
+            .collect(),
+        ..SocketConfig::default()
+    };
+    sleep(Duration::from_millis(50)).await;
+
+    // Dial the target through the proxy and send a few bytes through the tunnel.
+    let mut stream = config.connect(&target).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    assert_eq!(handle.await.unwrap(), b"hello");
+}
+
+#[test]
+fn parse_proxies() {
+    let config: SocketConfig = serde_json::from_str(
+        r#"{
+            "proxy": { "socks5": "127.0.0.1:9050" },
+            "peer_proxies": { "10.0.0.1:80": { "http": "proxy.example.com:3128" } }
+        }"#,
+    )
+    .unwrap();
+    let socks5 = "127.0.0.1:9050".parse::<Address>().unwrap();
+    assert_eq!(config.proxy, Some(Proxy::Socks5(socks5)));
+    let peer = "10.0.0.1:80".parse::<Address>().unwrap();
+    let http = "proxy.example.com:3128".parse::<Address>().unwrap();
+    assert_eq!(config.peer_proxies.get(&peer), Some(&Proxy::Http(http)));
+}
@@ -1,6 +1,7 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use futures::sink::SinkExt as _;
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::Sender;
 use tokio::time::{sleep, Duration};
@@ -46,3 +47,92 @@ async fn receive() {
     let received = message.unwrap();
     assert_eq!(received, sent);
 }
+
+#[tokio::test]
+async fn accept_allowlisted_peer() {
+    // Make a network receiver only accepting connections from localhost.
+    let address = "127.0.0.1:4001".parse::<SocketAddr>().unwrap();
+    let allowlist = vec!["localhost:1234".parse::<Address>().unwrap()];
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_with_options(
+        address,
+        TestHandler { deliver: tx },
+        Some(allowlist),
+        Arc::new(TcpTransport::default()),
+        /* chunked */ false,
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(bytes.clone()).await.unwrap();
+
+    // Ensure the message gets passed to the channel.
+    assert_eq!(rx.recv().await.unwrap(), sent);
+}
+
+#[tokio::test]
+async fn reject_unknown_peer() {
+    // Make a network receiver only accepting connections from another host.
+    let address = "127.0.0.1:4002".parse::<SocketAddr>().unwrap();
+    let allowlist = vec!["10.0.0.1:1234".parse::<Address>().unwrap()];
+    let (tx, _rx) = channel(1);
+    Receiver::spawn_with_options(
+        address,
+        TestHandler { deliver: tx },
+        Some(allowlist),
+        Arc::new(TcpTransport::default()),
+        /* chunked */ false,
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message.
+    let bytes = Bytes::from(bincode::serialize("Hello, world!").unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let _ = transport.send(bytes).await;
+
+    // Ensure the receiver closed the connection without replying.
+    assert!(matches!(transport.next().await, None | Some(Err(_))));
+}
+
+#[tokio::test]
+async fn reject_unknown_peer_among_allowed() {
+    // Make a network receiver only accepting connections from 127.0.0.1.
+    let address = "127.0.0.1:4003".parse::<SocketAddr>().unwrap();
+    let allowlist = vec!["127.0.0.1:1234".parse::<Address>().unwrap()];
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_with_options(
+        address,
+        TestHandler { deliver: tx },
+        Some(allowlist),
+        Arc::new(TcpTransport::default()),
+        /* chunked */ false,
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Connect from another host (of the loopback network) and keep the connection open.
+    let socket = TcpSocket::new_v4().unwrap();
+    socket
+        .bind("127.0.0.2:0".parse::<SocketAddr>().unwrap())
+        .unwrap();
+    let stream = socket.connect(address).await.unwrap();
+    let mut unknown = Framed::new(stream, LengthDelimitedCodec::new());
+    let bytes = Bytes::from(bincode::serialize("Hello, world!").unwrap());
+    let _ = unknown.send(bytes).await;
+
+    // Ensure a peer of the allowlist still gets its message through.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(bytes).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), sent);
+
+    // Ensure the receiver closed the connection of the unknown host without delivering its message.
+    assert!(matches!(unknown.next().await, None | Some(Err(_))));
+    assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,43 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::listener;
+use futures::future::try_join_all;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn track_acks() {
+    // Run 2 TCP servers out of 3 destinations.
+    let message = "Hello, world!";
+    let addresses: Vec<_> = (0..3)
+        .map(|x| {
+            format!("127.0.0.1:{}", 5_700 + x)
+                .parse::<Address>()
+                .unwrap()
+        })
+        .collect();
+    let handles: Vec<_> = addresses
+        .iter()
+        .take(2)
+        .map(|address| listener(address.clone(), message.to_string()))
+        .collect();
+
+    // Broadcast the message and wait for 2 acknowledgements.
+    let mut network = ReliableSender::new();
+    let mut broadcast =
+        ReliableBroadcast::send(&mut network, addresses.clone(), Bytes::from(message)).await;
+    assert_eq!(broadcast.wait_for(2).await, 2);
+    assert!(try_join_all(handles).await.is_ok());
+
+    // Ensure only the missing server is reported as straggler.
+    let status = broadcast.status();
+    assert_eq!(status.acked, addresses[..2].to_vec());
+    assert_eq!(status.stragglers, vec![addresses[2].clone()]);
+    assert!(!status.is_complete());
+
+    // Re-trigger the broadcast once the straggler is up.
+    let handle = listener(addresses[2].clone(), message.to_string());
+    sleep(Duration::from_millis(50)).await;
+    assert_eq!(broadcast.retrigger(&mut network).await, 1);
+    assert!(broadcast.wait().await.is_complete());
+    assert!(handle.await.is_ok());
+}
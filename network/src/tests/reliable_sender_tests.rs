@@ -6,9 +6,9 @@ use futures::future::try_join_all;
 #[tokio::test]
 async fn send() {
     // Run a TCP server.
-    let address = "127.0.0.1:5000".parse::<SocketAddr>().unwrap();
+    let address = "127.0.0.1:5000".parse::<Address>().unwrap();
     let message = "Hello, world!";
-    let handle = listener(address, message.to_string());
+    let handle = listener(address.clone(), message.to_string());
 
     // Make the network sender and send the message.
     let mut sender = ReliableSender::new();
@@ -28,9 +28,9 @@ async fn broadcast() {
     let (handles, addresses): (Vec<_>, Vec<_>) = (0..3)
         .map(|x| {
             let address = format!("127.0.0.1:{}", 5_200 + x)
-                .parse::<SocketAddr>()
+                .parse::<Address>()
                 .unwrap();
-            (listener(address, message.to_string()), address)
+            (listener(address.clone(), message.to_string()), address)
         })
         .collect::<Vec<_>>()
         .into_iter()
@@ -47,13 +47,47 @@ async fn broadcast() {
     assert!(try_join_all(handles).await.is_ok());
 }
 
+#[tokio::test]
+async fn broadcast_and_wait_quorum() {
+    // Run 2 TCP servers out of 3 destinations, holding 3 of the 4 units of stake.
+    let message = "Hello, world!";
+    let destinations: Vec<_> = [1u32, 2, 1]
+        .iter()
+        .enumerate()
+        .map(|(x, stake)| {
+            let address = format!("127.0.0.1:{}", 5_400 + x)
+                .parse::<Address>()
+                .unwrap();
+            (address, *stake)
+        })
+        .collect();
+    let handles: Vec<_> = destinations
+        .iter()
+        .take(2)
+        .map(|(address, _)| listener(address.clone(), message.to_string()))
+        .collect();
+
+    // Make the network sender and wait for acknowledgements holding 3 units of stake.
+    let mut sender = ReliableSender::new();
+    let pending = sender
+        .broadcast_and_wait_quorum(destinations, Bytes::from(message), 3)
+        .await
+        .unwrap();
+
+    // Ensure only the message to the missing server is still pending.
+    assert_eq!(pending.len(), 1);
+
+    // Ensure both servers received the broadcast.
+    assert!(try_join_all(handles).await.is_ok());
+}
+
 #[tokio::test]
 async fn retry() {
     // Make the network sender and send the message  (no listeners are running).
-    let address = "127.0.0.1:5300".parse::<SocketAddr>().unwrap();
+    let address = "127.0.0.1:5300".parse::<Address>().unwrap();
     let message = "Hello, world!";
     let mut sender = ReliableSender::new();
-    let cancel_handler = sender.send(address, Bytes::from(message)).await;
+    let cancel_handler = sender.send(address.clone(), Bytes::from(message)).await;
 
     // Run a TCP server.
     sleep(Duration::from_millis(50)).await;
@@ -65,3 +99,42 @@ async fn retry() {
     // Ensure the server received the message (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn open_circuit() {
+    // Make a network sender that gives up on a peer after a single failure, and send
+    // a message to a peer that is down.
+    let address = "127.0.0.1:5501".parse::<Address>().unwrap();
+    let breaker = CircuitBreaker::new(
+        /* failure_threshold */ 1,
+        /* reset_timeout */ Duration::from_secs(60),
+    );
+    let mut sender = ReliableSender::new().with_circuit_breaker(breaker);
+    let cancel_handler = sender.send(address.clone(), Bytes::from("Hello")).await;
+
+    // Ensure the message is dropped and the peer reported as unavailable.
+    assert!(cancel_handler.await.is_err());
+    assert!(!sender.is_available(&address));
+}
+
+#[tokio::test]
+async fn metrics() {
+    // Make the network sender and send the message (no listeners are running).
+    let address = "127.0.0.1:5601".parse::<Address>().unwrap();
+    let message = "Hello, world!";
+    let mut sender = ReliableSender::new();
+    let cancel_handler = sender.send(address.clone(), Bytes::from(message)).await;
+
+    // Run a TCP server.
+    sleep(Duration::from_millis(50)).await;
+    let handle = listener(address.clone(), message.to_string());
+    assert!(cancel_handler.await.is_ok());
+    assert!(handle.await.is_ok());
+
+    // Ensure the failed attempt and the time spent disconnected are recorded.
+    let metrics = sender.metrics().peer(&address);
+    assert_eq!(metrics.connection_attempts, metrics.connection_failures + 1);
+    assert!(metrics.connection_failures >= 1);
+    assert_eq!(metrics.reconnects, 0);
+    assert!(metrics.disconnected_time >= Duration::from_millis(50));
+}
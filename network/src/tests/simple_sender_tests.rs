@@ -6,9 +6,9 @@ use futures::future::try_join_all;
 #[tokio::test]
 async fn simple_send() {
     // Run a TCP server.
-    let address = "127.0.0.1:6100".parse::<SocketAddr>().unwrap();
+    let address = "127.0.0.1:6100".parse::<Address>().unwrap();
     let message = "Hello, world!";
-    let handle = listener(address, message.to_string());
+    let handle = listener(address.clone(), message.to_string());
 
     // Make the network sender and send the message.
     let mut sender = SimpleSender::new();
@@ -25,9 +25,9 @@ async fn broadcast() {
     let (handles, addresses): (Vec<_>, Vec<_>) = (0..3)
         .map(|x| {
             let address = format!("127.0.0.1:{}", 6_200 + x)
-                .parse::<SocketAddr>()
+                .parse::<Address>()
                 .unwrap();
-            (listener(address, message.to_string()), address)
+            (listener(address.clone(), message.to_string()), address)
         })
         .collect::<Vec<_>>()
         .into_iter()
@@ -0,0 +1,33 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+#[tokio::test]
+async fn connect_with_options() {
+    let config = SocketConfig {
+        nodelay: true,
+        send_buffer_size: Some(1_000_000),
+        recv_buffer_size: Some(1_000_000),
+        linger: Some(0),
+        ..SocketConfig::default()
+    };
+
+    // Run a TCP server with the custom options.
+    let address = "127.0.0.1:6900".parse::<SocketAddr>().unwrap();
+    let listener = config.listen(&address).unwrap();
+    let handle = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+    // Connect to it with the same options.
+    let stream = config.connect(&Address::from(address)).await.unwrap();
+    assert!(stream.nodelay().unwrap());
+    assert_eq!(stream.linger().unwrap(), Some(Duration::from_secs(0)));
+
+    // Ensure the server accepted the connection.
+    assert!(handle.await.is_ok());
+}
+
+#[test]
+fn parse_partial_config() {
+    let config: SocketConfig = serde_json::from_str(r#"{ "nodelay": true }"#).unwrap();
+    assert!(config.nodelay);
+    assert_eq!(config.send_buffer_size, None);
+}
@@ -0,0 +1,76 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::transport::MemoryTransport;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+#[test]
+fn token_bucket() {
+    let limit = RateLimit {
+        rate: 1_000,
+        burst: 100,
+    };
+    let mut bucket = TokenBucket::new(limit);
+
+    // The burst can be sent right away.
+    assert_eq!(bucket.reserve(500), Ok(100));
+    bucket.consume(100);
+
+    // Then we need to wait for the tokens to accumulate again (never more than the burst).
+    match bucket.reserve(500) {
+        Err(wait) => assert!(wait <= Duration::from_millis(100)),
+        Ok(_) => panic!("Unexpected tokens"),
+    }
+}
+
+#[test]
+fn parse_egress_config() {
+    let json = r#"{
+        "rate_limit": { "rate": 1000000, "burst": 65536 },
+        "peer_rate_limits": { "127.0.0.1:3000": { "rate": 1000, "burst": 1000 } }
+    }"#;
+    let config: EgressConfig = serde_json::from_str(json).unwrap();
+    let peer = "127.0.0.1:3000".parse::<Address>().unwrap();
+    let other = "127.0.0.1:3001".parse::<Address>().unwrap();
+    assert_eq!(config.rate_limit(&peer).unwrap().rate, 1_000);
+    assert_eq!(config.rate_limit(&other).unwrap().rate, 1_000_000);
+    assert!(!EgressConfig::default().is_enabled());
+}
+
+#[test]
+fn reject_null_rate_limit() {
+    let json = r#"{ "rate_limit": { "rate": 1000, "burst": 0 } }"#;
+    assert!(serde_json::from_str::<EgressConfig>(json).is_err());
+    let json = r#"{ "rate": 0, "burst": 1000 }"#;
+    assert!(serde_json::from_str::<RateLimit>(json).is_err());
+
+    // Limits built in code are clamped instead.
+    let mut bucket = TokenBucket::new(RateLimit { rate: 1, burst: 0 });
+    assert_eq!(bucket.reserve(10), Ok(1));
+}
+
+#[tokio::test]
+async fn limit_rate() {
+    // Listen on an in-memory transport.
+    let memory = MemoryTransport::new();
+    let address = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let mut listener = memory.listen(&address).await.unwrap();
+
+    // Send 30 KB at 100 KB/s with a burst of 10 KB.
+    let config = EgressConfig {
+        rate_limit: Some(RateLimit {
+            rate: 100_000,
+            burst: 10_000,
+        }),
+        ..EgressConfig::default()
+    };
+    let transport = ShapedTransport::wrap(Arc::new(memory), &config);
+    let now = Instant::now();
+    let mut stream = transport.connect(&Address::Socket(address)).await.unwrap();
+    stream.write_all(&[0u8; 30_000]).await.unwrap();
+
+    // Ensure the data gets through, after at least 200 ms (the time to send what exceeds the burst).
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut received = vec![0u8; 30_000];
+    stream.read_exact(&mut received).await.unwrap();
+    assert!(now.elapsed() >= Duration::from_millis(200));
+}
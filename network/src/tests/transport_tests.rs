@@ -0,0 +1,106 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::receiver::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use crate::reliable_sender::ReliableSender;
+use bytes::Bytes;
+use std::error::Error;
+use tokio::time::{sleep, timeout};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<Bytes>,
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        // Reply with an ACK.
+        let _ = writer.send(Bytes::from("Ack")).await;
+
+        // Deliver the message to the application.
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+/// Spawn a receiver listening on the specified transport.
+async fn spawn_receiver(address: &str, transport: MemoryTransport) -> Receiver<Bytes> {
+    let (tx, rx) = channel(1);
+    NetworkReceiver::spawn_with_options(
+        address.parse::<SocketAddr>().unwrap(),
+        TestHandler { deliver: tx },
+        /* allowlist */ None,
+        Arc::new(transport),
+        /* chunked */ false,
+    );
+    sleep(Duration::from_millis(50)).await;
+    rx
+}
+
+#[tokio::test]
+async fn send() {
+    // Run a receiver on the in-memory transport (no socket is bound).
+    let transport = MemoryTransport::new();
+    let mut rx = spawn_receiver("127.0.0.1:1", transport.clone()).await;
+
+    // Send a message through the same transport.
+    let address = "127.0.0.1:1".parse::<Address>().unwrap();
+    let message = Bytes::from("Hello, world!");
+    let mut sender = ReliableSender::new().with_transport(Arc::new(transport));
+    let cancel_handler = sender.send(address, message.clone()).await;
+
+    // Ensure the message is delivered and acknowledged.
+    assert_eq!(rx.recv().await.unwrap(), message);
+    assert!(cancel_handler.await.is_ok());
+}
+
+#[tokio::test]
+async fn latency() {
+    // Run a receiver on an in-memory transport delaying every message.
+    let latency = Duration::from_millis(200);
+    let transport = MemoryTransport::new().with_latency(latency);
+    let mut rx = spawn_receiver("0.0.0.0:2", transport.clone()).await;
+
+    // Send a message to the receiver (listening on all interfaces).
+    let address = "127.0.0.1:2".parse::<Address>().unwrap();
+    let mut sender = ReliableSender::new().with_transport(Arc::new(transport));
+    let now = Instant::now();
+    let cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+
+    // Ensure the message and its acknowledgement are both delayed.
+    assert!(rx.recv().await.is_some());
+    assert!(now.elapsed() >= latency);
+    assert!(cancel_handler.await.is_ok());
+    assert!(now.elapsed() >= 2 * latency);
+}
+
+#[tokio::test]
+async fn loss() {
+    // Run a receiver on an in-memory transport losing every message.
+    let transport = MemoryTransport::new().with_loss(1.0, /* seed */ 0);
+    let mut rx = spawn_receiver("127.0.0.1:3", transport.clone()).await;
+
+    // Send a message to the receiver.
+    let address = "127.0.0.1:3".parse::<Address>().unwrap();
+    let mut sender = ReliableSender::new().with_transport(Arc::new(transport));
+    let _cancel_handler = sender.send(address, Bytes::from("Hello")).await;
+
+    // Ensure the message is never delivered.
+    assert!(timeout(Duration::from_millis(200), rx.recv())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn connection_refused() {
+    // Ensure we cannot connect to an address nobody listens to, nor listen twice to the same address.
+    let transport = MemoryTransport::new();
+    let address = "127.0.0.1:4".parse::<SocketAddr>().unwrap();
+    assert!(transport.connect(&Address::Socket(address)).await.is_err());
+    let listener = transport.listen(&address).await.unwrap();
+    assert!(transport.listen(&address).await.is_err());
+
+    // The address is free again once the listener is dropped.
+    drop(listener);
+    assert!(transport.listen(&address).await.is_ok());
+}
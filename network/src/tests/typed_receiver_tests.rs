@@ -0,0 +1,60 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::address::Address;
+use crate::receiver::Receiver;
+use crate::transport::{MemoryTransport, Transport as _};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[derive(Clone)]
+struct TestHandler {
+    deliver: Sender<String>,
+}
+
+#[async_trait]
+impl TypedMessageHandler<String> for TestHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: String) -> Result<(), Box<dyn Error>> {
+        // Reply with an ACK.
+        let _ = writer.send(Bytes::from("Ack")).await;
+
+        // Deliver the message to the application.
+        self.deliver.send(message).await.unwrap();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn receive() {
+    // Make the network receiver.
+    let address = "127.0.0.1:1".parse::<SocketAddr>().unwrap();
+    let transport = MemoryTransport::new();
+    let (tx, mut rx) = channel(1);
+    Receiver::spawn_with_options(
+        address,
+        TypedReceiver::new(TestHandler { deliver: tx }),
+        /* allowlist */ None,
+        Arc::new(transport.clone()),
+        /* chunked */ false,
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Send a message.
+    let sent = "Hello, world!";
+    let bytes = Bytes::from(bincode::serialize(sent).unwrap());
+    let stream = transport.connect(&Address::Socket(address)).await.unwrap();
+    let mut stream = Framed::new(stream, LengthDelimitedCodec::new());
+    stream.send(bytes).await.unwrap();
+
+    // Ensure the handler receives the deserialized message.
+    assert_eq!(rx.recv().await.unwrap(), sent);
+    assert_eq!(stream.next().await.unwrap().unwrap(), "Ack");
+
+    // Ensure a frame that fails to deserialize closes the connection.
+    stream.send(Bytes::from(vec![255u8; 4])).await.unwrap();
+    assert!(matches!(stream.next().await, None | Some(Err(_))));
+}
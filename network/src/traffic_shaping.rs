@@ -0,0 +1,256 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::transport::{BoxedStream, DatagramSocket, Listener, SharedTransport, Transport};
+use async_trait::async_trait;
+use futures::ready;
+use serde::Deserialize;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future as _;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Duration, Instant, Sleep};
+
+#[cfg(test)]
+#[path = "tests/traffic_shaping_tests.rs"]
+pub mod traffic_shaping_tests;
+
+/// The maximum throughput of a link.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "RateLimitFile")]
+pub struct RateLimit {
+    /// The sustained rate of the link. Denominated in bytes per second.
+    pub rate: u64,
+    /// The amount of data that can be sent at once after the link stayed idle. Denominated in bytes.
+    pub burst: u64,
+}
+
+#[derive(Deserialize)]
+struct RateLimitFile {
+    rate: u64,
+    burst: u64,
+}
+
+impl TryFrom<RateLimitFile> for RateLimit {
+    type Error = String;
+
+    /// A null rate or burst would never let any data through: reject them rather than stalling the link.
+    fn try_from(file: RateLimitFile) -> Result<Self, Self::Error> {
+        if file.rate == 0 || file.burst == 0 {
+            return Err(format!(
+                "Invalid rate limit (rate {}, burst {}): both must be positive",
+                file.rate, file.burst
+            ));
+        }
+        Ok(Self {
+            rate: file.rate,
+            burst: file.burst,
+        })
+    }
+}
+
+/// The egress rate limits of the node. Each destination gets its own token bucket, shared by all the
+/// connections to that destination.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct EgressConfig {
+    /// The rate limit applied to each destination (if any).
+    pub rate_limit: Option<RateLimit>,
+    /// The rate limits of specific destinations, overriding `rate_limit`.
+    pub peer_rate_limits: HashMap<Address, RateLimit>,
+}
+
+impl EgressConfig {
+    /// Returns whether any rate limit is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.rate_limit.is_some() || !self.peer_rate_limits.is_empty()
+    }
+
+    /// Returns the rate limit of the specified destination (if any).
+    pub fn rate_limit(&self, address: &Address) -> Option<RateLimit> {
+        self.peer_rate_limits
+            .get(address)
+            .copied()
+            .or(self.rate_limit)
+    }
+}
+
+/// A token bucket: tokens (bytes) accumulate at the rate of the limit, up to the burst size.
+pub struct TokenBucket {
+    /// The rate limit.
+    limit: RateLimit,
+    /// The bytes that can be sent right away.
+    tokens: f64,
+    /// The last time the bucket was refilled.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        // Limits built in code skip the checks of the config files: never let them stall the link.
+        let limit = RateLimit {
+            rate: limit.rate.max(1),
+            burst: limit.burst.max(1),
+        };
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Add the tokens accumulated since the last refill.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate as f64).min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Returns how many bytes (up to `wanted`) can be sent right away, or how long to wait before
+    /// being able to send them. We never wait for more than the burst size.
+    fn reserve(&mut self, wanted: usize) -> Result<usize, Duration> {
+        self.refill();
+        let wanted = min(wanted as u64, self.limit.burst) as f64;
+        if self.tokens >= wanted {
+            Ok(self.tokens as usize)
+        } else {
+            let rate = self.limit.rate as f64;
+            Err(Duration::from_secs_f64((wanted - self.tokens) / rate))
+        }
+    }
+
+    /// Returns whether any token is available right away. This lets callers that cannot know the size of
+    /// their data in advance go ahead and then `consume` it (possibly overdrawing the bucket).
+    pub fn has_tokens(&mut self) -> bool {
+        self.refill();
+        self.tokens > 0.0
+    }
+
+    /// Remove the tokens of the bytes sent.
+    pub fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
+
+/// A transport limiting the rate at which we send data to each destination. This emulates links of
+/// heterogeneous capacities without relying on the OS (eg. in single-machine benchmarks). Incoming
+/// traffic is not limited.
+pub struct ShapedTransport {
+    /// The transport actually carrying the data.
+    inner: SharedTransport,
+    /// The rate limits.
+    config: EgressConfig,
+    /// The token bucket of each destination.
+    buckets: Mutex<HashMap<Address, Arc<Mutex<TokenBucket>>>>,
+}
+
+impl ShapedTransport {
+    pub fn new(inner: SharedTransport, config: EgressConfig) -> Self {
+        Self {
+            inner,
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wrap the transport into a `ShapedTransport`, unless no rate limit is configured.
+    pub fn wrap(inner: SharedTransport, config: &EgressConfig) -> SharedTransport {
+        match config.is_enabled() {
+            true => Arc::new(Self::new(inner, config.clone())),
+            false => inner,
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for ShapedTransport {
+    async fn connect(&self, address: &Address) -> io::Result<BoxedStream> {
+        let stream = self.inner.connect(address).await?;
+        let limit = match self.config.rate_limit(address) {
+            Some(limit) => limit,
+            None => return Ok(stream),
+        };
+        let bucket = self
+            .buckets
+            .lock()
+            .unwrap()
+            .entry(address.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(TokenBucket::new(limit))))
+            .clone();
+        Ok(Box::new(ShapedStream {
+            inner: stream,
+            bucket,
+            delay: None,
+        }))
+    }
+
+    async fn listen(&self, address: &SocketAddr) -> io::Result<Box<dyn Listener>> {
+        self.inner.listen(address).await
+    }
+
+    /// Datagrams are not shaped (they only carry small, latency-critical messages).
+    async fn bind_datagram(&self, address: &SocketAddr) -> io::Result<Box<dyn DatagramSocket>> {
+        self.inner.bind_datagram(address).await
+    }
+}
+
+/// A stream whose writes are limited by a token bucket.
+struct ShapedStream {
+    /// The underlying stream.
+    inner: BoxedStream,
+    /// The token bucket of the destination.
+    bucket: Arc<Mutex<TokenBucket>>,
+    /// The timer to wait for before writing (if we ran out of tokens).
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncRead for ShapedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ShapedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+
+            let reservation = this.bucket.lock().unwrap().reserve(buf.len());
+            match reservation {
+                Ok(available) => {
+                    let size = min(buf.len(), available);
+                    let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..size]))?;
+                    this.bucket.lock().unwrap().consume(written);
+                    return Poll::Ready(Ok(written));
+                }
+                Err(wait) => this.delay = Some(Box::pin(sleep(wait))),
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
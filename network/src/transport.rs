@@ -0,0 +1,385 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::address::Address;
+use crate::datagram::MAX_DATAGRAM_SIZE;
+use crate::socket_config::SocketConfig;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{debug, warn};
+use rand::rngs::SmallRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{duplex, split, AsyncRead, AsyncWrite, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{sleep_until, Duration, Instant};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/transport_tests.rs"]
+pub mod transport_tests;
+
+/// A bidirectional byte stream between two peers.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncStream for T {}
+
+/// Convenient alias for the streams returned by transports.
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+/// Convenient alias for a transport shared by several senders and receivers.
+pub type SharedTransport = Arc<dyn Transport>;
+
+/// Accepts incoming streams.
+#[async_trait]
+pub trait Listener: Send {
+    /// Wait for the next incoming stream. It returns the stream and the address of the peer.
+    async fn accept(&mut self) -> io::Result<(BoxedStream, SocketAddr)>;
+}
+
+/// Sends and receives datagrams.
+#[async_trait]
+pub trait DatagramSocket: Send + Sync {
+    /// Send a datagram to the specified peer (best-effort).
+    async fn send_to(&self, data: &[u8], peer: &SocketAddr) -> io::Result<()>;
+
+    /// Wait for the next datagram. It returns the datagram and the address of the peer.
+    async fn recv_from(&mut self) -> io::Result<(Bytes, SocketAddr)>;
+}
+
+/// Opens the streams and datagram sockets used by the senders and the receivers. The default is
+/// `TcpTransport`, while `MemoryTransport` allows to run a whole committee in a single process.
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+    /// Open a stream to the specified address.
+    async fn connect(&self, address: &Address) -> io::Result<BoxedStream>;
+
+    /// Start accepting streams on the specified address.
+    async fn listen(&self, address: &SocketAddr) -> io::Result<Box<dyn Listener>>;
+
+    /// Bind a datagram socket to the specified address (port 0 picks any free port).
+    async fn bind_datagram(&self, address: &SocketAddr) -> io::Result<Box<dyn DatagramSocket>>;
+}
+
+/// The transport over real TCP sockets (and UDP sockets for datagrams).
+#[derive(Clone, Default)]
+pub struct TcpTransport {
+    /// The options of the sockets.
+    socket_config: SocketConfig,
+}
+
+impl TcpTransport {
+    pub fn new(socket_config: SocketConfig) -> Self {
+        Self { socket_config }
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn connect(&self, address: &Address) -> io::Result<BoxedStream> {
+        let stream = self.socket_config.connect(address).await?;
+        Ok(Box::new(stream))
+    }
+
+    async fn listen(&self, address: &SocketAddr) -> io::Result<Box<dyn Listener>> {
+        let listener = self.socket_config.listen(address)?;
+        Ok(Box::new(TcpStreamListener {
+            listener,
+            socket_config: self.socket_config.clone(),
+        }))
+    }
+
+    async fn bind_datagram(&self, address: &SocketAddr) -> io::Result<Box<dyn DatagramSocket>> {
+        let socket = UdpSocket::bind(address).await?;
+        Ok(Box::new(UdpDatagramSocket {
+            socket,
+            buffer: vec![0u8; MAX_DATAGRAM_SIZE],
+        }))
+    }
+}
+
+/// Accepts TCP connections.
+struct TcpStreamListener {
+    listener: TcpListener,
+    socket_config: SocketConfig,
+}
+
+#[async_trait]
+impl Listener for TcpStreamListener {
+    async fn accept(&mut self) -> io::Result<(BoxedStream, SocketAddr)> {
+        let (stream, peer) = self.listener.accept().await?;
+        if let Err(e) = self.socket_config.apply(&stream) {
+            warn!(
+                "Failed to set socket options of connection with {}: {}",
+                peer, e
+            );
+        }
+        Ok((Box::new(stream), peer))
+    }
+}
+
+/// A real UDP socket.
+struct UdpDatagramSocket {
+    socket: UdpSocket,
+    /// The buffer receiving the datagrams.
+    buffer: Vec<u8>,
+}
+
+#[async_trait]
+impl DatagramSocket for UdpDatagramSocket {
+    async fn send_to(&self, data: &[u8], peer: &SocketAddr) -> io::Result<()> {
+        self.socket.send_to(data, peer).await.map(|_| ())
+    }
+
+    async fn recv_from(&mut self) -> io::Result<(Bytes, SocketAddr)> {
+        let (length, peer) = self.socket.recv_from(&mut self.buffer).await?;
+        Ok((Bytes::copy_from_slice(&self.buffer[..length]), peer))
+    }
+}
+
+/// The size of the in-memory buffer of each stream of the `MemoryTransport`.
+const MEMORY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The state shared by all clones of a `MemoryTransport`.
+#[derive(Default)]
+struct MemoryNetwork {
+    /// The listeners indexed by the address they listen to.
+    listeners: HashMap<SocketAddr, Sender<(BoxedStream, SocketAddr)>>,
+    /// The datagram sockets indexed by the address they are bound to.
+    datagrams: HashMap<SocketAddr, Sender<(Bytes, SocketAddr)>>,
+    /// The next (fake) port to assign to the client end of a stream (or to a datagram socket bound to port 0).
+    next_port: u16,
+}
+
+/// An in-process transport: streams are in-memory pipes and no socket is ever bound. All clones of
+/// the transport share the same 'network', so handing a clone to each node of a committee lets them
+/// talk to each other. Messages may be delayed by a fixed latency, and lost with a given probability;
+/// as with TCP, losing a message breaks the connection it was sent on.
+#[derive(Clone)]
+pub struct MemoryTransport {
+    /// The listeners of the network.
+    network: Arc<Mutex<MemoryNetwork>>,
+    /// The one-way latency of each message.
+    latency: Duration,
+    /// The probability to lose each message (between 0 and 1).
+    loss: f64,
+    /// The RNG deciding which messages are lost (not crypto related).
+    rng: Arc<Mutex<SmallRng>>,
+}
+
+impl Default for MemoryTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self {
+            network: Arc::new(Mutex::new(MemoryNetwork::default())),
+            latency: Duration::from_millis(0),
+            loss: 0.0,
+            rng: Arc::new(Mutex::new(SmallRng::seed_from_u64(0))),
+        }
+    }
+
+    /// Delay every message by the specified latency.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Lose every message with the specified probability. Lost messages are picked by an RNG seeded
+    /// with `seed`.
+    pub fn with_loss(mut self, loss: f64, seed: u64) -> Self {
+        self.loss = loss;
+        self.rng = Arc::new(Mutex::new(SmallRng::seed_from_u64(seed)));
+        self
+    }
+
+    /// Forward the messages (length-delimited frames) read from `reader` to `writer`, applying the latency
+    /// and loss of the transport. It returns (closing both ends) as soon as a message is lost.
+    async fn relay(&self, reader: ReadHalf<DuplexStream>, writer: WriteHalf<DuplexStream>) {
+        let (tx, mut rx) = channel::<(Instant, bytes::Bytes)>(1_000);
+        let mut writer = FramedWrite::new(writer, LengthDelimitedCodec::new());
+        tokio::spawn(async move {
+            while let Some((deadline, frame)) = rx.recv().await {
+                sleep_until(deadline).await;
+                if writer.send(frame).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut reader = FramedRead::new(reader, LengthDelimitedCodec::new());
+        while let Some(Ok(frame)) = reader.next().await {
+            if self.loss > 0.0 && self.rng.lock().unwrap().gen_bool(self.loss) {
+                debug!("Simulated network dropped a message");
+                return;
+            }
+            let deadline = Instant::now() + self.latency;
+            if tx.send((deadline, frame.freeze())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn connect(&self, address: &Address) -> io::Result<BoxedStream> {
+        // Find the listener of the address, or the one listening to all interfaces on the same port.
+        let any = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), address.port());
+        let (listener, peer) = {
+            let mut network = self.network.lock().unwrap();
+            let listener = match address {
+                Address::Socket(address) => network.listeners.get(address),
+                Address::Dns(..) => None,
+            }
+            .or_else(|| network.listeners.get(&any))
+            .cloned()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+            network.next_port = network.next_port.wrapping_add(1);
+            let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), network.next_port);
+            (listener, peer)
+        };
+
+        let (client, server): (BoxedStream, BoxedStream) =
+            if self.latency.is_zero() && self.loss == 0.0 {
+                let (client, server) = duplex(MEMORY_BUFFER_SIZE);
+                (Box::new(client), Box::new(server))
+            } else {
+                let (client, client_relay) = duplex(MEMORY_BUFFER_SIZE);
+                let (server, server_relay) = duplex(MEMORY_BUFFER_SIZE);
+                let (client_reader, client_writer) = split(client_relay);
+                let (server_reader, server_writer) = split(server_relay);
+                let (upstream, downstream) = (self.clone(), self.clone());
+                tokio::spawn(async move { upstream.relay(client_reader, server_writer).await });
+                tokio::spawn(async move { downstream.relay(server_reader, client_writer).await });
+                (Box::new(client), Box::new(server))
+            };
+
+        listener
+            .send((server, peer))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(client)
+    }
+
+    async fn listen(&self, address: &SocketAddr) -> io::Result<Box<dyn Listener>> {
+        let (tx, rx) = channel(1_000);
+        let mut network = self.network.lock().unwrap();
+        if network
+            .listeners
+            .get(address)
+            .is_some_and(|x| !x.is_closed())
+        {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+        network.listeners.insert(*address, tx);
+        Ok(Box::new(MemoryListener { receiver: rx }))
+    }
+
+    async fn bind_datagram(&self, address: &SocketAddr) -> io::Result<Box<dyn DatagramSocket>> {
+        let (tx, rx) = channel(1_000);
+        let mut network = self.network.lock().unwrap();
+        let in_use = |network: &MemoryNetwork, address: &SocketAddr| {
+            network
+                .datagrams
+                .get(address)
+                .is_some_and(|x| !x.is_closed())
+        };
+        let address = match address.port() {
+            0 => loop {
+                network.next_port = network.next_port.wrapping_add(1);
+                let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), network.next_port);
+                if network.next_port != 0 && !in_use(&network, &address) {
+                    break address;
+                }
+            },
+            _ if in_use(&network, address) => {
+                return Err(io::Error::from(io::ErrorKind::AddrInUse))
+            }
+            _ => *address,
+        };
+        network.datagrams.insert(address, tx);
+        Ok(Box::new(MemoryDatagramSocket {
+            transport: self.clone(),
+            address,
+            receiver: rx,
+        }))
+    }
+}
+
+/// An in-memory datagram socket. As with UDP, datagrams sent to an address nobody is bound to (or to a socket
+/// whose buffer is full) are silently dropped.
+struct MemoryDatagramSocket {
+    /// The transport the socket belongs to.
+    transport: MemoryTransport,
+    /// The address the socket is bound to.
+    address: SocketAddr,
+    /// Receives the datagrams sent to the socket.
+    receiver: Receiver<(Bytes, SocketAddr)>,
+}
+
+#[async_trait]
+impl DatagramSocket for MemoryDatagramSocket {
+    async fn send_to(&self, data: &[u8], peer: &SocketAddr) -> io::Result<()> {
+        let transport = &self.transport;
+        if transport.loss > 0.0 && transport.rng.lock().unwrap().gen_bool(transport.loss) {
+            debug!("Simulated network dropped a datagram");
+            return Ok(());
+        }
+
+        // Find the socket bound to the address, or the one bound to all interfaces on the same port.
+        let any = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), peer.port());
+        let sender = {
+            let network = transport.network.lock().unwrap();
+            match network
+                .datagrams
+                .get(peer)
+                .or_else(|| network.datagrams.get(&any))
+            {
+                Some(sender) => sender.clone(),
+                None => return Ok(()),
+            }
+        };
+        let message = (Bytes::copy_from_slice(data), self.address);
+        if transport.latency.is_zero() {
+            let _ = sender.try_send(message);
+        } else {
+            let deadline = Instant::now() + transport.latency;
+            tokio::spawn(async move {
+                sleep_until(deadline).await;
+                let _ = sender.try_send(message);
+            });
+        }
+        Ok(())
+    }
+
+    async fn recv_from(&mut self) -> io::Result<(Bytes, SocketAddr)> {
+        self.receiver
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+/// Accepts in-memory streams.
+struct MemoryListener {
+    receiver: Receiver<(BoxedStream, SocketAddr)>,
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&mut self) -> io::Result<(BoxedStream, SocketAddr)> {
+        self.receiver
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
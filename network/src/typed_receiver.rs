@@ -0,0 +1,55 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::NetworkError;
+use crate::receiver::{MessageHandler, Writer};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::marker::PhantomData;
+
+#[cfg(test)]
+#[path = "tests/typed_receiver_tests.rs"]
+pub mod typed_receiver_tests;
+
+#[async_trait]
+pub trait TypedMessageHandler<M>: Clone + Send + Sync + 'static {
+    /// Defines how to handle an incoming (already deserialized) message. As with `MessageHandler`, `writer`
+    /// can be used to send back responses or acknowledgements to the sender machine.
+    async fn dispatch(&self, writer: &mut Writer, message: M) -> Result<(), Box<dyn Error>>;
+}
+
+/// A `MessageHandler` deserializing the incoming frames (with bincode) before handing them to a typed
+/// handler. Frames that fail to deserialize close the connection without reaching the handler.
+pub struct TypedReceiver<M, H> {
+    /// The handler of the deserialized messages.
+    handler: H,
+    /// The type of the messages (the receiver holds no message).
+    _message: PhantomData<fn() -> M>,
+}
+
+impl<M, H> TypedReceiver<M, H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<M, H: Clone> Clone for TypedReceiver<M, H> {
+    fn clone(&self) -> Self {
+        Self::new(self.handler.clone())
+    }
+}
+
+#[async_trait]
+impl<M, H> MessageHandler for TypedReceiver<M, H>
+where
+    M: DeserializeOwned + Send + 'static,
+    H: TypedMessageHandler<M>,
+{
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let message = bincode::deserialize(&message).map_err(NetworkError::InvalidMessage)?;
+        self.handler.dispatch(writer, message).await
+    }
+}
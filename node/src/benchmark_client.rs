@@ -6,12 +6,16 @@ use clap::{crate_name, crate_version, App, AppSettings};
 use env_logger::Env;
 use futures::future::join_all;
 use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
 use log::{info, warn};
 use rand::Rng;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::time::{interval, sleep, Duration, Instant};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use worker::{REJECTED, SLOW_DOWN};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -22,6 +26,7 @@ async fn main() -> Result<()> {
         .args_from_usage("--size=<INT> 'The size of each transaction in bytes'")
         .args_from_usage("--rate=<INT> 'The rate (txs/s) at which to send the transactions'")
         .args_from_usage("--nodes=[ADDR]... 'Network addresses that must be reachable before starting the benchmark.'")
+        .args_from_usage("--ack 'Skip a burst of transactions whenever the node asks to slow down'")
         .setting(AppSettings::ArgRequiredElseHelp)
         .get_matches();
 
@@ -51,6 +56,7 @@ async fn main() -> Result<()> {
         .map(|x| x.parse::<SocketAddr>())
         .collect::<Result<Vec<_>, _>>()
         .context("Invalid socket address format")?;
+    let ack = matches.is_present("ack");
 
     info!("Node address: {}", target);
 
@@ -65,6 +71,7 @@ async fn main() -> Result<()> {
         size,
         rate,
         nodes,
+        ack,
     };
 
     // Wait for all nodes to be online and synchronized.
@@ -79,6 +86,7 @@ struct Client {
     size: usize,
     rate: u64,
     nodes: Vec<SocketAddr>,
+    ack: bool,
 }
 
 impl Client {
@@ -103,15 +111,36 @@ impl Client {
         let mut tx = BytesMut::with_capacity(self.size);
         let mut counter = 0;
         let mut r = rand::thread_rng().gen();
-        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+        let (mut transport, mut replies) =
+            Framed::new(stream, LengthDelimitedCodec::new()).split();
         let interval = interval(Duration::from_millis(BURST_DURATION));
         tokio::pin!(interval);
 
+        // Always read the replies of the node (so that they do not pile up in its socket): count its requests
+        // to slow down (only honored in ACK mode) and report the transactions it rejects.
+        let slow_downs = Arc::new(AtomicU64::new(0));
+        let received_slow_downs = slow_downs.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(reply)) = replies.next().await {
+                if &reply[..] == SLOW_DOWN {
+                    received_slow_downs.fetch_add(1, Ordering::Relaxed);
+                } else if let Some(reason) = reply.strip_prefix(REJECTED) {
+                    warn!("Transaction rejected: {}", String::from_utf8_lossy(reason));
+                } else {
+                    warn!("Unexpected reply: {}", String::from_utf8_lossy(&reply));
+                }
+            }
+        });
+
         // NOTE: This log entry is used to compute performance.
         info!("Start sending transactions");
 
         'main: loop {
             interval.as_mut().tick().await;
+            if slow_downs.swap(0, Ordering::Relaxed) > 0 && self.ack {
+                warn!("Node saturated, skipping a burst of transactions");
+                continue;
+            }
             let now = Instant::now();
 
             for x in 0..burst {
@@ -1,18 +1,29 @@
 // Copyright(C) Facebook, Inc. and its affiliates. 
-use anyhow::{Context, Result};
-use clap::{crate_name, crate_version, App, AppSettings, ArgMatches, SubCommand};
+use anyhow::{ensure, Context, Result};
+use clap::{crate_name, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
 use config::Export as _;
 use config::Import as _;
-use config::{Committee, KeyPair, Parameters, WorkerId};
-use consensus::Consensus;
+use config::{Committee, Epoch, EpochCommittees, KeyPair, Parameters, WorkerId};
+use consensus::{Checkpoints, Consensus};
 use env_logger::Env;
-use primary::{Certificate, Primary};
+use log::{info, warn};
+use primary::{CommitEventBus, CommittedSubDag, DagSnapshot, Primary, Round, SnapshotFormat};
 use store::Store;
+use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration};
 use worker::Worker;
 
-/// The default channel capacity.
-pub const CHANNEL_CAPACITY: usize = 1_000;
+/// How often to check the committee file for the committee of the next epoch. Denominated in ms.
+const COMMITTEE_POLL_INTERVAL: u64 = 1_000;
+
+/// How long to wait for the tasks of the previous epoch to stop. Denominated in ms.
+const SHUTDOWN_TIMEOUT: u64 = 1_000;
+
+/// The key holding the epoch the node runs (to resume it after a restart).
+const CURRENT_EPOCH_KEY: &[u8] = b"current_epoch";
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -29,9 +40,18 @@ async fn main() -> Result<()> {
             SubCommand::with_name("run")
                 .about("Run a node")
                 .args_from_usage("--keys=<FILE> 'The file containing the node keys'")
-                .args_from_usage("--committee=<FILE> 'The file containing committee information'")
-                .args_from_usage("--parameters=[FILE] 'The file containing the node parameters'")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information (json, toml or yaml)'")
+                .args_from_usage("--parameters=[FILE] 'The file containing the node parameters (json, toml or yaml)'")
                 .args_from_usage("--store=<PATH> 'The path where to create the data store'")
+                .arg(
+                    Arg::with_name("param")
+                        .long("param")
+                        .value_name("KEY=VALUE")
+                        .help("Override a parameter, eg. batch_size=500000 (batch_size, max_header_delay, gc_depth or sync_retry_delay)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
                 .subcommand(SubCommand::with_name("primary").about("Run a single primary"))
                 .subcommand(
                     SubCommand::with_name("worker")
@@ -40,6 +60,25 @@ async fn main() -> Result<()> {
                 )
                 .setting(AppSettings::SubcommandRequiredElseHelp),
         )
+        .subcommand(
+            SubCommand::with_name("export_dag")
+                .about("Export the DAG stored by a primary to file")
+                .args_from_usage("--store=<PATH> 'The path of the data store of the primary'")
+                .args_from_usage("--filename=<FILE> 'The file where to export the DAG'")
+                .args_from_usage("--epoch=[INT] 'The epoch of the DAG (default: 0)'")
+                .args_from_usage("--from=[INT] 'The first round to export (default: 1)'")
+                .args_from_usage("--to=[INT] 'The last round to export (default: the highest stored round)'")
+                .args_from_usage("--format=[FORMAT] 'The file format, json or binary (default: json)'"),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Replay the DAG stored by a primary through consensus and check the commits it logged")
+                .args_from_usage("--store=<PATH> 'The path of the data store of the primary'")
+                .args_from_usage("--committee=<FILE> 'The file containing committee information (json, toml or yaml)'")
+                .args_from_usage("--parameters=[FILE] 'The file containing the node parameters (json, toml or yaml)'")
+                .args_from_usage("--log=<FILE> 'The log file of the primary'")
+                .args_from_usage("--epoch=[INT] 'The epoch of the DAG (default: the last epoch of the committee file)'"),
+        )
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .get_matches();
 
@@ -60,82 +99,424 @@ async fn main() -> Result<()> {
             .export(sub_matches.value_of("filename").unwrap())
             .context("Failed to generate key pair")?,
         ("run", Some(sub_matches)) => run(sub_matches).await?,
+        ("export_dag", Some(sub_matches)) => export_dag(sub_matches).await?,
+        ("replay", Some(sub_matches)) => replay(sub_matches).await?,
         _ => unreachable!(),
     }
     Ok(())
 }
 
-// Runs either a worker or a primary.
-async fn run(matches: &ArgMatches<'_>) -> Result<()> {
-    let key_file = matches.value_of("keys").unwrap();
-    let committee_file = matches.value_of("committee").unwrap();
-    let parameters_file = matches.value_of("parameters");
+// Exports the DAG stored by a primary (which must not be running).
+async fn export_dag(matches: &ArgMatches<'_>) -> Result<()> {
     let store_path = matches.value_of("store").unwrap();
+    let filename = matches.value_of("filename").unwrap();
+    let epoch = matches
+        .value_of("epoch")
+        .unwrap_or("0")
+        .parse::<Epoch>()
+        .context("The epoch must be a positive integer")?;
+    let from = matches
+        .value_of("from")
+        .unwrap_or("1")
+        .parse::<Round>()
+        .context("The first round must be a positive integer")?;
+    let to = matches
+        .value_of("to")
+        .map(|x| x.parse::<Round>())
+        .transpose()
+        .context("The last round must be a positive integer")?;
+    let format = matches
+        .value_of("format")
+        .unwrap_or("json")
+        .parse::<SnapshotFormat>()
+        .map_err(anyhow::Error::msg)?;
+
+    let store = Store::new(store_path).context("Failed to open the store")?;
+    let snapshot = DagSnapshot::load(store, epoch, from, to)
+        .await
+        .context("Failed to read the DAG from the store")?;
+    snapshot
+        .export(filename, format)
+        .context("Failed to export the DAG")?;
+    info!(
+        "Exported {} certificates of epoch {} to {}",
+        snapshot.vertices.len(),
+        epoch,
+        filename
+    );
+    Ok(())
+}
 
-    // Read the committee and node's keypair from file.
-    let keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
-    let committee =
-        Committee::import(committee_file).context("Failed to load the committee information")?;
+// Replays the DAG stored by a primary (which must not be running) through consensus, and checks that the
+// resulting commit sequence matches the one logged by the primary.
+async fn replay(matches: &ArgMatches<'_>) -> Result<()> {
+    let store_path = matches.value_of("store").unwrap();
+    let committee_file = matches.value_of("committee").unwrap();
+    let parameters_file = matches.value_of("parameters");
+    let log_file = matches.value_of("log").unwrap();
 
-    // Load default parameters if none are specified.
+    let committees = EpochCommittees::import(committee_file)
+        .context("Failed to load the committee information")?;
     let parameters = match parameters_file {
         Some(filename) => {
             Parameters::import(filename).context("Failed to load the node's parameters")?
         }
         None => Parameters::default(),
     };
+    let committee = match matches.value_of("epoch") {
+        Some(epoch) => {
+            let epoch = epoch
+                .parse::<Epoch>()
+                .context("The epoch must be a positive integer")?;
+            committees
+                .committee_for_epoch(epoch)
+                .with_context(|| format!("No committee for epoch {}", epoch))?
+        }
+        None => committees
+            .latest()
+            .context("The committee file holds no committee")?,
+    }
+    .clone();
+    let epoch = committee.epoch;
 
-    // Make the data store.
-    let store = Store::new(store_path).context("Failed to create a store")?;
+    // Feed the stored certificates (by round) through the commit rule.
+    let store = Store::new(store_path).context("Failed to open the store")?;
+    let snapshot = DagSnapshot::load(store, epoch, 1, None)
+        .await
+        .context("Failed to read the DAG from the store")?;
+    let replayed: Vec<_> = Consensus::replay(committee, &parameters, snapshot.certificates())
+        .into_iter()
+        .map(|(certificate, _)| certificate)
+        // Benchmark builds only log the certificates with a payload.
+        .filter(|certificate| {
+            !cfg!(feature = "benchmark") || !certificate.header.payload.is_empty()
+        })
+        .map(|certificate| certificate.header.to_string())
+        .collect();
 
-    // Channels the sequence of certificates.
-    let (tx_output, rx_output) = channel(CHANNEL_CAPACITY);
+    // Read the commit sequence logged by the primary.
+    let log = std::fs::read_to_string(log_file).context("Failed to read the log file")?;
+    let mut logged: Vec<String> = Vec::new();
+    for line in log.lines() {
+        let header = match line.split("Committed ").nth(1) {
+            Some(x) => x.split_whitespace().next().unwrap_or_default(),
+            None => continue,
+        };
+        if !header.starts_with('B') {
+            continue;
+        }
+        // Benchmark builds log a line per batch of each certificate.
+        if logged.last().map(String::as_str) != Some(header) {
+            logged.push(header.to_string());
+        }
+    }
 
-    // Check whether to run a primary, a worker, or an entire authority.
-    match matches.subcommand() {
-        // Spawn the primary and consensus core.
-        ("primary", _) => {
-            let (tx_new_certificates, rx_new_certificates) = channel(CHANNEL_CAPACITY);
-            let (tx_feedback, rx_feedback) = channel(CHANNEL_CAPACITY);
-            Primary::spawn(
-                keypair,
-                committee.clone(),
-                parameters.clone(),
-                store,
-                /* tx_consensus */ tx_new_certificates,
-                /* rx_consensus */ rx_feedback,
-            );
-            Consensus::spawn(
-                committee,
-                parameters.gc_depth,
-                /* rx_primary */ rx_new_certificates,
-                /* tx_primary */ tx_feedback,
-                tx_output,
-            );
+    // The sequences must match up to the shortest one (the store may hold certificates delivered after the
+    // last logged commit, and the log may end after the last flushed write to the store).
+    if let Some(index) = replayed.iter().zip(logged.iter()).position(|(x, y)| x != y) {
+        let window = |sequence: &[String]| {
+            sequence[index.saturating_sub(3)..sequence.len().min(index + 4)].join(", ")
+        };
+        anyhow::bail!(
+            "The commit sequences diverge at commit {}: replayed [{}] but logged [{}]",
+            index,
+            window(&replayed),
+            window(&logged)
+        );
+    }
+    info!(
+        "Replayed {} commits of epoch {} ({} logged): the commit sequences match",
+        replayed.len(),
+        epoch,
+        logged.len()
+    );
+    Ok(())
+}
+
+// Runs either a worker or a primary.
+async fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    let key_file = matches.value_of("keys").unwrap();
+    let committee_file = matches.value_of("committee").unwrap();
+    let parameters_file = matches.value_of("parameters");
+    let overrides: Vec<_> = matches
+        .values_of("param")
+        .map_or_else(Vec::new, |values| values.map(String::from).collect());
+    let store_path = matches.value_of("store").unwrap();
+
+    // Make the data store.
+    let mut store = Store::new(store_path).context("Failed to create a store")?;
+
+    // Read the node's keypair and the committee from file: that of the epoch we reached before a restart, or
+    // else of the first epoch listed in the file.
+    let mut keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
+    let committees = EpochCommittees::import(committee_file)
+        .context("Failed to load the committee information")?;
+    let current_epoch = store
+        .read(CURRENT_EPOCH_KEY.to_vec())
+        .await
+        .context("Failed to read the current epoch")?
+        .map(|x| bincode::deserialize::<Epoch>(&x).expect("Failed to deserialize the epoch"));
+    let mut committee = match current_epoch {
+        Some(epoch) => {
+            info!("Resuming epoch {}", epoch);
+            committees.committee_for_epoch(epoch).with_context(|| {
+                format!("The committee file holds no committee for epoch {}", epoch)
+            })?
         }
+        None => committees
+            .first()
+            .context("The committee file holds no committee")?,
+    }
+    .clone();
+
+    // Load default parameters if none are specified, and apply the overrides of the command line.
+    let mut parameters = load_parameters(parameters_file, &overrides)?;
 
-        // Spawn a single worker.
-        ("worker", Some(sub_matches)) => {
-            let id = sub_matches
+    // Parse the id of the worker (if we run one) before starting the runtime of the first epoch.
+    let worker_id = match matches.subcommand() {
+        ("worker", Some(sub_matches)) => Some(
+            sub_matches
                 .value_of("id")
                 .unwrap()
                 .parse::<WorkerId>()
-                .context("The worker id must be a positive integer")?;
-            Worker::spawn(keypair.name, id, committee, parameters, store);
+                .context("The worker id must be a positive integer")?,
+        ),
+        _ => None,
+    };
+
+    // Channels the sequence of certificates.
+    let (tx_output, rx_output) = channel(parameters.channel_capacity.max(1));
+
+    // Analyze the consensus' output.
+    tokio::spawn(analyze(store.clone(), rx_output));
+
+    // Each epoch runs in its own runtime: shutting it down stops all the tasks of the epoch (and frees their
+    // sockets) before we start over with the committee of the next epoch.
+    loop {
+        ensure!(
+            committee.authorities.contains_key(&keypair.name),
+            "Our public key is not in the committee of epoch {}",
+            committee.epoch
+        );
+        if let (Some(_), Some(share)) = (&committee.threshold_key, &keypair.threshold_share) {
+            ensure!(
+                committee.share_index(&keypair.name) == Some(share.index),
+                "Our share of the threshold key (index {}) is not ours in the committee of epoch {}",
+                share.index,
+                committee.epoch
+            );
+        }
+        let bytes = bincode::serialize(&committee.epoch).expect("Failed to serialize the epoch");
+        store.write(CURRENT_EPOCH_KEY.to_vec(), bytes).await;
+        let runtime = Runtime::new().context("Failed to create a runtime")?;
+
+        // Check whether to run a primary, a worker, or an entire authority. Nothing may return early until the
+        // runtime is shut down below (dropping it from an asynchronous context panics).
+        let next_committee = match (matches.subcommand_name(), worker_id) {
+            // Spawn the primary and consensus core.
+            (Some("primary"), _) => {
+                let channel_capacity = parameters.channel_capacity.max(1);
+                let (tx_new_certificates, rx_new_certificates) = channel(channel_capacity);
+                let (tx_feedback, rx_feedback) = channel(channel_capacity);
+                let (tx_next_committee, rx_next_committee) = channel(1);
+                let (tx_reconfigure, mut rx_reconfigure) = channel(1);
+                let commit_events = CommitEventBus::new();
+                let checkpoints = (parameters.consensus_checkpoint_interval > 0).then(|| {
+                    Checkpoints::new(
+                        store.clone(),
+                        committee.epoch,
+                        parameters.consensus_checkpoint_interval,
+                    )
+                });
+                {
+                    let _guard = runtime.enter();
+                    let tx_parameters = Primary::spawn(
+                        keypair,
+                        committee.clone(),
+                        parameters.clone(),
+                        store.clone(),
+                        /* tx_consensus */ tx_new_certificates,
+                        /* rx_consensus */ rx_feedback,
+                        commit_events.clone(),
+                        rx_next_committee,
+                        tx_reconfigure,
+                    );
+                    Consensus::spawn(
+                        committee.clone(),
+                        parameters.clone(),
+                        checkpoints,
+                        /* rx_primary */ rx_new_certificates,
+                        /* tx_primary */ tx_feedback,
+                        tx_output.clone(),
+                        commit_events,
+                    );
+                    if let Some(file) = parameters_file {
+                        tokio::spawn(reload_on_hangup(
+                            file.to_string(),
+                            overrides.clone(),
+                            parameters.clone(),
+                            tx_parameters,
+                        ));
+                    }
+
+                    // Hand over the committee of the next epoch as soon as the operator provides it.
+                    let file = committee_file.to_string();
+                    let epoch = committee.epoch + 1;
+                    tokio::spawn(async move {
+                        let next_committee = wait_for_committee(&file, epoch).await;
+                        let _ = tx_next_committee.send(next_committee).await;
+                    });
+                }
+                rx_reconfigure
+                    .recv()
+                    .await
+                    .context("The primary stopped unexpectedly")
+            }
+
+            // Spawn a single worker.
+            (Some("worker"), Some(id)) => {
+                let (tx_reconfigure, mut rx_reconfigure) = channel(1);
+                {
+                    let _guard = runtime.enter();
+                    let tx_parameters = match keypair.threshold_share.clone() {
+                        #[cfg(feature = "threshold-encryption")]
+                        Some(share) => Worker::spawn_with_decryption(
+                            keypair.name,
+                            id,
+                            committee.clone(),
+                            parameters.clone(),
+                            store.clone(),
+                            tx_reconfigure,
+                            share,
+                        ),
+                        _ => Worker::spawn(
+                            keypair.name,
+                            id,
+                            committee.clone(),
+                            parameters.clone(),
+                            store.clone(),
+                            tx_reconfigure,
+                        ),
+                    };
+                    if let Some(file) = parameters_file {
+                        tokio::spawn(reload_on_hangup(
+                            file.to_string(),
+                            overrides.clone(),
+                            parameters.clone(),
+                            tx_parameters,
+                        ));
+                    }
+                }
+                match rx_reconfigure.recv().await {
+                    Some(epoch) => Ok(wait_for_committee(committee_file, epoch).await),
+                    None => Err(anyhow::anyhow!("The worker stopped unexpectedly")),
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        // Stop all the tasks of the previous epoch (even if it failed) and reload our keys (and our parameters,
+        // which all apply from the new epoch).
+        let timeout = Duration::from_millis(SHUTDOWN_TIMEOUT);
+        tokio::task::spawn_blocking(move || runtime.shutdown_timeout(timeout)).await?;
+        committee = next_committee?;
+        info!("Moving to epoch {}", committee.epoch);
+        keypair = KeyPair::import(key_file).context("Failed to load the node's keypair")?;
+        match load_parameters(parameters_file, &overrides) {
+            Ok(x) => parameters = x,
+            Err(e) => warn!("Keeping the parameters of the previous epoch: {:#}", e),
         }
-        _ => unreachable!(),
     }
+}
 
-    // Analyze the consensus' output.
-    analyze(rx_output).await;
+/// Load the parameters file (or the default parameters) and apply the overrides of the command line.
+fn load_parameters(file: Option<&str>, overrides: &[String]) -> Result<Parameters> {
+    let mut parameters = match file {
+        Some(filename) => {
+            Parameters::import(filename).context("Failed to load the node's parameters")?
+        }
+        None => Parameters::default(),
+    };
+    for param in overrides {
+        let (key, value) = param
+            .split_once('=')
+            .with_context(|| format!("The parameter '{}' is not of the form key=value", param))?;
+        parameters.set(key, value)?;
+    }
+    Ok(parameters)
+}
 
-    // If this expression is reached, the program ends and all other tasks terminate.
-    unreachable!();
+/// Read the committee file until it holds the committee of the specified epoch.
+async fn wait_for_committee(file: &str, epoch: Epoch) -> Committee {
+    loop {
+        match EpochCommittees::import(file) {
+            Ok(committees) => {
+                if let Some(committee) = committees.committee_for_epoch(epoch) {
+                    return committee.clone();
+                }
+            }
+            Err(e) => warn!("{}", e),
+        }
+        sleep(Duration::from_millis(COMMITTEE_POLL_INTERVAL)).await;
+    }
 }
 
-/// Receives an ordered list of certificates and apply any application-specific logic.
-async fn analyze(mut rx_output: Receiver<Certificate>) {
-    while let Some(_certificate) = rx_output.recv().await {
+/// Reload the parameters file whenever we receive SIGHUP, and apply the parameters that are safe to change while
+/// the node runs (see `Parameters::reload`). The overrides of the command line still take precedence.
+async fn reload_on_hangup(
+    file: String,
+    overrides: Vec<String>,
+    mut parameters: Parameters,
+    tx_parameters: watch::Sender<Parameters>,
+) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Failed to listen to SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match load_parameters(Some(&file), &overrides) {
+            Ok(update) => {
+                info!("Reloading the parameters from {}", file);
+                parameters = parameters.reload(&update);
+                if tx_parameters.send(parameters.clone()).is_err() {
+                    break;
+                }
+            }
+            Err(e) => warn!("Failed to reload the parameters: {:#}", e),
+        }
+    }
+}
+
+/// The key holding the epoch and leader round of the last sub-dag handed to the application.
+const LAST_EXECUTED_KEY: &[u8] = b"last_executed";
+
+/// Receives the sub-dags committed by consensus (in commit order) and apply any application-specific logic.
+/// After a crash, consensus may output again the last sub-dags (it records them only once handed over): they
+/// are skipped by their epoch and leader round, persisted after each sub-dag.
+async fn analyze(mut store: Store, mut rx_output: Receiver<CommittedSubDag>) {
+    let mut last: Option<(Epoch, Round)> = match store.read(LAST_EXECUTED_KEY.to_vec()).await {
+        Ok(last) => last.map(|x| {
+            bincode::deserialize(&x).expect("Failed to deserialize the last executed sub-dag")
+        }),
+        Err(e) => {
+            warn!("Failed to read the last executed sub-dag: {}", e);
+            None
+        }
+    };
+    while let Some(sub_dag) = rx_output.recv().await {
+        let position = (sub_dag.leader.header.epoch, sub_dag.round());
+        if last.is_some_and(|x| x >= position) {
+            continue;
+        }
+
         // NOTE: Here goes the application logic.
+
+        last = Some(position);
+        let bytes =
+            bincode::serialize(&position).expect("Failed to serialize the last executed sub-dag");
+        store.write(LAST_EXECUTED_KEY.to_vec(), bytes).await;
     }
 }
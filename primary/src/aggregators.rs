@@ -1,16 +1,18 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
-use crate::messages::{Certificate, Header, Vote};
+use crate::messages::{AggregateVotes, Certificate, Header, Vote};
 use config::{Committee, Stake};
 use crypto::Hash as _;
-use crypto::{Digest, PublicKey, Signature};
+use crypto::{BlsSignature, Digest, PublicKey, Signature};
 use log::debug;
 use std::collections::HashSet;
 
-/// Aggregates votes for a particular header into a certificate.
+/// Aggregates votes for a particular header into a certificate. If all votes of the quorum carry a BLS
+/// signature, the certificate carries their aggregate rather than the individual signatures.
 pub struct VotesAggregator {
     weight: Stake,
     votes: Vec<(PublicKey, Signature)>,
+    bls_votes: Vec<(PublicKey, BlsSignature)>,
     used: HashSet<PublicKey>,
 }
 
@@ -19,6 +21,7 @@ impl VotesAggregator {
         Self {
             weight: 0,
             votes: Vec::new(),
+            bls_votes: Vec::new(),
             used: HashSet::new(),
         }
     }
@@ -35,23 +38,36 @@ impl VotesAggregator {
         ensure!(self.used.insert(author), DagError::AuthorityReuse(author));
 
         self.votes.push((author, vote.signature));
+        if let Some(bls_signature) = vote.bls_signature {
+            self.bls_votes.push((author, bls_signature));
+        }
         self.weight += committee.stake(&author);
         if self.weight >= committee.quorum_threshold() {
             self.weight = 0; // Ensures quorum is only reached once.
+            if self.bls_votes.len() == self.votes.len() {
+                return Ok(Some(Certificate {
+                    header: header.clone(),
+                    votes: Vec::new(),
+                    aggregate: Some(AggregateVotes::new(committee, &self.bls_votes)?),
+                }));
+            }
             return Ok(Some(Certificate {
                 header: header.clone(),
                 votes: self.votes.clone(),
+                aggregate: None,
             }));
         }
         Ok(None)
     }
 }
 
-/// Aggregate certificates and check if we reach a quorum.
+/// Aggregate certificates and check if we reach a quorum. The certificates received after the quorum
+/// are returned one by one (as late parents).
 pub struct CertificatesAggregator {
     weight: Stake,
     certificates: Vec<Digest>,
     used: HashSet<PublicKey>,
+    quorum: bool,
 }
 
 impl CertificatesAggregator {
@@ -60,6 +76,7 @@ impl CertificatesAggregator {
             weight: 0,
             certificates: Vec::new(),
             used: HashSet::new(),
+            quorum: false,
         }
     }
 
@@ -74,10 +91,13 @@ impl CertificatesAggregator {
         if !self.used.insert(origin) {
             return Ok(None);
         }
+        if self.quorum {
+            return Ok(Some(vec![certificate.digest()]));
+        }
         self.certificates.push(certificate.digest());
         self.weight += committee.stake(&origin);
         if self.weight >= committee.quorum_threshold() {
-            self.weight = 0; // Ensures quorum is only reached once.
+            self.quorum = true; // Ensures quorum is only reached once.
             return Ok(Some(self.certificates.drain(..).collect()));
         }
         Ok(None)
@@ -0,0 +1,102 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::{DagError, DagResult};
+use crate::messages::Certificate;
+use crate::primary::{PrimaryMessage, Round};
+use bytes::Bytes;
+use config::Committee;
+use crypto::PublicKey;
+use network::{Address, NetworkMetrics, ReliableSender, SharedTransport};
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Duration};
+
+#[cfg(test)]
+#[path = "tests/certificate_fetcher_tests.rs"]
+pub mod certificate_fetcher_tests;
+
+/// The maximum (serialized) size of the certificates carried by a single reply to a range request. It
+/// keeps replies well below the maximum frame length of the network.
+pub const MAX_RANGE_REPLY_SIZE: usize = 4 * 1024 * 1024;
+
+/// The reply to a range request: all the certificates of the rounds up to `end` (included), in round order.
+/// A reply may cover only the beginning of the requested range (if the peer does not have the other rounds
+/// yet, or if they do not fit in a single reply); the requestor then asks for the rest of the range.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CertificateRange {
+    /// The last round covered by the reply.
+    pub end: Round,
+    /// The certificates of the rounds covered by the reply.
+    pub certificates: Vec<Certificate>,
+}
+
+/// Fetches all the certificates of a range of rounds from a single peer, in a single request-reply exchange
+/// (instead of one sync request per missing certificate).
+pub struct CertificateFetcher {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// How long to wait for the reply of the peer.
+    timeout: Duration,
+    /// A network sender to send the requests and receive the replies.
+    network: ReliableSender,
+}
+
+impl CertificateFetcher {
+    pub fn new(
+        name: PublicKey,
+        committee: Committee,
+        timeout: u64,
+        transport: SharedTransport,
+        network_metrics: NetworkMetrics,
+    ) -> Self {
+        Self {
+            name,
+            committee,
+            timeout: Duration::from_millis(timeout),
+            network: ReliableSender::new()
+                .with_transport(transport)
+                .with_metrics(network_metrics),
+        }
+    }
+
+    /// Request the certificates of the rounds `start` to `end` (inclusive) from a peer. All the certificates
+    /// of the reply are verified before any of them is returned.
+    pub async fn fetch(
+        &mut self,
+        address: Address,
+        start: Round,
+        end: Round,
+    ) -> DagResult<CertificateRange> {
+        let message = PrimaryMessage::CertificatesRangeRequest(start, end, self.name);
+        let bytes = bincode::serialize(&message).expect("Failed to serialize range request");
+        let handler = self.network.send(address, Bytes::from(bytes)).await;
+
+        // Dropping the handler (on timeout) cancels the request.
+        let reply = match timeout(self.timeout, handler).await {
+            Ok(Ok(reply)) => reply,
+            _ => return Err(DagError::RangeFetchFailed(start, end)),
+        };
+        let range: CertificateRange = bincode::deserialize(&reply)?;
+        self.verify(&range, start, end)?;
+        Ok(range)
+    }
+
+    /// Ensure the reply covers (part of) the requested range, lists the certificates in round order, and
+    /// only holds valid certificates.
+    fn verify(&self, range: &CertificateRange, start: Round, end: Round) -> DagResult<()> {
+        ensure!(
+            range.end <= end && range.end.saturating_add(1) >= start,
+            DagError::InvalidRangeReply(start, end)
+        );
+        let mut round = start;
+        for certificate in &range.certificates {
+            ensure!(
+                certificate.round() >= round && certificate.round() <= range.end,
+                DagError::InvalidRangeReply(start, end)
+            );
+            round = certificate.round();
+            certificate.verify(&self.committee)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::commit_proof::CommitProof;
+use crate::messages::CommittedSubDag;
+use crate::primary::Round;
+use crypto::{Digest, Hash as _, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+
+#[cfg(test)]
+#[path = "tests/commit_events_tests.rs"]
+pub mod commit_events_tests;
+
+/// The number of events a subscriber may lag behind before missing some.
+const BUS_CAPACITY: usize = 10_000;
+
+/// A certificate committed by consensus, along with the leader whose sub-dag it belongs to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommitEvent {
+    /// The round of the certificate.
+    pub round: Round,
+    /// The wave of the leader (each wave spans `wave_length` rounds, two by default).
+    pub wave: Round,
+    /// The author of the committed leader.
+    pub leader: PublicKey,
+    /// The round of the committed leader.
+    pub leader_round: Round,
+    /// The author of the certificate.
+    pub author: PublicKey,
+    /// The digest of the certificate.
+    pub certificate: Digest,
+    /// The time between the delivery of the certificate to consensus and its commit. Denominated in ms.
+    pub latency: f64,
+}
+
+/// A wave committed by consensus, along with the leader that drove it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommittedWave {
+    /// The wave of the leader.
+    pub wave: Round,
+    /// The author of the committed leader.
+    pub leader: PublicKey,
+    /// The round of the committed leader.
+    pub round: Round,
+    /// The number of certificates committed by the leader.
+    pub certificates: usize,
+}
+
+/// A leader whose certificate consensus stopped waiting for (see `leader_timeout`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LeaderSkip {
+    /// The round of the leader.
+    pub round: Round,
+    /// The elected leader.
+    pub leader: PublicKey,
+    /// How long consensus waited for the certificate of the leader. Denominated in ms.
+    pub waited: f64,
+}
+
+/// A leader slot resolved by consensus: either its leader was committed, or a later leader was committed
+/// without it (because its certificate was missing, or not linked to the later leader).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LeaderSlot {
+    /// The round of the slot.
+    pub round: Round,
+    /// The leader elected for the slot.
+    pub leader: PublicKey,
+    /// Whether the leader was committed.
+    pub committed: bool,
+}
+
+/// A certificate consensus dropped from its dag without committing it: it can no longer be committed (no later
+/// leader links to it in time).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrphanedCertificate {
+    /// The round of the certificate.
+    pub round: Round,
+    /// The author of the certificate.
+    pub author: PublicKey,
+    /// The digest of the certificate.
+    pub certificate: Digest,
+}
+
+/// A committed certificate, along with the links to its parents.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommittedVertex {
+    /// The round of the certificate.
+    pub round: Round,
+    /// The author of the certificate.
+    pub author: PublicKey,
+    /// The digest of the certificate.
+    pub certificate: Digest,
+    /// The digests of the parents of the certificate.
+    pub parents: Vec<Digest>,
+}
+
+/// The structure of the sub-dag committed by a leader: its certificates (in commit order) along with their parent
+/// links, so that analysis tools can reconstruct the causality of the commits. The parents outside of the sub-dag
+/// were committed by earlier leaders (or garbage collected before being committed).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SubDagStructure {
+    /// The round of the leader.
+    pub leader_round: Round,
+    /// The author of the leader.
+    pub leader: PublicKey,
+    /// The committed certificates.
+    pub vertices: Vec<CommittedVertex>,
+}
+
+impl SubDagStructure {
+    pub fn new(sub_dag: &CommittedSubDag) -> Self {
+        let vertices = sub_dag
+            .certificates
+            .iter()
+            .map(|x| CommittedVertex {
+                round: x.round(),
+                author: x.origin(),
+                certificate: x.digest(),
+                parents: x.header.parents.iter().cloned().collect(),
+            })
+            .collect();
+        Self {
+            leader_round: sub_dag.round(),
+            leader: sub_dag.leader.origin(),
+            vertices,
+        }
+    }
+
+    /// Returns the number of rounds spanned by the sub-dag (one for a sub-dag holding only its leader).
+    pub fn depth(&self) -> Round {
+        match self.vertices.iter().map(|x| x.round).min() {
+            Some(lowest) => self.leader_round.saturating_sub(lowest) + 1,
+            None => 0,
+        }
+    }
+}
+
+/// The memory held by the state of consensus (its dag), as published after each certificate it processes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConsensusMemory {
+    /// The number of rounds of the dag.
+    pub rounds: usize,
+    /// The number of certificates of the dag.
+    pub certificates: usize,
+    /// The (approximate) size of the certificates of the dag. Denominated in bytes.
+    pub bytes: usize,
+    /// The lowest round of the dag.
+    pub lowest_round: Round,
+}
+
+/// Publishes the commits of consensus (in order) to any number of consumers. Subscribers get every event
+/// (unless they lag more than `BUS_CAPACITY` events behind), while watchers only observe the latest one (or the
+/// latest committed wave).
+/// The skipped leaders, the resolved leader slots, the commit proofs, the orphaned certificates, the structure
+/// of the committed sub-dags and the finalized sub-dags are published separately, and the memory held by
+/// consensus can be watched. The bus is cheap to clone and all clones share the same subscribers.
+#[derive(Clone)]
+pub struct CommitEventBus {
+    events: broadcast::Sender<CommitEvent>,
+    latest: Arc<watch::Sender<Option<CommitEvent>>>,
+    wave: Arc<watch::Sender<Option<CommittedWave>>>,
+    skips: broadcast::Sender<LeaderSkip>,
+    slots: broadcast::Sender<LeaderSlot>,
+    proofs: broadcast::Sender<CommitProof>,
+    orphans: broadcast::Sender<OrphanedCertificate>,
+    sub_dags: broadcast::Sender<SubDagStructure>,
+    finalized: broadcast::Sender<CommittedSubDag>,
+    memory: Arc<watch::Sender<ConsensusMemory>>,
+}
+
+impl Default for CommitEventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommitEventBus {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(BUS_CAPACITY);
+        let (latest, _) = watch::channel(None);
+        let (wave, _) = watch::channel(None);
+        let (skips, _) = broadcast::channel(BUS_CAPACITY);
+        let (slots, _) = broadcast::channel(BUS_CAPACITY);
+        let (proofs, _) = broadcast::channel(BUS_CAPACITY);
+        let (orphans, _) = broadcast::channel(BUS_CAPACITY);
+        let (sub_dags, _) = broadcast::channel(BUS_CAPACITY);
+        let (finalized, _) = broadcast::channel(BUS_CAPACITY);
+        let (memory, _) = watch::channel(ConsensusMemory::default());
+        Self {
+            events,
+            latest: Arc::new(latest),
+            wave: Arc::new(wave),
+            skips,
+            slots,
+            proofs,
+            orphans,
+            sub_dags,
+            finalized,
+            memory: Arc::new(memory),
+        }
+    }
+
+    /// Publish a commit (it is fine to have no subscribers).
+    pub fn publish(&self, event: CommitEvent) {
+        let _ = self.events.send(event.clone());
+        self.latest.send_replace(Some(event));
+    }
+
+    /// Returns a receiver of all the events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<CommitEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns a receiver of the latest event.
+    pub fn watch(&self) -> watch::Receiver<Option<CommitEvent>> {
+        self.latest.subscribe()
+    }
+
+    /// Publish a committed wave, once all its commits are published.
+    pub fn publish_wave(&self, wave: CommittedWave) {
+        self.wave.send_replace(Some(wave));
+    }
+
+    /// Returns a receiver of the latest committed wave.
+    pub fn watch_wave(&self) -> watch::Receiver<Option<CommittedWave>> {
+        self.wave.subscribe()
+    }
+
+    /// Publish a skipped leader (it is fine to have no subscribers).
+    pub fn publish_skip(&self, skip: LeaderSkip) {
+        let _ = self.skips.send(skip);
+    }
+
+    /// Returns a receiver of all the skipped leaders published from now on.
+    pub fn subscribe_skips(&self) -> broadcast::Receiver<LeaderSkip> {
+        self.skips.subscribe()
+    }
+
+    /// Publish a resolved leader slot (it is fine to have no subscribers).
+    pub fn publish_slot(&self, slot: LeaderSlot) {
+        let _ = self.slots.send(slot);
+    }
+
+    /// Returns a receiver of all the leader slots resolved from now on.
+    pub fn subscribe_slots(&self) -> broadcast::Receiver<LeaderSlot> {
+        self.slots.subscribe()
+    }
+
+    /// Publish the proof of a committed leader (it is fine to have no subscribers).
+    pub fn publish_proof(&self, proof: CommitProof) {
+        let _ = self.proofs.send(proof);
+    }
+
+    /// Returns a receiver of all the commit proofs published from now on.
+    pub fn subscribe_proofs(&self) -> broadcast::Receiver<CommitProof> {
+        self.proofs.subscribe()
+    }
+
+    /// Publish an orphaned certificate (it is fine to have no subscribers).
+    pub fn publish_orphan(&self, orphan: OrphanedCertificate) {
+        let _ = self.orphans.send(orphan);
+    }
+
+    /// Returns a receiver of all the orphaned certificates published from now on.
+    pub fn subscribe_orphans(&self) -> broadcast::Receiver<OrphanedCertificate> {
+        self.orphans.subscribe()
+    }
+
+    /// Publish the structure of a committed sub-dag (it is fine to have no subscribers).
+    pub fn publish_sub_dag(&self, sub_dag: SubDagStructure) {
+        let _ = self.sub_dags.send(sub_dag);
+    }
+
+    /// Returns a receiver of the structure of all the sub-dags committed from now on.
+    pub fn subscribe_sub_dags(&self) -> broadcast::Receiver<SubDagStructure> {
+        self.sub_dags.subscribe()
+    }
+
+    /// Publish a finalized sub-dag (it is fine to have no subscribers).
+    pub fn publish_finalized(&self, sub_dag: CommittedSubDag) {
+        let _ = self.finalized.send(sub_dag);
+    }
+
+    /// Returns a receiver of all the sub-dags finalized from now on (see `finality_depth`), in commit order.
+    pub fn subscribe_finalized(&self) -> broadcast::Receiver<CommittedSubDag> {
+        self.finalized.subscribe()
+    }
+
+    /// Publish the memory held by consensus.
+    pub fn publish_memory(&self, memory: ConsensusMemory) {
+        self.memory.send_replace(memory);
+    }
+
+    /// Returns a receiver of the memory held by consensus.
+    pub fn watch_memory(&self) -> watch::Receiver<ConsensusMemory> {
+        self.memory.subscribe()
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::commit_events::CommitEventBus;
+use crate::error::{DagError, DagResult};
+use crate::messages::Certificate;
+use crate::primary::Round;
+use config::{Committee, Epoch};
+use crypto::Hash as _;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use store::Store;
+use tokio::sync::broadcast::error::RecvError;
+
+#[cfg(test)]
+#[path = "tests/commit_proof_tests.rs"]
+pub mod commit_proof_tests;
+
+/// The prefix of the keys of the commit proofs (one per directly committed leader, by epoch and round).
+const COMMIT_PROOF_KEY_PREFIX: &[u8] = b"commit_proof:";
+
+/// Proves to a light client that a leader was committed: the certificate of the leader, along with the
+/// certificates of the next round referencing it (holding at least f+1 of the stake). A light client holding
+/// the committee verifies it without the DAG. Only the leaders committed directly have a proof; each of them
+/// commits its whole causal history (including the leaders it links to), so the proven leaders are committed
+/// in round order.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommitProof {
+    /// The certificate of the leader.
+    pub leader: Certificate,
+    /// The certificates of the next round referencing the leader.
+    pub support: Vec<Certificate>,
+}
+
+impl CommitProof {
+    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        self.leader.verify(committee)?;
+
+        // Ensure the support certificates reference the leader, and hold f+1 of the stake.
+        let digest = self.leader.digest();
+        let mut stake = 0;
+        let mut used = HashSet::new();
+        for certificate in &self.support {
+            ensure!(
+                certificate.round() == self.leader.round() + 1
+                    && certificate.header.parents.contains(&digest),
+                DagError::InvalidCommitProof(digest)
+            );
+            let origin = certificate.origin();
+            ensure!(used.insert(origin), DagError::AuthorityReuse(origin));
+            certificate.verify(committee)?;
+            stake += committee.stake(&origin);
+        }
+        ensure!(
+            stake >= committee.validity_threshold(),
+            DagError::InvalidCommitProof(digest)
+        );
+        Ok(())
+    }
+
+    /// Returns the key of the proof of the leader of the specified round.
+    pub fn key(epoch: Epoch, round: Round) -> Vec<u8> {
+        [
+            COMMIT_PROOF_KEY_PREFIX,
+            &epoch.to_be_bytes(),
+            &round.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Persist all the commit proofs published from now on (so that light clients can fetch them).
+    pub fn persist(commit_events: &CommitEventBus, mut store: Store, epoch: Epoch) {
+        let mut rx_proofs = commit_events.subscribe_proofs();
+        tokio::spawn(async move {
+            loop {
+                let proof = match rx_proofs.recv().await {
+                    Ok(proof) => proof,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let round = proof.leader.round();
+                let bytes = bincode::serialize(&proof).expect("Failed to serialize commit proof");
+                store.write(Self::key(epoch, round), bytes).await;
+                debug!("Stored the commit proof of round {}", round);
+            }
+        });
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::{Certificate, CommittedSubDag};
+use async_trait::async_trait;
+use bytes::Bytes;
+use config::WorkerId;
+use crypto::Digest;
+use futures::sink::SinkExt as _;
+use log::info;
+use network::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/commit_stream_tests.rs"]
+pub mod commit_stream_tests;
+
+/// The number of commits a subscriber may lag behind before being disconnected.
+const STREAM_CAPACITY: usize = 10_000;
+
+/// The request of a subscriber (the first message it sends on its connection).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CommitSubscription {
+    /// Whether to include the digests of the batches referenced by each certificate.
+    pub batches: bool,
+}
+
+/// A certificate committed by consensus, as streamed to the subscribers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommittedCertificate {
+    /// The position of the certificate in the commit sequence (since the primary started).
+    pub index: u64,
+    /// The committed certificate.
+    pub certificate: Certificate,
+    /// The digests of the batches referenced by the certificate, along with the id of the worker holding them
+    /// (only if the subscriber asked for them).
+    pub batches: Vec<(Digest, WorkerId)>,
+}
+
+/// Streams the certificates committed by consensus (in commit order) to external subscribers over TCP, so
+/// that an execution engine can consume the output of Narwhal without linking the consensus crate. Each
+/// subscriber opens a connection, sends a serialized `CommitSubscription`, and then receives a serialized
+/// `CommittedCertificate` per commit (length-delimited frames). Subscribers only get the commits made after
+/// they subscribed; those lagging more than `STREAM_CAPACITY` commits behind are disconnected (rather than
+/// silently missing commits).
+pub struct CommitStream;
+
+impl CommitStream {
+    /// Spawn the stream on the specified address. The sub-dags committed by consensus (`rx_consensus`) are
+    /// forwarded to `tx_output` as they are streamed.
+    pub fn spawn(
+        address: SocketAddr,
+        mut rx_consensus: Receiver<CommittedSubDag>,
+        tx_output: Sender<CommittedSubDag>,
+    ) {
+        let (tx_commits, _) = broadcast::channel(STREAM_CAPACITY);
+        NetworkReceiver::spawn(
+            address,
+            CommitStreamHandler {
+                tx_commits: tx_commits.clone(),
+            },
+        );
+        info!("Streaming commits on {}", address);
+
+        tokio::spawn(async move {
+            let mut index = 0;
+            while let Some(sub_dag) = rx_consensus.recv().await {
+                for certificate in &sub_dag.certificates {
+                    // It is fine to have no subscribers.
+                    let _ = tx_commits.send(CommittedCertificate {
+                        index,
+                        certificate: certificate.clone(),
+                        batches: Vec::new(),
+                    });
+                    index += 1;
+                }
+
+                tx_output
+                    .send(sub_dag)
+                    .await
+                    .expect("Failed to output committed sub-dag");
+            }
+        });
+    }
+}
+
+/// Serves the subscribers of the `CommitStream`.
+#[derive(Clone)]
+struct CommitStreamHandler {
+    tx_commits: broadcast::Sender<CommittedCertificate>,
+}
+
+#[async_trait]
+impl MessageHandler for CommitStreamHandler {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        let subscription: CommitSubscription = bincode::deserialize(&message)?;
+        let mut rx_commits = self.tx_commits.subscribe();
+        loop {
+            let mut commit = match rx_commits.recv().await {
+                Ok(commit) => commit,
+                // Returning an error closes the connection.
+                Err(RecvError::Lagged(missed)) => {
+                    let e = format!("Commit subscriber lagging {} commits behind", missed);
+                    return Err(e.into());
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            };
+            if subscription.batches {
+                commit.batches = commit
+                    .certificate
+                    .header
+                    .payload
+                    .iter()
+                    .map(|(digest, worker_id)| (digest.clone(), *worker_id))
+                    .collect();
+            }
+            let bytes = bincode::serialize(&commit).expect("Failed to serialize commit");
+            writer.send(Bytes::from(bytes)).await?;
+        }
+    }
+}
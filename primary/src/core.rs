@@ -1,8 +1,15 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::aggregators::{CertificatesAggregator, VotesAggregator};
+use crate::erasure::{self, HeaderShard, ShardsAggregator};
 use crate::error::{DagError, DagResult};
-use crate::messages::{Certificate, Header, Vote};
+use crate::evidence::{Equivocation, EvidenceStore};
+use crate::header_validator::HeaderValidator;
+use crate::introspection::PendingVotes;
+use crate::messages::{Certificate, CompactCertificate, Header, Vote};
 use crate::primary::{PrimaryMessage, Round};
+use crate::recovery::RecoveryStore;
+use crate::round_index::RoundIndex;
+use crate::state_synchronizer::DagProgress;
 use crate::synchronizer::Synchronizer;
 use async_recursion::async_recursion;
 use bytes::Bytes;
@@ -10,7 +17,9 @@ use config::Committee;
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey, SignatureService};
 use log::{debug, error, warn};
-use network::{CancelHandler, ReliableSender};
+use network::{DatagramSender, NetworkMetrics, ReliableBroadcast, ReliableSender, SharedTransport};
+use std::collections::btree_map;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
@@ -21,6 +30,15 @@ use tokio::sync::mpsc::{Receiver, Sender};
 #[path = "tests/core_tests.rs"]
 pub mod core_tests;
 
+/// The lanes carrying the (verified) messages of the other primaries to the core.
+#[derive(Clone, Copy)]
+enum Lane {
+    /// The consensus-critical messages: headers, votes, and certificates of the current rounds.
+    Consensus,
+    /// The certificates received while synchronizing. They are only processed when the consensus lane is empty.
+    Sync,
+}
+
 pub struct Core {
     /// The public key of this primary.
     name: PublicKey,
@@ -32,13 +50,23 @@ pub struct Core {
     synchronizer: Synchronizer,
     /// Service to sign headers.
     signature_service: SignatureService,
+    /// The custom rules the headers must follow for us to vote for them.
+    header_validator: Box<dyn HeaderValidator>,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
+    /// The progress of our local DAG (used to detect that we are lagging behind).
+    progress: Arc<DagProgress>,
     /// The depth of the garbage collector.
     gc_depth: Round,
+    /// The maximum number of certificates processed as a batch (whose parents are checked concurrently).
+    certificate_concurrency: usize,
+    /// Whether we exclude the authorities we caught equivocating (ie. stop voting for their headers).
+    exclude_misbehaving: bool,
 
     /// Receiver for dag messages (headers, votes, certificates).
     rx_primaries: Receiver<PrimaryMessage>,
+    /// Receives the certificates obtained by synchronizing with the other primaries (lower priority).
+    rx_sync: Receiver<PrimaryMessage>,
     /// Receives loopback headers from the `HeaderWaiter`.
     rx_header_waiter: Receiver<Header>,
     /// Receives loopback certificates from the `CertificateWaiter`.
@@ -50,22 +78,44 @@ pub struct Core {
     /// Send valid a quorum of certificates' ids to the `Proposer` (along with their round).
     tx_proposer: Sender<(Vec<Digest>, Round)>,
 
+    /// The index of the stored certificates by round.
+    round_index: RoundIndex,
+    /// Persists the evidence of the equivocations we detect.
+    evidence: EvidenceStore,
+    /// Persists the state needed to resume after a restart.
+    recovery: RecoveryStore,
     /// The last garbage collected round.
     gc_round: Round,
     /// The authors of the last voted headers.
     last_voted: HashMap<Round, HashSet<PublicKey>>,
     /// The set of headers we are currently processing.
     processing: HashMap<Round, HashSet<Digest>>,
+    /// The first header we received from each author (used to detect equivocations).
+    first_headers: HashMap<Round, HashMap<PublicKey, Header>>,
     /// The last header we proposed (for which we are waiting votes).
     current_header: Header,
     /// Aggregates votes into a certificate.
     votes_aggregator: VotesAggregator,
+    /// The votes received so far for our last header.
+    own_votes: Vec<Vote>,
     /// Aggregates certificates to use as parents for new headers.
     certificates_aggregators: HashMap<Round, Box<CertificatesAggregator>>,
     /// A network sender to send the batches to the other workers.
     network: ReliableSender,
-    /// Keeps the cancel handlers of the messages we sent.
-    cancel_handlers: HashMap<Round, Vec<CancelHandler>>,
+    /// Keeps the messages we sent (re-transmitted until they are acknowledged or garbage collected).
+    broadcasts: HashMap<Round, Vec<ReliableBroadcast>>,
+    /// A network sender to send our votes as datagrams (if enabled).
+    datagram: Option<DatagramSender>,
+    /// Our headers of at least this size (in bytes) are disseminated as erasure-coded shards (0 to disable).
+    erasure_coding_threshold: usize,
+    /// The index of our shard of erasure-coded headers.
+    shard_index: usize,
+    /// Gathers the shards of the erasure-coded headers of the other authorities (by round and commitment).
+    shards: HashMap<Round, HashMap<Digest, ShardsAggregator>>,
+    /// The certificates of erasure-coded headers we did not reconstruct yet (by round and header id).
+    compact_certificates: HashMap<Round, HashMap<Digest, CompactCertificate>>,
+    /// The message that ended the last batch of certificates (processed right after the batch).
+    pending_message: Option<(PrimaryMessage, Lane)>,
 }
 
 impl Core {
@@ -76,25 +126,48 @@ impl Core {
         store: Store,
         synchronizer: Synchronizer,
         signature_service: SignatureService,
+        header_validator: Box<dyn HeaderValidator>,
         consensus_round: Arc<AtomicU64>,
+        progress: Arc<DagProgress>,
         gc_depth: Round,
+        certificate_concurrency: usize,
+        max_datagram_size: usize,
+        erasure_coding_threshold: usize,
+        exclude_misbehaving: bool,
+        transport: SharedTransport,
+        network_metrics: NetworkMetrics,
         rx_primaries: Receiver<PrimaryMessage>,
+        rx_sync: Receiver<PrimaryMessage>,
         rx_header_waiter: Receiver<Header>,
         rx_certificate_waiter: Receiver<Certificate>,
         rx_proposer: Receiver<Header>,
         tx_consensus: Sender<Certificate>,
         tx_proposer: Sender<(Vec<Digest>, Round)>,
     ) {
+        let shard_index = erasure::shard_index(&committee, &name)
+            .expect("Our public key is not in the committee");
+        let erasure_coding_threshold = match erasure::supports_erasure_coding(&committee) {
+            true => erasure_coding_threshold,
+            false => 0,
+        };
         tokio::spawn(async move {
             Self {
                 name,
+                round_index: RoundIndex::new(store.clone(), committee.epoch),
+                evidence: EvidenceStore::new(store.clone(), committee.epoch),
+                recovery: RecoveryStore::new(store.clone(), committee.epoch),
                 committee,
                 store,
                 synchronizer,
                 signature_service,
+                header_validator,
                 consensus_round,
+                progress,
                 gc_depth,
+                certificate_concurrency: certificate_concurrency.max(1),
+                exclude_misbehaving,
                 rx_primaries,
+                rx_sync,
                 rx_header_waiter,
                 rx_certificate_waiter,
                 rx_proposer,
@@ -103,11 +176,25 @@ impl Core {
                 gc_round: 0,
                 last_voted: HashMap::with_capacity(2 * gc_depth as usize),
                 processing: HashMap::with_capacity(2 * gc_depth as usize),
+                first_headers: HashMap::with_capacity(2 * gc_depth as usize),
                 current_header: Header::default(),
                 votes_aggregator: VotesAggregator::new(),
+                own_votes: Vec::new(),
                 certificates_aggregators: HashMap::with_capacity(2 * gc_depth as usize),
-                network: ReliableSender::new(),
-                cancel_handlers: HashMap::with_capacity(2 * gc_depth as usize),
+                network: ReliableSender::new()
+                    .with_transport(transport.clone())
+                    .with_metrics(network_metrics.clone()),
+                broadcasts: HashMap::with_capacity(2 * gc_depth as usize),
+                datagram: (max_datagram_size > 0).then(|| {
+                    DatagramSender::new(max_datagram_size)
+                        .with_transport(transport)
+                        .with_metrics(network_metrics)
+                }),
+                pending_message: None,
+                erasure_coding_threshold,
+                shard_index,
+                shards: HashMap::with_capacity(2 * gc_depth as usize),
+                compact_certificates: HashMap::with_capacity(2 * gc_depth as usize),
             }
             .run()
             .await;
@@ -115,32 +202,244 @@ impl Core {
     }
 
     async fn process_own_header(&mut self, header: Header) -> DagResult<()> {
+        // Persist the header before broadcasting it, so that we never propose another header for this round.
+        self.recovery.write_header(&header).await;
+        self.recovery.write_votes(&header.id, &[]).await;
+
         // Reset the votes aggregator.
         self.current_header = header.clone();
         self.votes_aggregator = VotesAggregator::new();
+        self.own_votes.clear();
+        *self.progress.pending_votes.lock().unwrap() = PendingVotes::new(&header);
 
         // Broadcast the new header in a reliable manner.
+        self.broadcast_header(&header).await;
+
+        // Process the header.
+        self.process_header(&header).await
+    }
+
+    /// Returns whether our header is disseminated as erasure-coded shards (its certificate then refers to it
+    /// by digest).
+    fn is_erasure_coded(&self, header: &Header) -> bool {
+        self.erasure_coding_threshold > 0
+            && bincode::serialized_size(&PrimaryMessage::Header(header.clone()))
+                .is_ok_and(|x| x as usize >= self.erasure_coding_threshold)
+    }
+
+    /// Reliably broadcast our header to the other primaries.
+    async fn broadcast_header(&mut self, header: &Header) {
+        if self.is_erasure_coded(header) {
+            return self.broadcast_shards(header).await;
+        }
+        let bytes = bincode::serialize(&PrimaryMessage::Header(header.clone()))
+            .expect("Failed to serialize our own header");
+
         let addresses = self
             .committee
             .others_primaries(&self.name)
             .iter()
-            .map(|(_, x)| x.primary_to_primary)
+            .map(|(_, x)| x.primary_to_primary.advertise.clone())
             .collect();
-        let bytes = bincode::serialize(&PrimaryMessage::Header(header.clone()))
-            .expect("Failed to serialize our own header");
-        let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
-        self.cancel_handlers
+        let broadcast =
+            ReliableBroadcast::send(&mut self.network, addresses, Bytes::from(bytes)).await;
+        self.broadcasts
             .entry(header.round)
             .or_insert_with(Vec::new)
-            .extend(handlers);
+            .push(broadcast);
+    }
 
-        // Process the header.
-        self.process_header(&header).await
+    /// Reliably disseminate our header as erasure-coded shards: each primary receives its own shard (which it
+    /// echoes to the others) as well as our shard.
+    async fn broadcast_shards(&mut self, header: &Header) {
+        let shards =
+            HeaderShard::encode(header, &self.committee, &mut self.signature_service).await;
+        let others = self.committee.others_primaries(&self.name);
+        let mut messages = vec![(
+            shards[self.shard_index].clone(),
+            others
+                .iter()
+                .map(|(_, x)| x.primary_to_primary.advertise.clone())
+                .collect(),
+        )];
+        for (name, addresses) in others {
+            let index = erasure::shard_index(&self.committee, &name)
+                .expect("Authority of the committee has no shard");
+            messages.push((
+                shards[index].clone(),
+                vec![addresses.primary_to_primary.advertise],
+            ));
+        }
+
+        for (shard, addresses) in messages {
+            let bytes = bincode::serialize(&PrimaryMessage::HeaderShard(shard))
+                .expect("Failed to serialize our own shard");
+            let broadcast =
+                ReliableBroadcast::send(&mut self.network, addresses, Bytes::from(bytes)).await;
+            self.broadcasts
+                .entry(header.round)
+                .or_insert_with(Vec::new)
+                .push(broadcast);
+        }
+    }
+
+    /// Gather the shards of the erasure-coded header of another authority, and process the header once it can
+    /// be reconstructed. We echo our own shard to the other primaries (so that they all gather enough shards).
+    async fn process_shard(&mut self, shard: HeaderShard) -> DagResult<()> {
+        debug!("Processing {:?}", shard);
+        ensure!(
+            self.gc_round <= shard.round,
+            DagError::TooOld(shard.id.clone(), shard.round)
+        );
+
+        let committee = &self.committee;
+        let aggregator = self
+            .shards
+            .entry(shard.round)
+            .or_insert_with(HashMap::new)
+            .entry(shard.digest())
+            .or_insert_with(|| ShardsAggregator::new(committee));
+        let echo = shard.index == self.shard_index
+            && shard.author != self.name
+            && !aggregator.contains(shard.index);
+        let (author, round) = (shard.author, shard.round);
+        let message = echo.then(|| PrimaryMessage::HeaderShard(shard.clone()));
+        let header = aggregator.append(shard)?;
+
+        if let Some(message) = message {
+            let addresses = self
+                .committee
+                .others_primaries(&self.name)
+                .iter()
+                .filter(|(name, _)| name != &author)
+                .map(|(_, x)| x.primary_to_primary.advertise.clone())
+                .collect();
+            let bytes = bincode::serialize(&message).expect("Failed to serialize shard");
+            let broadcast =
+                ReliableBroadcast::send(&mut self.network, addresses, Bytes::from(bytes)).await;
+            self.broadcasts
+                .entry(round)
+                .or_insert_with(Vec::new)
+                .push(broadcast);
+        }
+
+        // The shards only carry the signature of the author over their commitment: check the header itself.
+        if let Some(header) = header {
+            header.verify(&self.committee)?;
+            self.sanitize_header(&header)?;
+            self.process_header(&header).await?;
+
+            // Process its certificate if we received it first.
+            if let Some(certificate) = self
+                .compact_certificates
+                .get_mut(&header.round)
+                .and_then(|x| x.remove(&header.id))
+            {
+                let certificate = certificate.expand(header)?;
+                self.process_certificates(vec![certificate]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Process the certificate of an erasure-coded header, once we reconstructed the header.
+    async fn process_compact_certificate(
+        &mut self,
+        certificate: CompactCertificate,
+    ) -> DagResult<()> {
+        debug!("Processing {:?}", certificate);
+        ensure!(
+            self.gc_round <= certificate.round,
+            DagError::TooOld(certificate.digest(), certificate.round)
+        );
+
+        // Look for the header among the ones we received (or stored, if we processed it since).
+        let header = match self
+            .first_headers
+            .get(&certificate.round)
+            .and_then(|x| x.get(&certificate.origin))
+            .filter(|x| x.id == certificate.id)
+        {
+            Some(header) => Some(header.clone()),
+            None => match self.store.read(certificate.id.to_vec()).await? {
+                Some(bytes) => Some(bincode::deserialize(&bytes)?),
+                None => None,
+            },
+        };
+
+        // Otherwise, wait until we reconstruct it. If we never do, the certificate is eventually synced (in
+        // full) as the parent of a later certificate.
+        match header {
+            Some(header) => {
+                let certificate = certificate.expand(header)?;
+                self.process_certificates(vec![certificate]).await
+            }
+            None => {
+                debug!("Processing of {:?} suspended: missing header", certificate);
+                self.compact_certificates
+                    .entry(certificate.round)
+                    .or_insert_with(HashMap::new)
+                    .insert(certificate.id.clone(), certificate);
+                Ok(())
+            }
+        }
+    }
+
+    /// Check whether the author of the header already signed a different header for the same round, and if
+    /// so persist both headers as evidence.
+    async fn detect_equivocation(&mut self, header: &Header) -> DagResult<()> {
+        let first = match self
+            .first_headers
+            .entry(header.round)
+            .or_insert_with(HashMap::new)
+            .entry(header.author)
+        {
+            Entry::Occupied(entry) if entry.get().id != header.id => entry.get().clone(),
+            Entry::Occupied(_) => return Ok(()),
+            Entry::Vacant(entry) => {
+                entry.insert(header.clone());
+                return Ok(());
+            }
+        };
+
+        let equivocation = Equivocation {
+            first,
+            second: header.clone(),
+        };
+        if self.evidence.insert(&equivocation).await? {
+            warn!(
+                "Equivocation detected: {} signed headers {} and {} for round {}",
+                header.author, equivocation.first.id, header.id, header.round
+            );
+            self.exclude(&equivocation);
+        }
+        Ok(())
+    }
+
+    /// Exclude the author of an equivocation (if the policy is enabled). We never exclude ourselves.
+    fn exclude(&mut self, equivocation: &Equivocation) {
+        let author = equivocation.author();
+        if !self.exclude_misbehaving || author == self.name {
+            return;
+        }
+        let mut excluded = self.progress.excluded.lock().unwrap();
+        if let btree_map::Entry::Vacant(entry) = excluded.entry(author) {
+            warn!("Excluding {} (equivocated at round {})", author, equivocation.round());
+            entry.insert(equivocation.round());
+        }
+    }
+
+    /// Returns whether the authority is excluded.
+    fn is_excluded(&self, author: &PublicKey) -> bool {
+        self.progress.excluded.lock().unwrap().contains_key(author)
     }
 
     #[async_recursion]
     async fn process_header(&mut self, header: &Header) -> DagResult<()> {
         debug!("Processing {:?}", header);
+        // Check whether the author equivocated.
+        self.detect_equivocation(header).await?;
+
         // Indicate that we are processing this header.
         self.processing
             .entry(header.round)
@@ -181,13 +480,28 @@ impl Core {
         let bytes = bincode::serialize(header).expect("Failed to serialize header");
         self.store.write(header.id.to_vec(), bytes).await;
 
-        // Check if we can vote for this header.
-        if self
+        // Check the header against our custom rules. We do not vote for headers breaking them.
+        if let Err(reason) = self.header_validator.validate(header) {
+            warn!("{}", DagError::RejectedHeader(header.id.clone(), reason));
+            return Ok(());
+        }
+
+        // Do not vote for the headers of the authorities we excluded.
+        if self.is_excluded(&header.author) {
+            debug!("Not voting for {:?} (author excluded)", header);
+            return Ok(());
+        }
+
+        // Check if we can vote for this header. We persist our decision before voting, so that we never vote
+        // for two headers of the same author and round (even across restarts).
+        let voted = self
             .last_voted
             .entry(header.round)
-            .or_insert_with(HashSet::new)
-            .insert(header.author)
-        {
+            .or_insert_with(HashSet::new);
+        if voted.insert(header.author) {
+            let voted = voted.clone();
+            self.recovery.write_voted(header.round, &voted).await;
+
             // Make a vote and send it to the header's creator.
             let vote = Vote::new(header, &self.name, &mut self.signature_service).await;
             debug!("Created {:?}", vote);
@@ -200,14 +514,25 @@ impl Core {
                     .committee
                     .primary(&header.author)
                     .expect("Author of valid header is not in the committee")
-                    .primary_to_primary;
+                    .primary_to_primary
+                    .advertise;
                 let bytes = bincode::serialize(&PrimaryMessage::Vote(vote))
                     .expect("Failed to serialize our own vote");
-                let handler = self.network.send(address, Bytes::from(bytes)).await;
-                self.cancel_handlers
-                    .entry(header.round)
-                    .or_insert_with(Vec::new)
-                    .push(handler);
+                match self.datagram.as_mut() {
+                    Some(datagram) => datagram.send(address, Bytes::from(bytes)).await,
+                    None => {
+                        let broadcast = ReliableBroadcast::send(
+                            &mut self.network,
+                            vec![address],
+                            Bytes::from(bytes),
+                        )
+                        .await;
+                        self.broadcasts
+                            .entry(header.round)
+                            .or_insert_with(Vec::new)
+                            .push(broadcast);
+                    }
+                }
             }
         }
         Ok(())
@@ -218,26 +543,45 @@ impl Core {
         debug!("Processing {:?}", vote);
 
         // Add it to the votes' aggregator and try to make a new certificate.
-        if let Some(certificate) =
+        let author = vote.author;
+        let certificate =
             self.votes_aggregator
-                .append(vote, &self.committee, &self.current_header)?
+                .append(vote.clone(), &self.committee, &self.current_header)?;
+        self.own_votes.push(vote);
+        self.recovery
+            .write_votes(&self.current_header.id, &self.own_votes)
+            .await;
         {
+            let mut pending_votes = self.progress.pending_votes.lock().unwrap();
+            pending_votes.voters.push(author);
+            pending_votes.certified |= certificate.is_some();
+        }
+
+        if let Some(certificate) = certificate {
             debug!("Assembled {:?}", certificate);
+            self.progress
+                .pipeline
+                .record_vote_quorum(certificate.round());
 
             // Broadcast the certificate.
             let addresses = self
                 .committee
                 .others_primaries(&self.name)
                 .iter()
-                .map(|(_, x)| x.primary_to_primary)
+                .map(|(_, x)| x.primary_to_primary.advertise.clone())
                 .collect();
-            let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate.clone()))
-                .expect("Failed to serialize our own certificate");
-            let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
-            self.cancel_handlers
+            let message = match self.is_erasure_coded(&self.current_header) {
+                true => PrimaryMessage::CompactCertificate(certificate.compact()),
+                false => PrimaryMessage::Certificate(certificate.clone()),
+            };
+            let bytes =
+                bincode::serialize(&message).expect("Failed to serialize our own certificate");
+            let broadcast =
+                ReliableBroadcast::send(&mut self.network, addresses, Bytes::from(bytes)).await;
+            self.broadcasts
                 .entry(certificate.round())
                 .or_insert_with(Vec::new)
-                .extend(handlers);
+                .push(broadcast);
 
             // Process the new certificate.
             self.process_certificate(certificate)
@@ -250,20 +594,7 @@ impl Core {
     #[async_recursion]
     async fn process_certificate(&mut self, certificate: Certificate) -> DagResult<()> {
         debug!("Processing {:?}", certificate);
-        debug!("Received certificate from network: round {}, origin: {}, digest: {}", certificate.round(), certificate.origin(), certificate.digest());
-
-        // Process the header embedded in the certificate if we haven't already voted for it (if we already
-        // voted, it means we already processed it). Since this header got certified, we are sure that all
-        // the data it refers to (ie. its payload and its parents) are available. We can thus continue the
-        // processing of the certificate even if we don't have them in store right now.
-        if !self
-            .processing
-            .get(&certificate.header.round)
-            .map_or_else(|| false, |x| x.contains(&certificate.header.id))
-        {
-            // This function may still throw an error if the storage fails.
-            self.process_header(&certificate.header).await?;
-        }
+        self.process_embedded_header(&certificate).await?;
 
         // Ensure we have all the ancestors of this certificate yet. If we don't, the synchronizer will gather
         // them and trigger re-processing of this certificate.
@@ -274,12 +605,100 @@ impl Core {
             );
             return Ok(());
         }
+        self.accept_certificate(certificate).await
+    }
+
+    /// Process a batch of certificates received from the other primaries. The certificates go through a
+    /// pipeline: their signatures are verified beforehand (by the `Verifier`), their parents are then looked
+    /// up in storage concurrently, and they are finally stored and delivered one by one in the order they were
+    /// received (thus preserving the order of the certificates of each author). A certificate whose parents
+    /// precede it in the batch is delivered right after them.
+    async fn process_certificates(&mut self, certificates: Vec<Certificate>) -> DagResult<()> {
+        let certificates: Vec<_> = certificates
+            .into_iter()
+            .filter(|certificate| match self.sanitize_certificate(certificate) {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("{}", e);
+                    false
+                }
+            })
+            .collect();
+
+        // Check the parents of all certificates concurrently.
+        let missing = self
+            .synchronizer
+            .missing_parents(&certificates, self.certificate_concurrency)
+            .await?;
+
+        // Store and deliver the certificates in order.
+        let mut delivered = HashSet::new();
+        for (certificate, missing) in certificates.into_iter().zip(missing) {
+            debug!("Processing {:?}", certificate);
+            debug!(
+                "Received certificate from network: round {}, origin: {}, digest: {}",
+                certificate.round(),
+                certificate.origin(),
+                certificate.digest()
+            );
+            self.progress
+                .observed_round
+                .fetch_max(certificate.round(), Ordering::Relaxed);
+            if let Err(e) = self.process_embedded_header(&certificate).await {
+                Self::report(e);
+                continue;
+            }
+
+            // If we miss some ancestors, the synchronizer will gather them and trigger re-processing of this
+            // certificate.
+            if !missing.iter().all(|x| delivered.contains(x)) {
+                debug!(
+                    "Processing of {:?} suspended: missing ancestors",
+                    certificate
+                );
+                self.synchronizer.suspend_certificate(certificate).await;
+                continue;
+            }
 
+            let digest = certificate.digest();
+            match self.accept_certificate(certificate).await {
+                Ok(()) => {
+                    delivered.insert(digest);
+                }
+                Err(e) => Self::report(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Process the header embedded in the certificate if we haven't already voted for it (if we already
+    /// voted, it means we already processed it). Since this header got certified, we are sure that all
+    /// the data it refers to (ie. its payload and its parents) are available. We can thus continue the
+    /// processing of the certificate even if we don't have them in store right now.
+    async fn process_embedded_header(&mut self, certificate: &Certificate) -> DagResult<()> {
+        if !self
+            .processing
+            .get(&certificate.header.round)
+            .map_or_else(|| false, |x| x.contains(&certificate.header.id))
+        {
+            // This function may still throw an error if the storage fails.
+            self.process_header(&certificate.header).await?;
+        }
+        Ok(())
+    }
+
+    /// Store a certificate whose ancestors we all have, and deliver it to the `Proposer` (if it completes a
+    /// quorum) and to the consensus layer.
+    async fn accept_certificate(&mut self, certificate: Certificate) -> DagResult<()> {
         // Store the certificate.
         let bytes = bincode::serialize(&certificate).expect("Failed to serialize certificate");
         self.store.write(certificate.digest().to_vec(), bytes).await;
+        self.round_index.insert(&certificate).await?;
+        self.progress.certificates.fetch_add(1, Ordering::Relaxed);
+        self.progress.dag_shape.record_certificate(certificate.round());
                 
-        // Check if we have enough certificates to enter a new dag round and propose a header.
+        // Check if we have enough certificates to enter a new dag round and propose a header. The certificates
+        // arriving after the quorum are forwarded as well: the `Proposer` may still include them.
         if let Some(parents) = self
             .certificates_aggregators
             .entry(certificate.round())
@@ -287,6 +706,12 @@ impl Core {
             .append(certificate.clone(), &self.committee)?
         {
             // Send it to the `Proposer`.
+            self.progress
+                .dag_round
+                .fetch_max(certificate.round(), Ordering::Relaxed);
+            self.progress
+                .pipeline
+                .record_certificate_quorum(certificate.round());
             self.tx_proposer
                 .send((parents, certificate.round()))
                 .await
@@ -310,8 +735,7 @@ impl Core {
             DagError::TooOld(header.id.clone(), header.round)
         );
 
-        // Verify the header's signature.
-        header.verify(&self.committee)?;
+        // The header's signature has already been verified by the `Verifier`.
 
         // TODO [issue #3]: Prevent bad nodes from sending junk headers with high round numbers.
 
@@ -332,8 +756,8 @@ impl Core {
             DagError::UnexpectedVote(vote.id.clone())
         );
 
-        // Verify the vote.
-        vote.verify(&self.committee).map_err(DagError::from)
+        // The vote's signatures have already been verified by the `Verifier`.
+        Ok(())
     }
 
     fn sanitize_certificate(&mut self, certificate: &Certificate) -> DagResult<()> {
@@ -342,70 +766,195 @@ impl Core {
             DagError::TooOld(certificate.digest(), certificate.round())
         );
 
-        // Verify the certificate (and the embedded header).
-        certificate.verify(&self.committee).map_err(DagError::from)
+        // The certificate (and the embedded header) has already been verified by the `Verifier`.
+        Ok(())
     }
 
-    // Main loop listening to incoming messages.
-    pub async fn run(&mut self) {
-        loop {
-            let result = tokio::select! {
-                // We receive here messages from other primaries.
-                Some(message) = self.rx_primaries.recv() => {
-                    match message {
-                        PrimaryMessage::Header(header) => {
-                            match self.sanitize_header(&header) {
-                                Ok(()) => self.process_header(&header).await,
-                                error => error
-                            }
-
-                        },
-                        PrimaryMessage::Vote(vote) => {
-                            match self.sanitize_vote(&vote) {
-                                Ok(()) => self.process_vote(vote).await,
-                                error => error
-                            }
-                        },
-                        PrimaryMessage::Certificate(certificate) => {
-                            match self.sanitize_certificate(&certificate) {
-                                Ok(()) =>  self.process_certificate(certificate).await,
-                                error => error
-                            }
-                        },
-                        _ => panic!("Unexpected core message")
+    /// Rebuild our state from the store after a restart: the certificates of the last rounds (so that the
+    /// `Proposer` resumes from the last round with a quorum of certificates), the headers we voted for, and our
+    /// last header along with its votes (re-broadcasting it if it did not get certified).
+    async fn recover(&mut self) -> DagResult<()> {
+        let last_round = self.round_index.last_round().await?;
+        let start = last_round.saturating_sub(self.gc_depth).max(1);
+        let header = self
+            .recovery
+            .read_header()
+            .await?
+            .filter(|x| x.round >= start);
+
+        // Load the certificates of the last rounds.
+        let mut parents: Option<(Vec<Digest>, Round)> = None;
+        let mut certified = false;
+        for round in start..=last_round {
+            for digest in self.round_index.read(round).await? {
+                let certificate: Certificate = match self.store.read(digest.to_vec()).await? {
+                    Some(bytes) => bincode::deserialize(&bytes)?,
+                    None => continue,
+                };
+                certified |= header
+                    .as_ref()
+                    .is_some_and(|x| x.id == certificate.header.id);
+                if let Some(digests) = self
+                    .certificates_aggregators
+                    .entry(round)
+                    .or_insert_with(|| Box::new(CertificatesAggregator::new()))
+                    .append(certificate, &self.committee)?
+                {
+                    match parents.as_mut() {
+                        Some((x, r)) if *r == round => x.extend(digests),
+                        _ => parents = Some((digests, round)),
                     }
-                },
+                }
+            }
+        }
 
-                // We receive here loopback headers from the `HeaderWaiter`. Those are headers for which we interrupted
-                // execution (we were missing some of their dependencies) and we are now ready to resume processing.
-                Some(header) = self.rx_header_waiter.recv() => self.process_header(&header).await,
+        // Exclude again the authorities we caught equivocating before the restart.
+        for equivocation in self.evidence.read_all().await? {
+            self.exclude(&equivocation);
+        }
 
-                // We receive here loopback certificates from the `CertificateWaiter`. Those are certificates for which
-                // we interrupted execution (we were missing some of their ancestors) and we are now ready to resume
-                // processing.
-                Some(certificate) = self.rx_certificate_waiter.recv() => self.process_certificate(certificate).await,
+        // Load the headers we voted for.
+        for round in start..=last_round + 1 {
+            let voted = self.recovery.read_voted(round).await?;
+            if !voted.is_empty() {
+                self.last_voted.insert(round, voted);
+            }
+        }
 
-                // We also receive here our new headers created by the `Proposer`.
-                Some(header) = self.rx_proposer.recv() => self.process_own_header(header).await,
-            };
-            match result {
-                Ok(()) => (),
-                Err(DagError::StoreError(e)) => {
-                    error!("{}", e);
-                    panic!("Storage failure: killing node.");
+        // Resume from the last round with a quorum of certificates.
+        self.progress
+            .observed_round
+            .fetch_max(last_round, Ordering::Relaxed);
+        if let Some((digests, round)) = parents {
+            debug!("Resuming from round {}", round);
+            self.progress.dag_round.fetch_max(round, Ordering::Relaxed);
+            self.tx_proposer
+                .send((digests, round))
+                .await
+                .expect("Failed to send certificate");
+        }
+
+        // Resume gathering votes for our last header (unless it already got certified).
+        let header = match header {
+            Some(header) if !certified => header,
+            _ => return Ok(()),
+        };
+        debug!("Resuming the broadcast of {:?}", header);
+        let votes = match self.recovery.read_votes().await? {
+            Some((id, votes)) if id == header.id => votes,
+            _ => Vec::new(),
+        };
+        self.current_header = header.clone();
+        *self.progress.pending_votes.lock().unwrap() = PendingVotes::new(&header);
+        self.broadcast_header(&header).await;
+        for vote in votes {
+            self.process_vote(vote).await?;
+        }
+        Ok(())
+    }
+
+    /// Handle a (verified) message from the other primaries. The certificates already waiting in the same lane
+    /// are processed together as a batch.
+    async fn handle_message(&mut self, message: PrimaryMessage, lane: Lane) -> DagResult<()> {
+        match message {
+            PrimaryMessage::Header(header) => {
+                self.sanitize_header(&header)?;
+                self.process_header(&header).await
+            }
+            PrimaryMessage::Vote(vote) => {
+                self.sanitize_vote(&vote)?;
+                self.process_vote(vote).await
+            }
+            PrimaryMessage::Certificate(certificate) => {
+                let mut batch = vec![certificate];
+                let receiver = match lane {
+                    Lane::Consensus => &mut self.rx_primaries,
+                    Lane::Sync => &mut self.rx_sync,
+                };
+                while batch.len() < self.certificate_concurrency {
+                    match receiver.try_recv() {
+                        Ok(PrimaryMessage::Certificate(certificate)) => batch.push(certificate),
+                        Ok(message) => {
+                            self.pending_message = Some((message, lane));
+                            break;
+                        }
+                        Err(_) => break,
+                    }
                 }
-                Err(e @ DagError::TooOld(..)) => debug!("{}", e),
-                Err(e) => warn!("{}", e),
+                self.process_certificates(batch).await
+            }
+            PrimaryMessage::HeaderShard(shard) => self.process_shard(shard).await,
+            PrimaryMessage::CompactCertificate(certificate) => {
+                self.process_compact_certificate(certificate).await
+            }
+            _ => panic!("Unexpected core message"),
+        }
+    }
+
+    /// Log the error raised while processing a message (and kill the node on storage failures).
+    fn report(error: DagError) {
+        match error {
+            DagError::StoreError(e) => {
+                error!("{}", e);
+                panic!("Storage failure: killing node.");
+            }
+            e @ DagError::TooOld(..) => debug!("{}", e),
+            e => warn!("{}", e),
+        }
+    }
+
+    // Main loop listening to incoming messages.
+    pub async fn run(&mut self) {
+        if let Err(e) = self.recover().await {
+            error!("Failed to recover our state: {}", e);
+        }
+
+        loop {
+            let result = match self.pending_message.take() {
+                // The message that ended the last batch of certificates goes first.
+                Some((message, lane)) => self.handle_message(message, lane).await,
+                None => tokio::select! {
+                    // The branches are polled in order, so that consensus-critical messages are never delayed
+                    // behind the certificates received while synchronizing.
+                    biased;
+
+                    // We receive here messages from other primaries.
+                    Some(message) = self.rx_primaries.recv() => self.handle_message(message, Lane::Consensus).await,
+
+                    // We also receive here our new headers created by the `Proposer`.
+                    Some(header) = self.rx_proposer.recv() => self.process_own_header(header).await,
+
+                    // We receive here loopback headers from the `HeaderWaiter`. Those are headers for which we interrupted
+                    // execution (we were missing some of their dependencies) and we are now ready to resume processing.
+                    Some(header) = self.rx_header_waiter.recv() => self.process_header(&header).await,
+
+                    // We receive here loopback certificates from the `CertificateWaiter`. Those are certificates for which
+                    // we interrupted execution (we were missing some of their ancestors) and we are now ready to resume
+                    // processing.
+                    Some(certificate) = self.rx_certificate_waiter.recv() => self.process_certificate(certificate).await,
+
+                    // We receive here the certificates obtained by synchronizing with the other primaries.
+                    Some(message) = self.rx_sync.recv() => self.handle_message(message, Lane::Sync).await,
+                },
+            };
+            if let Err(e) = result {
+                Self::report(e);
             }
 
             // Cleanup internal state.
             let round = self.consensus_round.load(Ordering::Relaxed);
             if round > self.gc_depth {
                 let gc_round = round - self.gc_depth;
+                for r in self.gc_round.max(gc_round.saturating_sub(self.gc_depth))..gc_round {
+                    self.recovery.remove_voted(r).await;
+                }
                 self.last_voted.retain(|k, _| k >= &gc_round);
                 self.processing.retain(|k, _| k >= &gc_round);
+                self.first_headers.retain(|k, _| k >= &gc_round);
                 self.certificates_aggregators.retain(|k, _| k >= &gc_round);
-                self.cancel_handlers.retain(|k, _| k >= &gc_round);
+                self.shards.retain(|k, _| k >= &gc_round);
+                self.compact_certificates.retain(|k, _| k >= &gc_round);
+                self.broadcasts.retain(|k, _| k >= &gc_round);
                 self.gc_round = gc_round;
             }
         }
@@ -0,0 +1,234 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::{DagError, DagResult};
+use crate::messages::Header;
+use crate::primary::Round;
+use config::Committee;
+use crypto::{Digest, Hash, PublicKey, Signature, SignatureService};
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+
+#[cfg(test)]
+#[path = "tests/erasure_tests.rs"]
+pub mod erasure_tests;
+
+/// Returns the number of data shards of a header: the shards (one per authority) of any quorum suffice to
+/// reconstruct it (2f+1 shards with equal stakes, fewer if some authorities hold more stake).
+fn data_shards(committee: &Committee) -> usize {
+    committee.quorum_size()
+}
+
+/// Returns the index of the shard of an authority (ie. its rank in the committee, sorted by public key).
+pub fn shard_index(committee: &Committee, name: &PublicKey) -> Option<usize> {
+    let mut keys: Vec<_> = committee.authorities.keys().collect();
+    keys.sort();
+    keys.iter().position(|x| *x == name)
+}
+
+/// Returns whether the committee is large enough to erasure-code headers (ie. it tolerates at least one fault).
+pub fn supports_erasure_coding(committee: &Committee) -> bool {
+    data_shards(committee) < committee.size()
+}
+
+/// A shard of an erasure-coded header. Rather than sending its (large) header to every authority, the author
+/// sends one shard to each of them and they echo their shard to each other: the author's outbound bandwidth
+/// is then close to the size of the header, regardless of the size of the committee.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HeaderShard {
+    /// The id of the header.
+    pub id: Digest,
+    /// The author of the header.
+    pub author: PublicKey,
+    /// The round of the header.
+    pub round: Round,
+    /// The size of the serialized header.
+    pub length: usize,
+    /// The digests of all the shards of the header (in order).
+    pub digests: Vec<Digest>,
+    /// The signature of the author over the commitment to the shards.
+    pub signature: Signature,
+    /// The index of this shard.
+    pub index: usize,
+    /// The content of this shard.
+    pub data: Vec<u8>,
+}
+
+impl HeaderShard {
+    /// Erasure-code a header into one shard per authority of the committee.
+    pub async fn encode(
+        header: &Header,
+        committee: &Committee,
+        signature_service: &mut SignatureService,
+    ) -> Vec<Self> {
+        let total = committee.size();
+        let data = data_shards(committee);
+        let coder = ReedSolomon::new(data, total - data).expect("Invalid number of shards");
+
+        // Split the serialized header into data shards (of the same size) and compute the parity shards.
+        let serialized = bincode::serialize(header).expect("Failed to serialize header");
+        let size = serialized.len().div_ceil(data);
+        let mut shards: Vec<Vec<u8>> = (0..total)
+            .map(|i| {
+                let mut shard = serialized
+                    .iter()
+                    .skip(i * size)
+                    .take(size)
+                    .copied()
+                    .collect::<Vec<_>>();
+                shard.resize(size, 0);
+                shard
+            })
+            .collect();
+        coder.encode(&mut shards).expect("Failed to encode header");
+
+        // Sign the commitment to all the shards.
+        let mut shard = Self {
+            id: header.id.clone(),
+            author: header.author,
+            round: header.round,
+            length: serialized.len(),
+            digests: shards.iter().map(|x| Self::hash(x)).collect(),
+            signature: Signature::default(),
+            index: 0,
+            data: Vec::new(),
+        };
+        shard.signature = signature_service.request_signature(shard.digest()).await;
+        shards
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| Self {
+                index,
+                data,
+                ..shard.clone()
+            })
+            .collect()
+    }
+
+    fn hash(data: &[u8]) -> Digest {
+        Digest(Sha512::digest(data).as_slice()[..32].try_into().unwrap())
+    }
+
+    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        // Ensure the authority has voting rights.
+        ensure!(
+            committee.stake(&self.author) > 0,
+            DagError::UnknownAuthority(self.author)
+        );
+
+        // Ensure the shard matches the commitment of the author.
+        ensure!(
+            self.digests.len() == committee.size()
+                && self.digests.get(self.index) == Some(&Self::hash(&self.data)),
+            DagError::InvalidShard(self.id.clone())
+        );
+
+        // Check the signature.
+        self.signature
+            .verify(&self.digest(), &self.author)
+            .map_err(DagError::from)
+    }
+}
+
+impl Hash for HeaderShard {
+    /// The commitment to all the shards of the header.
+    fn digest(&self) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(&self.id);
+        hasher.update(self.round.to_le_bytes());
+        hasher.update(self.length.to_le_bytes());
+        for x in &self.digests {
+            hasher.update(x);
+        }
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+}
+
+impl fmt::Debug for HeaderShard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}: S{}({}, {}/{})",
+            self.id,
+            self.round,
+            self.author,
+            self.index,
+            self.digests.len()
+        )
+    }
+}
+
+/// Gathers the shards of a header (under the same commitment) until it can be reconstructed.
+pub struct ShardsAggregator {
+    /// The number of shards needed to reconstruct the header.
+    data: usize,
+    /// The shards received so far.
+    shards: Vec<Option<Vec<u8>>>,
+    /// The number of shards received so far.
+    received: usize,
+    /// Whether the header was already reconstructed.
+    done: bool,
+}
+
+impl ShardsAggregator {
+    pub fn new(committee: &Committee) -> Self {
+        Self {
+            data: data_shards(committee),
+            shards: vec![None; committee.size()],
+            received: 0,
+            done: false,
+        }
+    }
+
+    /// Returns whether we already hold the specified shard.
+    pub fn contains(&self, index: usize) -> bool {
+        self.shards.get(index).is_some_and(|x| x.is_some())
+    }
+
+    /// Add a (verified) shard. Returns the header once enough shards are gathered (only once). If the shards
+    /// gathered so far do not reconstruct the header the author committed to, we keep collecting shards and
+    /// try again with each new one.
+    pub fn append(&mut self, shard: HeaderShard) -> DagResult<Option<Header>> {
+        let id = shard.id.clone();
+        if self.done || self.contains(shard.index) {
+            return Ok(None);
+        }
+        ensure!(shard.index < self.shards.len(), DagError::InvalidShard(id));
+        self.shards[shard.index] = Some(shard.data);
+        self.received += 1;
+
+        if self.received < self.data {
+            return Ok(None);
+        }
+        let header = self.reconstruct(&id, shard.length)?;
+        ensure!(
+            header.id == id
+                && header.digest() == id
+                && header.author == shard.author
+                && header.round == shard.round,
+            DagError::InvalidShard(id)
+        );
+        self.done = true;
+        Ok(Some(header))
+    }
+
+    /// Reconstruct the header from the shards gathered so far (leaving them untouched).
+    fn reconstruct(&self, id: &Digest, length: usize) -> DagResult<Header> {
+        let total = self.shards.len();
+        let coder =
+            ReedSolomon::new(self.data, total - self.data).expect("Invalid number of shards");
+        let mut shards = self.shards.clone();
+        coder
+            .reconstruct_data(&mut shards)
+            .map_err(|_| DagError::InvalidShard(id.clone()))?;
+        let mut serialized: Vec<u8> = shards
+            .iter()
+            .take(self.data)
+            .flat_map(|x| x.as_ref().expect("Missing data shard").iter().copied())
+            .collect();
+        serialized.truncate(length);
+        Ok(bincode::deserialize(&serialized)?)
+    }
+}
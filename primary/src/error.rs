@@ -1,5 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::primary::Round;
+use config::Epoch;
 use crypto::{CryptoError, Digest, PublicKey};
 use store::StoreError;
 use thiserror::Error;
@@ -48,12 +49,36 @@ pub enum DagError {
     #[error("Received unexpected vote fo header {0}")]
     UnexpectedVote(Digest),
 
+    #[error("Authority {0} has no BLS public key")]
+    MissingBlsKey(PublicKey),
+
     #[error("Received certificate without a quorum")]
     CertificateRequiresQuorum,
 
+    #[error("Header {0} rejected: {1}")]
+    RejectedHeader(Digest, String),
+
+    #[error("Invalid shard of header {0}")]
+    InvalidShard(Digest),
+
     #[error("Parents of header {0} are not a quorum")]
     HeaderRequiresQuorum(Digest),
 
+    #[error("Invalid evidence of equivocation against {0}")]
+    InvalidEquivocation(PublicKey),
+
+    #[error("Header {0} belongs to another epoch ({1})")]
+    InvalidEpoch(Digest, Epoch),
+
+    #[error("Failed to fetch rounds {0} to {1}")]
+    RangeFetchFailed(Round, Round),
+
+    #[error("Invalid reply to the request for rounds {0} to {1}")]
+    InvalidRangeReply(Round, Round),
+
+    #[error("Invalid commit proof of leader {0}")]
+    InvalidCommitProof(Digest),
+
     #[error("Message {0} (round {1}) too old")]
     TooOld(Digest, Round),
 }
@@ -0,0 +1,114 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::{DagError, DagResult};
+use crate::messages::Header;
+use crate::primary::Round;
+use config::{Committee, Epoch};
+use crypto::PublicKey;
+use serde::{Deserialize, Serialize};
+use store::Store;
+
+#[cfg(test)]
+#[path = "tests/evidence_tests.rs"]
+pub mod evidence_tests;
+
+/// The prefix of the keys of the evidence (distinct from the 32-byte digests and the other indices).
+const KEY_PREFIX: &[u8] = b"evidence:";
+
+/// The proof that an authority equivocated: two different headers it signed for the same round. It can be
+/// verified by anyone holding the committee, so that slashing or exclusion policies can be built on top.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Equivocation {
+    /// The first header we received.
+    pub first: Header,
+    /// The conflicting header.
+    pub second: Header,
+}
+
+impl Equivocation {
+    /// The authority that equivocated.
+    pub fn author(&self) -> PublicKey {
+        self.first.author
+    }
+
+    /// The round of the conflicting headers.
+    pub fn round(&self) -> Round {
+        self.first.round
+    }
+
+    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        // Ensure the headers conflict.
+        ensure!(
+            self.first.author == self.second.author
+                && self.first.round == self.second.round
+                && self.first.id != self.second.id,
+            DagError::InvalidEquivocation(self.first.author)
+        );
+
+        // Ensure both headers are signed by the authority.
+        self.first.verify(committee)?;
+        self.second.verify(committee)
+    }
+}
+
+/// Persists the evidence of equivocations, indexed by author and round. It is written by the `Core` (which
+/// detects the equivocations) and can be read by anyone holding the store.
+#[derive(Clone)]
+pub struct EvidenceStore {
+    /// The persistent storage.
+    store: Store,
+    /// The current epoch.
+    epoch: Epoch,
+}
+
+impl EvidenceStore {
+    pub fn new(store: Store, epoch: Epoch) -> Self {
+        Self { store, epoch }
+    }
+
+    /// The key holding the evidence against an authority for a round.
+    fn key(&self, author: &PublicKey, round: Round) -> Vec<u8> {
+        [
+            KEY_PREFIX,
+            &self.epoch.to_be_bytes(),
+            &author.0,
+            &round.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Returns the evidence (if any) that the authority equivocated at the specified round.
+    pub async fn read(
+        &mut self,
+        author: &PublicKey,
+        round: Round,
+    ) -> DagResult<Option<Equivocation>> {
+        match self.store.read(self.key(author, round)).await? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Persist the evidence of an equivocation. Returns false if we already hold evidence against the
+    /// authority for that round.
+    pub async fn insert(&mut self, equivocation: &Equivocation) -> DagResult<bool> {
+        let key = self.key(&equivocation.author(), equivocation.round());
+        if self.store.read(key.clone()).await?.is_some() {
+            return Ok(false);
+        }
+        let bytes = bincode::serialize(equivocation).expect("Failed to serialize evidence");
+        self.store.write(key, bytes).await;
+        Ok(true)
+    }
+
+    /// Returns all the evidence of the current epoch.
+    pub async fn read_all(&mut self) -> DagResult<Vec<Equivocation>> {
+        let start = [KEY_PREFIX, &self.epoch.to_be_bytes()].concat();
+        let end = [KEY_PREFIX, &self.epoch.saturating_add(1).to_be_bytes()].concat();
+        self.store
+            .read_range(start, end)
+            .await?
+            .into_iter()
+            .map(|(_, bytes)| Ok(bincode::deserialize(&bytes)?))
+            .collect()
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::commit_events::CommitEventBus;
+use crate::commit_stream::{CommitSubscription, CommittedCertificate};
+use bytes::Bytes;
+use crypto::Digest;
+use crypto::Hash as _;
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[cfg(test)]
+#[path = "tests/fork_detector_tests.rs"]
+pub mod fork_detector_tests;
+
+/// The number of commits of each sequence kept to compare them.
+const HISTORY_SIZE: usize = 10_000;
+
+/// The number of commits of each sequence reported along with a fork.
+const REPORT_SIZE: usize = 10;
+
+/// The delay before reconnecting to the commit stream of a peer. Denominated in ms.
+const RECONNECT_DELAY: u64 = 1_000;
+
+/// Two commit sequences that diverge: a safety violation of consensus.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Fork {
+    /// The position of the first diverging commit (in the local sequence).
+    pub position: u64,
+    /// The last commits on which both sequences agree.
+    pub common: Vec<Digest>,
+    /// The local commits from the divergence.
+    pub local: Vec<Digest>,
+    /// The remote commits from the divergence.
+    pub remote: Vec<Digest>,
+}
+
+impl fmt::Display for Fork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Commit sequences diverge at position {}: local {:?}, remote {:?} (after {:?})",
+            self.position, self.local, self.remote, self.common
+        )
+    }
+}
+
+/// Compares the local commit sequence with the one of another node (eg. a peer, or another node of a test
+/// harness), as both grow. The sequences are compared from the first commit they have in common: either may be
+/// ahead of the other, and only the last `capacity` commits of each are kept. All honest authorities commit the
+/// same sequence, so any divergence is a safety violation.
+pub struct ForkDetector {
+    /// The number of commits of each sequence to keep.
+    capacity: usize,
+    /// The last local commits.
+    local: VecDeque<Digest>,
+    /// The position of the first commit of `local`.
+    start: u64,
+    /// The position of each commit of `local`.
+    positions: HashMap<Digest, u64>,
+    /// The remote commits not yet compared (ahead of the local sequence, or not yet aligned with it).
+    remote: VecDeque<Digest>,
+    /// The position (in the local sequence) of the first commit of `remote`, once the sequences are aligned.
+    aligned: Option<u64>,
+    /// The fork detected (if any); the sequences are no longer compared afterwards.
+    fork: Option<Fork>,
+}
+
+impl Default for ForkDetector {
+    fn default() -> Self {
+        Self::new(HISTORY_SIZE)
+    }
+}
+
+impl ForkDetector {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            local: VecDeque::new(),
+            start: 0,
+            positions: HashMap::new(),
+            remote: VecDeque::new(),
+            aligned: None,
+            fork: None,
+        }
+    }
+
+    /// Returns the fork detected (if any).
+    pub fn fork(&self) -> Option<&Fork> {
+        self.fork.as_ref()
+    }
+
+    /// Append a commit to the local sequence.
+    pub fn local(&mut self, digest: Digest) -> Result<(), Fork> {
+        let position = self.start + self.local.len() as u64;
+        self.positions.insert(digest.clone(), position);
+        self.local.push_back(digest);
+        if self.local.len() > self.capacity {
+            let oldest = self.local.pop_front().unwrap();
+            self.positions.remove(&oldest);
+            self.start += 1;
+        }
+        self.compare()
+    }
+
+    /// Append a commit to the remote sequence.
+    pub fn remote(&mut self, digest: Digest) -> Result<(), Fork> {
+        self.remote.push_back(digest);
+        if self.remote.len() > self.capacity {
+            // The remote sequence is too far ahead: skip its oldest commits.
+            self.remote.pop_front();
+            self.aligned = self.aligned.map(|x| x + 1);
+        }
+        self.compare()
+    }
+
+    /// Forget the remote sequence (eg. after reconnecting to the peer, which may have restarted in between).
+    pub fn reset_remote(&mut self) {
+        self.remote.clear();
+        self.aligned = None;
+    }
+
+    /// Returns the local commit at the specified position (if we still have it).
+    fn get(&self, position: u64) -> Option<&Digest> {
+        position
+            .checked_sub(self.start)
+            .and_then(|x| self.local.get(x as usize))
+    }
+
+    /// Compare the remote commits with the local ones at the same position.
+    fn compare(&mut self) -> Result<(), Fork> {
+        if self.fork.is_some() {
+            return Ok(());
+        }
+
+        // Align the sequences on the first remote commit we also have: the earlier ones are older than our
+        // history, except the one right before it which must match ours (if we have it).
+        let mut position = match self.aligned {
+            Some(position) => position,
+            None => {
+                let found = self
+                    .remote
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, x)| self.positions.get(x).map(|p| (i, *p)));
+                let (index, position) = match found {
+                    Some(x) => x,
+                    None => return Ok(()),
+                };
+                let previous = (index > 0 && position > self.start) as usize;
+                self.remote.drain(..index - previous);
+                position - previous as u64
+            }
+        };
+
+        // Compare the commits both sequences have.
+        while let Some(digest) = self.remote.front() {
+            if position >= self.start + self.local.len() as u64 {
+                break;
+            }
+            match self.get(position) {
+                Some(x) if x != digest => {
+                    let fork = self.report(position);
+                    self.fork = Some(fork.clone());
+                    return Err(fork);
+                }
+                // The local commits older than our history are no longer compared.
+                _ => {
+                    self.remote.pop_front();
+                    position += 1;
+                }
+            }
+        }
+        self.aligned = Some(position);
+        Ok(())
+    }
+
+    /// Returns the fork at the specified position (with both histories).
+    fn report(&self, position: u64) -> Fork {
+        let offset = (position - self.start) as usize;
+        Fork {
+            position,
+            common: self
+                .local
+                .range(offset.saturating_sub(REPORT_SIZE)..offset)
+                .cloned()
+                .collect(),
+            local: self
+                .local
+                .range(offset..)
+                .take(REPORT_SIZE)
+                .cloned()
+                .collect(),
+            remote: self.remote.iter().take(REPORT_SIZE).cloned().collect(),
+        }
+    }
+}
+
+/// Subscribes to the commit streams of other primaries (see `commit_stream_address`) and compares their commit
+/// sequences with ours, to detect safety violations of consensus (eg. under attack, or after a bug in the commit
+/// rule). A fork is logged (as JSON) with both histories.
+pub struct ForkMonitor;
+
+impl ForkMonitor {
+    pub fn spawn(peers: Vec<SocketAddr>, commit_events: &CommitEventBus) {
+        let mut rx_commits = commit_events.subscribe();
+        let (tx_remote, mut rx_remote) = channel(HISTORY_SIZE);
+        for (index, address) in peers.iter().enumerate() {
+            Self::subscribe(index, *address, tx_remote.clone());
+        }
+
+        tokio::spawn(async move {
+            let mut detectors: Vec<_> = peers.iter().map(|_| ForkDetector::default()).collect();
+            loop {
+                tokio::select! {
+                    result = rx_commits.recv() => match result {
+                        Ok(event) => {
+                            for (index, detector) in detectors.iter_mut().enumerate() {
+                                if let Err(fork) = detector.local(event.certificate.clone()) {
+                                    Self::report(&peers[index], &fork);
+                                }
+                            }
+                        }
+                        Err(RecvError::Lagged(missed)) => {
+                            // Our own sequence now has a gap: start over.
+                            warn!("Fork monitor missed {} local commits", missed);
+                            detectors = peers.iter().map(|_| ForkDetector::default()).collect();
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    Some((index, commit)) = rx_remote.recv() => match commit {
+                        Some(digest) => {
+                            if let Err(fork) = detectors[index].remote(digest) {
+                                Self::report(&peers[index], &fork);
+                            }
+                        }
+                        None => detectors[index].reset_remote(),
+                    },
+                }
+            }
+        });
+    }
+
+    /// Log a fork with a peer (loudly).
+    fn report(peer: &SocketAddr, fork: &Fork) {
+        let json = serde_json::to_string(fork).expect("Failed to serialize fork");
+        error!("SAFETY VIOLATION: {} with {}: {}", fork, peer, json);
+    }
+
+    /// Forward the commits streamed by a peer (reconnecting as needed). A `None` commit marks a new connection.
+    fn subscribe(index: usize, address: SocketAddr, tx_remote: Sender<(usize, Option<Digest>)>) {
+        tokio::spawn(async move {
+            loop {
+                match TcpStream::connect(address).await {
+                    Ok(stream) => {
+                        info!("Checking the commits of {} for forks", address);
+                        let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+                        let subscription = CommitSubscription { batches: false };
+                        let bytes = bincode::serialize(&subscription)
+                            .expect("Failed to serialize subscription");
+                        if tx_remote.send((index, None)).await.is_err() {
+                            return;
+                        }
+                        if transport.send(Bytes::from(bytes)).await.is_ok() {
+                            while let Some(Ok(bytes)) = transport.next().await {
+                                let commit: CommittedCertificate =
+                                    match bincode::deserialize(&bytes) {
+                                        Ok(x) => x,
+                                        Err(e) => {
+                                            warn!("Invalid commit from {}: {}", address, e);
+                                            break;
+                                        }
+                                    };
+                                let digest = commit.certificate.digest();
+                                if tx_remote.send((index, Some(digest))).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        debug!("Lost the commit stream of {}", address);
+                    }
+                    Err(e) => debug!(
+                        "Failed to connect to the commit stream of {}: {}",
+                        address, e
+                    ),
+                }
+                sleep(Duration::from_millis(RECONNECT_DELAY)).await;
+            }
+        });
+    }
+}
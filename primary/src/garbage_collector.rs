@@ -1,71 +1,356 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::messages::Certificate;
-use crate::primary::PrimaryWorkerMessage;
+use crate::error::DagResult;
+use crate::messages::{Certificate, CommittedSubDag};
+use crate::primary::{PrimaryWorkerMessage, Round};
+use crate::proposer::ProposerMessage;
+use crate::round_index::RoundIndex;
 use bytes::Bytes;
-use config::Committee;
-use crypto::PublicKey;
-use network::SimpleSender;
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use config::{Committee, Parameters, Stake, WorkerId};
+use crypto::{Digest, PublicKey};
+use futures::future::join_all;
+use log::{info, warn};
+use network::{Address, DatagramSender, NetworkMetrics, ReliableSender, SharedTransport};
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
+use store::Store;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{oneshot, watch};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/garbage_collector_tests.rs"]
+pub mod garbage_collector_tests;
+
+/// How long to wait for our workers to acknowledge an epoch change before giving up on them. Denominated in ms.
+const RECONFIGURATION_TIMEOUT: u64 = 5_000;
 
 /// Receives the highest round reached by consensus and update it for all tasks.
 pub struct GarbageCollector {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// The index of the stored certificates by round (used to prune the storage).
+    round_index: RoundIndex,
     /// The current consensus round (used for cleanup).
     consensus_round: Arc<AtomicU64>,
-    /// Receives the ordered certificates from consensus.
-    rx_consensus: Receiver<Certificate>,
+    /// The number of rounds kept in storage below the consensus round (0 to keep everything).
+    retention_depth: Round,
+    /// The number of committed rounds after which our workers are notified to clean up.
+    cleanup_rounds: Round,
+    /// The maximum delay between a committed round and the cleanup of our workers (0 to disable).
+    cleanup_interval: u64,
+    /// Whether we hold the committee of the next epoch (read by the `Proposer`).
+    ready: Arc<AtomicBool>,
+    /// Receives the committed sub-dags from consensus.
+    rx_consensus: Receiver<CommittedSubDag>,
+    /// Receives the committee of the next epoch (once the operator provides it).
+    rx_next_committee: Receiver<Committee>,
+    /// Receives the parameters reloaded while we run (we apply the new cleanup interval).
+    rx_parameters: watch::Receiver<Parameters>,
+    /// Notifies the `Proposer` of the rounds of our headers that have been sequenced (and of the end of the
+    /// epoch).
+    tx_sequenced: Sender<ProposerMessage>,
+    /// Outputs the committee of the next epoch once the epoch changes.
+    tx_reconfigure: Sender<Committee>,
     /// The network addresses of our workers.
-    addresses: Vec<SocketAddr>,
+    addresses: Vec<Address>,
+    /// The network addresses of our workers, indexed by worker id.
+    workers: HashMap<WorkerId, Address>,
     /// A network sender to notify our workers of cleanup events.
-    network: SimpleSender,
+    network: DatagramSender,
+    /// A network sender to notify our workers of epoch changes.
+    reliable_network: ReliableSender,
+    /// The committee of the next epoch (if we received it).
+    next_committee: Option<Committee>,
+    /// The authorities that committed a header signaling they are ready to switch epoch.
+    ready_authorities: HashSet<PublicKey>,
+    /// Whether the epoch ended (consensus outputs nothing after the sub-dag completing the quorum of ready
+    /// authorities).
+    ended: bool,
+    /// Whether we moved to the next epoch.
+    reconfigured: bool,
+    /// The last round pruned from storage.
+    pruned_round: Round,
+    /// The last round our workers were notified of.
+    cleanup_round: Round,
 }
 
 impl GarbageCollector {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: &PublicKey,
         committee: &Committee,
+        store: Store,
         consensus_round: Arc<AtomicU64>,
-        rx_consensus: Receiver<Certificate>,
+        gc_depth: Round,
+        retention_depth: Round,
+        cleanup_rounds: Round,
+        cleanup_interval: u64,
+        ready: Arc<AtomicBool>,
+        max_datagram_size: usize,
+        transport: SharedTransport,
+        network_metrics: NetworkMetrics,
+        rx_consensus: Receiver<CommittedSubDag>,
+        rx_next_committee: Receiver<Committee>,
+        rx_parameters: watch::Receiver<Parameters>,
+        tx_sequenced: Sender<ProposerMessage>,
+        tx_reconfigure: Sender<Committee>,
     ) {
-        let addresses = committee
-            .our_workers(name)
-            .expect("Our public key or worker id is not in the committee")
+        let workers: HashMap<_, _> = committee
+            .authorities
+            .get(name)
+            .expect("Our public key is not in the committee")
+            .workers
             .iter()
-            .map(|x| x.primary_to_worker)
+            .map(|(id, x)| (*id, x.primary_to_worker.advertise.clone()))
             .collect();
+        let addresses = workers.values().cloned().collect();
+
+        // We never prune rounds that other tasks may still need.
+        let retention_depth = match retention_depth {
+            0 => 0,
+            depth => max(depth, gc_depth),
+        };
 
+        let name = *name;
+        let committee = committee.clone();
         tokio::spawn(async move {
             Self {
+                name,
+                round_index: RoundIndex::new(store.clone(), committee.epoch),
+                committee,
+                store,
                 consensus_round,
+                retention_depth,
+                cleanup_rounds: max(cleanup_rounds, 1),
+                cleanup_interval,
+                ready,
                 rx_consensus,
+                rx_next_committee,
+                rx_parameters,
+                tx_sequenced,
+                tx_reconfigure,
                 addresses,
-                network: SimpleSender::new(),
+                workers,
+                network: DatagramSender::new(max_datagram_size)
+                    .with_transport(transport.clone())
+                    .with_metrics(network_metrics.clone()),
+                reliable_network: ReliableSender::new()
+                    .with_transport(transport)
+                    .with_metrics(network_metrics),
+                next_committee: None,
+                ready_authorities: HashSet::new(),
+                ended: false,
+                reconfigured: false,
+                pruned_round: 0,
+                cleanup_round: 0,
             }
             .run()
             .await;
         });
     }
 
+    /// Returns the stake of the authorities ready to switch epoch.
+    fn ready_stake(&self) -> Stake {
+        self.ready_authorities
+            .iter()
+            .map(|name| self.committee.stake(name))
+            .sum()
+    }
+
+    /// Move to the next epoch: let our workers know (and wait for them to acknowledge it), let the `Proposer`
+    /// carry over the payload that was not sequenced, and hand over the new committee so that the node restarts
+    /// the primary with it.
+    async fn reconfigure(&mut self, committee: Committee) {
+        info!("Moving to epoch {}", committee.epoch);
+        let message = PrimaryWorkerMessage::Reconfigure(committee.epoch);
+        let bytes = bincode::serialize(&message).expect("Failed to serialize our own message");
+        let handlers = self
+            .reliable_network
+            .broadcast(self.addresses.clone(), Bytes::from(bytes))
+            .await;
+        let delay = Duration::from_millis(RECONFIGURATION_TIMEOUT);
+        if timeout(delay, join_all(handlers)).await.is_err() {
+            warn!("Some of our workers did not acknowledge the epoch change");
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        self.tx_sequenced
+            .send(ProposerMessage::EndOfEpoch(sender))
+            .await
+            .expect("Failed to send the end of the epoch");
+        let _ = receiver.await;
+
+        self.reconfigured = true;
+        self.tx_reconfigure
+            .send(committee)
+            .await
+            .expect("Failed to send the next committee");
+    }
+
+    /// Send to each of our workers the batches of the certificate it handles (wrapped by `message`).
+    async fn notify_workers<F>(&mut self, certificate: &Certificate, message: F)
+    where
+        F: Fn(Vec<Digest>) -> PrimaryWorkerMessage,
+    {
+        let mut digests = HashMap::<_, Vec<_>>::new();
+        for (digest, worker_id) in &certificate.header.payload {
+            digests.entry(*worker_id).or_default().push(digest.clone());
+        }
+        for (worker_id, digests) in digests {
+            let address = match self.workers.get(&worker_id) {
+                Some(address) => address.clone(),
+                None => continue,
+            };
+            let bytes =
+                bincode::serialize(&message(digests)).expect("Failed to serialize our own message");
+            self.network.send(address, Bytes::from(bytes)).await;
+        }
+    }
+
+    /// Let our workers know that the batches of our sequenced header are safe, so that they stop tracking them.
+    async fn acknowledge(&mut self, certificate: &Certificate) {
+        self.notify_workers(certificate, PrimaryWorkerMessage::Sequenced)
+            .await;
+    }
+
+    /// Delete from storage the certificates (along with their headers and payload markers) of the rounds
+    /// that fell out of the retention window.
+    async fn prune(&mut self, consensus_round: Round) -> DagResult<()> {
+        if self.retention_depth == 0 || consensus_round <= self.retention_depth {
+            return Ok(());
+        }
+        let prune_round = consensus_round - self.retention_depth;
+        while self.pruned_round + 1 < prune_round {
+            let round = self.pruned_round + 1;
+            for digest in self.round_index.read(round).await? {
+                if let Some(bytes) = self.store.read(digest.to_vec()).await? {
+                    let certificate: Certificate = bincode::deserialize(&bytes)?;
+                    for (digest, worker_id) in &certificate.header.payload {
+                        let key = [digest.as_ref(), &worker_id.to_le_bytes()].concat();
+                        self.store.delete(key).await;
+                    }
+                    self.store.delete(certificate.header.id.to_vec()).await;
+                }
+                self.store.delete(digest.to_vec()).await;
+            }
+            self.round_index.remove(round).await?;
+            self.pruned_round = round;
+        }
+        Ok(())
+    }
+
+    /// Notify our workers that consensus reached the specified round, so that they clean up their state.
+    async fn cleanup(&mut self, round: Round) {
+        let bytes = bincode::serialize(&PrimaryWorkerMessage::Cleanup(round))
+            .expect("Failed to serialize our own message");
+        self.network
+            .broadcast(self.addresses.clone(), Bytes::from(bytes))
+            .await;
+        self.cleanup_round = round;
+    }
+
     async fn run(&mut self) {
         let mut last_committed_round = 0;
-        while let Some(certificate) = self.rx_consensus.recv().await {
-            // TODO [issue #9]: Re-include batch digests that have not been sequenced into our next block.
-
-            let round = certificate.round();
-            if round > last_committed_round {
-                last_committed_round = round;
-
-                // Trigger cleanup on the primary.
-                self.consensus_round.store(round, Ordering::Relaxed);
-
-                // Trigger cleanup on the workers..
-                let bytes = bincode::serialize(&PrimaryWorkerMessage::Cleanup(round))
-                    .expect("Failed to serialize our own message");
-                self.network
-                    .broadcast(self.addresses.clone(), Bytes::from(bytes))
-                    .await;
+
+        // The timer bounding the delay between a committed round and the cleanup of our workers.
+        let timer = sleep(Duration::from_millis(self.cleanup_interval));
+        tokio::pin!(timer);
+
+        loop {
+            tokio::select! {
+                Some(committee) = self.rx_next_committee.recv() => {
+                    if committee.epoch != self.committee.epoch + 1 {
+                        warn!("Ignoring committee of epoch {}", committee.epoch);
+                        continue;
+                    }
+                    self.next_committee = Some(committee);
+                    self.ready.store(true, Ordering::Relaxed);
+                },
+
+                Some(sub_dag) = self.rx_consensus.recv(), if !self.ended => {
+                    for certificate in &sub_dag.certificates {
+                        // Keep track of the authorities ready to switch epoch.
+                        if certificate.header.reconfigure {
+                            self.ready_authorities.insert(certificate.origin());
+                        }
+
+                        // Let the proposer (and our workers) know that the payload of our header is sequenced. The
+                        // payload of our headers that never get sequenced is re-included into our next header.
+                        if certificate.origin() == self.name {
+                            self.tx_sequenced
+                                .send(ProposerMessage::Sequenced(certificate.header.round))
+                                .await
+                                .expect("Failed to send sequenced round");
+                            self.acknowledge(certificate).await;
+                        }
+
+                        // Let our workers release their decryption shares of the committed batches (if the clients
+                        // encrypt their transactions to the committee) and skip the transactions they hold (if
+                        // they deduplicate across authorities).
+                        let covering = self.rx_parameters.borrow().covered_transactions_size > 0;
+                        if self.committee.threshold_key.is_some() || covering {
+                            self.notify_workers(certificate, PrimaryWorkerMessage::Committed)
+                                .await;
+                        }
+                    }
+
+                    // Clean up once per commit, up to the round of its leader.
+                    let round = sub_dag.round();
+                    if round > last_committed_round {
+                        last_committed_round = round;
+
+                        // Trigger cleanup on the primary.
+                        self.consensus_round.store(round, Ordering::Relaxed);
+
+                        // Trigger cleanup on the workers (once enough rounds are committed).
+                        if round >= self.cleanup_round + self.cleanup_rounds {
+                            self.cleanup(round).await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(self.cleanup_interval));
+                        }
+
+                        // Prune the rounds that fell out of the retention window from storage.
+                        if let Err(e) = self.prune(round).await {
+                            warn!("Failed to prune storage: {}", e);
+                        }
+                    }
+
+                    // The epoch ends with the sub-dag completing a quorum of ready authorities, at the same point
+                    // of the sequence for all honest nodes (consensus commits nothing after it).
+                    if self.ready_stake() >= self.committee.quorum_threshold() {
+                        self.ended = true;
+                        if self.next_committee.is_none() {
+                            let epoch = self.committee.epoch;
+                            warn!("Epoch {} ended: waiting for the committee of the next epoch", epoch);
+                        }
+                    }
+                },
+
+                // Trigger cleanup on the workers if some committed rounds are pending for too long.
+                () = &mut timer, if self.cleanup_interval > 0 && last_committed_round > self.cleanup_round => {
+                    self.cleanup(last_committed_round).await;
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(self.cleanup_interval));
+                },
+
+                Ok(()) = self.rx_parameters.changed() => {
+                    self.cleanup_interval = self.rx_parameters.borrow().cleanup_interval;
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(self.cleanup_interval));
+                },
+
+                else => break
+            }
+
+            // Switch epoch once it ended and we received the next committee ourselves (until then we stop at the
+            // end of the epoch).
+            if self.ended && !self.reconfigured {
+                if let Some(committee) = self.next_committee.take() {
+                    self.reconfigure(committee).await;
+                }
             }
         }
     }
@@ -0,0 +1,49 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::messages::Header;
+
+#[cfg(test)]
+#[path = "tests/header_validator_tests.rs"]
+pub mod header_validator_tests;
+
+/// Custom rules the headers of other primaries must follow for us to vote for them, on top of the rules of
+/// the protocol (eg. to bound their payload or to enforce application-specific constraints). The `Core` checks
+/// each header right before voting for it. Headers breaking the rules are not voted for, but their certificates
+/// are still accepted (since a quorum of authorities certified them).
+pub trait HeaderValidator: Send + 'static {
+    /// Returns why the header breaks the rules (if it does).
+    fn validate(&mut self, header: &Header) -> Result<(), String>;
+}
+
+/// Accepts all headers.
+pub struct AcceptAllHeaders;
+
+impl HeaderValidator for AcceptAllHeaders {
+    fn validate(&mut self, _header: &Header) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Rejects the headers referencing more than a maximum number of batches.
+pub struct PayloadLimit {
+    /// The maximum number of batches' digests of a header.
+    pub max_batches: usize,
+}
+
+impl HeaderValidator for PayloadLimit {
+    fn validate(&mut self, header: &Header) -> Result<(), String> {
+        match header.payload.len() {
+            x if x > self.max_batches => Err(format!(
+                "{} batches exceed the limit of {}",
+                x, self.max_batches
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A header must follow the rules of all the validators of the list (checked in order).
+impl HeaderValidator for Vec<Box<dyn HeaderValidator>> {
+    fn validate(&mut self, header: &Header) -> Result<(), String> {
+        self.iter_mut().try_for_each(|x| x.validate(header))
+    }
+}
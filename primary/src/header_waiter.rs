@@ -3,19 +3,20 @@ use crate::error::{DagError, DagResult};
 use crate::messages::Header;
 use crate::primary::{PrimaryMessage, PrimaryWorkerMessage, Round};
 use bytes::Bytes;
-use config::{Committee, WorkerId};
+use config::{Committee, Parameters, WorkerId};
 use crypto::{Digest, PublicKey};
 use futures::future::try_join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::{debug, error};
-use network::SimpleSender;
+use network::{NetworkMetrics, SharedTransport, SimpleSender};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use store::Store;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
 use tokio::time::{sleep, Duration, Instant};
 
 /// The resolution of the timer that checks whether we received replies to our sync requests, and triggers
@@ -48,6 +49,8 @@ pub struct HeaderWaiter {
 
     /// Receives sync commands from the `Synchronizer`.
     rx_synchronizer: Receiver<WaiterMessage>,
+    /// Receives the parameters reloaded while we run (we apply the new sync retry delay).
+    rx_parameters: watch::Receiver<Parameters>,
     /// Loops back to the core headers for which we got all parents and batches.
     tx_core: Sender<Header>,
 
@@ -74,7 +77,10 @@ impl HeaderWaiter {
         gc_depth: Round,
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
+        transport: SharedTransport,
+        network_metrics: NetworkMetrics,
         rx_synchronizer: Receiver<WaiterMessage>,
+        rx_parameters: watch::Receiver<Parameters>,
         tx_core: Sender<Header>,
     ) {
         tokio::spawn(async move {
@@ -87,8 +93,11 @@ impl HeaderWaiter {
                 sync_retry_delay,
                 sync_retry_nodes,
                 rx_synchronizer,
+                rx_parameters,
                 tx_core,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_transport(transport)
+                    .with_metrics(network_metrics),
                 parent_requests: HashMap::new(),
                 batch_requests: HashMap::new(),
                 pending: HashMap::new(),
@@ -165,7 +174,8 @@ impl HeaderWaiter {
                                 let address = self.committee
                                     .worker(&author, &worker_id)
                                     .expect("Author of valid header is not in the committee")
-                                    .primary_to_worker;
+                                    .primary_to_worker
+                                    .advertise;
                                 let message = PrimaryWorkerMessage::Synchronize(digests, author);
                                 let bytes = bincode::serialize(&message)
                                     .expect("Failed to serialize batch sync request");
@@ -214,7 +224,8 @@ impl HeaderWaiter {
                                 let address = self.committee
                                     .primary(&author)
                                     .expect("Author of valid header not in the committee")
-                                    .primary_to_primary;
+                                    .primary_to_primary
+                                    .advertise;
                                 let message = PrimaryMessage::CertificatesRequest(requires_sync, self.name);
                                 let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
                                 self.network.send(address, Bytes::from(bytes)).await;
@@ -243,6 +254,10 @@ impl HeaderWaiter {
                     }
                 },
 
+                Ok(()) = self.rx_parameters.changed() => {
+                    self.sync_retry_delay = self.rx_parameters.borrow().sync_retry_delay;
+                },
+
                 () = &mut timer => {
                     // We optimistically sent sync requests to a single node. If this timer triggers,
                     // it means we were wrong to trust it. We are done waiting for a reply and we now
@@ -263,7 +278,7 @@ impl HeaderWaiter {
                     let addresses = self.committee
                         .others_primaries(&self.name)
                         .iter()
-                        .map(|(_, x)| x.primary_to_primary)
+                        .map(|(_, x)| x.primary_to_primary.advertise.clone())
                         .collect();
                     let message = PrimaryMessage::CertificatesRequest(retry, self.name);
                     let bytes = bincode::serialize(&message).expect("Failed to serialize cert request");
@@ -1,71 +1,291 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::primary::PrimaryMessage;
+use crate::certificate_fetcher::{CertificateRange, MAX_RANGE_REPLY_SIZE};
+use crate::error::DagResult;
+use crate::messages::Certificate;
+use crate::primary::{PrimaryMessage, Round};
+use crate::round_index::RoundIndex;
+use crate::state_synchronizer::{DagProgress, MAX_SYNC_RANGE};
 use bytes::Bytes;
 use config::Committee;
 use crypto::{Digest, PublicKey};
-use log::{error, warn};
-use network::SimpleSender;
+use log::{debug, error, warn};
+use network::{Address, NetworkMetrics, RateLimit, SharedTransport, SimpleSender, TokenBucket};
+use std::cmp::min;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
 
-/// A task dedicated to help other authorities by replying to their certificates requests.
+#[cfg(test)]
+#[path = "tests/helper_tests.rs"]
+pub mod helper_tests;
+
+/// The maximum number of queued requests the helper gathers (and deduplicates) at once.
+const MAX_PENDING_REQUESTS: usize = 1_000;
+
+/// The requests served by the `Helper`.
+#[derive(Debug)]
+pub enum HelperRequest {
+    /// Certificates requested by digest.
+    Certificates(Vec<Digest>, /* requestor */ PublicKey),
+    /// All the certificates of a range of rounds (inclusive). The (serialized) reply is sent back on the
+    /// channel, to be returned to the requestor in-band.
+    Range(
+        Round,
+        Round,
+        /* requestor */ PublicKey,
+        oneshot::Sender<Bytes>,
+    ),
+}
+
+/// A request gathered by the helper: identical requests of the same requestor are merged.
+enum PendingRequest {
+    /// Certificates requested by digest (without the ones already pending for this requestor).
+    Certificates(Vec<Digest>, PublicKey),
+    /// A range of rounds, along with the channels of every identical request.
+    Range(Round, Round, PublicKey, Vec<oneshot::Sender<Bytes>>),
+}
+
+impl PendingRequest {
+    fn origin(&self) -> PublicKey {
+        match self {
+            Self::Certificates(_, origin) => *origin,
+            Self::Range(_, _, origin, _) => *origin,
+        }
+    }
+}
+
+/// A task dedicated to help other authorities by replying to their certificates requests. Identical
+/// requests waiting in the queue are served once, and each requestor is served at most at `rate_limit`.
+/// The requests of the authorities excluded for misbehavior are served last.
 pub struct Helper {
     /// The committee information.
     committee: Committee,
     /// The persistent storage.
     store: Store,
+    /// The index of the certificates by round.
+    round_index: RoundIndex,
     /// Input channel to receive certificates requests.
-    rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+    rx_primaries: Receiver<HelperRequest>,
     /// A network sender to reply to the sync requests.
     network: SimpleSender,
+    /// The rate at which we serve each requestor (if any).
+    rate_limit: Option<RateLimit>,
+    /// The token bucket of each requestor.
+    buckets: HashMap<PublicKey, TokenBucket>,
+    /// The progress of our local DAG (holding the authorities excluded for misbehavior).
+    progress: Arc<DagProgress>,
 }
 
 impl Helper {
     pub fn spawn(
         committee: Committee,
         store: Store,
-        rx_primaries: Receiver<(Vec<Digest>, PublicKey)>,
+        transport: SharedTransport,
+        network_metrics: NetworkMetrics,
+        rate_limit: Option<RateLimit>,
+        progress: Arc<DagProgress>,
+        rx_primaries: Receiver<HelperRequest>,
     ) {
         tokio::spawn(async move {
             Self {
+                round_index: RoundIndex::new(store.clone(), committee.epoch),
                 committee,
                 store,
                 rx_primaries,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_transport(transport)
+                    .with_metrics(network_metrics),
+                rate_limit,
+                buckets: HashMap::new(),
+                progress,
             }
             .run()
             .await;
         });
     }
 
-    async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_primaries.recv().await {
-            // TODO [issue #195]: Do some accounting to prevent bad nodes from monopolizing our resources.
-
-            // get the requestors address.
-            let address = match self.committee.primary(&origin) {
-                Ok(x) => x.primary_to_primary,
-                Err(e) => {
-                    warn!("Unexpected certificate request: {}", e);
-                    continue;
+    /// Returns whether we may serve the requestor (ie. it did not exceed its rate limit).
+    fn admit(&mut self, origin: &PublicKey) -> bool {
+        match self.rate_limit {
+            Some(limit) => self
+                .buckets
+                .entry(*origin)
+                .or_insert_with(|| TokenBucket::new(limit))
+                .has_tokens(),
+            None => true,
+        }
+    }
+
+    /// Charge the requestor for the bytes we sent it.
+    fn charge(&mut self, origin: &PublicKey, bytes: usize) {
+        if let Some(bucket) = self.buckets.get_mut(origin) {
+            bucket.consume(bytes);
+        }
+    }
+
+    /// Gather the requests waiting in the queue (starting with `first`), merging the identical ones: the
+    /// digests a requestor already asked for are dropped and identical ranges get a single reply. The requests
+    /// of excluded authorities are moved to the back.
+    fn gather(&mut self, first: HelperRequest) -> Vec<PendingRequest> {
+        let mut requests = vec![first];
+        while requests.len() < MAX_PENDING_REQUESTS {
+            match self.rx_primaries.try_recv() {
+                Ok(request) => requests.push(request),
+                Err(_) => break,
+            }
+        }
+
+        let mut pending = Vec::new();
+        let mut digests = HashSet::new();
+        let mut ranges = HashMap::new();
+        for request in requests {
+            match request {
+                HelperRequest::Certificates(requested, origin) => {
+                    let requested: Vec<_> = requested
+                        .into_iter()
+                        .filter(|x| digests.insert((origin, x.clone())))
+                        .collect();
+                    if !requested.is_empty() {
+                        pending.push(PendingRequest::Certificates(requested, origin));
+                    }
                 }
-            };
-
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => {
-                        // TODO: Remove this deserialization-serialization in the critical path.
-                        let certificate = bincode::deserialize(&data)
-                            .expect("Failed to deserialize our own certificate");
-                        let bytes = bincode::serialize(&PrimaryMessage::Certificate(certificate))
-                            .expect("Failed to serialize our own certificate");
-                        self.network.send(address, Bytes::from(bytes)).await;
+                HelperRequest::Range(start, end, origin, sender) => {
+                    match ranges.get(&(start, end, origin)) {
+                        Some(i) => {
+                            if let PendingRequest::Range(_, _, _, senders) = &mut pending[*i] {
+                                senders.push(sender);
+                            }
+                        }
+                        None => {
+                            ranges.insert((start, end, origin), pending.len());
+                            pending.push(PendingRequest::Range(start, end, origin, vec![sender]));
+                        }
                     }
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
                 }
             }
         }
+
+        let excluded = self.progress.excluded.lock().unwrap();
+        pending.sort_by_key(|x| excluded.contains_key(&x.origin()));
+        drop(excluded);
+        pending
+    }
+
+    /// Send a certificate from the store to the requestor (if we have it). Returns the number of bytes sent.
+    async fn reply(&mut self, address: &Address, digest: Digest) -> DagResult<usize> {
+        let mut size = 0;
+        if let Some(data) = self.store.read(digest.to_vec()).await? {
+            // TODO: Remove this deserialization-serialization in the critical path.
+            let certificate =
+                bincode::deserialize(&data).expect("Failed to deserialize our own certificate");
+            let bytes = bincode::serialize(&PrimaryMessage::SyncCertificate(certificate))
+                .expect("Failed to serialize our own certificate");
+            size = bytes.len();
+            self.network.send(address.clone(), Bytes::from(bytes)).await;
+        }
+        Ok(size)
+    }
+
+    async fn run(&mut self) {
+        while let Some(request) = self.rx_primaries.recv().await {
+            for request in self.gather(request) {
+                let origin = request.origin();
+
+                // get the requestors address.
+                let address = match self.committee.primary(&origin) {
+                    Ok(x) => x.primary_to_primary.advertise,
+                    Err(e) => {
+                        warn!("Unexpected certificate request: {}", e);
+                        continue;
+                    }
+                };
+
+                // Reply to the request (the best we can).
+                let result = match request {
+                    PendingRequest::Certificates(digests, _) => {
+                        self.reply_digests(&address, &origin, digests).await
+                    }
+                    PendingRequest::Range(start, end, _, senders) => {
+                        self.reply_range(start, end, &origin, senders).await
+                    }
+                };
+                if let Err(e) = result {
+                    error!("{}", e);
+                }
+            }
+        }
+    }
+
+    /// Reply to a request for certificates by digest (until the requestor exceeds its rate limit).
+    async fn reply_digests(
+        &mut self,
+        address: &Address,
+        origin: &PublicKey,
+        digests: Vec<Digest>,
+    ) -> DagResult<()> {
+        for digest in digests {
+            if !self.admit(origin) {
+                debug!("Rate limiting certificate requests of {}", origin);
+                break;
+            }
+            let size = self.reply(address, digest).await?;
+            self.charge(origin, size);
+        }
+        Ok(())
+    }
+
+    /// Reply to a request for a range of rounds, in round order (so that the requestor holds the parents of
+    /// each certificate by the time it receives it). The reply only holds whole rounds: it stops at the last
+    /// round we have, and once it reaches `MAX_RANGE_REPLY_SIZE` (but always holds at least one round).
+    /// Requests exceeding the rate limit of the requestor get no reply.
+    async fn reply_range(
+        &mut self,
+        start: Round,
+        end: Round,
+        origin: &PublicKey,
+        senders: Vec<oneshot::Sender<Bytes>>,
+    ) -> DagResult<()> {
+        if !self.admit(origin) {
+            debug!("Rate limiting range requests of {}", origin);
+            return Ok(());
+        }
+
+        let end = min(end, start.saturating_add(MAX_SYNC_RANGE - 1));
+        let end = min(end, self.round_index.last_round().await?);
+
+        let mut range = CertificateRange {
+            end: start.saturating_sub(1),
+            certificates: Vec::new(),
+        };
+        let mut size = 0;
+        for round in start..=end {
+            let mut certificates = Vec::new();
+            let mut round_size = 0;
+            for digest in self.round_index.read(round).await? {
+                if let Some(data) = self.store.read(digest.to_vec()).await? {
+                    round_size += data.len();
+                    let certificate: Certificate = bincode::deserialize(&data)
+                        .expect("Failed to deserialize our own certificate");
+                    certificates.push(certificate);
+                }
+            }
+            if round > start && size + round_size > MAX_RANGE_REPLY_SIZE {
+                break;
+            }
+            size += round_size;
+            range.end = round;
+            range.certificates.extend(certificates);
+        }
+
+        let bytes = Bytes::from(
+            bincode::serialize(&range).expect("Failed to serialize our own certificates"),
+        );
+        self.charge(origin, bytes.len());
+        for sender in senders {
+            let _ = sender.send(bytes.clone());
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,362 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::commit_events::{
+    CommitEvent, CommitEventBus, CommittedWave, ConsensusMemory, SubDagStructure,
+};
+use crate::commit_proof::CommitProof;
+use crate::messages::{Certificate, Header};
+use crate::metrics::{AuthorityQuality, DagShape, RoundLatency, WaveLatency};
+use crate::primary::Round;
+use crate::round_index::RoundIndex;
+use crate::snapshot::Vertex;
+use crate::state_synchronizer::DagProgress;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use config::{Epoch, WorkerId};
+use crypto::{Digest, PublicKey, SignatureMetrics};
+use log::{info, warn};
+use network::NetworkMetrics;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::Store;
+use tokio::sync::watch;
+
+#[cfg(test)]
+#[path = "tests/introspection_tests.rs"]
+pub mod introspection_tests;
+
+/// The votes gathered so far for our last header.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PendingVotes {
+    /// The digest of the header.
+    pub header: Digest,
+    /// The round of the header.
+    pub round: Round,
+    /// The authorities that voted for the header.
+    pub voters: Vec<PublicKey>,
+    /// Whether the votes already form a certificate.
+    pub certified: bool,
+}
+
+impl PendingVotes {
+    pub fn new(header: &Header) -> Self {
+        Self {
+            header: header.id.clone(),
+            round: header.round,
+            ..Self::default()
+        }
+    }
+}
+
+/// The state of the primary (returned by `GET /status`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// The public key of this primary.
+    pub name: PublicKey,
+    /// The current epoch.
+    pub epoch: Epoch,
+    /// The highest round for which we gathered a quorum of certificates.
+    pub round: Round,
+    /// The highest round of the certificates we received.
+    pub observed_round: Round,
+    /// The highest round committed by consensus.
+    pub last_committed_round: Round,
+    /// The rounds below this one are garbage collected.
+    pub gc_round: Round,
+    /// The votes gathered so far for our last header.
+    pub pending_votes: PendingVotes,
+    /// The number of batches' digests of each of our workers waiting to be included in our headers.
+    pub worker_backlog: BTreeMap<WorkerId, usize>,
+    /// The last certificate committed by consensus (if any).
+    pub last_commit: Option<CommitEvent>,
+    /// The last wave committed by consensus (if any), along with the leader that drove it.
+    #[serde(default)]
+    pub last_wave: Option<CommittedWave>,
+    /// The authorities excluded for misbehavior, along with the round of the first evidence against them.
+    pub excluded: BTreeMap<PublicKey, Round>,
+}
+
+/// The parameters of `GET /certificates` (at least one of them must be set).
+#[derive(Deserialize)]
+struct CertificatesQuery {
+    /// Only return the certificates of this round.
+    round: Option<Round>,
+    /// Only return the certificates of this authority (base64, percent-encoded).
+    author: Option<PublicKey>,
+}
+
+/// The parameters of `GET /commit_proof`.
+#[derive(Deserialize)]
+struct CommitProofQuery {
+    /// The round of the leader.
+    round: Round,
+}
+
+/// The state shared by the handlers of the API.
+#[derive(Clone)]
+struct ApiState {
+    name: PublicKey,
+    epoch: Epoch,
+    gc_depth: Round,
+    consensus_round: Arc<AtomicU64>,
+    progress: Arc<DagProgress>,
+    signature_metrics: Arc<SignatureMetrics>,
+    network_metrics: NetworkMetrics,
+    last_commit: watch::Receiver<Option<CommitEvent>>,
+    last_wave: watch::Receiver<Option<CommittedWave>>,
+    consensus_memory: watch::Receiver<ConsensusMemory>,
+    store: Store,
+}
+
+/// A read-only HTTP API exposing the state of the primary (as JSON), so that operators and test harnesses
+/// can observe the protocol without parsing logs:
+///   - `GET /status` returns the `Status` of the primary;
+///   - `GET /certificates?[round=<ROUND>][&author=<KEY>]` returns the stored certificates of a round and/or
+///     of an authority;
+///   - `GET /latencies` returns the latencies of the pipeline of the primary over the most recent rounds;
+///   - `GET /commit_latencies` returns the commit latencies of the certificates over the most recent waves;
+///   - `GET /commit_proof?round=<ROUND>` returns the `CommitProof` of the leader of a round (serialized with
+///     bincode), if it was committed directly;
+///   - `GET /chain_quality` returns the chain quality and inclusion fairness of every authority;
+///   - `GET /dag_shape` returns the shape of the DAG (its width over the most recent rounds, the committed and
+///     skipped leaders, and the orphaned certificates);
+///   - `GET /sub_dags` returns the structure of the most recent committed sub-dags (their certificates in commit
+///     order, along with their parent links);
+///   - `GET /metrics` returns the same latencies (summed over all rounds), along with the number of stalls
+///     of the DAG, the commit latencies, the number of skipped leaders, the chain quality, the shape of the
+///     DAG, the size of the committed sub-dags, the memory held by consensus, the latencies of the signature
+///     service and the connection counters of each peer, in the Prometheus text format.
+pub struct IntrospectionServer;
+
+impl IntrospectionServer {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        address: SocketAddr,
+        name: PublicKey,
+        epoch: Epoch,
+        gc_depth: Round,
+        consensus_round: Arc<AtomicU64>,
+        progress: Arc<DagProgress>,
+        signature_metrics: Arc<SignatureMetrics>,
+        network_metrics: NetworkMetrics,
+        commit_events: CommitEventBus,
+        store: Store,
+    ) {
+        let state = ApiState {
+            name,
+            epoch,
+            gc_depth,
+            consensus_round,
+            progress,
+            signature_metrics,
+            network_metrics,
+            last_commit: commit_events.watch(),
+            last_wave: commit_events.watch_wave(),
+            consensus_memory: commit_events.watch_memory(),
+            store,
+        };
+        let app = Router::new()
+            .route("/status", get(status))
+            .route("/certificates", get(certificates))
+            .route("/latencies", get(latencies))
+            .route("/commit_latencies", get(commit_latencies))
+            .route("/commit_proof", get(commit_proof))
+            .route("/chain_quality", get(chain_quality))
+            .route("/dag_shape", get(dag_shape))
+            .route("/sub_dags", get(sub_dags))
+            .route("/metrics", get(metrics))
+            .with_state(state);
+
+        tokio::spawn(async move {
+            let server = match axum::Server::try_bind(&address) {
+                Ok(server) => server,
+                Err(e) => {
+                    warn!("Failed to bind introspection API to {}: {}", address, e);
+                    return;
+                }
+            };
+            info!("Introspection API listening on {}", address);
+            if let Err(e) = server.serve(app.into_make_service()).await {
+                warn!("Introspection API failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn status(State(state): State<ApiState>) -> Json<Status> {
+    let last_committed_round = state.consensus_round.load(Ordering::Relaxed);
+    Json(Status {
+        name: state.name,
+        epoch: state.epoch,
+        round: state.progress.dag_round.load(Ordering::Relaxed),
+        observed_round: state.progress.observed_round.load(Ordering::Relaxed),
+        last_committed_round,
+        gc_round: last_committed_round.saturating_sub(state.gc_depth),
+        pending_votes: state.progress.pending_votes.lock().unwrap().clone(),
+        worker_backlog: state.progress.worker_backlog.lock().unwrap().clone(),
+        last_commit: state.last_commit.borrow().clone(),
+        last_wave: state.last_wave.borrow().clone(),
+        excluded: state.progress.excluded.lock().unwrap().clone(),
+    })
+}
+
+async fn certificates(
+    State(state): State<ApiState>,
+    Query(query): Query<CertificatesQuery>,
+) -> Result<Json<Vec<Vertex>>, (StatusCode, String)> {
+    let internal_error = |e: String| (StatusCode::INTERNAL_SERVER_ERROR, e);
+
+    let mut store = state.store.clone();
+    let mut round_index = RoundIndex::new(store.clone(), state.epoch);
+    let digests = match (query.round, query.author) {
+        (Some(round), None) => round_index.read(round).await,
+        (round, Some(author)) => {
+            let (start, end) = round.map_or((0, Round::MAX), |x| (x, x));
+            round_index
+                .read_author(&author, start, end)
+                .await
+                .map(|x| x.into_iter().map(|(_, digest)| digest).collect())
+        }
+        (None, None) => {
+            let message = "Specify a round and/or an author".to_string();
+            return Err((StatusCode::BAD_REQUEST, message));
+        }
+    }
+    .map_err(|e| internal_error(e.to_string()))?;
+
+    let mut vertices = Vec::new();
+    for digest in digests {
+        let bytes = match store.read(digest.to_vec()).await {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => continue,
+            Err(e) => return Err(internal_error(e.to_string())),
+        };
+        let certificate: Certificate =
+            bincode::deserialize(&bytes).map_err(|e| internal_error(e.to_string()))?;
+        vertices.push(Vertex::from(&certificate));
+    }
+    Ok(Json(vertices))
+}
+
+async fn latencies(State(state): State<ApiState>) -> Json<Vec<RoundLatency>> {
+    Json(state.progress.pipeline.rounds())
+}
+
+async fn commit_latencies(State(state): State<ApiState>) -> Json<Vec<WaveLatency>> {
+    Json(state.progress.commit_latency.waves())
+}
+
+async fn commit_proof(
+    State(state): State<ApiState>,
+    Query(query): Query<CommitProofQuery>,
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut store = state.store.clone();
+    match store.read(CommitProof::key(state.epoch, query.round)).await {
+        Ok(Some(bytes)) => Ok(bytes),
+        Ok(None) => {
+            let message = format!("No commit proof for round {}", query.round);
+            Err((StatusCode::NOT_FOUND, message))
+        }
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+async fn chain_quality(
+    State(state): State<ApiState>,
+) -> Json<BTreeMap<PublicKey, AuthorityQuality>> {
+    Json(state.progress.chain_quality.authorities())
+}
+
+async fn dag_shape(State(state): State<ApiState>) -> Json<DagShape> {
+    Json(state.progress.dag_shape.shape())
+}
+
+async fn sub_dags(State(state): State<ApiState>) -> Json<Vec<SubDagStructure>> {
+    Json(state.progress.sub_dags.sub_dags())
+}
+
+async fn metrics(State(state): State<ApiState>) -> String {
+    let mut output = state.progress.pipeline.encode();
+    output.push_str(&state.progress.commit_latency.encode());
+    output.push_str(&state.progress.chain_quality.encode());
+    output.push_str(&state.progress.dag_shape.encode());
+    output.push_str(&state.progress.sub_dags.encode());
+    let name = "primary_round_stalls_total";
+    let stalls = state.progress.stalls.load(Ordering::Relaxed);
+    let _ = writeln!(output, "# HELP {} Number of times the DAG stalled.", name);
+    let _ = writeln!(output, "# TYPE {} counter", name);
+    let _ = writeln!(output, "{} {}", name, stalls);
+
+    let name = "primary_leader_skips_total";
+    let skips = state.progress.leader_skips.load(Ordering::Relaxed);
+    let _ = writeln!(
+        output,
+        "# HELP {} Number of leaders consensus timed out waiting for.",
+        name
+    );
+    let _ = writeln!(output, "# TYPE {} counter", name);
+    let _ = writeln!(output, "{} {}", name, skips);
+
+    let memory = state.consensus_memory.borrow().clone();
+    let gauges = [
+        (
+            "consensus_dag_rounds",
+            "Number of rounds of the dag of consensus.",
+            memory.rounds,
+        ),
+        (
+            "consensus_dag_certificates",
+            "Number of certificates of the dag of consensus.",
+            memory.certificates,
+        ),
+        (
+            "consensus_dag_bytes",
+            "Approximate size of the certificates of the dag of consensus.",
+            memory.bytes,
+        ),
+    ];
+    for (name, help, value) in gauges.iter() {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} gauge", name);
+        let _ = writeln!(output, "{} {}", name, value);
+    }
+
+    let signatures = &state.signature_metrics;
+    let count = signatures.signatures.load(Ordering::Relaxed);
+    let stages = [
+        (
+            "primary_signature_queue_seconds",
+            "Time the signature requests waited for a signer.",
+            &signatures.queue_time,
+        ),
+        (
+            "primary_signature_seconds",
+            "Time spent signing.",
+            &signatures.sign_time,
+        ),
+    ];
+    for (name, help, sum) in stages.iter() {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} summary", name);
+        let sum = sum.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(output, "{}_sum {}", name, sum);
+        let _ = writeln!(output, "{}_count {}", name, count);
+    }
+    let name = "primary_signature_requests_pending";
+    let pending = signatures.pending.load(Ordering::Relaxed);
+    let _ = writeln!(
+        output,
+        "# HELP {} Number of signature requests waiting for a signer.",
+        name
+    );
+    let _ = writeln!(output, "# TYPE {} gauge", name);
+    let _ = writeln!(output, "{} {}", name, pending);
+
+    output.push_str(&state.network_metrics.encode());
+    output
+}
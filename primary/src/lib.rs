@@ -2,20 +2,51 @@
 #[macro_use]
 mod error;
 mod aggregators;
+mod certificate_fetcher;
+mod commit_events;
+mod commit_proof;
+mod commit_stream;
 mod certificate_waiter;
 mod core;
+mod erasure;
+mod evidence;
+mod fork_detector;
 mod garbage_collector;
+mod header_validator;
 mod header_waiter;
+mod introspection;
 mod helper;
 mod messages;
+mod metrics;
 mod payload_receiver;
 mod primary;
 mod proposer;
+mod recovery;
+mod round_index;
+mod snapshot;
+mod state_synchronizer;
 mod synchronizer;
+mod verifier;
+mod watchdog;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::messages::{Certificate, Header};
+pub use crate::commit_events::{
+    CommitEvent, CommitEventBus, CommittedVertex, CommittedWave, ConsensusMemory, LeaderSkip, LeaderSlot,
+    OrphanedCertificate, SubDagStructure,
+};
+pub use crate::commit_proof::CommitProof;
+pub use crate::commit_stream::{CommitSubscription, CommittedCertificate};
+pub use crate::evidence::{Equivocation, EvidenceStore};
+pub use crate::fork_detector::{Fork, ForkDetector};
+pub use crate::header_validator::{AcceptAllHeaders, HeaderValidator, PayloadLimit};
+pub use crate::introspection::{PendingVotes, Status};
+pub use crate::messages::{Certificate, CommittedSubDag, Header};
+pub use crate::metrics::{
+    AuthorityQuality, ChainQualityMetrics, CommitLatencyMetrics, DagShape, DagShapeMetrics,
+    PipelineMetrics, RoundLatency, SubDagMetrics, WaveLatency,
+};
 pub use crate::primary::{Primary, PrimaryWorkerMessage, Round, WorkerPrimaryMessage};
+pub use crate::snapshot::{DagSnapshot, SnapshotFormat, Vertex};
@@ -1,8 +1,8 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::error::{DagError, DagResult};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
-use crypto::{Digest, Hash, PublicKey, Signature, SignatureService};
+use config::{Committee, Epoch, WorkerId};
+use crypto::{BlsSignature, CoinShare, Digest, Hash, PublicKey, Signature, SignatureService};
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use serde::{Deserialize, Serialize};
@@ -10,29 +10,45 @@ use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::convert::TryInto;
 use std::fmt;
 
+#[cfg(test)]
+#[path = "tests/messages_tests.rs"]
+pub mod messages_tests;
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Header {
     pub author: PublicKey,
+    pub epoch: Epoch,
     pub round: Round,
     pub payload: BTreeMap<Digest, WorkerId>,
     pub parents: BTreeSet<Digest>,
+    /// Whether the author is ready to switch to the committee of the next epoch.
+    pub reconfigure: bool,
+    /// The share of the author of the random coin of the round (if consensus elects the leaders randomly).
+    pub coin: Option<CoinShare>,
     pub id: Digest,
     pub signature: Signature,
 }
 
 impl Header {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         author: PublicKey,
+        epoch: Epoch,
         round: Round,
         payload: BTreeMap<Digest, WorkerId>,
         parents: BTreeSet<Digest>,
+        reconfigure: bool,
+        coin: Option<CoinShare>,
         signature_service: &mut SignatureService,
     ) -> Self {
         let header = Self {
             author,
+            epoch,
             round,
             payload,
             parents,
+            reconfigure,
+            coin,
             id: Digest::default(),
             signature: Signature::default(),
         };
@@ -49,6 +65,12 @@ impl Header {
         // Ensure the header id is well formed.
         ensure!(self.digest() == self.id, DagError::InvalidHeaderId);
 
+        // Ensure the header belongs to the epoch of the committee.
+        ensure!(
+            self.epoch == committee.epoch,
+            DagError::InvalidEpoch(self.id.clone(), self.epoch)
+        );
+
         // Ensure the authority has voting rights.
         let voting_rights = committee.stake(&self.author);
         ensure!(voting_rights > 0, DagError::UnknownAuthority(self.author));
@@ -60,17 +82,36 @@ impl Header {
                 .map_err(|_| DagError::MalformedHeader(self.id.clone()))?;
         }
 
+        // Check the share of the random coin (if any): it must be the share of the author.
+        if let Some(share) = &self.coin {
+            let index = committee
+                .share_index(&self.author)
+                .ok_or_else(|| DagError::MalformedHeader(self.id.clone()))?;
+            committee
+                .threshold_key
+                .as_ref()
+                .ok_or_else(|| DagError::MalformedHeader(self.id.clone()))?
+                .verify_coin_share(&Self::coin_seed(self.epoch, self.round), index, share)
+                .map_err(|_| DagError::MalformedHeader(self.id.clone()))?;
+        }
+
         // Check the signature.
         self.signature
             .verify(&self.id, &self.author)
             .map_err(DagError::from)
     }
+
+    /// Returns the seed of the random coin of a round, revealed by the shares in the headers of the round.
+    pub fn coin_seed(epoch: Epoch, round: Round) -> Vec<u8> {
+        [b"coin:", &epoch.to_le_bytes()[..], &round.to_le_bytes()[..]].concat()
+    }
 }
 
 impl Hash for Header {
     fn digest(&self) -> Digest {
         let mut hasher = Sha512::new();
         hasher.update(&self.author);
+        hasher.update(self.epoch.to_le_bytes());
         hasher.update(self.round.to_le_bytes());
         for (x, y) in &self.payload {
             hasher.update(x);
@@ -79,6 +120,10 @@ impl Hash for Header {
         for x in &self.parents {
             hasher.update(x);
         }
+        hasher.update([self.reconfigure as u8]);
+        if let Some(coin) = &self.coin {
+            hasher.update(bincode::serialize(coin).expect("Failed to serialize coin share"));
+        }
         Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
     }
 }
@@ -109,6 +154,8 @@ pub struct Vote {
     pub origin: PublicKey,
     pub author: PublicKey,
     pub signature: Signature,
+    /// The BLS signature of the vote (if the author signs with BLS), aggregated into the certificate.
+    pub bls_signature: Option<BlsSignature>,
 }
 
 impl Vote {
@@ -123,22 +170,42 @@ impl Vote {
             origin: header.author,
             author: *author,
             signature: Signature::default(),
+            bls_signature: None,
         };
         let signature = signature_service.request_signature(vote.digest()).await;
-        Self { signature, ..vote }
+        let bls_signature = signature_service.request_bls_signature(vote.digest()).await;
+        Self {
+            signature,
+            bls_signature,
+            ..vote
+        }
     }
 
     pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        self.verify_authority(committee)?;
+
+        // Check the signature.
+        self.signature
+            .verify(&self.digest(), &self.author)
+            .map_err(DagError::from)
+    }
+
+    /// Verify everything but the Ed25519 signature of the vote (which can then be checked in batches).
+    pub fn verify_authority(&self, committee: &Committee) -> DagResult<()> {
         // Ensure the authority has voting rights.
         ensure!(
             committee.stake(&self.author) > 0,
             DagError::UnknownAuthority(self.author)
         );
 
-        // Check the signature.
-        self.signature
-            .verify(&self.digest(), &self.author)
-            .map_err(DagError::from)
+        // Check the BLS signature (if any), so that it can be safely aggregated.
+        if let Some(bls_signature) = &self.bls_signature {
+            let key = committee
+                .bls_public_key(&self.author)
+                .ok_or(DagError::MissingBlsKey(self.author))?;
+            bls_signature.verify(&self.digest(), &key)?;
+        }
+        Ok(())
     }
 }
 
@@ -165,10 +232,52 @@ impl fmt::Debug for Vote {
     }
 }
 
+/// The votes of a certificate aggregated into a single BLS signature.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AggregateVotes {
+    /// The voters, as a bitmap over the (ordered) authorities of the committee.
+    pub signers: Vec<u8>,
+    /// The aggregate of the BLS signatures of the voters.
+    pub signature: BlsSignature,
+}
+
+impl AggregateVotes {
+    pub fn new(committee: &Committee, votes: &[(PublicKey, BlsSignature)]) -> DagResult<Self> {
+        let mut signers = vec![0u8; committee.size().div_ceil(8)];
+        for (name, _) in votes {
+            let index = committee
+                .authorities
+                .keys()
+                .position(|x| x == name)
+                .ok_or(DagError::UnknownAuthority(*name))?;
+            signers[index / 8] |= 1 << (index % 8);
+        }
+        let signature = BlsSignature::aggregate(votes.iter().map(|(_, x)| x))?;
+        Ok(Self { signers, signature })
+    }
+
+    /// Returns the voters.
+    pub fn signers(&self, committee: &Committee) -> Vec<PublicKey> {
+        committee
+            .authorities
+            .keys()
+            .enumerate()
+            .filter(|(i, _)| {
+                self.signers
+                    .get(i / 8)
+                    .is_some_and(|x| x & (1 << (i % 8)) != 0)
+            })
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Certificate {
     pub header: Header,
     pub votes: Vec<(PublicKey, Signature)>,
+    /// The votes aggregated into a single signature (replacing `votes`).
+    pub aggregate: Option<AggregateVotes>,
 }
 
 impl Certificate {
@@ -179,6 +288,7 @@ impl Certificate {
             .map(|name| Self {
                 header: Header {
                     author: *name,
+                    epoch: committee.epoch,
                     ..Header::default()
                 },
                 ..Self::default()
@@ -195,23 +305,19 @@ impl Certificate {
         // Check the embedded header.
         self.header.verify(committee)?;
 
-        // Ensure the certificate has a quorum.
-        let mut weight = 0;
-        let mut used = HashSet::new();
-        for (name, _) in self.votes.iter() {
-            ensure!(!used.contains(name), DagError::AuthorityReuse(*name));
-            let voting_rights = committee.stake(name);
-            ensure!(voting_rights > 0, DagError::UnknownAuthority(*name));
-            used.insert(*name);
-            weight += voting_rights;
-        }
-        ensure!(
-            weight >= committee.quorum_threshold(),
-            DagError::CertificateRequiresQuorum
-        );
+        // Check the votes.
+        verify_votes(&self.digest(), &self.votes, &self.aggregate, committee)
+    }
 
-        // Check the signatures.
-        Signature::verify_batch(&self.digest(), &self.votes).map_err(DagError::from)
+    /// Returns the certificate without its header (which the receivers reconstruct from its shards).
+    pub fn compact(&self) -> CompactCertificate {
+        CompactCertificate {
+            id: self.header.id.clone(),
+            round: self.round(),
+            origin: self.origin(),
+            votes: self.votes.clone(),
+            aggregate: self.aggregate.clone(),
+        }
     }
 
     pub fn round(&self) -> Round {
@@ -233,6 +339,52 @@ impl Hash for Certificate {
     }
 }
 
+/// Ensure the votes of a certificate come from a quorum and check their signatures.
+fn verify_votes(
+    digest: &Digest,
+    votes: &[(PublicKey, Signature)],
+    aggregate: &Option<AggregateVotes>,
+    committee: &Committee,
+) -> DagResult<()> {
+    // Ensure the certificate has a quorum.
+    let voters = match aggregate {
+        Some(aggregate) => aggregate.signers(committee),
+        None => votes.iter().map(|(name, _)| *name).collect(),
+    };
+    let mut weight = 0;
+    let mut used = HashSet::new();
+    for name in voters.iter() {
+        ensure!(!used.contains(name), DagError::AuthorityReuse(*name));
+        let voting_rights = committee.stake(name);
+        ensure!(voting_rights > 0, DagError::UnknownAuthority(*name));
+        used.insert(*name);
+        weight += voting_rights;
+    }
+    ensure!(
+        weight >= committee.quorum_threshold(),
+        DagError::CertificateRequiresQuorum
+    );
+
+    // Check the signatures.
+    match aggregate {
+        Some(aggregate) => {
+            let keys = voters
+                .iter()
+                .map(|x| {
+                    committee
+                        .bls_public_key(x)
+                        .ok_or(DagError::MissingBlsKey(*x))
+                })
+                .collect::<DagResult<Vec<_>>>()?;
+            aggregate
+                .signature
+                .verify_aggregate(digest, &keys)
+                .map_err(DagError::from)
+        }
+        None => Signature::verify_batch(digest, votes).map_err(DagError::from),
+    }
+}
+
 impl fmt::Debug for Certificate {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(
@@ -254,3 +406,94 @@ impl PartialEq for Certificate {
         ret
     }
 }
+
+/// A certificate referring to its header by digest. The authors of erasure-coded headers broadcast their
+/// certificates in this form: the receivers already reconstructed the header from its shards, and sending it
+/// again to each of them would defeat the purpose of erasure coding.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CompactCertificate {
+    /// The id of the header.
+    pub id: Digest,
+    /// The round of the header.
+    pub round: Round,
+    /// The author of the header.
+    pub origin: PublicKey,
+    pub votes: Vec<(PublicKey, Signature)>,
+    /// The votes aggregated into a single signature (replacing `votes`).
+    pub aggregate: Option<AggregateVotes>,
+}
+
+impl CompactCertificate {
+    pub fn verify(&self, committee: &Committee) -> DagResult<()> {
+        // Ensure the authority has voting rights.
+        ensure!(
+            committee.stake(&self.origin) > 0,
+            DagError::UnknownAuthority(self.origin)
+        );
+
+        // Check the votes.
+        verify_votes(&self.digest(), &self.votes, &self.aggregate, committee)
+    }
+
+    /// Returns the full certificate, given its header.
+    pub fn expand(self, header: Header) -> DagResult<Certificate> {
+        ensure!(
+            header.id == self.id && header.round == self.round && header.author == self.origin,
+            DagError::MalformedHeader(self.id)
+        );
+        Ok(Certificate {
+            header,
+            votes: self.votes,
+            aggregate: self.aggregate,
+        })
+    }
+}
+
+impl Hash for CompactCertificate {
+    /// The digest of the full certificate.
+    fn digest(&self) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(&self.id);
+        hasher.update(self.round.to_le_bytes());
+        hasher.update(&self.origin);
+        Digest(hasher.finalize().as_slice()[..32].try_into().unwrap())
+    }
+}
+
+impl fmt::Debug for CompactCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}: C{}({}, {})",
+            self.digest(),
+            self.round,
+            self.origin,
+            self.id
+        )
+    }
+}
+
+/// The certificates committed by a leader: the leader and the part of its causal history that was not committed
+/// yet, in commit order. Consensus outputs one per committed leader.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommittedSubDag {
+    /// The certificate of the leader.
+    pub leader: Certificate,
+    /// The committed certificates (including the leader), in commit order.
+    pub certificates: Vec<Certificate>,
+}
+
+impl CommittedSubDag {
+    /// Returns the highest round of the committed certificates (the round of the leader).
+    pub fn round(&self) -> Round {
+        self.leader.round()
+    }
+
+    pub fn len(&self) -> usize {
+        self.certificates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.certificates.is_empty()
+    }
+}
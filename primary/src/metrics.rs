@@ -0,0 +1,799 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::commit_events::{CommitEvent, CommitEventBus, LeaderSlot, SubDagStructure};
+use crate::primary::Round;
+use crypto::PublicKey;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::broadcast::error::RecvError;
+
+#[cfg(test)]
+#[path = "tests/metrics_tests.rs"]
+pub mod metrics_tests;
+
+/// The number of rounds whose latencies are kept in memory.
+pub const MAX_TRACKED_ROUNDS: usize = 100;
+
+/// The number of waves whose commit latencies are kept in memory.
+pub const MAX_TRACKED_WAVES: usize = 100;
+
+/// The quantiles of the commit latencies we report.
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// The weight of the latest sample in the smoothed latency of the vote quorums.
+const SMOOTHING_FACTOR: f64 = 0.2;
+
+/// The latencies of the pipeline of the primary for a single round. Denominated in ms.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoundLatency {
+    /// The round.
+    pub round: Round,
+    /// The time between entering the round (ie. holding a quorum of parents) and creating our header.
+    pub header_creation: Option<f64>,
+    /// The time between creating our header and gathering a quorum of votes for it (ie. its certificate).
+    pub vote_quorum: Option<f64>,
+    /// The time between creating our header and gathering a quorum of certificates of the round (ie. being
+    /// able to move to the next round).
+    pub certificate_quorum: Option<f64>,
+}
+
+/// The stages of the pipeline.
+#[derive(Clone, Copy)]
+enum Stage {
+    HeaderCreation,
+    VoteQuorum,
+    CertificateQuorum,
+}
+
+/// The progress of a single round.
+#[derive(Default)]
+struct RoundTimes {
+    started: Option<Instant>,
+    header: Option<Instant>,
+    latency: RoundLatency,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// The most recent rounds.
+    rounds: BTreeMap<Round, RoundTimes>,
+    /// The sum of the latencies of each stage (over all rounds) and their number, in ms.
+    totals: [(f64, u64); 3],
+    /// The exponential moving average of the latencies of the vote quorums, in ms.
+    smoothed_vote_quorum: Option<f64>,
+}
+
+/// Measures how long each stage of the pipeline of the primary takes in each round, so that bottlenecks can
+/// be localized. The metrics are cheap to clone and all clones share the same records.
+#[derive(Clone, Default)]
+pub struct PipelineMetrics {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl std::fmt::Debug for PipelineMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PipelineMetrics")
+    }
+}
+
+impl PipelineMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the record of the specified round, creating it if necessary (and forgetting the oldest ones).
+    fn round(inner: &mut Inner, round: Round) -> &mut RoundTimes {
+        if !inner.rounds.contains_key(&round) && inner.rounds.len() >= MAX_TRACKED_ROUNDS {
+            let oldest = *inner.rounds.keys().next().unwrap();
+            inner.rounds.remove(&oldest);
+        }
+        inner.rounds.entry(round).or_insert_with(|| RoundTimes {
+            latency: RoundLatency {
+                round,
+                ..RoundLatency::default()
+            },
+            ..RoundTimes::default()
+        })
+    }
+
+    /// Record the latency of a stage (if not already recorded).
+    fn record(&self, round: Round, stage: Stage, since: fn(&RoundTimes) -> Option<Instant>) {
+        let mut inner = self.inner.lock().unwrap();
+        let times = Self::round(&mut inner, round);
+        let start = match since(times) {
+            Some(start) => start,
+            None => return,
+        };
+        let slot = match stage {
+            Stage::HeaderCreation => &mut times.latency.header_creation,
+            Stage::VoteQuorum => &mut times.latency.vote_quorum,
+            Stage::CertificateQuorum => &mut times.latency.certificate_quorum,
+        };
+        if slot.is_some() {
+            return;
+        }
+        let latency = start.elapsed().as_secs_f64() * 1_000.0;
+        *slot = Some(latency);
+        inner.totals[stage as usize].0 += latency;
+        inner.totals[stage as usize].1 += 1;
+        if let Stage::VoteQuorum = stage {
+            inner.smoothed_vote_quorum = Some(match inner.smoothed_vote_quorum {
+                Some(x) => x + SMOOTHING_FACTOR * (latency - x),
+                None => latency,
+            });
+        }
+    }
+
+    /// Record that we entered a new round (ie. we hold a quorum of parents).
+    pub fn record_round_start(&self, round: Round) {
+        let mut inner = self.inner.lock().unwrap();
+        Self::round(&mut inner, round)
+            .started
+            .get_or_insert_with(Instant::now);
+    }
+
+    /// Record that we created our header of the specified round.
+    pub fn record_header(&self, round: Round) {
+        self.record(round, Stage::HeaderCreation, |x| x.started);
+        let mut inner = self.inner.lock().unwrap();
+        Self::round(&mut inner, round)
+            .header
+            .get_or_insert_with(Instant::now);
+    }
+
+    /// Record that we assembled the certificate of our header of the specified round.
+    pub fn record_vote_quorum(&self, round: Round) {
+        self.record(round, Stage::VoteQuorum, |x| x.header);
+    }
+
+    /// Record that we gathered a quorum of certificates of the specified round.
+    pub fn record_certificate_quorum(&self, round: Round) {
+        self.record(round, Stage::CertificateQuorum, |x| x.header);
+    }
+
+    /// Returns the smoothed time it takes to gather a quorum of votes for our headers (ie. the round-trip
+    /// time to a quorum of authorities), in ms.
+    pub fn vote_quorum_latency(&self) -> Option<f64> {
+        self.inner.lock().unwrap().smoothed_vote_quorum
+    }
+
+    /// Returns the latencies of the most recent rounds, sorted by round.
+    pub fn rounds(&self) -> Vec<RoundLatency> {
+        let inner = self.inner.lock().unwrap();
+        inner.rounds.values().map(|x| x.latency.clone()).collect()
+    }
+
+    /// Encode the latencies (summed over all rounds) in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let stages = [
+            (
+                "primary_header_creation_seconds",
+                "Time between entering a round and creating our header.",
+            ),
+            (
+                "primary_vote_quorum_seconds",
+                "Time between creating our header and gathering a quorum of votes.",
+            ),
+            (
+                "primary_certificate_quorum_seconds",
+                "Time between creating our header and gathering a quorum of certificates of the round.",
+            ),
+        ];
+
+        let mut output = String::new();
+        for ((name, help), (sum, count)) in stages.iter().zip(inner.totals.iter()) {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} summary", name);
+            let _ = writeln!(output, "{}_sum {}", name, sum / 1_000.0);
+            let _ = writeln!(output, "{}_count {}", name, count);
+        }
+        output
+    }
+}
+
+/// The commit latencies of the certificates committed by the leader of a wave. Denominated in ms.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WaveLatency {
+    /// The wave.
+    pub wave: Round,
+    /// The number of certificates committed in the wave.
+    pub commits: usize,
+    /// The median commit latency.
+    pub p50: f64,
+    /// The 90th percentile of the commit latencies.
+    pub p90: f64,
+    /// The 99th percentile of the commit latencies.
+    pub p99: f64,
+}
+
+/// Returns the specified quantile of sorted samples (nearest rank).
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl WaveLatency {
+    fn new(wave: Round, samples: &[f64]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Self {
+            wave,
+            commits: sorted.len(),
+            p50: quantile(&sorted, QUANTILES[0]),
+            p90: quantile(&sorted, QUANTILES[1]),
+            p99: quantile(&sorted, QUANTILES[2]),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CommitLatencyInner {
+    /// The commit latencies of the most recent waves, in ms.
+    waves: BTreeMap<Round, Vec<f64>>,
+    /// The sum of the commit latencies (over all waves) and their number, in ms.
+    total: (f64, u64),
+}
+
+/// Measures the time between the delivery of each certificate to consensus and its commit, so that latency
+/// regressions are quantified per run. The latencies are reported per wave (as percentiles); each completed
+/// wave is also logged. The metrics are cheap to clone and all clones share the same records.
+#[derive(Clone, Default)]
+pub struct CommitLatencyMetrics {
+    inner: Arc<Mutex<CommitLatencyInner>>,
+}
+
+impl std::fmt::Debug for CommitLatencyMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CommitLatencyMetrics")
+    }
+}
+
+impl CommitLatencyMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the commit latency (in ms) of a certificate committed by the leader of the specified wave. The
+    /// previous waves are considered complete once a certificate of a later wave is committed.
+    pub fn record(&self, wave: Round, latency: f64) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((last, samples)) = inner.waves.iter().next_back() {
+            if *last < wave {
+                let summary = WaveLatency::new(*last, samples);
+                // NOTE: This log entry is used to compute performance.
+                info!(
+                    "Wave {} committed {} certificates with latency p50 {:.0} ms, p90 {:.0} ms, p99 {:.0} ms",
+                    summary.wave, summary.commits, summary.p50, summary.p90, summary.p99
+                );
+            }
+        }
+        if !inner.waves.contains_key(&wave) && inner.waves.len() >= MAX_TRACKED_WAVES {
+            let oldest = *inner.waves.keys().next().unwrap();
+            inner.waves.remove(&oldest);
+        }
+        inner.waves.entry(wave).or_default().push(latency);
+        inner.total.0 += latency;
+        inner.total.1 += 1;
+    }
+
+    /// Record the commit latencies of all the commits published from now on.
+    pub fn follow(&self, commit_events: &CommitEventBus) {
+        let metrics = self.clone();
+        let mut rx_commits = commit_events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx_commits.recv().await {
+                    Ok(event) => metrics.record(event.wave, event.latency),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Returns the commit latencies of the most recent waves, sorted by wave.
+    pub fn waves(&self) -> Vec<WaveLatency> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .waves
+            .iter()
+            .map(|(wave, samples)| WaveLatency::new(*wave, samples))
+            .collect()
+    }
+
+    /// Encode the commit latencies (the quantiles over the most recent waves, and the sum over all waves) in
+    /// the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut recent: Vec<_> = inner.waves.values().flatten().copied().collect();
+        recent.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let name = "primary_commit_latency_seconds";
+        let mut output = String::new();
+        let _ = writeln!(
+            output,
+            "# HELP {} Time between the delivery of a certificate to consensus and its commit.",
+            name
+        );
+        let _ = writeln!(output, "# TYPE {} summary", name);
+        for q in QUANTILES.iter() {
+            let value = quantile(&recent, *q) / 1_000.0;
+            let _ = writeln!(output, "{}{{quantile=\"{}\"}} {}", name, q, value);
+        }
+        let _ = writeln!(output, "{}_sum {}", name, inner.total.0 / 1_000.0);
+        let _ = writeln!(output, "{}_count {}", name, inner.total.1);
+        output
+    }
+}
+
+/// The chain quality and inclusion fairness of an authority, ie. how its certificates fare in the commit
+/// sequence.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuthorityQuality {
+    /// The number of certificates of the authority committed.
+    pub commits: u64,
+    /// The fraction of the committed certificates authored by the authority.
+    pub share: f64,
+    /// The number of leader slots the authority was elected for.
+    pub leader_slots: u64,
+    /// The number of those slots whose leader was committed.
+    pub leaders_committed: u64,
+    /// The average number of rounds between a certificate of the authority and the leader committing it.
+    pub inclusion_rounds: f64,
+    /// The average time between the delivery of a certificate of the authority to consensus (shortly after
+    /// its creation) and its commit. Denominated in ms.
+    pub inclusion_delay: f64,
+}
+
+#[derive(Default)]
+struct QualityTotals {
+    commits: u64,
+    leader_slots: u64,
+    leaders_committed: u64,
+    /// The sum of the inclusion delays, in rounds.
+    rounds: u64,
+    /// The sum of the inclusion delays, in ms.
+    delay: f64,
+}
+
+/// Measures, for every authority, its share of the commit sequence, the leader slots it honored, and how long
+/// its certificates wait to be committed, so that censorship and fairness can be quantified (eg. under
+/// attack). The metrics are cheap to clone and all clones share the same records.
+#[derive(Clone, Default)]
+pub struct ChainQualityMetrics {
+    inner: Arc<Mutex<BTreeMap<PublicKey, QualityTotals>>>,
+}
+
+impl std::fmt::Debug for ChainQualityMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ChainQualityMetrics")
+    }
+}
+
+impl ChainQualityMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a committed certificate.
+    pub fn record_commit(&self, event: &CommitEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        let totals = inner.entry(event.author).or_default();
+        totals.commits += 1;
+        totals.rounds += event.leader_round.saturating_sub(event.round);
+        totals.delay += event.latency;
+    }
+
+    /// Record a resolved leader slot.
+    pub fn record_slot(&self, slot: &LeaderSlot) {
+        let mut inner = self.inner.lock().unwrap();
+        let totals = inner.entry(slot.leader).or_default();
+        totals.leader_slots += 1;
+        if slot.committed {
+            totals.leaders_committed += 1;
+        }
+    }
+
+    /// Record all the commits and leader slots published from now on.
+    pub fn follow(&self, commit_events: &CommitEventBus) {
+        let metrics = self.clone();
+        let mut rx_commits = commit_events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx_commits.recv().await {
+                    Ok(event) => metrics.record_commit(&event),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        let metrics = self.clone();
+        let mut rx_slots = commit_events.subscribe_slots();
+        tokio::spawn(async move {
+            loop {
+                match rx_slots.recv().await {
+                    Ok(slot) => metrics.record_slot(&slot),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Returns the chain quality of every authority (that committed certificates or was elected leader).
+    pub fn authorities(&self) -> BTreeMap<PublicKey, AuthorityQuality> {
+        let inner = self.inner.lock().unwrap();
+        let total: u64 = inner.values().map(|x| x.commits).sum();
+        inner
+            .iter()
+            .map(|(name, x)| {
+                let average = |sum: f64| match x.commits {
+                    0 => 0.0,
+                    n => sum / n as f64,
+                };
+                let quality = AuthorityQuality {
+                    commits: x.commits,
+                    share: match total {
+                        0 => 0.0,
+                        n => x.commits as f64 / n as f64,
+                    },
+                    leader_slots: x.leader_slots,
+                    leaders_committed: x.leaders_committed,
+                    inclusion_rounds: average(x.rounds as f64),
+                    inclusion_delay: average(x.delay),
+                };
+                (*name, quality)
+            })
+            .collect()
+    }
+
+    /// Encode the chain quality of every authority in the Prometheus text exposition format (labelled by the
+    /// authority).
+    pub fn encode(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let labels: Vec<_> = inner
+            .keys()
+            .map(|x| format!("{{authority=\"{}\"}}", x.encode_base64()))
+            .collect();
+        let counters = [
+            (
+                "primary_committed_certificates_total",
+                "Number of committed certificates of the authority.",
+                inner.values().map(|x| x.commits).collect::<Vec<_>>(),
+            ),
+            (
+                "primary_leader_slots_total",
+                "Number of leader slots the authority was elected for.",
+                inner.values().map(|x| x.leader_slots).collect(),
+            ),
+            (
+                "primary_leader_slots_committed_total",
+                "Number of leader slots of the authority whose leader was committed.",
+                inner.values().map(|x| x.leaders_committed).collect(),
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, help, values) in counters.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            for (label, value) in labels.iter().zip(values) {
+                let _ = writeln!(output, "{}{} {}", name, label, value);
+            }
+        }
+
+        let summaries = [
+            (
+                "primary_inclusion_rounds",
+                "Rounds between a certificate of the authority and the leader committing it.",
+                inner.values().map(|x| x.rounds as f64).collect::<Vec<_>>(),
+            ),
+            (
+                "primary_inclusion_delay_seconds",
+                "Time between the delivery of a certificate of the authority to consensus and its commit.",
+                inner.values().map(|x| x.delay / 1_000.0).collect(),
+            ),
+        ];
+        for (name, help, sums) in summaries.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} summary", name);
+            for ((label, sum), totals) in labels.iter().zip(sums).zip(inner.values()) {
+                let _ = writeln!(output, "{}_sum{} {}", name, label, sum);
+                let _ = writeln!(output, "{}_count{} {}", name, label, totals.commits);
+            }
+        }
+        output
+    }
+}
+
+/// The shape of the DAG, as seen by consensus.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DagShape {
+    /// The number of certificates of each of the most recent rounds.
+    pub widths: BTreeMap<Round, usize>,
+    /// The number of committed leaders.
+    pub committed_leaders: u64,
+    /// The number of leader slots whose leader was not committed.
+    pub skipped_leaders: u64,
+    /// The average number of rounds between two committed leaders (the rounds spanned by each committed wave,
+    /// which grows with the skipped leaders).
+    pub rounds_per_wave: f64,
+    /// The number of certificates dropped by consensus without being committed.
+    pub orphans: u64,
+}
+
+#[derive(Default)]
+struct DagShapeInner {
+    widths: BTreeMap<Round, usize>,
+    committed_leaders: u64,
+    skipped_leaders: u64,
+    /// The round of the last committed leader (if any).
+    last_leader: Option<Round>,
+    /// The sum of the rounds between two committed leaders, and their number.
+    wave_rounds: (u64, u64),
+    orphans: u64,
+}
+
+/// Measures the shape of the DAG (its width per round, the leaders skipped, the rounds spanned by each committed
+/// wave and the certificates never committed), so that the effect of faults on its structure is quantified.
+/// The metrics are cheap to clone and all clones share the same records.
+#[derive(Clone, Default)]
+pub struct DagShapeMetrics {
+    inner: Arc<Mutex<DagShapeInner>>,
+}
+
+impl std::fmt::Debug for DagShapeMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DagShapeMetrics")
+    }
+}
+
+impl DagShapeMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a certificate added to the DAG.
+    pub fn record_certificate(&self, round: Round) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.widths.contains_key(&round) && inner.widths.len() >= MAX_TRACKED_ROUNDS {
+            match inner.widths.keys().next() {
+                Some(oldest) if *oldest < round => {
+                    let oldest = *oldest;
+                    inner.widths.remove(&oldest);
+                }
+                // Do not track rounds older than the ones we have.
+                _ => return,
+            }
+        }
+        *inner.widths.entry(round).or_default() += 1;
+    }
+
+    /// Record a resolved leader slot.
+    pub fn record_slot(&self, slot: &LeaderSlot) {
+        let mut inner = self.inner.lock().unwrap();
+        if !slot.committed {
+            inner.skipped_leaders += 1;
+            return;
+        }
+        inner.committed_leaders += 1;
+        if let Some(last) = inner.last_leader {
+            inner.wave_rounds.0 += slot.round.saturating_sub(last);
+            inner.wave_rounds.1 += 1;
+        }
+        inner.last_leader = Some(slot.round);
+    }
+
+    /// Record orphaned certificates.
+    pub fn record_orphans(&self, orphans: u64) {
+        self.inner.lock().unwrap().orphans += orphans;
+    }
+
+    /// Record all the leader slots and orphaned certificates published from now on.
+    pub fn follow(&self, commit_events: &CommitEventBus) {
+        let metrics = self.clone();
+        let mut rx_slots = commit_events.subscribe_slots();
+        tokio::spawn(async move {
+            loop {
+                match rx_slots.recv().await {
+                    Ok(slot) => metrics.record_slot(&slot),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        let metrics = self.clone();
+        let mut rx_orphans = commit_events.subscribe_orphans();
+        tokio::spawn(async move {
+            loop {
+                match rx_orphans.recv().await {
+                    Ok(_) => metrics.record_orphans(1),
+                    Err(RecvError::Lagged(missed)) => metrics.record_orphans(missed),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Returns the shape of the DAG.
+    pub fn shape(&self) -> DagShape {
+        let inner = self.inner.lock().unwrap();
+        DagShape {
+            widths: inner.widths.clone(),
+            committed_leaders: inner.committed_leaders,
+            skipped_leaders: inner.skipped_leaders,
+            rounds_per_wave: match inner.wave_rounds {
+                (_, 0) => 0.0,
+                (sum, n) => sum as f64 / n as f64,
+            },
+            orphans: inner.orphans,
+        }
+    }
+
+    /// Encode the shape of the DAG in the Prometheus text exposition format. The width is reported over the
+    /// most recent complete rounds (all but the highest one, which may still grow).
+    pub fn encode(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut widths: Vec<_> = inner.widths.values().copied().collect();
+        widths.pop();
+
+        let mut output = String::new();
+        let gauges = [
+            (
+                "primary_dag_width_min",
+                "Smallest number of certificates of the recent rounds of the DAG.",
+                widths.iter().min().copied().unwrap_or_default() as f64,
+            ),
+            (
+                "primary_dag_width_average",
+                "Average number of certificates of the recent rounds of the DAG.",
+                match widths.len() {
+                    0 => 0.0,
+                    n => widths.iter().sum::<usize>() as f64 / n as f64,
+                },
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+
+        let counters = [
+            (
+                "primary_committed_leaders_total",
+                "Number of committed leaders.",
+                inner.committed_leaders,
+            ),
+            (
+                "primary_skipped_leaders_total",
+                "Number of leader slots whose leader was not committed.",
+                inner.skipped_leaders,
+            ),
+            (
+                "primary_orphaned_certificates_total",
+                "Number of certificates dropped by consensus without being committed.",
+                inner.orphans,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+
+        let name = "primary_rounds_per_wave";
+        let _ = writeln!(
+            output,
+            "# HELP {} Rounds between two committed leaders.",
+            name
+        );
+        let _ = writeln!(output, "# TYPE {} summary", name);
+        let _ = writeln!(output, "{}_sum {}", name, inner.wave_rounds.0);
+        let _ = writeln!(output, "{}_count {}", name, inner.wave_rounds.1);
+        output
+    }
+}
+
+#[derive(Default)]
+struct SubDagInner {
+    /// The structure of the most recent committed sub-dags.
+    sub_dags: VecDeque<SubDagStructure>,
+    /// The sum of the number of certificates of the committed sub-dags.
+    certificates: u64,
+    /// The sum of the number of rounds spanned by the committed sub-dags.
+    depth: u64,
+    /// The number of committed sub-dags.
+    count: u64,
+}
+
+/// Keeps the structure of the most recent sub-dags committed by consensus (their certificates and parent links),
+/// and measures how much of the DAG each leader commits. The metrics are cheap to clone and all clones share the
+/// same records.
+#[derive(Clone, Default)]
+pub struct SubDagMetrics {
+    inner: Arc<Mutex<SubDagInner>>,
+}
+
+impl std::fmt::Debug for SubDagMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SubDagMetrics")
+    }
+}
+
+impl SubDagMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a committed sub-dag.
+    pub fn record(&self, sub_dag: SubDagStructure) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.certificates += sub_dag.vertices.len() as u64;
+        inner.depth += sub_dag.depth();
+        inner.count += 1;
+        if inner.sub_dags.len() >= MAX_TRACKED_WAVES {
+            inner.sub_dags.pop_front();
+        }
+        inner.sub_dags.push_back(sub_dag);
+    }
+
+    /// Record all the sub-dags committed from now on.
+    pub fn follow(&self, commit_events: &CommitEventBus) {
+        let metrics = self.clone();
+        let mut rx_sub_dags = commit_events.subscribe_sub_dags();
+        tokio::spawn(async move {
+            loop {
+                match rx_sub_dags.recv().await {
+                    Ok(sub_dag) => metrics.record(sub_dag),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Returns the structure of the most recent committed sub-dags, in commit order.
+    pub fn sub_dags(&self) -> Vec<SubDagStructure> {
+        self.inner
+            .lock()
+            .unwrap()
+            .sub_dags
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Encode the size of the committed sub-dags (summed over all of them) in the Prometheus text exposition
+    /// format.
+    pub fn encode(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut output = String::new();
+        let summaries = [
+            (
+                "primary_sub_dag_certificates",
+                "Number of certificates committed by a leader.",
+                inner.certificates,
+            ),
+            (
+                "primary_sub_dag_depth",
+                "Number of rounds spanned by the sub-dag committed by a leader.",
+                inner.depth,
+            ),
+        ];
+        for (name, help, sum) in summaries.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} summary", name);
+            let _ = writeln!(output, "{}_sum {}", name, sum);
+            let _ = writeln!(output, "{}_count {}", name, inner.count);
+        }
+        output
+    }
+}
@@ -1,30 +1,42 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::certificate_waiter::CertificateWaiter;
+use crate::commit_events::CommitEventBus;
+use crate::commit_proof::CommitProof;
+use crate::commit_stream::CommitStream;
 use crate::core::Core;
+use crate::erasure::HeaderShard;
 use crate::error::DagError;
+use crate::fork_detector::ForkMonitor;
 use crate::garbage_collector::GarbageCollector;
+use crate::header_validator::{AcceptAllHeaders, HeaderValidator};
 use crate::header_waiter::HeaderWaiter;
-use crate::helper::Helper;
-use crate::messages::{Certificate, Header, Vote};
+use crate::helper::{Helper, HelperRequest};
+use crate::introspection::IntrospectionServer;
+use crate::messages::{Certificate, CommittedSubDag, CompactCertificate, Header, Vote};
 use crate::payload_receiver::PayloadReceiver;
 use crate::proposer::Proposer;
+use crate::state_synchronizer::{DagProgress, StateSynchronizer};
 use crate::synchronizer::Synchronizer;
+use crate::verifier::Verifier;
+use crate::watchdog::StallWatchdog;
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, KeyPair, Parameters, WorkerId};
+use config::{Committee, Epoch, KeyPair, Parameters, WorkerId};
 use crypto::{Digest, PublicKey, SignatureService};
 use futures::sink::SinkExt as _;
-use log::info;
-use network::{MessageHandler, Receiver as NetworkReceiver, Writer};
+use log::{debug, info};
+use network::{
+    DatagramHandler, DatagramReceiver, NetworkMetrics, Receiver as NetworkReceiver,
+    ShapedTransport, SharedTransport, TcpTransport, TypedMessageHandler, TypedReceiver, Writer,
+};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use store::Store;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-
-/// The default channel capacity for each channel of the primary.
-pub const CHANNEL_CAPACITY: usize = 1_000;
+use tokio::sync::{oneshot, watch};
 
 /// The round number.
 pub type Round = u64;
@@ -34,7 +46,17 @@ pub enum PrimaryMessage {
     Header(Header),
     Vote(Vote),
     Certificate(Certificate),
+    HeaderShard(HeaderShard),
+    /// The certificate of an erasure-coded header (see `CompactCertificate`).
+    CompactCertificate(CompactCertificate),
+    /// A certificate sent in reply to a sync request (handled with a lower priority than the other messages).
+    SyncCertificate(Certificate),
     CertificatesRequest(Vec<Digest>, /* requestor */ PublicKey),
+    CertificatesRangeRequest(
+        /* start */ Round,
+        /* end */ Round,
+        /* requestor */ PublicKey,
+    ),
 }
 
 /// The messages sent by the primary to its workers.
@@ -44,6 +66,15 @@ pub enum PrimaryWorkerMessage {
     Synchronize(Vec<Digest>, /* target */ PublicKey),
     /// The primary indicates a round update.
     Cleanup(Round),
+    /// The primary indicates that the epoch changed: the worker must restart with the new committee.
+    Reconfigure(Epoch),
+    /// The primary indicates that the target batches of the worker have been sequenced: the worker no
+    /// longer needs to re-send their digests after a crash.
+    Sequenced(Vec<Digest>),
+    /// The primary indicates that the target batches (of any authority) have been committed: the worker may
+    /// release its decryption shares of their transactions (if the committee uses threshold encryption) and
+    /// stop accepting these transactions from clients (if it deduplicates them across authorities).
+    Committed(Vec<Digest>),
 }
 
 /// The messages sent by the workers to their primary.
@@ -58,49 +89,156 @@ pub enum WorkerPrimaryMessage {
 pub struct Primary;
 
 impl Primary {
+    /// Spawn a primary for the epoch of the committee. Once the epoch changes, the primary outputs the committee of
+    /// the next epoch (received through `rx_next_committee`) on `tx_reconfigure`: the caller should then stop the
+    /// primary and spawn a new one with that committee. The returned sender updates the parameters of the running
+    /// primary (only the ones safe to change live are applied, see `Parameters::reload`).
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         keypair: KeyPair,
         committee: Committee,
         parameters: Parameters,
         store: Store,
         tx_consensus: Sender<Certificate>,
-        rx_consensus: Receiver<Certificate>,
-    ) {
-        let (tx_others_digests, rx_others_digests) = channel(CHANNEL_CAPACITY);
-        let (tx_our_digests, rx_our_digests) = channel(CHANNEL_CAPACITY);
-        let (tx_parents, rx_parents) = channel(CHANNEL_CAPACITY);
-        let (tx_headers, rx_headers) = channel(CHANNEL_CAPACITY);
-        let (tx_sync_headers, rx_sync_headers) = channel(CHANNEL_CAPACITY);
-        let (tx_sync_certificates, rx_sync_certificates) = channel(CHANNEL_CAPACITY);
-        let (tx_headers_loopback, rx_headers_loopback) = channel(CHANNEL_CAPACITY);
-        let (tx_certificates_loopback, rx_certificates_loopback) = channel(CHANNEL_CAPACITY);
-        let (tx_primary_messages, rx_primary_messages) = channel(CHANNEL_CAPACITY);
-        let (tx_cert_requests, rx_cert_requests) = channel(CHANNEL_CAPACITY);
+        rx_consensus: Receiver<CommittedSubDag>,
+        commit_events: CommitEventBus,
+        rx_next_committee: Receiver<Committee>,
+        tx_reconfigure: Sender<Committee>,
+    ) -> watch::Sender<Parameters> {
+        Self::spawn_with_validator(
+            keypair,
+            committee,
+            parameters,
+            store,
+            tx_consensus,
+            rx_consensus,
+            commit_events,
+            rx_next_committee,
+            tx_reconfigure,
+            Box::new(AcceptAllHeaders),
+        )
+    }
+
+    /// Spawn a primary that only votes for the headers following the custom rules of the validator.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn_with_validator(
+        keypair: KeyPair,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        tx_consensus: Sender<Certificate>,
+        rx_consensus: Receiver<CommittedSubDag>,
+        commit_events: CommitEventBus,
+        rx_next_committee: Receiver<Committee>,
+        tx_reconfigure: Sender<Committee>,
+        header_validator: Box<dyn HeaderValidator>,
+    ) -> watch::Sender<Parameters> {
+        let transport = ShapedTransport::wrap(
+            Arc::new(TcpTransport::new(parameters.socket.clone())),
+            &parameters.egress,
+        );
+        Self::spawn_with_transport(
+            keypair,
+            committee,
+            parameters,
+            store,
+            tx_consensus,
+            rx_consensus,
+            commit_events,
+            rx_next_committee,
+            tx_reconfigure,
+            transport,
+            header_validator,
+        )
+    }
+
+    /// Spawn a primary communicating through the specified transport rather than TCP (eg. to run a whole
+    /// committee in a single process).
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn_with_transport(
+        keypair: KeyPair,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        tx_consensus: Sender<Certificate>,
+        rx_consensus: Receiver<CommittedSubDag>,
+        commit_events: CommitEventBus,
+        rx_next_committee: Receiver<Committee>,
+        tx_reconfigure: Sender<Committee>,
+        transport: SharedTransport,
+        header_validator: Box<dyn HeaderValidator>,
+    ) -> watch::Sender<Parameters> {
+        let channel_capacity = parameters.channel_capacity.max(1);
+        let (tx_others_digests, rx_others_digests) = channel(channel_capacity);
+        let (tx_our_digests, rx_our_digests) = channel(channel_capacity);
+        let (tx_parents, rx_parents) = channel(channel_capacity);
+        let (tx_headers, rx_headers) = channel(channel_capacity);
+        let (tx_sync_headers, rx_sync_headers) = channel(channel_capacity);
+        let (tx_sync_certificates, rx_sync_certificates) = channel(channel_capacity);
+        let (tx_headers_loopback, rx_headers_loopback) = channel(channel_capacity);
+        let (tx_certificates_loopback, rx_certificates_loopback) = channel(channel_capacity);
+        let (tx_primary_messages, rx_primary_messages) = channel(channel_capacity);
+        let (tx_verified_messages, rx_verified_messages) = channel(channel_capacity);
+        let (tx_sync_messages, rx_sync_messages) = channel(channel_capacity);
+        let (tx_verified_sync, rx_verified_sync) = channel(channel_capacity);
+        let (tx_cert_requests, rx_cert_requests) = channel(channel_capacity);
+        let (tx_sequenced, rx_sequenced) = channel(channel_capacity);
 
         // Write the parameters to the logs.
         parameters.log();
 
+        // The parameters reloaded while we run, applied by the tasks using them.
+        let (tx_parameters, rx_parameters) = watch::channel(parameters.clone());
+
         // Parse the public and secret key of this authority.
         let name = keypair.name;
         let secret = keypair.secret;
+        let bls_secret = keypair.bls_secret;
+
+        // Add our share of the random coin of each round to our headers (if consensus elects the leaders randomly,
+        // or may fall back to electing them randomly).
+        let random_leaders = parameters.random_leaders || parameters.fallback_after > 0;
+        let coin_key = match random_leaders && committee.threshold_key.is_some() {
+            true => keypair.threshold_share,
+            false => None,
+        };
 
         // Atomic variable use to synchronizer all tasks with the latest consensus round. This is only
         // used for cleanup. The only tasks that write into this variable is `GarbageCollector`.
         let consensus_round = Arc::new(AtomicU64::new(0));
 
+        // The progress of our local DAG, written by the `Core` and used to detect that we are lagging behind.
+        let progress = Arc::new(DagProgress::default());
+
+        // Whether we hold the committee of the next epoch. Written by the `GarbageCollector` and signaled by the
+        // `Proposer` in our headers.
+        let ready = Arc::new(AtomicBool::new(false));
+
+        // Only accept connections from the hosts of the committee (if enabled).
+        let allowlist = parameters
+            .committee_allowlist
+            .then(|| committee.addresses());
+
         // Spawn the network receiver listening to messages from the other primaries.
-        let mut address = committee
+        let address = committee
             .primary(&name)
             .expect("Our public key or worker id is not in the committee")
-            .primary_to_primary;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+            .primary_to_primary
+            .bind;
+        let handler = PrimaryReceiverHandler {
+            tx_primary_messages,
+            tx_sync_messages,
+            tx_cert_requests,
+        };
+        if parameters.max_datagram_size > 0 {
+            DatagramReceiver::spawn_with_transport(address, handler.clone(), transport.clone());
+        }
+        NetworkReceiver::spawn_with_options(
             address,
-            /* handler */
-            PrimaryReceiverHandler {
-                tx_primary_messages,
-                tx_cert_requests,
-            },
+            TypedReceiver::new(handler),
+            allowlist.clone(),
+            transport.clone(),
+            /* chunked */ false,
         );
         info!(
             "Primary {} listening to primary messages on {}",
@@ -108,18 +246,21 @@ impl Primary {
         );
 
         // Spawn the network receiver listening to messages from our workers.
-        let mut address = committee
+        let address = committee
             .primary(&name)
             .expect("Our public key or worker id is not in the committee")
-            .worker_to_primary;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        NetworkReceiver::spawn(
+            .worker_to_primary
+            .bind;
+        NetworkReceiver::spawn_with_options(
             address,
             /* handler */
-            WorkerReceiverHandler {
+            TypedReceiver::new(WorkerReceiverHandler {
                 tx_our_digests,
                 tx_others_digests,
-            },
+            }),
+            allowlist,
+            transport.clone(),
+            /* chunked */ false,
         );
         info!(
             "Primary {} listening to workers messages on {}",
@@ -136,7 +277,33 @@ impl Primary {
         );
 
         // The `SignatureService` is used to require signatures on specific digests.
-        let signature_service = SignatureService::new(secret);
+        // It also signs our votes with BLS if certificates aggregate their signatures.
+        let mut signature_service =
+            SignatureService::with_signers(secret, parameters.signature_workers);
+        if parameters.aggregate_signatures {
+            let bls_secret = bls_secret.expect("Aggregate signatures require a BLS secret key");
+            signature_service = signature_service.with_bls(bls_secret);
+        }
+        let signature_metrics = signature_service.metrics();
+
+        // The connection counters of each peer, shared by all the network senders of the primary.
+        let network_metrics = NetworkMetrics::new();
+
+        // The `Verifier` checks (in batches) the signatures of the messages from the other primaries. The messages
+        // travel to the `Core` in two lanes: the consensus-critical messages (headers, votes, and certificates of
+        // the current rounds) are never delayed behind the (bulk) certificates received while synchronizing.
+        Verifier::spawn(
+            committee.clone(),
+            parameters.duplicate_cache_size,
+            /* rx_primaries */ rx_primary_messages,
+            /* tx_core */ tx_verified_messages,
+        );
+        Verifier::spawn(
+            committee.clone(),
+            parameters.duplicate_cache_size,
+            /* rx_primaries */ rx_sync_messages,
+            /* tx_core */ tx_verified_sync.clone(),
+        );
 
         // The `Core` receives and handles headers, votes, and certificates from the other primaries.
         Core::spawn(
@@ -145,9 +312,18 @@ impl Primary {
             store.clone(),
             synchronizer,
             signature_service.clone(),
+            header_validator,
             consensus_round.clone(),
+            progress.clone(),
             parameters.gc_depth,
-            /* rx_primaries */ rx_primary_messages,
+            parameters.certificate_concurrency,
+            parameters.max_datagram_size,
+            parameters.erasure_coding_threshold,
+            parameters.exclude_misbehaving,
+            transport.clone(),
+            network_metrics.clone(),
+            /* rx_primaries */ rx_verified_messages,
+            /* rx_sync */ rx_verified_sync,
             /* rx_header_waiter */ rx_headers_loopback,
             /* rx_certificate_waiter */ rx_certificates_loopback,
             /* rx_proposer */ rx_headers,
@@ -155,8 +331,37 @@ impl Primary {
             /* tx_proposer */ tx_parents,
         );
 
+        // The `CommitStream` (if enabled) streams the committed certificates to external subscribers.
+        let rx_consensus = match parameters.commit_stream_address {
+            Some(address) => {
+                let (tx_committed, rx_committed) = channel(channel_capacity);
+                CommitStream::spawn(address, rx_consensus, tx_committed);
+                rx_committed
+            }
+            None => rx_consensus,
+        };
+
         // Keeps track of the latest consensus round and allows other tasks to clean up their their internal state
-        GarbageCollector::spawn(&name, &committee, consensus_round.clone(), rx_consensus);
+        // (and detects the end of the epoch from the committed headers).
+        GarbageCollector::spawn(
+            &name,
+            &committee,
+            store.clone(),
+            consensus_round.clone(),
+            parameters.gc_depth,
+            parameters.retention_depth,
+            parameters.cleanup_rounds,
+            parameters.cleanup_interval,
+            ready.clone(),
+            parameters.max_datagram_size,
+            transport.clone(),
+            network_metrics.clone(),
+            rx_consensus,
+            rx_next_committee,
+            rx_parameters.clone(),
+            /* tx_sequenced */ tx_sequenced,
+            tx_reconfigure,
+        );
 
         // Receives batch digests from other workers. They are only used to validate headers.
         PayloadReceiver::spawn(store.clone(), /* rx_workers */ rx_others_digests);
@@ -168,11 +373,14 @@ impl Primary {
             name,
             committee.clone(),
             store.clone(),
-            consensus_round,
+            consensus_round.clone(),
             parameters.gc_depth,
             parameters.sync_retry_delay,
             parameters.sync_retry_nodes,
+            transport.clone(),
+            network_metrics.clone(),
             /* rx_synchronizer */ rx_sync_headers,
+            rx_parameters.clone(),
             /* tx_core */ tx_headers_loopback,
         );
 
@@ -190,15 +398,114 @@ impl Primary {
             name,
             &committee,
             signature_service,
+            coin_key,
+            store.clone(),
             parameters.header_size,
             parameters.max_header_delay,
+            parameters.min_header_delay,
+            parameters.adaptive_header_delay,
+            parameters.max_header_size,
+            parameters.max_header_num_of_batches,
+            parameters.max_parent_delay,
+            parameters.include_late_parents,
+            consensus_round.clone(),
+            parameters.gc_depth,
+            ready,
+            parameters.worker_weights.clone(),
+            progress.clone(),
             /* rx_core */ rx_parents,
             /* rx_workers */ rx_our_digests,
+            /* rx_sequenced */ rx_sequenced,
+            rx_parameters.clone(),
             /* tx_core */ tx_headers,
         );
 
+        // The `StateSynchronizer` requests the missing rounds in bulk from other primaries when our DAG lags far
+        // behind the certificates we observe (eg. after a restart or a network partition).
+        StateSynchronizer::spawn(
+            name,
+            committee.clone(),
+            progress.clone(),
+            parameters.catch_up_threshold,
+            parameters.sync_retry_delay,
+            parameters.sync_retry_nodes,
+            transport.clone(),
+            network_metrics.clone(),
+            /* tx_core */ tx_verified_sync,
+        );
+
+        // The `StallWatchdog` raises an alert when our DAG stops growing (if enabled).
+        if parameters.stall_timeout > 0 {
+            StallWatchdog::spawn(
+                committee.clone(),
+                store.clone(),
+                progress.clone(),
+                parameters.stall_timeout,
+                rx_parameters,
+            );
+        }
+
+        // Measure the commit latencies of the certificates (per wave).
+        progress.commit_latency.follow(&commit_events);
+
+        // Measure the chain quality of every authority.
+        progress.chain_quality.follow(&commit_events);
+
+        // Measure the shape of the DAG.
+        progress.dag_shape.follow(&commit_events);
+
+        // Keep the structure of the committed sub-dags.
+        progress.sub_dags.follow(&commit_events);
+
+        // Store the proofs of the committed leaders, for light clients.
+        CommitProof::persist(&commit_events, store.clone(), committee.epoch);
+
+        // Count the leaders skipped by consensus.
+        let mut rx_skips = commit_events.subscribe_skips();
+        let skips = progress.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx_skips.recv().await {
+                    Ok(_) => skips.leader_skips.fetch_add(1, Ordering::Relaxed),
+                    Err(RecvError::Lagged(missed)) => {
+                        skips.leader_skips.fetch_add(missed, Ordering::Relaxed)
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+            }
+        });
+
+        // Compare our commit sequence with the ones of other primaries to detect forks (if enabled).
+        if !parameters.fork_check_peers.is_empty() {
+            ForkMonitor::spawn(parameters.fork_check_peers.clone(), &commit_events);
+        }
+
+        // The introspection API exposes the state of the primary to operators (if enabled).
+        if let Some(address) = parameters.introspection_address {
+            IntrospectionServer::spawn(
+                address,
+                name,
+                committee.epoch,
+                parameters.gc_depth,
+                consensus_round,
+                progress.clone(),
+                signature_metrics,
+                network_metrics.clone(),
+                commit_events,
+                store.clone(),
+            );
+        }
+
         // The `Helper` is dedicated to reply to certificates requests from other primaries.
-        Helper::spawn(committee.clone(), store, rx_cert_requests);
+        Helper::spawn(
+            committee.clone(),
+            store,
+            transport,
+            network_metrics,
+            parameters.sync_rate_limit,
+            progress,
+            rx_cert_requests,
+        );
 
         // NOTE: This log entry is used to compute performance.
         info!(
@@ -208,8 +515,10 @@ impl Primary {
                 .primary(&name)
                 .expect("Our public key or worker id is not in the committee")
                 .primary_to_primary
-                .ip()
+                .advertise
+                .host()
         );
+        tx_parameters
     }
 }
 
@@ -217,28 +526,71 @@ impl Primary {
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_primary_messages: Sender<PrimaryMessage>,
-    tx_cert_requests: Sender<(Vec<Digest>, PublicKey)>,
+    tx_sync_messages: Sender<PrimaryMessage>,
+    tx_cert_requests: Sender<HelperRequest>,
 }
 
-#[async_trait]
-impl MessageHandler for PrimaryReceiverHandler {
-    async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // Reply with an ACK.
-        let _ = writer.send(Bytes::from("Ack")).await;
-
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
+impl PrimaryReceiverHandler {
+    /// Parse the message and forward it to the appropriate task.
+    async fn handle(&self, message: PrimaryMessage) {
+        match message {
             PrimaryMessage::CertificatesRequest(missing, requestor) => self
                 .tx_cert_requests
-                .send((missing, requestor))
+                .send(HelperRequest::Certificates(missing, requestor))
                 .await
                 .expect("Failed to send primary message"),
+            PrimaryMessage::CertificatesRangeRequest(..) => {
+                debug!("Dropping range request without reply channel")
+            }
+            PrimaryMessage::SyncCertificate(certificate) => self
+                .tx_sync_messages
+                .send(PrimaryMessage::Certificate(certificate))
+                .await
+                .expect("Failed to send certificate"),
             request => self
                 .tx_primary_messages
                 .send(request)
                 .await
                 .expect("Failed to send certificate"),
         }
+    }
+}
+
+#[async_trait]
+impl TypedMessageHandler<PrimaryMessage> for PrimaryReceiverHandler {
+    async fn dispatch(
+        &self,
+        writer: &mut Writer,
+        message: PrimaryMessage,
+    ) -> Result<(), Box<dyn Error>> {
+        // Range requests are answered in-band: the reply carries the certificates.
+        if let PrimaryMessage::CertificatesRangeRequest(start, end, requestor) = message {
+            let (sender, receiver) = oneshot::channel();
+            self.tx_cert_requests
+                .send(HelperRequest::Range(start, end, requestor, sender))
+                .await
+                .expect("Failed to send primary message");
+            if let Ok(reply) = receiver.await {
+                let _ = writer.send(reply).await;
+            }
+            return Ok(());
+        }
+
+        // Reply with an ACK.
+        let _ = writer.send(Bytes::from("Ack")).await;
+
+        // Parse the message.
+        self.handle(message).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DatagramHandler for PrimaryReceiverHandler {
+    async fn dispatch(&self, serialized: Bytes) -> Result<(), Box<dyn Error>> {
+        // Deserialize and parse the message.
+        let message = bincode::deserialize(&serialized).map_err(DagError::SerializationError)?;
+        self.handle(message).await;
         Ok(())
     }
 }
@@ -251,14 +603,14 @@ struct WorkerReceiverHandler {
 }
 
 #[async_trait]
-impl MessageHandler for WorkerReceiverHandler {
+impl TypedMessageHandler<WorkerPrimaryMessage> for WorkerReceiverHandler {
     async fn dispatch(
         &self,
-        _writer: &mut Writer,
-        serialized: Bytes,
+        writer: &mut Writer,
+        message: WorkerPrimaryMessage,
     ) -> Result<(), Box<dyn Error>> {
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized).map_err(DagError::SerializationError)? {
+        // Parse the message.
+        match message {
             WorkerPrimaryMessage::OurBatch(digest, worker_id) => self
                 .tx_our_digests
                 .send((digest, worker_id))
@@ -270,6 +622,9 @@ impl MessageHandler for WorkerReceiverHandler {
                 .await
                 .expect("Failed to send workers' digests"),
         }
+
+        // Reply with an ACK (the workers re-transmit their digests until acknowledged).
+        let _ = writer.send(Bytes::from("Ack")).await;
         Ok(())
     }
 }
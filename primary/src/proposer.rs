@@ -1,34 +1,86 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use crate::messages::{Certificate, Header};
 use crate::primary::Round;
-use config::{Committee, WorkerId};
+use crate::recovery::RecoveryStore;
+use crate::state_synchronizer::DagProgress;
+use config::{Committee, Epoch, Parameters, WorkerId};
 use crypto::Hash as _;
-use crypto::{Digest, PublicKey, SignatureService};
-use log::debug;
-#[cfg(feature = "benchmark")]
-use log::info;
+use crypto::{Digest, PublicKey, SignatureService, ThresholdKeyShare};
+use log::{debug, info};
+use rand::rngs::OsRng;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{oneshot, watch};
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/proposer_tests.rs"]
 pub mod proposer_tests;
 
+/// The messages of the `GarbageCollector` to the `Proposer`.
+#[derive(Debug)]
+pub enum ProposerMessage {
+    /// Our header of this round has been sequenced (its payload is safe).
+    Sequenced(Round),
+    /// The epoch ended: carry over the payload not sequenced so far to the next epoch, and acknowledge it.
+    EndOfEpoch(oneshot::Sender<()>),
+}
+
 /// The proposer creates new headers and send them to the core for broadcasting and further processing.
 pub struct Proposer {
     /// The public key of this primary.
     name: PublicKey,
+    /// The epoch of the committee.
+    epoch: Epoch,
     /// Service to sign headers.
     signature_service: SignatureService,
+    /// Our share of the threshold key of the committee, to add our share of the random coin of each round to
+    /// our headers (if consensus elects the leaders randomly).
+    coin_key: Option<ThresholdKeyShare>,
+    /// Holds our last header (to resume after a restart).
+    recovery: RecoveryStore,
     /// The size of the headers' payload.
     header_size: usize,
     /// The maximum delay to wait for batches' digests.
     max_header_delay: u64,
+    /// The delay to wait after a full header (if digests are still pending).
+    min_header_delay: u64,
+    /// Whether to adapt the delay between headers to the observed vote round-trip time.
+    adaptive_header_delay: bool,
+    /// The maximum size of the headers' payload (0 for no limit).
+    max_header_size: usize,
+    /// The maximum number of batches' digests in a header (0 for no limit).
+    max_header_num_of_batches: usize,
+    /// The maximum delay to wait for the remaining parents once we hold a quorum.
+    max_parent_delay: u64,
+    /// Whether to include the parents received after the quorum.
+    include_late_parents: bool,
+    /// The number of authorities in the committee.
+    committee_size: usize,
+    /// The current consensus round (used to detect payloads that will never be sequenced).
+    consensus_round: Arc<AtomicU64>,
+    /// The depth of the garbage collector.
+    gc_depth: Round,
+    /// Whether we hold the committee of the next epoch (signaled in our headers).
+    ready: Arc<AtomicBool>,
+    /// The share of the payload of each worker when the headers cannot fit all pending digests.
+    worker_weights: HashMap<WorkerId, u64>,
+    /// The progress of our local DAG (where we publish the latencies of the pipeline and our backlog).
+    progress: Arc<DagProgress>,
 
     /// Receives the parents to include in the next header (along with their round number).
     rx_core: Receiver<(Vec<Digest>, Round)>,
     /// Receives the batches' digests from our workers.
     rx_workers: Receiver<(Digest, WorkerId)>,
+    /// Receives the rounds of our headers that have been sequenced, and the end of the epoch (from the
+    /// `GarbageCollector`).
+    rx_sequenced: Receiver<ProposerMessage>,
+    /// Receives the parameters reloaded while we run (we apply the new delays between headers).
+    rx_parameters: watch::Receiver<Parameters>,
     /// Sends newly created headers to the `Core`.
     tx_core: Sender<Header>,
 
@@ -36,10 +88,22 @@ pub struct Proposer {
     round: Round,
     /// Holds the certificates' ids waiting to be included in the next header.
     last_parents: Vec<Digest>,
-    /// Holds the batches' digests waiting to be included in the next header.
-    digests: Vec<(Digest, WorkerId)>,
+    /// The number of certificates of the previous round we received (including the ones not included).
+    parents_count: usize,
+    /// The time until which we wait for the remaining parents.
+    parents_deadline: Instant,
+    /// Holds the batches' digests waiting to be included in the next header (one queue per worker).
+    digests: BTreeMap<WorkerId, VecDeque<Digest>>,
+    /// The number of batches' digests waiting to be included in the next header.
+    pending: usize,
+    /// The credit of each worker with pending digests (smooth weighted round-robin).
+    credits: HashMap<WorkerId, i64>,
     /// Keeps track of the size (in bytes) of batches' digests that we received so far.
     payload_size: usize,
+    /// The payload of our headers that have not been sequenced yet (indexed by round).
+    unsequenced: HashMap<Round, Vec<(Digest, WorkerId)>>,
+    /// Whether the epoch ended (we no longer propose headers).
+    ended: bool,
 }
 
 impl Proposer {
@@ -48,47 +112,186 @@ impl Proposer {
         name: PublicKey,
         committee: &Committee,
         signature_service: SignatureService,
+        coin_key: Option<ThresholdKeyShare>,
+        store: Store,
         header_size: usize,
         max_header_delay: u64,
+        min_header_delay: u64,
+        adaptive_header_delay: bool,
+        max_header_size: usize,
+        max_header_num_of_batches: usize,
+        max_parent_delay: u64,
+        include_late_parents: bool,
+        consensus_round: Arc<AtomicU64>,
+        gc_depth: Round,
+        ready: Arc<AtomicBool>,
+        worker_weights: HashMap<WorkerId, u64>,
+        progress: Arc<DagProgress>,
         rx_core: Receiver<(Vec<Digest>, Round)>,
         rx_workers: Receiver<(Digest, WorkerId)>,
+        rx_sequenced: Receiver<ProposerMessage>,
+        rx_parameters: watch::Receiver<Parameters>,
         tx_core: Sender<Header>,
     ) {
-        let genesis = Certificate::genesis(committee)
+        let genesis: Vec<_> = Certificate::genesis(committee)
             .iter()
             .map(|x| x.digest())
             .collect();
+        let epoch = committee.epoch;
+        let committee_size = committee.size();
+        progress.pipeline.record_round_start(1);
 
         tokio::spawn(async move {
             Self {
                 name,
+                epoch,
                 signature_service,
+                coin_key,
+                recovery: RecoveryStore::new(store, epoch),
                 header_size,
                 max_header_delay,
+                min_header_delay: min_header_delay.min(max_header_delay),
+                adaptive_header_delay,
+                max_header_size,
+                max_header_num_of_batches,
+                max_parent_delay,
+                include_late_parents,
+                committee_size,
+                consensus_round,
+                gc_depth,
+                ready,
+                worker_weights,
+                progress,
                 rx_core,
                 rx_workers,
+                rx_sequenced,
+                rx_parameters,
                 tx_core,
                 round: 1,
+                parents_count: genesis.len(),
+                parents_deadline: Instant::now(),
                 last_parents: genesis,
-                digests: Vec::with_capacity(2 * header_size),
+                digests: BTreeMap::new(),
+                pending: 0,
+                credits: HashMap::new(),
                 payload_size: 0,
+                unsequenced: HashMap::with_capacity(2 * gc_depth as usize),
+                ended: false,
             }
             .run()
             .await;
         });
     }
 
+    /// Returns the weight of a worker (workers that are not configured weigh 1).
+    fn weight(&self, worker_id: &WorkerId) -> i64 {
+        self.worker_weights
+            .get(worker_id)
+            .map_or(1, |x| (*x).max(1) as i64)
+    }
+
+    /// Queue a batch's digest of one of our workers.
+    fn push_digest(&mut self, digest: Digest, worker_id: WorkerId) {
+        self.payload_size += digest.size();
+        self.pending += 1;
+        self.digests.entry(worker_id).or_default().push_back(digest);
+    }
+
+    /// Returns the worker whose digest should be included next (smooth weighted round-robin): each worker with
+    /// pending digests earns its weight in credit, and the worker with the most credit is picked.
+    fn next_worker(&self) -> Option<WorkerId> {
+        self.digests
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .max_by_key(|(id, _)| {
+                let credit = self.credits.get(id).copied().unwrap_or(0) + self.weight(id);
+                (credit, Reverse(**id))
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Take the pending digests that fit in a header (at least one, if any). When they do not all fit, the
+    /// workers get a share of the header proportional to their weight, so that a busy worker does not starve
+    /// the others.
+    fn take_payload(&mut self) -> Vec<(Digest, WorkerId)> {
+        let mut payload = Vec::new();
+        let mut size = 0;
+        while let Some(worker_id) = self.next_worker() {
+            let digest_size = self.digests[&worker_id].front().unwrap().size();
+            let full_size = self.max_header_size > 0 && size + digest_size > self.max_header_size;
+            let full_count = self.max_header_num_of_batches > 0
+                && payload.len() >= self.max_header_num_of_batches;
+            if !payload.is_empty() && (full_size || full_count) {
+                break;
+            }
+
+            // Update the credits of the workers with pending digests.
+            let mut total = 0;
+            for (id, queue) in &self.digests {
+                if !queue.is_empty() {
+                    let weight = self.weight(id);
+                    *self.credits.entry(*id).or_insert(0) += weight;
+                    total += weight;
+                }
+            }
+            *self.credits.get_mut(&worker_id).unwrap() -= total;
+
+            let queue = self.digests.get_mut(&worker_id).unwrap();
+            let digest = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                self.credits.remove(&worker_id);
+            }
+            size += digest_size;
+            self.pending -= 1;
+            self.payload_size -= digest_size;
+            payload.push((digest, worker_id));
+        }
+        payload
+    }
+
+    /// Returns whether the pending digests are enough to fill a header.
+    fn enough_digests(&self) -> bool {
+        self.payload_size >= self.header_size
+            || (self.max_header_size > 0 && self.payload_size >= self.max_header_size)
+            || (self.max_header_num_of_batches > 0
+                && self.pending >= self.max_header_num_of_batches)
+    }
+
+    /// Publish the number of digests of each worker waiting to be included in our headers.
+    fn publish_backlog(&self) {
+        let backlog = self
+            .digests
+            .iter()
+            .map(|(id, queue)| (*id, queue.len()))
+            .collect();
+        *self.progress.worker_backlog.lock().unwrap() = backlog;
+    }
+
     async fn make_header(&mut self) {
-        // Make a new header.
+        // Remember the payload until it gets sequenced. The digests that do not fit wait for the next header.
+        let payload = self.take_payload();
+        if !payload.is_empty() {
+            self.unsequenced.insert(self.round, payload.clone());
+        }
+
+        // Make a new header (along with our share of the random coin of the round).
+        let coin = self.coin_key.as_ref().and_then(|key| {
+            key.coin_share(&Header::coin_seed(self.epoch, self.round), &mut OsRng)
+                .ok()
+        });
         let header = Header::new(
             self.name,
+            self.epoch,
             self.round,
-            self.digests.drain(..).collect(),
+            payload.into_iter().collect(),
             self.last_parents.drain(..).collect(),
+            self.ready.load(Ordering::Relaxed),
+            coin,
             &mut self.signature_service,
         )
         .await;
         debug!("Created {:?}", header);
+        self.progress.pipeline.record_header(header.round);
 
         #[cfg(feature = "benchmark")]
         for digest in header.payload.keys() {
@@ -103,34 +306,158 @@ impl Proposer {
             .expect("Failed to send header");
     }
 
+    /// Returns the delay to wait before the next header (if the last one was not full). If adaptive, we wait
+    /// about as long as it takes to gather a quorum of votes (bounded by `min_header_delay` and
+    /// `max_header_delay`): there is no point proposing faster than the round-trip to a quorum, and waiting
+    /// longer only adds latency.
+    fn header_delay(&self) -> u64 {
+        if !self.adaptive_header_delay {
+            return self.max_header_delay;
+        }
+        match self.progress.pipeline.vote_quorum_latency() {
+            Some(latency) => {
+                (latency.round() as u64).clamp(self.min_header_delay, self.max_header_delay)
+            }
+            None => self.max_header_delay,
+        }
+    }
+
+    /// Re-include into our next header the payload of our headers that have been garbage collected by
+    /// consensus without being sequenced. Otherwise the clients' transactions they carry would be lost.
+    fn requeue_unsequenced(&mut self) {
+        let round = self.consensus_round.load(Ordering::Relaxed);
+        if round <= self.gc_depth {
+            return;
+        }
+        let gc_round = round - self.gc_depth;
+        let expired: Vec<_> = self
+            .unsequenced
+            .keys()
+            .filter(|r| **r < gc_round)
+            .cloned()
+            .collect();
+        for r in expired {
+            let payload = self.unsequenced.remove(&r).unwrap();
+            debug!(
+                "Re-including {} digests of our unsequenced header of round {}",
+                payload.len(),
+                r
+            );
+            for (digest, worker_id) in payload {
+                self.push_digest(digest, worker_id);
+            }
+        }
+    }
+
+    /// Carry over to the next epoch the payload of our headers that was not sequenced by the end of this epoch
+    /// (along with the digests waiting for a header), and stop proposing headers. The sequenced headers are
+    /// all notified before the end of the epoch.
+    async fn carry_over(&mut self) {
+        let mut rounds: Vec<_> = self.unsequenced.keys().cloned().collect();
+        rounds.sort_unstable();
+        let mut payload: Vec<_> = rounds
+            .iter()
+            .flat_map(|r| self.unsequenced.remove(r).unwrap())
+            .collect();
+        for (worker_id, queue) in std::mem::take(&mut self.digests) {
+            payload.extend(queue.into_iter().map(|digest| (digest, worker_id)));
+        }
+        self.pending = 0;
+        self.payload_size = 0;
+
+        info!(
+            "Carrying over {} digests to epoch {}",
+            payload.len(),
+            self.epoch + 1
+        );
+        self.recovery.write_carried_payload(&payload).await;
+        self.ended = true;
+    }
+
+    /// Resume after our last header (if we restarted), so that we never propose two headers for the same
+    /// round. We then wait for the `Core` to deliver a quorum of certificates of the round of that header.
+    /// The payload carried over from the previous epoch is re-included into our next headers.
+    async fn recover(&mut self) {
+        let header = self
+            .recovery
+            .read_header()
+            .await
+            .expect("Failed to read our last header");
+        if let Some(header) = header {
+            self.round = header.round;
+            self.last_parents.clear();
+            self.parents_count = 0;
+        }
+
+        let carried = self
+            .recovery
+            .read_carried_payload()
+            .await
+            .expect("Failed to read the payload carried over");
+        if !carried.is_empty() {
+            info!(
+                "Re-including {} digests carried over from the previous epoch",
+                carried.len()
+            );
+        }
+        for (digest, worker_id) in carried {
+            self.push_digest(digest, worker_id);
+        }
+        self.recovery.remove_carried_payload().await;
+    }
+
     // Main loop listening to incoming messages.
     pub async fn run(&mut self) {
+        self.recover().await;
         debug!("Dag starting at round {}", self.round);
 
         let timer = sleep(Duration::from_millis(self.max_header_delay));
         tokio::pin!(timer);
+        let parents_timer = sleep(Duration::from_millis(self.max_parent_delay));
+        tokio::pin!(parents_timer);
 
         loop {
+            // Re-include the digests that will never be sequenced.
+            self.requeue_unsequenced();
+
             // Check if we can propose a new header. We propose a new header when one of the following
             // conditions is met:
             // 1. We have a quorum of certificates from the previous round and enough batches' digests;
             // 2. We have a quorum of certificates from the previous round and the specified maximum
             // inter-header delay has passed.
-            let enough_parents = !self.last_parents.is_empty();
-            let enough_digests = self.payload_size >= self.header_size;
+            // In both cases, we first wait (up to `max_parent_delay`) for the certificates of the remaining
+            // authorities.
+            let waiting_parents =
+                self.parents_count < self.committee_size && Instant::now() < self.parents_deadline;
+            let enough_parents = !self.last_parents.is_empty() && !waiting_parents;
+            let enough_digests = self.enough_digests();
             let timer_expired = timer.is_elapsed();
-            if (timer_expired || enough_digests) && enough_parents {
+            if (timer_expired || enough_digests) && enough_parents && !self.ended {
                 // Make a new header.
                 self.make_header().await;
-                self.payload_size = 0;
 
-                // Reschedule the timer.
-                let deadline = Instant::now() + Duration::from_millis(self.max_header_delay);
+                // Reschedule the timer. If the header was full, we only wait for the minimum delay so
+                // that the pending digests do not accumulate.
+                let delay = match self.pending == 0 {
+                    true => self.header_delay(),
+                    false => self.min_header_delay,
+                };
+                let deadline = Instant::now() + Duration::from_millis(delay);
                 timer.as_mut().reset(deadline);
             }
 
+            self.publish_backlog();
+
             tokio::select! {
                 Some((parents, round)) = self.rx_core.recv() => {
+                    // Late parents of our current round (received after the quorum).
+                    if round + 1 == self.round {
+                        self.parents_count += parents.len();
+                        if self.include_late_parents && !self.last_parents.is_empty() {
+                            self.last_parents.extend(parents);
+                        }
+                        continue;
+                    }
                     if round < self.round {
                         continue;
                     }
@@ -138,17 +465,44 @@ impl Proposer {
                     // Advance to the next round.
                     self.round = round + 1;
                     debug!("Dag moved to round {}", self.round);
+                    self.progress.pipeline.record_round_start(self.round);
 
                     // Signal that we have enough parent certificates to propose a new header.
+                    self.parents_count = parents.len();
                     self.last_parents = parents;
+                    self.parents_deadline = Instant::now() + Duration::from_millis(self.max_parent_delay);
+                    parents_timer.as_mut().reset(self.parents_deadline);
                 }
                 Some((digest, worker_id)) = self.rx_workers.recv() => {
-                    self.payload_size += digest.size();
-                    self.digests.push((digest, worker_id));
+                    self.push_digest(digest, worker_id);
+                }
+                Some(message) = self.rx_sequenced.recv() => match message {
+                    // The payload of this header is safe.
+                    ProposerMessage::Sequenced(round) => {
+                        self.unsequenced.remove(&round);
+                    }
+                    ProposerMessage::EndOfEpoch(ack) => {
+                        self.carry_over().await;
+                        let _ = ack.send(());
+                    }
+                },
+                Ok(()) = self.rx_parameters.changed() => {
+                    {
+                        let parameters = self.rx_parameters.borrow();
+                        self.max_header_delay = parameters.max_header_delay;
+                        self.min_header_delay = parameters.min_header_delay.min(parameters.max_header_delay);
+                    }
+
+                    // Wait for the next header with the new delay.
+                    let deadline = Instant::now() + Duration::from_millis(self.header_delay());
+                    timer.as_mut().reset(deadline);
                 }
                 () = &mut timer => {
                     // Nothing to do.
                 }
+                () = &mut parents_timer, if waiting_parents => {
+                    // Nothing to do.
+                }
             }
         }
     }
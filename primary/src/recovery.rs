@@ -0,0 +1,127 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::DagResult;
+use crate::messages::{Header, Vote};
+use crate::primary::Round;
+use config::{Epoch, WorkerId};
+use crypto::{Digest, PublicKey};
+use std::collections::HashSet;
+use store::Store;
+
+#[cfg(test)]
+#[path = "tests/recovery_tests.rs"]
+pub mod recovery_tests;
+
+/// The prefix of the key holding our last header.
+const HEADER_KEY_PREFIX: &[u8] = b"last_header:";
+
+/// The prefix of the key holding the votes received for our last header.
+const VOTES_KEY_PREFIX: &[u8] = b"own_votes:";
+
+/// The prefix of the keys holding the authors of the headers we voted for (one key per round).
+const VOTED_KEY_PREFIX: &[u8] = b"voted:";
+
+/// The prefix of the key holding the payload carried over from the previous epoch.
+const CARRIED_KEY_PREFIX: &[u8] = b"carried_payload:";
+
+/// Persists the state of the primary that cannot be rebuilt from the stored certificates, so that a restarted
+/// primary never proposes nor votes for two different headers of the same round: our last header along with
+/// the votes it gathered, and the headers we voted for. Each epoch has its own state (along with the payload
+/// of our headers the previous epoch did not sequence).
+#[derive(Clone)]
+pub struct RecoveryStore {
+    /// The persistent storage.
+    store: Store,
+    /// The current epoch.
+    epoch: Epoch,
+}
+
+impl RecoveryStore {
+    pub fn new(store: Store, epoch: Epoch) -> Self {
+        Self { store, epoch }
+    }
+
+    fn key(&self, prefix: &[u8]) -> Vec<u8> {
+        [prefix, &self.epoch.to_be_bytes()].concat()
+    }
+
+    fn carried_key(&self, epoch: Epoch) -> Vec<u8> {
+        [CARRIED_KEY_PREFIX, &epoch.to_be_bytes()].concat()
+    }
+
+    fn voted_key(&self, round: Round) -> Vec<u8> {
+        [
+            VOTED_KEY_PREFIX,
+            &self.epoch.to_be_bytes(),
+            &round.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Returns our last header (if any).
+    pub async fn read_header(&mut self) -> DagResult<Option<Header>> {
+        match self.store.read(self.key(HEADER_KEY_PREFIX)).await? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Persist our last header.
+    pub async fn write_header(&mut self, header: &Header) {
+        let bytes = bincode::serialize(header).expect("Failed to serialize header");
+        self.store.write(self.key(HEADER_KEY_PREFIX), bytes).await;
+    }
+
+    /// Returns the votes received for our last header (along with the id of the header).
+    pub async fn read_votes(&mut self) -> DagResult<Option<(Digest, Vec<Vote>)>> {
+        match self.store.read(self.key(VOTES_KEY_PREFIX)).await? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Persist the votes received for our last header.
+    pub async fn write_votes(&mut self, header: &Digest, votes: &[Vote]) {
+        let bytes = bincode::serialize(&(header, votes)).expect("Failed to serialize votes");
+        self.store.write(self.key(VOTES_KEY_PREFIX), bytes).await;
+    }
+
+    /// Returns the authors of the headers of the specified round we voted for.
+    pub async fn read_voted(&mut self, round: Round) -> DagResult<HashSet<PublicKey>> {
+        match self.store.read(self.voted_key(round)).await? {
+            Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+            None => Ok(HashSet::new()),
+        }
+    }
+
+    /// Persist the authors of the headers of the specified round we voted for.
+    pub async fn write_voted(&mut self, round: Round, authors: &HashSet<PublicKey>) {
+        let bytes = bincode::serialize(authors).expect("Failed to serialize authors");
+        self.store.write(self.voted_key(round), bytes).await;
+    }
+
+    /// Forget the headers of the specified round we voted for (once the round is garbage collected).
+    pub async fn remove_voted(&mut self, round: Round) {
+        self.store.delete(self.voted_key(round)).await;
+    }
+
+    /// Returns the payload carried over from the previous epoch (to re-include into our headers).
+    pub async fn read_carried_payload(&mut self) -> DagResult<Vec<(Digest, WorkerId)>> {
+        match self.store.read(self.carried_key(self.epoch)).await? {
+            Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Persist the payload to carry over to the next epoch (not sequenced by the end of this epoch).
+    pub async fn write_carried_payload(&mut self, payload: &[(Digest, WorkerId)]) {
+        let bytes = bincode::serialize(payload).expect("Failed to serialize payload");
+        self.store
+            .write(self.carried_key(self.epoch + 1), bytes)
+            .await;
+    }
+
+    /// Forget the payload carried over from the previous epoch (once re-included).
+    pub async fn remove_carried_payload(&mut self) {
+        self.store.delete(self.carried_key(self.epoch)).await;
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::DagResult;
+use crate::messages::Certificate;
+use crate::primary::Round;
+use config::Epoch;
+use crypto::Hash as _;
+use crypto::{Digest, PublicKey};
+use std::convert::TryInto;
+use store::Store;
+
+#[cfg(test)]
+#[path = "tests/round_index_tests.rs"]
+pub mod round_index_tests;
+
+/// The prefix of the keys of the index by round (distinct from the 32-byte digests and 36-byte batch keys).
+const ROUND_KEY_PREFIX: &[u8] = b"cert_round:";
+
+/// The prefix of the keys of the index by author.
+const AUTHOR_KEY_PREFIX: &[u8] = b"cert_author:";
+
+/// The prefix of the key holding the highest indexed round.
+const LAST_ROUND_KEY_PREFIX: &[u8] = b"last_round:";
+
+/// Indexes the certificates of the store by round and by author, so that we can serve requests for ranges of
+/// rounds (or for the certificates of an authority) with range scans of the store. Each certificate has one
+/// key in each index (holding its digest): `round || author` and `author || round`. The index is only written
+/// by the `Core` (the only task storing certificates). Each epoch has its own index, since rounds start over
+/// at every epoch.
+#[derive(Clone)]
+pub struct RoundIndex {
+    /// The persistent storage.
+    store: Store,
+    /// The current epoch.
+    epoch: Epoch,
+    /// The highest round indexed by this instance (loaded from storage on the first insert).
+    last_round: Option<Round>,
+}
+
+impl RoundIndex {
+    pub fn new(store: Store, epoch: Epoch) -> Self {
+        Self {
+            store,
+            epoch,
+            last_round: None,
+        }
+    }
+
+    /// The prefix of the keys of the certificates of a round.
+    fn round_prefix(&self, round: Round) -> Vec<u8> {
+        [
+            ROUND_KEY_PREFIX,
+            &self.epoch.to_be_bytes(),
+            &round.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// The prefix of the keys of the certificates of an author.
+    fn author_prefix(&self, author: &PublicKey) -> Vec<u8> {
+        [AUTHOR_KEY_PREFIX, &self.epoch.to_be_bytes(), &author.0].concat()
+    }
+
+    /// The key holding the highest indexed round.
+    fn last_round_key(&self) -> Vec<u8> {
+        [LAST_ROUND_KEY_PREFIX, &self.epoch.to_be_bytes()].concat()
+    }
+
+    /// Returns the first key after all the keys starting with the specified prefix.
+    fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+        let mut end = prefix.to_vec();
+        while let Some(byte) = end.pop() {
+            if byte < u8::MAX {
+                end.push(byte + 1);
+                break;
+            }
+        }
+        end
+    }
+
+    /// Returns the highest round for which we indexed a certificate (0 if none).
+    pub async fn last_round(&mut self) -> DagResult<Round> {
+        match self.store.read(self.last_round_key()).await? {
+            Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the digests of the certificates we stored for the specified round (sorted by author).
+    pub async fn read(&mut self, round: Round) -> DagResult<Vec<Digest>> {
+        let prefix = self.round_prefix(round);
+        let entries = self
+            .store
+            .read_range(prefix.clone(), Self::prefix_end(&prefix))
+            .await?;
+        entries
+            .iter()
+            .map(|(_, value)| Ok(bincode::deserialize(value)?))
+            .collect()
+    }
+
+    /// Returns the digests of the certificates of an author for the rounds `start` to `end` (inclusive), along
+    /// with their round (sorted by round).
+    pub async fn read_author(
+        &mut self,
+        author: &PublicKey,
+        start: Round,
+        end: Round,
+    ) -> DagResult<Vec<(Round, Digest)>> {
+        let prefix = self.author_prefix(author);
+        let from = [&prefix[..], &start.to_be_bytes()].concat();
+        let to = match end.checked_add(1) {
+            Some(end) => [&prefix[..], &end.to_be_bytes()].concat(),
+            None => Self::prefix_end(&prefix),
+        };
+        let entries = self.store.read_range(from, to).await?;
+        entries
+            .iter()
+            .map(|(key, value)| {
+                let round = key[prefix.len()..].try_into().expect("Invalid index key");
+                Ok((Round::from_be_bytes(round), bincode::deserialize(value)?))
+            })
+            .collect()
+    }
+
+    /// Remove a round from the index (once its certificates are pruned).
+    pub async fn remove(&mut self, round: Round) -> DagResult<()> {
+        let prefix = self.round_prefix(round);
+        let entries = self
+            .store
+            .read_range(prefix.clone(), Self::prefix_end(&prefix))
+            .await?;
+        for (key, _) in entries {
+            let author = key[prefix.len()..].try_into().expect("Invalid index key");
+            let author_key = [
+                &self.author_prefix(&PublicKey(author))[..],
+                &round.to_be_bytes(),
+            ]
+            .concat();
+            self.store.delete(author_key).await;
+            self.store.delete(key).await;
+        }
+        Ok(())
+    }
+
+    /// Add a certificate to the index.
+    pub async fn insert(&mut self, certificate: &Certificate) -> DagResult<()> {
+        let round = certificate.round();
+        let author = certificate.origin();
+        let bytes = bincode::serialize(&certificate.digest()).expect("Failed to serialize digest");
+        let round_key = [&self.round_prefix(round)[..], &author.0].concat();
+        self.store.write(round_key, bytes.clone()).await;
+        let author_key = [&self.author_prefix(&author)[..], &round.to_be_bytes()].concat();
+        self.store.write(author_key, bytes).await;
+
+        let last_round = match self.last_round {
+            Some(round) => round,
+            None => self.last_round().await?,
+        };
+        if round > last_round {
+            let bytes = bincode::serialize(&round).expect("Failed to serialize round");
+            self.store.write(self.last_round_key(), bytes).await;
+        }
+        self.last_round = Some(round.max(last_round));
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::DagResult;
+use crate::messages::{Certificate, Header};
+use crate::primary::Round;
+use crate::round_index::RoundIndex;
+use config::{Epoch, WorkerId};
+use crypto::Hash as _;
+use crypto::{CoinShare, Digest, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write as _};
+use std::str::FromStr;
+use store::Store;
+
+#[cfg(test)]
+#[path = "tests/snapshot_tests.rs"]
+pub mod snapshot_tests;
+
+/// The file format of a DAG snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// Human-readable (digests and keys are base64-encoded).
+    Json,
+    /// Compact (bincode).
+    Binary,
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "binary" => Ok(Self::Binary),
+            x => Err(format!("Unknown snapshot format '{}'", x)),
+        }
+    }
+}
+
+/// A vertex of the DAG: a certificate along with the links to its parents.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Vertex {
+    /// The digest of the certificate.
+    pub digest: Digest,
+    /// The round of the certificate.
+    pub round: Round,
+    /// The author of the certified header.
+    pub author: PublicKey,
+    /// The digest of the certified header.
+    pub header: Digest,
+    /// The digests of the parent certificates.
+    pub parents: Vec<Digest>,
+    /// The digests of the batches referenced by the header (along with their worker id).
+    pub payload: Vec<(Digest, WorkerId)>,
+    /// The share of the author of the random coin of the round (if any).
+    #[serde(default)]
+    pub coin: Option<CoinShare>,
+}
+
+impl From<&Certificate> for Vertex {
+    fn from(certificate: &Certificate) -> Self {
+        let header = &certificate.header;
+        Self {
+            digest: certificate.digest(),
+            round: header.round,
+            author: header.author,
+            header: header.id.clone(),
+            parents: header.parents.iter().cloned().collect(),
+            payload: header
+                .payload
+                .iter()
+                .map(|(x, y)| (x.clone(), *y))
+                .collect(),
+            coin: header.coin.clone(),
+        }
+    }
+}
+
+/// The structure of the DAG stored by a primary, so that a run can be analyzed or visualized offline.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct DagSnapshot {
+    /// The epoch of the DAG.
+    pub epoch: Epoch,
+    /// The vertices of the DAG, ordered by round.
+    pub vertices: Vec<Vertex>,
+}
+
+impl DagSnapshot {
+    /// Read from storage the certificates of the rounds `from..=to` of the specified epoch (up to the highest
+    /// stored round if `to` is not specified). Rounds that were pruned from storage are skipped.
+    pub async fn load(
+        store: Store,
+        epoch: Epoch,
+        from: Round,
+        to: Option<Round>,
+    ) -> DagResult<Self> {
+        let mut round_index = RoundIndex::new(store.clone(), epoch);
+        let to = match to {
+            Some(round) => round,
+            None => round_index.last_round().await?,
+        };
+
+        let mut store = store;
+        let mut vertices = Vec::new();
+        for round in from..=to {
+            for digest in round_index.read(round).await? {
+                if let Some(bytes) = store.read(digest.to_vec()).await? {
+                    let certificate: Certificate = bincode::deserialize(&bytes)?;
+                    vertices.push(Vertex::from(&certificate));
+                }
+            }
+        }
+        Ok(Self { epoch, vertices })
+    }
+
+    /// Returns the certificates of the snapshot, without their votes and signatures. They have the same digests
+    /// (and parents) as the originals, eg. to replay the DAG through consensus.
+    pub fn certificates(&self) -> Vec<Certificate> {
+        self.vertices
+            .iter()
+            .map(|vertex| Certificate {
+                header: Header {
+                    author: vertex.author,
+                    epoch: self.epoch,
+                    round: vertex.round,
+                    payload: vertex.payload.iter().cloned().collect(),
+                    parents: vertex.parents.iter().cloned().collect(),
+                    coin: vertex.coin.clone(),
+                    id: vertex.header.clone(),
+                    ..Header::default()
+                },
+                ..Certificate::default()
+            })
+            .collect()
+    }
+
+    /// Write the snapshot to a file.
+    pub fn export(&self, path: &str, format: SnapshotFormat) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            SnapshotFormat::Json => serde_json::to_writer_pretty(&mut writer, self)?,
+            SnapshotFormat::Binary => bincode::serialize_into(&mut writer, self)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        }
+        writer.flush()
+    }
+
+    /// Read a snapshot from a file.
+    pub fn import(path: &str, format: SnapshotFormat) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        match format {
+            SnapshotFormat::Json => Ok(serde_json::from_reader(reader)?),
+            SnapshotFormat::Binary => bincode::deserialize_from(reader)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::certificate_fetcher::CertificateFetcher;
+use crate::introspection::PendingVotes;
+use crate::metrics::{
+    ChainQualityMetrics, CommitLatencyMetrics, DagShapeMetrics, PipelineMetrics, SubDagMetrics,
+};
+use crate::primary::{PrimaryMessage, Round};
+use config::{Committee, WorkerId};
+use crypto::PublicKey;
+use log::{debug, warn};
+use network::{Address, NetworkMetrics, SharedTransport};
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/state_synchronizer_tests.rs"]
+pub mod state_synchronizer_tests;
+
+/// The maximum number of rounds requested (and served) at once.
+pub const MAX_SYNC_RANGE: Round = 20;
+
+/// The resolution of the timer that checks whether we are lagging behind.
+const TIMER_RESOLUTION: u64 = 500;
+
+/// The progress of our local DAG. It is updated by the `Core` and the `Proposer`, and read by the
+/// `StateSynchronizer` (and the introspection API).
+#[derive(Debug, Default)]
+pub struct DagProgress {
+    /// The highest round of the (valid) certificates we received.
+    pub observed_round: AtomicU64,
+    /// The highest round for which we gathered a quorum of certificates.
+    pub dag_round: AtomicU64,
+    /// The number of certificates we stored (formed or received).
+    pub certificates: AtomicU64,
+    /// The number of times the DAG stalled (see `StallWatchdog`).
+    pub stalls: AtomicU64,
+    /// The number of leaders consensus stopped waiting for (see `LeaderSkip`).
+    pub leader_skips: AtomicU64,
+    /// The votes gathered so far for our last header.
+    pub pending_votes: Mutex<PendingVotes>,
+    /// The latencies of the pipeline of the primary.
+    pub pipeline: PipelineMetrics,
+    /// The commit latencies of the certificates, per wave.
+    pub commit_latency: CommitLatencyMetrics,
+    /// The chain quality and inclusion fairness of every authority.
+    pub chain_quality: ChainQualityMetrics,
+    /// The shape of the DAG (width, skipped leaders, orphaned certificates).
+    pub dag_shape: DagShapeMetrics,
+    /// The structure of the most recent committed sub-dags.
+    pub sub_dags: SubDagMetrics,
+    /// The number of batches' digests of each of our workers waiting to be included in our headers.
+    pub worker_backlog: Mutex<BTreeMap<WorkerId, usize>>,
+    /// The authorities excluded for proven misbehavior (see `exclude_misbehaving`), along with the round
+    /// of the first evidence against them.
+    pub excluded: Mutex<BTreeMap<PublicKey, Round>>,
+}
+
+/// Detects when our DAG lags far behind the certificates we observe (eg. after a restart or a partition),
+/// and fetches the missing rounds in bulk from other primaries. The certificates we receive in reply are
+/// verified and handed to the `Core` in round order, which processes them as usual (and also syncs their
+/// payload), fast-forwarding our DAG.
+pub struct StateSynchronizer {
+    /// The public key of this primary.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The progress of our local DAG.
+    progress: Arc<DagProgress>,
+    /// How many rounds behind the observed certificates we tolerate before catching up.
+    catch_up_threshold: Round,
+    /// The delay to wait before re-trying sync requests.
+    sync_retry_delay: u64,
+    /// Determine from how many nodes (at most) to try fetching each range.
+    sync_retry_nodes: usize,
+    /// Fetches the certificates of a range of rounds from a peer.
+    fetcher: CertificateFetcher,
+    /// The index (among the other primaries) of the next peer to fetch from.
+    next_peer: usize,
+    /// The last round of the last range we requested, along with the time of the request.
+    last_request: Option<(Round, Instant)>,
+    /// Output channel to deliver the fetched certificates to the `Core`.
+    tx_core: Sender<PrimaryMessage>,
+}
+
+impl StateSynchronizer {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        committee: Committee,
+        progress: Arc<DagProgress>,
+        catch_up_threshold: Round,
+        sync_retry_delay: u64,
+        sync_retry_nodes: usize,
+        transport: SharedTransport,
+        network_metrics: NetworkMetrics,
+        tx_core: Sender<PrimaryMessage>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                fetcher: CertificateFetcher::new(
+                    name,
+                    committee.clone(),
+                    /* timeout */ sync_retry_delay,
+                    transport,
+                    network_metrics,
+                ),
+                name,
+                committee,
+                progress,
+                catch_up_threshold,
+                sync_retry_delay,
+                sync_retry_nodes,
+                next_peer: 0,
+                last_request: None,
+                tx_core,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Returns the range of rounds (inclusive) to request, if we are lagging behind. We only request a new
+    /// range once we processed the previous one, or after `sync_retry_delay` if we did not hear back.
+    fn next_range(&self) -> Option<(Round, Round)> {
+        let observed_round = self.progress.observed_round.load(Ordering::Relaxed);
+        let dag_round = self.progress.dag_round.load(Ordering::Relaxed);
+        if observed_round <= dag_round.saturating_add(self.catch_up_threshold) {
+            return None;
+        }
+
+        if let Some((end, time)) = self.last_request {
+            let timeout = Duration::from_millis(self.sync_retry_delay);
+            if dag_round < end && time.elapsed() < timeout {
+                return None;
+            }
+        }
+
+        let start = dag_round + 1;
+        Some((start, min(observed_round, start + MAX_SYNC_RANGE - 1)))
+    }
+
+    /// Fetch the rounds `start` to `end` from a single peer, possibly over several requests (each reply
+    /// may only cover the beginning of the range). Returns the next round to fetch.
+    async fn fetch_from(&mut self, address: Address, start: Round, end: Round) -> Round {
+        let mut next = start;
+        while next <= end {
+            let range = match self.fetcher.fetch(address.clone(), next, end).await {
+                Ok(range) => range,
+                Err(e) => {
+                    warn!("Failed to sync with {}: {}", address, e);
+                    break;
+                }
+            };
+            if range.end < next {
+                debug!("{} does not have round {}", address, next);
+                break;
+            }
+            for certificate in range.certificates {
+                self.tx_core
+                    .send(PrimaryMessage::Certificate(certificate))
+                    .await
+                    .expect("Failed to send certificate");
+            }
+            next = range.end + 1;
+        }
+        next
+    }
+
+    async fn run(&mut self) {
+        if self.catch_up_threshold == 0 {
+            return;
+        }
+
+        loop {
+            sleep(Duration::from_millis(TIMER_RESOLUTION)).await;
+
+            if let Some((start, end)) = self.next_range() {
+                debug!("Lagging behind: requesting rounds {} to {}", start, end);
+                let addresses: Vec<_> = self
+                    .committee
+                    .others_primaries(&self.name)
+                    .into_iter()
+                    .map(|(_, x)| x.primary_to_primary.advertise)
+                    .collect();
+
+                // Try the peers in turn until we get the whole range.
+                let mut next = start;
+                for _ in 0..min(self.sync_retry_nodes, addresses.len()) {
+                    let address = addresses[self.next_peer % addresses.len()].clone();
+                    self.next_peer = self.next_peer.wrapping_add(1);
+                    next = self.fetch_from(address, next, end).await;
+                    if next > end {
+                        break;
+                    }
+                }
+                self.last_request = Some((end, Instant::now()));
+            }
+        }
+    }
+}
@@ -1,10 +1,11 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::error::DagResult;
+use crate::error::{DagError, DagResult};
 use crate::header_waiter::WaiterMessage;
 use crate::messages::{Certificate, Header};
 use config::Committee;
 use crypto::Hash as _;
 use crypto::{Digest, PublicKey};
+use futures::stream::{self, StreamExt as _, TryStreamExt as _};
 use std::collections::HashMap;
 use store::Store;
 use tokio::sync::mpsc::Sender;
@@ -126,13 +127,55 @@ impl Synchronizer {
             }
 
             if self.store.read(digest.to_vec()).await?.is_none() {
-                self.tx_certificate_waiter
-                    .send(certificate.clone())
-                    .await
-                    .expect("Failed to send sync certificate request");
+                self.suspend_certificate(certificate.clone()).await;
                 return Ok(false);
             };
         }
         Ok(true)
     }
+
+    /// Returns, for each certificate, the parents missing from our storage. The certificates are checked
+    /// concurrently (at most `concurrency` at a time). Unlike `deliver_certificate`, this function does not
+    /// trigger any synchronization.
+    pub async fn missing_parents(
+        &self,
+        certificates: &[Certificate],
+        concurrency: usize,
+    ) -> DagResult<Vec<Vec<Digest>>> {
+        let lookups: Vec<_> = certificates
+            .iter()
+            .map(|certificate| {
+                let parents: Vec<_> = certificate
+                    .header
+                    .parents
+                    .iter()
+                    .filter(|digest| !self.genesis.iter().any(|(x, _)| x == *digest))
+                    .cloned()
+                    .collect();
+                let mut store = self.store.clone();
+                async move {
+                    let mut missing = Vec::new();
+                    for digest in parents {
+                        if store.read(digest.to_vec()).await?.is_none() {
+                            missing.push(digest);
+                        }
+                    }
+                    Ok::<_, DagError>(missing)
+                }
+            })
+            .collect();
+        stream::iter(lookups)
+            .buffered(concurrency.max(1))
+            .try_collect()
+            .await
+    }
+
+    /// Send the certificate to the `CertificateWaiter`, which will trigger re-processing once we have all its
+    /// ancestors.
+    pub async fn suspend_certificate(&mut self, certificate: Certificate) {
+        self.tx_certificate_waiter
+            .send(certificate)
+            .await
+            .expect("Failed to send sync certificate request");
+    }
 }
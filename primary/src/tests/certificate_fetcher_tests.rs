@@ -0,0 +1,86 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, headers, keys, transport};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use network::NetworkMetrics;
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+// Spawn a peer replying to a single range request with the specified certificates.
+fn peer(address: Address, reply: CertificateRange) -> JoinHandle<PrimaryMessage> {
+    tokio::spawn(async move {
+        let mut listener = transport().listen(&address.listen_address()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (mut writer, mut reader) = Framed::new(socket, LengthDelimitedCodec::new()).split();
+        let received = reader.next().await.unwrap().unwrap();
+        let bytes = bincode::serialize(&reply).unwrap();
+        writer.send(Bytes::from(bytes)).await.unwrap();
+        bincode::deserialize(&received).unwrap()
+    })
+}
+
+#[tokio::test]
+async fn fetch_range() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(13_800);
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+
+    // Spawn a peer holding the certificates of round 1.
+    let (_, authority) = committee.others_primaries(&name).pop().unwrap();
+    let address = authority.primary_to_primary.advertise;
+    let reply = CertificateRange {
+        end: 1,
+        certificates: certificates.clone(),
+    };
+    let handle = peer(address.clone(), reply);
+
+    // Fetch the range and ensure we get all the certificates.
+    let mut fetcher = CertificateFetcher::new(
+        name,
+        committee,
+        /* timeout */ 1_000,
+        transport(),
+        NetworkMetrics::default(),
+    );
+    let range = fetcher.fetch(address, 1, 10).await.unwrap();
+    assert_eq!(range.end, 1);
+    assert_eq!(range.certificates, certificates);
+
+    // Ensure the peer received our request.
+    match handle.await.unwrap() {
+        PrimaryMessage::CertificatesRangeRequest(start, end, requestor) => {
+            assert_eq!(start, 1);
+            assert_eq!(end, 10);
+            assert_eq!(requestor, name);
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn reject_invalid_range() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(13_900);
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+
+    // Spawn a peer replying with certificates outside the requested range.
+    let (_, authority) = committee.others_primaries(&name).pop().unwrap();
+    let address = authority.primary_to_primary.advertise;
+    let reply = CertificateRange {
+        end: 5,
+        certificates,
+    };
+    let _handle = peer(address.clone(), reply);
+
+    // Ensure we reject the reply.
+    let mut fetcher = CertificateFetcher::new(
+        name,
+        committee,
+        /* timeout */ 1_000,
+        transport(),
+        NetworkMetrics::default(),
+    );
+    let result = fetcher.fetch(address, 5, 10).await;
+    assert!(matches!(result, Err(DagError::InvalidRangeReply(5, 10))));
+}
@@ -0,0 +1,74 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, headers, keys};
+use crate::messages::Header;
+use std::collections::BTreeSet;
+
+fn event(round: Round) -> CommitEvent {
+    let (leader, _) = keys().pop().unwrap();
+    CommitEvent {
+        round,
+        wave: round / 2,
+        leader,
+        leader_round: round,
+        author: leader,
+        certificate: Digest([round as u8; 32]),
+        latency: 0.0,
+    }
+}
+
+#[tokio::test]
+async fn publish_events() {
+    let bus = CommitEventBus::new();
+
+    // Publishing without subscribers is fine.
+    bus.publish(event(1));
+
+    // Every subscriber receives all the later events, in order.
+    let mut first = bus.subscribe();
+    let mut second = bus.clone().subscribe();
+    let watcher = bus.watch();
+    bus.publish(event(2));
+    bus.publish(event(3));
+    for subscriber in [&mut first, &mut second].iter_mut() {
+        assert_eq!(subscriber.recv().await.unwrap(), event(2));
+        assert_eq!(subscriber.recv().await.unwrap(), event(3));
+    }
+
+    // Watchers only observe the latest event.
+    assert_eq!(*watcher.borrow(), Some(event(3)));
+}
+
+#[test]
+fn sub_dag_structure() {
+    // A leader of round 2 committing the certificates of round 1 it links to.
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+    let (author, _) = keys().pop().unwrap();
+    let leader = certificate(&Header {
+        author,
+        round: 2,
+        parents: certificates.iter().map(|x| x.digest()).collect(),
+        ..Header::default()
+    });
+    let sub_dag = CommittedSubDag {
+        leader: leader.clone(),
+        certificates: certificates
+            .iter()
+            .cloned()
+            .chain(std::iter::once(leader.clone()))
+            .collect(),
+    };
+
+    // The structure holds the parent links of every committed certificate, in commit order.
+    let structure = SubDagStructure::new(&sub_dag);
+    assert_eq!(structure.leader_round, 2);
+    assert_eq!(structure.leader, author);
+    assert_eq!(structure.depth(), 2);
+    assert_eq!(structure.vertices.len(), sub_dag.len());
+    for (vertex, certificate) in structure.vertices.iter().zip(&sub_dag.certificates) {
+        assert_eq!(vertex.certificate, certificate.digest());
+        assert_eq!(vertex.round, certificate.round());
+        let parents: BTreeSet<_> = vertex.parents.iter().cloned().collect();
+        assert_eq!(parents, certificate.header.parents);
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header, keys};
+use crate::messages::Header;
+use crypto::Signature;
+use std::fs;
+use tokio::time::{sleep, Duration};
+
+// Fixture: the certificate of the leader of round 1, and the certificates of round 2 of all authorities (only
+// the first `support` of them reference the leader).
+fn certificates(support: usize) -> (Certificate, Vec<Certificate>) {
+    let leader = certificate(&header());
+    let children = keys()
+        .into_iter()
+        .enumerate()
+        .map(|(i, (author, secret))| {
+            let parents = match i < support {
+                true => [leader.digest()].iter().cloned().collect(),
+                false => Default::default(),
+            };
+            let header = Header {
+                author,
+                round: 2,
+                parents,
+                ..Header::default()
+            };
+            certificate(&Header {
+                id: header.digest(),
+                signature: Signature::new(&header.digest(), &secret),
+                ..header
+            })
+        })
+        .collect();
+    (leader, children)
+}
+
+#[test]
+fn verify_commit_proof() {
+    let committee = committee();
+
+    // The leader is supported by f+1 certificates.
+    let (leader, children) = certificates(2);
+    let proof = CommitProof {
+        leader: leader.clone(),
+        support: children[..2].to_vec(),
+    };
+    assert!(proof.verify(&committee).is_ok());
+
+    // A single support certificate is not enough, nor twice the same.
+    let proof = CommitProof {
+        leader: leader.clone(),
+        support: children[..1].to_vec(),
+    };
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(DagError::InvalidCommitProof(_))
+    ));
+    let proof = CommitProof {
+        leader: leader.clone(),
+        support: vec![children[0].clone(), children[0].clone()],
+    };
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(DagError::AuthorityReuse(_))
+    ));
+
+    // The support certificates must reference the leader.
+    let proof = CommitProof {
+        leader,
+        support: children[1..3].to_vec(),
+    };
+    assert!(matches!(
+        proof.verify(&committee),
+        Err(DagError::InvalidCommitProof(_))
+    ));
+}
+
+#[tokio::test]
+async fn persist_commit_proofs() {
+    let path = ".db_test_persist_commit_proofs";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Publish the proof of the leader of round 1.
+    let commit_events = CommitEventBus::new();
+    CommitProof::persist(&commit_events, store.clone(), /* epoch */ 0);
+    let (leader, children) = certificates(2);
+    commit_events.publish_proof(CommitProof {
+        leader: leader.clone(),
+        support: children[..2].to_vec(),
+    });
+    sleep(Duration::from_millis(50)).await;
+
+    // The proof is stored under the round of the leader.
+    let bytes = store.read(CommitProof::key(0, 1)).await.unwrap().unwrap();
+    let proof: CommitProof = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(proof.leader.digest(), leader.digest());
+    assert!(proof.verify(&committee()).is_ok());
+    assert!(store.read(CommitProof::key(0, 2)).await.unwrap().is_none());
+}
@@ -0,0 +1,61 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header};
+use crate::messages::Header;
+use crypto::Hash as _;
+use futures::stream::StreamExt as _;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+#[tokio::test]
+async fn stream_commits() {
+    let address = "127.0.0.1:14900".parse::<SocketAddr>().unwrap();
+    let (tx_consensus, rx_consensus) = channel(10);
+    let (tx_output, mut rx_output) = channel(10);
+    CommitStream::spawn(address, rx_consensus, tx_output);
+    sleep(Duration::from_millis(50)).await;
+
+    // Subscribe to the commits, along with their batches.
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    let subscription = CommitSubscription { batches: true };
+    let bytes = bincode::serialize(&subscription).unwrap();
+    transport.send(Bytes::from(bytes)).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    // Commit two certificates (the second one commits the first one).
+    let first = certificate(&header());
+    let mut second = certificate(&Header {
+        round: 2,
+        payload: vec![(Digest([1; 32]), 0)].into_iter().collect(),
+        ..header()
+    });
+    second.header.id = second.header.digest();
+    let sub_dag = CommittedSubDag {
+        leader: second.clone(),
+        certificates: vec![first.clone(), second.clone()],
+    };
+    tx_consensus.send(sub_dag).await.unwrap();
+
+    // The sub-dag is still forwarded downstream...
+    let output = rx_output.recv().await.unwrap();
+    assert_eq!(output.leader.digest(), second.digest());
+    assert_eq!(output.len(), 2);
+
+    // ...and streamed to the subscriber in commit order.
+    for (index, expected) in vec![first, second].into_iter().enumerate() {
+        let bytes = transport.next().await.unwrap().unwrap();
+        let commit: CommittedCertificate = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(commit.index, index as u64);
+        assert_eq!(commit.certificate.digest(), expected.digest());
+        let batches: Vec<_> = expected
+            .header
+            .payload
+            .iter()
+            .map(|(digest, id)| (digest.clone(), *id))
+            .collect();
+        assert_eq!(commit.batches, batches);
+    }
+}
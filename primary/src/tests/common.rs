@@ -3,13 +3,16 @@ use crate::messages::{Certificate, Header, Vote};
 use bytes::Bytes;
 use config::{Authority, Committee, PrimaryAddresses, WorkerAddresses};
 use crypto::Hash as _;
-use crypto::{generate_keypair, PublicKey, SecretKey, Signature};
+use crypto::{
+    generate_bls_keypair, generate_keypair, BlsProofOfPossession, BlsPublicKey, BlsSecretKey,
+    PublicKey, SecretKey, Signature,
+};
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
+use network::{Address, MemoryTransport, SharedTransport};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
-use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use std::sync::{Arc, OnceLock};
 use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
@@ -31,6 +34,12 @@ pub fn keys() -> Vec<(PublicKey, SecretKey)> {
     (0..4).map(|_| generate_keypair(&mut rng)).collect()
 }
 
+// Fixture
+pub fn bls_keys() -> Vec<(BlsPublicKey, BlsSecretKey)> {
+    let mut rng = StdRng::from_seed([0; 32]);
+    (0..4).map(|_| generate_bls_keypair(&mut rng)).collect()
+}
+
 // Fixture
 pub fn committee() -> Committee {
     Committee {
@@ -47,7 +56,12 @@ pub fn committee() -> Committee {
                     WorkerAddresses {
                         primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
                         transactions: format!("127.0.0.1:{}", 400 + i).parse().unwrap(),
+                        additional_transactions: Vec::new(),
                         worker_to_worker: format!("127.0.0.1:{}", 500 + i).parse().unwrap(),
+                        grpc: None,
+                        http: None,
+                        executor: None,
+                        metrics: None,
                     },
                 )]
                 .iter()
@@ -59,13 +73,29 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        bls_public_key: None,
+                        bls_proof_of_possession: None,
                     },
                 )
             })
             .collect(),
+        epoch: 0,
+        threshold_key: None,
     }
 }
 
+// Fixture
+pub fn committee_with_bls_keys() -> Committee {
+    let mut committee = committee();
+    for ((name, _), (bls_public_key, bls_secret)) in keys().iter().zip(bls_keys()) {
+        let authority = committee.authorities.get_mut(name).unwrap();
+        authority.bls_public_key = Some(bls_public_key);
+        authority.bls_proof_of_possession =
+            Some(BlsProofOfPossession::new(&bls_public_key, &bls_secret));
+    }
+    committee
+}
+
 // Fixture.
 pub fn committee_with_base_port(base_port: u16) -> Committee {
     let mut committee = committee();
@@ -111,6 +141,20 @@ pub fn header() -> Header {
     }
 }
 
+// Fixture: a header of the same author and round as `header()`, with a different content.
+pub fn conflicting_header() -> Header {
+    let (_, secret) = keys().pop().unwrap();
+    let header = Header {
+        reconfigure: true,
+        ..header()
+    };
+    Header {
+        id: header.digest(),
+        signature: Signature::new(&header.digest(), &secret),
+        ..header
+    }
+}
+
 // Fixture
 pub fn headers() -> Vec<Header> {
     keys()
@@ -145,6 +189,7 @@ pub fn votes(header: &Header) -> Vec<Vote> {
                 origin: header.author,
                 author,
                 signature: Signature::default(),
+                bls_signature: None,
             };
             Vote {
                 signature: Signature::new(&vote.digest(), &secret),
@@ -162,13 +207,23 @@ pub fn certificate(header: &Header) -> Certificate {
             .into_iter()
             .map(|x| (x.author, x.signature))
             .collect(),
+        aggregate: None,
     }
 }
 
 // Fixture
-pub fn listener(address: SocketAddr) -> JoinHandle<Bytes> {
+pub fn transport() -> SharedTransport {
+    // All the tests of the crate share the same in-memory network (they each use their own ports).
+    static TRANSPORT: OnceLock<SharedTransport> = OnceLock::new();
+    TRANSPORT
+        .get_or_init(|| Arc::new(MemoryTransport::new()))
+        .clone()
+}
+
+// Fixture
+pub fn listener(address: Address) -> JoinHandle<Bytes> {
     tokio::spawn(async move {
-        let listener = TcpListener::bind(&address).await.unwrap();
+        let mut listener = transport().listen(&address.listen_address()).await.unwrap();
         let (socket, _) = listener.accept().await.unwrap();
         let transport = Framed::new(socket, LengthDelimitedCodec::new());
         let (mut writer, mut reader) = transport.split();
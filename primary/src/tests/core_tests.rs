@@ -1,11 +1,17 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{
-    certificate, committee, committee_with_base_port, header, headers, keys, listener, votes,
+    certificate, committee, committee_with_base_port, conflicting_header, header, headers, keys,
+    listener, transport, votes,
 };
+use crate::header_validator::{AcceptAllHeaders, PayloadLimit};
+use crypto::Signature;
 use futures::future::try_join_all;
+use network::NetworkMetrics;
+use std::collections::BTreeMap;
 use std::fs;
 use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, timeout, Duration};
 
 #[tokio::test]
 async fn process_header() {
@@ -19,6 +25,7 @@ async fn process_header() {
     let (tx_sync_headers, _rx_sync_headers) = channel(1);
     let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
     let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
     let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
     let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
     let (_tx_headers, rx_headers) = channel(1);
@@ -37,7 +44,8 @@ async fn process_header() {
     let address = committee
         .primary(&header().author)
         .unwrap()
-        .primary_to_primary;
+        .primary_to_primary
+        .advertise;
     let handle = listener(address);
 
     // Make a synchronizer for the core.
@@ -56,9 +64,18 @@ async fn process_header() {
         store.clone(),
         synchronizer,
         signature_service,
+        Box::new(AcceptAllHeaders),
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
         /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
@@ -88,6 +105,285 @@ async fn process_header() {
     assert_eq!(stored, Some(header()));
 }
 
+#[tokio::test]
+async fn reject_header() {
+    let mut keys = keys();
+    let (author, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(14_300);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store holding the payload of the header.
+    let path = ".db_test_reject_header";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let key = [Digest::default().as_ref(), &0u32.to_le_bytes()].concat();
+    store.write(key, Vec::default()).await;
+
+    // Make a header with a payload of one batch.
+    let header = Header {
+        author,
+        payload: vec![(Digest::default(), 0)].into_iter().collect(),
+        ..header()
+    };
+    let header = Header {
+        id: header.digest(),
+        signature: Signature::new(&header.digest(), &author_secret),
+        ..header
+    };
+
+    // Spawn a listener to receive our vote (if any).
+    let address = committee
+        .primary(&author)
+        .unwrap()
+        .primary_to_primary
+        .advertise;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core, only accepting headers without payload.
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        Box::new(PayloadLimit { max_batches: 0 }),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Send the header to the core.
+    tx_primary_messages
+        .send(PrimaryMessage::Header(header.clone()))
+        .await
+        .unwrap();
+
+    // Ensure the core does not vote for the header.
+    assert!(timeout(Duration::from_millis(200), handle).await.is_err());
+
+    // Ensure the header is stored nonetheless.
+    let stored = store.read(header.id.to_vec()).await.unwrap();
+    assert!(stored.is_some());
+}
+
+#[tokio::test]
+async fn process_header_shards() {
+    let mut keys = keys();
+    let (_, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(14_400);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(4);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_process_header_shards";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make the vote we expect to receive.
+    let expected = Vote::new(&header(), &name, &mut signature_service).await;
+
+    // Spawn a listener to receive the vote.
+    let address = committee
+        .primary(&header().author)
+        .unwrap()
+        .primary_to_primary
+        .advertise;
+    let handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    let shards = HeaderShard::encode(
+        &header(),
+        &committee,
+        &mut SignatureService::new(author_secret),
+    )
+    .await;
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        Box::new(AcceptAllHeaders),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Send 3 shards (out of 4) of the header to the core.
+    for shard in shards.into_iter().skip(1) {
+        tx_primary_messages
+            .send(PrimaryMessage::HeaderShard(shard))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core reconstructs the header and votes for it.
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::Vote(x) => assert_eq!(x, expected),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn process_compact_certificate() {
+    let mut keys = keys();
+    let (_, author_secret) = keys.pop().unwrap();
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(15_100);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(4);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_process_compact_certificate";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a listener to receive our vote.
+    let address = committee
+        .primary(&header().author)
+        .unwrap()
+        .primary_to_primary
+        .advertise;
+    let _handle = listener(address);
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    let shards = HeaderShard::encode(
+        &header(),
+        &committee,
+        &mut SignatureService::new(author_secret),
+    )
+    .await;
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        Box::new(AcceptAllHeaders),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Send the certificate (without its header) before the shards of the header.
+    let certificate = certificate(&header());
+    tx_primary_messages
+        .send(PrimaryMessage::CompactCertificate(certificate.compact()))
+        .await
+        .unwrap();
+    for shard in shards.into_iter().skip(1) {
+        tx_primary_messages
+            .send(PrimaryMessage::HeaderShard(shard))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core delivers the full certificate once it reconstructs the header.
+    let received = rx_consensus.recv().await.unwrap();
+    assert_eq!(received, certificate);
+    assert_eq!(received.header, header());
+}
+
 #[tokio::test]
 async fn process_header_missing_parent() {
     let (name, secret) = keys().pop().unwrap();
@@ -96,6 +392,7 @@ async fn process_header_missing_parent() {
     let (tx_sync_headers, _rx_sync_headers) = channel(1);
     let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
     let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
     let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
     let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
     let (_tx_headers, rx_headers) = channel(1);
@@ -123,9 +420,18 @@ async fn process_header_missing_parent() {
         store.clone(),
         synchronizer,
         signature_service,
+        Box::new(AcceptAllHeaders),
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
         /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
@@ -156,6 +462,7 @@ async fn process_header_missing_payload() {
     let (tx_sync_headers, _rx_sync_headers) = channel(1);
     let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
     let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
     let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
     let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
     let (_tx_headers, rx_headers) = channel(1);
@@ -183,9 +490,18 @@ async fn process_header_missing_payload() {
         store.clone(),
         synchronizer,
         signature_service,
+        Box::new(AcceptAllHeaders),
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
         /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
@@ -218,6 +534,7 @@ async fn process_votes() {
     let (tx_sync_headers, _rx_sync_headers) = channel(1);
     let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
     let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
     let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
     let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
     let (_tx_headers, rx_headers) = channel(1);
@@ -245,9 +562,18 @@ async fn process_votes() {
         store.clone(),
         synchronizer,
         signature_service,
+        Box::new(AcceptAllHeaders),
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
         /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
@@ -262,7 +588,7 @@ async fn process_votes() {
     let handles: Vec<_> = committee
         .others_primaries(&name)
         .iter()
-        .map(|(_, address)| listener(address.primary_to_primary))
+        .map(|(_, address)| listener(address.primary_to_primary.advertise.clone()))
         .collect();
 
     // Send a votes to the core.
@@ -290,6 +616,7 @@ async fn process_certificates() {
     let (tx_sync_headers, _rx_sync_headers) = channel(1);
     let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
     let (tx_primary_messages, rx_primary_messages) = channel(3);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
     let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
     let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
     let (_tx_headers, rx_headers) = channel(1);
@@ -317,9 +644,18 @@ async fn process_certificates() {
         store.clone(),
         synchronizer,
         signature_service,
+        Box::new(AcceptAllHeaders),
         /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
         /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
         /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
         /* rx_header_waiter */ rx_headers_loopback,
         /* rx_certificate_waiter */ rx_certificates_loopback,
         /* rx_proposer */ rx_headers,
@@ -359,3 +695,456 @@ async fn process_certificates() {
         assert_eq!(stored, Some(serialized));
     }
 }
+
+#[tokio::test]
+async fn process_certificates_batch() {
+    let mut keys = keys();
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, mut rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(10);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(10);
+    let (tx_parents, _rx_parents) = channel(10);
+
+    // Create a new test store.
+    let path = ".db_test_process_certificates_batch";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Make three certificates of round 1, a certificate of round 2 referencing them, and a certificate of
+    // round 2 referencing an unknown parent.
+    let mut certificates: Vec<_> = headers().iter().take(3).map(certificate).collect();
+    let parents = certificates.iter().map(|x| x.digest()).collect();
+    let (author, secret) = keys.pop().unwrap();
+    let header = Header::new(
+        author,
+        /* epoch */ 0,
+        /* round */ 2,
+        BTreeMap::new(),
+        parents,
+        /* reconfigure */ false,
+        /* coin */ None,
+        &mut SignatureService::new(secret),
+    )
+    .await;
+    certificates.push(certificate(&header));
+    let (author, secret) = keys.pop().unwrap();
+    let header = Header::new(
+        author,
+        /* epoch */ 0,
+        /* round */ 2,
+        BTreeMap::new(),
+        vec![Digest::default()].into_iter().collect(),
+        /* reconfigure */ false,
+        /* coin */ None,
+        &mut SignatureService::new(secret),
+    )
+    .await;
+    let orphan = certificate(&header);
+
+    // Queue the certificates before the core starts, so that it processes them as a single batch.
+    for x in certificates.iter().chain(std::iter::once(&orphan)) {
+        tx_primary_messages
+            .send(PrimaryMessage::Certificate(x.clone()))
+            .await
+            .unwrap();
+    }
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store.clone(),
+        synchronizer,
+        signature_service,
+        Box::new(AcceptAllHeaders),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Ensure the core delivers the certificate of round 2 right after its parents (of the same batch).
+    for x in &certificates {
+        let received = rx_consensus.recv().await.unwrap();
+        assert_eq!(&received, x);
+        let stored = store.read(x.digest().to_vec()).await.unwrap();
+        assert!(stored.is_some());
+    }
+
+    // Ensure the certificate with an unknown parent is suspended.
+    let received = rx_sync_certificates.recv().await.unwrap();
+    assert_eq!(received, orphan);
+    assert!(rx_consensus.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn prioritize_consensus_lane() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(1);
+    let (tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, mut rx_consensus) = channel(2);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_prioritize_consensus_lane";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Queue a certificate obtained by synchronizing, and then a certificate of the consensus lane.
+    let certificates: Vec<_> = headers().iter().take(2).map(certificate).collect();
+    tx_sync_messages
+        .send(PrimaryMessage::Certificate(certificates[0].clone()))
+        .await
+        .unwrap();
+    tx_primary_messages
+        .send(PrimaryMessage::Certificate(certificates[1].clone()))
+        .await
+        .unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee(),
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee(),
+        store,
+        synchronizer,
+        signature_service,
+        Box::new(AcceptAllHeaders),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Ensure the certificate of the consensus lane is processed first.
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificates[1]);
+    assert_eq!(rx_consensus.recv().await.unwrap(), certificates[0]);
+}
+
+#[tokio::test]
+async fn detect_equivocation() {
+    let mut keys = keys();
+    let _ = keys.pop().unwrap(); // Skip the header' author.
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(13_500);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(2);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_detect_equivocation";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        Box::new(AcceptAllHeaders),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        Arc::new(DagProgress::default()),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Send two conflicting headers of the same author to the core.
+    for header in [header(), conflicting_header()] {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core persists the evidence of the equivocation.
+    let mut evidence = EvidenceStore::new(store, /* epoch */ 0);
+    let author = header().author;
+    let equivocation = loop {
+        match evidence.read(&author, header().round).await.unwrap() {
+            Some(equivocation) => break equivocation,
+            None => sleep(Duration::from_millis(50)).await,
+        }
+    };
+    assert_eq!(equivocation.first, header());
+    assert_eq!(equivocation.second, conflicting_header());
+}
+
+#[tokio::test]
+async fn exclude_equivocator() {
+    let mut keys = keys();
+    let _ = keys.pop().unwrap(); // Skip the header' author.
+    let (name, secret) = keys.pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(14_700);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (tx_primary_messages, rx_primary_messages) = channel(2);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, _rx_parents) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_exclude_equivocator";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Spawn the core.
+    let progress = Arc::new(DagProgress::default());
+    Core::spawn(
+        name,
+        committee,
+        store.clone(),
+        synchronizer,
+        signature_service,
+        Box::new(AcceptAllHeaders),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        progress.clone(),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ true,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Send two conflicting headers of the same author to the core.
+    for header in [header(), conflicting_header()] {
+        tx_primary_messages
+            .send(PrimaryMessage::Header(header))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the core excludes the author of the headers.
+    let author = header().author;
+    loop {
+        let excluded = progress.excluded.lock().unwrap().get(&author).cloned();
+        match excluded {
+            Some(round) => break assert_eq!(round, header().round),
+            None => sleep(Duration::from_millis(50)).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn recover_after_restart() {
+    let (name, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+
+    let committee = committee_with_base_port(14_000);
+
+    let (tx_sync_headers, _rx_sync_headers) = channel(1);
+    let (tx_sync_certificates, _rx_sync_certificates) = channel(1);
+    let (_tx_primary_messages, rx_primary_messages) = channel(1);
+    let (_tx_sync_messages, rx_sync_messages) = channel(1);
+    let (_tx_headers_loopback, rx_headers_loopback) = channel(1);
+    let (_tx_certificates_loopback, rx_certificates_loopback) = channel(1);
+    let (_tx_headers, rx_headers) = channel(1);
+    let (tx_consensus, _rx_consensus) = channel(1);
+    let (tx_parents, mut rx_parents) = channel(1);
+
+    // Create a new test store holding the certificates of round 1.
+    let path = ".db_test_recover_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let mut round_index = RoundIndex::new(store.clone(), /* epoch */ 0);
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+    for x in &certificates {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+        round_index.insert(x).await.unwrap();
+    }
+
+    // We crashed in the middle of round 2, after proposing our header and voting for it.
+    let parents = certificates.iter().map(|x| x.digest()).collect();
+    let header = Header::new(
+        name,
+        /* epoch */ 0,
+        /* round */ 2,
+        BTreeMap::new(),
+        parents,
+        /* reconfigure */ false,
+        /* coin */ None,
+        &mut signature_service,
+    )
+    .await;
+    let vote = Vote::new(&header, &name, &mut signature_service).await;
+    let mut recovery = RecoveryStore::new(store.clone(), /* epoch */ 0);
+    recovery.write_header(&header).await;
+    recovery.write_votes(&header.id, &[vote]).await;
+    recovery
+        .write_voted(2, &vec![name].into_iter().collect())
+        .await;
+
+    // Spawn listeners to receive our header.
+    let handles: Vec<_> = committee
+        .others_primaries(&name)
+        .iter()
+        .map(|(_, address)| listener(address.primary_to_primary.advertise.clone()))
+        .collect();
+
+    // Make a synchronizer for the core.
+    let synchronizer = Synchronizer::new(
+        name,
+        &committee,
+        store.clone(),
+        /* tx_header_waiter */ tx_sync_headers,
+        /* tx_certificate_waiter */ tx_sync_certificates,
+    );
+
+    // Restart the core.
+    let progress = Arc::new(DagProgress::default());
+    Core::spawn(
+        name,
+        committee,
+        store,
+        synchronizer,
+        signature_service,
+        Box::new(AcceptAllHeaders),
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        progress.clone(),
+        /* gc_depth */ 50,
+        /* certificate_concurrency */ 10,
+        /* max_datagram_size */ 0,
+        /* erasure_coding_threshold */ 0,
+        /* exclude_misbehaving */ false,
+        transport(),
+        NetworkMetrics::default(),
+        /* rx_primaries */ rx_primary_messages,
+        /* rx_sync */ rx_sync_messages,
+        /* rx_header_waiter */ rx_headers_loopback,
+        /* rx_certificate_waiter */ rx_certificates_loopback,
+        /* rx_proposer */ rx_headers,
+        tx_consensus,
+        /* tx_proposer */ tx_parents,
+    );
+
+    // Ensure the core resumes from round 1.
+    let (mut received, round) = rx_parents.recv().await.unwrap();
+    received.sort();
+    let mut expected: Vec<_> = certificates.iter().map(|x| x.digest()).collect();
+    expected.sort();
+    assert_eq!((received, round), (expected, 1));
+
+    // Ensure the core broadcasts our header of round 2 anew.
+    for received in try_join_all(handles).await.unwrap() {
+        match bincode::deserialize(&received).unwrap() {
+            PrimaryMessage::Header(x) => assert_eq!(x, header),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+
+    // Ensure the votes gathered before the crash are not lost.
+    let pending_votes = progress.pending_votes.lock().unwrap().clone();
+    assert_eq!(pending_votes.header, header.id);
+    assert_eq!(pending_votes.voters, vec![name]);
+}
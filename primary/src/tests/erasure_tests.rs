@@ -0,0 +1,111 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, header, keys};
+
+// Fixture: a header referencing many batches.
+async fn large_header() -> Header {
+    let (author, secret) = keys().pop().unwrap();
+    Header::new(
+        author,
+        /* epoch */ 0,
+        /* round */ 1,
+        (0..100u8).map(|i| (Digest([i; 32]), 0)).collect(),
+        header().parents,
+        /* reconfigure */ false,
+        /* coin */ None,
+        &mut SignatureService::new(secret),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn reconstruct_header() {
+    let (_, secret) = keys().pop().unwrap();
+    let header = large_header().await;
+    let shards =
+        HeaderShard::encode(&header, &committee(), &mut SignatureService::new(secret)).await;
+    assert_eq!(shards.len(), 4);
+
+    // Any 3 shards (out of 4) suffice to reconstruct the header.
+    let mut aggregator = ShardsAggregator::new(&committee());
+    for shard in shards.iter().skip(1) {
+        assert!(shard.verify(&committee()).is_ok());
+        assert!(shard.data.len() < bincode::serialize(&header).unwrap().len());
+    }
+    assert!(aggregator.append(shards[3].clone()).unwrap().is_none());
+    assert!(aggregator.append(shards[1].clone()).unwrap().is_none());
+    let reconstructed = aggregator.append(shards[2].clone()).unwrap();
+    assert_eq!(reconstructed, Some(header));
+
+    // The header is only output once.
+    assert!(aggregator.append(shards[0].clone()).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn reconstruct_header_with_stake() {
+    // One authority holds 4 of the 7 units of stake: it forms a quorum with any other authority.
+    let mut committee = committee();
+    let (name, secret) = keys().pop().unwrap();
+    committee.authorities.get_mut(&name).unwrap().stake = 4;
+    assert_eq!(committee.quorum_size(), 2);
+
+    // The shards of any quorum suffice to reconstruct the header.
+    let header = large_header().await;
+    let shards = HeaderShard::encode(&header, &committee, &mut SignatureService::new(secret)).await;
+    assert_eq!(shards.len(), 4);
+    let index = shard_index(&committee, &name).unwrap();
+    let mut aggregator = ShardsAggregator::new(&committee);
+    assert!(aggregator.append(shards[index].clone()).unwrap().is_none());
+    let other = (index + 1) % shards.len();
+    let reconstructed = aggregator.append(shards[other].clone()).unwrap();
+    assert_eq!(reconstructed, Some(header));
+}
+
+#[tokio::test]
+async fn reject_forged_shard() {
+    let (_, secret) = keys().pop().unwrap();
+    let header = large_header().await;
+    let mut shards =
+        HeaderShard::encode(&header, &committee(), &mut SignatureService::new(secret)).await;
+
+    // A shard whose content does not match the commitment of the author.
+    shards[0].data[0] ^= 1;
+    match shards[0].verify(&committee()) {
+        Err(DagError::InvalidShard(id)) => assert_eq!(id, header.id),
+        x => panic!("Unexpected result: {:?}", x),
+    }
+
+    // A shard whose commitment is not signed by the author.
+    shards[1].length += 1;
+    assert!(shards[1].verify(&committee()).is_err());
+}
+
+#[tokio::test]
+async fn reconstruct_after_inconsistent_shards() {
+    let (_, secret) = keys().pop().unwrap();
+    let mut signature_service = SignatureService::new(secret);
+    let header = large_header().await;
+    let mut shards = HeaderShard::encode(&header, &committee(), &mut signature_service).await;
+
+    // The author commits to a parity shard that does not match the header.
+    shards[3].data[0] ^= 1;
+    let digests: Vec<_> = shards.iter().map(|x| HeaderShard::hash(&x.data)).collect();
+    let signature = {
+        let mut shard = shards[0].clone();
+        shard.digests = digests.clone();
+        signature_service.request_signature(shard.digest()).await
+    };
+    for shard in shards.iter_mut() {
+        shard.digests = digests.clone();
+        shard.signature = signature.clone();
+        assert!(shard.verify(&committee()).is_ok());
+    }
+
+    // The reconstruction fails with the inconsistent shard, but succeeds once we receive another one.
+    let mut aggregator = ShardsAggregator::new(&committee());
+    assert!(aggregator.append(shards[3].clone()).unwrap().is_none());
+    assert!(aggregator.append(shards[1].clone()).unwrap().is_none());
+    assert!(aggregator.append(shards[2].clone()).is_err());
+    let reconstructed = aggregator.append(shards[0].clone()).unwrap();
+    assert_eq!(reconstructed, Some(header));
+}
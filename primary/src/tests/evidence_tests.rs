@@ -0,0 +1,74 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, conflicting_header, header};
+use std::fs;
+
+#[test]
+fn verify_equivocation() {
+    let equivocation = Equivocation {
+        first: header(),
+        second: conflicting_header(),
+    };
+    assert!(equivocation.verify(&committee()).is_ok());
+
+    // The same header twice is no evidence.
+    let equivocation = Equivocation {
+        first: header(),
+        second: header(),
+    };
+    assert!(matches!(
+        equivocation.verify(&committee()),
+        Err(DagError::InvalidEquivocation(_))
+    ));
+}
+
+#[tokio::test]
+async fn store_evidence() {
+    // Create a new test store.
+    let path = ".db_test_store_evidence";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let mut evidence = EvidenceStore::new(store, /* epoch */ 0);
+
+    let equivocation = Equivocation {
+        first: header(),
+        second: conflicting_header(),
+    };
+    let (author, round) = (equivocation.author(), equivocation.round());
+    assert!(evidence.read(&author, round).await.unwrap().is_none());
+
+    // Only the first evidence against an authority for a round is kept.
+    assert!(evidence.insert(&equivocation).await.unwrap());
+    let duplicate = Equivocation {
+        first: conflicting_header(),
+        second: header(),
+    };
+    assert!(!evidence.insert(&duplicate).await.unwrap());
+
+    let stored = evidence.read(&author, round).await.unwrap().unwrap();
+    assert_eq!(stored.first, header());
+    assert_eq!(stored.second, conflicting_header());
+}
+
+#[tokio::test]
+async fn read_all_evidence() {
+    // Create a new test store.
+    let path = ".db_test_read_all_evidence";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let mut evidence = EvidenceStore::new(store.clone(), /* epoch */ 0);
+
+    let equivocation = Equivocation {
+        first: header(),
+        second: conflicting_header(),
+    };
+    evidence.insert(&equivocation).await.unwrap();
+
+    // The evidence of other epochs is not returned.
+    let mut next_epoch = EvidenceStore::new(store, /* epoch */ 1);
+    assert!(next_epoch.read_all().await.unwrap().is_empty());
+
+    let stored = evidence.read_all().await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].first, header());
+}
@@ -0,0 +1,67 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+
+fn digest(x: u8) -> Digest {
+    Digest([x; 32])
+}
+
+#[test]
+fn agreeing_sequences() {
+    // The remote sequence starts later and runs ahead of ours, beyond our history.
+    let mut detector = ForkDetector::new(4);
+    for x in 0..6 {
+        assert!(detector.local(digest(x)).is_ok());
+    }
+    for x in 3..12 {
+        assert!(detector.remote(digest(x)).is_ok());
+    }
+    for x in 6..12 {
+        assert!(detector.local(digest(x)).is_ok());
+    }
+    assert!(detector.fork().is_none());
+
+    // The remote sequence starts before our history.
+    let mut detector = ForkDetector::new(4);
+    for x in 0..8 {
+        assert!(detector.local(digest(x)).is_ok());
+    }
+    for x in 0..8 {
+        assert!(detector.remote(digest(x)).is_ok());
+    }
+    assert!(detector.fork().is_none());
+}
+
+#[test]
+fn detect_fork() {
+    let mut detector = ForkDetector::new(100);
+    for x in 0..5 {
+        assert!(detector.local(digest(x)).is_ok());
+    }
+    for x in [3, 4, 20, 21] {
+        assert!(detector.remote(digest(x)).is_ok());
+    }
+
+    // Our next commit differs from the remote one.
+    let fork = detector.local(digest(10)).unwrap_err();
+    let expected = Fork {
+        position: 5,
+        common: (0..5).map(digest).collect(),
+        local: vec![digest(10)],
+        remote: vec![digest(20), digest(21)],
+    };
+    assert_eq!(fork, expected);
+    assert_eq!(detector.fork(), Some(&expected));
+
+    // The sequences are no longer compared.
+    assert!(detector.local(digest(11)).is_ok());
+
+    // The commit preceding the first one in common must match too.
+    let mut detector = ForkDetector::new(100);
+    for x in 0..5 {
+        assert!(detector.local(digest(x)).is_ok());
+    }
+    assert!(detector.remote(digest(9)).is_ok());
+    let fork = detector.remote(digest(3)).unwrap_err();
+    assert_eq!(fork.position, 2);
+    assert_eq!(fork.remote, vec![digest(9), digest(3)]);
+}
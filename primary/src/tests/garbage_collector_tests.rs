@@ -0,0 +1,558 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, header, keys, listener, transport};
+use crate::messages::Header;
+use config::WorkerId;
+use crypto::{Digest, Hash as _};
+use network::NetworkMetrics;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// Make a committed certificate whose header signals that its author is ready to switch epoch.
+fn ready_certificate(author: PublicKey) -> Certificate {
+    Certificate {
+        header: Header {
+            author,
+            reconfigure: true,
+            ..Header::default()
+        },
+        ..Certificate::default()
+    }
+}
+
+/// Make the sub-dag committed by a leader without uncommitted history.
+fn commit(certificate: Certificate) -> CommittedSubDag {
+    CommittedSubDag {
+        leader: certificate.clone(),
+        certificates: vec![certificate],
+    }
+}
+
+/// Act as the proposer: acknowledge the end of the epoch (once the garbage collector signals it).
+fn proposer(mut rx_sequenced: Receiver<ProposerMessage>) -> JoinHandle<bool> {
+    tokio::spawn(async move {
+        while let Some(message) = rx_sequenced.recv().await {
+            if let ProposerMessage::EndOfEpoch(ack) = message {
+                let _ = ack.send(());
+                return true;
+            }
+        }
+        false
+    })
+}
+
+#[tokio::test]
+async fn reconfigure() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(13_400);
+    let next_committee = Committee {
+        epoch: committee.epoch + 1,
+        ..committee.clone()
+    };
+
+    let (tx_consensus, rx_consensus) = channel(1);
+    let (tx_next_committee, rx_next_committee) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_sequenced, rx_sequenced) = channel(10);
+    let (tx_reconfigure, mut rx_reconfigure) = channel(1);
+    let ready = Arc::new(AtomicBool::new(false));
+    let proposer = proposer(rx_sequenced);
+
+    // Spawn a listener to act as our worker.
+    let address = committee.our_workers(&name).unwrap()[0]
+        .primary_to_worker
+        .advertise
+        .clone();
+    let handle = listener(address);
+
+    // Create a new test store.
+    let path = ".db_test_reconfigure";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        store,
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* retention_depth */ 0,
+        /* cleanup_rounds */ 1,
+        /* cleanup_interval */ 0,
+        ready.clone(),
+        /* max_datagram_size */ 0,
+        transport(),
+        NetworkMetrics::default(),
+        rx_consensus,
+        rx_next_committee,
+        rx_parameters,
+        tx_sequenced,
+        tx_reconfigure,
+    );
+
+    // Provide the committee of the next epoch.
+    tx_next_committee.send(next_committee).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    assert!(ready.load(Ordering::Relaxed));
+
+    // The epoch does not change before a quorum of authorities is ready.
+    tx_consensus
+        .send(commit(ready_certificate(name)))
+        .await
+        .unwrap();
+    for (author, _) in keys.drain(..1) {
+        tx_consensus
+            .send(commit(ready_certificate(author)))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(50)).await;
+    assert!(rx_reconfigure.try_recv().is_err());
+
+    // Ensure our worker and the node are notified of the epoch change once a quorum is ready.
+    let (author, _) = keys.pop().unwrap();
+    tx_consensus
+        .send(commit(ready_certificate(author)))
+        .await
+        .unwrap();
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryWorkerMessage::Reconfigure(epoch) => assert_eq!(epoch, 1),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+    let committee = rx_reconfigure.recv().await.unwrap();
+    assert_eq!(committee.epoch, 1);
+    assert!(proposer.await.unwrap());
+}
+
+#[tokio::test]
+async fn wait_for_next_committee() {
+    let keys = keys();
+    let (name, _) = keys[0];
+    let committee = committee_with_base_port(14_900);
+    let next_committee = Committee {
+        epoch: committee.epoch + 1,
+        ..committee.clone()
+    };
+
+    let (tx_consensus, rx_consensus) = channel(10);
+    let (tx_next_committee, rx_next_committee) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_sequenced, rx_sequenced) = channel(10);
+    let (tx_reconfigure, mut rx_reconfigure) = channel(1);
+    let proposer = proposer(rx_sequenced);
+
+    // Spawn a listener to act as our worker.
+    let address = committee.our_workers(&name).unwrap()[0]
+        .primary_to_worker
+        .advertise
+        .clone();
+    let handle = listener(address);
+
+    // Create a new test store.
+    let path = ".db_test_wait_for_next_committee";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        store,
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* retention_depth */ 0,
+        /* cleanup_rounds */ 1_000,
+        /* cleanup_interval */ 0,
+        Arc::new(AtomicBool::new(false)),
+        /* max_datagram_size */ 0,
+        transport(),
+        NetworkMetrics::default(),
+        rx_consensus,
+        rx_next_committee,
+        rx_parameters,
+        tx_sequenced,
+        tx_reconfigure,
+    );
+
+    // A quorum of authorities is ready before we receive the next committee: the epoch ends, but we wait for
+    // the committee without processing the rest of the sequence.
+    for (author, _) in keys.iter().take(3) {
+        tx_consensus
+            .send(commit(ready_certificate(*author)))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(50)).await;
+    assert!(rx_reconfigure.try_recv().is_err());
+    for _ in 0..10 {
+        tx_consensus
+            .send(commit(certificate(&header())))
+            .await
+            .unwrap();
+    }
+    assert!(tx_consensus
+        .try_send(commit(certificate(&header())))
+        .is_err());
+
+    // Move to the next epoch once we receive its committee.
+    tx_next_committee.send(next_committee).await.unwrap();
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryWorkerMessage::Reconfigure(epoch) => assert_eq!(epoch, 1),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+    let committee = rx_reconfigure.recv().await.unwrap();
+    assert_eq!(committee.epoch, 1);
+    assert!(proposer.await.unwrap());
+}
+
+#[tokio::test]
+async fn prune_storage() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(13_600);
+
+    let (tx_consensus, rx_consensus) = channel(1);
+    let (_tx_next_committee, rx_next_committee) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_sequenced, _rx_sequenced) = channel(10);
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+
+    // Create a new test store holding a certificate (and its header and payload marker) at each round.
+    let path = ".db_test_prune_storage";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let mut round_index = RoundIndex::new(store.clone(), committee.epoch);
+    let worker_id: WorkerId = 0;
+    let mut certificates = Vec::new();
+    for round in 1..=3 {
+        let mut header = Header {
+            round,
+            payload: vec![(Digest([round as u8; 32]), worker_id)]
+                .into_iter()
+                .collect(),
+            ..header()
+        };
+        header.id = header.digest();
+        let certificate = certificate(&header);
+        let bytes = bincode::serialize(&header).unwrap();
+        store.write(header.id.to_vec(), bytes).await;
+        let bytes = bincode::serialize(&certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        let key = [&[round as u8; 32][..], &worker_id.to_le_bytes()].concat();
+        store.write(key, Vec::default()).await;
+        round_index.insert(&certificate).await.unwrap();
+        certificates.push(certificate);
+    }
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        store.clone(),
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 1,
+        /* retention_depth */ 1,
+        /* cleanup_rounds */ 1,
+        /* cleanup_interval */ 0,
+        Arc::new(AtomicBool::new(false)),
+        /* max_datagram_size */ 0,
+        transport(),
+        NetworkMetrics::default(),
+        rx_consensus,
+        rx_next_committee,
+        rx_parameters,
+        tx_sequenced,
+        tx_reconfigure,
+    );
+
+    // Commit a certificate of round 3.
+    tx_consensus
+        .send(commit(certificates[2].clone()))
+        .await
+        .unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    // Ensure round 1 is pruned while rounds 2 and 3 are kept.
+    for certificate in &certificates {
+        let round = certificate.round();
+        let pruned = round < 2;
+        let stored = store.read(certificate.digest().to_vec()).await.unwrap();
+        assert_eq!(stored.is_none(), pruned);
+        let stored = store.read(certificate.header.id.to_vec()).await.unwrap();
+        assert_eq!(stored.is_none(), pruned);
+        let key = [&[round as u8; 32][..], &worker_id.to_le_bytes()].concat();
+        let stored = store.read(key).await.unwrap();
+        assert_eq!(stored.is_none(), pruned);
+        let indexed = round_index.read(round).await.unwrap();
+        assert_eq!(indexed.is_empty(), pruned, "round {}", round);
+    }
+}
+
+#[tokio::test]
+async fn batch_cleanup() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_100);
+
+    let (tx_consensus, rx_consensus) = channel(10);
+    let (_tx_next_committee, rx_next_committee) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_sequenced, _rx_sequenced) = channel(10);
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+
+    // Spawn a listener to act as our worker.
+    let address = committee.our_workers(&name).unwrap()[0]
+        .primary_to_worker
+        .advertise
+        .clone();
+    let handle = listener(address);
+
+    // Create a new test store.
+    let path = ".db_test_batch_cleanup";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        store,
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* retention_depth */ 0,
+        /* cleanup_rounds */ 3,
+        /* cleanup_interval */ 0,
+        Arc::new(AtomicBool::new(false)),
+        /* max_datagram_size */ 0,
+        transport(),
+        NetworkMetrics::default(),
+        rx_consensus,
+        rx_next_committee,
+        rx_parameters,
+        tx_sequenced,
+        tx_reconfigure,
+    );
+
+    // Commit certificates of rounds 1 to 3.
+    for round in 1..=3 {
+        let header = Header { round, ..header() };
+        tx_consensus
+            .send(commit(certificate(&header)))
+            .await
+            .unwrap();
+    }
+
+    // Ensure our worker is only notified once the third round is committed.
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryWorkerMessage::Cleanup(round) => assert_eq!(round, 3),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn cleanup_on_timer() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_200);
+
+    let (tx_consensus, rx_consensus) = channel(1);
+    let (_tx_next_committee, rx_next_committee) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_sequenced, _rx_sequenced) = channel(10);
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+
+    // Spawn a listener to act as our worker.
+    let address = committee.our_workers(&name).unwrap()[0]
+        .primary_to_worker
+        .advertise
+        .clone();
+    let handle = listener(address);
+
+    // Create a new test store.
+    let path = ".db_test_cleanup_on_timer";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        store,
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* retention_depth */ 0,
+        /* cleanup_rounds */ 10,
+        /* cleanup_interval */ 100,
+        Arc::new(AtomicBool::new(false)),
+        /* max_datagram_size */ 0,
+        transport(),
+        NetworkMetrics::default(),
+        rx_consensus,
+        rx_next_committee,
+        rx_parameters,
+        tx_sequenced,
+        tx_reconfigure,
+    );
+
+    // Commit a single certificate.
+    let header = Header {
+        round: 2,
+        ..header()
+    };
+    tx_consensus
+        .send(commit(certificate(&header)))
+        .await
+        .unwrap();
+
+    // Ensure our worker is notified once the timer fires (even though fewer than 10 rounds were committed).
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryWorkerMessage::Cleanup(round) => assert_eq!(round, 2),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn acknowledge_sequenced_batches() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_800);
+
+    let (tx_consensus, rx_consensus) = channel(1);
+    let (_tx_next_committee, rx_next_committee) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_sequenced, mut rx_sequenced) = channel(10);
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+
+    // Spawn a listener to act as our worker.
+    let worker_id: WorkerId = 0;
+    let address = committee
+        .worker(&name, &worker_id)
+        .unwrap()
+        .primary_to_worker
+        .advertise;
+    let handle = listener(address);
+
+    // Create a new test store.
+    let path = ".db_test_acknowledge_sequenced_batches";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        store,
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* retention_depth */ 0,
+        /* cleanup_rounds */ 10,
+        /* cleanup_interval */ 0,
+        Arc::new(AtomicBool::new(false)),
+        /* max_datagram_size */ 0,
+        transport(),
+        NetworkMetrics::default(),
+        rx_consensus,
+        rx_next_committee,
+        rx_parameters,
+        tx_sequenced,
+        tx_reconfigure,
+    );
+
+    // Commit one of our certificates carrying a batch.
+    let digest = Digest([1; 32]);
+    let header = Header {
+        payload: vec![(digest.clone(), worker_id)].into_iter().collect(),
+        ..header()
+    };
+    tx_consensus
+        .send(commit(certificate(&header)))
+        .await
+        .unwrap();
+
+    // Ensure both the proposer and our worker are notified.
+    match rx_sequenced.recv().await {
+        Some(ProposerMessage::Sequenced(round)) => assert_eq!(round, header.round),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryWorkerMessage::Sequenced(digests) => assert_eq!(digests, vec![digest]),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn notify_committed_batches() {
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (other, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(15_000);
+
+    let (tx_consensus, rx_consensus) = channel(1);
+    let (_tx_next_committee, rx_next_committee) = channel(1);
+    let parameters = Parameters {
+        covered_transactions_size: 1_000,
+        ..Parameters::default()
+    };
+    let (_tx_parameters, rx_parameters) = watch::channel(parameters);
+    let (tx_sequenced, _rx_sequenced) = channel(10);
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+
+    // Spawn a listener to act as our worker.
+    let worker_id: WorkerId = 0;
+    let address = committee
+        .worker(&name, &worker_id)
+        .unwrap()
+        .primary_to_worker
+        .advertise;
+    let handle = listener(address);
+
+    // Create a new test store.
+    let path = ".db_test_notify_committed_batches";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the garbage collector.
+    GarbageCollector::spawn(
+        &name,
+        &committee,
+        store,
+        Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* retention_depth */ 0,
+        /* cleanup_rounds */ 10,
+        /* cleanup_interval */ 0,
+        Arc::new(AtomicBool::new(false)),
+        /* max_datagram_size */ 0,
+        transport(),
+        NetworkMetrics::default(),
+        rx_consensus,
+        rx_next_committee,
+        rx_parameters,
+        tx_sequenced,
+        tx_reconfigure,
+    );
+
+    // Commit a certificate of another authority carrying a batch.
+    let digest = Digest([1; 32]);
+    let header = Header {
+        author: other,
+        payload: vec![(digest.clone(), worker_id)].into_iter().collect(),
+        ..header()
+    };
+    tx_consensus
+        .send(commit(certificate(&header)))
+        .await
+        .unwrap();
+
+    // Ensure our worker learns that the batch is committed (to skip its transactions).
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryWorkerMessage::Committed(digests) => assert_eq!(digests, vec![digest]),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
@@ -0,0 +1,24 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::header;
+use crypto::Digest;
+
+#[test]
+fn validate_payload() {
+    let header = Header {
+        payload: vec![(Digest([0; 32]), 0), (Digest([1; 32]), 0)]
+            .into_iter()
+            .collect(),
+        ..header()
+    };
+
+    let mut validator = PayloadLimit { max_batches: 2 };
+    assert!(validator.validate(&header).is_ok());
+
+    // A list of validators rejects the header as soon as one of them does.
+    let mut validators: Vec<Box<dyn HeaderValidator>> = vec![
+        Box::new(AcceptAllHeaders),
+        Box::new(PayloadLimit { max_batches: 1 }),
+    ];
+    assert!(validators.validate(&header).is_err());
+}
@@ -0,0 +1,95 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee_with_base_port, headers, keys, transport};
+use crypto::Hash as _;
+use network::NetworkMetrics;
+use std::fs;
+use std::sync::Arc;
+use tokio::sync::mpsc::channel;
+
+/// Returns a store holding (and indexing) the certificates of round 1.
+async fn store_with_certificates(path: &str) -> (Store, Vec<Certificate>) {
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let mut round_index = RoundIndex::new(store.clone(), committee_with_base_port(0).epoch);
+    let certificates: Vec<_> = headers().iter().map(certificate).collect();
+    for x in &certificates {
+        let bytes = bincode::serialize(x).unwrap();
+        store.write(x.digest().to_vec(), bytes).await;
+        round_index.insert(x).await.unwrap();
+    }
+    (store, certificates)
+}
+
+#[tokio::test]
+async fn merge_identical_ranges() {
+    let (tx_request, rx_request) = channel(10);
+    let (requestor, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_500);
+    let (store, certificates) = store_with_certificates(".db_test_merge_identical_ranges").await;
+
+    // Queue two identical range requests before the helper starts.
+    let (sender_1, receiver_1) = oneshot::channel();
+    let (sender_2, receiver_2) = oneshot::channel();
+    tx_request
+        .send(HelperRequest::Range(1, 1, requestor, sender_1))
+        .await
+        .unwrap();
+    tx_request
+        .send(HelperRequest::Range(1, 1, requestor, sender_2))
+        .await
+        .unwrap();
+
+    Helper::spawn(
+        committee,
+        store,
+        transport(),
+        NetworkMetrics::default(),
+        /* rate_limit */ None,
+        Arc::new(DagProgress::default()),
+        rx_request,
+    );
+
+    // Both requests get the same reply.
+    let reply_1 = receiver_1.await.unwrap();
+    let reply_2 = receiver_2.await.unwrap();
+    assert_eq!(reply_1, reply_2);
+    let range: CertificateRange = bincode::deserialize(&reply_1).unwrap();
+    assert_eq!(range.end, 1);
+    assert_eq!(range.certificates.len(), certificates.len());
+}
+
+#[tokio::test]
+async fn rate_limit_requestor() {
+    let (tx_request, rx_request) = channel(10);
+    let (requestor, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(14_600);
+    let (store, _) = store_with_certificates(".db_test_rate_limit_requestor").await;
+
+    // The first reply exhausts the tokens of the requestor.
+    let limit = RateLimit { rate: 1, burst: 1 };
+    Helper::spawn(
+        committee,
+        store,
+        transport(),
+        NetworkMetrics::default(),
+        Some(limit),
+        Arc::new(DagProgress::default()),
+        rx_request,
+    );
+
+    let (sender, receiver) = oneshot::channel();
+    tx_request
+        .send(HelperRequest::Range(1, 1, requestor, sender))
+        .await
+        .unwrap();
+    assert!(receiver.await.is_ok());
+
+    // The next request of the requestor is dropped.
+    let (sender, receiver) = oneshot::channel();
+    tx_request
+        .send(HelperRequest::Range(1, 2, requestor, sender))
+        .await
+        .unwrap();
+    assert!(receiver.await.is_err());
+}
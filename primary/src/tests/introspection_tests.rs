@@ -0,0 +1,181 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header, keys};
+use crate::messages::CommittedSubDag;
+use crypto::Hash as _;
+use network::NetworkMetrics;
+use std::fs;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+
+/// Send a GET request to the API and return the body of the response.
+async fn get(address: &SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, address
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    response.split("\r\n\r\n").nth(1).unwrap().to_string()
+}
+
+/// Encode a public key to be used in a query string.
+fn encode(key: &PublicKey) -> String {
+    key.encode_base64()
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D")
+}
+
+#[tokio::test]
+async fn introspect_primary() {
+    let (name, _) = keys().pop().unwrap();
+    let address = "127.0.0.1:13700".parse::<SocketAddr>().unwrap();
+
+    // Create a new test store holding a certificate.
+    let path = ".db_test_introspect_primary";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificate = certificate(&header());
+    let bytes = bincode::serialize(&certificate).unwrap();
+    store.write(certificate.digest().to_vec(), bytes).await;
+    let mut round_index = RoundIndex::new(store.clone(), /* epoch */ 0);
+    round_index.insert(&certificate).await.unwrap();
+
+    // Set the progress of the primary.
+    let consensus_round = Arc::new(AtomicU64::new(60));
+    let progress = Arc::new(DagProgress::default());
+    progress.dag_round.store(62, Ordering::Relaxed);
+    progress.observed_round.store(63, Ordering::Relaxed);
+    *progress.pending_votes.lock().unwrap() = PendingVotes {
+        voters: vec![name],
+        ..PendingVotes::new(&header())
+    };
+    progress.pipeline.record_round_start(62);
+    progress.pipeline.record_header(62);
+    progress.commit_latency.record(30, 10.0);
+    let commit_events = CommitEventBus::new();
+    let event = CommitEvent {
+        round: 60,
+        wave: 30,
+        leader: name,
+        leader_round: 60,
+        author: name,
+        certificate: certificate.digest(),
+        latency: 10.0,
+    };
+    commit_events.publish(event.clone());
+    let wave = CommittedWave {
+        wave: 30,
+        leader: name,
+        round: 60,
+        certificates: 1,
+    };
+    commit_events.publish_wave(wave.clone());
+    commit_events.publish_memory(ConsensusMemory {
+        rounds: 3,
+        certificates: 10,
+        bytes: 5_000,
+        lowest_round: 58,
+    });
+    progress.chain_quality.record_commit(&event);
+    progress.dag_shape.record_certificate(62);
+    progress
+        .sub_dags
+        .record(SubDagStructure::new(&CommittedSubDag {
+            leader: certificate.clone(),
+            certificates: vec![certificate.clone()],
+        }));
+    let network_metrics = NetworkMetrics::default();
+    let peer = "127.0.0.1:1".parse().unwrap();
+    network_metrics.record_attempt(&peer);
+
+    // Spawn the API.
+    IntrospectionServer::spawn(
+        address,
+        name,
+        /* epoch */ 0,
+        /* gc_depth */ 50,
+        consensus_round,
+        progress,
+        Arc::new(SignatureMetrics::default()),
+        network_metrics,
+        commit_events,
+        store,
+    );
+    sleep(Duration::from_millis(50)).await;
+
+    // Ensure the status reflects the state of the primary.
+    let status: Status = serde_json::from_str(&get(&address, "/status").await).unwrap();
+    assert_eq!(status.name, name);
+    assert_eq!(status.round, 62);
+    assert_eq!(status.observed_round, 63);
+    assert_eq!(status.last_committed_round, 60);
+    assert_eq!(status.gc_round, 10);
+    assert_eq!(status.pending_votes.header, header().id);
+    assert_eq!(status.pending_votes.voters, vec![name]);
+    assert_eq!(status.last_commit, Some(event));
+    assert_eq!(status.last_wave, Some(wave));
+
+    // Ensure we can query the certificates by round and author.
+    let path = format!("/certificates?round={}", certificate.round());
+    let vertices: Vec<Vertex> = serde_json::from_str(&get(&address, &path).await).unwrap();
+    assert_eq!(vertices, vec![Vertex::from(&certificate)]);
+
+    let author = encode(&certificate.origin());
+    let path = format!("/certificates?round=1&author={}", author);
+    let vertices: Vec<Vertex> = serde_json::from_str(&get(&address, &path).await).unwrap();
+    assert_eq!(vertices.len(), 1);
+
+    let path = format!("/certificates?author={}", author);
+    let vertices: Vec<Vertex> = serde_json::from_str(&get(&address, &path).await).unwrap();
+    assert_eq!(vertices, vec![Vertex::from(&certificate)]);
+
+    let (other, _) = keys().remove(0);
+    let path = format!("/certificates?round=1&author={}", encode(&other));
+    let vertices: Vec<Vertex> = serde_json::from_str(&get(&address, &path).await).unwrap();
+    assert!(vertices.is_empty());
+
+    // Ensure the latencies of the pipeline are exposed.
+    let latencies: Vec<RoundLatency> =
+        serde_json::from_str(&get(&address, "/latencies").await).unwrap();
+    assert_eq!(latencies.len(), 1);
+    assert_eq!(latencies[0].round, 62);
+    assert!(latencies[0].header_creation.is_some());
+    assert!(latencies[0].vote_quorum.is_none());
+
+    let waves: Vec<WaveLatency> =
+        serde_json::from_str(&get(&address, "/commit_latencies").await).unwrap();
+    assert_eq!(waves.len(), 1);
+    assert_eq!(waves[0].p50, 10.0);
+
+    let authorities: BTreeMap<PublicKey, AuthorityQuality> =
+        serde_json::from_str(&get(&address, "/chain_quality").await).unwrap();
+    assert_eq!(authorities[&name].commits, 1);
+    assert_eq!(authorities[&name].share, 1.0);
+
+    let shape: DagShape = serde_json::from_str(&get(&address, "/dag_shape").await).unwrap();
+    assert_eq!(shape.widths.get(&62), Some(&1));
+
+    let sub_dags: Vec<SubDagStructure> =
+        serde_json::from_str(&get(&address, "/sub_dags").await).unwrap();
+    assert_eq!(sub_dags.len(), 1);
+    assert_eq!(sub_dags[0].vertices[0].certificate, certificate.digest());
+
+    let metrics = get(&address, "/metrics").await;
+    assert!(metrics.contains("primary_header_creation_seconds_count 1"));
+    assert!(metrics.contains("primary_round_stalls_total 0"));
+    assert!(metrics.contains("primary_leader_skips_total 0"));
+    assert!(metrics.contains("consensus_dag_certificates 10"));
+    assert!(metrics.contains("consensus_dag_bytes 5000"));
+    assert!(metrics.contains("primary_commit_latency_seconds_count 1"));
+    assert!(metrics.contains("# TYPE primary_committed_certificates_total counter"));
+    assert!(metrics.contains("primary_orphaned_certificates_total 0"));
+    assert!(metrics.contains("primary_sub_dag_certificates_sum 1"));
+    assert!(metrics.contains("primary_signature_seconds_count 0"));
+    assert!(metrics.contains("network_connection_attempts_total{peer=\"127.0.0.1:1\"} 1"));
+}
@@ -0,0 +1,68 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::aggregators::VotesAggregator;
+use crate::common::{bls_keys, committee_with_bls_keys, header, keys};
+
+#[tokio::test]
+async fn aggregate_votes() {
+    let committee = committee_with_bls_keys();
+    let header = header();
+
+    // Make a certificate out of votes signed with BLS.
+    let mut aggregator = VotesAggregator::new();
+    let mut certificate = None;
+    for ((name, secret), (_, bls_secret)) in keys().into_iter().zip(bls_keys()) {
+        let mut signature_service = SignatureService::new(secret).with_bls(bls_secret);
+        let vote = Vote::new(&header, &name, &mut signature_service).await;
+        assert!(vote.verify(&committee).is_ok());
+        if let Some(x) = aggregator.append(vote, &committee, &header).unwrap() {
+            certificate = Some(x);
+        }
+    }
+
+    // Ensure the certificate carries a single valid aggregate signature.
+    let mut certificate = certificate.unwrap();
+    assert!(certificate.votes.is_empty());
+    assert_eq!(
+        certificate
+            .aggregate
+            .as_ref()
+            .unwrap()
+            .signers(&committee)
+            .len(),
+        3
+    );
+    assert!(certificate.verify(&committee).is_ok());
+
+    // Ensure the certificate is rejected if it claims a voter that did not sign.
+    certificate.aggregate.as_mut().unwrap().signers = vec![0b1111];
+    assert!(certificate.verify(&committee).is_err());
+}
+
+#[tokio::test]
+async fn aggregate_requires_bls_votes() {
+    let committee = committee_with_bls_keys();
+    let header = header();
+
+    // Make a certificate out of votes of which only one is signed with BLS.
+    let mut aggregator = VotesAggregator::new();
+    let mut certificate = None;
+    let bls_secrets = bls_keys().into_iter().map(|(_, x)| Some(x));
+    let bls_secrets = bls_secrets.take(1).chain(std::iter::repeat_with(|| None));
+    for ((name, secret), bls_secret) in keys().into_iter().zip(bls_secrets) {
+        let mut signature_service = SignatureService::new(secret);
+        if let Some(bls_secret) = bls_secret {
+            signature_service = signature_service.with_bls(bls_secret);
+        }
+        let vote = Vote::new(&header, &name, &mut signature_service).await;
+        if let Some(x) = aggregator.append(vote, &committee, &header).unwrap() {
+            certificate = Some(x);
+        }
+    }
+
+    // Ensure the certificate falls back to the individual signatures.
+    let certificate = certificate.unwrap();
+    assert!(certificate.aggregate.is_none());
+    assert_eq!(certificate.votes.len(), 3);
+    assert!(certificate.verify(&committee).is_ok());
+}
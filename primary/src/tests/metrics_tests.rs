@@ -0,0 +1,186 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::keys;
+use crypto::Digest;
+
+#[test]
+fn record_latencies() {
+    let metrics = PipelineMetrics::new();
+
+    // Go through all stages of round 1.
+    metrics.record_round_start(1);
+    metrics.record_header(1);
+    metrics.record_vote_quorum(1);
+    metrics.record_certificate_quorum(1);
+
+    // Only the first quorum of certificates counts.
+    metrics.record_certificate_quorum(1);
+
+    // A round we did not create a header for only records its start.
+    metrics.record_round_start(2);
+    metrics.record_vote_quorum(2);
+
+    let rounds = metrics.rounds();
+    assert_eq!(rounds.len(), 2);
+    assert_eq!(rounds[0].round, 1);
+    assert!(rounds[0].header_creation.is_some());
+    assert!(rounds[0].vote_quorum.is_some());
+    assert!(rounds[0].certificate_quorum.is_some());
+    assert_eq!(
+        rounds[1],
+        RoundLatency {
+            round: 2,
+            ..RoundLatency::default()
+        }
+    );
+
+    let encoded = metrics.encode();
+    assert!(encoded.contains("# TYPE primary_vote_quorum_seconds summary"));
+    assert!(encoded.contains("primary_certificate_quorum_seconds_count 1"));
+}
+
+#[test]
+fn smooth_vote_quorum_latency() {
+    let metrics = PipelineMetrics::new();
+    assert!(metrics.vote_quorum_latency().is_none());
+
+    metrics.record_header(1);
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    metrics.record_vote_quorum(1);
+    let first = metrics.vote_quorum_latency().unwrap();
+    assert!(first >= 50.0);
+
+    // A faster round lowers the smoothed latency, without replacing it.
+    metrics.record_header(2);
+    metrics.record_vote_quorum(2);
+    let second = metrics.vote_quorum_latency().unwrap();
+    assert!(second < first && second > first / 2.0);
+}
+
+#[test]
+fn forget_old_rounds() {
+    let metrics = PipelineMetrics::new();
+    let last = MAX_TRACKED_ROUNDS as Round + 10;
+    for round in 1..=last {
+        metrics.record_round_start(round);
+    }
+    let rounds = metrics.rounds();
+    assert_eq!(rounds.len(), MAX_TRACKED_ROUNDS);
+    assert_eq!(rounds.last().unwrap().round, last);
+}
+
+#[test]
+fn commit_latency_percentiles() {
+    let metrics = CommitLatencyMetrics::new();
+    for latency in 1..=100 {
+        metrics.record(1, latency as f64);
+    }
+    metrics.record(2, 500.0);
+
+    let waves = metrics.waves();
+    assert_eq!(
+        waves[0],
+        WaveLatency {
+            wave: 1,
+            commits: 100,
+            p50: 50.0,
+            p90: 90.0,
+            p99: 99.0,
+        }
+    );
+    assert_eq!(waves[1].commits, 1);
+    assert_eq!(waves[1].p99, 500.0);
+
+    let encoded = metrics.encode();
+    assert!(encoded.contains("primary_commit_latency_seconds{quantile=\"0.5\"} 0.051"));
+    assert!(encoded.contains("primary_commit_latency_seconds_count 101"));
+}
+
+#[test]
+fn chain_quality() {
+    let metrics = ChainQualityMetrics::new();
+    let mut keys: Vec<_> = keys().into_iter().map(|(x, _)| x).collect();
+    keys.sort();
+    let (leader, other) = (keys[0], keys[1]);
+
+    // The leader of round 4 commits a certificate of each authority (including its own).
+    for (author, round, latency) in [(other, 3, 30.0), (leader, 4, 10.0)] {
+        metrics.record_commit(&CommitEvent {
+            round,
+            wave: 2,
+            leader,
+            leader_round: 4,
+            author,
+            certificate: Digest::default(),
+            latency,
+        });
+    }
+    // The slot of round 2 was not honored.
+    for (round, committed) in [(2, false), (4, true)] {
+        metrics.record_slot(&LeaderSlot {
+            round,
+            leader,
+            committed,
+        });
+    }
+
+    let authorities = metrics.authorities();
+    assert_eq!(
+        authorities[&leader],
+        AuthorityQuality {
+            commits: 1,
+            share: 0.5,
+            leader_slots: 2,
+            leaders_committed: 1,
+            inclusion_rounds: 0.0,
+            inclusion_delay: 10.0,
+        }
+    );
+    assert_eq!(authorities[&other].inclusion_rounds, 1.0);
+    assert_eq!(authorities[&other].leader_slots, 0);
+
+    let encoded = metrics.encode();
+    let label = format!("{{authority=\"{}\"}}", leader.encode_base64());
+    assert!(encoded.contains(&format!("primary_leader_slots_committed_total{} 1", label)));
+    assert!(encoded.contains(&format!(
+        "primary_inclusion_delay_seconds_sum{} 0.01",
+        label
+    )));
+}
+
+#[test]
+fn dag_shape() {
+    let metrics = DagShapeMetrics::new();
+    let (leader, _) = keys().pop().unwrap();
+
+    // Rounds 1 and 2 are complete, round 3 is still growing.
+    for (round, width) in [(1, 4), (2, 3), (3, 1)] {
+        for _ in 0..width {
+            metrics.record_certificate(round);
+        }
+    }
+    // The leader of round 4 is skipped: the next committed leader comes 4 rounds after the previous one.
+    for (round, committed) in [(2, true), (4, false), (6, true)] {
+        metrics.record_slot(&LeaderSlot {
+            round,
+            leader,
+            committed,
+        });
+    }
+    metrics.record_orphans(2);
+
+    let shape = metrics.shape();
+    let widths: Vec<_> = shape.widths.into_iter().collect();
+    assert_eq!(widths, vec![(1, 4), (2, 3), (3, 1)]);
+    assert_eq!(shape.committed_leaders, 2);
+    assert_eq!(shape.skipped_leaders, 1);
+    assert_eq!(shape.rounds_per_wave, 4.0);
+    assert_eq!(shape.orphans, 2);
+
+    let encoded = metrics.encode();
+    assert!(encoded.contains("primary_dag_width_min 3"));
+    assert!(encoded.contains("primary_dag_width_average 3.5"));
+    assert!(encoded.contains("primary_skipped_leaders_total 1"));
+    assert!(encoded.contains("primary_orphaned_certificates_total 2"));
+    assert!(encoded.contains("primary_rounds_per_wave_sum 4"));
+}
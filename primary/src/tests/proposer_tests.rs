@@ -1,7 +1,9 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
 use crate::common::{committee, keys};
+use std::fs;
 use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn propose_empty() {
@@ -10,17 +12,39 @@ async fn propose_empty() {
 
     let (_tx_parents, rx_parents) = channel(1);
     let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
     let (tx_headers, mut rx_headers) = channel(1);
 
+    // Create a new test store.
+    let path = ".db_test_propose_empty";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
     // Spawn the proposer.
     Proposer::spawn(
         name,
         &committee(),
         signature_service,
+        /* coin_key */ None,
+        store,
         /* header_size */ 1_000,
         /* max_header_delay */ 20,
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 0,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        Arc::new(DagProgress::default()),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
         /* tx_core */ tx_headers,
     );
 
@@ -31,6 +55,61 @@ async fn propose_empty() {
     assert!(header.verify(&committee()).is_ok());
 }
 
+#[tokio::test]
+async fn reload_header_delay() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (_tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_reload_header_delay";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the proposer with a very long delay between headers.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* coin_key */ None,
+        store,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 1_000_000,
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 0,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        Arc::new(DagProgress::default()),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
+        /* tx_core */ tx_headers,
+    );
+    let delay = Duration::from_millis(100);
+    assert!(timeout(delay, rx_headers.recv()).await.is_err());
+
+    // Ensure the proposer makes its header once the delay is reloaded.
+    let parameters = Parameters {
+        max_header_delay: 20,
+        ..Parameters::default()
+    };
+    tx_parameters.send(parameters).unwrap();
+    let header = timeout(delay, rx_headers.recv()).await.unwrap().unwrap();
+    assert_eq!(header.round, 1);
+}
+
 #[tokio::test]
 async fn propose_payload() {
     let (name, secret) = keys().pop().unwrap();
@@ -38,17 +117,39 @@ async fn propose_payload() {
 
     let (_tx_parents, rx_parents) = channel(1);
     let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
     let (tx_headers, mut rx_headers) = channel(1);
 
+    // Create a new test store.
+    let path = ".db_test_propose_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
     // Spawn the proposer.
     Proposer::spawn(
         name,
         &committee(),
         signature_service,
+        /* coin_key */ None,
+        store,
         /* header_size */ 32,
         /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 0,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        Arc::new(DagProgress::default()),
         /* rx_core */ rx_parents,
         /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
         /* tx_core */ tx_headers,
     );
 
@@ -66,3 +167,479 @@ async fn propose_payload() {
     assert_eq!(header.payload.get(&digest), Some(&worker_id));
     assert!(header.verify(&committee()).is_ok());
 }
+
+#[tokio::test]
+async fn requeue_unsequenced_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let consensus_round = Arc::new(AtomicU64::new(0));
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_requeue_unsequenced_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* coin_key */ None,
+        store,
+        /* header_size */ 32,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 0,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        consensus_round.clone(),
+        /* gc_depth */ 1,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        Arc::new(DagProgress::default()),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
+        /* tx_core */ tx_headers,
+    );
+
+    // Make a header with a digest.
+    let digest = Digest(name.0);
+    let worker_id = 0;
+    tx_our_digests
+        .send((digest.clone(), worker_id))
+        .await
+        .unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Garbage collect round 1 without sequencing the header, and move to round 2.
+    consensus_round.store(3, Ordering::Relaxed);
+    tx_parents.send((vec![Digest::default()], 1)).await.unwrap();
+
+    // Ensure the digest is re-included in the next header.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!(header.payload.get(&digest), Some(&worker_id));
+}
+
+#[tokio::test]
+async fn carry_over_unsequenced_payload() {
+    let (name, _) = keys().pop().unwrap();
+    let path = ".db_test_carry_over_unsequenced_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let digest = Digest(name.0);
+    let worker_id = 0;
+
+    for epoch in 0..2 {
+        let (_, secret) = keys().pop().unwrap();
+        let (_tx_parents, rx_parents) = channel(1);
+        let (tx_our_digests, rx_our_digests) = channel(1);
+        let (tx_sequenced, rx_sequenced) = channel(1);
+        let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+        let (tx_headers, mut rx_headers) = channel(1);
+
+        // Spawn the proposer of the epoch.
+        Proposer::spawn(
+            name,
+            &Committee {
+                epoch,
+                ..committee()
+            },
+            SignatureService::new(secret),
+            /* coin_key */ None,
+            store.clone(),
+            /* header_size */ 32,
+            /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+            /* min_header_delay */ 0,
+            /* adaptive_header_delay */ false,
+            /* max_header_size */ 0,
+            /* max_header_num_of_batches */ 0,
+            /* max_parent_delay */ 0,
+            /* include_late_parents */ false,
+            /* consensus_round */ Arc::new(AtomicU64::new(0)),
+            /* gc_depth */ 50,
+            /* ready */ Arc::new(AtomicBool::new(false)),
+            /* worker_weights */ HashMap::new(),
+            Arc::new(DagProgress::default()),
+            /* rx_core */ rx_parents,
+            /* rx_workers */ rx_our_digests,
+            /* rx_sequenced */ rx_sequenced,
+            rx_parameters,
+            /* tx_core */ tx_headers,
+        );
+
+        match epoch {
+            // Make a header with a digest, and end the epoch before it is sequenced.
+            0 => {
+                tx_our_digests
+                    .send((digest.clone(), worker_id))
+                    .await
+                    .unwrap();
+                let header = rx_headers.recv().await.unwrap();
+                assert_eq!(header.payload.get(&digest), Some(&worker_id));
+                let (sender, receiver) = oneshot::channel();
+                tx_sequenced
+                    .send(ProposerMessage::EndOfEpoch(sender))
+                    .await
+                    .unwrap();
+                receiver.await.unwrap();
+            }
+
+            // Ensure the digest is re-included in the first header of the next epoch.
+            _ => {
+                let header = rx_headers.recv().await.unwrap();
+                assert_eq!(header.epoch, 1);
+                assert_eq!(header.round, 1);
+                assert_eq!(header.payload.get(&digest), Some(&worker_id));
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn limit_header_payload() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(3);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_limit_header_payload";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* coin_key */ None,
+        store,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 1,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        Arc::new(DagProgress::default()),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
+        /* tx_core */ tx_headers,
+    );
+
+    // Send more digests than fit in a header.
+    let worker_id = 0;
+    for i in 0..3 {
+        tx_our_digests
+            .send((Digest([i; 32]), worker_id))
+            .await
+            .unwrap();
+    }
+
+    // Ensure each header carries a single digest, and that the pending ones make it to the next headers.
+    let mut included = Vec::new();
+    for round in 1..=3 {
+        let header = rx_headers.recv().await.unwrap();
+        assert_eq!(header.round, round);
+        assert_eq!(header.payload.len(), 1);
+        included.extend(header.payload.keys().cloned());
+        tx_parents
+            .send((vec![Digest::default()], round))
+            .await
+            .unwrap();
+    }
+    included.sort();
+    assert_eq!(
+        included,
+        (0..3).map(|i| Digest([i; 32])).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn balance_workers() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let progress = Arc::new(DagProgress::default());
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(6);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_balance_workers";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the proposer. Worker 0 gets twice the share of worker 1.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* coin_key */ None,
+        store,
+        /* header_size */ 1_000,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 3,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ vec![(0, 2)].into_iter().collect(),
+        progress.clone(),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
+        /* tx_core */ tx_headers,
+    );
+
+    // Fill the first header with digests of worker 0.
+    for i in 0..3 {
+        tx_our_digests.send((Digest([i; 32]), 0)).await.unwrap();
+    }
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Both workers now have a backlog while the proposer waits for the parents of round 1.
+    for i in 3..6 {
+        tx_our_digests.send((Digest([i; 32]), 0)).await.unwrap();
+        tx_our_digests
+            .send((Digest([i + 10; 32]), 1))
+            .await
+            .unwrap();
+    }
+    sleep(Duration::from_millis(100)).await;
+    let backlog = progress.worker_backlog.lock().unwrap().clone();
+    assert_eq!(backlog, vec![(0, 3), (1, 3)].into_iter().collect());
+
+    // Ensure the workers share the next headers according to their weights.
+    let count =
+        |header: &Header, worker_id| header.payload.values().filter(|x| **x == worker_id).count();
+    tx_parents.send((vec![Digest::default()], 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!((count(&header, 0), count(&header, 1)), (2, 1));
+
+    tx_parents.send((vec![Digest::default()], 2)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 3);
+    assert_eq!((count(&header, 0), count(&header, 1)), (1, 2));
+}
+
+#[tokio::test]
+async fn adapt_header_delay() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+    let progress = Arc::new(DagProgress::default());
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_adapt_header_delay";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* coin_key */ None,
+        store,
+        /* header_size */ 32,
+        /* max_header_delay */
+        1_000_000, // Only reached without vote round-trip samples.
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ true,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 0,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        progress.clone(),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
+        /* tx_core */ tx_headers,
+    );
+
+    // Make a full header and gather its votes.
+    tx_our_digests.send((Digest([1; 32]), 0)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+    sleep(Duration::from_millis(20)).await;
+    progress.pipeline.record_vote_quorum(1);
+
+    // Make another full header: the proposer now waits about one vote round-trip between headers.
+    tx_our_digests.send((Digest([2; 32]), 0)).await.unwrap();
+    tx_parents.send((vec![Digest::default()], 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+
+    // Ensure the next (empty) header does not wait for `max_header_delay`.
+    tx_parents.send((vec![Digest::default()], 2)).await.unwrap();
+    let header = timeout(Duration::from_millis(1_000), rx_headers.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(header.round, 3);
+    assert!(header.payload.is_empty());
+}
+
+#[tokio::test]
+async fn wait_for_late_parents() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_wait_for_late_parents";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* coin_key */ None,
+        store,
+        /* header_size */ 0,
+        /* max_header_delay */ 1_000_000, // Ensure it is not triggered.
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 0,
+        /* max_parent_delay */ 1_000_000, // Ensure it is not triggered.
+        /* include_late_parents */ true,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        Arc::new(DagProgress::default()),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
+        /* tx_core */ tx_headers,
+    );
+
+    // The first header references all the genesis certificates.
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 1);
+
+    // Send a quorum of parents: the proposer waits for the last one.
+    let parents: Vec<_> = (0..4).map(|i| Digest([i; 32])).collect();
+    tx_parents.send((parents[..3].to_vec(), 1)).await.unwrap();
+    let result = timeout(Duration::from_millis(100), rx_headers.recv()).await;
+    assert!(result.is_err());
+
+    // Send the late parent and ensure it is included in the next header.
+    tx_parents.send((parents[3..].to_vec(), 1)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 2);
+    assert_eq!(header.parents, parents.into_iter().collect());
+}
+
+#[tokio::test]
+async fn resume_after_restart() {
+    let (name, secret) = keys().pop().unwrap();
+    let signature_service = SignatureService::new(secret);
+
+    let (tx_parents, rx_parents) = channel(1);
+    let (_tx_our_digests, rx_our_digests) = channel(1);
+    let (_tx_sequenced, rx_sequenced) = channel(1);
+    let (_tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_headers, mut rx_headers) = channel(1);
+
+    // Create a new test store holding the header we created for round 5 before crashing.
+    let path = ".db_test_resume_after_restart";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let header = Header {
+        round: 5,
+        ..Header::default()
+    };
+    RecoveryStore::new(store.clone(), /* epoch */ 0)
+        .write_header(&header)
+        .await;
+
+    // Spawn the proposer.
+    Proposer::spawn(
+        name,
+        &committee(),
+        signature_service,
+        /* coin_key */ None,
+        store,
+        /* header_size */ 0,
+        /* max_header_delay */ 20,
+        /* min_header_delay */ 0,
+        /* adaptive_header_delay */ false,
+        /* max_header_size */ 0,
+        /* max_header_num_of_batches */ 0,
+        /* max_parent_delay */ 0,
+        /* include_late_parents */ false,
+        /* consensus_round */ Arc::new(AtomicU64::new(0)),
+        /* gc_depth */ 50,
+        /* ready */ Arc::new(AtomicBool::new(false)),
+        /* worker_weights */ HashMap::new(),
+        Arc::new(DagProgress::default()),
+        /* rx_core */ rx_parents,
+        /* rx_workers */ rx_our_digests,
+        /* rx_sequenced */ rx_sequenced,
+        rx_parameters,
+        /* tx_core */ tx_headers,
+    );
+
+    // Ensure the proposer does not propose again for the rounds up to 5 (not even with their parents).
+    tx_parents.send((vec![Digest::default()], 4)).await.unwrap();
+    let result = timeout(Duration::from_millis(100), rx_headers.recv()).await;
+    assert!(result.is_err());
+
+    // Ensure it resumes once it gets a quorum of certificates of round 5.
+    tx_parents.send((vec![Digest::default()], 5)).await.unwrap();
+    let header = rx_headers.recv().await.unwrap();
+    assert_eq!(header.round, 6);
+}
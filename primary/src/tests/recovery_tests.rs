@@ -0,0 +1,41 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{header, keys, votes};
+use std::fs;
+
+#[tokio::test]
+async fn persist_state() {
+    // Create a new test store.
+    let path = ".db_test_persist_state";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let mut recovery = RecoveryStore::new(store.clone(), /* epoch */ 0);
+
+    // Nothing is persisted initially.
+    assert!(recovery.read_header().await.unwrap().is_none());
+    assert!(recovery.read_votes().await.unwrap().is_none());
+    assert!(recovery.read_voted(1).await.unwrap().is_empty());
+
+    // Persist our last header, its votes, and the headers we voted for.
+    let header = header();
+    let votes = votes(&header);
+    recovery.write_header(&header).await;
+    recovery.write_votes(&header.id, &votes).await;
+    let authors: HashSet<_> = keys().into_iter().map(|(name, _)| name).collect();
+    recovery.write_voted(1, &authors).await;
+
+    // Ensure the state is read back.
+    assert_eq!(recovery.read_header().await.unwrap(), Some(header.clone()));
+    let (id, stored) = recovery.read_votes().await.unwrap().unwrap();
+    assert_eq!(id, header.id);
+    assert_eq!(stored, votes);
+    assert_eq!(recovery.read_voted(1).await.unwrap(), authors);
+
+    // The state of another epoch is separate.
+    let mut other = RecoveryStore::new(store, /* epoch */ 1);
+    assert!(other.read_header().await.unwrap().is_none());
+
+    // Ensure garbage collected rounds are forgotten.
+    recovery.remove_voted(1).await;
+    assert!(recovery.read_voted(1).await.unwrap().is_empty());
+}
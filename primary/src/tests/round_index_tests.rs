@@ -0,0 +1,67 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, headers};
+use crate::messages::Header;
+use std::fs;
+
+/// Returns the certificates of all authorities for the rounds 1 to 3.
+fn certificates() -> Vec<Certificate> {
+    let mut certificates = Vec::new();
+    for round in 1..=3 {
+        for header in headers() {
+            let mut header = Header { round, ..header };
+            header.id = header.digest();
+            certificates.push(certificate(&header));
+        }
+    }
+    certificates
+}
+
+#[tokio::test]
+async fn index_by_round_and_author() {
+    // Create a new test store.
+    let path = ".db_test_index_by_round_and_author";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Index the certificates.
+    let mut round_index = RoundIndex::new(store, /* epoch */ 0);
+    let certificates = certificates();
+    for certificate in &certificates {
+        round_index.insert(certificate).await.unwrap();
+    }
+    assert_eq!(round_index.last_round().await.unwrap(), 3);
+
+    // Query the certificates of a round.
+    let mut digests = round_index.read(2).await.unwrap();
+    digests.sort();
+    let mut expected: Vec<_> = certificates
+        .iter()
+        .filter(|x| x.round() == 2)
+        .map(|x| x.digest())
+        .collect();
+    expected.sort();
+    assert_eq!(digests, expected);
+
+    // Query the certificates of an author over a range of rounds.
+    let author = certificates[0].origin();
+    let expected: Vec<_> = certificates
+        .iter()
+        .filter(|x| x.origin() == author && x.round() >= 2)
+        .map(|x| (x.round(), x.digest()))
+        .collect();
+    let result = round_index.read_author(&author, 2, Round::MAX).await;
+    assert_eq!(result.unwrap(), expected);
+
+    // Remove a round from both indices.
+    round_index.remove(2).await.unwrap();
+    assert!(round_index.read(2).await.unwrap().is_empty());
+    let rounds: Vec<_> = round_index
+        .read_author(&author, 0, Round::MAX)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(round, _)| round)
+        .collect();
+    assert_eq!(rounds, vec![1, 3]);
+}
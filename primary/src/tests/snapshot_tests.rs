@@ -0,0 +1,83 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, header};
+use crate::messages::Header;
+use std::fs;
+
+/// Store a certificate at each of the specified rounds (indexed by round).
+async fn store_certificates(store: &mut Store, rounds: Round) -> Vec<Certificate> {
+    let mut round_index = RoundIndex::new(store.clone(), /* epoch */ 0);
+    let mut certificates = Vec::new();
+    for round in 1..=rounds {
+        let mut header = Header { round, ..header() };
+        header.id = header.digest();
+        let certificate = certificate(&header);
+        let bytes = bincode::serialize(&certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        round_index.insert(&certificate).await.unwrap();
+        certificates.push(certificate);
+    }
+    certificates
+}
+
+#[tokio::test]
+async fn load_snapshot() {
+    // Create a new test store.
+    let path = ".db_test_load_snapshot";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificates = store_certificates(&mut store, 3).await;
+
+    // Load the whole DAG.
+    let snapshot = DagSnapshot::load(store.clone(), /* epoch */ 0, 1, None)
+        .await
+        .unwrap();
+    let expected: Vec<_> = certificates.iter().map(Vertex::from).collect();
+    assert_eq!(snapshot.vertices, expected);
+    assert_eq!(snapshot.vertices[0].parents.len(), 4);
+
+    // Load a single round.
+    let snapshot = DagSnapshot::load(store, /* epoch */ 0, 2, Some(2))
+        .await
+        .unwrap();
+    assert_eq!(snapshot.vertices, vec![Vertex::from(&certificates[1])]);
+}
+
+#[tokio::test]
+async fn export_import_snapshot() {
+    // Create a new test store.
+    let path = ".db_test_export_import_snapshot";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store_certificates(&mut store, 2).await;
+    let snapshot = DagSnapshot::load(store, /* epoch */ 0, 1, None)
+        .await
+        .unwrap();
+
+    // Ensure the snapshot survives a round-trip through a file, in both formats.
+    for (file, format) in [
+        (".test_snapshot.json", SnapshotFormat::Json),
+        (".test_snapshot.bin", SnapshotFormat::Binary),
+    ] {
+        snapshot.export(file, format).unwrap();
+        assert_eq!(DagSnapshot::import(file, format).unwrap(), snapshot);
+        let _ = fs::remove_file(file);
+    }
+}
+
+#[tokio::test]
+async fn snapshot_certificates() {
+    // Create a new test store.
+    let path = ".db_test_snapshot_certificates";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let certificates = store_certificates(&mut store, 2).await;
+    let snapshot = DagSnapshot::load(store, /* epoch */ 0, 1, None)
+        .await
+        .unwrap();
+
+    // The certificates rebuilt from the snapshot keep their digests.
+    let digests: Vec<_> = snapshot.certificates().iter().map(|x| x.digest()).collect();
+    let expected: Vec<_> = certificates.iter().map(|x| x.digest()).collect();
+    assert_eq!(digests, expected);
+}
@@ -0,0 +1,45 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, listener, transport};
+use network::NetworkMetrics;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn request_missing_rounds() {
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(13_300);
+
+    // Our DAG is at round 5 while we observe certificates of round 30.
+    let progress = Arc::new(DagProgress::default());
+    progress.dag_round.store(5, Ordering::Relaxed);
+    progress.observed_round.store(30, Ordering::Relaxed);
+
+    // Spawn a listener to receive our sync request.
+    let (_, authority) = committee.others_primaries(&name).pop().unwrap();
+    let handle = listener(authority.primary_to_primary.advertise);
+
+    // Spawn the state synchronizer.
+    let (tx_core, _rx_core) = channel(1);
+    StateSynchronizer::spawn(
+        name,
+        committee,
+        progress,
+        /* catch_up_threshold */ 10,
+        /* sync_retry_delay */ 1_000,
+        /* sync_retry_nodes */ 3,
+        transport(),
+        NetworkMetrics::default(),
+        tx_core,
+    );
+
+    // Ensure we request the first missing rounds (up to the maximum range).
+    let received = handle.await.unwrap();
+    match bincode::deserialize(&received).unwrap() {
+        PrimaryMessage::CertificatesRangeRequest(start, end, requestor) => {
+            assert_eq!(start, 6);
+            assert_eq!(end, 6 + MAX_SYNC_RANGE - 1);
+            assert_eq!(requestor, name);
+        }
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, header, votes};
+use crypto::Signature;
+use tokio::sync::mpsc::channel;
+use tokio::time::{timeout, Duration};
+
+#[tokio::test]
+async fn drop_invalid_messages() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+
+    // Make a batch of valid votes, with a forged vote in the middle.
+    let mut forged = votes(&header());
+    forged[1].signature = Signature::default();
+    let mut messages: Vec<_> = forged.into_iter().map(PrimaryMessage::Vote).collect();
+    messages.push(PrimaryMessage::Header(header()));
+    messages.push(PrimaryMessage::Certificate(certificate(&header())));
+    for message in messages {
+        tx_primaries.send(message).await.unwrap();
+    }
+
+    // Spawn the verifier.
+    Verifier::spawn(
+        committee(),
+        /* duplicate_cache_size */ 100,
+        rx_primaries,
+        tx_core,
+    );
+
+    // Ensure only the valid messages reach the core, in order.
+    let mut expected = votes(&header());
+    expected.remove(1);
+    for expected in expected {
+        match rx_core.recv().await.unwrap() {
+            PrimaryMessage::Vote(vote) => assert_eq!(vote.digest(), expected.digest()),
+            x => panic!("Unexpected message: {:?}", x),
+        }
+    }
+    match rx_core.recv().await.unwrap() {
+        PrimaryMessage::Header(x) => assert_eq!(x, header()),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+    match rx_core.recv().await.unwrap() {
+        PrimaryMessage::Certificate(x) => assert_eq!(x.digest(), certificate(&header()).digest()),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+}
+
+#[tokio::test]
+async fn drop_duplicates() {
+    let (tx_primaries, rx_primaries) = channel(10);
+    let (tx_core, mut rx_core) = channel(10);
+
+    // Spawn the verifier.
+    Verifier::spawn(
+        committee(),
+        /* duplicate_cache_size */ 100,
+        rx_primaries,
+        tx_core,
+    );
+
+    // Send a forged copy of a header, followed by the valid header and one of its replays.
+    let mut forged = header();
+    forged.signature = Signature::default();
+    tx_primaries
+        .send(PrimaryMessage::Header(forged))
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        tx_primaries
+            .send(PrimaryMessage::Header(header()))
+            .await
+            .unwrap();
+    }
+    match rx_core.recv().await.unwrap() {
+        PrimaryMessage::Header(x) => assert_eq!(x, header()),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+
+    // Replay the header and a vote (twice): only the first copy of the vote reaches the core.
+    let vote = votes(&header()).pop().unwrap();
+    tx_primaries
+        .send(PrimaryMessage::Header(header()))
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        tx_primaries
+            .send(PrimaryMessage::Vote(vote.clone()))
+            .await
+            .unwrap();
+    }
+    match rx_core.recv().await.unwrap() {
+        PrimaryMessage::Vote(x) => assert_eq!(x.digest(), vote.digest()),
+        x => panic!("Unexpected message: {:?}", x),
+    }
+    let result = timeout(Duration::from_millis(100), rx_core.recv()).await;
+    assert!(result.is_err());
+}
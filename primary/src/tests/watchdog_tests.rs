@@ -0,0 +1,85 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{certificate, committee, headers, keys};
+use crate::introspection::PendingVotes;
+use crypto::Hash as _;
+use std::fs;
+
+#[tokio::test]
+async fn diagnose_stall() {
+    let committee = committee();
+    let (name, _) = keys().pop().unwrap();
+
+    // Create a new test store.
+    let path = ".db_test_diagnose_stall";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Store the certificates of round 1 of all authorities but the first one.
+    let mut round_index = RoundIndex::new(store.clone(), committee.epoch);
+    let headers = headers();
+    for header in &headers[1..] {
+        let certificate = certificate(header);
+        let bytes = bincode::serialize(&certificate).unwrap();
+        store.write(certificate.digest().to_vec(), bytes).await;
+        round_index.insert(&certificate).await.unwrap();
+    }
+
+    // Our last header (of round 1) only got our own vote.
+    let progress = Arc::new(DagProgress::default());
+    *progress.pending_votes.lock().unwrap() = PendingVotes {
+        round: 1,
+        voters: vec![name],
+        ..PendingVotes::default()
+    };
+
+    let watchdog = StallWatchdog {
+        committee: committee.clone(),
+        store,
+        progress,
+        stall_timeout: 1_000,
+        rx_parameters: watch::channel(Parameters::default()).1,
+    };
+    let report = watchdog.diagnose(Instant::now()).await.unwrap();
+    assert_eq!(report.round, 0);
+    assert_eq!(report.missing_parents, vec![headers[0].author]);
+    assert_eq!(report.header_round, 1);
+    let mut expected: Vec<_> = committee
+        .authorities
+        .keys()
+        .filter(|x| **x != name)
+        .cloned()
+        .collect();
+    expected.sort();
+    let mut missing_votes = report.missing_votes;
+    missing_votes.sort();
+    assert_eq!(missing_votes, expected);
+}
+
+#[tokio::test]
+async fn alert_on_stall() {
+    // Create a new test store.
+    let path = ".db_test_alert_on_stall";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let progress = Arc::new(DagProgress::default());
+    StallWatchdog::spawn(
+        committee(),
+        store,
+        progress.clone(),
+        /* stall_timeout */ 100,
+        /* rx_parameters */ watch::channel(Parameters::default()).1,
+    );
+
+    // The DAG makes progress: no alert.
+    for _ in 0..4 {
+        sleep(Duration::from_millis(50)).await;
+        progress.certificates.fetch_add(1, Ordering::Relaxed);
+    }
+    assert_eq!(progress.stalls.load(Ordering::Relaxed), 0);
+
+    // The DAG stalls.
+    sleep(Duration::from_millis(300)).await;
+    assert!(progress.stalls.load(Ordering::Relaxed) >= 1);
+}
@@ -0,0 +1,188 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::{DagError, DagResult};
+use crate::primary::PrimaryMessage;
+use config::Committee;
+use crypto::{Digest, Hash as _, PublicKey, Signature};
+use log::{debug, warn};
+use lru::LruCache;
+use rayon::prelude::*;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/verifier_tests.rs"]
+pub mod verifier_tests;
+
+/// The maximum number of messages verified in a single batch.
+pub const MAX_BATCH_SIZE: usize = 256;
+
+/// Identifies a message regardless of the peer it came from (or of the way it was re-broadcast).
+#[derive(Hash, PartialEq, Eq)]
+enum MessageKey {
+    Header(Digest),
+    Vote(Digest, PublicKey),
+    Certificate(Digest),
+    HeaderShard(Digest, usize),
+    CompactCertificate(Digest),
+}
+
+impl MessageKey {
+    /// Returns the key of the message (if duplicates of this kind of message can be dropped).
+    fn new(message: &PrimaryMessage) -> Option<Self> {
+        match message {
+            PrimaryMessage::Header(header) => Some(Self::Header(header.id.clone())),
+            PrimaryMessage::Vote(vote) => Some(Self::Vote(vote.digest(), vote.author)),
+            PrimaryMessage::Certificate(certificate) => {
+                Some(Self::Certificate(certificate.digest()))
+            }
+            PrimaryMessage::HeaderShard(shard) => {
+                Some(Self::HeaderShard(shard.digest(), shard.index))
+            }
+            PrimaryMessage::CompactCertificate(certificate) => {
+                Some(Self::CompactCertificate(certificate.digest()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Verifies the signatures of the headers, votes, and certificates received from the other primaries before
+/// handing them to the `Core`. The messages are verified in batches off the async runtime: the Ed25519
+/// signatures of the votes are batch-verified while headers and certificates are checked in parallel on
+/// the rayon thread pool. Valid messages are delivered in the order they were received.
+///
+/// The verifier remembers the most recent valid messages, so that replayed or re-broadcast copies are dropped
+/// before paying for their signature verification (the digests bind their content).
+pub struct Verifier {
+    /// The committee information.
+    committee: Arc<Committee>,
+    /// The keys of the most recently verified messages (if enabled).
+    verified: Option<LruCache<MessageKey, ()>>,
+    /// Receives the messages from the other primaries.
+    rx_primaries: Receiver<PrimaryMessage>,
+    /// Delivers the verified messages to the `Core`.
+    tx_core: Sender<PrimaryMessage>,
+}
+
+impl Verifier {
+    pub fn spawn(
+        committee: Committee,
+        duplicate_cache_size: usize,
+        rx_primaries: Receiver<PrimaryMessage>,
+        tx_core: Sender<PrimaryMessage>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                committee: Arc::new(committee),
+                verified: NonZeroUsize::new(duplicate_cache_size).map(LruCache::new),
+                rx_primaries,
+                tx_core,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Returns the messages of the batch that passed verification (in their original order).
+    fn verify(committee: &Committee, batch: Vec<PrimaryMessage>) -> Vec<PrimaryMessage> {
+        // Verify all messages in parallel, except the Ed25519 signatures of the votes.
+        let mut results: Vec<DagResult<()>> = batch
+            .par_iter()
+            .map(|message| match message {
+                PrimaryMessage::Header(header) => header.verify(committee),
+                PrimaryMessage::Vote(vote) => vote.verify_authority(committee),
+                PrimaryMessage::Certificate(certificate) => certificate.verify(committee),
+                PrimaryMessage::HeaderShard(shard) => shard.verify(committee),
+                PrimaryMessage::CompactCertificate(certificate) => certificate.verify(committee),
+                _ => Ok(()),
+            })
+            .collect();
+
+        // Batch-verify the signatures of the votes. If the batch fails, find the culprits one by one.
+        let votes: Vec<_> = batch
+            .iter()
+            .zip(results.iter())
+            .enumerate()
+            .filter_map(|(i, (message, result))| match (message, result) {
+                (PrimaryMessage::Vote(vote), Ok(())) => Some((i, vote, vote.digest())),
+                _ => None,
+            })
+            .collect();
+        let items = votes
+            .iter()
+            .map(|(_, vote, digest)| (digest, &vote.author, &vote.signature));
+        if Signature::verify_many(items).is_err() {
+            let failures: Vec<(usize, DagError)> = votes
+                .par_iter()
+                .filter_map(|(i, vote, _)| vote.verify(committee).err().map(|e| (*i, e)))
+                .collect();
+            for (i, e) in failures {
+                results[i] = Err(e);
+            }
+        }
+
+        batch
+            .into_iter()
+            .zip(results)
+            .filter_map(|(message, result)| match result {
+                Ok(()) => Some(message),
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Returns whether we already verified (a copy of) the message. Otherwise, remember it if `insert` is set.
+    fn is_duplicate(&mut self, message: &PrimaryMessage, insert: bool) -> bool {
+        let (verified, key) = match (self.verified.as_mut(), MessageKey::new(message)) {
+            (Some(verified), Some(key)) => (verified, key),
+            _ => return false,
+        };
+        if verified.get(&key).is_some() {
+            debug!("Dropping duplicate {:?}", message);
+            return true;
+        }
+        if insert {
+            verified.put(key, ());
+        }
+        false
+    }
+
+    async fn run(&mut self) {
+        while let Some(message) = self.rx_primaries.recv().await {
+            // Gather all messages already waiting in the channel (up to the maximum batch size).
+            let mut batch = vec![message];
+            while batch.len() < MAX_BATCH_SIZE {
+                match self.rx_primaries.try_recv() {
+                    Ok(message) => batch.push(message),
+                    Err(_) => break,
+                }
+            }
+
+            // Drop the copies of the messages we already verified. The copies within the batch are verified
+            // (so that a forged copy cannot shadow the valid one), but only the first valid one is delivered.
+            batch.retain(|message| !self.is_duplicate(message, false));
+            if batch.is_empty() {
+                continue;
+            }
+
+            // Verify the batch without blocking the runtime, and deliver the valid messages to the core.
+            let committee = self.committee.clone();
+            let verified = tokio::task::spawn_blocking(move || Self::verify(&committee, batch))
+                .await
+                .expect("Failed to verify messages");
+            for message in verified {
+                if self.is_duplicate(&message, true) {
+                    continue;
+                }
+                self.tx_core
+                    .send(message)
+                    .await
+                    .expect("Failed to deliver message to the core");
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::error::DagResult;
+use crate::messages::Certificate;
+use crate::primary::Round;
+use crate::round_index::RoundIndex;
+use crate::state_synchronizer::DagProgress;
+use config::{Committee, Parameters};
+use crypto::PublicKey;
+use log::warn;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use store::Store;
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration, Instant};
+
+#[cfg(test)]
+#[path = "tests/watchdog_tests.rs"]
+pub mod watchdog_tests;
+
+/// The diagnostic of a stalled DAG.
+#[derive(Debug, PartialEq)]
+pub struct StallReport {
+    /// How long we have been without new certificates, in ms.
+    pub stalled_for: u128,
+    /// The highest round for which we gathered a quorum of certificates.
+    pub round: Round,
+    /// The highest round of the certificates we received.
+    pub observed_round: Round,
+    /// The authorities whose certificate of the next round (ie. the parents of the following headers) we
+    /// do not have.
+    pub missing_parents: Vec<PublicKey>,
+    /// The round of our last header.
+    pub header_round: Round,
+    /// The authorities that did not vote (yet) for our last header, if it is not certified.
+    pub missing_votes: Vec<PublicKey>,
+}
+
+impl fmt::Display for StallReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys = |x: &[PublicKey]| {
+            x.iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        write!(
+            f,
+            "stalled_for={}ms round={} observed_round={} missing_parents=[{}] header_round={} missing_votes=[{}]",
+            self.stalled_for,
+            self.round,
+            self.observed_round,
+            keys(&self.missing_parents),
+            self.header_round,
+            keys(&self.missing_votes)
+        )
+    }
+}
+
+/// Raises an alert when the DAG stops growing, ie. when no certificate was formed nor received for
+/// `stall_timeout`, along with a diagnostic of what the primary is waiting for. The alert is repeated every
+/// `stall_timeout` until the DAG makes progress again.
+pub struct StallWatchdog {
+    /// The committee information.
+    committee: Committee,
+    /// The persistent storage.
+    store: Store,
+    /// The progress of our local DAG.
+    progress: Arc<DagProgress>,
+    /// The duration without new certificates after which we raise an alert, in ms.
+    stall_timeout: u64,
+    /// Receives the parameters reloaded while we run (we apply the new stall timeout).
+    rx_parameters: watch::Receiver<Parameters>,
+}
+
+impl StallWatchdog {
+    pub fn spawn(
+        committee: Committee,
+        store: Store,
+        progress: Arc<DagProgress>,
+        stall_timeout: u64,
+        rx_parameters: watch::Receiver<Parameters>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                committee,
+                store,
+                progress,
+                stall_timeout,
+                rx_parameters,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Returns the diagnostic of the DAG (we are stalled since `since`).
+    async fn diagnose(&self, since: Instant) -> DagResult<StallReport> {
+        let round = self.progress.dag_round.load(Ordering::Relaxed);
+
+        // Find the authorities whose certificate of the next round we miss.
+        let mut store = self.store.clone();
+        let mut round_index = RoundIndex::new(store.clone(), self.committee.epoch);
+        let mut origins = HashSet::new();
+        for digest in round_index.read(round + 1).await? {
+            if let Some(bytes) = store.read(digest.to_vec()).await? {
+                let certificate: Certificate = bincode::deserialize(&bytes)?;
+                origins.insert(certificate.origin());
+            }
+        }
+        let missing_parents = self
+            .committee
+            .authorities
+            .keys()
+            .filter(|x| !origins.contains(x))
+            .cloned()
+            .collect();
+
+        // Find the authorities that did not vote for our last header.
+        let pending_votes = self.progress.pending_votes.lock().unwrap().clone();
+        let missing_votes = match pending_votes.certified {
+            true => Vec::new(),
+            false => self
+                .committee
+                .authorities
+                .keys()
+                .filter(|x| !pending_votes.voters.contains(x))
+                .cloned()
+                .collect(),
+        };
+
+        Ok(StallReport {
+            stalled_for: since.elapsed().as_millis(),
+            round,
+            observed_round: self.progress.observed_round.load(Ordering::Relaxed),
+            missing_parents,
+            header_round: pending_votes.round,
+            missing_votes,
+        })
+    }
+
+    async fn run(&mut self) {
+        let mut last_count = self.progress.certificates.load(Ordering::Relaxed);
+        let mut since = Instant::now();
+        let mut last_alert = since;
+        loop {
+            if self.rx_parameters.has_changed().unwrap_or(false) {
+                self.stall_timeout = self.rx_parameters.borrow_and_update().stall_timeout;
+            }
+            let timeout = Duration::from_millis(self.stall_timeout);
+            sleep(timeout / 4).await;
+
+            let count = self.progress.certificates.load(Ordering::Relaxed);
+            if count != last_count {
+                last_count = count;
+                since = Instant::now();
+                last_alert = since;
+                continue;
+            }
+            if last_alert.elapsed() < timeout {
+                continue;
+            }
+
+            last_alert = Instant::now();
+            self.progress.stalls.fetch_add(1, Ordering::Relaxed);
+            match self.diagnose(since).await {
+                Ok(report) => warn!("Round stall: {}", report),
+                Err(e) => warn!("Round stall (failed to diagnose: {})", e),
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use rocksdb::{Direction, IteratorMode};
 use std::collections::{HashMap, VecDeque};
 use tokio::sync::mpsc::{channel, Sender};
 use tokio::sync::oneshot;
@@ -15,8 +16,10 @@ type Value = Vec<u8>;
 
 pub enum StoreCommand {
     Write(Key, Value),
+    Delete(Key),
     Read(Key, oneshot::Sender<StoreResult<Option<Value>>>),
     NotifyRead(Key, oneshot::Sender<StoreResult<Value>>),
+    ReadRange(Key, Key, oneshot::Sender<StoreResult<Vec<(Key, Value)>>>),
 }
 
 #[derive(Clone)]
@@ -40,6 +43,9 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::Delete(key) => {
+                        let _ = db.delete(&key);
+                    }
                     StoreCommand::Read(key, sender) => {
                         let response = db.get(&key);
                         let _ = sender.send(response);
@@ -56,6 +62,21 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::ReadRange(start, end, sender) => {
+                        let mut entries = Vec::new();
+                        let mut response = Ok(());
+                        for item in db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+                            match item {
+                                Ok((key, _)) if *key >= *end => break,
+                                Ok((key, value)) => entries.push((key.to_vec(), value.to_vec())),
+                                Err(e) => {
+                                    response = Err(e);
+                                    break;
+                                }
+                            }
+                        }
+                        let _ = sender.send(response.map(|()| entries));
+                    }
                 }
             }
         });
@@ -68,6 +89,12 @@ impl Store {
         }
     }
 
+    pub async fn delete(&mut self, key: Key) {
+        if let Err(e) = self.channel.send(StoreCommand::Delete(key)).await {
+            panic!("Failed to send Delete command to store: {}", e);
+        }
+    }
+
     pub async fn read(&mut self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
@@ -91,4 +118,19 @@ impl Store {
             .await
             .expect("Failed to receive reply to NotifyRead command from store")
     }
+
+    /// Returns the entries whose key is in the range [start, end), sorted by key.
+    pub async fn read_range(&mut self, start: Key, end: Key) -> StoreResult<Vec<(Key, Value)>> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self
+            .channel
+            .send(StoreCommand::ReadRange(start, end, sender))
+            .await
+        {
+            panic!("Failed to send ReadRange command to store: {}", e);
+        }
+        receiver
+            .await
+            .expect("Failed to receive reply to ReadRange command from store")
+    }
 }
@@ -31,6 +31,25 @@ async fn read_write_value() {
     assert_eq!(read_value.unwrap(), value);
 }
 
+#[tokio::test]
+async fn delete_value() {
+    // Create new store.
+    let path = ".db_test_delete_value";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write value to the store and delete it.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+    store.write(key.clone(), value).await;
+    store.delete(key.clone()).await;
+
+    // Ensure the value is gone.
+    let result = store.read(key).await;
+    assert!(result.is_ok());
+    assert!(result.unwrap().is_none());
+}
+
 #[tokio::test]
 async fn read_unknown_key() {
     // Create new store.
@@ -72,3 +91,27 @@ async fn read_notify() {
     store.write(key, value).await;
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn read_range() {
+    // Create new store.
+    let path = ".db_test_read_range";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Write values under keys sharing a prefix, and one outside of it.
+    for i in 0..5u8 {
+        store.write(vec![1u8, i], vec![i]).await;
+    }
+    store.write(vec![2u8, 0u8], vec![9u8]).await;
+
+    // Read a range of keys.
+    let result = store.read_range(vec![1u8, 1u8], vec![1u8, 4u8]).await;
+    assert!(result.is_ok());
+    let expected: Vec<_> = (1..4u8).map(|i| (vec![1u8, i], vec![i])).collect();
+    assert_eq!(result.unwrap(), expected);
+
+    // Read a whole prefix.
+    let result = store.read_range(vec![1u8], vec![2u8]).await;
+    assert_eq!(result.unwrap().len(), 5);
+}
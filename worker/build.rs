@@ -0,0 +1,10 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use the vendored `protoc` so that building does not require a system-wide installation.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    // The generated `connect` helper of the client relies on the 2021 prelude: clients build their channel.
+    tonic_build::configure()
+        .build_transport(false)
+        .compile(&["proto/transactions.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use lru::LruCache;
+use network::{RateLimit, TokenBucket};
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+#[cfg(test)]
+#[path = "tests/admission_tests.rs"]
+pub mod admission_tests;
+
+/// The number of client addresses whose quota we remember (the least recently seen ones are forgotten).
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// The transactions admitted into the worker that are not yet sealed into a batch.
+#[derive(Default)]
+struct Pending {
+    /// The number of pending transactions.
+    transactions: usize,
+    /// The size of the pending transactions (in bytes).
+    bytes: usize,
+}
+
+/// Bounds the memory used by the transactions waiting to be batched. Each transaction entering the worker
+/// is admitted (counted as pending) until the `BatchMaker` seals it into a batch, or until it is dropped on
+/// the way (eg. as a duplicate). Clients may additionally be limited to a quota per source address.
+pub struct AdmissionControl {
+    /// The maximum number of pending transactions (0 for no limit).
+    max_transactions: usize,
+    /// The maximum size of the pending transactions (0 for no limit).
+    max_bytes: usize,
+    /// The transactions currently pending.
+    pending: Mutex<Pending>,
+    /// Wakes up the clients waiting for room when transactions are released.
+    released: Notify,
+    /// The rate at which each client address may submit transactions (if limited).
+    client_rate_limit: Option<RateLimit>,
+    /// The token bucket of each client address.
+    clients: Mutex<LruCache<IpAddr, TokenBucket>>,
+}
+
+impl Default for AdmissionControl {
+    fn default() -> Self {
+        Self::new(0, 0, None)
+    }
+}
+
+impl AdmissionControl {
+    pub fn new(
+        max_transactions: usize,
+        max_bytes: usize,
+        client_rate_limit: Option<RateLimit>,
+    ) -> Self {
+        Self {
+            max_transactions,
+            max_bytes,
+            pending: Mutex::new(Pending::default()),
+            released: Notify::new(),
+            client_rate_limit,
+            clients: Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_TRACKED_CLIENTS).unwrap(),
+            )),
+        }
+    }
+
+    /// Admit a transaction of the specified size if there is room for it. A transaction is always admitted
+    /// when nothing is pending, so that transactions larger than `max_bytes` do not wait forever.
+    pub fn try_admit(&self, size: usize) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let full = pending.transactions > 0
+            && ((self.max_transactions > 0 && pending.transactions >= self.max_transactions)
+                || (self.max_bytes > 0 && pending.bytes + size > self.max_bytes));
+        if !full {
+            pending.transactions += 1;
+            pending.bytes += size;
+        }
+        !full
+    }
+
+    /// Admit a transaction of the specified size, waiting for room if needed.
+    pub async fn admit(&self, size: usize) {
+        loop {
+            // Register for wake-ups before checking, so we do not miss a release in between.
+            let released = self.released.notified();
+            if self.try_admit(size) {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    /// Returns whether the specified client is within its quota, and charges it for the transaction.
+    pub fn within_quota(&self, client: IpAddr, size: usize) -> bool {
+        let limit = match self.client_rate_limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let mut clients = self.clients.lock().unwrap();
+        let bucket = clients.get_or_insert_mut(client, || TokenBucket::new(limit));
+        if !bucket.has_tokens() {
+            return false;
+        }
+        bucket.consume(size);
+        true
+    }
+
+    /// Returns the number and size of the pending transactions.
+    pub fn pending(&self) -> (usize, usize) {
+        let pending = self.pending.lock().unwrap();
+        (pending.transactions, pending.bytes)
+    }
+
+    /// Release the specified transactions (sealed into a batch or dropped), making room for new ones.
+    pub fn release(&self, transactions: usize, bytes: usize) {
+        if transactions == 0 {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        pending.transactions = pending.transactions.saturating_sub(transactions);
+        pending.bytes = pending.bytes.saturating_sub(bytes);
+        drop(pending);
+        self.released.notify_waiters();
+    }
+}
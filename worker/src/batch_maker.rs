@@ -1,19 +1,28 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::admission::AdmissionControl;
+use crate::compression::BatchCompressor;
+use crate::erasure::ShardEncoder;
+use crate::hasher::BatchHasher;
+use crate::metrics::WorkerMetrics;
+use crate::padding::BatchPadder;
+use crate::processor::SerializedBatchMessage;
 use crate::quorum_waiter::QuorumWaiterMessage;
-use crate::worker::WorkerMessage;
-use bytes::Bytes;
-#[cfg(feature = "benchmark")]
-use crypto::Digest;
+use config::Parameters;
 use crypto::PublicKey;
-#[cfg(feature = "benchmark")]
-use ed25519_dalek::{Digest as _, Sha512};
+use futures::stream::{FuturesOrdered, StreamExt as _};
+use futures::Future;
+use log::debug;
 #[cfg(feature = "benchmark")]
 use log::info;
-use network::ReliableSender;
+use network::{Address, ReliableSender, SharedTransport};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 #[cfg(feature = "benchmark")]
 use std::convert::TryInto as _;
-use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
 use tokio::time::{sleep, Duration, Instant};
 
 #[cfg(test)]
@@ -23,44 +32,233 @@ pub mod batch_maker_tests;
 pub type Transaction = Vec<u8>;
 pub type Batch = Vec<Transaction>;
 
+/// The maximum number of batches being serialized at once. Beyond it, we stop ingesting transactions.
+const MAX_SEALING_BATCHES: usize = 16;
+
+/// The weight of the latest sample in the (exponentially weighted) average rate of transactions.
+const SMOOTHING_FACTOR: f64 = 0.2;
+
+/// Adapts the size and delay of the batches to the rate of incoming transactions: at low load, batches are
+/// small and sealed quickly (for latency), while at high load they grow up to the maximum size (for
+/// throughput). The load is the fraction of a maximum-size batch we receive within the maximum delay.
+pub struct BatchSizer {
+    /// The smallest target size of the batches (in bytes).
+    min_size: usize,
+    /// The largest target size of the batches (in bytes).
+    max_size: usize,
+    /// The shortest delay after which to seal a batch (in ms).
+    min_delay: u64,
+    /// The longest delay after which to seal a batch (in ms).
+    max_delay: u64,
+    /// The smoothed rate of incoming transactions (in bytes per ms), once we sealed a batch.
+    rate: Option<f64>,
+    /// The time we sealed the last batch.
+    last_seal: Instant,
+}
+
+impl BatchSizer {
+    pub fn new(min_size: usize, max_size: usize, min_delay: u64, max_delay: u64) -> Self {
+        Self {
+            min_size: min_size.min(max_size),
+            max_size,
+            min_delay: min_delay.min(max_delay),
+            max_delay,
+            rate: None,
+            last_seal: Instant::now(),
+        }
+    }
+
+    /// A sizer that does not adapt: batches are sealed when they reach `size` or after `delay`.
+    pub fn fixed(size: usize, delay: u64) -> Self {
+        Self::new(size, size, delay, delay)
+    }
+
+    /// The sizer configured by the parameters (adaptive if `adaptive_batching` is set).
+    pub fn from_parameters(parameters: &Parameters) -> Self {
+        match parameters.adaptive_batching {
+            true => Self::new(
+                parameters.min_batch_size,
+                parameters.batch_size,
+                parameters.min_batch_delay,
+                parameters.max_batch_delay,
+            ),
+            false => Self::fixed(parameters.batch_size, parameters.max_batch_delay),
+        }
+    }
+
+    /// Adopt the sizes and delays of another sizer, keeping the rate of transactions measured so far.
+    fn resize(&mut self, other: Self) {
+        self.min_size = other.min_size;
+        self.max_size = other.max_size;
+        self.min_delay = other.min_delay;
+        self.max_delay = other.max_delay;
+    }
+
+    /// Returns the current load, between 0 and 1.
+    fn load(&self) -> f64 {
+        let rate = self.rate.unwrap_or_default();
+        (rate * self.max_delay as f64 / self.max_size.max(1) as f64).clamp(0.0, 1.0)
+    }
+
+    /// Returns the size at which to seal the current batch (in bytes).
+    pub fn batch_size(&self) -> usize {
+        self.min_size + (self.load() * (self.max_size - self.min_size) as f64) as usize
+    }
+
+    /// Returns the delay after which to seal the current batch (in ms).
+    pub fn batch_delay(&self) -> u64 {
+        self.min_delay + (self.load() * (self.max_delay - self.min_delay) as f64) as u64
+    }
+
+    /// Record that we sealed a batch of the specified size (in bytes).
+    pub fn record(&mut self, size: usize) {
+        let now = Instant::now();
+        self.update(size, now.duration_since(self.last_seal));
+        self.last_seal = now;
+    }
+
+    /// Update the rate of transactions with a batch of `size` bytes gathered over `elapsed`.
+    fn update(&mut self, size: usize, elapsed: Duration) {
+        let sample = size as f64 / (elapsed.as_secs_f64() * 1_000.0).max(1.0);
+        self.rate = Some(match self.rate {
+            Some(rate) => rate + SMOOTHING_FACTOR * (sample - rate),
+            None => sample,
+        });
+    }
+}
+
+/// Extracts the priority of a transaction (eg. from a fee prefix). Higher priorities are batched first.
+pub type TransactionPriority = Arc<dyn Fn(&[u8]) -> u64 + Send + Sync>;
+
+/// The transactions waiting to be batched, ordered by priority (and by arrival among equal priorities).
+/// Once full, the queue evicts its lowest-priority transactions (the latest ones among equal priorities).
+pub struct PriorityQueue {
+    /// Extracts the priority of the transactions.
+    priority: TransactionPriority,
+    /// The maximum number of pending transactions (0 for no limit).
+    capacity: usize,
+    /// The pending transactions, indexed by priority and arrival order.
+    transactions: BTreeMap<(u64, Reverse<u64>), Transaction>,
+    /// The arrival number of the next transaction.
+    next: u64,
+    /// The size of the pending transactions (in bytes).
+    size: usize,
+}
+
+impl PriorityQueue {
+    pub fn new(priority: TransactionPriority, capacity: usize) -> Self {
+        Self {
+            priority,
+            capacity,
+            transactions: BTreeMap::new(),
+            next: 0,
+            size: 0,
+        }
+    }
+
+    /// Add a transaction, evicting the lowest-priority one if the queue is full. It returns the evicted
+    /// transaction (if any).
+    fn push(&mut self, transaction: Transaction) -> Option<Transaction> {
+        let key = ((self.priority)(&transaction), Reverse(self.next));
+        self.next += 1;
+        self.size += transaction.len();
+        self.transactions.insert(key, transaction);
+        if self.capacity == 0 || self.transactions.len() <= self.capacity {
+            return None;
+        }
+        let ((priority, _), evicted) = self.transactions.pop_first()?;
+        debug!("Evicting pending transaction of priority {}", priority);
+        self.size -= evicted.len();
+        Some(evicted)
+    }
+
+    /// Remove the highest-priority transaction.
+    fn pop(&mut self) -> Option<Transaction> {
+        let (_, transaction) = self.transactions.pop_last()?;
+        self.size -= transaction.len();
+        Some(transaction)
+    }
+}
+
+/// How a sealed batch is sent to the other workers.
+pub enum Dissemination {
+    /// The same message (the whole batch) to every worker.
+    Broadcast(SerializedBatchMessage),
+    /// A different message (a few shards of the batch) to each worker.
+    Shards(Vec<(PublicKey, SerializedBatchMessage)>),
+}
+
 /// Assemble clients transactions into batches.
 pub struct BatchMaker {
-    /// The preferred batch size (in bytes).
-    batch_size: usize,
-    /// The maximum delay after which to seal the batch (in ms).
-    max_batch_delay: u64,
+    /// Determines the size at which (and the delay after which) to seal the batch.
+    sizer: BatchSizer,
     /// Channel to receive transactions from the network.
     rx_transaction: Receiver<Transaction>,
+    /// Receives the parameters reloaded while we run (we apply the new sizes and delays of the batches).
+    rx_parameters: watch::Receiver<Parameters>,
     /// Output channel to deliver sealed batches to the `QuorumWaiter`.
     tx_message: Sender<QuorumWaiterMessage>,
     /// The network addresses of the other workers that share our worker id.
-    workers_addresses: Vec<(PublicKey, SocketAddr)>,
+    workers_addresses: Vec<(PublicKey, Address)>,
     /// Holds the current batch.
     current_batch: Batch,
     /// Holds the size of the current batch (in bytes).
     current_batch_size: usize,
+    /// Holds the pending transactions, if they are batched by priority rather than in arrival order.
+    queue: Option<PriorityQueue>,
     /// A network sender to broadcast the batches to the other workers.
     network: ReliableSender,
+    /// Compresses the batches we broadcast to the other workers (if enabled).
+    compressor: Option<BatchCompressor>,
+    /// Pads the batches we broadcast to the other workers to fixed-size buckets (if enabled).
+    padder: Option<BatchPadder>,
+    /// Serializes (and compresses) the batches off the event loop.
+    hasher: BatchHasher,
+    /// Erasure-codes the large batches we send to the other workers (if enabled).
+    encoder: Option<ShardEncoder>,
+    /// Releases the transactions we seal (or evict), making room for new ones.
+    admission: Arc<AdmissionControl>,
+    /// The metrics of the worker.
+    metrics: Arc<WorkerMetrics>,
 }
 
 impl BatchMaker {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        batch_size: usize,
-        max_batch_delay: u64,
+        sizer: BatchSizer,
+        queue: Option<PriorityQueue>,
         rx_transaction: Receiver<Transaction>,
+        rx_parameters: watch::Receiver<Parameters>,
         tx_message: Sender<QuorumWaiterMessage>,
-        workers_addresses: Vec<(PublicKey, SocketAddr)>,
+        workers_addresses: Vec<(PublicKey, Address)>,
+        transport: SharedTransport,
+        chunk_size: usize,
+        compressor: Option<BatchCompressor>,
+        padder: Option<BatchPadder>,
+        hasher: BatchHasher,
+        encoder: Option<ShardEncoder>,
+        admission: Arc<AdmissionControl>,
+        metrics: Arc<WorkerMetrics>,
     ) {
         tokio::spawn(async move {
             Self {
-                batch_size,
-                max_batch_delay,
+                current_batch: Batch::with_capacity(sizer.max_size * 2),
+                sizer,
                 rx_transaction,
+                rx_parameters,
                 tx_message,
                 workers_addresses,
-                current_batch: Batch::with_capacity(batch_size * 2),
                 current_batch_size: 0,
-                network: ReliableSender::new(),
+                queue,
+                network: ReliableSender::new()
+                    .with_transport(transport)
+                    .with_chunk_size(chunk_size),
+                compressor,
+                padder,
+                hasher,
+                encoder,
+                admission,
+                metrics,
             }
             .run()
             .await;
@@ -69,28 +267,39 @@ impl BatchMaker {
 
     /// Main loop receiving incoming transactions and creating batches.
     async fn run(&mut self) {
-        let timer = sleep(Duration::from_millis(self.max_batch_delay));
+        let timer = sleep(Duration::from_millis(self.sizer.batch_delay()));
         tokio::pin!(timer);
 
+        // The batches being serialized by the hasher, in the order we sealed them.
+        let mut sealing = FuturesOrdered::new();
+
         loop {
             tokio::select! {
-                // Assemble client transactions into batches of preset size.
-                Some(transaction) = self.rx_transaction.recv() => {
-                    self.current_batch_size += transaction.len();
-                    self.current_batch.push(transaction);
-                    if self.current_batch_size >= self.batch_size {
-                        self.seal().await;
-                        timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                // Assemble client transactions into batches of the current target size.
+                Some(transaction) = self.rx_transaction.recv(), if sealing.len() < MAX_SEALING_BATCHES => {
+                    self.push(transaction);
+                    if self.pending_size() >= self.sizer.batch_size() {
+                        sealing.push_back(self.seal());
+                        timer.as_mut().reset(Instant::now() + Duration::from_millis(self.sizer.batch_delay()));
                     }
                 },
 
                 // If the timer triggers, seal the batch even if it contains few transactions.
                 () = &mut timer => {
-                    if !self.current_batch.is_empty() {
-                        self.seal().await;
+                    if !self.is_empty() {
+                        sealing.push_back(self.seal());
                     }
-                    timer.as_mut().reset(Instant::now() + Duration::from_millis(self.max_batch_delay));
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(self.sizer.batch_delay()));
                 }
+
+                Ok(()) = self.rx_parameters.changed() => {
+                    let sizer = BatchSizer::from_parameters(&self.rx_parameters.borrow());
+                    self.sizer.resize(sizer);
+                    timer.as_mut().reset(Instant::now() + Duration::from_millis(self.sizer.batch_delay()));
+                }
+
+                // Broadcast the batches once serialized.
+                Some((batch, dissemination)) = sealing.next() => self.broadcast(batch, dissemination).await,
             }
 
             // Give the change to schedule other tasks.
@@ -98,8 +307,71 @@ impl BatchMaker {
         }
     }
 
-    /// Seal and broadcast the current batch.
-    async fn seal(&mut self) {
+    /// Add a transaction to the current batch (or to the priority queue).
+    fn push(&mut self, transaction: Transaction) {
+        self.metrics.transactions.fetch_add(1, Ordering::Relaxed);
+        match self.queue.as_mut() {
+            Some(queue) => {
+                if let Some(evicted) = queue.push(transaction) {
+                    self.admission.release(1, evicted.len());
+                }
+            }
+            None => {
+                self.current_batch_size += transaction.len();
+                self.current_batch.push(transaction);
+            }
+        }
+    }
+
+    /// Returns the size of the transactions waiting to be batched (in bytes).
+    fn pending_size(&self) -> usize {
+        match self.queue.as_ref() {
+            Some(queue) => queue.size,
+            None => self.current_batch_size,
+        }
+    }
+
+    /// Returns whether no transaction is waiting to be batched.
+    fn is_empty(&self) -> bool {
+        match self.queue.as_ref() {
+            Some(queue) => queue.transactions.is_empty(),
+            None => self.current_batch.is_empty(),
+        }
+    }
+
+    /// Fill the current batch with the highest-priority transactions (if we batch by priority), considering
+    /// all the transactions already waiting in the channel.
+    fn fill(&mut self) {
+        let queue = match self.queue.as_mut() {
+            Some(queue) => queue,
+            None => return,
+        };
+        while let Ok(transaction) = self.rx_transaction.try_recv() {
+            self.metrics.transactions.fetch_add(1, Ordering::Relaxed);
+            if let Some(evicted) = queue.push(transaction) {
+                self.admission.release(1, evicted.len());
+            }
+        }
+        let target = self.sizer.batch_size();
+        while self.current_batch_size < target {
+            match queue.pop() {
+                Some(transaction) => {
+                    self.current_batch_size += transaction.len();
+                    self.current_batch.push(transaction);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Seal the current batch. It returns a future serializing (and compressing and padding, or erasure-coding)
+    /// the batch on the threads of the hasher, and outputting the serialized batch along with the messages to
+    /// send.
+    fn seal(
+        &mut self,
+    ) -> impl Future<Output = (SerializedBatchMessage, Dissemination)> + Send + 'static {
+        self.fill();
+
         #[cfg(feature = "benchmark")]
         let size = self.current_batch_size;
 
@@ -112,45 +384,112 @@ impl BatchMaker {
             .filter_map(|tx| tx[1..9].try_into().ok())
             .collect();
 
-        // Serialize the batch.
+        self.sizer.record(self.current_batch_size);
+        self.metrics.batches.fetch_add(1, Ordering::Relaxed);
+        self.admission
+            .release(self.current_batch.len(), self.current_batch_size);
         self.current_batch_size = 0;
         let batch: Vec<_> = self.current_batch.drain(..).collect();
-        let message = WorkerMessage::Batch(batch);
-        let serialized = bincode::serialize(&message).expect("Failed to serialize our own batch");
+        let hasher = self.hasher.clone();
+        let compressor = self.compressor.clone();
+        let padder = self.padder.clone();
+        let encoder = self.encoder.clone();
+        let names: Vec<_> = self.workers_addresses.iter().map(|(x, _)| *x).collect();
+
+        async move {
+            // Serialize the batch.
+            #[allow(unused_variables)]
+            let (digest, serialized) = hasher.serialize(batch).await;
+
+            #[cfg(feature = "benchmark")]
+            {
+                for id in tx_ids {
+                    // NOTE: This log entry is used to compute performance.
+                    info!(
+                        "Batch {:?} contains sample tx {}",
+                        digest,
+                        u64::from_be_bytes(id)
+                    );
+                }
 
-        #[cfg(feature = "benchmark")]
-        {
-            // NOTE: This is one extra hash that is only needed to print the following log entries.
-            let digest = Digest(
-                Sha512::digest(&serialized).as_slice()[..32]
-                    .try_into()
-                    .unwrap(),
-            );
-
-            for id in tx_ids {
                 // NOTE: This log entry is used to compute performance.
-                info!(
-                    "Batch {:?} contains sample tx {}",
-                    digest,
-                    u64::from_be_bytes(id)
-                );
+                info!("Batch {:?} contains {} B", digest, size);
+            }
+
+            // Erasure-code the large batches (if enabled).
+            if let Some(encoder) = encoder.filter(|x| x.applies(&serialized)) {
+                let batch = serialized.clone();
+                let messages = hasher.run(move || encoder.messages(&batch, &names)).await;
+                return (serialized, Dissemination::Shards(messages));
             }
 
-            // NOTE: This log entry is used to compute performance.
-            info!("Batch {:?} contains {} B", digest, size);
+            // Compress the batch we broadcast to the other workers (if enabled).
+            let broadcast = match compressor {
+                Some(compressor) => {
+                    let batch = serialized.clone();
+                    let (compressed, ratio) = hasher
+                        .run(move || {
+                            let compressed = compressor.compress(&batch);
+                            (compressed, compressor.stats().ratio())
+                        })
+                        .await;
+                    debug!(
+                        "Compressed batch from {} B to {} B (overall ratio {:.2})",
+                        serialized.len(),
+                        compressed.len(),
+                        ratio.unwrap_or(1.0)
+                    );
+                    compressed
+                }
+                None => serialized.clone(),
+            };
+
+            // Pad the batch to its bucket (if enabled).
+            let broadcast = match padder {
+                Some(padder) => {
+                    let (padded, overhead) = hasher
+                        .run(move || {
+                            let padded = padder.pad(&broadcast);
+                            (padded, padder.stats().overhead())
+                        })
+                        .await;
+                    debug!(
+                        "Padded batch to {} B (overall overhead {:.2})",
+                        padded.len(),
+                        overhead.unwrap_or(1.0)
+                    );
+                    padded
+                }
+                None => broadcast,
+            };
+            (serialized, Dissemination::Broadcast(broadcast))
         }
+    }
 
-        // Broadcast the batch through the network.
-        let (names, addresses): (Vec<_>, _) = self.workers_addresses.iter().cloned().unzip();
-        let bytes = Bytes::from(serialized.clone());
-        let handlers = self.network.broadcast(addresses, bytes).await;
+    /// Send a sealed batch to the other workers, and deliver it to the `QuorumWaiter`.
+    async fn broadcast(&mut self, batch: SerializedBatchMessage, dissemination: Dissemination) {
+        let handlers = match dissemination {
+            Dissemination::Broadcast(message) => {
+                let (names, addresses): (Vec<_>, _) =
+                    self.workers_addresses.iter().cloned().unzip();
+                let handlers = self.network.broadcast(addresses, message).await;
+                names.into_iter().zip(handlers.into_iter()).collect()
+            }
+            Dissemination::Shards(messages) => {
+                let mut handlers = Vec::new();
+                for ((name, address), (_, message)) in
+                    self.workers_addresses.iter().zip(messages.into_iter())
+                {
+                    let handler = self.network.send(address.clone(), message).await;
+                    handlers.push((*name, handler));
+                }
+                handlers
+            }
+        };
 
         // Send the batch through the deliver channel for further processing.
         self.tx_message
-            .send(QuorumWaiterMessage {
-                batch: serialized,
-                handlers: names.into_iter().zip(handlers.into_iter()).collect(),
-            })
+            .send(QuorumWaiterMessage { batch, handlers })
             .await
             .expect("Failed to deliver batch");
     }
@@ -0,0 +1,155 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::pending_batches::PendingBatches;
+use crate::worker::Round;
+use crypto::Digest;
+use log::{debug, warn};
+use std::convert::TryInto as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::{Store, StoreError};
+use tokio::sync::mpsc::Receiver;
+
+#[cfg(test)]
+#[path = "tests/batch_pruner_tests.rs"]
+pub mod batch_pruner_tests;
+
+/// The prefix of the keys indexing the stored batches by round (one key per batch).
+const KEY_PREFIX: &[u8] = b"batch_round:";
+
+/// The end (excluded) of the range of keys of the index.
+const KEY_PREFIX_END: &[u8] = b"batch_round;";
+
+/// Indexes the stored batches by the cleanup round of our primary at the time we stored them, along with
+/// their size.
+#[derive(Clone)]
+pub struct BatchIndex {
+    /// The persistent storage.
+    store: Store,
+    /// The last cleanup round of our primary.
+    round: Arc<AtomicU64>,
+}
+
+impl BatchIndex {
+    pub fn new(store: Store) -> Self {
+        Self {
+            store,
+            round: Arc::default(),
+        }
+    }
+
+    fn key(round: Round, digest: &Digest) -> Vec<u8> {
+        [KEY_PREFIX, &round.to_be_bytes(), digest.as_ref()].concat()
+    }
+
+    /// Index a batch we just stored.
+    pub async fn insert(&mut self, digest: &Digest, size: usize) {
+        let key = Self::key(self.round.load(Ordering::Relaxed), digest);
+        self.store
+            .write(key, (size as u64).to_be_bytes().to_vec())
+            .await;
+    }
+
+    /// Returns the batches indexed below the specified round, along with their round and size (sorted by
+    /// round).
+    async fn read_below(&mut self, round: Round) -> Result<Vec<(Round, Digest, u64)>, StoreError> {
+        let end = [KEY_PREFIX, &round.to_be_bytes()].concat();
+        Ok(self
+            .store
+            .read_range(KEY_PREFIX.to_vec(), end)
+            .await?
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let key = &key[KEY_PREFIX.len()..];
+                let round = Round::from_be_bytes(key.get(..8)?.try_into().ok()?);
+                let digest = Digest(key.get(8..)?.try_into().ok()?);
+                let size = u64::from_be_bytes(value.as_slice().try_into().ok()?);
+                Some((round, digest, size))
+            })
+            .collect())
+    }
+
+    /// Resume indexing at the highest indexed round (if we restarted).
+    async fn recover(&mut self) -> Result<(), StoreError> {
+        let end = KEY_PREFIX_END.to_vec();
+        let last = self.store.read_range(KEY_PREFIX.to_vec(), end).await?;
+        let round = last
+            .last()
+            .and_then(|(key, _)| key.get(KEY_PREFIX.len()..KEY_PREFIX.len() + 8))
+            .and_then(|x| x.try_into().ok())
+            .map_or(0, Round::from_be_bytes);
+        self.round.fetch_max(round, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Deletes the batches that fell `depth` rounds behind the cleanup round of our primary, except the most
+/// recent ones up to `retention_bytes` (so that we can still serve late sync requests of slow workers) and
+/// our own batches that are not yet sequenced.
+pub struct BatchPruner {
+    /// The persistent storage.
+    store: Store,
+    /// The index of the stored batches.
+    index: BatchIndex,
+    /// Our own batches that are not yet sequenced.
+    pending: PendingBatches,
+    /// The number of rounds batches are kept below the cleanup round.
+    depth: Round,
+    /// The size of the most recent batches kept beyond `depth`. Denominated in bytes.
+    retention_bytes: usize,
+    /// Receives the cleanup rounds of our primary.
+    rx_cleanup: Receiver<Round>,
+}
+
+impl BatchPruner {
+    pub fn spawn(
+        store: Store,
+        index: BatchIndex,
+        depth: Round,
+        retention_bytes: usize,
+        rx_cleanup: Receiver<Round>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                pending: PendingBatches::new(store.clone()),
+                store,
+                index,
+                depth,
+                retention_bytes,
+                rx_cleanup,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Delete the batches indexed below the specified round (except the ones we retain).
+    async fn prune(&mut self, round: Round) -> Result<(), StoreError> {
+        let mut retained = 0;
+        for (r, digest, size) in self.index.read_below(round).await?.into_iter().rev() {
+            retained += size;
+            if retained <= self.retention_bytes as u64 || self.pending.contains(&digest).await? {
+                continue;
+            }
+            debug!("Pruning batch {} of round {}", digest, r);
+            self.store.delete(digest.to_vec()).await;
+            self.store.delete(BatchIndex::key(r, &digest)).await;
+        }
+        Ok(())
+    }
+
+    async fn run(&mut self) {
+        if let Err(e) = self.index.recover().await {
+            warn!("Failed to read the batch index: {}", e);
+        }
+
+        while let Some(round) = self.rx_cleanup.recv().await {
+            self.index.round.fetch_max(round, Ordering::Relaxed);
+            if round <= self.depth {
+                continue;
+            }
+            if let Err(e) = self.prune(round - self.depth).await {
+                warn!("Failed to prune batches: {}", e);
+            }
+        }
+    }
+}
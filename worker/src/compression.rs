@@ -0,0 +1,86 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::processor::SerializedBatchMessage;
+use crate::worker::WorkerMessage;
+use network::MAX_CHUNKED_MESSAGE_LENGTH;
+use std::io::{self, Read as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/compression_tests.rs"]
+pub mod compression_tests;
+
+/// The total size of the batches we compressed, before and after compression.
+#[derive(Debug, Default)]
+pub struct CompressionStats {
+    /// The size of the serialized batches. Denominated in bytes.
+    pub uncompressed_bytes: AtomicU64,
+    /// The size of the compressed batches. Denominated in bytes.
+    pub compressed_bytes: AtomicU64,
+}
+
+impl CompressionStats {
+    /// Returns the overall compression ratio (uncompressed over compressed size), if we compressed anything.
+    pub fn ratio(&self) -> Option<f64> {
+        let compressed = self.compressed_bytes.load(Ordering::Relaxed);
+        let uncompressed = self.uncompressed_bytes.load(Ordering::Relaxed);
+        (compressed > 0).then(|| uncompressed as f64 / compressed as f64)
+    }
+}
+
+/// Compresses (zstd) the batches we broadcast to the other workers, since batches dominate our network
+/// usage. The receivers decompress them before hashing and storing them, so the digest of a batch does not
+/// depend on its compression.
+#[derive(Clone)]
+pub struct BatchCompressor {
+    /// The zstd compression level.
+    level: i32,
+    /// The compression statistics.
+    stats: Arc<CompressionStats>,
+}
+
+impl BatchCompressor {
+    pub fn new(level: i32) -> Self {
+        Self {
+            level,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Returns the compression statistics.
+    pub fn stats(&self) -> &CompressionStats {
+        &self.stats
+    }
+
+    /// Wrap a serialized batch into a serialized `WorkerMessage::CompressedBatch`.
+    pub fn compress(&self, batch: &[u8]) -> SerializedBatchMessage {
+        let compressed =
+            zstd::bulk::compress(batch, self.level).expect("Failed to compress our own batch");
+        self.stats
+            .uncompressed_bytes
+            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+        self.stats
+            .compressed_bytes
+            .fetch_add(compressed.len() as u64, Ordering::Relaxed);
+        let message = WorkerMessage::CompressedBatch(compressed);
+        bincode::serialize(&message)
+            .expect("Failed to serialize our own batch")
+            .into()
+    }
+}
+
+/// Decompress the content of a `WorkerMessage::CompressedBatch`, refusing to inflate it beyond the size of
+/// the largest message we would accept uncompressed.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    zstd::stream::read::Decoder::new(data)?
+        .take(MAX_CHUNKED_MESSAGE_LENGTH as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > MAX_CHUNKED_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Decompressed batch too large",
+        ));
+    }
+    Ok(decompressed)
+}
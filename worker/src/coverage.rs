@@ -0,0 +1,121 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::admission::AdmissionControl;
+use crate::batch_maker::Transaction;
+use crypto::Digest;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use log::debug;
+use lru::LruCache;
+use std::convert::TryInto;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/coverage_tests.rs"]
+pub mod coverage_tests;
+
+/// The number of batches whose transactions we remember until they are committed (and of committed batches
+/// we remember until we receive them).
+const MAX_PENDING_BATCHES: usize = 1_000;
+
+/// Returns the digest of a transaction.
+fn transaction_digest(transaction: &[u8]) -> Digest {
+    Digest(Sha512::digest(transaction)[..32].try_into().unwrap())
+}
+
+struct Inner {
+    /// The transactions (digests) of the batches we received or made, by batch digest.
+    batches: LruCache<Digest, Vec<Digest>>,
+    /// The batches committed before we received them.
+    committed: LruCache<Digest, ()>,
+    /// The transactions (digests) of the committed batches.
+    covered: LruCache<Digest, ()>,
+}
+
+/// Tracks the transactions already covered by a committed batch. Clients often submit the same transactions
+/// to several authorities: once a batch holding them is committed, there is no point in disseminating them
+/// again in one of our batches. Only our primary tells us which batches are committed (see
+/// `PrimaryWorkerMessage::Committed`), so other workers cannot get transactions dropped by claiming batches
+/// they never disseminated.
+#[derive(Clone)]
+pub struct Coverage {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Coverage {
+    /// Create a coverage tracker remembering up to `capacity` covered transactions.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        let pending = NonZeroUsize::new(MAX_PENDING_BATCHES).unwrap();
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                batches: LruCache::new(pending),
+                committed: LruCache::new(pending),
+                covered: LruCache::new(capacity),
+            })),
+        }
+    }
+
+    /// Record the transactions of a batch.
+    pub fn record(&self, batch: Digest, transactions: &[Transaction]) {
+        let digests: Vec<_> = transactions.iter().map(|x| transaction_digest(x)).collect();
+        let mut inner = self.inner.lock().unwrap();
+        match inner.committed.pop(&batch) {
+            Some(()) => {
+                for digest in digests {
+                    inner.covered.put(digest, ());
+                }
+            }
+            None => {
+                inner.batches.put(batch, digests);
+            }
+        }
+    }
+
+    /// Mark the transactions of a batch as covered (our primary reports it committed).
+    pub fn mark_committed(&self, batch: Digest) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.batches.pop(&batch) {
+            Some(digests) => {
+                for digest in digests {
+                    inner.covered.put(digest, ());
+                }
+            }
+            None => {
+                inner.committed.put(batch, ());
+            }
+        }
+    }
+
+    /// Returns whether a transaction is covered by a committed batch.
+    pub fn is_covered(&self, transaction: &[u8]) -> bool {
+        let digest = transaction_digest(transaction);
+        self.inner.lock().unwrap().covered.contains(&digest)
+    }
+}
+
+/// Drops the clients' transactions already covered by a committed batch.
+pub struct CoverageFilter;
+
+impl CoverageFilter {
+    pub fn spawn(
+        coverage: Coverage,
+        admission: Arc<AdmissionControl>,
+        mut rx_transaction: Receiver<Transaction>,
+        tx_batch_maker: Sender<Transaction>,
+    ) {
+        tokio::spawn(async move {
+            while let Some(transaction) = rx_transaction.recv().await {
+                if coverage.is_covered(&transaction) {
+                    debug!("Dropping transaction covered by a committed batch");
+                    admission.release(1, transaction.len());
+                    continue;
+                }
+                tx_batch_maker
+                    .send(transaction)
+                    .await
+                    .expect("Failed to deliver transaction");
+            }
+        });
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Transaction;
+use crate::transaction_validator::TransactionValidator;
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{Committee, WorkerId};
+use crypto::{
+    Ciphertext, DecryptionShare, Digest, PublicKey, ThresholdKeyShare, ThresholdPublicKey,
+};
+use log::{debug, error, info, warn};
+use lru::LruCache;
+use network::{Address, ReliableSender, SharedTransport};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use store::Store;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration};
+
+#[cfg(test)]
+#[path = "tests/decryption_tests.rs"]
+pub mod decryption_tests;
+
+/// The number of committed batches we decrypt at once (the least recently updated ones are dropped).
+const MAX_PENDING_BATCHES: usize = 1_000;
+
+/// The reply of a worker to decryption shares it cannot use yet (it did not read the committed batch): the
+/// sender should send them again later.
+pub const RETRY_REPLY: &[u8] = b"Retry";
+
+/// The delay (in ms) before sending again decryption shares a worker could not use yet (doubling each time).
+const RETRY_DELAY: u64 = 500;
+
+/// The maximum delay (in ms) between two attempts to deliver decryption shares.
+const MAX_RETRY_DELAY: u64 = 30_000;
+
+/// The number of times we send decryption shares to a worker that cannot use them yet before giving up.
+const MAX_RETRIES: u32 = 10;
+
+/// Returns the store key of the decrypted transactions of a batch.
+pub fn decrypted_batch_key(digest: &Digest) -> Vec<u8> {
+    let mut key = b"decrypted:".to_vec();
+    key.extend_from_slice(&digest.0);
+    key
+}
+
+/// The decryption shares of an authority for the transactions of a committed batch (in order).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchDecryptionShares {
+    /// The authority holding the key share (its share index must match its position in the committee).
+    pub author: PublicKey,
+    /// The digest of the batch.
+    pub batch: Digest,
+    /// The decryption share of each transaction of the batch.
+    pub shares: Vec<DecryptionShare>,
+}
+
+/// Only accepts transactions encrypted to the committee (before the rules of the application, which only
+/// see ciphertexts).
+pub struct Ciphertexts {
+    /// The rules of the application.
+    inner: Arc<dyn TransactionValidator>,
+}
+
+impl Ciphertexts {
+    pub fn new(inner: Arc<dyn TransactionValidator>) -> Self {
+        Self { inner }
+    }
+}
+
+impl TransactionValidator for Ciphertexts {
+    fn validate(&self, transaction: &[u8]) -> Result<(), String> {
+        bincode::deserialize::<Ciphertext>(transaction)
+            .map_err(|e| format!("Transaction is not a ciphertext: {}", e))?;
+        self.inner.validate(transaction)
+    }
+}
+
+/// Returns the ciphertexts of a serialized batch.
+fn ciphertexts(serialized: &[u8]) -> Option<Vec<Ciphertext>> {
+    match bincode::deserialize(serialized) {
+        Ok(WorkerMessage::Batch(transactions)) => transactions
+            .iter()
+            .map(|x| bincode::deserialize(x).ok())
+            .collect(),
+        _ => None,
+    }
+}
+
+/// The decryption of a committed batch.
+#[derive(Default)]
+struct Decryption {
+    /// The ciphertexts of the batch (once we read it from the store).
+    ciphertexts: Option<Vec<Ciphertext>>,
+    /// The verified decryption shares, by author.
+    verified: HashMap<PublicKey, Vec<DecryptionShare>>,
+    /// Whether the batch is decrypted.
+    done: bool,
+}
+
+/// Decrypts the transactions of the committed batches (encrypted by the clients to the committee). The
+/// decryption shares of a batch are only released once our primary reports it as committed, so that its
+/// transactions remain hidden (to front-runners and censors) until their order is fixed. Once `threshold`
+/// (f+1, see `Committee::validate`) valid shares are gathered, the decrypted batch is stored for the
+/// execution layer (see `BatchesService`).
+/// We only hold shares for the batches our primary committed, once we read them: the shares received
+/// before are answered with `RETRY_REPLY`, and their sender keeps sending them until we use them.
+pub struct Decryptor {
+    /// Our public key.
+    name: PublicKey,
+    /// The id of this worker.
+    id: WorkerId,
+    /// The committee information.
+    committee: Committee,
+    /// The threshold encryption key of the committee.
+    key: ThresholdPublicKey,
+    /// Our share of the decryption key.
+    share: Arc<ThresholdKeyShare>,
+    /// The persistent storage.
+    store: Store,
+    /// Input channel to receive the digests of the committed batches (from our primary).
+    rx_committed: Receiver<Vec<Digest>>,
+    /// Input channel to receive the decryption shares of the other authorities, along with a channel to
+    /// reply whether we used them (or the sender should retry).
+    rx_shares: Receiver<(BatchDecryptionShares, oneshot::Sender<bool>)>,
+    /// Channel to gather the committed batches we read (along with our decryption shares).
+    tx_loaded: Sender<(Vec<Ciphertext>, BatchDecryptionShares)>,
+    /// Receives the committed batches we read.
+    rx_loaded: Receiver<(Vec<Ciphertext>, BatchDecryptionShares)>,
+    /// Channel to send again the decryption shares a worker could not use yet (along with the attempt).
+    tx_retry: Sender<(Address, Bytes, u32)>,
+    /// Receives the decryption shares to send again.
+    rx_retry: Receiver<(Address, Bytes, u32)>,
+    /// The batches being decrypted.
+    decryptions: LruCache<Digest, Decryption>,
+    /// A network sender to send our decryption shares to the other workers.
+    network: ReliableSender,
+}
+
+impl Decryptor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        key: ThresholdPublicKey,
+        share: ThresholdKeyShare,
+        store: Store,
+        transport: SharedTransport,
+        chunk_size: usize,
+        rx_committed: Receiver<Vec<Digest>>,
+        rx_shares: Receiver<(BatchDecryptionShares, oneshot::Sender<bool>)>,
+    ) {
+        tokio::spawn(async move {
+            let (tx_loaded, rx_loaded) = channel(MAX_PENDING_BATCHES);
+            let (tx_retry, rx_retry) = channel(MAX_PENDING_BATCHES);
+            Self {
+                name,
+                id,
+                committee,
+                key,
+                share: Arc::new(share),
+                store,
+                rx_committed,
+                rx_shares,
+                tx_loaded,
+                rx_loaded,
+                tx_retry,
+                rx_retry,
+                decryptions: LruCache::new(NonZeroUsize::new(MAX_PENDING_BATCHES).unwrap()),
+                network: ReliableSender::new()
+                    .with_transport(transport)
+                    .with_chunk_size(chunk_size),
+            }
+            .run()
+            .await;
+        });
+    }
+
+    /// Read a committed batch (waiting for it if we do not have it yet) and compute our decryption shares.
+    fn load(&mut self, batch: Digest) {
+        let author = self.name;
+        let mut store = self.store.clone();
+        let share = self.share.clone();
+        let tx_loaded = self.tx_loaded.clone();
+        tokio::spawn(async move {
+            let serialized = match store.notify_read(batch.to_vec()).await {
+                Ok(serialized) => serialized,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            let result = tokio::task::spawn_blocking(move || {
+                let ciphertexts = ciphertexts(&serialized)?;
+                let shares = ciphertexts
+                    .iter()
+                    .map(|x| share.decryption_share(x, &mut OsRng).ok())
+                    .collect::<Option<Vec<_>>>()?;
+                Some((
+                    ciphertexts,
+                    BatchDecryptionShares {
+                        author,
+                        batch,
+                        shares,
+                    },
+                ))
+            })
+            .await;
+            match result {
+                Ok(Some(loaded)) => {
+                    let _ = tx_loaded.send(loaded).await;
+                }
+                _ => warn!("Committed batch does not hold valid ciphertexts"),
+            }
+        });
+    }
+
+    /// Verify the decryption shares of an authority against the ciphertexts of their batch.
+    fn verify(
+        &self,
+        ciphertexts: &[Ciphertext],
+        author: &PublicKey,
+        shares: &[DecryptionShare],
+    ) -> bool {
+        let index = match self.committee.share_index(author) {
+            Some(index) => index,
+            None => return false,
+        };
+        shares.len() == ciphertexts.len()
+            && ciphertexts
+                .iter()
+                .zip(shares.iter())
+                .all(|(ciphertext, share)| {
+                    share.index == index && self.key.verify_share(ciphertext, share).is_ok()
+                })
+    }
+
+    /// Add the decryption shares of an authority. Returns whether the sender is done with them: it should
+    /// send them again if we did not read the batch yet (or were not notified that it is committed).
+    fn append(&mut self, shares: BatchDecryptionShares) -> bool {
+        let ciphertexts = match self.decryptions.get(&shares.batch) {
+            Some(decryption) if decryption.done => return true,
+            Some(decryption) => match &decryption.ciphertexts {
+                Some(ciphertexts) => ciphertexts.clone(),
+                None => return false,
+            },
+            None => return false,
+        };
+        if !self.verify(&ciphertexts, &shares.author, &shares.shares) {
+            warn!("Invalid decryption shares for batch {}", shares.batch);
+            return true;
+        }
+        if let Some(decryption) = self.decryptions.get_mut(&shares.batch) {
+            decryption.verified.insert(shares.author, shares.shares);
+        }
+        true
+    }
+
+    /// Record the ciphertexts of a committed batch we read, along with our decryption shares.
+    fn loaded(&mut self, ciphertexts: Vec<Ciphertext>, ours: BatchDecryptionShares) {
+        if let Some(decryption) = self.decryptions.get_mut(&ours.batch) {
+            decryption.ciphertexts = Some(ciphertexts);
+            decryption.verified.insert(ours.author, ours.shares);
+        }
+    }
+
+    /// Send our decryption shares to a worker (again, if it could not use them yet).
+    async fn send(&mut self, address: Address, bytes: Bytes, attempt: u32) {
+        let handler = self.network.send(address.clone(), bytes.clone()).await;
+        let tx_retry = self.tx_retry.clone();
+        tokio::spawn(async move {
+            match handler.await {
+                Ok(reply) if reply == RETRY_REPLY && attempt < MAX_RETRIES => {
+                    let delay = min(RETRY_DELAY << attempt, MAX_RETRY_DELAY);
+                    sleep(Duration::from_millis(delay)).await;
+                    let _ = tx_retry.send((address, bytes, attempt + 1)).await;
+                }
+                Ok(reply) if reply == RETRY_REPLY => {
+                    debug!("Giving up sending decryption shares to {}", address)
+                }
+                _ => (),
+            }
+        });
+    }
+
+    /// Decrypt and store the batch once we gathered enough decryption shares.
+    async fn try_decrypt(&mut self, batch: &Digest) {
+        let decryption = match self.decryptions.get_mut(batch) {
+            Some(decryption) if !decryption.done => decryption,
+            _ => return,
+        };
+        let ciphertexts = match &decryption.ciphertexts {
+            Some(ciphertexts) if decryption.verified.len() >= self.key.threshold => ciphertexts,
+            _ => return,
+        };
+
+        let mut transactions: Vec<Transaction> = Vec::with_capacity(ciphertexts.len());
+        for (i, ciphertext) in ciphertexts.iter().enumerate() {
+            let shares: Vec<_> = decryption.verified.values().map(|x| x[i].clone()).collect();
+            match self.key.decrypt(ciphertext, &shares) {
+                Ok(plaintext) => transactions.push(plaintext),
+                Err(e) => {
+                    warn!("Failed to decrypt a transaction of batch {}: {}", batch, e);
+                    transactions.push(Vec::new());
+                }
+            }
+        }
+        decryption.done = true;
+        decryption.verified.clear();
+
+        let serialized = bincode::serialize(&WorkerMessage::Batch(transactions))
+            .expect("Failed to serialize decrypted batch");
+        self.store
+            .write(decrypted_batch_key(batch), serialized)
+            .await;
+        info!("Decrypted batch {}", batch);
+    }
+
+    async fn run(&mut self) {
+        loop {
+            tokio::select! {
+                // Release our decryption shares of the committed batches.
+                Some(digests) = self.rx_committed.recv() => {
+                    for digest in digests {
+                        if self.decryptions.contains(&digest) {
+                            continue;
+                        }
+                        self.decryptions.put(digest.clone(), Decryption::default());
+                        debug!("Releasing decryption shares of batch {}", digest);
+                        self.load(digest);
+                    }
+                },
+
+                // Send our shares to the other workers, and use them ourselves.
+                Some((ciphertexts, ours)) = self.rx_loaded.recv() => {
+                    let message = WorkerMessage::DecryptionShares(ours.clone());
+                    let bytes = Bytes::from(
+                        bincode::serialize(&message).expect("Failed to serialize decryption shares")
+                    );
+                    for (_, addresses) in self.committee.others_workers(&self.name, &self.id) {
+                        self.send(addresses.worker_to_worker.advertise, bytes.clone(), 0).await;
+                    }
+
+                    let batch = ours.batch.clone();
+                    self.loaded(ciphertexts, ours);
+                    self.try_decrypt(&batch).await;
+                },
+
+                // Send again the shares a worker could not use yet.
+                Some((address, bytes, attempt)) = self.rx_retry.recv() => {
+                    self.send(address, bytes, attempt).await;
+                },
+
+                // Gather the shares of the other authorities (and tell them whether to send them again).
+                Some((shares, reply)) = self.rx_shares.recv() => {
+                    let batch = shares.batch.clone();
+                    let _ = reply.send(self.append(shares));
+                    self.try_decrypt(&batch).await;
+                },
+
+                else => break,
+            }
+        }
+    }
+}
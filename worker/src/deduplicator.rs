@@ -0,0 +1,64 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::admission::AdmissionControl;
+use crate::batch_maker::Transaction;
+use crypto::Digest;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use log::debug;
+use lru::LruCache;
+use std::convert::TryInto;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/deduplicator_tests.rs"]
+pub mod deduplicator_tests;
+
+/// Drops the clients' transactions we recently received, so that clients retrying their submissions do not
+/// get the same transaction in several batches. It remembers the hashes of the last `capacity` transactions.
+pub struct Deduplicator {
+    /// The hashes of the transactions we recently received.
+    seen: LruCache<Digest, ()>,
+    /// Releases the duplicates we drop, making room for new transactions.
+    admission: Arc<AdmissionControl>,
+    /// Input channel to receive the clients' transactions.
+    rx_transaction: Receiver<Transaction>,
+    /// Output channel to deliver the new transactions to the `BatchMaker`.
+    tx_batch_maker: Sender<Transaction>,
+}
+
+impl Deduplicator {
+    pub fn spawn(
+        capacity: NonZeroUsize,
+        admission: Arc<AdmissionControl>,
+        rx_transaction: Receiver<Transaction>,
+        tx_batch_maker: Sender<Transaction>,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                seen: LruCache::new(capacity),
+                admission,
+                rx_transaction,
+                tx_batch_maker,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        while let Some(transaction) = self.rx_transaction.recv().await {
+            let digest = Digest(Sha512::digest(&transaction)[..32].try_into().unwrap());
+            if self.seen.put(digest.clone(), ()).is_some() {
+                debug!("Dropping duplicate transaction {}", digest);
+                self.admission.release(1, transaction.len());
+                continue;
+            }
+            self.tx_batch_maker
+                .send(transaction)
+                .await
+                .expect("Failed to deliver transaction");
+        }
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::worker::SerializedBatchDigestMessage;
+use std::convert::TryInto as _;
+use store::{Store, StoreError};
+
+#[cfg(test)]
+#[path = "tests/digest_queue_tests.rs"]
+pub mod digest_queue_tests;
+
+/// The prefix of the keys holding the queued messages (followed by their sequence number).
+const KEY_PREFIX: &[u8] = b"digest_queue:";
+
+/// The end (excluded) of the range of keys holding the queued messages.
+const KEY_PREFIX_END: &[u8] = b"digest_queue;";
+
+/// Persists the messages (batches' digests) awaiting delivery to our primary until it acknowledges them, so
+/// that the digests produced right before a crash still reach the primary once the worker restarts.
+pub struct DigestQueue {
+    /// The persistent storage.
+    store: Store,
+    /// The sequence number of the next message.
+    next: u64,
+}
+
+impl DigestQueue {
+    /// Open the queue, returning it along with the messages still awaiting delivery (in order).
+    pub async fn open(
+        mut store: Store,
+    ) -> Result<(Self, Vec<(u64, SerializedBatchDigestMessage)>), StoreError> {
+        let messages: Vec<_> = store
+            .read_range(KEY_PREFIX.to_vec(), KEY_PREFIX_END.to_vec())
+            .await?
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let sequence = key[KEY_PREFIX.len()..].try_into().ok()?;
+                Some((u64::from_be_bytes(sequence), value))
+            })
+            .collect();
+        let next = messages.last().map_or(0, |(sequence, _)| sequence + 1);
+        Ok((Self { store, next }, messages))
+    }
+
+    /// The key of a message. Sequence numbers are big-endian, so that keys are sorted in order.
+    fn key(sequence: u64) -> Vec<u8> {
+        [KEY_PREFIX, &sequence.to_be_bytes()].concat()
+    }
+
+    /// Persist a message, returning its sequence number.
+    pub async fn push(&mut self, message: &SerializedBatchDigestMessage) -> u64 {
+        let sequence = self.next;
+        self.next += 1;
+        self.store.write(Self::key(sequence), message.clone()).await;
+        sequence
+    }
+
+    /// Forget a message acknowledged by the primary.
+    pub async fn remove(&mut self, sequence: u64) {
+        self.store.delete(Self::key(sequence)).await;
+    }
+}
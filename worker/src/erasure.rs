@@ -0,0 +1,239 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::hasher;
+use crate::processor::SerializedBatchMessage;
+use crate::worker::WorkerMessage;
+use config::Committee;
+use crypto::{Digest, PublicKey};
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use log::warn;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+
+#[cfg(test)]
+#[path = "tests/erasure_tests.rs"]
+pub mod erasure_tests;
+
+/// Returns the number of data shards of a batch: the shards (one per authority) of any quorum suffice to
+/// reconstruct it (2f+1 shards with equal stakes, fewer if some authorities hold more stake).
+fn data_shards(committee: &Committee) -> usize {
+    committee.quorum_size()
+}
+
+/// Returns the index of the shard of an authority (ie. its rank in the committee, sorted by public key).
+pub fn shard_index(committee: &Committee, name: &PublicKey) -> Option<usize> {
+    let mut keys: Vec<_> = committee.authorities.keys().collect();
+    keys.sort();
+    keys.iter().position(|x| *x == name)
+}
+
+/// A shard of an erasure-coded batch. Rather than sending its (large) batch to every worker, the origin
+/// sends each of them its own shard along with the shard of the origin, and they echo their shard to each
+/// other: the outbound bandwidth of the origin is then about `2n / (2f+1)` times the size of the batch
+/// rather than `n - 1` times.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchShard {
+    /// The digest of the serialized batch.
+    pub batch: Digest,
+    /// The author of the batch.
+    pub origin: PublicKey,
+    /// The size of the serialized batch.
+    pub length: usize,
+    /// The digests of all the shards of the batch (in order).
+    pub digests: Vec<Digest>,
+    /// The index of this shard.
+    pub index: usize,
+    /// The content of this shard.
+    pub data: Vec<u8>,
+}
+
+impl BatchShard {
+    fn hash(data: &[u8]) -> Digest {
+        Digest(Sha512::digest(data)[..32].try_into().unwrap())
+    }
+
+    /// Returns whether the shard matches the commitment it carries.
+    pub fn verify(&self, committee: &Committee) -> bool {
+        self.digests.len() == committee.size()
+            && self.digests.get(self.index) == Some(&Self::hash(&self.data))
+    }
+
+    /// The commitment to all the shards of the batch.
+    pub fn commitment(&self) -> Digest {
+        let mut hasher = Sha512::new();
+        hasher.update(&self.batch);
+        hasher.update(self.origin);
+        hasher.update(self.length.to_le_bytes());
+        for x in &self.digests {
+            hasher.update(x);
+        }
+        Digest(hasher.finalize()[..32].try_into().unwrap())
+    }
+}
+
+impl fmt::Debug for BatchShard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}: S({}, {}/{})",
+            self.batch,
+            self.origin,
+            self.index,
+            self.digests.len()
+        )
+    }
+}
+
+/// Erasure-codes our large batches into one shard per authority of the committee.
+#[derive(Clone)]
+pub struct ShardEncoder {
+    /// Our public key.
+    name: PublicKey,
+    /// The committee information.
+    committee: Committee,
+    /// The batches of at least this size (in bytes) are erasure-coded.
+    threshold: usize,
+}
+
+impl ShardEncoder {
+    /// Returns an encoder, unless erasure coding is disabled (`threshold` set to 0) or the committee is too
+    /// small to tolerate a fault.
+    pub fn new(name: PublicKey, committee: Committee, threshold: usize) -> Option<Self> {
+        let enabled = threshold > 0 && data_shards(&committee) < committee.size();
+        enabled.then_some(Self {
+            name,
+            committee,
+            threshold,
+        })
+    }
+
+    /// Returns whether the serialized batch is large enough to be erasure-coded.
+    pub fn applies(&self, serialized: &[u8]) -> bool {
+        serialized.len() >= self.threshold
+    }
+
+    /// Erasure-code a serialized batch into one shard per authority of the committee.
+    pub fn encode(&self, serialized: &[u8]) -> Vec<BatchShard> {
+        let total = self.committee.size();
+        let data = data_shards(&self.committee);
+        let coder = ReedSolomon::new(data, total - data).expect("Invalid number of shards");
+
+        // Split the serialized batch into data shards (of the same size) and compute the parity shards.
+        let size = serialized.len().div_ceil(data);
+        let mut shards: Vec<Vec<u8>> = (0..total)
+            .map(|i| {
+                let mut shard = serialized
+                    .iter()
+                    .skip(i * size)
+                    .take(size)
+                    .copied()
+                    .collect::<Vec<_>>();
+                shard.resize(size, 0);
+                shard
+            })
+            .collect();
+        coder.encode(&mut shards).expect("Failed to encode batch");
+
+        let batch = hasher::digest(serialized);
+        let digests: Vec<_> = shards.iter().map(|x| BatchShard::hash(x)).collect();
+        shards
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| BatchShard {
+                batch: batch.clone(),
+                origin: self.name,
+                length: serialized.len(),
+                digests: digests.clone(),
+                index,
+                data,
+            })
+            .collect()
+    }
+
+    /// Erasure-code a serialized batch, and return the message to send to each of the specified workers
+    /// (their own shard and ours).
+    pub fn messages(
+        &self,
+        serialized: &[u8],
+        names: &[PublicKey],
+    ) -> Vec<(PublicKey, SerializedBatchMessage)> {
+        let shards = self.encode(serialized);
+        let index = |name| shard_index(&self.committee, name).expect("Unknown authority");
+        let ours = &shards[index(&self.name)];
+        names
+            .iter()
+            .map(|name| {
+                let message =
+                    WorkerMessage::BatchShards(vec![shards[index(name)].clone(), ours.clone()]);
+                let bytes = bincode::serialize(&message).expect("Failed to serialize shards");
+                (*name, SerializedBatchMessage::from(bytes))
+            })
+            .collect()
+    }
+}
+
+/// Gathers the shards of a batch (under the same commitment) until it can be reconstructed.
+pub struct ShardsAggregator {
+    /// The number of shards needed to reconstruct the batch.
+    data: usize,
+    /// The shards received so far.
+    shards: Vec<Option<Vec<u8>>>,
+    /// The number of shards received so far.
+    received: usize,
+    /// Whether the batch was already reconstructed.
+    done: bool,
+}
+
+impl ShardsAggregator {
+    pub fn new(committee: &Committee) -> Self {
+        Self {
+            data: data_shards(committee),
+            shards: vec![None; committee.size()],
+            received: 0,
+            done: false,
+        }
+    }
+
+    /// Returns whether we already hold the specified shard.
+    pub fn contains(&self, index: usize) -> bool {
+        self.shards.get(index).is_some_and(|x| x.is_some())
+    }
+
+    /// Add a (verified) shard. Returns the serialized batch once enough shards are gathered (only once),
+    /// provided it is the batch the origin committed to.
+    pub fn append(&mut self, shard: BatchShard) -> Option<SerializedBatchMessage> {
+        if self.done || shard.index >= self.shards.len() || self.contains(shard.index) {
+            return None;
+        }
+        self.shards[shard.index] = Some(shard.data);
+        self.received += 1;
+
+        if self.received < self.data {
+            return None;
+        }
+        self.done = true;
+
+        // Reconstruct the serialized batch and ensure it is the batch the origin committed to.
+        let total = self.shards.len();
+        let coder =
+            ReedSolomon::new(self.data, total - self.data).expect("Invalid number of shards");
+        if coder.reconstruct_data(&mut self.shards).is_err() {
+            warn!("Failed to reconstruct batch {}", shard.batch);
+            return None;
+        }
+        let mut serialized: Vec<u8> = self
+            .shards
+            .iter()
+            .take(self.data)
+            .flat_map(|x| x.as_ref().expect("Missing data shard").iter().copied())
+            .collect();
+        serialized.truncate(shard.length);
+        if hasher::digest(&serialized) != shard.batch {
+            warn!("Shards of {} do not match the batch", shard.batch);
+            return None;
+        }
+        Some(SerializedBatchMessage::from(serialized))
+    }
+}
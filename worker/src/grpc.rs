@@ -0,0 +1,176 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::admission::AdmissionControl;
+use crate::batch_maker::Transaction;
+use crate::transaction_validator::TransactionValidator;
+use crate::worker::WorkerMessage;
+use crypto::Digest;
+use log::{error, info, warn};
+use proto::batches_server::{Batches, BatchesServer};
+use proto::transactions_server::{Transactions, TransactionsServer};
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use store::Store;
+use tokio::sync::mpsc::Sender;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+#[cfg(test)]
+#[path = "tests/grpc_tests.rs"]
+pub mod grpc_tests;
+
+/// The messages, client, and service generated from `proto/transactions.proto`.
+pub mod proto {
+    tonic::include_proto!("narwhal");
+}
+
+/// A gRPC service receiving clients' transactions alongside the raw TCP socket, so that applications written
+/// in other languages can feed the mempool without implementing our framing. Transactions breaking the rules
+/// of the validator are rejected with `INVALID_ARGUMENT`, and transactions arriving while too many are waiting
+/// to be batched with `RESOURCE_EXHAUSTED`; a stream stops at its first rejected transaction (the previous
+/// ones are kept).
+pub struct TransactionsService {
+    /// Checks the transactions before they enter the mempool.
+    validator: Arc<dyn TransactionValidator>,
+    /// Bounds the transactions waiting to be batched.
+    admission: Arc<AdmissionControl>,
+    /// Output channel to deliver the transactions to the `BatchMaker`.
+    tx_batch_maker: Sender<Transaction>,
+}
+
+impl TransactionsService {
+    pub fn spawn(
+        address: SocketAddr,
+        validator: Arc<dyn TransactionValidator>,
+        admission: Arc<AdmissionControl>,
+        tx_batch_maker: Sender<Transaction>,
+    ) {
+        tokio::spawn(async move {
+            let service = TransactionsServer::new(Self {
+                validator,
+                admission,
+                tx_batch_maker,
+            });
+            info!("Listening to gRPC transactions on {}", address);
+            if let Err(e) = Server::builder().add_service(service).serve(address).await {
+                warn!("gRPC transactions service failed: {}", e);
+            }
+        });
+    }
+
+    /// Hand a transaction to the `BatchMaker` (if it follows the rules of the validator and there is room for it).
+    async fn submit(&self, transaction: Transaction) -> Result<(), Status> {
+        self.validator
+            .validate(&transaction)
+            .map_err(Status::invalid_argument)?;
+        if !self.admission.try_admit(transaction.len()) {
+            return Err(Status::resource_exhausted("Too many pending transactions"));
+        }
+        self.tx_batch_maker
+            .send(transaction)
+            .await
+            .map_err(|_| Status::unavailable("The worker is shutting down"))
+    }
+}
+
+#[tonic::async_trait]
+impl Transactions for TransactionsService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        self.submit(request.into_inner().transaction).await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn submit_transaction_stream(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let mut stream = request.into_inner();
+        while let Some(message) = stream.message().await? {
+            self.submit(message.transaction).await?;
+        }
+        Ok(Response::new(proto::Empty {}))
+    }
+}
+
+/// A gRPC service through which an external executor pulls the transactions of the batches whose digest was
+/// committed, rather than reading the store of the worker directly. Unknown (or pruned) batches are reported
+/// with `NOT_FOUND`.
+pub struct BatchesService {
+    /// The persistent storage.
+    store: Store,
+}
+
+impl BatchesService {
+    pub fn spawn(address: SocketAddr, store: Store) {
+        tokio::spawn(async move {
+            let service = BatchesServer::new(Self { store });
+            info!("Serving batches over gRPC on {}", address);
+            if let Err(e) = Server::builder().add_service(service).serve(address).await {
+                warn!("gRPC batches service failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Parse the digest of a request.
+fn parse_digest(request: Request<proto::BatchDigest>) -> Option<Digest> {
+    request.into_inner().digest.try_into().ok().map(Digest)
+}
+
+impl BatchesService {
+    /// Read the batch stored under the specified key.
+    async fn read(
+        &self,
+        key: Vec<u8>,
+        digest: &Digest,
+    ) -> Result<Response<proto::BatchTransactions>, Status> {
+        let mut store = self.store.clone();
+        let serialized = match store.read(key).await {
+            Ok(Some(serialized)) => serialized,
+            Ok(None) => return Err(Status::not_found(format!("Unknown batch {}", digest))),
+            Err(e) => {
+                error!("{}", e);
+                return Err(Status::internal("Failed to read the store"));
+            }
+        };
+        match bincode::deserialize(&serialized) {
+            Ok(WorkerMessage::Batch(transactions)) => {
+                Ok(Response::new(proto::BatchTransactions { transactions }))
+            }
+            _ => Err(Status::internal(format!("Corrupted batch {}", digest))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Batches for BatchesService {
+    async fn get_batch(
+        &self,
+        request: Request<proto::BatchDigest>,
+    ) -> Result<Response<proto::BatchTransactions>, Status> {
+        let digest =
+            parse_digest(request).ok_or_else(|| Status::invalid_argument("Invalid digest"))?;
+        self.read(digest.to_vec(), &digest).await
+    }
+
+    async fn get_decrypted_batch(
+        &self,
+        request: Request<proto::BatchDigest>,
+    ) -> Result<Response<proto::BatchTransactions>, Status> {
+        #[cfg(feature = "threshold-encryption")]
+        {
+            let digest =
+                parse_digest(request).ok_or_else(|| Status::invalid_argument("Invalid digest"))?;
+            self.read(crate::decryption::decrypted_batch_key(&digest), &digest)
+                .await
+        }
+        #[cfg(not(feature = "threshold-encryption"))]
+        {
+            let _ = request;
+            Err(Status::unimplemented("Threshold encryption is not enabled"))
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::batch_maker::Batch;
+use crate::processor::SerializedBatchMessage;
+use crate::worker::WorkerMessage;
+use crypto::Digest;
+use ed25519_dalek::{Digest as _, Sha512};
+use std::convert::TryInto as _;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+#[path = "tests/hasher_tests.rs"]
+pub mod hasher_tests;
+
+/// The maximum number of jobs waiting for a thread of the pool.
+const QUEUE_CAPACITY: usize = 1_000;
+
+/// A job run by the pool.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Returns the digest of a serialized batch.
+pub fn digest(batch: &[u8]) -> Digest {
+    Digest(Sha512::digest(batch)[..32].try_into().unwrap())
+}
+
+/// Serializes and hashes batches on a pool of dedicated threads, so that large batches never block the
+/// async tasks ingesting transactions.
+#[derive(Clone)]
+pub struct BatchHasher {
+    channel: Sender<Job>,
+}
+
+impl BatchHasher {
+    /// Spawn the specified number of threads (at least one). They exit once all the clones of the hasher
+    /// are dropped.
+    pub fn new(threads: usize) -> Self {
+        let (tx, rx) = channel::<Job>(QUEUE_CAPACITY);
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..threads.max(1) {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("hasher-{}", i))
+                .spawn(move || loop {
+                    let job = rx.lock().unwrap().blocking_recv();
+                    match job {
+                        Some(job) => job(),
+                        None => return,
+                    }
+                })
+                .expect("Failed to spawn hasher thread");
+        }
+        Self { channel: tx }
+    }
+
+    /// Run a function on the pool and return its output.
+    pub async fn run<T, F>(&self, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job = Box::new(move || {
+            let _ = sender.send(f());
+        });
+        if self.channel.send(job).await.is_err() {
+            panic!("Failed to send job to the hasher");
+        }
+        receiver
+            .await
+            .expect("Failed to receive result from the hasher")
+    }
+
+    /// Serialize a batch into a `WorkerMessage::Batch` message, and hash it.
+    pub async fn serialize(&self, batch: Batch) -> (Digest, SerializedBatchMessage) {
+        self.run(move || {
+            let message = WorkerMessage::Batch(batch);
+            let serialized: SerializedBatchMessage = bincode::serialize(&message)
+                .expect("Failed to serialize our own batch")
+                .into();
+            (digest(&serialized), serialized)
+        })
+        .await
+    }
+
+    /// Hash a serialized batch.
+    pub async fn hash(&self, batch: SerializedBatchMessage) -> (Digest, SerializedBatchMessage) {
+        self.run(move || (digest(&batch), batch)).await
+    }
+}
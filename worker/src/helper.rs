@@ -1,17 +1,52 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::metrics::WorkerMetrics;
 use bytes::Bytes;
 use config::{Committee, WorkerId};
 use crypto::{Digest, PublicKey};
-use log::{error, warn};
-use network::SimpleSender;
+use log::{debug, error, warn};
+use network::{RateLimit, SharedTransport, SimpleSender, TokenBucket};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
 
 #[cfg(test)]
 #[path = "tests/helper_tests.rs"]
 pub mod helper_tests;
 
-/// A task dedicated to help other authorities by replying to their batch requests.
+/// The maximum number of queued requests the helper gathers (and deduplicates) at once.
+const MAX_PENDING_REQUESTS: usize = 1_000;
+
+/// A reply to a stream request carries batches until their size reaches this bound. It keeps replies well
+/// below the maximum frame length of the network.
+const MAX_STREAM_REPLY_SIZE: usize = 4 * 1024 * 1024;
+
+/// The requests served by the helper.
+#[derive(Debug)]
+pub enum HelperRequest {
+    /// Send the requested batches to the worker of the requestor (one message per batch).
+    Batches(Vec<Digest>, /* origin */ PublicKey),
+    /// Reply with the requested batches that fit in a single `BatchStreamReply`.
+    Stream(
+        Vec<Digest>,
+        /* origin */ PublicKey,
+        oneshot::Sender<Bytes>,
+    ),
+}
+
+/// The reply to a stream request: the requested batches (serialized `WorkerMessage::Batch` messages) that
+/// fit in a single reply, in the order of the request. The requestor then asks for the rest.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BatchStreamReply {
+    pub batches: Vec<Vec<u8>>,
+}
+
+/// A task dedicated to help other authorities by replying to their batch requests. The batches a requestor
+/// asks for several times while its requests are queued are sent once, and each requestor is served at
+/// most at `rate_limit`.
 pub struct Helper {
     /// The id of this worker.
     id: WorkerId,
@@ -20,17 +55,28 @@ pub struct Helper {
     /// The persistent storage.
     store: Store,
     /// Input channel to receive batch requests.
-    rx_request: Receiver<(Vec<Digest>, PublicKey)>,
+    rx_request: Receiver<HelperRequest>,
     /// A network sender to send the batches to the other workers.
     network: SimpleSender,
+    /// The rate at which we serve each requestor (if any).
+    rate_limit: Option<RateLimit>,
+    /// The token bucket of each requestor.
+    buckets: HashMap<PublicKey, TokenBucket>,
+    /// The metrics of the worker.
+    metrics: Arc<WorkerMetrics>,
 }
 
 impl Helper {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         id: WorkerId,
         committee: Committee,
         store: Store,
-        rx_request: Receiver<(Vec<Digest>, PublicKey)>,
+        transport: SharedTransport,
+        chunk_size: usize,
+        rate_limit: Option<RateLimit>,
+        metrics: Arc<WorkerMetrics>,
+        rx_request: Receiver<HelperRequest>,
     ) {
         tokio::spawn(async move {
             Self {
@@ -38,32 +84,128 @@ impl Helper {
                 committee,
                 store,
                 rx_request,
-                network: SimpleSender::new(),
+                network: SimpleSender::new()
+                    .with_transport(transport)
+                    .with_chunk_size(chunk_size),
+                rate_limit,
+                buckets: HashMap::new(),
+                metrics,
             }
             .run()
             .await;
         });
     }
 
-    async fn run(&mut self) {
-        while let Some((digests, origin)) = self.rx_request.recv().await {
-            // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
+    /// Returns whether we may serve the requestor (ie. it did not exceed its rate limit).
+    fn admit(&mut self, origin: &PublicKey) -> bool {
+        match self.rate_limit {
+            Some(limit) => self
+                .buckets
+                .entry(*origin)
+                .or_insert_with(|| TokenBucket::new(limit))
+                .has_tokens(),
+            None => true,
+        }
+    }
 
-            // get the requestors address.
-            let address = match self.committee.worker(&origin, &self.id) {
-                Ok(x) => x.worker_to_worker,
-                Err(e) => {
-                    warn!("Unexpected batch request: {}", e);
-                    continue;
+    /// Gather the requests waiting in the queue (starting with `first`), dropping the digests their
+    /// requestor already asked for.
+    fn gather(&mut self, first: HelperRequest) -> Vec<HelperRequest> {
+        let mut requests = vec![first];
+        while requests.len() < MAX_PENDING_REQUESTS {
+            match self.rx_request.try_recv() {
+                Ok(request) => requests.push(request),
+                Err(_) => break,
+            }
+        }
+
+        let mut seen = HashSet::new();
+        requests
+            .into_iter()
+            .filter_map(|request| match request {
+                HelperRequest::Batches(digests, origin) => {
+                    let digests = digests
+                        .into_iter()
+                        .filter(|x| seen.insert((origin, x.clone())))
+                        .collect::<Vec<_>>();
+                    (!digests.is_empty()).then(|| HelperRequest::Batches(digests, origin))
                 }
-            };
+                request => Some(request),
+            })
+            .collect()
+    }
 
-            // Reply to the request (the best we can).
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => self.network.send(address, Bytes::from(data)).await,
-                    Ok(None) => (),
-                    Err(e) => error!("{}", e),
+    /// Read a requested batch (if we have it), charging its size to the requestor.
+    async fn read(&mut self, digest: &Digest, origin: &PublicKey) -> Option<Vec<u8>> {
+        match self.store.read(digest.to_vec()).await {
+            Ok(Some(data)) => {
+                self.metrics.synced_batches.fetch_add(1, Ordering::Relaxed);
+                if let Some(bucket) = self.buckets.get_mut(origin) {
+                    bucket.consume(data.len());
+                }
+                Some(data)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Send the requested batches to the worker of the requestor (the best we can).
+    async fn send(&mut self, digests: Vec<Digest>, origin: PublicKey) {
+        // get the requestors address.
+        let address = match self.committee.worker(&origin, &self.id) {
+            Ok(x) => x.worker_to_worker.advertise,
+            Err(e) => {
+                warn!("Unexpected batch request: {}", e);
+                return;
+            }
+        };
+
+        for digest in digests {
+            if !self.admit(&origin) {
+                debug!("Rate limiting batch requests of {}", origin);
+                break;
+            }
+            if let Some(data) = self.read(&digest, &origin).await {
+                self.network.send(address.clone(), Bytes::from(data)).await;
+            }
+        }
+    }
+
+    /// Reply with the requested batches that fit in a single reply (the best we can).
+    async fn stream(&mut self, digests: Vec<Digest>, origin: PublicKey) -> BatchStreamReply {
+        let mut reply = BatchStreamReply::default();
+        let mut size = 0;
+        for digest in digests {
+            if !self.admit(&origin) {
+                debug!("Rate limiting batch requests of {}", origin);
+                break;
+            }
+            if let Some(data) = self.read(&digest, &origin).await {
+                size += data.len();
+                reply.batches.push(data);
+                if size >= MAX_STREAM_REPLY_SIZE {
+                    break;
+                }
+            }
+        }
+        reply
+    }
+
+    async fn run(&mut self) {
+        while let Some(request) = self.rx_request.recv().await {
+            for request in self.gather(request) {
+                self.metrics.sync_requests.fetch_add(1, Ordering::Relaxed);
+                match request {
+                    HelperRequest::Batches(digests, origin) => self.send(digests, origin).await,
+                    HelperRequest::Stream(digests, origin, sender) => {
+                        let reply = self.stream(digests, origin).await;
+                        let bytes = bincode::serialize(&reply).expect("Failed to serialize reply");
+                        let _ = sender.send(Bytes::from(bytes));
+                    }
                 }
             }
         }
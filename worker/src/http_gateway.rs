@@ -0,0 +1,116 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::admission::AdmissionControl;
+use crate::batch_maker::Transaction;
+use crate::transaction_validator::TransactionValidator;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use log::{info, warn};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+
+#[cfg(test)]
+#[path = "tests/http_gateway_tests.rs"]
+pub mod http_gateway_tests;
+
+/// The encoding of a transaction submitted over HTTP.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+/// The body of `POST /transactions`.
+#[derive(Deserialize)]
+struct SubmitRequest {
+    /// The encoded transaction.
+    transaction: String,
+    /// The encoding of the transaction (base64 by default).
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+/// The state shared by the handlers of the gateway.
+#[derive(Clone)]
+struct GatewayState {
+    validator: Arc<dyn TransactionValidator>,
+    admission: Arc<AdmissionControl>,
+    tx_batch_maker: Sender<Transaction>,
+}
+
+/// A lightweight HTTP endpoint to submit transactions, meant for debugging and low-rate integrations:
+///   - `POST /transactions` with the JSON body `{"transaction": "<DATA>", "encoding": "base64" | "hex"}`
+///     replies `202 Accepted` once the transaction is handed to the `BatchMaker`, or `400 Bad Request` if
+///     it cannot be decoded or breaks the rules of the validator, or `429 Too Many Requests` if too many
+///     transactions are waiting to be batched.
+pub struct HttpGateway;
+
+impl HttpGateway {
+    pub fn spawn(
+        address: SocketAddr,
+        validator: Arc<dyn TransactionValidator>,
+        admission: Arc<AdmissionControl>,
+        tx_batch_maker: Sender<Transaction>,
+    ) {
+        let state = GatewayState {
+            validator,
+            admission,
+            tx_batch_maker,
+        };
+        let app = Router::new()
+            .route("/transactions", post(submit))
+            .with_state(state);
+
+        tokio::spawn(async move {
+            let server = match axum::Server::try_bind(&address) {
+                Ok(server) => server,
+                Err(e) => {
+                    warn!("Failed to bind HTTP gateway to {}: {}", address, e);
+                    return;
+                }
+            };
+            info!("Listening to HTTP transactions on {}", address);
+            if let Err(e) = server.serve(app.into_make_service()).await {
+                warn!("HTTP gateway failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn submit(
+    State(state): State<GatewayState>,
+    Json(request): Json<SubmitRequest>,
+) -> (StatusCode, String) {
+    let decoded = match request.encoding {
+        Encoding::Base64 => base64::decode(&request.transaction).map_err(|e| e.to_string()),
+        Encoding::Hex => hex::decode(&request.transaction).map_err(|e| e.to_string()),
+    };
+    let transaction = match decoded {
+        Ok(transaction) => transaction,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid encoding: {}", e)),
+    };
+    if let Err(e) = state.validator.validate(&transaction) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Rejected transaction: {}", e),
+        );
+    }
+    if !state.admission.try_admit(transaction.len()) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many pending transactions".to_string(),
+        );
+    }
+    match state.tx_batch_maker.send(transaction).await {
+        Ok(()) => (StatusCode::ACCEPTED, String::new()),
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The worker is shutting down".to_string(),
+        ),
+    }
+}
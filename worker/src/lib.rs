@@ -1,14 +1,36 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+mod admission;
 mod batch_maker;
+mod batch_pruner;
+mod compression;
+mod coverage;
+#[cfg(feature = "threshold-encryption")]
+mod decryption;
+mod deduplicator;
+mod digest_queue;
+mod erasure;
+mod grpc;
+mod hasher;
 mod helper;
+mod http_gateway;
+mod metrics;
+mod padding;
+mod pending_batches;
 mod primary_connector;
 mod processor;
 mod quorum_waiter;
+mod reconstructor;
 mod synchronizer;
+mod transaction_validator;
 mod worker;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::worker::Worker;
+pub use crate::batch_maker::TransactionPriority;
+pub use crate::grpc::proto;
+pub use crate::metrics::WorkerMetrics;
+pub use crate::primary_connector::DeliveryMetrics;
+pub use crate::transaction_validator::{AcceptAllTransactions, TransactionValidator};
+pub use crate::worker::{Worker, REJECTED, SLOW_DOWN};
@@ -0,0 +1,153 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::admission::AdmissionControl;
+use crate::primary_connector::DeliveryMetrics;
+use axum::extract::State;
+use axum::routing::get;
+use axum::Router;
+use log::{info, warn};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(test)]
+#[path = "tests/metrics_tests.rs"]
+pub mod metrics_tests;
+
+/// The counters of the tasks of a worker, so that its bottlenecks are observable.
+#[derive(Debug, Default)]
+pub struct WorkerMetrics {
+    /// The number of client transactions handed to the `BatchMaker`.
+    pub transactions: AtomicU64,
+    /// The number of batches we sealed.
+    pub batches: AtomicU64,
+    /// The number of our batches acknowledged by enough authorities.
+    pub quorum_waits: AtomicU64,
+    /// The total time our batches waited for enough acknowledgements. Denominated in microseconds.
+    pub quorum_wait_time: AtomicU64,
+    /// The number of batch requests of the other workers we served.
+    pub sync_requests: AtomicU64,
+    /// The number of batches we sent to the other workers in reply to their requests.
+    pub synced_batches: AtomicU64,
+}
+
+impl WorkerMetrics {
+    /// Record that one of our batches waited for its acknowledgements for the specified time.
+    pub fn record_quorum_wait(&self, elapsed: Duration) {
+        self.quorum_waits.fetch_add(1, Ordering::Relaxed);
+        self.quorum_wait_time
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Encode the counters (along with the transactions waiting to be batched) in the Prometheus text
+    /// exposition format.
+    pub fn encode(&self, admission: &AdmissionControl) -> String {
+        let mut output = String::new();
+        let counters = [
+            (
+                "worker_transactions_total",
+                "Client transactions handed to the batch maker.",
+                &self.transactions,
+            ),
+            ("worker_batches_total", "Batches sealed.", &self.batches),
+            (
+                "worker_sync_requests_total",
+                "Batch requests of other workers served.",
+                &self.sync_requests,
+            ),
+            (
+                "worker_synced_batches_total",
+                "Batches sent in reply to the requests of other workers.",
+                &self.synced_batches,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            let _ = writeln!(output, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        let name = "worker_quorum_wait_seconds";
+        let _ = writeln!(
+            output,
+            "# HELP {} Time our batches waited for enough acknowledgements.",
+            name
+        );
+        let _ = writeln!(output, "# TYPE {} summary", name);
+        let sum = self.quorum_wait_time.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(output, "{}_sum {}", name, sum);
+        let count = self.quorum_waits.load(Ordering::Relaxed);
+        let _ = writeln!(output, "{}_count {}", name, count);
+
+        let (transactions, bytes) = admission.pending();
+        let gauges = [
+            (
+                "worker_pending_transactions",
+                "Transactions waiting to be batched.",
+                transactions,
+            ),
+            (
+                "worker_pending_bytes",
+                "Size of the transactions waiting to be batched.",
+                bytes,
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+        output
+    }
+}
+
+/// The state shared by the handlers of the metrics server.
+#[derive(Clone)]
+struct MetricsState {
+    metrics: Arc<WorkerMetrics>,
+    admission: Arc<AdmissionControl>,
+    delivery: Arc<DeliveryMetrics>,
+}
+
+/// An HTTP endpoint exposing the metrics of the worker: `GET /metrics` returns the `WorkerMetrics` and the
+/// `DeliveryMetrics` of the worker in the Prometheus text format.
+pub struct MetricsServer;
+
+impl MetricsServer {
+    pub fn spawn(
+        address: SocketAddr,
+        metrics: Arc<WorkerMetrics>,
+        admission: Arc<AdmissionControl>,
+        delivery: Arc<DeliveryMetrics>,
+    ) {
+        let state = MetricsState {
+            metrics,
+            admission,
+            delivery,
+        };
+        let app = Router::new()
+            .route("/metrics", get(encode))
+            .with_state(state);
+
+        tokio::spawn(async move {
+            let server = match axum::Server::try_bind(&address) {
+                Ok(server) => server,
+                Err(e) => {
+                    warn!("Failed to bind metrics server to {}: {}", address, e);
+                    return;
+                }
+            };
+            info!("Metrics server listening on {}", address);
+            if let Err(e) = server.serve(app.into_make_service()).await {
+                warn!("Metrics server failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn encode(State(state): State<MetricsState>) -> String {
+    let mut output = state.metrics.encode(&state.admission);
+    output.push_str(&state.delivery.encode());
+    output
+}
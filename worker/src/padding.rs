@@ -0,0 +1,88 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::processor::SerializedBatchMessage;
+use crate::worker::WorkerMessage;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/padding_tests.rs"]
+pub mod padding_tests;
+
+/// The bytes a `WorkerMessage::PaddedBatch` adds to the message it wraps (the variant tag and the lengths
+/// of the message and of the padding).
+const PADDING_HEADER: usize = 4 + 8 + 8;
+
+/// The total size of the messages we padded, before and after padding.
+#[derive(Debug, Default)]
+pub struct PaddingStats {
+    /// The size of the messages before padding. Denominated in bytes.
+    pub payload_bytes: AtomicU64,
+    /// The size of the padded messages. Denominated in bytes.
+    pub padded_bytes: AtomicU64,
+}
+
+impl PaddingStats {
+    /// Returns the overall bandwidth overhead of padding (padded over unpadded size), if we padded anything.
+    pub fn overhead(&self) -> Option<f64> {
+        let payload = self.payload_bytes.load(Ordering::Relaxed);
+        let padded = self.padded_bytes.load(Ordering::Relaxed);
+        (payload > 0).then(|| padded as f64 / payload as f64)
+    }
+}
+
+/// Pads the batches we broadcast to the other workers up to fixed-size buckets, so that an observer of the
+/// network cannot infer the size of the batches (and thus the load of the clients). The receivers strip the
+/// padding before hashing and storing the batches, so the digest of a batch does not depend on its padding.
+#[derive(Clone)]
+pub struct BatchPadder {
+    /// The sizes of the buckets, in increasing order. Denominated in bytes.
+    buckets: Vec<usize>,
+    /// The padding statistics.
+    stats: Arc<PaddingStats>,
+}
+
+impl BatchPadder {
+    /// Returns a padder, unless no bucket is specified.
+    pub fn new(mut buckets: Vec<usize>) -> Option<Self> {
+        buckets.retain(|x| *x > 0);
+        buckets.sort_unstable();
+        buckets.dedup();
+        (!buckets.is_empty()).then(|| Self {
+            buckets,
+            stats: Arc::default(),
+        })
+    }
+
+    /// Returns the padding statistics.
+    pub fn stats(&self) -> &PaddingStats {
+        &self.stats
+    }
+
+    /// Returns the size of the bucket of a message: the smallest bucket it fits in, or a multiple of the
+    /// largest bucket if it fits in none.
+    fn bucket(&self, size: usize) -> usize {
+        match self.buckets.iter().find(|x| **x >= size) {
+            Some(bucket) => *bucket,
+            None => {
+                let largest = *self.buckets.last().expect("No padding bucket");
+                size.div_ceil(largest) * largest
+            }
+        }
+    }
+
+    /// Wrap a serialized message (a batch, possibly compressed) into a serialized `WorkerMessage::PaddedBatch`
+    /// filling its bucket.
+    pub fn pad(&self, message: &[u8]) -> SerializedBatchMessage {
+        let size = message.len() + PADDING_HEADER;
+        let padding = vec![0; self.bucket(size) - size];
+        let padded = WorkerMessage::PaddedBatch(message.to_vec(), padding);
+        let serialized = bincode::serialize(&padded).expect("Failed to serialize our own batch");
+        self.stats
+            .payload_bytes
+            .fetch_add(message.len() as u64, Ordering::Relaxed);
+        self.stats
+            .padded_bytes
+            .fetch_add(serialized.len() as u64, Ordering::Relaxed);
+        serialized.into()
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crypto::Digest;
+use std::convert::TryInto as _;
+use store::{Store, StoreError};
+
+#[cfg(test)]
+#[path = "tests/pending_batches_tests.rs"]
+pub mod pending_batches_tests;
+
+/// The prefix of the keys marking our batches that are not yet sequenced (one key per batch).
+const KEY_PREFIX: &[u8] = b"pending_batch:";
+
+/// The end (excluded) of the range of keys holding the markers.
+const KEY_PREFIX_END: &[u8] = b"pending_batch;";
+
+/// Persists the digests of our own batches until our primary reports them as sequenced, so that a restarted
+/// worker can re-send the digests the primary may have lost (along with the clients' transactions).
+#[derive(Clone)]
+pub struct PendingBatches {
+    /// The persistent storage.
+    store: Store,
+}
+
+impl PendingBatches {
+    pub fn new(store: Store) -> Self {
+        Self { store }
+    }
+
+    fn key(digest: &Digest) -> Vec<u8> {
+        [KEY_PREFIX, digest.as_ref()].concat()
+    }
+
+    /// Mark a batch as pending.
+    pub async fn insert(&mut self, digest: &Digest) {
+        self.store.write(Self::key(digest), Vec::new()).await;
+    }
+
+    /// Forget a batch that has been sequenced.
+    pub async fn remove(&mut self, digest: &Digest) {
+        self.store.delete(Self::key(digest)).await;
+    }
+
+    /// Returns whether a batch is pending.
+    pub async fn contains(&mut self, digest: &Digest) -> Result<bool, StoreError> {
+        Ok(self.store.read(Self::key(digest)).await?.is_some())
+    }
+
+    /// Returns the digests of all pending batches.
+    pub async fn read_all(&mut self) -> Result<Vec<Digest>, StoreError> {
+        let entries = self
+            .store
+            .read_range(KEY_PREFIX.to_vec(), KEY_PREFIX_END.to_vec())
+            .await?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|(key, _)| key[KEY_PREFIX.len()..].try_into().ok().map(Digest))
+            .collect())
+    }
+}
@@ -1,39 +1,161 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::digest_queue::DigestQueue;
 use crate::worker::SerializedBatchDigestMessage;
 use bytes::Bytes;
-use network::SimpleSender;
-use std::net::SocketAddr;
+use futures::stream::{FuturesUnordered, StreamExt as _};
+use log::{info, warn};
+use network::{Address, CancelHandler, NetworkMetrics, ReliableSender, SharedTransport};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot::error::RecvError;
 
-// Send batches' digests to the primary.
+#[cfg(test)]
+#[path = "tests/primary_connector_tests.rs"]
+pub mod primary_connector_tests;
+
+/// The delivery counters of the messages the worker sends to its primary. Digests that never reach the
+/// primary mean whole batches never make it into a header, so these are worth watching.
+#[derive(Default)]
+pub struct DeliveryMetrics {
+    /// The number of messages handed to the network.
+    pub sent: AtomicU64,
+    /// The number of messages acknowledged by the primary.
+    pub delivered: AtomicU64,
+    /// The number of messages dropped before the primary acknowledged them.
+    pub failed: AtomicU64,
+    /// The connection counters of the link to the primary (failures, re-transmissions, ...).
+    pub network: NetworkMetrics,
+}
+
+impl DeliveryMetrics {
+    /// Returns the number of messages sent but not yet acknowledged by the primary.
+    pub fn pending(&self) -> u64 {
+        let done = self.delivered.load(Ordering::Relaxed) + self.failed.load(Ordering::Relaxed);
+        self.sent.load(Ordering::Relaxed).saturating_sub(done)
+    }
+
+    /// Encode the counters in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut output = self.network.encode();
+        let counters = [
+            (
+                "worker_primary_messages_sent_total",
+                "Messages sent to the primary.",
+                &self.sent,
+            ),
+            (
+                "worker_primary_messages_delivered_total",
+                "Messages acknowledged by the primary.",
+                &self.delivered,
+            ),
+            (
+                "worker_primary_messages_failed_total",
+                "Messages dropped before the primary acknowledged them.",
+                &self.failed,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            let _ = writeln!(output, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+        output
+    }
+}
+
+/// Send batches' digests to the primary. The digests are re-transmitted until the primary acknowledges
+/// them, so that they survive the primary being briefly unreachable (eg. while it restarts). They are also
+/// persisted until then, and re-sent when the worker restarts.
 pub struct PrimaryConnector {
     /// The primary network address.
-    primary_address: SocketAddr,
+    primary_address: Address,
+    /// Persists the digests until the primary acknowledges them.
+    queue: DigestQueue,
     /// Input channel to receive the digests to send to the primary.
     rx_digest: Receiver<SerializedBatchDigestMessage>,
     /// A network sender to send the baches' digests to the primary.
-    network: SimpleSender,
+    network: ReliableSender,
+    /// The delivery counters of the digests.
+    metrics: Arc<DeliveryMetrics>,
 }
 
 impl PrimaryConnector {
-    pub fn spawn(primary_address: SocketAddr, rx_digest: Receiver<SerializedBatchDigestMessage>) {
+    pub fn spawn(
+        primary_address: Address,
+        transport: SharedTransport,
+        store: Store,
+        metrics: Arc<DeliveryMetrics>,
+        rx_digest: Receiver<SerializedBatchDigestMessage>,
+    ) {
         tokio::spawn(async move {
+            let (queue, queued) = DigestQueue::open(store)
+                .await
+                .expect("Failed to read the digest queue");
             Self {
                 primary_address,
+                queue,
                 rx_digest,
-                network: SimpleSender::new(),
+                network: ReliableSender::new()
+                    .with_transport(transport)
+                    .with_metrics(metrics.network.clone()),
+                metrics,
             }
-            .run()
+            .run(queued)
             .await;
         });
     }
 
-    async fn run(&mut self) {
-        while let Some(digest) = self.rx_digest.recv().await {
-            // Send the digest through the network.
-            self.network
-                .send(self.primary_address, Bytes::from(digest))
-                .await;
+    /// Helper function. It waits for the primary to acknowledge a message and returns its sequence number.
+    async fn waiter(sequence: u64, handler: CancelHandler) -> (u64, Result<Bytes, RecvError>) {
+        (sequence, handler.await)
+    }
+
+    /// Send a (persisted) message to the primary.
+    async fn send(&mut self, digest: SerializedBatchDigestMessage) -> CancelHandler {
+        let handler = self
+            .network
+            .send(self.primary_address.clone(), Bytes::from(digest))
+            .await;
+        self.metrics.sent.fetch_add(1, Ordering::Relaxed);
+        handler
+    }
+
+    async fn run(&mut self, queued: Vec<(u64, SerializedBatchDigestMessage)>) {
+        // The cancel handlers of the digests not yet acknowledged by the primary.
+        let mut pending = FuturesUnordered::new();
+
+        // Re-send the digests the primary did not acknowledge before we restarted.
+        if !queued.is_empty() {
+            info!("Re-sending {} queued digests to our primary", queued.len());
+        }
+        for (sequence, digest) in queued {
+            let handler = self.send(digest).await;
+            pending.push(Self::waiter(sequence, handler));
+        }
+
+        loop {
+            tokio::select! {
+                Some(digest) = self.rx_digest.recv() => {
+                    // Persist the digest, and send it through the network.
+                    let sequence = self.queue.push(&digest).await;
+                    let handler = self.send(digest).await;
+                    pending.push(Self::waiter(sequence, handler));
+                },
+                Some((sequence, result)) = pending.next() => match result {
+                    Ok(_) => {
+                        self.queue.remove(sequence).await;
+                        self.metrics.delivered.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(_) => {
+                        warn!("Failed to deliver a message to primary {}", self.primary_address);
+                        self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+                else => break,
+            }
         }
     }
 }
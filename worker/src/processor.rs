@@ -1,11 +1,12 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::worker::SerializedBatchDigestMessage;
+use crate::batch_pruner::BatchIndex;
+use crate::coverage::Coverage;
+use crate::hasher::BatchHasher;
+use crate::pending_batches::PendingBatches;
+use crate::worker::{SerializedBatchDigestMessage, WorkerMessage};
+use bytes::Bytes;
 use config::WorkerId;
-use crypto::Digest;
-use ed25519_dalek::Digest as _;
-use ed25519_dalek::Sha512;
 use primary::WorkerPrimaryMessage;
-use std::convert::TryInto;
 use store::Store;
 use tokio::sync::mpsc::{Receiver, Sender};
 
@@ -13,13 +14,15 @@ use tokio::sync::mpsc::{Receiver, Sender};
 #[path = "tests/processor_tests.rs"]
 pub mod processor_tests;
 
-/// Indicates a serialized `WorkerMessage::Batch` message.
-pub type SerializedBatchMessage = Vec<u8>;
+/// Indicates a serialized `WorkerMessage::Batch` message. It is kept as `Bytes` so that the same buffer
+/// can be shared by the network, the `QuorumWaiter`, and the `Processor` without being copied.
+pub type SerializedBatchMessage = Bytes;
 
 /// Hashes and stores batches, it then outputs the batch's digest.
 pub struct Processor;
 
 impl Processor {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         // Our worker's id.
         id: WorkerId,
@@ -31,14 +34,45 @@ impl Processor {
         tx_digest: Sender<SerializedBatchDigestMessage>,
         // Whether we are processing our own batches or the batches of other nodes.
         own_digest: bool,
+        // Indexes the stored batches by round (if they are pruned).
+        mut index: Option<BatchIndex>,
+        // Hashes the batches off the event loop.
+        hasher: BatchHasher,
+        // Tracks the transactions covered by the batches of the other workers (if enabled).
+        coverage: Option<Coverage>,
     ) {
+        let mut pending = PendingBatches::new(store.clone());
         tokio::spawn(async move {
             while let Some(batch) = rx_batch.recv().await {
                 // Hash the batch.
-                let digest = Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap());
+                let (digest, batch) = hasher.hash(batch).await;
 
                 // Store the batch.
-                store.write(digest.to_vec(), batch).await;
+                store.write(digest.to_vec(), batch.to_vec()).await;
+
+                if let Some(index) = index.as_mut() {
+                    index.insert(&digest, batch.len()).await;
+                }
+
+                // Remember the transactions of the batch, to skip them once it is committed.
+                if let Some(coverage) = &coverage {
+                    let (coverage, digest, batch) =
+                        (coverage.clone(), digest.clone(), batch.clone());
+                    hasher
+                        .run(move || {
+                            if let Ok(WorkerMessage::Batch(transactions)) =
+                                bincode::deserialize(&batch)
+                            {
+                                coverage.record(digest, &transactions);
+                            }
+                        })
+                        .await;
+                }
+
+                // Remember our own batches until they are sequenced (in case we crash before).
+                if own_digest {
+                    pending.insert(&digest).await;
+                }
 
                 // Deliver the batch's digest.
                 let message = match own_digest {
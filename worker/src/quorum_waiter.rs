@@ -1,11 +1,12 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::metrics::WorkerMetrics;
 use crate::processor::SerializedBatchMessage;
-use config::{Committee, Stake};
+use config::{Committee, DeliveryThreshold, Stake};
 use crypto::PublicKey;
-use futures::stream::futures_unordered::FuturesUnordered;
-use futures::stream::StreamExt as _;
-use network::CancelHandler;
+use network::{CancelHandler, ReliableSender};
+use std::sync::Arc;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::Instant;
 
 #[cfg(test)]
 #[path = "tests/quorum_waiter_tests.rs"]
@@ -19,16 +20,21 @@ pub struct QuorumWaiterMessage {
     pub handlers: Vec<(PublicKey, CancelHandler)>,
 }
 
-/// The QuorumWaiter waits for 2f authorities to acknowledge reception of a batch.
+/// The QuorumWaiter waits for enough authorities (by default 2f, weighted by stake) to acknowledge reception
+/// of a batch.
 pub struct QuorumWaiter {
     /// The committee information.
     committee: Committee,
     /// The stake of this authority.
     stake: Stake,
+    /// The stake of the acknowledgements (including ours) to wait for.
+    threshold: DeliveryThreshold,
     /// Input Channel to receive commands.
     rx_message: Receiver<QuorumWaiterMessage>,
     /// Channel to deliver batches for which we have enough acknowledgements.
     tx_batch: Sender<SerializedBatchMessage>,
+    /// The metrics of the worker.
+    metrics: Arc<WorkerMetrics>,
 }
 
 impl QuorumWaiter {
@@ -36,51 +42,47 @@ impl QuorumWaiter {
     pub fn spawn(
         committee: Committee,
         stake: Stake,
+        threshold: DeliveryThreshold,
         rx_message: Receiver<QuorumWaiterMessage>,
-        tx_batch: Sender<Vec<u8>>,
+        tx_batch: Sender<SerializedBatchMessage>,
+        metrics: Arc<WorkerMetrics>,
     ) {
         tokio::spawn(async move {
             Self {
                 committee,
                 stake,
+                threshold,
                 rx_message,
                 tx_batch,
+                metrics,
             }
             .run()
             .await;
         });
     }
 
-    /// Helper function. It waits for a future to complete and then delivers a value.
-    async fn waiter(wait_for: CancelHandler, deliver: Stake) -> Stake {
-        let _ = wait_for.await;
-        deliver
-    }
-
     /// Main loop.
     async fn run(&mut self) {
         while let Some(QuorumWaiterMessage { batch, handlers }) = self.rx_message.recv().await {
-            let mut wait_for_quorum: FuturesUnordered<_> = handlers
+            let start = Instant::now();
+            let handlers = handlers
                 .into_iter()
-                .map(|(name, handler)| {
-                    let stake = self.committee.stake(&name);
-                    Self::waiter(handler, stake)
-                })
+                .map(|(name, handler)| (self.committee.stake(&name), handler))
                 .collect();
 
-            // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
-            // delivered and we send its digest to the primary (that will include it into
-            // the dag). This should reduce the amount of synching.
-            let mut total_stake = self.stake;
-            while let Some(stake) = wait_for_quorum.next().await {
-                total_stake += stake;
-                if total_stake >= self.committee.quorum_threshold() {
-                    self.tx_batch
-                        .send(batch)
-                        .await
-                        .expect("Failed to deliver batch");
-                    break;
-                }
+            // Wait for the first 2f nodes (or the configured stake) to send back an Ack. Then we consider
+            // the batch delivered and we send its digest to the primary (that will include it into the dag).
+            // This should reduce the amount of synching.
+            let threshold = self.threshold.stake(&self.committee, batch.len());
+            if ReliableSender::wait_for_quorum(handlers, self.stake, threshold)
+                .await
+                .is_some()
+            {
+                self.metrics.record_quorum_wait(start.elapsed());
+                self.tx_batch
+                    .send(batch)
+                    .await
+                    .expect("Failed to deliver batch");
             }
         }
     }
@@ -0,0 +1,137 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use crate::erasure::{self, BatchShard, ShardsAggregator};
+use crate::processor::SerializedBatchMessage;
+use crate::transaction_validator::TransactionValidator;
+use crate::worker::WorkerMessage;
+use bytes::Bytes;
+use config::{Committee, WorkerId};
+use crypto::{Digest, PublicKey};
+use log::{debug, warn};
+use lru::LruCache;
+use network::{SharedTransport, SimpleSender};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+#[cfg(test)]
+#[path = "tests/reconstructor_tests.rs"]
+pub mod reconstructor_tests;
+
+/// The number of batches whose shards we gather at once (the least recently updated ones are dropped: their
+/// batches are then synchronized on demand).
+const MAX_PENDING_BATCHES: usize = 1_000;
+
+/// Gathers the shards of the erasure-coded batches of the other workers, and hands each batch to the
+/// `Processor` once it can be reconstructed. We echo our own shard to the other workers, so that they all
+/// gather enough shards.
+pub struct Reconstructor {
+    /// Our public key.
+    name: PublicKey,
+    /// The id of this worker.
+    id: WorkerId,
+    /// The committee information.
+    committee: Committee,
+    /// The index of our shard.
+    index: usize,
+    /// Checks the transactions of the reconstructed batches.
+    validator: Arc<dyn TransactionValidator>,
+    /// The shards gathered so far, by commitment.
+    aggregators: LruCache<Digest, ShardsAggregator>,
+    /// Input channel to receive the shards.
+    rx_shards: Receiver<Vec<BatchShard>>,
+    /// Output channel to deliver the reconstructed batches to the `Processor`.
+    tx_processor: Sender<SerializedBatchMessage>,
+    /// A network sender to echo our shards.
+    network: SimpleSender,
+}
+
+impl Reconstructor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        validator: Arc<dyn TransactionValidator>,
+        transport: SharedTransport,
+        chunk_size: usize,
+        rx_shards: Receiver<Vec<BatchShard>>,
+        tx_processor: Sender<SerializedBatchMessage>,
+    ) {
+        tokio::spawn(async move {
+            let index = erasure::shard_index(&committee, &name)
+                .expect("Our public key is not in the committee");
+            Self {
+                name,
+                id,
+                committee,
+                index,
+                validator,
+                aggregators: LruCache::new(NonZeroUsize::new(MAX_PENDING_BATCHES).unwrap()),
+                rx_shards,
+                tx_processor,
+                network: SimpleSender::new()
+                    .with_transport(transport)
+                    .with_chunk_size(chunk_size),
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        while let Some(shards) = self.rx_shards.recv().await {
+            for shard in shards {
+                self.process(shard).await;
+            }
+        }
+    }
+
+    /// Gather a shard, echoing it if it is ours, and deliver the batch once reconstructed.
+    async fn process(&mut self, shard: BatchShard) {
+        debug!("Processing {:?}", shard);
+        if !shard.verify(&self.committee) {
+            warn!("Invalid shard {:?}", shard);
+            return;
+        }
+
+        let committee = &self.committee;
+        let aggregator = self
+            .aggregators
+            .get_or_insert_mut(shard.commitment(), || ShardsAggregator::new(committee));
+        let echo = shard.index == self.index
+            && shard.origin != self.name
+            && !aggregator.contains(shard.index);
+        let origin = shard.origin;
+        let message = echo.then(|| WorkerMessage::BatchShards(vec![shard.clone()]));
+        let batch = aggregator.append(shard);
+
+        if let Some(message) = message {
+            let addresses = self
+                .committee
+                .others_workers(&self.name, &self.id)
+                .into_iter()
+                .filter(|(name, _)| name != &origin)
+                .map(|(_, x)| x.worker_to_worker.advertise)
+                .collect();
+            let bytes = bincode::serialize(&message).expect("Failed to serialize shard");
+            self.network.broadcast(addresses, Bytes::from(bytes)).await;
+        }
+
+        // Drop the batches holding transactions breaking the rules of the application.
+        if let Some(batch) = batch {
+            if let Ok(WorkerMessage::Batch(transactions)) = bincode::deserialize(&batch) {
+                if let Err(e) = transactions
+                    .iter()
+                    .try_for_each(|x| self.validator.validate(x))
+                {
+                    warn!("Rejected batch: {}", e);
+                    return;
+                }
+                self.tx_processor
+                    .send(batch)
+                    .await
+                    .expect("Failed to send batch");
+            }
+        }
+    }
+}
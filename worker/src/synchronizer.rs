@@ -1,18 +1,26 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
+use crate::hasher;
+use crate::helper::BatchStreamReply;
+use crate::pending_batches::PendingBatches;
+use crate::processor::SerializedBatchMessage;
+use crate::transaction_validator::TransactionValidator;
 use crate::worker::{Round, WorkerMessage};
 use bytes::Bytes;
-use config::{Committee, WorkerId};
+use config::{Committee, Parameters, WorkerId};
 use crypto::{Digest, PublicKey};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
-use log::{debug, error};
-use network::SimpleSender;
+use log::{debug, error, warn};
+use network::{Address, CancelHandler, ReliableSender, SharedTransport};
 use primary::PrimaryWorkerMessage;
-use std::collections::HashMap;
+use rand::seq::SliceRandom as _;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use store::{Store, StoreError};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::time::{sleep, Duration, Instant};
+use tokio::sync::watch;
+use tokio::time::{sleep, timeout, Duration, Instant};
 
 #[cfg(test)]
 #[path = "tests/synchronizer_tests.rs"]
@@ -40,14 +48,22 @@ pub struct Synchronizer {
     sync_retry_nodes: usize,
     /// Input channel to receive the commands from the primary.
     rx_message: Receiver<PrimaryWorkerMessage>,
-    /// A network sender to send requests to the other workers.
-    network: SimpleSender,
+    /// Receives the parameters reloaded while we run (we apply the new sync retry delay).
+    rx_parameters: watch::Receiver<Parameters>,
+    /// Output channel to deliver the batches we receive to the `Processor`.
+    tx_processor: Sender<SerializedBatchMessage>,
+    /// Checks the transactions of the batches we receive.
+    validator: Arc<dyn TransactionValidator>,
+    /// A network sender to stream the missing batches from the other workers (one connection per worker).
+    network: ReliableSender,
     /// Loosely keep track of the primary's round number (only used for cleanup).
     round: Round,
     /// Keeps the digests (of batches) that are waiting to be processed by the primary. Their
     /// processing will resume when we get the missing batches in the store or we no longer need them.
     /// It also keeps the round number and a timestamp (`u128`) of each request we sent.
     pending: HashMap<Digest, (Round, Sender<()>, u128)>,
+    /// Keeps track of our own batches that are not yet sequenced.
+    pending_batches: PendingBatches,
 }
 
 impl Synchronizer {
@@ -60,19 +76,30 @@ impl Synchronizer {
         gc_depth: Round,
         sync_retry_delay: u64,
         sync_retry_nodes: usize,
+        transport: SharedTransport,
+        chunk_size: usize,
+        validator: Arc<dyn TransactionValidator>,
         rx_message: Receiver<PrimaryWorkerMessage>,
+        rx_parameters: watch::Receiver<Parameters>,
+        tx_processor: Sender<SerializedBatchMessage>,
     ) {
         tokio::spawn(async move {
             Self {
                 name,
                 id,
                 committee,
+                pending_batches: PendingBatches::new(store.clone()),
                 store,
                 gc_depth,
                 sync_retry_delay,
                 sync_retry_nodes,
                 rx_message,
-                network: SimpleSender::new(),
+                rx_parameters,
+                tx_processor,
+                validator,
+                network: ReliableSender::new()
+                    .with_transport(transport)
+                    .with_chunk_size(chunk_size),
                 round: Round::default(),
                 pending: HashMap::new(),
             }
@@ -97,9 +124,83 @@ impl Synchronizer {
         }
     }
 
+    /// Ask a worker for the batches it has among `digests`. It replies with the ones that fit in a single
+    /// reply: we then ask for the rest (see `deliver`), so that the worker streams the batches over a single
+    /// connection without ever getting more than one reply ahead of us.
+    async fn request(&mut self, address: Address, digests: Vec<Digest>) -> CancelHandler {
+        let message = WorkerMessage::BatchStreamRequest(digests, self.name);
+        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+        self.network.send(address, Bytes::from(serialized)).await
+    }
+
+    /// Helper function. It waits (up to `delay`) for the reply to a stream request.
+    async fn fetch(
+        handler: CancelHandler,
+        address: Address,
+        requested: Vec<Digest>,
+        delay: u64,
+    ) -> (Address, Vec<Digest>, Option<Bytes>) {
+        let reply = timeout(Duration::from_millis(delay), handler)
+            .await
+            .ok()
+            .and_then(Result::ok);
+        (address, requested, reply)
+    }
+
+    /// Deliver the valid batches of a stream reply to the `Processor`, and returns the requested digests
+    /// that are still missing (if the reply was not empty).
+    async fn deliver(&mut self, requested: Vec<Digest>, reply: &[u8]) -> Vec<Digest> {
+        let reply: BatchStreamReply = match bincode::deserialize(reply) {
+            Ok(reply) => reply,
+            Err(e) => {
+                warn!("Invalid stream reply: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut missing: HashSet<_> = requested.iter().cloned().collect();
+        let mut received = false;
+        for batch in reply.batches {
+            let digest = hasher::digest(&batch);
+            if !missing.remove(&digest) {
+                warn!("Received unexpected batch {}", digest);
+                continue;
+            }
+            match bincode::deserialize(&batch) {
+                Ok(WorkerMessage::Batch(transactions)) => {
+                    if let Err(e) = transactions
+                        .iter()
+                        .try_for_each(|x| self.validator.validate(x))
+                    {
+                        warn!("Rejected batch: {}", e);
+                        continue;
+                    }
+                }
+                _ => {
+                    warn!("Received invalid batch {}", digest);
+                    continue;
+                }
+            }
+            received = true;
+            self.tx_processor
+                .send(Bytes::from(batch))
+                .await
+                .expect("Failed to send batch");
+        }
+
+        match received {
+            true => requested
+                .into_iter()
+                .filter(|x| missing.contains(x))
+                .collect(),
+            false => Vec::new(),
+        }
+    }
+
     /// Main loop listening to the primary's messages.
     async fn run(&mut self) {
         let mut waiting = FuturesUnordered::new();
+        let mut fetching = FuturesUnordered::new();
 
         let timer = sleep(Duration::from_millis(TIMER_RESOLUTION));
         tokio::pin!(timer);
@@ -147,15 +248,16 @@ impl Synchronizer {
                         // Send sync request to a single node. If this fails, we will send it
                         // to other nodes when a timer times out.
                         let address = match self.committee.worker(&target, &self.id) {
-                            Ok(address) => address.worker_to_worker,
+                            Ok(address) => address.worker_to_worker.advertise,
                             Err(e) => {
                                 error!("The primary asked us to sync with an unknown node: {}", e);
                                 continue;
                             }
                         };
-                        let message = WorkerMessage::BatchRequest(missing, self.name);
-                        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
-                        self.network.send(address, Bytes::from(serialized)).await;
+                        if !missing.is_empty() {
+                            let handler = self.request(address.clone(), missing.clone()).await;
+                            fetching.push(Self::fetch(handler, address, missing, self.sync_retry_delay));
+                        }
                     },
                     PrimaryWorkerMessage::Cleanup(round) => {
                         // Keep track of the primary's round number.
@@ -173,6 +275,28 @@ impl Synchronizer {
                             }
                         }
                         self.pending.retain(|_, (r, _, _)| r > &mut gc_round);
+                    },
+                    PrimaryWorkerMessage::Reconfigure(_) | PrimaryWorkerMessage::Committed(_) => {
+                        // Epoch changes and committed batches are handled by the worker itself.
+                    }
+                    PrimaryWorkerMessage::Sequenced(digests) => {
+                        // Our batches are safe: we no longer need to re-send them after a crash.
+                        for digest in &digests {
+                            self.pending_batches.remove(digest).await;
+                        }
+                    }
+                },
+
+                // Keep streaming the batches we are still missing from the workers that replied.
+                Some((address, requested, reply)) = fetching.next() => {
+                    let missing = match reply {
+                        Some(reply) => self.deliver(requested, &reply).await,
+                        None => continue,
+                    };
+                    let missing: Vec<_> = missing.into_iter().filter(|x| self.pending.contains_key(x)).collect();
+                    if !missing.is_empty() {
+                        let handler = self.request(address.clone(), missing.clone()).await;
+                        fetching.push(Self::fetch(handler, address, missing, self.sync_retry_delay));
                     }
                 },
 
@@ -188,6 +312,10 @@ impl Synchronizer {
                     Err(e) => error!("{}", e)
                 },
 
+                Ok(()) = self.rx_parameters.changed() => {
+                    self.sync_retry_delay = self.rx_parameters.borrow().sync_retry_delay;
+                },
+
                 // Triggers on timer's expiration.
                 () = &mut timer => {
                     // We optimistically sent sync requests to a single node. If this timer triggers,
@@ -206,15 +334,17 @@ impl Synchronizer {
                         }
                     }
                     if !retry.is_empty() {
-                        let addresses = self.committee
+                        let mut addresses: Vec<_> = self.committee
                             .others_workers(&self.name, &self.id)
-                            .iter().map(|(_, address)| address.worker_to_worker)
+                            .into_iter()
+                            .map(|(_, address)| address.worker_to_worker.advertise)
                             .collect();
-                        let message = WorkerMessage::BatchRequest(retry, self.name);
-                        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
-                        self.network
-                            .lucky_broadcast(addresses, Bytes::from(serialized), self.sync_retry_nodes)
-                            .await;
+                        addresses.shuffle(&mut rand::thread_rng());
+                        addresses.truncate(self.sync_retry_nodes);
+                        for address in addresses {
+                            let handler = self.request(address.clone(), retry.clone()).await;
+                            fetching.push(Self::fetch(handler, address, retry.clone(), self.sync_retry_delay));
+                        }
                     }
 
                     // Reschedule the timer.
@@ -0,0 +1,53 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::sync::Arc;
+use tokio::time::{timeout, Duration};
+
+#[test]
+fn bound_pending_transactions() {
+    let admission = AdmissionControl::new(
+        /* max_transactions */ 2, /* max_bytes */ 100, /* client_rate_limit */ None,
+    );
+
+    // A transaction larger than the limit is admitted when nothing is pending.
+    assert!(admission.try_admit(150));
+    assert!(!admission.try_admit(10));
+    admission.release(1, 150);
+
+    // Then the number of pending transactions is bounded.
+    assert!(admission.try_admit(10));
+    assert!(admission.try_admit(10));
+    assert!(!admission.try_admit(10));
+
+    // Sealing a transaction makes room for another one.
+    admission.release(1, 10);
+    assert!(admission.try_admit(10));
+}
+
+#[tokio::test]
+async fn wait_for_room() {
+    let admission = Arc::new(AdmissionControl::new(1, 0, None));
+    admission.admit(10).await;
+
+    // The next transaction waits until the first one is released.
+    assert!(timeout(Duration::from_millis(100), admission.admit(10))
+        .await
+        .is_err());
+    let cloned = admission.clone();
+    let handle = tokio::spawn(async move { cloned.admit(10).await });
+    admission.release(1, 10);
+    assert!(timeout(Duration::from_millis(100), handle).await.is_ok());
+}
+
+#[test]
+fn client_quota() {
+    let limit = RateLimit { rate: 1, burst: 10 };
+    let admission = AdmissionControl::new(0, 0, Some(limit));
+    let client: IpAddr = "127.0.0.1".parse().unwrap();
+    let other: IpAddr = "127.0.0.2".parse().unwrap();
+
+    // The first transaction overdraws the quota of the client, but not the quota of the others.
+    assert!(admission.within_quota(client, 20));
+    assert!(!admission.within_quota(client, 10));
+    assert!(admission.within_quota(other, 10));
+}
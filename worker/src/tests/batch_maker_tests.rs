@@ -1,7 +1,10 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::transaction;
+use crate::common::{transaction, transport};
+use crate::worker::WorkerMessage;
+use std::sync::Arc;
 use tokio::sync::mpsc::channel;
+use tokio::time::timeout;
 
 #[tokio::test]
 async fn make_batch() {
@@ -11,11 +14,20 @@ async fn make_batch() {
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
-        /* max_batch_size */ 200,
-        /* max_batch_delay */ 1_000_000, // Ensure the timer is not triggered.
+        BatchSizer::fixed(200, 1_000_000), // Ensure the timer is not triggered.
+        /* queue */ None,
         rx_transaction,
+        /* rx_parameters */ watch::channel(Parameters::default()).1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        transport(),
+        /* chunk_size */ 0,
+        /* compressor */ None,
+        /* padder */ None,
+        BatchHasher::new(/* threads */ 1),
+        /* encoder */ None,
+        Arc::new(AdmissionControl::default()),
+        Arc::new(WorkerMetrics::default()),
     );
 
     // Send enough transactions to seal a batch.
@@ -39,11 +51,20 @@ async fn batch_timeout() {
 
     // Spawn a `BatchMaker` instance.
     BatchMaker::spawn(
-        /* max_batch_size */ 200,
-        /* max_batch_delay */ 50, // Ensure the timer is triggered.
+        BatchSizer::fixed(200, 50), // Ensure the timer is triggered.
+        /* queue */ None,
         rx_transaction,
+        /* rx_parameters */ watch::channel(Parameters::default()).1,
         tx_message,
         /* workers_addresses */ dummy_addresses,
+        transport(),
+        /* chunk_size */ 0,
+        /* compressor */ None,
+        /* padder */ None,
+        BatchHasher::new(/* threads */ 1),
+        /* encoder */ None,
+        Arc::new(AdmissionControl::default()),
+        Arc::new(WorkerMetrics::default()),
     );
 
     // Do not send enough transactions to seal a batch..
@@ -57,3 +78,168 @@ async fn batch_timeout() {
         _ => panic!("Unexpected message"),
     }
 }
+
+/// Returns a transaction whose priority is its first byte.
+#[tokio::test]
+async fn reload_batch_size() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_parameters, rx_parameters) = watch::channel(Parameters::default());
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        BatchSizer::fixed(1_000_000, 1_000_000), // Ensure the timer is not triggered.
+        /* queue */ None,
+        rx_transaction,
+        rx_parameters,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        transport(),
+        /* chunk_size */ 0,
+        /* compressor */ None,
+        /* padder */ None,
+        BatchHasher::new(/* threads */ 1),
+        /* encoder */ None,
+        Arc::new(AdmissionControl::default()),
+        Arc::new(WorkerMetrics::default()),
+    );
+
+    // The batch is far from full.
+    tx_transaction.send(transaction()).await.unwrap();
+    let delay = Duration::from_millis(100);
+    assert!(timeout(delay, rx_message.recv()).await.is_err());
+
+    // Reload a smaller batch size: the next transaction seals the batch.
+    let parameters = Parameters {
+        batch_size: 200,
+        max_batch_delay: 1_000_000,
+        ..Parameters::default()
+    };
+    tx_parameters.send(parameters).unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(batch) => assert_eq!(batch, vec![transaction(), transaction()]),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+fn transaction_with_priority(priority: u8) -> Transaction {
+    let mut transaction = transaction();
+    transaction[0] = priority;
+    transaction
+}
+
+#[tokio::test]
+async fn order_by_priority() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Queue transactions before the batch maker starts, so they are all pending when sealing.
+    for priority in vec![1, 3, 2].into_iter() {
+        tx_transaction
+            .send(transaction_with_priority(priority))
+            .await
+            .unwrap();
+    }
+
+    // Spawn a `BatchMaker` instance ordering the transactions by their first byte.
+    let priority: TransactionPriority = Arc::new(|x: &[u8]| x[0] as u64);
+    BatchMaker::spawn(
+        BatchSizer::fixed(200, 1_000_000), // Ensure the timer is not triggered.
+        Some(PriorityQueue::new(priority, /* capacity */ 0)),
+        rx_transaction,
+        /* rx_parameters */ watch::channel(Parameters::default()).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        transport(),
+        /* chunk_size */ 0,
+        /* compressor */ None,
+        /* padder */ None,
+        BatchHasher::new(/* threads */ 1),
+        /* encoder */ None,
+        Arc::new(AdmissionControl::default()),
+        Arc::new(WorkerMetrics::default()),
+    );
+
+    // Ensure the batch holds the two highest-priority transactions.
+    let expected_batch = vec![transaction_with_priority(3), transaction_with_priority(2)];
+    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn evict_low_priority() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_message, mut rx_message) = channel(1);
+    let dummy_addresses = vec![(PublicKey::default(), "127.0.0.1:0".parse().unwrap())];
+
+    // Spawn a `BatchMaker` instance holding at most two pending transactions.
+    let priority: TransactionPriority = Arc::new(|x: &[u8]| x[0] as u64);
+    BatchMaker::spawn(
+        BatchSizer::fixed(1_000, 50), // Ensure the timer is triggered.
+        Some(PriorityQueue::new(priority, /* capacity */ 2)),
+        rx_transaction,
+        /* rx_parameters */ watch::channel(Parameters::default()).1,
+        tx_message,
+        /* workers_addresses */ dummy_addresses,
+        transport(),
+        /* chunk_size */ 0,
+        /* compressor */ None,
+        /* padder */ None,
+        BatchHasher::new(/* threads */ 1),
+        /* encoder */ None,
+        Arc::new(AdmissionControl::default()),
+        Arc::new(WorkerMetrics::default()),
+    );
+
+    for priority in vec![1, 3, 2].into_iter() {
+        tx_transaction
+            .send(transaction_with_priority(priority))
+            .await
+            .unwrap();
+    }
+
+    // Ensure the lowest-priority transaction was evicted.
+    let expected_batch = vec![transaction_with_priority(3), transaction_with_priority(2)];
+    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        WorkerMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[test]
+fn adapt_batch_size() {
+    let mut sizer = BatchSizer::new(
+        /* min_size */ 100, /* max_size */ 1_000, /* min_delay */ 10,
+        /* max_delay */ 100,
+    );
+
+    // Until we observe the load, batches are small and sealed quickly.
+    assert_eq!(sizer.batch_size(), 100);
+    assert_eq!(sizer.batch_delay(), 10);
+
+    // At 5 bytes per ms, half a maximum-size batch arrives within the maximum delay.
+    sizer.update(500, Duration::from_millis(100));
+    assert_eq!(sizer.batch_size(), 550);
+    assert_eq!(sizer.batch_delay(), 55);
+
+    // Under heavy load, batches reach the maximum size (and delay).
+    for _ in 0..50 {
+        sizer.update(1_000, Duration::from_millis(10));
+    }
+    assert_eq!(sizer.batch_size(), 1_000);
+    assert_eq!(sizer.batch_delay(), 100);
+
+    // A fixed sizer never adapts.
+    let mut sizer = BatchSizer::fixed(200, 50);
+    sizer.update(1_000, Duration::from_millis(10));
+    assert_eq!(sizer.batch_size(), 200);
+    assert_eq!(sizer.batch_delay(), 50);
+}
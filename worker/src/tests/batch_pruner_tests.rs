@@ -0,0 +1,48 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn prune_batches() {
+    let (tx_cleanup, rx_cleanup) = channel(1);
+
+    // Create a new test store.
+    let path = ".db_test_prune_batches";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let mut index = BatchIndex::new(store.clone());
+
+    // Spawn a `BatchPruner` keeping 2 rounds and the last 10 bytes of older batches.
+    BatchPruner::spawn(
+        store.clone(),
+        index.clone(),
+        /* depth */ 2,
+        /* retention_bytes */ 10,
+        rx_cleanup,
+    );
+
+    // Store three batches at round 0 (the first one is ours and not yet sequenced).
+    let digests: Vec<_> = (0..4).map(|i| Digest([i; 32])).collect();
+    for digest in &digests[..3] {
+        store.write(digest.to_vec(), vec![0; 10]).await;
+        index.insert(digest, 10).await;
+    }
+    PendingBatches::new(store.clone()).insert(&digests[0]).await;
+
+    // Store a batch at round 5.
+    tx_cleanup.send(5).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    store.write(digests[3].to_vec(), vec![0; 10]).await;
+    index.insert(&digests[3], 10).await;
+
+    // Prune the batches below round 4: only the second batch is deleted. The first one is pending and
+    // the third one fits in the retained bytes.
+    tx_cleanup.send(6).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+    for (i, digest) in digests.iter().enumerate() {
+        let stored = store.read(digest.to_vec()).await.unwrap();
+        assert_eq!(stored.is_none(), i == 1, "batch {}", i);
+    }
+}
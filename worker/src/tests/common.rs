@@ -8,11 +8,11 @@ use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
+use network::{Address, MemoryTransport, SharedTransport};
 use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use std::convert::TryInto as _;
-use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use std::sync::{Arc, OnceLock};
 use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
@@ -38,7 +38,12 @@ pub fn committee() -> Committee {
                     WorkerAddresses {
                         primary_to_worker: format!("127.0.0.1:{}", 300 + i).parse().unwrap(),
                         transactions: format!("127.0.0.1:{}", 400 + i).parse().unwrap(),
+                        additional_transactions: Vec::new(),
                         worker_to_worker: format!("127.0.0.1:{}", 500 + i).parse().unwrap(),
+                        grpc: None,
+                        http: None,
+                        executor: None,
+                        metrics: None,
                     },
                 )]
                 .iter()
@@ -50,10 +55,14 @@ pub fn committee() -> Committee {
                         stake: 1,
                         primary,
                         workers,
+                        bls_public_key: None,
+                        bls_proof_of_possession: None,
                     },
                 )
             })
             .collect(),
+        epoch: 0,
+        threshold_key: None,
     }
 }
 
@@ -109,9 +118,18 @@ pub fn batch_digest() -> Digest {
 }
 
 // Fixture
-pub fn listener(address: SocketAddr, expected: Option<Bytes>) -> JoinHandle<()> {
+pub fn transport() -> SharedTransport {
+    // All the tests of the crate share the same in-memory network (they each use their own ports).
+    static TRANSPORT: OnceLock<SharedTransport> = OnceLock::new();
+    TRANSPORT
+        .get_or_init(|| Arc::new(MemoryTransport::new()))
+        .clone()
+}
+
+// Fixture
+pub fn listener(address: Address, expected: Option<Bytes>) -> JoinHandle<()> {
     tokio::spawn(async move {
-        let listener = TcpListener::bind(&address).await.unwrap();
+        let mut listener = transport().listen(&address.listen_address()).await.unwrap();
         let (socket, _) = listener.accept().await.unwrap();
         let transport = Framed::new(socket, LengthDelimitedCodec::new());
         let (mut writer, mut reader) = transport.split();
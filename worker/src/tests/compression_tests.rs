@@ -0,0 +1,21 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::serialized_batch;
+
+#[test]
+fn compress_batch() {
+    let compressor = BatchCompressor::new(/* level */ 3);
+    assert!(compressor.stats().ratio().is_none());
+
+    // The receiver recovers the exact serialized batch.
+    let compressed = compressor.compress(&serialized_batch());
+    match bincode::deserialize(&compressed).unwrap() {
+        WorkerMessage::CompressedBatch(data) => {
+            assert_eq!(decompress(&data).unwrap(), serialized_batch())
+        }
+        _ => panic!("Unexpected message"),
+    }
+
+    // The test batch (two transactions of zeros) compresses well.
+    assert!(compressor.stats().ratio().unwrap() > 1.0);
+}
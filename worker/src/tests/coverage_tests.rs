@@ -0,0 +1,44 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch, batch_digest, transaction};
+use tokio::sync::mpsc::channel;
+
+#[test]
+fn cover_committed_batches() {
+    let coverage = Coverage::new(NonZeroUsize::new(10).unwrap());
+
+    // The transactions of a batch are covered once it is committed.
+    coverage.record(batch_digest(), &batch());
+    assert!(!coverage.is_covered(&transaction()));
+    coverage.mark_committed(batch_digest());
+    assert!(coverage.is_covered(&transaction()));
+
+    // The batch may be committed before we receive it.
+    let other = vec![1u8; 10];
+    let digest = Digest([1; 32]);
+    coverage.mark_committed(digest.clone());
+    coverage.record(digest, std::slice::from_ref(&other));
+    assert!(coverage.is_covered(&other));
+}
+
+#[tokio::test]
+async fn filter_covered_transactions() {
+    let coverage = Coverage::new(NonZeroUsize::new(10).unwrap());
+    coverage.record(batch_digest(), &batch());
+    coverage.mark_committed(batch_digest());
+
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    CoverageFilter::spawn(
+        coverage,
+        Arc::new(AdmissionControl::default()),
+        rx_transaction,
+        tx_batch_maker,
+    );
+
+    // Only the transaction that is not covered reaches the batch maker.
+    let other = vec![1u8; 10];
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(other.clone()).await.unwrap();
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), other);
+}
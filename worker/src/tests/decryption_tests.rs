@@ -0,0 +1,134 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, listener, transport};
+use crate::transaction_validator::AcceptAllTransactions;
+use crypto::generate_threshold_keys;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use rand::rngs::StdRng;
+use rand::SeedableRng as _;
+use std::convert::TryInto as _;
+use std::fs;
+use tokio::time::{sleep, Duration};
+
+#[test]
+fn accept_only_ciphertexts() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (key, _) = generate_threshold_keys(4, 2, &mut rng);
+    let ciphertext = key.encrypt(b"transaction", &mut rng).unwrap();
+
+    let validator = Ciphertexts::new(Arc::new(AcceptAllTransactions));
+    assert!(validator
+        .validate(&bincode::serialize(&ciphertext).unwrap())
+        .is_ok());
+    assert!(validator.validate(b"transaction").is_err());
+}
+
+#[tokio::test]
+async fn decrypt_committed_batch() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let (key, mut shares) = generate_threshold_keys(4, 2, &mut rng);
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let mut committee = committee_with_base_port(12_700);
+    committee.threshold_key = Some(key.clone());
+
+    // Store a batch of ciphertexts.
+    let plaintexts = vec![b"first".to_vec(), b"second".to_vec()];
+    let ciphertexts: Vec<_> = plaintexts
+        .iter()
+        .map(|x| key.encrypt(x, &mut rng).unwrap())
+        .collect();
+    let transactions = ciphertexts
+        .iter()
+        .map(|x| bincode::serialize(x).unwrap())
+        .collect();
+    let serialized = bincode::serialize(&WorkerMessage::Batch(transactions)).unwrap();
+    let digest = Digest(Sha512::digest(&serialized)[..32].try_into().unwrap());
+
+    let path = ".db_test_decrypt_committed_batch";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store.write(digest.to_vec(), serialized).await;
+
+    // Spawn a listener for the other workers, receiving our decryption shares.
+    let handles: Vec<_> = committee
+        .others_workers(&name, &id)
+        .into_iter()
+        .map(|(_, addresses)| listener(addresses.worker_to_worker.advertise, None))
+        .collect();
+
+    // Spawn a `Decryptor` holding the last share of the key.
+    let (tx_committed, rx_committed) = channel(1);
+    let (tx_shares, rx_shares) = channel(1);
+    Decryptor::spawn(
+        name,
+        id,
+        committee.clone(),
+        key,
+        shares.pop().unwrap(),
+        store.clone(),
+        transport(),
+        /* chunk_size */ 0,
+        rx_committed,
+        rx_shares,
+    );
+
+    // The shares of another authority are not used before the batch is committed: its worker should send
+    // them again.
+    let other = shares.pop().unwrap();
+    let author = *committee
+        .authorities
+        .keys()
+        .nth(other.index as usize - 1)
+        .unwrap();
+    let batch_shares = BatchDecryptionShares {
+        author,
+        batch: digest.clone(),
+        shares: ciphertexts
+            .iter()
+            .map(|x| other.decryption_share(x, &mut rng).unwrap())
+            .collect(),
+    };
+    let (tx_reply, rx_reply) = oneshot::channel();
+    tx_shares
+        .send((batch_shares.clone(), tx_reply))
+        .await
+        .unwrap();
+    assert!(!rx_reply.await.unwrap());
+
+    // Shares claiming to come from the authority of another key share are never used.
+    let impostor = committee
+        .authorities
+        .keys()
+        .find(|x| **x != name && **x != author)
+        .unwrap();
+    let forged = BatchDecryptionShares {
+        author: *impostor,
+        ..batch_shares.clone()
+    };
+
+    // Once committed, we release our shares and decrypt the batch with those of the other authority.
+    tx_committed.send(vec![digest.clone()]).await.unwrap();
+    for handle in handles {
+        assert!(handle.await.is_ok());
+    }
+    for shares in [forged, batch_shares] {
+        loop {
+            let (tx_reply, rx_reply) = oneshot::channel();
+            tx_shares.send((shares.clone(), tx_reply)).await.unwrap();
+            if rx_reply.await.unwrap() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+    let decrypted = store
+        .notify_read(decrypted_batch_key(&digest))
+        .await
+        .unwrap();
+    match bincode::deserialize(&decrypted).unwrap() {
+        WorkerMessage::Batch(transactions) => assert_eq!(transactions, plaintexts),
+        _ => panic!("Unexpected message"),
+    }
+}
@@ -0,0 +1,30 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn drop_duplicate_transactions() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    Deduplicator::spawn(
+        NonZeroUsize::new(2).unwrap(),
+        Arc::new(AdmissionControl::default()),
+        rx_transaction,
+        tx_batch_maker,
+    );
+
+    // Send a transaction twice, then two other transactions (evicting the first one).
+    let other = |x: u8| vec![x; 10];
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(other(1)).await.unwrap();
+    tx_transaction.send(other(2)).await.unwrap();
+    tx_transaction.send(transaction()).await.unwrap();
+
+    // The duplicate is dropped, but the transaction is accepted again once forgotten.
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), transaction());
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), other(1));
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), other(2));
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), transaction());
+}
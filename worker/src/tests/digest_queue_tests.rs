@@ -0,0 +1,24 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::fs;
+
+#[tokio::test]
+async fn reopen_queue() {
+    // Create a new test store.
+    let path = ".db_test_digest_queue";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Queue a few messages, and acknowledge one of them.
+    let (mut queue, messages) = DigestQueue::open(store.clone()).await.unwrap();
+    assert!(messages.is_empty());
+    for i in 0..3 {
+        assert_eq!(queue.push(&vec![i; 10]).await, i as u64);
+    }
+    queue.remove(1).await;
+
+    // The messages not yet acknowledged are still queued once we restart (in order).
+    let (mut queue, messages) = DigestQueue::open(store).await.unwrap();
+    assert_eq!(messages, vec![(0, vec![0; 10]), (2, vec![2; 10])]);
+    assert_eq!(queue.push(&vec![3; 10]).await, 3);
+}
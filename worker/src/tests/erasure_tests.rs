@@ -0,0 +1,32 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee, keys, serialized_batch};
+
+#[test]
+fn reconstruct_from_quorum_of_shards() {
+    let (name, _) = keys().pop().unwrap();
+    let encoder = ShardEncoder::new(name, committee(), /* threshold */ 1).unwrap();
+    let batch = serialized_batch();
+    let shards = encoder.encode(&batch);
+    assert_eq!(shards.len(), 4);
+    assert!(shards.iter().all(|x| x.verify(&committee())));
+
+    // Any 2f+1 shards reconstruct the batch.
+    let mut aggregator = ShardsAggregator::new(&committee());
+    assert!(aggregator.append(shards[3].clone()).is_none());
+    assert!(aggregator.append(shards[1].clone()).is_none());
+    let reconstructed = aggregator.append(shards[0].clone()).unwrap();
+    assert_eq!(reconstructed, batch);
+
+    // The batch is only delivered once.
+    assert!(aggregator.append(shards[2].clone()).is_none());
+}
+
+#[test]
+fn reject_tampered_shard() {
+    let (name, _) = keys().pop().unwrap();
+    let encoder = ShardEncoder::new(name, committee(), /* threshold */ 1).unwrap();
+    let mut shard = encoder.encode(&serialized_batch()).pop().unwrap();
+    shard.data[0] ^= 1;
+    assert!(!shard.verify(&committee()));
+}
@@ -0,0 +1,100 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch, batch_digest, serialized_batch, transaction};
+use crate::transaction_validator::TransactionValidator;
+use proto::batches_client::BatchesClient;
+use proto::transactions_client::TransactionsClient;
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+use tonic::transport::Endpoint;
+use tonic::Code;
+
+/// Rejects the empty transactions.
+struct RejectEmpty;
+
+impl TransactionValidator for RejectEmpty {
+    fn validate(&self, transaction: &[u8]) -> Result<(), String> {
+        match transaction.is_empty() {
+            true => Err("Empty transaction".to_string()),
+            false => Ok(()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn submit_transactions() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let address: SocketAddr = "127.0.0.1:12000".parse().unwrap();
+    TransactionsService::spawn(
+        address,
+        Arc::new(RejectEmpty),
+        Arc::new(AdmissionControl::default()),
+        tx_batch_maker,
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    let channel = Endpoint::from_shared(format!("http://{}", address))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = TransactionsClient::new(channel);
+    let message = |transaction| proto::Transaction { transaction };
+
+    // Submit a single transaction.
+    client
+        .submit_transaction(message(transaction()))
+        .await
+        .unwrap();
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), transaction());
+
+    // Submit a stream of transactions.
+    let stream = futures::stream::iter(vec![message(vec![1; 10]), message(vec![2; 10])]);
+    client.submit_transaction_stream(stream).await.unwrap();
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), vec![1; 10]);
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), vec![2; 10]);
+
+    // Invalid transactions are rejected.
+    let status = client
+        .submit_transaction(message(Vec::new()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn get_batch() {
+    // Create a new test store holding a batch.
+    let path = ".db_test_get_batch";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+
+    let address: SocketAddr = "127.0.0.1:12050".parse().unwrap();
+    BatchesService::spawn(address, store);
+    sleep(Duration::from_millis(100)).await;
+
+    let channel = Endpoint::from_shared(format!("http://{}", address))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = BatchesClient::new(channel);
+
+    // Get the transactions of a stored batch.
+    let request = proto::BatchDigest {
+        digest: batch_digest().to_vec(),
+    };
+    let reply = client.get_batch(request).await.unwrap().into_inner();
+    assert_eq!(reply.transactions, batch());
+
+    // Unknown batches are reported.
+    let request = proto::BatchDigest {
+        digest: vec![0; 32],
+    };
+    let status = client.get_batch(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
@@ -0,0 +1,15 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{batch, batch_digest, serialized_batch};
+
+#[tokio::test]
+async fn serialize_and_hash() {
+    let hasher = BatchHasher::new(/* threads */ 2);
+
+    let (digest, serialized) = hasher.serialize(batch()).await;
+    assert_eq!(digest, batch_digest());
+    assert_eq!(serialized, serialized_batch());
+
+    let (digest, _) = hasher.hash(serialized).await;
+    assert_eq!(digest, batch_digest());
+}
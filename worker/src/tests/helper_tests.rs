@@ -1,7 +1,10 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener, serialized_batch};
+use crate::common::{
+    batch_digest, committee_with_base_port, keys, listener, serialized_batch, transport,
+};
 use std::fs;
+use std::sync::Arc;
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -22,17 +25,73 @@ async fn batch_reply() {
         .await;
 
     // Spawn an `Helper` instance.
-    Helper::spawn(id, committee.clone(), store, rx_request);
+    Helper::spawn(
+        id,
+        committee.clone(),
+        store,
+        transport(),
+        /* chunk_size */ 0,
+        /* rate_limit */ None,
+        Arc::new(WorkerMetrics::default()),
+        rx_request,
+    );
 
     // Spawn a listener to receive the batch reply.
-    let address = committee.worker(&requestor, &id).unwrap().worker_to_worker;
+    let address = committee
+        .worker(&requestor, &id)
+        .unwrap()
+        .worker_to_worker
+        .advertise;
     let expected = Bytes::from(serialized_batch());
     let handle = listener(address, Some(expected));
 
     // Send a batch request.
     let digests = vec![batch_digest()];
-    tx_request.send((digests, requestor)).await.unwrap();
+    tx_request
+        .send(HelperRequest::Batches(digests, requestor))
+        .await
+        .unwrap();
 
     // Ensure the requestor received the batch (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn stream_reply() {
+    let (tx_request, rx_request) = channel(1);
+    let (requestor, _) = keys().pop().unwrap();
+
+    // Create a new test store.
+    let path = ".db_test_stream_reply";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Add a batch to the store.
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+
+    // Spawn an `Helper` instance.
+    Helper::spawn(
+        /* id */ 0,
+        committee_with_base_port(0),
+        store,
+        transport(),
+        /* chunk_size */ 0,
+        /* rate_limit */ None,
+        Arc::new(WorkerMetrics::default()),
+        rx_request,
+    );
+
+    // Request the batch along with one we do not have.
+    let (sender, receiver) = oneshot::channel();
+    let digests = vec![Digest([1; 32]), batch_digest()];
+    tx_request
+        .send(HelperRequest::Stream(digests, requestor, sender))
+        .await
+        .unwrap();
+
+    // Ensure the reply only carries the batch we have.
+    let reply: BatchStreamReply = bincode::deserialize(&receiver.await.unwrap()).unwrap();
+    assert_eq!(reply.batches, vec![serialized_batch()]);
+}
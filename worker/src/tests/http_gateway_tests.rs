@@ -0,0 +1,49 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::transaction_validator::AcceptAllTransactions;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+/// Send a POST request to the gateway and return the status line of the response.
+async fn post(address: &SocketAddr, body: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "POST /transactions HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        address,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response.lines().next().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn submit_transactions() {
+    let (tx_batch_maker, mut rx_batch_maker) = channel(10);
+    let address: SocketAddr = "127.0.0.1:12100".parse().unwrap();
+    HttpGateway::spawn(
+        address,
+        Arc::new(AcceptAllTransactions),
+        Arc::new(AdmissionControl::default()),
+        tx_batch_maker,
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    // Submit a base64 transaction.
+    let status = post(&address, r#"{"transaction": "AQID"}"#).await;
+    assert!(status.starts_with("HTTP/1.1 202"), "{}", status);
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), vec![1, 2, 3]);
+
+    // Submit a hex transaction.
+    let status = post(&address, r#"{"transaction": "0a0b", "encoding": "hex"}"#).await;
+    assert!(status.starts_with("HTTP/1.1 202"), "{}", status);
+    assert_eq!(rx_batch_maker.recv().await.unwrap(), vec![10, 11]);
+
+    // Transactions that cannot be decoded are rejected.
+    let status = post(&address, r#"{"transaction": "xyz", "encoding": "hex"}"#).await;
+    assert!(status.starts_with("HTTP/1.1 400"), "{}", status);
+}
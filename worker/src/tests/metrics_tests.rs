@@ -0,0 +1,49 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+#[test]
+fn encode_metrics() {
+    let metrics = WorkerMetrics::default();
+    metrics.transactions.fetch_add(3, Ordering::Relaxed);
+    metrics.batches.fetch_add(1, Ordering::Relaxed);
+    metrics.record_quorum_wait(Duration::from_millis(1_500));
+
+    let admission = AdmissionControl::default();
+    assert!(admission.try_admit(10));
+
+    let encoded = metrics.encode(&admission);
+    assert!(encoded.contains("worker_transactions_total 3\n"));
+    assert!(encoded.contains("worker_batches_total 1\n"));
+    assert!(encoded.contains("worker_quorum_wait_seconds_sum 1.5\n"));
+    assert!(encoded.contains("worker_quorum_wait_seconds_count 1\n"));
+    assert!(encoded.contains("worker_pending_transactions 1\n"));
+    assert!(encoded.contains("worker_pending_bytes 10\n"));
+}
+
+#[tokio::test]
+async fn serve_metrics() {
+    let address: SocketAddr = "127.0.0.1:12600".parse().unwrap();
+    let metrics = Arc::new(WorkerMetrics::default());
+    metrics.sync_requests.fetch_add(2, Ordering::Relaxed);
+    MetricsServer::spawn(
+        address,
+        metrics,
+        Arc::new(AdmissionControl::default()),
+        Arc::new(DeliveryMetrics::default()),
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "GET /metrics HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        address
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("worker_sync_requests_total 2\n"));
+}
@@ -0,0 +1,25 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::serialized_batch;
+
+#[test]
+fn pad_batch() {
+    let padder = BatchPadder::new(vec![1_000, 500]).unwrap();
+    assert!(padder.stats().overhead().is_none());
+
+    // The batch fills the smallest bucket it fits in, and the receiver recovers the exact batch.
+    let padded = padder.pad(&serialized_batch());
+    assert_eq!(padded.len(), 500);
+    match bincode::deserialize(&padded).unwrap() {
+        WorkerMessage::PaddedBatch(batch, _) => assert_eq!(batch, serialized_batch()),
+        _ => panic!("Unexpected message"),
+    }
+    assert!(padder.stats().overhead().unwrap() > 1.0);
+}
+
+#[test]
+fn pad_beyond_largest_bucket() {
+    let padder = BatchPadder::new(vec![100]).unwrap();
+    let padded = padder.pad(&serialized_batch());
+    assert_eq!(padded.len(), 300);
+}
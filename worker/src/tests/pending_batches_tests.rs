@@ -0,0 +1,25 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use std::fs;
+
+#[tokio::test]
+async fn insert_and_remove() {
+    // Create a new test store.
+    let path = ".db_test_pending_batches";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+    let mut pending = PendingBatches::new(store.clone());
+
+    // The markers do not interfere with the batches (stored under their digest).
+    let digests: Vec<_> = (0..3).map(|i| Digest([i; 32])).collect();
+    store.write(digests[0].to_vec(), vec![1]).await;
+    for digest in &digests {
+        pending.insert(digest).await;
+    }
+    assert_eq!(pending.read_all().await.unwrap(), digests);
+
+    // Sequenced batches are no longer pending.
+    pending.remove(&digests[1]).await;
+    let expected = vec![digests[0].clone(), digests[2].clone()];
+    assert_eq!(pending.read_all().await.unwrap(), expected);
+}
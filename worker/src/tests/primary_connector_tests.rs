@@ -0,0 +1,68 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{listener, transport};
+use std::fs;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+#[tokio::test]
+async fn deliver_once_primary_is_up() {
+    // Create a new test store.
+    let path = ".db_test_deliver_once_primary_is_up";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    let (tx_digest, rx_digest) = channel(1);
+    let address: Address = "127.0.0.1:12300".parse().unwrap();
+    let metrics = Arc::new(DeliveryMetrics::default());
+    PrimaryConnector::spawn(
+        address.clone(),
+        transport(),
+        store,
+        metrics.clone(),
+        rx_digest,
+    );
+
+    // Send a digest while the primary is unreachable.
+    let message = vec![1u8; 10];
+    tx_digest.send(message.clone()).await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(metrics.pending(), 1);
+
+    // The digest is re-transmitted once the primary is up.
+    let handle = listener(address, Some(Bytes::from(message)));
+    assert!(handle.await.is_ok());
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(metrics.delivered.load(Ordering::Relaxed), 1);
+    assert_eq!(metrics.pending(), 0);
+    assert!(metrics.network.peers()[0].1.connection_failures > 0);
+}
+
+#[tokio::test]
+async fn resend_queued_digests() {
+    // Create a new test store holding a digest the primary did not acknowledge before we crashed.
+    let path = ".db_test_resend_queued_digests";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let message = vec![2u8; 10];
+    let (mut queue, _) = DigestQueue::open(store.clone()).await.unwrap();
+    queue.push(&message).await;
+
+    // The digest is sent to the primary once we restart.
+    let address: Address = "127.0.0.1:12310".parse().unwrap();
+    let handle = listener(address.clone(), Some(Bytes::from(message)));
+    let (_tx_digest, rx_digest) = channel(1);
+    PrimaryConnector::spawn(
+        address,
+        transport(),
+        store.clone(),
+        Arc::new(DeliveryMetrics::default()),
+        rx_digest,
+    );
+    assert!(handle.await.is_ok());
+
+    // And forgotten once acknowledged.
+    sleep(Duration::from_millis(100)).await;
+    let (_, queued) = DigestQueue::open(store).await.unwrap();
+    assert!(queued.is_empty());
+}
@@ -1,6 +1,6 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::batch;
+use crate::common::{batch, batch_digest};
 use crate::worker::WorkerMessage;
 use std::fs;
 use tokio::sync::mpsc::channel;
@@ -23,20 +23,19 @@ async fn hash_and_store() {
         rx_batch,
         tx_digest,
         /* own_batch */ true,
+        /* index */ None,
+        BatchHasher::new(/* threads */ 1),
+        /* coverage */ None,
     );
 
     // Send a batch to the `Processor`.
     let message = WorkerMessage::Batch(batch());
-    let serialized = bincode::serialize(&message).unwrap();
+    let serialized = Bytes::from(bincode::serialize(&message).unwrap());
     tx_batch.send(serialized.clone()).await.unwrap();
 
     // Ensure the `Processor` outputs the batch's digest.
     let output = rx_digest.recv().await.unwrap();
-    let digest = Digest(
-        Sha512::digest(&serialized).as_slice()[..32]
-            .try_into()
-            .unwrap(),
-    );
+    let digest = batch_digest();
     let expected = bincode::serialize(&WorkerPrimaryMessage::OurBatch(digest.clone(), id)).unwrap();
     assert_eq!(output, expected);
 
@@ -1,11 +1,12 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch, committee_with_base_port, keys, listener};
+use crate::common::{batch, committee_with_base_port, keys, listener, transport};
 use crate::worker::WorkerMessage;
 use bytes::Bytes;
 use futures::future::try_join_all;
 use network::ReliableSender;
 use tokio::sync::mpsc::channel;
+use tokio::time::{timeout, Duration};
 
 #[tokio::test]
 async fn wait_for_quorum() {
@@ -15,28 +16,37 @@ async fn wait_for_quorum() {
     let committee = committee_with_base_port(7_000);
 
     // Spawn a `QuorumWaiter` instance.
-    QuorumWaiter::spawn(committee.clone(), /* stake */ 1, rx_message, tx_batch);
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        DeliveryThreshold::Quorum,
+        rx_message,
+        tx_batch,
+        Arc::new(WorkerMetrics::default()),
+    );
 
     // Make a batch.
     let message = WorkerMessage::Batch(batch());
-    let serialized = bincode::serialize(&message).unwrap();
-    let expected = Bytes::from(serialized.clone());
+    let serialized = Bytes::from(bincode::serialize(&message).unwrap());
+    let expected = serialized.clone();
 
     // Spawn enough listeners to acknowledge our batches.
     let mut names = Vec::new();
     let mut addresses = Vec::new();
     let mut listener_handles = Vec::new();
     for (name, address) in committee.others_workers(&myself, /* id */ &0) {
-        let address = address.worker_to_worker;
-        let handle = listener(address, Some(expected.clone()));
+        let address = address.worker_to_worker.advertise;
+        let handle = listener(address.clone(), Some(expected.clone()));
         names.push(name);
         addresses.push(address);
         listener_handles.push(handle);
     }
 
     // Broadcast the batch through the network.
-    let bytes = Bytes::from(serialized.clone());
-    let handlers = ReliableSender::new().broadcast(addresses, bytes).await;
+    let handlers = ReliableSender::new()
+        .with_transport(transport())
+        .broadcast(addresses, serialized.clone())
+        .await;
 
     // Forward the batch along with the handlers to the `QuorumWaiter`.
     let message = QuorumWaiterMessage {
@@ -52,3 +62,56 @@ async fn wait_for_quorum() {
     // Ensure the other listeners correctly received the batch.
     assert!(try_join_all(listener_handles).await.is_ok());
 }
+
+#[tokio::test]
+async fn wait_for_all() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) = channel(1);
+    let (myself, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(12_200);
+
+    // Spawn a `QuorumWaiter` instance waiting for the whole stake.
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        DeliveryThreshold::All,
+        rx_message,
+        tx_batch,
+        Arc::new(WorkerMetrics::default()),
+    );
+
+    // Make a batch.
+    let message = WorkerMessage::Batch(batch());
+    let serialized = Bytes::from(bincode::serialize(&message).unwrap());
+
+    // Spawn listeners for all but one of the other workers.
+    let (names, addresses): (Vec<_>, Vec<_>) = committee
+        .others_workers(&myself, /* id */ &0)
+        .into_iter()
+        .map(|(name, address)| (name, address.worker_to_worker.advertise))
+        .unzip();
+    let (last, others) = addresses.split_last().unwrap();
+    for address in others {
+        listener(address.clone(), Some(serialized.clone()));
+    }
+
+    // Broadcast the batch through the network and forward it to the `QuorumWaiter`.
+    let handlers = ReliableSender::new()
+        .with_transport(transport())
+        .broadcast(addresses.clone(), serialized.clone())
+        .await;
+    let message = QuorumWaiterMessage {
+        batch: serialized.clone(),
+        handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+    };
+    tx_message.send(message).await.unwrap();
+
+    // A quorum of acknowledgements is not enough.
+    let result = timeout(Duration::from_millis(500), rx_batch.recv()).await;
+    assert!(result.is_err());
+
+    // The batch is delivered once the last worker acknowledges it.
+    listener(last.clone(), Some(serialized.clone()));
+    let output = rx_batch.recv().await.unwrap();
+    assert_eq!(output, serialized);
+}
@@ -0,0 +1,51 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::{committee_with_base_port, keys, listener, serialized_batch, transport};
+use crate::erasure::ShardEncoder;
+use crate::transaction_validator::AcceptAllTransactions;
+use tokio::sync::mpsc::channel;
+
+#[tokio::test]
+async fn reconstruct_and_echo() {
+    let (tx_shards, rx_shards) = channel(10);
+    let (tx_processor, mut rx_processor) = channel(10);
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (origin, _) = keys.pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(12_400);
+
+    // Spawn a listener for the workers that should receive our echo (all but the origin).
+    let handles: Vec<_> = committee
+        .others_workers(&name, &id)
+        .into_iter()
+        .filter(|(x, _)| x != &origin)
+        .map(|(_, addresses)| listener(addresses.worker_to_worker.advertise, None))
+        .collect();
+
+    Reconstructor::spawn(
+        name,
+        id,
+        committee.clone(),
+        Arc::new(AcceptAllTransactions),
+        transport(),
+        /* chunk_size */ 0,
+        rx_shards,
+        tx_processor,
+    );
+
+    // Send our shard and the shard of the origin, then the echo of a third worker.
+    let encoder = ShardEncoder::new(origin, committee.clone(), /* threshold */ 1).unwrap();
+    let batch = serialized_batch();
+    let mut shards = encoder.encode(&batch);
+    let index = |x| erasure::shard_index(&committee, x).unwrap();
+    let ours = shards[index(&name)].clone();
+    let theirs = shards[index(&origin)].clone();
+    shards.retain(|x| x.index != ours.index && x.index != theirs.index);
+    tx_shards.send(vec![ours, theirs]).await.unwrap();
+    tx_shards.send(vec![shards.pop().unwrap()]).await.unwrap();
+
+    // The batch is reconstructed, and our shard echoed.
+    assert_eq!(rx_processor.recv().await.unwrap(), batch);
+    assert!(futures::future::try_join_all(handles).await.is_ok());
+}
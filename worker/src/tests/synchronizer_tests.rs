@@ -1,12 +1,33 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener};
+use crate::common::{batch_digest, committee_with_base_port, keys, serialized_batch, transport};
+use crate::transaction_validator::AcceptAllTransactions;
+use futures::sink::SinkExt as _;
 use std::fs;
-use tokio::sync::mpsc::channel;
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// Spawn a worker replying to the expected stream request with the specified batches.
+fn stream_listener(address: Address, expected: Bytes, batches: Vec<Vec<u8>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut listener = transport().listen(&address.listen_address()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut transport = Framed::new(socket, LengthDelimitedCodec::new());
+        match transport.next().await {
+            Some(Ok(received)) => {
+                assert_eq!(received.freeze(), expected);
+                let reply = bincode::serialize(&BatchStreamReply { batches }).unwrap();
+                transport.send(Bytes::from(reply)).await.unwrap();
+            }
+            _ => panic!("Failed to receive network message"),
+        }
+    })
+}
 
 #[tokio::test]
 async fn synchronize() {
     let (tx_message, rx_message) = channel(1);
+    let (tx_processor, mut rx_processor) = channel(1);
 
     let mut keys = keys();
     let (name, _) = keys.pop().unwrap();
@@ -27,21 +48,32 @@ async fn synchronize() {
         /* gc_depth */ 50, // Not used in this test.
         /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
         /* sync_retry_nodes */ 3, // Not used in this test.
+        transport(),
+        /* chunk_size */ 0,
+        Arc::new(AcceptAllTransactions),
         rx_message,
+        /* rx_parameters */ watch::channel(Parameters::default()).1,
+        tx_processor,
     );
 
-    // Spawn a listener to receive our batch requests.
+    // Spawn a listener to receive our stream request and reply with the batch.
     let (target, _) = keys.pop().unwrap();
-    let address = committee.worker(&target, &id).unwrap().worker_to_worker;
+    let address = committee
+        .worker(&target, &id)
+        .unwrap()
+        .worker_to_worker
+        .advertise;
     let missing = vec![batch_digest()];
-    let message = WorkerMessage::BatchRequest(missing.clone(), name);
+    let message = WorkerMessage::BatchStreamRequest(missing.clone(), name);
     let serialized = bincode::serialize(&message).unwrap();
-    let handle = listener(address, Some(Bytes::from(serialized)));
+    let handle = stream_listener(address, Bytes::from(serialized), vec![serialized_batch()]);
 
     // Send a sync request.
     let message = PrimaryWorkerMessage::Synchronize(missing, target);
     tx_message.send(message).await.unwrap();
 
-    // Ensure the target receives the sync request.
+    // Ensure the target receives the sync request, and that its batch is delivered to the `Processor`.
     assert!(handle.await.is_ok());
+    let batch = rx_processor.recv().await.unwrap();
+    assert_eq!(batch, serialized_batch());
 }
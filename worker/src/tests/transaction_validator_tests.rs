@@ -0,0 +1,36 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use super::*;
+use crate::common::transaction;
+
+/// Rejects the empty transactions.
+struct RejectEmpty;
+
+impl TransactionValidator for RejectEmpty {
+    fn validate(&self, transaction: &[u8]) -> Result<(), String> {
+        match transaction.is_empty() {
+            true => Err("Empty transaction".to_string()),
+            false => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn validate_transactions() {
+    // A list of validators rejects the transaction as soon as one of them does.
+    let validators: Vec<Box<dyn TransactionValidator>> =
+        vec![Box::new(AcceptAllTransactions), Box::new(RejectEmpty)];
+    assert!(validators.validate(&transaction()).is_ok());
+    assert!(validators.validate(&[]).is_err());
+}
+
+#[test]
+fn limit_transaction_size() {
+    let validator = SizeLimit::new(/* max_size */ 4, Arc::new(AcceptAllTransactions));
+    assert!(validator.validate(&[1, 2, 3, 4]).is_ok());
+    assert!(validator.validate(&[]).is_err());
+    assert!(validator.validate(&[1, 2, 3, 4, 5]).is_err());
+
+    // The rules of the application still apply.
+    let validator = SizeLimit::new(/* max_size */ 0, Arc::new(RejectEmpty));
+    assert!(validator.validate(&transaction()).is_ok());
+}
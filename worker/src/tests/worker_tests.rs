@@ -1,9 +1,14 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener, transaction};
+use crate::common::{
+    batch_digest, committee_with_base_port, keys, listener, transaction, transport,
+};
+use futures::stream::StreamExt as _;
 use network::SimpleSender;
 use primary::WorkerPrimaryMessage;
 use std::fs;
+use tokio::time::{sleep, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn handle_clients_transactions() {
@@ -21,25 +26,188 @@ async fn handle_clients_transactions() {
     let store = Store::new(path).unwrap();
 
     // Spawn a `Worker` instance.
-    Worker::spawn(name, id, committee.clone(), parameters, store);
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+    Worker::spawn_with_transport(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        transport(),
+        tx_reconfigure,
+        Arc::new(AcceptAllTransactions),
+        /* priority */ None,
+        /* decryption_key */ None,
+    );
 
     // Spawn a network listener to receive our batch's digest.
-    let primary_address = committee.primary(&name).unwrap().worker_to_primary;
+    let primary_address = committee
+        .primary(&name)
+        .unwrap()
+        .worker_to_primary
+        .advertise;
     let expected = bincode::serialize(&WorkerPrimaryMessage::OurBatch(batch_digest(), id)).unwrap();
     let handle = listener(primary_address, Some(Bytes::from(expected)));
 
     // Spawn enough workers' listeners to acknowledge our batches.
     for (_, addresses) in committee.others_workers(&name, &id) {
-        let address = addresses.worker_to_worker;
+        let address = addresses.worker_to_worker.advertise;
         let _ = listener(address, /* expected */ None);
     }
 
     // Send enough transactions to create a batch.
-    let mut network = SimpleSender::new();
-    let address = committee.worker(&name, &id).unwrap().transactions;
-    network.send(address, Bytes::from(transaction())).await;
+    let mut network = SimpleSender::new().with_transport(transport());
+    let address = committee.worker(&name, &id).unwrap().transactions.advertise;
+    network
+        .send(address.clone(), Bytes::from(transaction()))
+        .await;
     network.send(address, Bytes::from(transaction())).await;
 
     // Ensure the primary received the batch's digest (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn merge_transactions_sockets() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let mut committee = committee_with_base_port(15_000);
+    let additional: Address = "127.0.0.1:15600".parse().unwrap();
+    committee
+        .authorities
+        .get_mut(&name)
+        .unwrap()
+        .workers
+        .get_mut(&id)
+        .unwrap()
+        .additional_transactions = vec![additional.clone().into()];
+    let parameters = Parameters {
+        batch_size: 200, // Two transactions.
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_merge_transactions_sockets";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+    Worker::spawn_with_transport(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        transport(),
+        tx_reconfigure,
+        Arc::new(AcceptAllTransactions),
+        /* priority */ None,
+        /* decryption_key */ None,
+    );
+
+    // Spawn a network listener to receive our batch's digest.
+    let primary_address = committee
+        .primary(&name)
+        .unwrap()
+        .worker_to_primary
+        .advertise;
+    let expected = bincode::serialize(&WorkerPrimaryMessage::OurBatch(batch_digest(), id)).unwrap();
+    let handle = listener(primary_address, Some(Bytes::from(expected)));
+
+    // Spawn enough workers' listeners to acknowledge our batches.
+    for (_, addresses) in committee.others_workers(&name, &id) {
+        let address = addresses.worker_to_worker.advertise;
+        let _ = listener(address, /* expected */ None);
+    }
+
+    // Send one transaction on each socket: they end up in the same batch.
+    let mut network = SimpleSender::new().with_transport(transport());
+    let address = committee.worker(&name, &id).unwrap().transactions.advertise;
+    network.send(address, Bytes::from(transaction())).await;
+    network.send(additional, Bytes::from(transaction())).await;
+
+    // Ensure the primary received the batch's digest.
+    assert!(handle.await.is_ok());
+}
+
+#[tokio::test]
+async fn ask_clients_to_slow_down() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(16_000);
+    let parameters = Parameters {
+        max_admitted_transactions: 1,
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_ask_clients_to_slow_down";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+    Worker::spawn_with_transport(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        transport(),
+        tx_reconfigure,
+        Arc::new(AcceptAllTransactions),
+        /* priority */ None,
+        /* decryption_key */ None,
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    // Send more transactions than the worker admits: it asks us to slow down.
+    let address = committee.worker(&name, &id).unwrap().transactions.advertise;
+    let stream = transport().connect(&address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(Bytes::from(transaction())).await.unwrap();
+    transport.send(Bytes::from(transaction())).await.unwrap();
+    let reply = transport.next().await.unwrap().unwrap();
+    assert_eq!(&reply[..], SLOW_DOWN);
+}
+
+#[tokio::test]
+async fn reject_oversized_transactions() {
+    let (name, _) = keys().pop().unwrap();
+    let id = 0;
+    let committee = committee_with_base_port(16_500);
+    let parameters = Parameters {
+        max_transaction_size: 10,
+        ..Parameters::default()
+    };
+
+    // Create a new test store.
+    let path = ".db_test_reject_oversized_transactions";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Worker` instance.
+    let (tx_reconfigure, _rx_reconfigure) = channel(1);
+    Worker::spawn_with_transport(
+        name,
+        id,
+        committee.clone(),
+        parameters,
+        store,
+        transport(),
+        tx_reconfigure,
+        Arc::new(AcceptAllTransactions),
+        /* priority */ None,
+        /* decryption_key */ None,
+    );
+    sleep(Duration::from_millis(100)).await;
+
+    // Send a transaction larger than the limit: the worker tells us it rejected it.
+    let address = committee.worker(&name, &id).unwrap().transactions.advertise;
+    let stream = transport().connect(&address).await.unwrap();
+    let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
+    transport.send(Bytes::from(transaction())).await.unwrap();
+    let reply = transport.next().await.unwrap().unwrap();
+    assert!(reply.starts_with(REJECTED));
+}
@@ -0,0 +1,63 @@
+// Copyright(C) Facebook, Inc. and its affiliates.
+use std::sync::Arc;
+
+#[cfg(test)]
+#[path = "tests/transaction_validator_tests.rs"]
+pub mod transaction_validator_tests;
+
+/// Application-specific rules the transactions must follow to enter the mempool (eg. to reject malformed or
+/// oversized transactions). The worker checks the transactions of its clients before batching them, and the
+/// transactions of the batches of other workers before storing them: batches holding any invalid transaction
+/// are dropped. Validators are shared by all the connections, so they must be thread-safe.
+pub trait TransactionValidator: Send + Sync + 'static {
+    /// Returns why the transaction breaks the rules (if it does).
+    fn validate(&self, transaction: &[u8]) -> Result<(), String>;
+}
+
+/// Accepts all transactions.
+pub struct AcceptAllTransactions;
+
+impl TransactionValidator for AcceptAllTransactions {
+    fn validate(&self, _transaction: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A transaction must follow the rules of all the validators of the list (checked in order).
+impl TransactionValidator for Vec<Box<dyn TransactionValidator>> {
+    fn validate(&self, transaction: &[u8]) -> Result<(), String> {
+        self.iter().try_for_each(|x| x.validate(transaction))
+    }
+}
+
+/// Sanity checks of the clients' transactions at ingress, before the rules of the application: empty
+/// transactions and transactions larger than `max_size` (if set) are rejected, so that a single huge
+/// transaction cannot exceed the size the batches are dimensioned for.
+pub struct SizeLimit {
+    /// The maximum size of a transaction (0 for no limit). Denominated in bytes.
+    max_size: usize,
+    /// The rules of the application.
+    inner: Arc<dyn TransactionValidator>,
+}
+
+impl SizeLimit {
+    pub fn new(max_size: usize, inner: Arc<dyn TransactionValidator>) -> Self {
+        Self { max_size, inner }
+    }
+}
+
+impl TransactionValidator for SizeLimit {
+    fn validate(&self, transaction: &[u8]) -> Result<(), String> {
+        if transaction.is_empty() {
+            return Err("Empty transaction".to_string());
+        }
+        if self.max_size > 0 && transaction.len() > self.max_size {
+            return Err(format!(
+                "Transaction of {} B exceeds the maximum size of {} B",
+                transaction.len(),
+                self.max_size
+            ));
+        }
+        self.inner.validate(transaction)
+    }
+}
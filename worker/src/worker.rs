@@ -1,29 +1,59 @@
 // Copyright(C) Facebook, Inc. and its affiliates.
-use crate::batch_maker::{Batch, BatchMaker, Transaction};
-use crate::helper::Helper;
-use crate::primary_connector::PrimaryConnector;
+use crate::admission::AdmissionControl;
+use crate::batch_maker::{
+    Batch, BatchMaker, BatchSizer, PriorityQueue, Transaction, TransactionPriority,
+};
+use crate::batch_pruner::{BatchIndex, BatchPruner};
+use crate::compression::{decompress, BatchCompressor};
+use crate::coverage::{Coverage, CoverageFilter};
+#[cfg(feature = "threshold-encryption")]
+use crate::decryption::{BatchDecryptionShares, Ciphertexts, Decryptor, RETRY_REPLY};
+use crate::deduplicator::Deduplicator;
+use crate::erasure::{BatchShard, ShardEncoder};
+use crate::grpc::{BatchesService, TransactionsService};
+use crate::hasher::BatchHasher;
+use crate::helper::{Helper, HelperRequest};
+use crate::http_gateway::HttpGateway;
+use crate::metrics::{MetricsServer, WorkerMetrics};
+use crate::padding::BatchPadder;
+use crate::pending_batches::PendingBatches;
+use crate::primary_connector::{DeliveryMetrics, PrimaryConnector};
 use crate::processor::{Processor, SerializedBatchMessage};
 use crate::quorum_waiter::QuorumWaiter;
+use crate::reconstructor::Reconstructor;
 use crate::synchronizer::Synchronizer;
+use crate::transaction_validator::{AcceptAllTransactions, SizeLimit, TransactionValidator};
 use async_trait::async_trait;
 use bytes::Bytes;
-use config::{Committee, Parameters, WorkerId};
-use crypto::{Digest, PublicKey};
+use config::{Committee, Epoch, Parameters, WorkerId};
+use crypto::{Digest, PublicKey, ThresholdKeyShare};
 use futures::sink::SinkExt as _;
-use log::{error, info, warn};
-use network::{MessageHandler, Receiver, Writer};
-use primary::PrimaryWorkerMessage;
+use log::{debug, error, info, warn};
+use network::{
+    Address, DatagramHandler, DatagramReceiver, MessageHandler, Receiver, ShapedTransport,
+    SharedTransport, TcpTransport, TypedMessageHandler, TypedReceiver, Writer,
+};
+use primary::{PrimaryWorkerMessage, WorkerPrimaryMessage};
 use serde::{Deserialize, Serialize};
+use std::cmp::max;
 use std::error::Error;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::{oneshot, watch};
 
 #[cfg(test)]
 #[path = "tests/worker_tests.rs"]
 pub mod worker_tests;
 
-/// The default channel capacity for each channel of the worker.
-pub const CHANNEL_CAPACITY: usize = 1_000;
+/// The control message asking a client to slow down, sent on its connection when the worker cannot admit
+/// its transactions (the worker then stops reading the connection until there is room).
+pub const SLOW_DOWN: &[u8] = b"SlowDown";
+
+/// The prefix of the reply to a transaction that the worker rejects (and drops), followed by the reason.
+pub const REJECTED: &[u8] = b"Rejected: ";
 
 /// The primary round number.
 // TODO: Move to the primary.
@@ -37,6 +67,17 @@ pub type SerializedBatchDigestMessage = Vec<u8>;
 pub enum WorkerMessage {
     Batch(Batch),
     BatchRequest(Vec<Digest>, /* origin */ PublicKey),
+    /// Requests the batches the receiver has among the specified ones, in a single reply (see `Synchronizer`).
+    BatchStreamRequest(Vec<Digest>, /* origin */ PublicKey),
+    /// A serialized `WorkerMessage::Batch`, compressed with zstd.
+    CompressedBatch(Vec<u8>),
+    /// A serialized `WorkerMessage::Batch` or `WorkerMessage::CompressedBatch`, followed by padding.
+    PaddedBatch(Vec<u8>, Vec<u8>),
+    /// Shards of an erasure-coded batch (see `ShardEncoder`).
+    BatchShards(Vec<BatchShard>),
+    /// The decryption shares of the sender for a committed batch (see `Decryptor`).
+    #[cfg(feature = "threshold-encryption")]
+    DecryptionShares(BatchDecryptionShares),
 }
 
 pub struct Worker {
@@ -48,32 +89,245 @@ pub struct Worker {
     committee: Committee,
     /// The configuration parameters.
     parameters: Parameters,
+    /// Receives the parameters reloaded while we run.
+    rx_parameters: watch::Receiver<Parameters>,
     /// The persistent storage.
     store: Store,
+    /// The transport used to communicate with the other nodes.
+    transport: SharedTransport,
+    /// Outputs the new epoch when our primary moves to the next epoch.
+    tx_reconfigure: Sender<Epoch>,
+    /// Checks the transactions before they enter the mempool.
+    validator: Arc<dyn TransactionValidator>,
+    /// Extracts the priority of the transactions (if they are batched by priority).
+    priority: Option<TransactionPriority>,
+    /// Indexes the stored batches by round (if they are pruned).
+    batch_index: Option<BatchIndex>,
+    /// Serializes and hashes the batches off the event loops.
+    hasher: BatchHasher,
+    /// Tracks the transactions covered by the batches of the other workers (if enabled).
+    coverage: Option<Coverage>,
+    /// The delivery counters of the messages we send to our primary.
+    delivery_metrics: Arc<DeliveryMetrics>,
+    /// Bounds the transactions waiting to be batched, and the rate of each client.
+    admission: Arc<AdmissionControl>,
+    /// The counters of the worker tasks.
+    metrics: Arc<WorkerMetrics>,
+    /// Channel to notify the `Decryptor` of the committed batches (if the clients encrypt their transactions).
+    #[cfg(feature = "threshold-encryption")]
+    tx_committed: Option<Sender<Vec<Digest>>>,
+    /// Channel to deliver the decryption shares of the other workers to the `Decryptor` (if any).
+    #[cfg(feature = "threshold-encryption")]
+    tx_decryption_shares: Option<Sender<(BatchDecryptionShares, oneshot::Sender<bool>)>>,
 }
 
 impl Worker {
+    /// Spawn a worker for the epoch of the committee. Once our primary moves to the next epoch, the worker
+    /// outputs the new epoch on `tx_reconfigure`: the caller should then stop the worker and spawn a new one
+    /// with the committee of that epoch. The returned sender updates the parameters of the running worker (only
+    /// the ones safe to change live are applied, see `Parameters::reload`).
     pub fn spawn(
         name: PublicKey,
         id: WorkerId,
         committee: Committee,
         parameters: Parameters,
         store: Store,
-    ) {
+        tx_reconfigure: Sender<Epoch>,
+    ) -> watch::Sender<Parameters> {
+        Self::spawn_with_validator(
+            name,
+            id,
+            committee,
+            parameters,
+            store,
+            tx_reconfigure,
+            Arc::new(AcceptAllTransactions),
+        )
+    }
+
+    /// Spawn a worker that only accepts the transactions following the custom rules of the validator.
+    pub fn spawn_with_validator(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        tx_reconfigure: Sender<Epoch>,
+        validator: Arc<dyn TransactionValidator>,
+    ) -> watch::Sender<Parameters> {
+        Self::spawn_with_priority(
+            name,
+            id,
+            committee,
+            parameters,
+            store,
+            tx_reconfigure,
+            validator,
+            /* priority */ None,
+        )
+    }
+
+    /// Spawn a worker that (if `priority` is set) batches the highest-priority transactions first, and evicts
+    /// the lowest-priority ones once `max_pending_transactions` are waiting to be batched.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn_with_priority(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        tx_reconfigure: Sender<Epoch>,
+        validator: Arc<dyn TransactionValidator>,
+        priority: Option<TransactionPriority>,
+    ) -> watch::Sender<Parameters> {
+        let transport = ShapedTransport::wrap(
+            Arc::new(TcpTransport::new(parameters.socket.clone())),
+            &parameters.egress,
+        );
+        Self::spawn_with_transport(
+            name,
+            id,
+            committee,
+            parameters,
+            store,
+            transport,
+            tx_reconfigure,
+            validator,
+            priority,
+            /* decryption_key */ None,
+        )
+    }
+
+    /// Spawn a worker decrypting the committed batches with our share of the threshold key of the committee
+    /// (the clients then encrypt their transactions to the committee, see `Decryptor`). The threshold of the key
+    /// must be f+1 for the honest workers alone to decrypt the committed batches.
+    #[cfg(feature = "threshold-encryption")]
+    pub fn spawn_with_decryption(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        tx_reconfigure: Sender<Epoch>,
+        decryption_key: ThresholdKeyShare,
+    ) -> watch::Sender<Parameters> {
+        let transport = ShapedTransport::wrap(
+            Arc::new(TcpTransport::new(parameters.socket.clone())),
+            &parameters.egress,
+        );
+        Self::spawn_with_transport(
+            name,
+            id,
+            committee,
+            parameters,
+            store,
+            transport,
+            tx_reconfigure,
+            Arc::new(AcceptAllTransactions),
+            /* priority */ None,
+            Some(decryption_key),
+        )
+    }
+
+    /// Spawn a worker communicating through the specified transport rather than TCP (eg. to run a whole
+    /// committee in a single process).
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn_with_transport(
+        name: PublicKey,
+        id: WorkerId,
+        committee: Committee,
+        parameters: Parameters,
+        store: Store,
+        transport: SharedTransport,
+        tx_reconfigure: Sender<Epoch>,
+        validator: Arc<dyn TransactionValidator>,
+        priority: Option<TransactionPriority>,
+        decryption_key: Option<ThresholdKeyShare>,
+    ) -> watch::Sender<Parameters> {
+        let channel_capacity = parameters.channel_capacity.max(1);
+        let batch_index =
+            (parameters.batch_retention_depth > 0).then(|| BatchIndex::new(store.clone()));
+        let hasher = BatchHasher::new(parameters.hashing_workers);
+        let coverage = NonZeroUsize::new(parameters.covered_transactions_size).map(Coverage::new);
+        let admission = Arc::new(AdmissionControl::new(
+            parameters.max_admitted_transactions,
+            parameters.max_admitted_bytes,
+            parameters.client_rate_limit,
+        ));
+
+        // If the clients encrypt their transactions to the committee, we only disseminate ciphertexts and the
+        // `Decryptor` (if we hold a share of the key) decrypts the batches once they are committed.
+        #[cfg(feature = "threshold-encryption")]
+        let (validator, tx_committed, tx_decryption_shares) = match &committee.threshold_key {
+            Some(key) => {
+                let validator: Arc<dyn TransactionValidator> =
+                    Arc::new(Ciphertexts::new(validator));
+                match decryption_key {
+                    Some(share) => {
+                        let (tx_committed, rx_committed) = channel(channel_capacity);
+                        let (tx_shares, rx_shares) = channel(channel_capacity);
+                        Decryptor::spawn(
+                            name,
+                            id,
+                            committee.clone(),
+                            key.clone(),
+                            share,
+                            store.clone(),
+                            transport.clone(),
+                            parameters.chunk_size,
+                            rx_committed,
+                            rx_shares,
+                        );
+                        (validator, Some(tx_committed), Some(tx_shares))
+                    }
+                    None => {
+                        warn!(
+                            "No share of the threshold key: we will not decrypt committed batches"
+                        );
+                        (validator, None, None)
+                    }
+                }
+            }
+            None => (validator, None, None),
+        };
+        #[cfg(not(feature = "threshold-encryption"))]
+        if committee.threshold_key.is_some() || decryption_key.is_some() {
+            warn!("Threshold encryption is not enabled: transactions are disseminated as received");
+        }
+
+        // The parameters reloaded while we run, applied by the tasks using them.
+        let (tx_parameters, rx_parameters) = watch::channel(parameters.clone());
+
         // Define a worker instance.
         let worker = Self {
             name,
             id,
             committee,
             parameters,
+            rx_parameters,
             store,
+            transport,
+            tx_reconfigure,
+            validator,
+            priority,
+            batch_index,
+            hasher,
+            coverage,
+            delivery_metrics: Arc::new(DeliveryMetrics::default()),
+            admission,
+            metrics: Arc::new(WorkerMetrics::default()),
+            #[cfg(feature = "threshold-encryption")]
+            tx_committed,
+            #[cfg(feature = "threshold-encryption")]
+            tx_decryption_shares,
         };
 
         // Spawn all worker tasks.
-        let (tx_primary, rx_primary) = channel(CHANNEL_CAPACITY);
-        worker.handle_primary_messages();
+        let (tx_primary, rx_primary) = channel(channel_capacity);
+        let tx_processor = worker.handle_workers_messages(tx_primary.clone());
+        worker.handle_primary_messages(tx_processor);
         worker.handle_clients_transactions(tx_primary.clone());
-        worker.handle_workers_messages(tx_primary);
+        worker.resend_pending_batches(tx_primary);
 
         // The `PrimaryConnector` allows the worker to send messages to its primary.
         PrimaryConnector::spawn(
@@ -81,10 +335,39 @@ impl Worker {
                 .committee
                 .primary(&worker.name)
                 .expect("Our public key is not in the committee")
-                .worker_to_primary,
+                .worker_to_primary
+                .advertise,
+            worker.transport.clone(),
+            worker.store.clone(),
+            worker.delivery_metrics.clone(),
             rx_primary,
         );
 
+        // Serve our stored batches to the execution layer (if configured).
+        if let Some(address) = worker
+            .committee
+            .worker(&worker.name, &worker.id)
+            .expect("Our public key or worker id is not in the committee")
+            .executor
+        {
+            BatchesService::spawn(address.bind, worker.store.clone());
+        }
+
+        // Expose the metrics of the worker (if configured).
+        if let Some(address) = worker
+            .committee
+            .worker(&worker.name, &worker.id)
+            .expect("Our public key or worker id is not in the committee")
+            .metrics
+        {
+            MetricsServer::spawn(
+                address.bind,
+                worker.metrics.clone(),
+                worker.admission.clone(),
+                worker.delivery_metrics.clone(),
+            );
+        }
+
         // NOTE: This log entry is used to compute performance.
         info!(
             "Worker {} successfully booted on {}",
@@ -94,25 +377,101 @@ impl Worker {
                 .worker(&worker.name, &worker.id)
                 .expect("Our public key or worker id is not in the committee")
                 .transactions
-                .ip()
+                .advertise
+                .host()
         );
+        tx_parameters
+    }
+
+    /// Re-send to our primary the digests of our batches that were not sequenced before we (or our primary)
+    /// crashed, so that the clients' transactions they carry are not lost.
+    fn resend_pending_batches(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
+        let id = self.id;
+        let mut pending = PendingBatches::new(self.store.clone());
+        tokio::spawn(async move {
+            let digests = match pending.read_all().await {
+                Ok(digests) => digests,
+                Err(e) => {
+                    error!("Failed to read our pending batches: {}", e);
+                    return;
+                }
+            };
+            if !digests.is_empty() {
+                info!(
+                    "Re-sending {} pending batches to our primary",
+                    digests.len()
+                );
+            }
+            for digest in digests {
+                let message = WorkerPrimaryMessage::OurBatch(digest, id);
+                let serialized = bincode::serialize(&message)
+                    .expect("Failed to serialize our own worker-primary message");
+                tx_primary
+                    .send(serialized)
+                    .await
+                    .expect("Failed to send digest");
+            }
+        });
+    }
+
+    /// Returns the hosts allowed to send us messages (if restricted). Clients' transactions are not restricted.
+    fn allowlist(&self) -> Option<Vec<Address>> {
+        self.parameters
+            .committee_allowlist
+            .then(|| self.committee.addresses())
     }
 
     /// Spawn all tasks responsible to handle messages from our primary.
-    fn handle_primary_messages(&self) {
-        let (tx_synchronizer, rx_synchronizer) = channel(CHANNEL_CAPACITY);
+    fn handle_primary_messages(&self, tx_processor: Sender<SerializedBatchMessage>) {
+        let channel_capacity = self.parameters.channel_capacity.max(1);
+        let (tx_synchronizer, rx_synchronizer) = channel(channel_capacity);
 
         // Receive incoming messages from our primary.
-        let mut address = self
+        let address = self
             .committee
             .worker(&self.name, &self.id)
             .expect("Our public key or worker id is not in the committee")
-            .primary_to_worker;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+            .primary_to_worker
+            .bind;
+        // The `BatchPruner` (if enabled) deletes the batches that fell out of the retention window of the
+        // cleanup round of our primary.
+        let tx_pruner = self.batch_index.clone().map(|index| {
+            let (tx_pruner, rx_pruner) = channel(channel_capacity);
+            BatchPruner::spawn(
+                self.store.clone(),
+                index,
+                /* depth */
+                max(
+                    self.parameters.batch_retention_depth,
+                    self.parameters.gc_depth,
+                ),
+                self.parameters.batch_retention_bytes,
+                /* rx_cleanup */ rx_pruner,
+            );
+            tx_pruner
+        });
+
+        let handler = PrimaryReceiverHandler {
+            tx_synchronizer,
+            tx_pruner,
+            tx_reconfigure: self.tx_reconfigure.clone(),
+            coverage: self.coverage.clone(),
+            #[cfg(feature = "threshold-encryption")]
+            tx_committed: self.tx_committed.clone(),
+        };
+        if self.parameters.max_datagram_size > 0 {
+            DatagramReceiver::spawn_with_transport(
+                address,
+                handler.clone(),
+                self.transport.clone(),
+            );
+        }
+        Receiver::spawn_with_options(
             address,
-            /* handler */
-            PrimaryReceiverHandler { tx_synchronizer },
+            TypedReceiver::new(handler),
+            self.allowlist(),
+            self.transport.clone(),
+            /* chunked */ false,
         );
 
         // The `Synchronizer` is responsible to keep the worker in sync with the others. It handles the commands
@@ -125,7 +484,12 @@ impl Worker {
             self.parameters.gc_depth,
             self.parameters.sync_retry_delay,
             self.parameters.sync_retry_nodes,
+            self.transport.clone(),
+            self.parameters.chunk_size,
+            self.validator.clone(),
             /* rx_message */ rx_synchronizer,
+            self.rx_parameters.clone(),
+            tx_processor,
         );
 
         info!(
@@ -136,45 +500,128 @@ impl Worker {
 
     /// Spawn all tasks responsible to handle clients transactions.
     fn handle_clients_transactions(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
-        let (tx_batch_maker, rx_batch_maker) = channel(CHANNEL_CAPACITY);
-        let (tx_quorum_waiter, rx_quorum_waiter) = channel(CHANNEL_CAPACITY);
-        let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
-
-        // We first receive clients' transactions from the network.
-        let mut address = self
+        let channel_capacity = self.parameters.channel_capacity.max(1);
+        let (tx_batch_maker, rx_batch_maker) = channel(channel_capacity);
+        let (tx_quorum_waiter, rx_quorum_waiter) = channel(channel_capacity);
+        let (tx_processor, rx_processor) = channel(channel_capacity);
+
+        let admission = self.admission.clone();
+
+        // Sanity-check the size of the clients' transactions before the rules of the application.
+        let validator: Arc<dyn TransactionValidator> = Arc::new(SizeLimit::new(
+            self.parameters.max_transaction_size,
+            self.validator.clone(),
+        ));
+
+        // We first receive clients' transactions from the network, on each of our transactions sockets (their
+        // streams are merged into the same batches).
+        let addresses = self
             .committee
             .worker(&self.name, &self.id)
-            .expect("Our public key or worker id is not in the committee")
-            .transactions;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
-            address,
-            /* handler */ TxReceiverHandler { tx_batch_maker },
-        );
+            .expect("Our public key or worker id is not in the committee");
+        let address = addresses.transactions.bind;
+        let handler = TxReceiverHandler {
+            tx_batch_maker: tx_batch_maker.clone(),
+            validator: validator.clone(),
+            admission: admission.clone(),
+        };
+        for address in addresses.all_transactions() {
+            Receiver::spawn_with_options(
+                address.bind,
+                handler.clone(),
+                /* allowlist */ None,
+                self.transport.clone(),
+                /* chunked */ false,
+            );
+        }
+
+        // We may also receive clients' transactions over gRPC...
+        if let Some(address) = addresses.grpc {
+            TransactionsService::spawn(
+                address.bind,
+                validator.clone(),
+                admission.clone(),
+                tx_batch_maker.clone(),
+            );
+        }
+
+        // And over HTTP (for debugging and low-rate clients).
+        if let Some(address) = addresses.http {
+            HttpGateway::spawn(
+                address.bind,
+                validator.clone(),
+                admission.clone(),
+                tx_batch_maker,
+            );
+        }
+
+        // The `Deduplicator` (if enabled) drops the transactions we recently received.
+        let rx_batch_maker = match NonZeroUsize::new(self.parameters.transaction_dedup_size) {
+            Some(capacity) => {
+                let (tx_deduplicated, rx_deduplicated) = channel(channel_capacity);
+                Deduplicator::spawn(capacity, admission.clone(), rx_batch_maker, tx_deduplicated);
+                rx_deduplicated
+            }
+            None => rx_batch_maker,
+        };
+
+        // The `CoverageFilter` (if enabled) drops the transactions already in a committed batch.
+        let rx_batch_maker = match self.coverage.clone() {
+            Some(coverage) => {
+                let (tx_filtered, rx_filtered) = channel(channel_capacity);
+                CoverageFilter::spawn(coverage, admission.clone(), rx_batch_maker, tx_filtered);
+                rx_filtered
+            }
+            None => rx_batch_maker,
+        };
 
         // The transactions are sent to the `BatchMaker` that assembles them into batches. It then broadcasts
         // (in a reliable manner) the batches to all other workers that share the same `id` as us. Finally, it
         // gathers the 'cancel handlers' of the messages and send them to the `QuorumWaiter`.
+        let sizer = BatchSizer::from_parameters(&self.parameters);
+        let queue = self
+            .priority
+            .clone()
+            .map(|priority| PriorityQueue::new(priority, self.parameters.max_pending_transactions));
         BatchMaker::spawn(
-            self.parameters.batch_size,
-            self.parameters.max_batch_delay,
+            sizer,
+            queue,
             /* rx_transaction */ rx_batch_maker,
+            self.rx_parameters.clone(),
             /* tx_message */ tx_quorum_waiter,
             /* workers_addresses */
             self.committee
                 .others_workers(&self.name, &self.id)
                 .iter()
-                .map(|(name, addresses)| (*name, addresses.worker_to_worker))
+                .map(|(name, addresses)| (*name, addresses.worker_to_worker.advertise.clone()))
                 .collect(),
+            self.transport.clone(),
+            self.parameters.chunk_size,
+            /* compressor */
+            (self.parameters.batch_compression_level != 0)
+                .then(|| BatchCompressor::new(self.parameters.batch_compression_level)),
+            /* padder */
+            BatchPadder::new(self.parameters.batch_padding_buckets.clone()),
+            self.hasher.clone(),
+            /* encoder */
+            ShardEncoder::new(
+                self.name,
+                self.committee.clone(),
+                self.parameters.batch_erasure_coding_threshold,
+            ),
+            admission,
+            self.metrics.clone(),
         );
 
-        // The `QuorumWaiter` waits for 2f authorities to acknowledge reception of the batch. It then forwards
-        // the batch to the `Processor`.
+        // The `QuorumWaiter` waits for 2f authorities (or the configured delivery threshold) to acknowledge
+        // reception of the batch. It then forwards the batch to the `Processor`.
         QuorumWaiter::spawn(
             self.committee.clone(),
             /* stake */ self.committee.stake(&self.name),
+            self.parameters.delivery_threshold,
             /* rx_message */ rx_quorum_waiter,
             /* tx_batch */ tx_processor,
+            self.metrics.clone(),
         );
 
         // The `Processor` hashes and stores the batch. It then forwards the batch's digest to the `PrimaryConnector`
@@ -185,33 +632,55 @@ impl Worker {
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ true,
+            self.batch_index.clone(),
+            self.hasher.clone(),
+            self.coverage.clone(),
         );
 
         info!(
             "Worker {} listening to client transactions on {}",
             self.id, address
         );
+        for address in &addresses.additional_transactions {
+            info!(
+                "Worker {} also listening to client transactions on {}",
+                self.id, address.bind
+            );
+        }
     }
 
-    /// Spawn all tasks responsible to handle messages from other workers.
-    fn handle_workers_messages(&self, tx_primary: Sender<SerializedBatchDigestMessage>) {
-        let (tx_helper, rx_helper) = channel(CHANNEL_CAPACITY);
-        let (tx_processor, rx_processor) = channel(CHANNEL_CAPACITY);
+    /// Spawn all tasks responsible to handle messages from other workers. It returns the input channel of the
+    /// `Processor` storing the batches of the other workers.
+    fn handle_workers_messages(
+        &self,
+        tx_primary: Sender<SerializedBatchDigestMessage>,
+    ) -> Sender<SerializedBatchMessage> {
+        let channel_capacity = self.parameters.channel_capacity.max(1);
+        let (tx_helper, rx_helper) = channel(channel_capacity);
+        let (tx_processor, rx_processor) = channel(channel_capacity);
+        let (tx_shards, rx_shards) = channel(channel_capacity);
 
         // Receive incoming messages from other workers.
-        let mut address = self
+        let address = self
             .committee
             .worker(&self.name, &self.id)
             .expect("Our public key or worker id is not in the committee")
-            .worker_to_worker;
-        address.set_ip("0.0.0.0".parse().unwrap());
-        Receiver::spawn(
+            .worker_to_worker
+            .bind;
+        Receiver::spawn_with_options(
             address,
             /* handler */
             WorkerReceiverHandler {
                 tx_helper,
-                tx_processor,
+                tx_processor: tx_processor.clone(),
+                tx_shards,
+                validator: self.validator.clone(),
+                #[cfg(feature = "threshold-encryption")]
+                tx_decryption_shares: self.tx_decryption_shares.clone(),
             },
+            self.allowlist(),
+            self.transport.clone(),
+            /* chunked */ self.parameters.chunk_size > 0,
         );
 
         // The `Helper` is dedicated to reply to batch requests from other workers.
@@ -219,9 +688,25 @@ impl Worker {
             self.id,
             self.committee.clone(),
             self.store.clone(),
+            self.transport.clone(),
+            self.parameters.chunk_size,
+            self.parameters.sync_rate_limit,
+            self.metrics.clone(),
             /* rx_request */ rx_helper,
         );
 
+        // The `Reconstructor` rebuilds the erasure-coded batches of the other workers from their shards.
+        Reconstructor::spawn(
+            self.name,
+            self.id,
+            self.committee.clone(),
+            self.validator.clone(),
+            self.transport.clone(),
+            self.parameters.chunk_size,
+            rx_shards,
+            /* tx_processor */ tx_processor.clone(),
+        );
+
         // This `Processor` hashes and stores the batches we receive from the other workers. It then forwards the
         // batch's digest to the `PrimaryConnector` that will send it to our primary.
         Processor::spawn(
@@ -230,12 +715,16 @@ impl Worker {
             /* rx_batch */ rx_processor,
             /* tx_digest */ tx_primary,
             /* own_batch */ false,
+            self.batch_index.clone(),
+            self.hasher.clone(),
+            self.coverage.clone(),
         );
 
         info!(
             "Worker {} listening to worker messages on {}",
             self.id, address
         );
+        tx_processor
     }
 }
 
@@ -243,11 +732,29 @@ impl Worker {
 #[derive(Clone)]
 struct TxReceiverHandler {
     tx_batch_maker: Sender<Transaction>,
+    validator: Arc<dyn TransactionValidator>,
+    admission: Arc<AdmissionControl>,
 }
 
 #[async_trait]
 impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        // Reject the malformed transactions and those breaking the rules of the application.
+        if let Err(e) = self.validator.validate(&message) {
+            warn!("Rejected transaction: {}", e);
+            let reply = [REJECTED, e.to_string().as_bytes()].concat();
+            let _ = writer.send(Bytes::from(reply)).await;
+            return Ok(());
+        }
+
+        // Wait for room if too many transactions are pending. We ask the client to slow down, and stop reading
+        // its socket meanwhile so that TCP back-pressures it.
+        if !self.admission.try_admit(message.len()) {
+            debug!("Transactions pipeline saturated, asking client to slow down");
+            let _ = writer.send(Bytes::from_static(SLOW_DOWN)).await;
+            self.admission.admit(message.len()).await;
+        }
+
         // Send the transaction to the batch maker.
         self.tx_batch_maker
             .send(message.to_vec())
@@ -258,33 +765,144 @@ impl MessageHandler for TxReceiverHandler {
         tokio::task::yield_now().await;
         Ok(())
     }
+
+    async fn dispatch_from(
+        &self,
+        peer: SocketAddr,
+        writer: &mut Writer,
+        message: Bytes,
+    ) -> Result<(), Box<dyn Error>> {
+        // Drop the transactions of the clients exceeding their quota.
+        if !self.admission.within_quota(peer.ip(), message.len()) {
+            debug!("Client {} exceeded its quota", peer);
+            return Ok(());
+        }
+        self.dispatch(writer, message).await
+    }
 }
 
 /// Defines how the network receiver handles incoming workers messages.
 #[derive(Clone)]
 struct WorkerReceiverHandler {
-    tx_helper: Sender<(Vec<Digest>, PublicKey)>,
+    tx_helper: Sender<HelperRequest>,
     tx_processor: Sender<SerializedBatchMessage>,
+    tx_shards: Sender<Vec<BatchShard>>,
+    validator: Arc<dyn TransactionValidator>,
+    #[cfg(feature = "threshold-encryption")]
+    tx_decryption_shares: Option<Sender<(BatchDecryptionShares, oneshot::Sender<bool>)>>,
 }
 
 #[async_trait]
 impl MessageHandler for WorkerReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // Reply with an ACK.
-        let _ = writer.send(Bytes::from("Ack")).await;
+        let mut message = bincode::deserialize(&serialized);
+
+        // Strip the padding of the padded batches.
+        let serialized = match message {
+            Ok(WorkerMessage::PaddedBatch(inner, _)) => {
+                message = bincode::deserialize(&inner);
+                if !matches!(
+                    message,
+                    Ok(WorkerMessage::Batch(..)) | Ok(WorkerMessage::CompressedBatch(..))
+                ) {
+                    warn!("Invalid padded batch");
+                    return Ok(());
+                }
+                Bytes::from(inner)
+            }
+            _ => serialized,
+        };
 
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized) {
+        // Decompress the compressed batches: we hash and store them uncompressed.
+        let serialized = match message {
+            Ok(WorkerMessage::CompressedBatch(data)) => match decompress(&data) {
+                Ok(decompressed) => {
+                    message = bincode::deserialize(&decompressed);
+                    if !matches!(message, Ok(WorkerMessage::Batch(..))) {
+                        warn!("Invalid compressed batch");
+                        return Ok(());
+                    }
+                    Bytes::from(decompressed)
+                }
+                Err(e) => {
+                    warn!("Failed to decompress batch: {}", e);
+                    return Ok(());
+                }
+            },
+            _ => serialized,
+        };
+
+        // Drop (without acknowledging them) the batches holding transactions breaking the rules of the
+        // application: we do not store them.
+        if let Ok(WorkerMessage::Batch(batch)) = &message {
+            if let Err(e) = batch.iter().try_for_each(|x| self.validator.validate(x)) {
+                warn!("Rejected batch: {}", e);
+                return Ok(());
+            }
+        }
+
+        // Reply with an ACK (stream requests are answered with the batches instead, and decryption shares once
+        // the `Decryptor` used them).
+        #[cfg(feature = "threshold-encryption")]
+        let replied_later = matches!(
+            message,
+            Ok(WorkerMessage::BatchStreamRequest(..)) | Ok(WorkerMessage::DecryptionShares(..))
+        );
+        #[cfg(not(feature = "threshold-encryption"))]
+        let replied_later = matches!(message, Ok(WorkerMessage::BatchStreamRequest(..)));
+        if !replied_later {
+            let _ = writer.send(Bytes::from("Ack")).await;
+        }
+
+        // Parse the message.
+        match message {
             Ok(WorkerMessage::Batch(..)) => self
                 .tx_processor
-                .send(serialized.to_vec())
+                .send(serialized)
                 .await
                 .expect("Failed to send batch"),
             Ok(WorkerMessage::BatchRequest(missing, requestor)) => self
                 .tx_helper
-                .send((missing, requestor))
+                .send(HelperRequest::Batches(missing, requestor))
                 .await
                 .expect("Failed to send batch request"),
+            Ok(WorkerMessage::BatchStreamRequest(missing, requestor)) => {
+                let (sender, receiver) = oneshot::channel();
+                self.tx_helper
+                    .send(HelperRequest::Stream(missing, requestor, sender))
+                    .await
+                    .expect("Failed to send batch request");
+                if let Ok(reply) = receiver.await {
+                    let _ = writer.send(reply).await;
+                }
+            }
+            Ok(WorkerMessage::BatchShards(shards)) => self
+                .tx_shards
+                .send(shards)
+                .await
+                .expect("Failed to send shards"),
+            #[cfg(feature = "threshold-encryption")]
+            Ok(WorkerMessage::DecryptionShares(shares)) => {
+                let used = match &self.tx_decryption_shares {
+                    Some(tx_decryption_shares) => {
+                        let (sender, receiver) = oneshot::channel();
+                        tx_decryption_shares
+                            .send((shares, sender))
+                            .await
+                            .expect("Failed to send decryption shares");
+                        receiver.await.unwrap_or(true)
+                    }
+                    None => true,
+                };
+                let reply = match used {
+                    true => Bytes::from("Ack"),
+                    false => Bytes::from(RETRY_REPLY),
+                };
+                let _ = writer.send(reply).await;
+            }
+            Ok(WorkerMessage::CompressedBatch(..)) | Ok(WorkerMessage::PaddedBatch(..)) => {
+                unreachable!()
+            }
             Err(e) => warn!("Serialization error: {}", e),
         }
         Ok(())
@@ -295,23 +913,73 @@ impl MessageHandler for WorkerReceiverHandler {
 #[derive(Clone)]
 struct PrimaryReceiverHandler {
     tx_synchronizer: Sender<PrimaryWorkerMessage>,
+    tx_pruner: Option<Sender<Round>>,
+    tx_reconfigure: Sender<Epoch>,
+    coverage: Option<Coverage>,
+    #[cfg(feature = "threshold-encryption")]
+    tx_committed: Option<Sender<Vec<Digest>>>,
+}
+
+impl PrimaryReceiverHandler {
+    /// Send the message to the synchronizer (and the cleanup rounds to the `BatchPruner`, if any).
+    async fn forward(&self, message: PrimaryWorkerMessage) {
+        if let (PrimaryWorkerMessage::Committed(digests), Some(coverage)) =
+            (&message, &self.coverage)
+        {
+            for digest in digests {
+                coverage.mark_committed(digest.clone());
+            }
+        }
+        if let (PrimaryWorkerMessage::Cleanup(round), Some(tx_pruner)) = (&message, &self.tx_pruner)
+        {
+            tx_pruner
+                .send(*round)
+                .await
+                .expect("Failed to send cleanup round");
+        }
+        #[cfg(feature = "threshold-encryption")]
+        if let (PrimaryWorkerMessage::Committed(digests), Some(tx_committed)) =
+            (&message, &self.tx_committed)
+        {
+            tx_committed
+                .send(digests.clone())
+                .await
+                .expect("Failed to send committed batches");
+        }
+        self.tx_synchronizer
+            .send(message)
+            .await
+            .expect("Failed to send transaction");
+    }
 }
 
 #[async_trait]
-impl MessageHandler for PrimaryReceiverHandler {
+impl TypedMessageHandler<PrimaryWorkerMessage> for PrimaryReceiverHandler {
     async fn dispatch(
         &self,
-        _writer: &mut Writer,
-        serialized: Bytes,
+        writer: &mut Writer,
+        message: PrimaryWorkerMessage,
     ) -> Result<(), Box<dyn Error>> {
+        match message {
+            PrimaryWorkerMessage::Reconfigure(epoch) => {
+                // Reply with an ACK (the primary waits for it before moving to the next epoch).
+                let _ = writer.send(Bytes::from("Ack")).await;
+                let _ = self.tx_reconfigure.send(epoch).await;
+            }
+            // Send the message to the synchronizer.
+            message => self.forward(message).await,
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DatagramHandler for PrimaryReceiverHandler {
+    async fn dispatch(&self, serialized: Bytes) -> Result<(), Box<dyn Error>> {
         // Deserialize the message and send it to the synchronizer.
         match bincode::deserialize(&serialized) {
             Err(e) => error!("Failed to deserialize primary message: {}", e),
-            Ok(message) => self
-                .tx_synchronizer
-                .send(message)
-                .await
-                .expect("Failed to send transaction"),
+            Ok(message) => self.forward(message).await,
         }
         Ok(())
     }